@@ -0,0 +1,45 @@
+//! Runs a clock and a custom block without i3bar, and prints what they show
+//! every time it changes:
+//!
+//! ```sh
+//! cargo run --example two_blocks
+//! ```
+
+use i3status_rs::config::Config;
+use i3status_rs::errors::*;
+use i3status_rs::runner::BlockRunner;
+
+const CONFIG: &str = r#"
+[theme]
+name = "solarized-dark"
+
+[[block]]
+block = "time"
+interval = 1
+format = "%H:%M:%S"
+
+[[block]]
+block = "custom"
+command = "uname -n"
+interval = 60
+"#;
+
+fn main() -> Result<()> {
+    let config: Config = toml::from_str(CONFIG).configuration_error("invalid configuration")?;
+    let mut runner = BlockRunner::new(config)?;
+    let stop = crossbeam_channel::never();
+    for _ in 0..5 {
+        let frame = match runner.next_frame(&stop)? {
+            Some(frame) => frame,
+            None => break,
+        };
+        let shown: Vec<String> = frame
+            .blocks()
+            .iter()
+            .flat_map(|block| &block.widgets)
+            .map(|widget| format!("{} ({:?})", widget.text, widget.state))
+            .collect();
+        println!("{}", shown.join(" | "));
+    }
+    Ok(())
+}
@@ -1,18 +1,29 @@
+pub mod aggregate;
 pub mod backlight;
 pub mod battery;
+pub mod ble_battery;
 pub mod bluetooth;
+pub mod compositor_perf;
 pub mod cpu;
 pub mod custom;
 pub mod custom_dbus;
+pub mod data_usage;
 pub mod disk_space;
 pub mod docker;
+pub mod external_ip;
 pub mod focused_window;
 pub mod github;
+pub mod heartbeat;
+pub mod http_json;
 pub mod hueshift;
 pub mod ibus;
+pub mod imap;
 pub mod kdeconnect;
 pub mod keyboard_layout;
+pub mod keylight;
+mod led_common;
 pub mod load;
+pub mod local_sensor;
 pub mod maildir;
 pub mod memory;
 pub mod music;
@@ -22,34 +33,51 @@ pub mod networkmanager;
 pub mod notmuch;
 pub mod nvidia_gpu;
 pub mod pacman;
+pub mod pager;
 pub mod pomodoro;
+pub mod sessions;
 pub mod sound;
+pub mod spacer;
 pub mod speedtest;
 pub mod taskwarrior;
 pub mod temperature;
 pub mod template;
 pub mod time;
+pub mod timer;
 pub mod toggle;
 pub mod uptime;
+pub mod usb_guard;
+pub mod vpn;
 pub mod watson;
 pub mod weather;
+pub mod window_count;
 pub mod xrandr;
 
+use self::aggregate::*;
 use self::backlight::*;
 use self::battery::*;
+use self::ble_battery::*;
 use self::bluetooth::*;
+use self::compositor_perf::*;
 use self::cpu::*;
 use self::custom::*;
 use self::custom_dbus::*;
+use self::data_usage::*;
 use self::disk_space::*;
 use self::docker::*;
+use self::external_ip::*;
 use self::focused_window::*;
 use self::github::*;
+use self::heartbeat::*;
+use self::http_json::*;
 use self::hueshift::*;
 use self::ibus::*;
+use self::imap::*;
 use self::kdeconnect::*;
 use self::keyboard_layout::*;
+use self::keylight::*;
 use self::load::*;
+use self::local_sensor::*;
 use self::maildir::*;
 use self::memory::*;
 use self::music::*;
@@ -59,34 +87,52 @@ use self::networkmanager::*;
 use self::notmuch::*;
 use self::nvidia_gpu::*;
 use self::pacman::*;
+use self::pager::*;
 use self::pomodoro::*;
+use self::sessions::*;
 use self::sound::*;
+use self::spacer::*;
 use self::speedtest::*;
 use self::taskwarrior::*;
 use self::temperature::*;
 use self::template::*;
 use self::time::*;
+use self::timer::*;
 use self::toggle::*;
 use self::uptime::*;
+use self::usb_guard::*;
+use self::vpn::*;
 use self::watson::*;
 use self::weather::*;
+use self::window_count::*;
 use self::xrandr::*;
 
-use std::time::Duration;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 use crossbeam_channel::Sender;
 use serde::de::Deserialize;
+use serde_json::value::Value as JsonValue;
 use toml::value::Value;
 
 use crate::config::Config;
 use crate::errors::*;
-use crate::input::I3BarEvent;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::registry::Values;
 use crate::scheduler::Task;
+use crate::subprocess::spawn_child_async;
 use crate::widget::I3BarWidget;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Update {
+    /// Update again after this long, as measured by the monotonic clock.
     Every(Duration),
+    /// Update again when the wall clock reaches this time. If the wall clock
+    /// jumps, e.g. after suspend or when it is set, the block is updated
+    /// right away instead, so it can schedule itself from the new time.
+    AtWallClock(DateTime<Utc>),
     Once,
 }
 
@@ -102,6 +148,17 @@ impl Into<Update> for Duration {
     }
 }
 
+/// Whether a block copes with clicks it did not expect, see
+/// [`Block::click_safety`](trait.Block.html#method.click_safety).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClickSafety {
+    /// Clicks only change what the block displays.
+    Safe,
+    /// Clicks have side effects, such as running commands or changing
+    /// hardware settings.
+    Unsafe,
+}
+
 pub trait Block {
     /// A unique id for the block.
     fn id(&self) -> &str;
@@ -119,6 +176,146 @@ pub trait Block {
     fn click(&mut self, _event: &I3BarEvent) -> Result<()> {
         Ok(())
     }
+
+    /// Whether `--self-test` may send synthetic clicks to this block.
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Safe
+    }
+
+    /// The numeric values behind the block's placeholders as of its last
+    /// update. If the block is named, these are published to the
+    /// [`registry`](../registry/index.html) for meta blocks to use.
+    fn values(&self) -> Values {
+        Values::new()
+    }
+
+    /// A snapshot of the state that should survive a restart of the bar, if
+    /// this block has any. Only used when running with `--persist-state`.
+    fn save_state(&self) -> Option<JsonValue> {
+        None
+    }
+
+    /// Restores a snapshot previously returned by `save_state`. This is called
+    /// once, after the block has been created and before its first update.
+    fn restore_state(&mut self, _state: JsonValue) -> Result<()> {
+        Ok(())
+    }
+
+    /// Shows `text` pushed through the [`control`](../control/index.html)
+    /// socket, until the next update. Most blocks do not take text.
+    fn push_text(&mut self, _text: &str) -> Result<()> {
+        Err(InternalError(
+            "control".to_owned(),
+            "the block does not take text".to_owned(),
+            None,
+        ))
+    }
+
+    /// Whether the block calls [`data_refreshed`](fn.data_refreshed.html)
+    /// itself when it got fresh data. Otherwise every successful update
+    /// counts as a refresh.
+    fn reports_refreshes(&self) -> bool {
+        false
+    }
+}
+
+thread_local! {
+    /// When each block last refreshed its data, by block id.
+    static REFRESHES: RefCell<HashMap<String, Instant>> = RefCell::new(HashMap::new());
+
+    /// The last refresh of the block being updated or clicked right now.
+    static CURRENT_REFRESH: RefCell<Option<Instant>> = const { RefCell::new(None) };
+
+    /// The `on_click` commands of blocks, by block id.
+    static ON_CLICK: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// Records that the block being updated just got fresh data, for blocks whose
+/// `reports_refreshes` is true.
+pub fn data_refreshed() {
+    CURRENT_REFRESH.with(|current| *current.borrow_mut() = Some(Instant::now()));
+}
+
+/// How old the data of the block being updated or clicked is, which is what
+/// the `{age}` placeholder shows. `None` if it never got any.
+pub fn data_age() -> Option<Duration> {
+    CURRENT_REFRESH.with(|current| current.borrow().map(|refresh| refresh.elapsed()))
+}
+
+/// Runs `f` on `block` with its last refresh available to `data_age`.
+fn with_refresh<T, F>(block: &mut dyn Block, refreshes: bool, f: F) -> Result<T>
+where
+    F: FnOnce(&mut dyn Block) -> Result<T>,
+{
+    let id = block.id().to_string();
+    let last = REFRESHES.with(|refreshes| refreshes.borrow().get(&id).cloned());
+    let refresh = if refreshes && !block.reports_refreshes() {
+        Some(Instant::now())
+    } else {
+        last
+    };
+    CURRENT_REFRESH.with(|current| *current.borrow_mut() = refresh);
+    let result = f(block);
+    let refresh = CURRENT_REFRESH.with(|current| current.borrow_mut().take());
+    if let (Ok(_), Some(refresh)) = (&result, refresh) {
+        REFRESHES.with(|refreshes| refreshes.borrow_mut().insert(id, refresh));
+    }
+    result
+}
+
+/// Updates `block`. Use this rather than `Block::update` so that the block's
+/// `{age}` placeholder is kept track of.
+pub fn update(block: &mut dyn Block) -> Result<Option<Update>> {
+    with_refresh(block, true, |block| block.update())
+}
+
+/// Runs `command` whenever the block `id` is left clicked, before the block
+/// handles the click itself.
+pub fn set_on_click(id: &str, command: &str) {
+    ON_CLICK.with(|commands| {
+        commands
+            .borrow_mut()
+            .insert(id.to_string(), command.to_string())
+    });
+}
+
+/// Forgets the `on_click` commands, refreshes and errors of the blocks with
+/// the given ids, once a reload has replaced them.
+pub fn forget(ids: &[String]) {
+    ON_CLICK.with(|commands| commands.borrow_mut().retain(|id, _| !ids.contains(id)));
+    REFRESHES.with(|refreshes| refreshes.borrow_mut().retain(|id, _| !ids.contains(id)));
+    forget_blocks(ids);
+}
+
+/// `command` with `{text}` replaced by what `block` shows and `{key}` by the
+/// value `key` of the block. Anything else in braces is left alone, since it
+/// may well be part of the shell command.
+fn on_click_command(command: &str, block: &dyn Block) -> String {
+    let text = block
+        .view()
+        .iter()
+        .map(|widget| widget.widget_state().text)
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut command = command.replace("{text}", text.trim());
+    for (key, value) in block.values() {
+        command = command.replace(&format!("{{{}}}", key), &value.to_string());
+    }
+    command
+}
+
+/// Sends a click event to `block`, see [`update`](fn.update.html). Runs the
+/// `on_click` command of the block, if any, without waiting for it.
+pub fn click(block: &mut dyn Block, event: &I3BarEvent) -> Result<()> {
+    if event.is_plain(MouseButton::Left) && event.matches_name(block.id()) {
+        let command = ON_CLICK.with(|commands| commands.borrow().get(block.id()).cloned());
+        if let Some(command) = command {
+            spawn_child_async("sh", &["-c", &on_click_command(&command, block)])
+                .block_error("on_click", "could not spawn child")?;
+        }
+    }
+    with_refresh(block, false, |block| block.click(event))
 }
 
 pub trait ConfigBlock: Block {
@@ -151,20 +348,30 @@ pub fn create_block(
 ) -> Result<Box<dyn Block>> {
     match name {
         // Please keep these in alphabetical order.
+        "aggregate" => block!(Aggregate, block_config, config, update_request),
         "backlight" => block!(Backlight, block_config, config, update_request),
         "battery" => block!(Battery, block_config, config, update_request),
+        "ble_battery" => block!(BleBattery, block_config, config, update_request),
         "bluetooth" => block!(Bluetooth, block_config, config, update_request),
+        "compositor_perf" => block!(CompositorPerf, block_config, config, update_request),
         "cpu" => block!(Cpu, block_config, config, update_request),
         "custom" => block!(Custom, block_config, config, update_request),
         "custom_dbus" => block!(CustomDBus, block_config, config, update_request),
+        "data_usage" => block!(DataUsage, block_config, config, update_request),
         "disk_space" => block!(DiskSpace, block_config, config, update_request),
         "docker" => block!(Docker, block_config, config, update_request),
+        "external_ip" => block!(ExternalIp, block_config, config, update_request),
         "focused_window" => block!(FocusedWindow, block_config, config, update_request),
         "github" => block!(Github, block_config, config, update_request),
+        "heartbeat" => block!(Heartbeat, block_config, config, update_request),
+        "http_json" => block!(HttpJson, block_config, config, update_request),
         "ibus" => block!(IBus, block_config, config, update_request),
+        "imap" => block!(Imap, block_config, config, update_request),
         "kdeconnect" => block!(KDEConnect, block_config, config, update_request),
         "keyboard_layout" => block!(KeyboardLayout, block_config, config, update_request),
+        "keylight" => block!(Keylight, block_config, config, update_request),
         "load" => block!(Load, block_config, config, update_request),
+        "local_sensor" => block!(LocalSensor, block_config, config, update_request),
         "maildir" => block!(Maildir, block_config, config, update_request),
         "memory" => block!(Memory, block_config, config, update_request),
         "music" => block!(Music, block_config, config, update_request),
@@ -174,17 +381,24 @@ pub fn create_block(
         "notmuch" => block!(Notmuch, block_config, config, update_request),
         "nvidia_gpu" => block!(NvidiaGpu, block_config, config, update_request),
         "pacman" => block!(Pacman, block_config, config, update_request),
+        "pager" => block!(Pager, block_config, config, update_request),
         "pomodoro" => block!(Pomodoro, block_config, config, update_request),
+        "sessions" => block!(Sessions, block_config, config, update_request),
         "sound" => block!(Sound, block_config, config, update_request),
+        "spacer" | "label" => block!(Spacer, block_config, config, update_request),
         "speedtest" => block!(SpeedTest, block_config, config, update_request),
         "taskwarrior" => block!(Taskwarrior, block_config, config, update_request),
         "temperature" => block!(Temperature, block_config, config, update_request),
         "template" => block!(Template, block_config, config, update_request),
         "time" => block!(Time, block_config, config, update_request),
+        "timer" => block!(Timer, block_config, config, update_request),
         "toggle" => block!(Toggle, block_config, config, update_request),
         "uptime" => block!(Uptime, block_config, config, update_request),
+        "usb_guard" => block!(UsbGuard, block_config, config, update_request),
+        "vpn" => block!(Vpn, block_config, config, update_request),
         "watson" => block!(Watson, block_config, config, update_request),
         "weather" => block!(Weather, block_config, config, update_request),
+        "window_count" => block!(WindowCount, block_config, config, update_request),
         "xrandr" => block!(Xrandr, block_config, config, update_request),
         "hueshift" => block!(Hueshift, block_config, config, update_request),
         other => Err(BlockError(other.to_string(), "Unknown block!".to_string())),
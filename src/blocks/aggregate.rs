@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::{Block, ConfigBlock, Update};
+use crate::config::Config;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::registry::{self, Values};
+use crate::scheduler::Task;
+use crate::subprocess::spawn_child_async;
+use crate::util::FormatTemplate;
+use crate::widget::I3BarWidget;
+use crate::widgets::button::ButtonWidget;
+
+pub struct Aggregate {
+    id: String,
+    output: ButtonWidget,
+    sources: BTreeMap<String, String>,
+    format: FormatTemplate,
+    hide_when_zero: bool,
+    breakdown: Vec<(String, f64)>,
+    total: f64,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AggregateConfig {
+    /// Names of the blocks to combine, each mapped to the placeholder whose
+    /// value is taken from that block.
+    pub sources: BTreeMap<String, String>,
+
+    /// Format override
+    #[serde(default = "AggregateConfig::default_format")]
+    pub format: String,
+
+    /// Hide the block while the total is zero
+    #[serde(default = "AggregateConfig::default_hide_when_zero")]
+    pub hide_when_zero: bool,
+
+    /// Icon to show in front of the total
+    #[serde(default = "AggregateConfig::default_icon")]
+    pub icon: Option<String>,
+}
+
+impl AggregateConfig {
+    fn default_format() -> String {
+        "{total}".to_owned()
+    }
+
+    fn default_hide_when_zero() -> bool {
+        false
+    }
+
+    fn default_icon() -> Option<String> {
+        None
+    }
+}
+
+impl ConfigBlock for Aggregate {
+    type Config = AggregateConfig;
+
+    fn new(
+        block_config: Self::Config,
+        config: Config,
+        tx_update_request: Sender<Task>,
+    ) -> Result<Self> {
+        if block_config.sources.is_empty() {
+            return Err(BlockError(
+                "aggregate".to_owned(),
+                "at least one source is required".to_owned(),
+            ));
+        }
+
+        let id = Uuid::new_v4().to_simple().to_string();
+        registry::depend(
+            &id,
+            block_config.sources.keys().cloned().collect(),
+            tx_update_request,
+        )?;
+
+        let mut output = ButtonWidget::new(config, &id);
+        if let Some(ref icon) = block_config.icon {
+            output.set_icon(icon);
+        }
+
+        Ok(Aggregate {
+            id,
+            output,
+            sources: block_config.sources,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("aggregate", "Invalid format specified")?,
+            hide_when_zero: block_config.hide_when_zero,
+            breakdown: Vec::new(),
+            total: 0.0,
+        })
+    }
+}
+
+/// Formats whole numbers without a fractional part.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+impl Block for Aggregate {
+    fn update(&mut self) -> Result<Option<Update>> {
+        self.breakdown = self
+            .sources
+            .iter()
+            .map(|(name, placeholder)| {
+                let value = registry::get(name)
+                    .and_then(|values| values.get(placeholder).cloned())
+                    .unwrap_or(0.0);
+                (name.clone(), value)
+            })
+            .collect();
+        self.total = self.breakdown.iter().map(|(_, value)| value).sum();
+
+        let total = if self.total.fract() == 0.0 {
+            Value::integer(self.total as i64)
+        } else {
+            Value::float(self.total, 1)
+        };
+        let values = map!("{total}" => total);
+        self.output
+            .set_text(self.format.render_static_str(&values)?);
+        Ok(None)
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        if self.hide_when_zero && self.total == 0.0 {
+            vec![]
+        } else {
+            vec![&self.output]
+        }
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) && event.button == MouseButton::Left {
+            let body = self
+                .breakdown
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, format_number(*value)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            spawn_child_async("notify-send", &[&format_number(self.total), &body])
+                .block_error("aggregate", "could not spawn notify-send")?;
+        }
+        Ok(())
+    }
+
+    fn values(&self) -> Values {
+        let mut values = Values::new();
+        values.insert("total".to_owned(), self.total);
+        values
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
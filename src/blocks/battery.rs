@@ -4,9 +4,9 @@
 //! display the status, capacity, and time remaining for (dis)charge for an
 //! internal power supply.
 
+use std::cell::RefCell;
 use std::path::{Path, PathBuf};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use crossbeam_channel::Sender;
 use dbus::arg::Array;
@@ -15,17 +15,29 @@ use serde_derive::Deserialize;
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
+use crate::dbus_manager::{self, Bus};
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::{ClickAction, ClickBindings, I3BarEvent};
 use crate::scheduler::Task;
-use crate::util::{battery_level_to_icon, format_percent_bar, read_file, FormatTemplate};
+use crate::subprocess::spawn_child_async;
+use crate::util::{battery_level_to_icon, read_file, FormatTemplate};
 use crate::widget::{I3BarWidget, State};
-use crate::widgets::text::TextWidget;
+use crate::widgets::button::ButtonWidget;
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
 
 /// A battery device can be queried for a few properties relevant to the user.
 pub trait BatteryDevice {
+    /// Whether the device is there at all. Devices that can come and go, like
+    /// wireless mice, are not shown while they are away.
+    fn is_present(&self) -> bool {
+        true
+    }
+
     /// Query the device status, one of `"Full"`, `"Charging"`, `"Discharging"`,
     /// or `"Unknown"`. Thinkpad batteries also report "`Not charging`", which
     /// for our purposes should be treated as equivalent to full.
@@ -54,7 +66,10 @@ impl PowerSupplyDevice {
     /// `/sys/class/power_supply` directory. Raises an error if a directory for
     /// that device is not found.
     pub fn from_device(device: &str) -> Result<Self> {
-        let device_path = Path::new("/sys/class/power_supply").join(device);
+        Self::from_path(Path::new(POWER_SUPPLY).join(device))
+    }
+
+    fn from_path(device_path: PathBuf) -> Result<Self> {
         if !device_path.exists() {
             return Err(BlockError(
                 "battery".to_string(),
@@ -93,6 +108,158 @@ impl PowerSupplyDevice {
             energy_full,
         })
     }
+
+    /// The energy left and the energy when full, in µWh. Charges in µAh are
+    /// converted with the voltage, so that batteries reporting either can be
+    /// added up.
+    fn energy(&self) -> Result<(f64, f64)> {
+        let read = |name: &str| -> Result<f64> {
+            read_file("battery", &self.device_path.join(name))?
+                .parse::<f64>()
+                .block_error("battery", &format!("failed to parse {}", name))
+        };
+        if let Some(full) = self.energy_full {
+            return Ok((read("energy_now")?, full as f64));
+        }
+        let full = self
+            .charge_full
+            .block_error("battery", "Device does not support reading energy")?;
+        let voltage = read("voltage_now").or_else(|_| read("voltage_min_design"))? / 1_000_000.0;
+        Ok((read("charge_now")? * voltage, full as f64 * voltage))
+    }
+}
+
+/// Several power supply devices shown as one battery, as set with
+/// `device = "all"` or a list of devices.
+pub struct MultiBatteryDevice {
+    devices: Vec<PowerSupplyDevice>,
+}
+
+impl MultiBatteryDevice {
+    /// Use the power supply devices `devices` in `/sys/class/power_supply`.
+    pub fn from_devices(devices: &[String]) -> Result<Self> {
+        Self::from_paths(
+            devices
+                .iter()
+                .map(|device| Path::new(POWER_SUPPLY).join(device))
+                .collect(),
+        )
+    }
+
+    /// Use all batteries of the system, leaving out those of peripherals like
+    /// mice and keyboards.
+    pub fn discover() -> Result<Self> {
+        Self::discover_in(Path::new(POWER_SUPPLY))
+    }
+
+    fn discover_in(root: &Path) -> Result<Self> {
+        let mut paths: Vec<PathBuf> = root
+            .read_dir()
+            .block_error("battery", "failed to list power supply devices")?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                read_file("battery", &path.join("type")).ok().as_deref() == Some("Battery")
+                    && read_file("battery", &path.join("scope")).ok().as_deref() != Some("Device")
+            })
+            .collect();
+        paths.sort();
+        Self::from_paths(paths)
+    }
+
+    fn from_paths(paths: Vec<PathBuf>) -> Result<Self> {
+        if paths.is_empty() {
+            return Err(BlockError(
+                "battery".to_string(),
+                "No power supply devices found".to_string(),
+            ));
+        }
+        Ok(MultiBatteryDevice {
+            devices: paths
+                .into_iter()
+                .map(PowerSupplyDevice::from_path)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// The energy left and the energy when full of all devices, in µWh.
+    fn energy(&self) -> Result<(f64, f64)> {
+        self.devices
+            .iter()
+            .try_fold((0.0, 0.0), |(now, full), device| {
+                let (device_now, device_full) = device.energy()?;
+                Ok((now + device_now, full + device_full))
+            })
+    }
+}
+
+impl BatteryDevice for MultiBatteryDevice {
+    /// Charging if any device is, else discharging if any device is, so that a
+    /// ThinkPad draining one battery while the other is "Not charging" shows
+    /// as discharging.
+    fn status(&self) -> Result<String> {
+        let statuses = self
+            .devices
+            .iter()
+            .map(|device| device.status())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(
+            if let Some(status) = ["Charging", "Discharging"]
+                .iter()
+                .find(|status| statuses.iter().any(|s| s == *status))
+            {
+                status.to_string()
+            } else if statuses.iter().all(|s| s == "Full" || s == "Not charging") {
+                "Full".to_string()
+            } else {
+                statuses[0].clone()
+            },
+        )
+    }
+
+    fn capacity(&self) -> Result<u64> {
+        let capacity = match self.energy() {
+            Ok((now, full)) if full > 0.0 => ((now / full) * 100.0) as u64,
+            // Without the energy of every device, their capacities are
+            // averaged, as if they were equally large.
+            _ => {
+                let capacities = self
+                    .devices
+                    .iter()
+                    .map(|device| device.capacity())
+                    .collect::<Result<Vec<_>>>()?;
+                capacities.iter().sum::<u64>() / capacities.len() as u64
+            }
+        };
+        Ok(capacity.min(100))
+    }
+
+    fn time_remaining(&self) -> Result<u64> {
+        let (now, full) = self.energy()?;
+        let power = self.power_consumption()? as f64;
+        if power == 0.0 {
+            return Ok(0);
+        }
+        match self.status()?.as_str() {
+            "Discharging" => Ok(((now / power) * 60.0) as u64),
+            "Charging" => Ok((((full - now).max(0.0) / power) * 60.0) as u64),
+            _ => Ok(0),
+        }
+    }
+
+    fn power_consumption(&self) -> Result<u64> {
+        let powers: Vec<u64> = self
+            .devices
+            .iter()
+            .filter_map(|device| device.power_consumption().ok())
+            .collect();
+        if powers.is_empty() {
+            return Err(BlockError(
+                "battery".to_string(),
+                "Devices do not support power consumption".to_string(),
+            ));
+        }
+        Ok(powers.iter().sum())
+    }
 }
 
 impl BatteryDevice for PowerSupplyDevice {
@@ -275,25 +442,47 @@ impl BatteryDevice for PowerSupplyDevice {
 }
 
 /// Represents a battery known to UPower.
+///
+/// Peripherals like wireless mice come and go, so the device is looked up
+/// again whenever reading it fails, and it is not an error for it to be
+/// missing for a while.
 pub struct UpowerDevice {
-    device_path: String,
+    device: String,
+    device_path: RefCell<Option<String>>,
     con: dbus::ffidisp::Connection,
 }
 
 impl UpowerDevice {
-    /// Create the UPower device from the `device` string, which is converted to
-    /// the path `"/org/freedesktop/UPower/devices/battery_<device>"`, except if
+    /// Create the UPower device from the `device` string, which is matched
+    /// against the end of the UPower device paths, such as
+    /// `"/org/freedesktop/UPower/devices/battery_BAT0"` or
+    /// `"/org/freedesktop/UPower/devices/mouse_hidpp_battery_0"`, except if
     /// `device` equals `"DisplayDevice"`, in which case it is converted to the
     /// path `"/org/freedesktop/UPower/devices/DisplayDevice"`. Raises an error
-    /// if D-Bus cannot connect to this device, or if the device is not a
-    /// battery.
+    /// if D-Bus cannot connect to UPower, or if the device is not a battery.
     pub fn from_device(device: &str) -> Result<Self> {
-        let device_path;
         let con = dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::System)
             .block_error("battery", "Failed to establish D-Bus connection.")?;
+        let out = UpowerDevice {
+            device: device.to_string(),
+            device_path: RefCell::new(None),
+            con,
+        };
+        match out.find_device() {
+            // A missing device may still show up later.
+            Ok(_) | Err(UpowerError::Missing) => Ok(out),
+            Err(UpowerError::Failed(error)) => Err(error),
+        }
+    }
+
+    /// The path of the device, looked up if it is not known yet.
+    fn find_device(&self) -> std::result::Result<String, UpowerError> {
+        if let Some(ref path) = *self.device_path.borrow() {
+            return Ok(path.clone());
+        }
 
-        if device == "DisplayDevice" {
-            device_path = String::from("/org/freedesktop/UPower/devices/DisplayDevice");
+        let device_path = if self.device == "DisplayDevice" {
+            String::from("/org/freedesktop/UPower/devices/DisplayDevice")
         } else {
             let msg = dbus::Message::new_method_call(
                 "org.freedesktop.UPower",
@@ -302,20 +491,24 @@ impl UpowerDevice {
                 "EnumerateDevices",
             )
             .unwrap();
-            let dbus_reply = con.send_with_reply_and_block(msg, 2000).unwrap();
+            let dbus_reply = self
+                .con
+                .send_with_reply_and_block(msg, 2000)
+                .block_error("battery", "Failed to enumerate UPower devices.")?;
 
             // EnumerateDevices returns one argument, which is an array of ObjectPaths (not dbus::tree:ObjectPath).
-            let mut paths: Array<dbus::Path, _> = dbus_reply.get1().unwrap();
-            let path = paths.find(|entry| entry.ends_with(device));
-            if path.is_none() {
-                return Err(BlockError(
-                    "battery".into(),
-                    "UPower device could not be found.".into(),
-                ));
-            }
-            device_path = path.unwrap().as_cstr().to_string_lossy().into_owned();
-        }
-        let upower_type: u32 = con
+            let mut paths: Array<dbus::Path, _> = dbus_reply
+                .get1()
+                .block_error("battery", "Failed to read UPower devices.")?;
+            paths
+                .find(|entry| entry.ends_with(&self.device))
+                .ok_or(UpowerError::Missing)?
+                .as_cstr()
+                .to_string_lossy()
+                .into_owned()
+        };
+        let upower_type: u32 = self
+            .con
             .with_path("org.freedesktop.UPower", &device_path, 1000)
             .get("org.freedesktop.UPower.Device", "Type")
             .block_error("battery", "Failed to read UPower Type property.")?;
@@ -323,62 +516,86 @@ impl UpowerDevice {
         // https://upower.freedesktop.org/docs/Device.html#Device:Type
         // consider any peripheral, UPS and internal battery
         if upower_type == 1 {
-            return Err(BlockError(
-                "battery".into(),
-                "UPower device is not a battery.".into(),
-            ));
+            return Err(
+                BlockError("battery".into(), "UPower device is not a battery.".into()).into(),
+            );
         }
-        Ok(UpowerDevice { device_path, con })
-    }
-
-    /// Monitor UPower property changes in a separate thread and send updates
-    /// via the `update_request` channel.
-    pub fn monitor(&self, id: String, update_request: Sender<Task>) {
-        let path = self.device_path.clone();
-        thread::Builder::new()
-            .name("battery".into())
-            .spawn(move || {
-                let con = dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::System)
-                    .expect("Failed to establish D-Bus connection.");
-                let rule = format!(
-                    "type='signal',\
-                 path='{}',\
-                 interface='org.freedesktop.DBus.Properties',\
-                 member='PropertiesChanged'",
-                    path
-                );
-
-                // First we're going to get an (irrelevant) NameAcquired event.
-                con.incoming(10_000).next();
-
-                con.add_match(&rule)
-                    .expect("Failed to add D-Bus match rule.");
-
-                loop {
-                    if con.incoming(10_000).next().is_some() {
-                        update_request
-                            .send(Task {
-                                id: id.clone(),
-                                update_time: Instant::now(),
-                            })
-                            .unwrap();
-                        // Avoid update spam.
-                        // TODO: Is this necessary?
-                        thread::sleep(Duration::from_millis(1000))
-                    }
-                }
-            })
-            .unwrap();
+        *self.device_path.borrow_mut() = Some(device_path.clone());
+        Ok(device_path)
+    }
+
+    /// Read the UPower `property` of the device. Once that fails, the device
+    /// is looked up again next time, in case it was removed and added back.
+    fn get<T>(&self, property: &str) -> Result<T>
+    where
+        for<'b> T: dbus::arg::Get<'b> + 'static,
+    {
+        let device_path = self.find_device().map_err(|error| match error {
+            UpowerError::Missing => {
+                BlockError("battery".into(), "UPower device could not be found.".into())
+            }
+            UpowerError::Failed(error) => error,
+        })?;
+        let value = self
+            .con
+            .with_path("org.freedesktop.UPower", &device_path, 1000)
+            .get("org.freedesktop.UPower.Device", property);
+        if value.is_err() {
+            *self.device_path.borrow_mut() = None;
+        }
+        value.block_error(
+            "battery",
+            &format!("Failed to read UPower {} property.", property),
+        )
+    }
+
+    /// Monitor UPower property changes and devices being added or removed,
+    /// and send updates via the `update_request` channel.
+    pub fn monitor(&self, id: String, update_request: Sender<Task>) -> Result<()> {
+        // The path of the device may change when it comes back, so changes of
+        // all UPower devices are listened to.
+        dbus_manager::request_updates(
+            Bus::System,
+            "type='signal',\
+             path_namespace='/org/freedesktop/UPower',\
+             interface='org.freedesktop.DBus.Properties',\
+             member='PropertiesChanged'",
+            id.clone(),
+            update_request.clone(),
+        )
+        .block_error("battery", "Failed to add D-Bus match rule.")?;
+        dbus_manager::request_updates(
+            Bus::System,
+            "type='signal',\
+             path='/org/freedesktop/UPower',\
+             interface='org.freedesktop.UPower'",
+            id,
+            update_request,
+        )
+        .block_error("battery", "Failed to add D-Bus match rule.")
+    }
+}
+
+/// Why a UPower device could not be looked up.
+enum UpowerError {
+    /// UPower does not know the device, at least for now.
+    Missing,
+    Failed(Error),
+}
+
+impl From<Error> for UpowerError {
+    fn from(error: Error) -> Self {
+        UpowerError::Failed(error)
     }
 }
 
 impl BatteryDevice for UpowerDevice {
+    fn is_present(&self) -> bool {
+        !matches!(self.find_device(), Err(UpowerError::Missing))
+    }
+
     fn status(&self) -> Result<String> {
-        let status: u32 = self
-            .con
-            .with_path("org.freedesktop.UPower", &self.device_path, 1000)
-            .get("org.freedesktop.UPower.Device", "State")
-            .block_error("battery", "Failed to read UPower State property.")?;
+        let status: u32 = self.get("State")?;
 
         // https://upower.freedesktop.org/docs/Device.html#Device:State
         match status {
@@ -393,11 +610,7 @@ impl BatteryDevice for UpowerDevice {
     }
 
     fn capacity(&self) -> Result<u64> {
-        let capacity: f64 = self
-            .con
-            .with_path("org.freedesktop.UPower", &self.device_path, 1000)
-            .get("org.freedesktop.UPower.Device", "Percentage")
-            .block_error("battery", "Failed to read UPower Percentage property.")?;
+        let capacity: f64 = self.get("Percentage")?;
 
         if capacity > 100.0 {
             Ok(100)
@@ -412,23 +625,12 @@ impl BatteryDevice for UpowerDevice {
         } else {
             "TimeToEmpty"
         };
-        let time_to_empty: i64 = self
-            .con
-            .with_path("org.freedesktop.UPower", &self.device_path, 1000)
-            .get("org.freedesktop.UPower.Device", property)
-            .block_error(
-                "battery",
-                &format!("Failed to read UPower {} property.", property),
-            )?;
+        let time_to_empty: i64 = self.get(property)?;
         Ok((time_to_empty / 60) as u64)
     }
 
     fn power_consumption(&self) -> Result<u64> {
-        let energy_rate: f64 = self
-            .con
-            .with_path("org.freedesktop.UPower", &self.device_path, 1000)
-            .get("org.freedesktop.UPower.Device", "EnergyRate")
-            .block_error("battery", "Failed to read UPower EnergyRate property.")?;
+        let energy_rate: f64 = self.get("EnergyRate")?;
         // FIXME: Might want to make the interface send Watts instead.
         Ok((energy_rate * 1_000_000.0) as u64)
     }
@@ -436,10 +638,11 @@ impl BatteryDevice for UpowerDevice {
 
 /// A block for displaying information about an internal power supply.
 pub struct Battery {
-    output: TextWidget,
+    output: ButtonWidget,
     id: String,
     update_interval: Duration,
     device: Box<dyn BatteryDevice>,
+    present: bool,
     format: FormatTemplate,
     full_format: FormatTemplate,
     driver: BatteryDriver,
@@ -447,6 +650,7 @@ pub struct Battery {
     info: u64,
     warning: u64,
     critical: u64,
+    click: ClickBindings<BatteryAction>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -456,6 +660,14 @@ pub enum BatteryDriver {
     Upower,
 }
 
+/// One power supply device, `"all"` of them, or a list.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum BatteryDevices {
+    One(String),
+    Many(Vec<String>),
+}
+
 impl Default for BatteryDriver {
     fn default() -> Self {
         BatteryDriver::Sysfs
@@ -474,9 +686,9 @@ pub struct BatteryConfig {
     pub interval: Duration,
 
     /// The internal power supply device in `/sys/class/power_supply/` to read
-    /// from.
+    /// from, `"all"` or a list of devices to show together.
     #[serde(default = "BatteryConfig::default_device")]
-    pub device: String,
+    pub device: BatteryDevices,
 
     /// (DEPRECATED) Options for displaying battery information.
     #[serde()]
@@ -514,6 +726,18 @@ pub struct BatteryConfig {
     /// The threshold below which the remaining capacity is shown as critical
     #[serde(default = "BatteryConfig::default_critical")]
     pub critical: u64,
+
+    /// Actions bound to clicks
+    #[serde(default)]
+    pub click: ClickBindings<BatteryAction>,
+}
+
+/// What a click on the battery block can do.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryAction {
+    /// Read the battery again right away.
+    Update,
 }
 
 impl BatteryConfig {
@@ -521,8 +745,8 @@ impl BatteryConfig {
         Duration::from_secs(10)
     }
 
-    fn default_device() -> String {
-        "BAT0".to_string()
+    fn default_device() -> BatteryDevices {
+        BatteryDevices::One("BAT0".to_string())
     }
 
     fn default_format() -> String {
@@ -585,18 +809,41 @@ impl ConfigBlock for Battery {
         let id = Uuid::new_v4().to_simple().to_string();
         let device: Box<dyn BatteryDevice> = match driver {
             BatteryDriver::Upower => {
-                let out = UpowerDevice::from_device(&block_config.device)?;
-                out.monitor(id.clone(), update_request);
+                // UPower merges all batteries into its display device itself.
+                let out = match block_config.device {
+                    BatteryDevices::One(ref device) if device == "all" => {
+                        UpowerDevice::from_device("DisplayDevice")?
+                    }
+                    BatteryDevices::One(ref device) => UpowerDevice::from_device(device)?,
+                    BatteryDevices::Many(_) => {
+                        return Err(BlockError(
+                            "battery".into(),
+                            "a list of devices is not supported with UPower, use \"all\"".into(),
+                        ));
+                    }
+                };
+                out.monitor(id.clone(), update_request)?;
                 Box::new(out)
             }
-            BatteryDriver::Sysfs => Box::new(PowerSupplyDevice::from_device(&block_config.device)?),
+            BatteryDriver::Sysfs => match block_config.device {
+                BatteryDevices::One(ref device) if device == "all" => {
+                    Box::new(MultiBatteryDevice::discover()?)
+                }
+                BatteryDevices::One(ref device) => {
+                    Box::new(PowerSupplyDevice::from_device(device)?)
+                }
+                BatteryDevices::Many(ref devices) => {
+                    Box::new(MultiBatteryDevice::from_devices(devices)?)
+                }
+            },
         };
 
         Ok(Battery {
+            output: ButtonWidget::new(config, &id),
             id,
             update_interval: block_config.interval,
-            output: TextWidget::new(config),
             device,
+            present: true,
             format: FormatTemplate::from_string(&format)?,
             full_format: FormatTemplate::from_string(&block_config.full_format)?,
             driver,
@@ -604,13 +851,21 @@ impl ConfigBlock for Battery {
             info: block_config.info,
             warning: block_config.warning,
             critical: block_config.critical,
+            click: block_config.click,
         })
     }
 }
 
 impl Block for Battery {
     fn update(&mut self) -> Result<Option<Update>> {
-        // TODO: Maybe use dbus to immediately signal when the battery state changes.
+        self.present = self.device.is_present();
+        if !self.present {
+            return match self.driver {
+                BatteryDriver::Sysfs => Ok(Some(self.update_interval.into())),
+                // The device being added back requests an update.
+                BatteryDriver::Upower => Ok(None),
+            };
+        }
 
         let status = self.device.status()?;
         let capacity = self.device.capacity();
@@ -619,8 +874,8 @@ impl Block for Battery {
             Err(_) => "×".into(),
         };
         let bar = match capacity {
-            Ok(capacity) => format_percent_bar(capacity as f32),
-            Err(_) => "×".into(),
+            Ok(capacity) => Value::bar(capacity),
+            Err(_) => Value::from("×"),
         };
         let time = match self.device.time_remaining() {
             Ok(time) => match time {
@@ -634,10 +889,10 @@ impl Block for Battery {
             Ok(power) => format!("{:.2}", power as f64 / 1000.0 / 1000.0),
             Err(_) => "×".into(),
         };
-        let values = map!("{percentage}" => percentage,
+        let values = map!("{percentage}" => Value::from(percentage),
                             "{bar}" => bar,
-                            "{time}" => time,
-                            "{power}" => power);
+                            "{time}" => Value::from(time),
+                            "{power}" => Value::from(power));
 
         if status == "Full" || status == "Not charging" {
             self.output.set_icon("bat_full");
@@ -688,10 +943,101 @@ impl Block for Battery {
     }
 
     fn view(&self) -> Vec<&dyn I3BarWidget> {
-        vec![&self.output]
+        if self.present {
+            vec![&self.output]
+        } else {
+            vec![]
+        }
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) {
+            match self.click.get(event).cloned() {
+                Some(ClickAction::Block(BatteryAction::Update)) => {
+                    self.update()?;
+                }
+                Some(ClickAction::Command { cmd }) => {
+                    spawn_child_async("sh", &["-c", &cmd])
+                        .block_error("battery", "could not spawn child")?;
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn click_safety(&self) -> ClickSafety {
+        if self.click.is_empty() {
+            ClickSafety::Safe
+        } else {
+            ClickSafety::Unsafe
+        }
     }
 
     fn id(&self) -> &str {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    fn fake_supply(root: &Path, name: &str, files: &[(&str, &str)]) {
+        let path = root.join(name);
+        fs::create_dir_all(&path).unwrap();
+        for (file, contents) in files {
+            fs::write(path.join(file), format!("{}\n", contents)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_multiple_batteries() {
+        let root = TempDir::new().unwrap();
+        fake_supply(root.path(), "AC", &[("type", "Mains"), ("online", "0")]);
+        fake_supply(
+            root.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Not charging"),
+                ("energy_now", "10000000"),
+                ("energy_full", "20000000"),
+                ("power_now", "0"),
+            ],
+        );
+        // The charge in µAh, at 12V, and the draw in µA.
+        fake_supply(
+            root.path(),
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("charge_now", "2500000"),
+                ("charge_full", "5000000"),
+                ("voltage_now", "12000000"),
+                ("current_now", "500000"),
+            ],
+        );
+        fake_supply(
+            root.path(),
+            "hidpp_battery_0",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+        );
+
+        let batteries = MultiBatteryDevice::discover_in(root.path()).unwrap();
+        assert_eq!(batteries.devices.len(), 2);
+        assert_eq!(batteries.status().unwrap(), "Discharging");
+        // 40Wh of 80Wh left.
+        assert_eq!(batteries.capacity().unwrap(), 50);
+        assert_eq!(batteries.power_consumption().unwrap(), 6_000_000);
+        // 40Wh at 6W.
+        assert_eq!(batteries.time_remaining().unwrap(), 400);
+
+        assert!(MultiBatteryDevice::discover_in(&root.path().join("AC")).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+//! A block for the battery level of a Bluetooth Low Energy device.
+//!
+//! Many wearables (smartwatches, fitness trackers) do not report their battery
+//! over `org.bluez.Battery1` like headsets do, but do implement the standard
+//! GATT Battery Service. This block reads its Battery Level characteristic
+//! directly and subscribes to its notifications where the device supports
+//! them. All D-Bus traffic to the device happens on a background thread, as
+//! connection attempts to a device out of range can take a long time.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use dbus::arg;
+use dbus::ffidisp::stdintf::org_freedesktop_dbus::{ObjectManager, Properties};
+use dbus::ffidisp::{BusType, Connection};
+use dbus::Message;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::bluetooth::BluetoothDevice;
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::I3BarEvent;
+use crate::scheduler::Task;
+use crate::stop::{self, Stop, Stopped};
+use crate::util::{format_age, FormatTemplate};
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
+
+/// The UUID of the Battery Level characteristic (0x2A19).
+const BATTERY_LEVEL_UUID: &str = "00002a19-0000-1000-8000-00805f9b34fb";
+
+/// What the background thread knows about the device.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct BatteryState {
+    level: Option<u8>,
+    read_at: Option<Instant>,
+    connected: bool,
+}
+
+pub struct BleBattery {
+    id: String,
+    output: TextWidget,
+    label: String,
+    format: FormatTemplate,
+    state: Arc<Mutex<BatteryState>>,
+    /// Stops polling the device when the block is dropped.
+    _poller: Stop,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BleBatteryConfig {
+    /// MAC address of the (already paired) device
+    pub mac: String,
+
+    /// Text shown in front of the battery level
+    pub label: Option<String>,
+
+    /// Format of the battery level, with the placeholder `{level}`
+    #[serde(default = "BleBatteryConfig::default_format")]
+    pub format: String,
+
+    /// How often to read the battery level while connected
+    #[serde(
+        default = "BleBatteryConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// Minimum time between two attempts to connect to the device
+    #[serde(
+        default = "BleBatteryConfig::default_retry_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub retry_interval: Duration,
+}
+
+impl BleBatteryConfig {
+    fn default_interval() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn default_retry_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_format() -> String {
+        "{level}%".to_owned()
+    }
+}
+
+impl ConfigBlock for BleBattery {
+    type Config = BleBatteryConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id: String = Uuid::new_v4().to_simple().to_string();
+        let device = BluetoothDevice::new(block_config.mac, None)?;
+        let state = Arc::new(Mutex::new(BatteryState::default()));
+        let (stop, stopped) = stop::pair()?;
+
+        let poller = Poller {
+            id: id.clone(),
+            path: device.path,
+            state: state.clone(),
+            send,
+            interval: block_config.interval,
+            retry_interval: block_config.retry_interval,
+            stopped,
+        };
+        thread::Builder::new()
+            .name("ble_battery".into())
+            .spawn(move || poller.run())
+            .block_error("ble_battery", "failed to spawn thread")?;
+
+        Ok(BleBattery {
+            id,
+            output: TextWidget::new(config).with_icon("bluetooth"),
+            label: block_config.label.unwrap_or_default(),
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("ble_battery", "Invalid format specified for ble_battery")?,
+            state,
+            _poller: stop,
+        })
+    }
+}
+
+struct Poller {
+    id: String,
+    path: String,
+    state: Arc<Mutex<BatteryState>>,
+    send: Sender<Task>,
+    interval: Duration,
+    retry_interval: Duration,
+    stopped: Stopped,
+}
+
+impl Poller {
+    fn run(self) {
+        let con = match Connection::get_private(BusType::System) {
+            Ok(con) => con,
+            Err(e) => {
+                log_block_error(
+                    &self.id,
+                    "ble_battery",
+                    &format!("failed to establish D-Bus connection: {}", e),
+                );
+                return;
+            }
+        };
+
+        let mut last_attempt: Option<Instant> = None;
+        let mut last_read: Option<Instant> = None;
+        let mut subscribed = false;
+        // D-Bus calls block for a while, so this is only noticed between them.
+        while !self.stopped.is_stopped() {
+            let connected = con
+                .with_path("org.bluez", &self.path, 1000)
+                .get("org.bluez.Device1", "Connected")
+                .unwrap_or(false);
+
+            if !connected {
+                subscribed = false;
+                if last_attempt.is_none_or(|t| t.elapsed() >= self.retry_interval) {
+                    last_attempt = Some(Instant::now());
+                    // Failing is expected while the device is out of range.
+                    if let Ok(msg) = Message::new_method_call(
+                        "org.bluez",
+                        &self.path,
+                        "org.bluez.Device1",
+                        "Connect",
+                    ) {
+                        con.send_with_reply_and_block(msg, 30_000).ok();
+                    }
+                }
+            } else if last_read.is_none_or(|t| t.elapsed() >= self.interval) {
+                if let Some(characteristic) = find_battery_characteristic(&con, &self.path) {
+                    if let Some(level) = read_level(&con, &characteristic) {
+                        last_read = Some(Instant::now());
+                        self.set_level(level);
+                    }
+                    if !subscribed {
+                        subscribed = subscribe(&con, &characteristic);
+                    }
+                }
+            }
+            self.set_connected(connected);
+
+            // Wait for a notification, or until it is time to check again.
+            if let Some(msg) = con.incoming(5000).next() {
+                if let Some(level) = level_from_properties_changed(&msg) {
+                    last_read = Some(Instant::now());
+                    self.set_level(level);
+                }
+            }
+        }
+    }
+
+    fn update_state<F: FnOnce(&mut BatteryState)>(&self, f: F) {
+        let changed = match self.state.lock() {
+            Ok(mut state) => {
+                let before = *state;
+                f(&mut state);
+                before != *state
+            }
+            Err(_) => false,
+        };
+        if changed {
+            self.send
+                .send(Task {
+                    id: self.id.clone(),
+                    update_time: Instant::now(),
+                })
+                .ok();
+        }
+    }
+
+    fn set_level(&self, level: u8) {
+        self.update_state(|state| {
+            state.level = Some(level);
+            state.read_at = Some(Instant::now());
+        });
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.update_state(|state| state.connected = connected);
+    }
+}
+
+/// The object path of the Battery Level characteristic of the device at
+/// `device_path`, if the device has one.
+fn find_battery_characteristic(con: &Connection, device_path: &str) -> Option<String> {
+    let objects = con
+        .with_path("org.bluez", "/", 1000)
+        .get_managed_objects()
+        .ok()?;
+    let prefix = format!("{}/", device_path);
+    objects
+        .into_iter()
+        .filter(|(path, _)| path.starts_with(&prefix))
+        .find(|(_, interfaces)| {
+            interfaces
+                .get("org.bluez.GattCharacteristic1")
+                .and_then(|props| props.get("UUID"))
+                .and_then(|uuid| {
+                    uuid.0
+                        .as_str()
+                        .map(|s| s.eq_ignore_ascii_case(BATTERY_LEVEL_UUID))
+                })
+                .unwrap_or(false)
+        })
+        .map(|(path, _)| path.to_string())
+}
+
+fn read_level(con: &Connection, characteristic: &str) -> Option<u8> {
+    let msg = Message::new_method_call(
+        "org.bluez",
+        characteristic,
+        "org.bluez.GattCharacteristic1",
+        "ReadValue",
+    )
+    .ok()?
+    .append1(HashMap::<&str, arg::Variant<u8>>::new());
+    let reply = con.send_with_reply_and_block(msg, 5000).ok()?;
+    let value: Vec<u8> = reply.get1()?;
+    level_from_bytes(&value)
+}
+
+/// Asks the device to notify us of changes. Returns whether this worked; not
+/// all devices support notifications for the battery level.
+fn subscribe(con: &Connection, characteristic: &str) -> bool {
+    let rule = format!(
+        "type='signal',\
+         path='{}',\
+         interface='org.freedesktop.DBus.Properties',\
+         member='PropertiesChanged'",
+        characteristic
+    );
+    if con.add_match(&rule).is_err() {
+        return false;
+    }
+    Message::new_method_call(
+        "org.bluez",
+        characteristic,
+        "org.bluez.GattCharacteristic1",
+        "StartNotify",
+    )
+    .ok()
+    .and_then(|msg| con.send_with_reply_and_block(msg, 5000).ok())
+    .is_some()
+}
+
+fn level_from_properties_changed(msg: &Message) -> Option<u8> {
+    let (interface, changed) =
+        msg.get2::<&str, HashMap<String, arg::Variant<Box<dyn arg::RefArg>>>>();
+    if interface? != "org.bluez.GattCharacteristic1" {
+        return None;
+    }
+    let bytes: Vec<u8> = changed?
+        .get("Value")?
+        .0
+        .as_iter()?
+        .filter_map(|b| b.as_u64().map(|b| b as u8))
+        .collect();
+    level_from_bytes(&bytes)
+}
+
+/// The Battery Level characteristic is a single byte holding a percentage.
+fn level_from_bytes(value: &[u8]) -> Option<u8> {
+    value.first().map(|level| (*level).min(100))
+}
+
+impl BleBattery {
+    fn render_level(&self, level: u8) -> Result<String> {
+        let values = map!("{level}" => Value::integer(level));
+        self.format.render_static_str(&values)
+    }
+}
+
+impl Block for BleBattery {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn update(&mut self) -> Result<Option<Update>> {
+        let state = *self
+            .state
+            .lock()
+            .block_error("ble_battery", "failed to acquire lock")?;
+
+        let prefix = if self.label.is_empty() {
+            String::new()
+        } else {
+            format!("{} ", self.label)
+        };
+        match (state.level, state.read_at) {
+            (Some(level), Some(_)) if state.connected => {
+                let level_str = self.render_level(level)?;
+                self.output.set_text(format!("{}{}", prefix, level_str));
+                self.output.set_state(match level {
+                    0..=15 => State::Critical,
+                    16..=30 => State::Warning,
+                    31..=60 => State::Info,
+                    _ => State::Good,
+                });
+            }
+            (Some(level), Some(read_at)) => {
+                // Out of range: show the last known value, with its age.
+                let level_str = self.render_level(level)?;
+                self.output.set_text(format!(
+                    "{}{} ({})",
+                    prefix,
+                    level_str,
+                    format_age(read_at.elapsed())
+                ));
+                self.output.set_state(State::Idle);
+            }
+            _ => {
+                self.output.set_text(format!("{}?", prefix));
+                self.output.set_state(State::Idle);
+            }
+        }
+
+        // Keep the age indicator current.
+        Ok(Some(Duration::from_secs(60).into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.output]
+    }
+
+    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_from_bytes() {
+        assert_eq!(level_from_bytes(&[87]), Some(87));
+        assert_eq!(level_from_bytes(&[255]), Some(100));
+        assert_eq!(level_from_bytes(&[]), None);
+    }
+}
@@ -1,17 +1,18 @@
 use serde_derive::Deserialize;
-use std::thread;
-use std::time::Instant;
 
 use crossbeam_channel::Sender;
 use dbus::ffidisp::stdintf::org_freedesktop_dbus::{ObjectManager, Properties};
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
+use crate::dbus_manager::{self, Bus};
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
+use crate::util::FormatTemplate;
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::button::ButtonWidget;
 
@@ -116,41 +117,25 @@ impl BluetoothDevice {
         Ok(())
     }
 
-    /// Monitor Bluetooth property changes in a separate thread and send updates
-    /// via the `update_request` channel.
-    pub fn monitor(&self, id: String, update_request: Sender<Task>) {
-        let path = self.path.clone();
-        thread::Builder::new()
-            .name("bluetooth".into())
-            .spawn(move || {
-                let con = dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::System)
-                    .expect("Failed to establish D-Bus connection.");
-                let rule = format!(
-                    "type='signal',\
-                 path='{}',\
-                 interface='org.freedesktop.DBus.Properties',\
-                 member='PropertiesChanged'",
-                    path
-                );
-
-                // Skip the NameAcquired event.
-                con.incoming(10_000).next();
-
-                con.add_match(&rule)
-                    .expect("Failed to add D-Bus match rule.");
-
-                loop {
-                    if con.incoming(10_000).next().is_some() {
-                        update_request
-                            .send(Task {
-                                id: id.clone(),
-                                update_time: Instant::now(),
-                            })
-                            .unwrap();
-                    }
-                }
-            })
-            .unwrap();
+    /// Monitor Bluetooth property changes and send updates via the
+    /// `update_request` channel.
+    pub fn monitor(&self, id: String, update_request: Sender<Task>) -> Result<()> {
+        let rule = format!(
+            "type='signal',\
+             path='{}',\
+             interface='org.freedesktop.DBus.Properties',\
+             member='PropertiesChanged'",
+            self.path
+        );
+        dbus_manager::request_updates(Bus::System, &rule, id.clone(), update_request.clone())
+            .block_error("bluetooth", "Failed to add D-Bus match rule.")?;
+        // The Battery1 interface comes and goes with the connection, which
+        // the object manager tells about instead.
+        let rule = "type='signal',\
+                    sender='org.bluez',\
+                    interface='org.freedesktop.DBus.ObjectManager'";
+        dbus_manager::request_updates(Bus::System, rule, id, update_request)
+            .block_error("bluetooth", "Failed to add D-Bus match rule.")
     }
 }
 
@@ -158,6 +143,10 @@ pub struct Bluetooth {
     id: String,
     output: ButtonWidget,
     device: BluetoothDevice,
+    format: FormatTemplate,
+    format_unavailable: FormatTemplate,
+    hide_disconnected: bool,
+    connected: bool,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -165,6 +154,28 @@ pub struct Bluetooth {
 pub struct BluetoothConfig {
     pub mac: String,
     pub label: Option<String>,
+
+    /// Format override, used when the device reports its battery
+    #[serde(default = "BluetoothConfig::default_format")]
+    pub format: String,
+
+    /// Format override, used when it does not
+    #[serde(default = "BluetoothConfig::default_format_unavailable")]
+    pub format_unavailable: String,
+
+    /// Whether to hide the block while the device is disconnected
+    #[serde(default)]
+    pub hide_disconnected: bool,
+}
+
+impl BluetoothConfig {
+    fn default_format() -> String {
+        "{label} {percentage}".to_owned()
+    }
+
+    fn default_format_unavailable() -> String {
+        "{label}".to_owned()
+    }
 }
 
 impl ConfigBlock for Bluetooth {
@@ -173,7 +184,7 @@ impl ConfigBlock for Bluetooth {
     fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
         let id: String = Uuid::new_v4().to_simple().to_string();
         let device = BluetoothDevice::new(block_config.mac, block_config.label)?;
-        device.monitor(id.clone(), send);
+        device.monitor(id.clone(), send)?;
 
         Ok(Bluetooth {
             id: id.clone(),
@@ -185,6 +196,12 @@ impl ConfigBlock for Bluetooth {
                 _ => "bluetooth",
             }),
             device,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("bluetooth", "Invalid format specified")?,
+            format_unavailable: FormatTemplate::from_string(&block_config.format_unavailable)
+                .block_error("bluetooth", "Invalid format_unavailable specified")?,
+            hide_disconnected: block_config.hide_disconnected,
+            connected: false,
         })
     }
 }
@@ -195,10 +212,17 @@ impl Block for Bluetooth {
     }
 
     fn update(&mut self) -> Result<Option<Update>> {
-        let connected = self.device.connected();
-        self.output.set_text(self.device.label.to_string());
-        self.output
-            .set_state(if connected { State::Good } else { State::Idle });
+        self.connected = self.device.connected();
+        let label = Value::from(self.device.label.as_str());
+        self.output.set_text(
+            self.format_unavailable
+                .render_static_str(&map!("{label}" => label.clone()))?,
+        );
+        self.output.set_state(if self.connected {
+            State::Good
+        } else {
+            State::Idle
+        });
 
         // Use battery info, when available.
         if let Some(value) = self.device.battery() {
@@ -209,25 +233,33 @@ impl Block for Bluetooth {
                 61..=100 => State::Good,
                 _ => State::Warning,
             });
-            self.output
-                .set_text(format!("{} {}%", self.device.label, value));
+            self.output.set_text(self.format.render_static_str(&map!(
+                "{label}" => label,
+                "{percentage}" => Value::integer(value).with_suffix("%")
+            ))?);
         }
 
         Ok(None)
     }
 
     fn click(&mut self, event: &I3BarEvent) -> Result<()> {
-        if let Some(ref name) = event.name {
-            if name.as_str() == self.id {
-                if let MouseButton::Right = event.button {
-                    self.device.toggle()?;
-                }
+        if event.matches_name(&self.id) {
+            if let MouseButton::Left | MouseButton::Right = event.button {
+                self.device.toggle()?;
             }
         }
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn view(&self) -> Vec<&dyn I3BarWidget> {
-        vec![&self.output]
+        if self.connected || !self.hide_disconnected {
+            vec![&self.output]
+        } else {
+            vec![]
+        }
     }
 }
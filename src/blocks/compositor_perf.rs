@@ -0,0 +1,952 @@
+//! A block showing how smoothly a Wayland compositor such as sway draws.
+//!
+//! sway's IPC has no frame timings, so the block measures them itself: a tiny
+//! Wayland client, in a thread of its own, keeps a transparent pixel on an
+//! overlay layer surface redrawn every frame and asks for presentation-time
+//! feedback (`wp_presentation`) on each of them. The time between two
+//! presentations is a frame time, and gaps in the vertical retrace counter are
+//! missed frames. The client speaks the wire protocol directly, and connects
+//! again, backing off, when the compositor goes away or restarts.
+//!
+//! There is nothing like this on X11, so the block hides itself there.
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CString;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+use nix::sys::uio::IoVec;
+use nix::unistd;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::scheduler::Task;
+use crate::stop::{self, Stop, Stopped, Wake};
+use crate::util::{xdg_runtime_dir, FormatTemplate};
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
+
+/// How long to wait before connecting again after the first failure. The
+/// delay doubles with every further failure.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// The id of the `wl_display` object, which every connection starts with.
+const DISPLAY: u32 = 1;
+/// `zwlr_layer_shell_v1.layer.overlay`
+const LAYER_OVERLAY: u32 = 3;
+/// `zwlr_layer_surface_v1.anchor.top` and `left`
+const ANCHOR_TOP_LEFT: u32 = 1 | 4;
+/// `wl_shm.format.argb8888`
+const FORMAT_ARGB8888: u32 = 0;
+/// `wp_presentation_feedback.kind.vsync`: `seq` counts vertical retraces.
+const PRESENTED_VSYNC: u32 = 0x1;
+
+pub struct CompositorPerf {
+    id: String,
+    text: TextWidget,
+    format: FormatTemplate,
+    interval: Duration,
+    missed_warning: u64,
+    missed_critical: u64,
+    stats: Arc<Mutex<FrameStats>>,
+    /// Stops the Wayland client when the block is dropped. `None` outside of
+    /// Wayland sessions, where the block is hidden.
+    client: Option<Stop>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CompositorPerfConfig {
+    /// Format override
+    #[serde(default = "CompositorPerfConfig::default_format")]
+    pub format: String,
+
+    /// Update interval in seconds
+    #[serde(
+        default = "CompositorPerfConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// How many seconds of frames the values are about
+    #[serde(
+        default = "CompositorPerfConfig::default_window",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub window: Duration,
+
+    /// Number of missed frames within the window where state is set to warning
+    #[serde(default = "CompositorPerfConfig::default_missed_warning")]
+    pub missed_warning: u64,
+
+    /// Number of missed frames within the window where state is set to critical
+    #[serde(default = "CompositorPerfConfig::default_missed_critical")]
+    pub missed_critical: u64,
+}
+
+impl CompositorPerfConfig {
+    fn default_format() -> String {
+        "{frame_time}ms {missed}".to_owned()
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_window() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn default_missed_warning() -> u64 {
+        1
+    }
+
+    fn default_missed_critical() -> u64 {
+        30
+    }
+}
+
+impl ConfigBlock for CompositorPerf {
+    type Config = CompositorPerfConfig;
+
+    fn new(block_config: Self::Config, config: Config, _: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        let stats = Arc::new(Mutex::new(FrameStats::new(block_config.window)));
+
+        let client = if wayland_socket().is_some() {
+            let (stop, stopped) = stop::pair()?;
+            let stats_copy = stats.clone();
+            let id_copy = id.clone();
+            thread::Builder::new()
+                .name("compositor_perf".into())
+                .spawn(move || run(&id_copy, &stats_copy, &stopped))
+                .block_error("compositor_perf", "failed to spawn thread")?;
+            Some(stop)
+        } else {
+            log_block_error(
+                &id,
+                "compositor_perf",
+                "not supported outside of Wayland (e.g. on X11), hiding the block",
+            );
+            None
+        };
+
+        Ok(CompositorPerf {
+            id,
+            text: TextWidget::new(config).with_text("…"),
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("compositor_perf", "Invalid format specified")?,
+            interval: block_config.interval,
+            missed_warning: block_config.missed_warning,
+            missed_critical: block_config.missed_critical,
+            stats,
+            client,
+        })
+    }
+}
+
+impl Block for CompositorPerf {
+    fn update(&mut self) -> Result<Option<Update>> {
+        if self.client.is_none() {
+            return Ok(None);
+        }
+        let summary = self
+            .stats
+            .lock()
+            .block_error("compositor_perf", "failed to acquire lock")?
+            .summary(Instant::now());
+        match summary {
+            Some((frame_time, missed)) => {
+                let milliseconds = frame_time.as_secs_f64() * 1000.0;
+                let fps = if milliseconds > 0.0 {
+                    1000.0 / milliseconds
+                } else {
+                    0.0
+                };
+                let values = map!("{frame_time}" => Value::float(milliseconds, 1),
+                                  "{fps}" => Value::integer(fps.round()),
+                                  "{missed}" => Value::integer(missed));
+                self.text.set_text(self.format.render_static_str(&values)?);
+                self.text.set_state(if missed >= self.missed_critical {
+                    State::Critical
+                } else if missed >= self.missed_warning {
+                    State::Warning
+                } else {
+                    State::Idle
+                });
+            }
+            // Not connected, or no frames yet.
+            None => {
+                self.text.set_text("…".to_owned());
+                self.text.set_state(State::Idle);
+            }
+        }
+        Ok(Some(self.interval.into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        if self.client.is_none() {
+            return Vec::new();
+        }
+        vec![&self.text]
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// The socket of the Wayland compositor, `None` outside of Wayland sessions.
+fn wayland_socket() -> Option<PathBuf> {
+    let display = std::env::var_os("WAYLAND_DISPLAY").filter(|display| !display.is_empty())?;
+    Some(xdg_runtime_dir().join(display))
+}
+
+/// A presentation of the surface, as the compositor reports it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Presentation {
+    /// In the presentation clock of the compositor.
+    time: Duration,
+    /// How long a frame of the output is, zero if unknown.
+    refresh: Duration,
+    /// The vertical retrace counter, if the output has one.
+    seq: Option<u64>,
+}
+
+/// The frames presented within the last `window`.
+#[derive(Debug)]
+struct FrameStats {
+    window: Duration,
+    last: Option<Presentation>,
+    /// When each frame was presented, how long it took and how many frames
+    /// were missed before it, oldest first.
+    frames: VecDeque<(Instant, Duration, u64)>,
+}
+
+impl FrameStats {
+    fn new(window: Duration) -> Self {
+        FrameStats {
+            window,
+            last: None,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Takes note of a presentation coming in at `now`.
+    fn presented(&mut self, presentation: Presentation, now: Instant) {
+        if let Some(last) = self.last {
+            let frame_time = presentation.time.checked_sub(last.time).unwrap_or_default();
+            // Longer pauses, e.g. while the output was off, are no frames.
+            if frame_time <= self.window {
+                let missed = missed_frames(&last, &presentation, frame_time);
+                self.frames.push_back((now, frame_time, missed));
+            }
+        }
+        self.last = Some(presentation);
+        self.expire(now);
+    }
+
+    /// Forgets everything, the next connection starts afresh.
+    fn disconnected(&mut self) {
+        self.last = None;
+        self.frames.clear();
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(at, _, _)) = self.frames.front() {
+            if at + self.window >= now {
+                break;
+            }
+            self.frames.pop_front();
+        }
+    }
+
+    /// The average frame time and the number of frames missed within the
+    /// window, `None` without any frames.
+    fn summary(&mut self, now: Instant) -> Option<(Duration, u64)> {
+        self.expire(now);
+        if self.frames.is_empty() {
+            return None;
+        }
+        let total: Duration = self.frames.iter().map(|&(_, time, _)| time).sum();
+        let missed = self.frames.iter().map(|&(_, _, missed)| missed).sum();
+        Some((total / self.frames.len() as u32, missed))
+    }
+}
+
+/// How many frames the compositor missed between the presentations of two
+/// surface commits in a row, each made as soon as the compositor asked for
+/// the next frame.
+fn missed_frames(last: &Presentation, next: &Presentation, frame_time: Duration) -> u64 {
+    match (last.seq, next.seq) {
+        (Some(last), Some(next)) => next.saturating_sub(last).saturating_sub(1),
+        _ if next.refresh > Duration::default() => {
+            let refresh = next.refresh.as_nanos();
+            let frames = (frame_time.as_nanos() + refresh / 2) / refresh;
+            (frames as u64).saturating_sub(1)
+        }
+        _ => 0,
+    }
+}
+
+/// Measures the frames of the compositor until `stopped`, connecting again
+/// whenever the connection fails.
+fn run(id: &str, stats: &Mutex<FrameStats>, stopped: &Stopped) {
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        let result = match wayland_socket() {
+            Some(socket) => measure(&socket, stats, stopped),
+            None => Err(wayland_error("WAYLAND_DISPLAY is no longer set")),
+        };
+        let presented = match stats.lock() {
+            Ok(mut stats) => {
+                let presented = stats.last.is_some();
+                stats.disconnected();
+                presented
+            }
+            Err(_) => return,
+        };
+        match result {
+            Ok(()) => return,
+            Err(error) => log_block_error(id, "compositor_perf", error.parts().1),
+        }
+        // A connection that worked for a while starts the delays over.
+        if presented {
+            delay = RECONNECT_DELAY;
+        }
+        if !stopped.sleep(delay) {
+            return;
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+fn wayland_error(message: &str) -> Error {
+    BlockError("compositor_perf".to_owned(), message.to_owned())
+}
+
+/// The objects created by the client which it handles events of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Object {
+    Registry,
+    /// The `wl_callback` that is done once all globals are announced.
+    Sync,
+    LayerSurface,
+    /// The `wl_callback` of the next frame.
+    Frame,
+    Feedback,
+}
+
+/// What `draw` needs to draw another frame.
+#[derive(Debug, Clone, Copy)]
+struct Surface {
+    surface: u32,
+    buffer: u32,
+    presentation: u32,
+}
+
+/// Connects to the compositor at `socket` and measures its frames until
+/// `stopped`. Fails once the connection does.
+fn measure(socket: &Path, stats: &Mutex<FrameStats>, stopped: &Stopped) -> Result<()> {
+    let mut client = Client::connect(socket)?;
+    let registry = client.new_id(Some(Object::Registry));
+    // wl_display.get_registry and sync
+    client.send(DISPLAY, 1, &[Arg::Uint(registry)])?;
+    let sync = client.new_id(Some(Object::Sync));
+    client.send(DISPLAY, 0, &[Arg::Uint(sync)])?;
+
+    let mut globals = HashMap::new();
+    let mut surface = None;
+    let mut drawing = false;
+    loop {
+        let events = match client.events(stopped)? {
+            Some(events) => events,
+            None => return Ok(()),
+        };
+        for event in events {
+            let mut args = Args(&event.args);
+            match (event.object, client.object(event.object), event.opcode) {
+                // wl_display.error
+                (DISPLAY, _, 0) => {
+                    let (_object, code, message) = (args.uint()?, args.uint()?, args.string()?);
+                    return Err(wayland_error(&format!(
+                        "protocol error {}: {}",
+                        code, message
+                    )));
+                }
+                // wl_display.delete_id
+                (DISPLAY, _, 1) => client.delete(args.uint()?),
+                // wl_registry.global
+                (_, Some(Object::Registry), 0) => {
+                    let (name, interface, version) = (args.uint()?, args.string()?, args.uint()?);
+                    globals.insert(interface, (name, version));
+                }
+                // wl_callback.done of the sync
+                (_, Some(Object::Sync), 0) => {
+                    surface = Some(create_surface(&mut client, registry, &globals)?);
+                }
+                // zwlr_layer_surface_v1.configure
+                (_, Some(Object::LayerSurface), 0) => {
+                    let serial = args.uint()?;
+                    // ack_configure, the size asked for will do.
+                    client.send(event.object, 6, &[Arg::Uint(serial)])?;
+                    if let (Some(surface), false) = (surface, drawing) {
+                        drawing = true;
+                        draw(&mut client, surface)?;
+                    }
+                }
+                // zwlr_layer_surface_v1.closed
+                (_, Some(Object::LayerSurface), 1) => {
+                    return Err(wayland_error("the compositor closed the surface"));
+                }
+                // wl_callback.done of a frame
+                (_, Some(Object::Frame), 0) => {
+                    if let Some(surface) = surface {
+                        draw(&mut client, surface)?;
+                    }
+                }
+                // wp_presentation_feedback.presented
+                (_, Some(Object::Feedback), 1) => {
+                    let presentation = parse_presented(&mut args)?;
+                    stats
+                        .lock()
+                        .block_error("compositor_perf", "failed to acquire lock")?
+                        .presented(presentation, Instant::now());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Binds the globals needed and creates a transparent 1x1 overlay surface
+/// taking no input, for `draw` to redraw every frame.
+fn create_surface(
+    client: &mut Client,
+    registry: u32,
+    globals: &HashMap<String, (u32, u32)>,
+) -> Result<Surface> {
+    let bind = |client: &mut Client, interface: &str, version: u32| -> Result<u32> {
+        let &(name, advertised) = globals.get(interface).ok_or_else(|| {
+            wayland_error(&format!("the compositor does not support {}", interface))
+        })?;
+        let id = client.new_id(None);
+        client.send(
+            registry,
+            0,
+            &[
+                Arg::Uint(name),
+                Arg::Str(interface),
+                Arg::Uint(version.min(advertised)),
+                Arg::Uint(id),
+            ],
+        )?;
+        Ok(id)
+    };
+    let compositor = bind(client, "wl_compositor", 4)?;
+    let shm = bind(client, "wl_shm", 1)?;
+    let layer_shell = bind(client, "zwlr_layer_shell_v1", 1)?;
+    let presentation = bind(client, "wp_presentation", 1)?;
+
+    // wl_compositor.create_surface and create_region, with the empty region
+    // as the input region of the surface, so clicks go through it.
+    let surface = client.new_id(None);
+    client.send(compositor, 0, &[Arg::Uint(surface)])?;
+    let region = client.new_id(None);
+    client.send(compositor, 1, &[Arg::Uint(region)])?;
+    client.send(surface, 5, &[Arg::Uint(region)])?;
+    client.send(region, 0, &[])?;
+
+    // zwlr_layer_shell_v1.get_layer_surface on any output, then set_size,
+    // set_anchor and a commit for the compositor to configure it.
+    let layer_surface = client.new_id(Some(Object::LayerSurface));
+    client.send(
+        layer_shell,
+        0,
+        &[
+            Arg::Uint(layer_surface),
+            Arg::Uint(surface),
+            Arg::Uint(0),
+            Arg::Uint(LAYER_OVERLAY),
+            Arg::Str("i3status-rs"),
+        ],
+    )?;
+    client.send(layer_surface, 0, &[Arg::Uint(1), Arg::Uint(1)])?;
+    client.send(layer_surface, 1, &[Arg::Uint(ANCHOR_TOP_LEFT)])?;
+    client.send(surface, 6, &[])?;
+
+    // A transparent pixel: zeroed shared memory, in a wl_shm_pool holding
+    // just the one wl_buffer.
+    let name = CString::new("i3status-rs").unwrap_or_default();
+    let fd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC)
+        .block_error("compositor_perf", "failed to create shared memory")?;
+    let pool = client.new_id(None);
+    let sent = unistd::ftruncate(fd, 4)
+        .block_error("compositor_perf", "failed to create shared memory")
+        .and_then(|_| client.send_fd(shm, 0, &[Arg::Uint(pool), Arg::Int(4)], fd));
+    unistd::close(fd).ok();
+    sent?;
+    let buffer = client.new_id(None);
+    client.send(
+        pool,
+        0,
+        &[
+            Arg::Uint(buffer),
+            Arg::Int(0),
+            Arg::Int(1),
+            Arg::Int(1),
+            Arg::Int(4),
+            Arg::Uint(FORMAT_ARGB8888),
+        ],
+    )?;
+    client.send(pool, 1, &[])?;
+
+    Ok(Surface {
+        surface,
+        buffer,
+        presentation,
+    })
+}
+
+/// Redraws the pixel, asking for the next frame and for feedback on when
+/// this one is presented.
+fn draw(client: &mut Client, surface: Surface) -> Result<()> {
+    let Surface {
+        surface,
+        buffer,
+        presentation,
+    } = surface;
+    // wl_surface.attach, damage and frame
+    client.send(surface, 1, &[Arg::Uint(buffer), Arg::Int(0), Arg::Int(0)])?;
+    client.send(
+        surface,
+        2,
+        &[Arg::Int(0), Arg::Int(0), Arg::Int(1), Arg::Int(1)],
+    )?;
+    let frame = client.new_id(Some(Object::Frame));
+    client.send(surface, 3, &[Arg::Uint(frame)])?;
+    // wp_presentation.feedback
+    let feedback = client.new_id(Some(Object::Feedback));
+    client.send(presentation, 1, &[Arg::Uint(surface), Arg::Uint(feedback)])?;
+    // wl_surface.commit
+    client.send(surface, 6, &[])
+}
+
+/// The arguments of `wp_presentation_feedback.presented`.
+fn parse_presented(args: &mut Args) -> Result<Presentation> {
+    let seconds = (args.uint()? as u64) << 32 | args.uint()? as u64;
+    let nanoseconds = args.uint()?;
+    let refresh = args.uint()?;
+    let seq = (args.uint()? as u64) << 32 | args.uint()? as u64;
+    let flags = args.uint()?;
+    Ok(Presentation {
+        time: Duration::new(seconds, nanoseconds),
+        refresh: Duration::from_nanos(refresh as u64),
+        seq: Some(seq).filter(|_| flags & PRESENTED_VSYNC != 0),
+    })
+}
+
+/// An argument of a request. Object ids and new ids are `Uint`s, and file
+/// descriptors are sent alongside, see `Client::send_fd`.
+enum Arg<'a> {
+    Uint(u32),
+    Int(i32),
+    Str(&'a str),
+}
+
+/// A request as sent over the wire.
+fn encode(object: u32, opcode: u16, args: &[Arg]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for arg in args {
+        match *arg {
+            Arg::Uint(value) => body.extend_from_slice(&value.to_ne_bytes()),
+            Arg::Int(value) => body.extend_from_slice(&value.to_ne_bytes()),
+            Arg::Str(value) => {
+                // The length includes the terminating zero, and the string
+                // is padded to 32 bits.
+                body.extend_from_slice(&(value.len() as u32 + 1).to_ne_bytes());
+                body.extend_from_slice(value.as_bytes());
+                body.push(0);
+                while body.len() % 4 != 0 {
+                    body.push(0);
+                }
+            }
+        }
+    }
+    let header = ((8 + body.len() as u32) << 16) | opcode as u32;
+    let mut message = Vec::with_capacity(8 + body.len());
+    message.extend_from_slice(&object.to_ne_bytes());
+    message.extend_from_slice(&header.to_ne_bytes());
+    message.extend_from_slice(&body);
+    message
+}
+
+/// An event as received over the wire.
+#[derive(Debug, PartialEq)]
+struct Event {
+    object: u32,
+    opcode: u16,
+    args: Vec<u8>,
+}
+
+/// Takes the complete events off the start of `incoming`.
+fn decode(incoming: &mut Vec<u8>) -> Result<Vec<Event>> {
+    let word = |bytes: &[u8]| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let mut events = Vec::new();
+    let mut start = 0;
+    while incoming.len() - start >= 8 {
+        let object = word(&incoming[start..]);
+        let header = word(&incoming[start + 4..]);
+        let size = (header >> 16) as usize;
+        if size < 8 {
+            return Err(wayland_error("the compositor sent an invalid message"));
+        }
+        if incoming.len() - start < size {
+            break;
+        }
+        events.push(Event {
+            object,
+            opcode: (header & 0xffff) as u16,
+            args: incoming[start + 8..start + size].to_vec(),
+        });
+        start += size;
+    }
+    incoming.drain(..start);
+    Ok(events)
+}
+
+/// Reads the arguments of an event one after the other.
+struct Args<'a>(&'a [u8]);
+
+impl Args<'_> {
+    fn uint(&mut self) -> Result<u32> {
+        if self.0.len() < 4 {
+            return Err(wayland_error("the compositor sent a truncated message"));
+        }
+        let (value, rest) = self.0.split_at(4);
+        self.0 = rest;
+        Ok(u32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.uint()? as usize;
+        let padded = length.div_ceil(4) * 4;
+        if length == 0 || self.0.len() < padded {
+            return Err(wayland_error("the compositor sent a truncated message"));
+        }
+        let value = String::from_utf8_lossy(&self.0[..length - 1]).into_owned();
+        self.0 = &self.0[padded..];
+        Ok(value)
+    }
+}
+
+/// A connection to the compositor.
+struct Client {
+    socket: UnixStream,
+    /// What was received but not decoded yet.
+    incoming: Vec<u8>,
+    last_id: u32,
+    /// Ids the compositor deleted, to use again.
+    free_ids: Vec<u32>,
+    objects: HashMap<u32, Object>,
+}
+
+impl Client {
+    fn connect(socket: &Path) -> Result<Self> {
+        let socket = UnixStream::connect(socket).map_err(|e| {
+            wayland_error(&format!("failed to connect to {}: {}", socket.display(), e))
+        })?;
+        Ok(Client {
+            socket,
+            incoming: Vec::new(),
+            last_id: DISPLAY,
+            free_ids: Vec::new(),
+            objects: HashMap::new(),
+        })
+    }
+
+    /// A new object id, remembering that it is `object` if it is one the
+    /// client handles events of.
+    fn new_id(&mut self, object: Option<Object>) -> u32 {
+        let id = match self.free_ids.pop() {
+            Some(id) => id,
+            None => {
+                self.last_id += 1;
+                self.last_id
+            }
+        };
+        if let Some(object) = object {
+            self.objects.insert(id, object);
+        }
+        id
+    }
+
+    fn object(&self, id: u32) -> Option<Object> {
+        self.objects.get(&id).copied()
+    }
+
+    fn delete(&mut self, id: u32) {
+        self.objects.remove(&id);
+        self.free_ids.push(id);
+    }
+
+    fn send(&mut self, object: u32, opcode: u16, args: &[Arg]) -> Result<()> {
+        self.socket
+            .write_all(&encode(object, opcode, args))
+            .map_err(|e| wayland_error(&format!("failed to send a request: {}", e)))
+    }
+
+    /// Sends a request with the file descriptor `fd` as its only one.
+    fn send_fd(&mut self, object: u32, opcode: u16, args: &[Arg], fd: RawFd) -> Result<()> {
+        let message = encode(object, opcode, args);
+        let sent = sendmsg(
+            self.socket.as_raw_fd(),
+            &[IoVec::from_slice(&message)],
+            &[ControlMessage::ScmRights(&[fd])],
+            MsgFlags::empty(),
+            None,
+        )
+        .block_error("compositor_perf", "failed to send a request")?;
+        if sent != message.len() {
+            return Err(wayland_error("failed to send a request"));
+        }
+        Ok(())
+    }
+
+    /// Waits for events, `None` once `stopped`.
+    fn events(&mut self, stopped: &Stopped) -> Result<Option<Vec<Event>>> {
+        if stopped.wait(Some(self.socket.as_raw_fd()), None)? == Wake::Stopped {
+            return Ok(None);
+        }
+        let mut buffer = [0; 4096];
+        let read = match self.socket.read(&mut buffer) {
+            Ok(0) => return Err(wayland_error("the compositor closed the connection")),
+            Ok(read) => read,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => 0,
+            Err(e) => {
+                return Err(wayland_error(&format!(
+                    "failed to read from the compositor: {}",
+                    e
+                )))
+            }
+        };
+        self.incoming.extend_from_slice(&buffer[..read]);
+        decode(&mut self.incoming).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presentation(milliseconds: u64, seq: Option<u64>) -> Presentation {
+        Presentation {
+            time: Duration::from_millis(milliseconds),
+            refresh: Duration::from_micros(16_667),
+            seq,
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            encode(3, 1, &[Arg::Uint(7), Arg::Int(-1)]),
+            [
+                3u32.to_ne_bytes(),
+                (16u32 << 16 | 1).to_ne_bytes(),
+                7u32.to_ne_bytes(),
+                (-1i32).to_ne_bytes()
+            ]
+            .concat()
+        );
+        // Strings are zero-terminated and padded.
+        let message = encode(2, 0, &[Arg::Str("wl_shm")]);
+        assert_eq!(message.len(), 8 + 4 + 8);
+        assert_eq!(&message[8..12], &7u32.to_ne_bytes());
+        assert_eq!(&message[12..], b"wl_shm\0\0");
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut global = encode(
+            2,
+            0,
+            &[Arg::Uint(5), Arg::Str("wp_presentation"), Arg::Uint(1)],
+        );
+        let mut incoming = global.clone();
+        // Half of another event.
+        incoming.extend_from_slice(&global[..10]);
+        let events = decode(&mut incoming).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].object, events[0].opcode), (2, 0));
+        let mut args = Args(&events[0].args);
+        assert_eq!(args.uint().unwrap(), 5);
+        assert_eq!(args.string().unwrap(), "wp_presentation");
+        assert_eq!(args.uint().unwrap(), 1);
+        assert!(args.uint().is_err());
+
+        // The rest of it comes in later.
+        incoming.extend_from_slice(&global.split_off(10));
+        assert_eq!(decode(&mut incoming).unwrap().len(), 1);
+        assert!(incoming.is_empty());
+
+        // A size smaller than the header is nonsense.
+        let mut invalid = [1u32.to_ne_bytes(), 4u32.to_ne_bytes()].concat();
+        assert!(decode(&mut invalid).is_err());
+    }
+
+    #[test]
+    fn test_parse_presented() {
+        let args: Vec<u8> = [0, 12, 500_000_000, 16_666_666, 0, 42, PRESENTED_VSYNC]
+            .iter()
+            .flat_map(|word: &u32| word.to_ne_bytes().to_vec())
+            .collect();
+        assert_eq!(
+            parse_presented(&mut Args(&args)).unwrap(),
+            Presentation {
+                time: Duration::from_millis(12_500),
+                refresh: Duration::from_nanos(16_666_666),
+                seq: Some(42),
+            }
+        );
+        // Without vsync, seq is not a retrace counter.
+        let mut args = args;
+        let flags = args.len() - 4;
+        args[flags..].copy_from_slice(&0u32.to_ne_bytes());
+        assert_eq!(parse_presented(&mut Args(&args)).unwrap().seq, None);
+    }
+
+    #[test]
+    fn test_missed_frames() {
+        let frame = Duration::from_micros(16_667);
+        // From the retrace counter if there is one, from the refresh rate
+        // otherwise.
+        assert_eq!(
+            missed_frames(&presentation(0, Some(1)), &presentation(17, Some(2)), frame),
+            0
+        );
+        assert_eq!(
+            missed_frames(&presentation(0, Some(1)), &presentation(50, Some(4)), frame),
+            2
+        );
+        assert_eq!(
+            missed_frames(&presentation(0, None), &presentation(50, None), frame * 3),
+            2
+        );
+        assert_eq!(
+            missed_frames(&presentation(0, None), &presentation(17, None), frame),
+            0
+        );
+    }
+
+    #[test]
+    fn test_frame_stats() {
+        let start = Instant::now();
+        let at = |milliseconds| start + Duration::from_millis(milliseconds);
+        let mut stats = FrameStats::new(Duration::from_secs(1));
+        assert_eq!(stats.summary(start), None);
+
+        stats.presented(presentation(0, Some(10)), at(0));
+        assert_eq!(stats.summary(at(0)), None);
+        stats.presented(presentation(20, Some(11)), at(20));
+        stats.presented(presentation(80, Some(15)), at(80));
+        assert_eq!(stats.summary(at(80)), Some((Duration::from_millis(40), 3)));
+
+        // Frames leave the window, and a pause is not a frame.
+        stats.presented(presentation(5000, Some(300)), at(5000));
+        assert_eq!(stats.summary(at(5000)), None);
+        stats.presented(presentation(5016, Some(301)), at(5016));
+        assert_eq!(
+            stats.summary(at(5016)),
+            Some((Duration::from_millis(16), 0))
+        );
+
+        stats.disconnected();
+        assert_eq!(stats.summary(at(5016)), None);
+    }
+
+    #[test]
+    fn test_measure() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let socket = dir.path().join("wayland-0");
+        let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        let compositor = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let presented = |seq: u32, milliseconds: u32| -> Vec<Arg> {
+                [
+                    0,
+                    1,
+                    milliseconds * 1_000_000,
+                    16_666_666,
+                    0,
+                    seq,
+                    PRESENTED_VSYNC,
+                ]
+                .iter()
+                .map(|&arg| Arg::Uint(arg))
+                .collect()
+            };
+            // The ids are the ones the client takes, in order: the registry
+            // and the sync, the four globals, the surface, the region, the
+            // layer surface, the pool and the buffer, then a frame callback
+            // and a feedback per frame.
+            let globals = [
+                "wl_compositor",
+                "wl_shm",
+                "zwlr_layer_shell_v1",
+                "wp_presentation",
+            ];
+            let mut events = Vec::new();
+            for (name, &interface) in globals.iter().enumerate() {
+                let version = Arg::Uint(1);
+                events.push(encode(
+                    2,
+                    0,
+                    &[Arg::Uint(name as u32), Arg::Str(interface), version],
+                ));
+            }
+            events.push(encode(3, 0, &[Arg::Uint(0)]));
+            events.push(encode(10, 0, &[Arg::Uint(7), Arg::Uint(1), Arg::Uint(1)]));
+            events.push(encode(14, 1, &presented(1, 0)));
+            events.push(encode(13, 0, &[Arg::Uint(0)]));
+            events.push(encode(16, 1, &presented(3, 33)));
+            stream.write_all(&events.concat()).unwrap();
+            // Until the client hangs up.
+            let mut buffer = [0; 4096];
+            while stream.read(&mut buffer).unwrap() > 0 {}
+        });
+
+        let stats = Arc::new(Mutex::new(FrameStats::new(Duration::from_secs(5))));
+        let (stop, stopped) = stop::pair().unwrap();
+        let stats_copy = stats.clone();
+        let client = thread::spawn(move || measure(&socket, &stats_copy, &stopped));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let summary = loop {
+            let summary = stats.lock().unwrap().summary(Instant::now());
+            if summary.is_some() || Instant::now() > deadline {
+                break summary;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(summary, Some((Duration::from_millis(33), 1)));
+
+        drop(stop);
+        assert!(client.join().unwrap().is_ok());
+        compositor.join().unwrap();
+    }
+}
@@ -12,12 +12,11 @@ use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
-use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
-use crate::subprocess::spawn_child_async;
 use crate::util::{format_percent_bar, FormatTemplate};
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::button::ButtonWidget;
+use crate::widgets::graph::{GraphConfig, GraphWidget};
 
 /// Maximum number of CPUs we support.
 const MAX_CPUS: usize = 32;
@@ -31,11 +30,11 @@ pub struct Cpu {
     minimum_info: u64,
     minimum_warning: u64,
     minimum_critical: u64,
-    on_click: Option<String>,
     format: FormatTemplate,
     has_barchart: bool,
     has_frequency: bool,
     per_core: bool,
+    graph: Option<GraphWidget>,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -60,9 +59,6 @@ pub struct CpuConfig {
     #[serde(default = "CpuConfig::default_critical")]
     pub critical: u64,
 
-    #[serde(default = "CpuConfig::default_on_click")]
-    pub on_click: Option<String>,
-
     /// Display frequency
     #[serde(default = "CpuConfig::default_frequency")]
     pub frequency: bool,
@@ -74,6 +70,10 @@ pub struct CpuConfig {
     /// Compute the metrics (utilization and frequency) per core.
     #[serde(default)]
     pub per_core: bool,
+
+    /// Show a graph of the recent utilization next to the text
+    #[serde(default)]
+    pub graph: Option<GraphConfig>,
 }
 
 impl CpuConfig {
@@ -100,10 +100,6 @@ impl CpuConfig {
     fn default_frequency() -> bool {
         false
     }
-
-    fn default_on_click() -> Option<String> {
-        None
-    }
 }
 
 impl ConfigBlock for Cpu {
@@ -127,6 +123,11 @@ impl ConfigBlock for Cpu {
         Ok(Cpu {
             id: id.clone(),
             update_interval: block_config.interval,
+            graph: block_config.graph.map(|graph| {
+                GraphWidget::new(config.clone())
+                    .with_graph(&graph)
+                    .with_range(Some(0.0), Some(100.0))
+            }),
             output: ButtonWidget::new(config, &id).with_icon("cpu"),
             prev_idles: [0; MAX_CPUS],
             prev_non_idles: [0; MAX_CPUS],
@@ -138,7 +139,6 @@ impl ConfigBlock for Cpu {
             has_frequency: format.contains("{frequency}"),
             has_barchart: format.contains("{barchart}"),
             per_core: block_config.per_core,
-            on_click: block_config.on_click,
         })
     }
 }
@@ -221,12 +221,17 @@ impl Block for Cpu {
 
         let avg_utilization = (100.0 * cpu_utilizations[0]) as u64;
 
-        self.output.set_state(match avg_utilization {
+        let state = match avg_utilization {
             x if x > self.minimum_critical => State::Critical,
             x if x > self.minimum_warning => State::Warning,
             x if x > self.minimum_info => State::Info,
             _ => State::Idle,
-        });
+        };
+        self.output.set_state(state);
+        if let Some(ref mut graph) = self.graph {
+            graph.set_state(state);
+            graph.push(avg_utilization);
+        }
 
         let mut barchart = String::new();
 
@@ -254,19 +259,10 @@ impl Block for Cpu {
     }
 
     fn view(&self) -> Vec<&dyn I3BarWidget> {
-        vec![&self.output]
-    }
-
-    fn click(&mut self, e: &I3BarEvent) -> Result<()> {
-        if e.matches_name(self.id()) {
-            if let MouseButton::Left = e.button {
-                if let Some(ref cmd) = self.on_click {
-                    spawn_child_async("sh", &["-c", cmd])
-                        .block_error("cpu", "could not spawn child")?;
-                }
-            }
+        match self.graph {
+            Some(ref graph) => vec![&self.output, graph],
+            None => vec![&self.output],
         }
-        Ok(())
     }
 
     fn id(&self) -> &str {
@@ -1,14 +1,20 @@
 use std::env;
+use std::io::{BufRead, BufReader};
 use std::iter::{Cycle, Peekable};
-use std::process::Command;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 use std::vec;
 
 use crossbeam_channel::Sender;
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
 use serde_derive::Deserialize;
 use uuid::Uuid;
 
-use crate::blocks::{Block, ConfigBlock, Update};
+use crate::blocks::{Block, ClickSafety, ConfigBlock, Update};
 use crate::config::Config;
 use crate::de::deserialize_update;
 use crate::errors::*;
@@ -27,6 +33,19 @@ pub struct Custom {
     cycle: Option<Peekable<Cycle<vec::IntoIter<String>>>>,
     tx_update_request: Sender<Task>,
     pub json: bool,
+    persistent: Option<Arc<Mutex<Persistent>>>,
+}
+
+/// A command kept running with `persistent`, shared with the thread reading
+/// its output.
+#[derive(Default)]
+struct Persistent {
+    /// The last line the command printed.
+    line: Option<String>,
+    /// The process group of the running command.
+    group: Option<Pid>,
+    /// Set once the block is gone, to not restart the command.
+    stopped: bool,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -51,6 +70,10 @@ pub struct CustomConfig {
     /// Parse command output if it contains valid bar JSON
     #[serde(default = "CustomConfig::default_json")]
     pub json: bool,
+
+    /// Run the command once and show every line it prints
+    #[serde(default = "CustomConfig::default_persistent")]
+    pub persistent: bool,
 }
 
 impl CustomConfig {
@@ -61,6 +84,10 @@ impl CustomConfig {
     fn default_json() -> bool {
         false
     }
+
+    fn default_persistent() -> bool {
+        false
+    }
 }
 
 impl ConfigBlock for Custom {
@@ -76,6 +103,7 @@ impl ConfigBlock for Custom {
             cycle: None,
             tx_update_request: tx,
             json: block_config.json,
+            persistent: None,
         };
         custom.output = ButtonWidget::new(config, &custom.id);
 
@@ -83,6 +111,28 @@ impl ConfigBlock for Custom {
             custom.on_click = Some(on_click)
         };
 
+        if block_config.persistent {
+            let command = match (block_config.command, block_config.cycle.is_some()) {
+                (Some(command), false) => command,
+                _ => {
+                    return Err(BlockError(
+                        "custom".to_string(),
+                        "persistent needs a command and no cycle".to_string(),
+                    ))
+                }
+            };
+            let persistent = Arc::new(Mutex::new(Persistent::default()));
+            run_persistent(
+                command,
+                custom.update_interval.clone(),
+                persistent.clone(),
+                custom.id.clone(),
+                custom.tx_update_request.clone(),
+            )?;
+            custom.persistent = Some(persistent);
+            return Ok(custom);
+        }
+
         if let Some(cycle) = block_config.cycle {
             custom.cycle = Some(cycle.into_iter().cycle().peekable());
             return Ok(custom);
@@ -96,6 +146,97 @@ impl ConfigBlock for Custom {
     }
 }
 
+/// Runs `command` in a thread of its own, keeping the last line it printed in
+/// `persistent` and asking for an update of the block with `id` on each. Once
+/// the command exits, it is started again after `restart`.
+fn run_persistent(
+    command: String,
+    restart: Update,
+    persistent: Arc<Mutex<Persistent>>,
+    id: String,
+    tx_update_request: Sender<Task>,
+) -> Result<()> {
+    let shell = env::var("SHELL").unwrap_or_else(|_| "sh".to_owned());
+    thread::Builder::new()
+        .name("custom".into())
+        .spawn(move || loop {
+            // In a process group of its own, so that stopping it stops the
+            // whole pipeline.
+            let mut child = match Command::new(&shell)
+                .args(["-c", &command])
+                .stdout(Stdio::piped())
+                .process_group(0)
+                .spawn()
+            {
+                Ok(child) => child,
+                Err(e) => {
+                    persistent.lock().unwrap().line = Some(e.to_string());
+                    tx_update_request
+                        .send(Task {
+                            id: id.clone(),
+                            update_time: Instant::now(),
+                        })
+                        .ok();
+                    return;
+                }
+            };
+            let group = Pid::from_raw(child.id() as i32);
+            {
+                let mut persistent = persistent.lock().unwrap();
+                if persistent.stopped {
+                    killpg(group, Signal::SIGTERM).ok();
+                    return;
+                }
+                persistent.group = Some(group);
+            }
+
+            if let Some(stdout) = child.stdout.take() {
+                for line in BufReader::new(stdout).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    persistent.lock().unwrap().line = Some(line.trim().to_owned());
+                    let task = Task {
+                        id: id.clone(),
+                        update_time: Instant::now(),
+                    };
+                    if tx_update_request.send(task).is_err() {
+                        break;
+                    }
+                }
+            }
+            child.wait().ok();
+
+            {
+                let mut persistent = persistent.lock().unwrap();
+                persistent.group = None;
+                if persistent.stopped {
+                    return;
+                }
+            }
+            match restart {
+                Update::Every(delay) => thread::sleep(delay),
+                _ => return,
+            }
+        })
+        .block_error("custom", "failed to spawn thread")?;
+    Ok(())
+}
+
+impl Drop for Custom {
+    fn drop(&mut self) {
+        if let Some(persistent) = &self.persistent {
+            if let Ok(mut persistent) = persistent.lock() {
+                persistent.stopped = true;
+                if let Some(group) = persistent.group {
+                    killpg(group, Signal::SIGTERM).ok();
+                }
+            }
+        }
+    }
+}
+
 fn default_icon() -> String {
     String::from("")
 }
@@ -111,23 +252,13 @@ struct Output {
     #[serde(default = "default_state")]
     state: State,
     text: String,
+    #[serde(default)]
+    short_text: Option<String>,
 }
 
-impl Block for Custom {
-    fn update(&mut self) -> Result<Option<Update>> {
-        let command_str = self
-            .cycle
-            .as_mut()
-            .map(|c| c.peek().cloned().unwrap_or_else(|| "".to_owned()))
-            .or_else(|| self.command.clone())
-            .unwrap_or_else(|| "".to_owned());
-
-        let raw_output = Command::new(env::var("SHELL").unwrap_or_else(|_| "sh".to_owned()))
-            .args(&["-c", &command_str])
-            .output()
-            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
-            .unwrap_or_else(|e| e.to_string());
-
+impl Custom {
+    /// Shows the output of the command, parsed as JSON with `json`.
+    fn show(&mut self, raw_output: String) -> Result<()> {
         if self.json {
             let output: Output = match serde_json::from_str(&*raw_output) {
                 Err(e) => {
@@ -141,13 +272,45 @@ impl Block for Custom {
             self.output.set_icon(&output.icon);
             self.output.set_state(output.state);
             self.output.set_text(output.text);
+            self.output.set_short_text(output.short_text);
         } else {
             self.output.set_text(raw_output);
         }
+        Ok(())
+    }
+}
+
+impl Block for Custom {
+    fn update(&mut self) -> Result<Option<Update>> {
+        if let Some(persistent) = &self.persistent {
+            let line = persistent.lock().unwrap().line.clone();
+            if let Some(line) = line {
+                self.show(line)?;
+            }
+            return Ok(None);
+        }
+
+        let command_str = self
+            .cycle
+            .as_mut()
+            .map(|c| c.peek().cloned().unwrap_or_else(|| "".to_owned()))
+            .or_else(|| self.command.clone())
+            .unwrap_or_else(|| "".to_owned());
+
+        let raw_output = Command::new(env::var("SHELL").unwrap_or_else(|_| "sh".to_owned()))
+            .args(&["-c", &command_str])
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+            .unwrap_or_else(|e| e.to_string());
+        self.show(raw_output)?;
 
         Ok(Some(self.update_interval.clone()))
     }
 
+    fn push_text(&mut self, text: &str) -> Result<()> {
+        self.show(text.to_string())
+    }
+
     fn view(&self) -> Vec<&dyn I3BarWidget> {
         vec![&self.output]
     }
@@ -183,6 +346,10 @@ impl Block for Custom {
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
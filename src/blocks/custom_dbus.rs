@@ -15,6 +15,7 @@ use crate::config::Config;
 use crate::errors::*;
 use crate::input::I3BarEvent;
 use crate::scheduler::Task;
+use crate::stop::{self, Stop};
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::text::TextWidget;
 
@@ -29,6 +30,9 @@ pub struct CustomDBus {
     id: String,
     text: TextWidget,
     status: Arc<Mutex<CustomDBusStatus>>,
+    /// Stops serving clients when the block is dropped, which releases the
+    /// bus name for the block replacing it.
+    _server: Stop,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -50,6 +54,7 @@ impl ConfigBlock for CustomDBus {
             state: State::Idle,
         }));
         let status = status_original.clone();
+        let (stop, stopped) = stop::pair()?;
         thread::Builder::new()
             .name("custom_dbus".into())
             .spawn(move || {
@@ -93,7 +98,7 @@ impl ConfigBlock for CustomDBus {
                                             id: id.clone(),
                                             update_time: Instant::now(),
                                         })
-                                        .unwrap();
+                                        .ok();
 
                                         Ok(vec![m.msg.method_return()])
                                     })
@@ -111,8 +116,8 @@ impl ConfigBlock for CustomDBus {
                 // We add the tree to the connection so that incoming method calls will be handled.
                 tree.start_receive(&c);
 
-                // Serve clients forever.
-                loop {
+                // Serve clients until the block is dropped.
+                while !stopped.is_stopped() {
                     c.process(Duration::from_millis(1000)).unwrap();
                 }
             })
@@ -122,6 +127,7 @@ impl ConfigBlock for CustomDBus {
             id: id_copy,
             text: TextWidget::new(config).with_text("CustomDBus"),
             status,
+            _server: stop,
         })
     }
 }
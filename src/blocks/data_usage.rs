@@ -0,0 +1,393 @@
+//! A block for the data an interface used today or this month.
+//!
+//! The usage comes from `vnstat`, or from the counters of the interface in
+//! `/sys/class/net`, which the block then adds up itself. Those totals are kept
+//! in a file, so that they survive restarts of the bar and of the machine,
+//! which start the counters of the interface over.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDate};
+use crossbeam_channel::Sender;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::value::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::blocks::net::NetworkDevice;
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::{Prefix, Value};
+use crate::scheduler::Task;
+use crate::util::{read_file, xdg_state_home, FormatTemplate};
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
+
+const GB: f64 = 1_000_000_000.0;
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    #[default]
+    Month,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Vnstat,
+    Counters,
+}
+
+pub struct DataUsage {
+    text: TextWidget,
+    id: String,
+    update_interval: Duration,
+    format: FormatTemplate,
+    device: String,
+    period: Period,
+    backend: Backend,
+    /// The totals of the `counters` backend, and where they are kept.
+    usage: Usage,
+    usage_path: PathBuf,
+    threshold_warning: Option<f64>,
+    threshold_critical: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DataUsageConfig {
+    /// Update interval in seconds
+    #[serde(
+        default = "DataUsageConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    #[serde(default = "DataUsageConfig::default_format")]
+    pub format: String,
+
+    /// The interface to count, by default the one of the default route
+    #[serde(default)]
+    pub device: Option<String>,
+
+    /// Whether to count the data of the day or of the month
+    #[serde(default)]
+    pub period: Period,
+
+    #[serde(default)]
+    pub backend: Backend,
+
+    /// The usage in GB from which the block is a warning
+    #[serde(default)]
+    pub threshold_warning: Option<f64>,
+
+    /// The usage in GB from which the block is critical
+    #[serde(default)]
+    pub threshold_critical: Option<f64>,
+}
+
+impl DataUsageConfig {
+    fn default_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_format() -> String {
+        "{total}".to_owned()
+    }
+}
+
+/// The data counted by the `counters` backend in one period.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct Usage {
+    /// The day or month counted, e.g. `2020-06`.
+    period: String,
+    rx: u64,
+    tx: u64,
+    /// The counters of the interface when last read, and the boot they were
+    /// read in.
+    rx_counter: u64,
+    tx_counter: u64,
+    boot_id: String,
+}
+
+impl Usage {
+    /// Adds what the interface received and sent since the counters were last
+    /// read.
+    fn count(&mut self, period: &str, boot_id: &str, rx_counter: u64, tx_counter: u64) {
+        if self.period != period {
+            self.period = period.to_string();
+            self.rx = 0;
+            self.tx = 0;
+        }
+        // After a reboot, everything the counters show is new.
+        let rebooted = self.boot_id != boot_id;
+        let since = |counter: u64, last: u64| {
+            if rebooted || counter < last {
+                counter
+            } else {
+                counter - last
+            }
+        };
+        self.rx += since(rx_counter, self.rx_counter);
+        self.tx += since(tx_counter, self.tx_counter);
+        self.rx_counter = rx_counter;
+        self.tx_counter = tx_counter;
+        self.boot_id = boot_id.to_string();
+    }
+}
+
+fn load_usage(path: &Path) -> Option<Usage> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn save_usage(path: &Path, usage: &Usage) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).block_error(
+            "data_usage",
+            &format!("failed to create directory {}", parent.to_string_lossy()),
+        )?;
+    }
+    let contents =
+        serde_json::to_string(usage).block_error("data_usage", "failed to serialize usage")?;
+    // Write to a temporary file first so a crash midway never leaves a
+    // truncated file behind.
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, contents).block_error(
+        "data_usage",
+        &format!("failed to write {}", tmp_path.to_string_lossy()),
+    )?;
+    fs::rename(&tmp_path, path).block_error(
+        "data_usage",
+        &format!("failed to move usage to {}", path.to_string_lossy()),
+    )
+}
+
+fn period_key(period: Period, today: NaiveDate) -> String {
+    match period {
+        Period::Day => today.format("%Y-%m-%d").to_string(),
+        Period::Month => today.format("%Y-%m").to_string(),
+    }
+}
+
+/// The bytes `device` received and sent in the period of `today`, from the
+/// output of `vnstat --json`. Versions before 2.0 count in KiB and name the
+/// lists differently.
+fn parse_vnstat(json: &str, device: &str, period: Period, today: NaiveDate) -> Option<(u64, u64)> {
+    let json: JsonValue = serde_json::from_str(json).ok()?;
+    let old = json["jsonversion"].as_str() == Some("1");
+    let interface = json["interfaces"].as_array()?.iter().find(|interface| {
+        interface["name"].as_str() == Some(device) || interface["id"].as_str() == Some(device)
+    })?;
+    let list = match (period, old) {
+        (Period::Day, false) => "day",
+        (Period::Day, true) => "days",
+        (Period::Month, false) => "month",
+        (Period::Month, true) => "months",
+    };
+    let entries = interface["traffic"][list].as_array()?;
+    let current = entries.iter().find(|entry| {
+        let date = &entry["date"];
+        date["year"].as_i64() == Some(i64::from(today.year()))
+            && date["month"].as_u64() == Some(u64::from(today.month()))
+            && (period == Period::Month || date["day"].as_u64() == Some(u64::from(today.day())))
+    });
+    let factor = if old { 1024 } else { 1 };
+    Some(match current {
+        Some(entry) => (
+            entry["rx"].as_u64()? * factor,
+            entry["tx"].as_u64()? * factor,
+        ),
+        // Nothing was counted yet.
+        None => (0, 0),
+    })
+}
+
+impl ConfigBlock for DataUsage {
+    type Config = DataUsageConfig;
+
+    fn new(block_config: Self::Config, config: Config, _: Sender<Task>) -> Result<Self> {
+        let device = block_config
+            .device
+            .or_else(NetworkDevice::default_device)
+            .block_error("data_usage", "no device given and no default route")?;
+        let usage_path = xdg_state_home().join(format!("i3status-rust/data_usage_{}.json", device));
+
+        Ok(DataUsage {
+            id: Uuid::new_v4().to_simple().to_string(),
+            text: TextWidget::new(config).with_text("").with_icon("net_down"),
+            update_interval: block_config.interval,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("data_usage", "Invalid format specified")?,
+            usage: load_usage(&usage_path).unwrap_or_default(),
+            usage_path,
+            device,
+            period: block_config.period,
+            backend: block_config.backend,
+            threshold_warning: block_config.threshold_warning,
+            threshold_critical: block_config.threshold_critical,
+        })
+    }
+}
+
+impl DataUsage {
+    /// The bytes received and sent in the current period.
+    fn usage(&mut self) -> Result<(u64, u64)> {
+        let today = Local::today().naive_local();
+        match self.backend {
+            Backend::Vnstat => {
+                let output = Command::new("vnstat")
+                    .args(["--json", "-i", &self.device])
+                    .output()
+                    .block_error("data_usage", "failed to run vnstat")?;
+                parse_vnstat(
+                    &String::from_utf8_lossy(&output.stdout),
+                    &self.device,
+                    self.period,
+                    today,
+                )
+                .block_error(
+                    "data_usage",
+                    &format!("vnstat has no data for {}", self.device),
+                )
+            }
+            Backend::Counters => {
+                let statistics = Path::new("/sys/class/net")
+                    .join(&self.device)
+                    .join("statistics");
+                let counter = |name: &str| -> Result<u64> {
+                    read_file("data_usage", &statistics.join(name))?
+                        .trim()
+                        .parse()
+                        .block_error("data_usage", "invalid interface counter")
+                };
+                let boot_id =
+                    read_file("data_usage", Path::new("/proc/sys/kernel/random/boot_id"))?;
+                let (rx, tx) = (counter("rx_bytes")?, counter("tx_bytes")?);
+                if self.usage.period.is_empty() {
+                    // Counting starts now, what came before is unknown.
+                    self.usage.rx_counter = rx;
+                    self.usage.tx_counter = tx;
+                    self.usage.boot_id = boot_id.clone();
+                }
+                self.usage
+                    .count(&period_key(self.period, today), &boot_id, rx, tx);
+                save_usage(&self.usage_path, &self.usage)?;
+                Ok((self.usage.rx, self.usage.tx))
+            }
+        }
+    }
+}
+
+impl Block for DataUsage {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let (rx, tx) = self.usage()?;
+        let bytes = |bytes: u64| Value::float(bytes, 1).with_suffix("B").scaled(Prefix::One);
+        let values = map!(
+            "{rx}" => bytes(rx),
+            "{tx}" => bytes(tx),
+            "{total}" => bytes(rx + tx),
+            "{device}" => Value::from(self.device.as_str())
+        );
+        self.text.set_text(self.format.render_static_str(&values)?);
+
+        let total = (rx + tx) as f64 / GB;
+        let reached = |threshold: Option<f64>| threshold.is_some_and(|t| total >= t);
+        self.text.set_state(if reached(self.threshold_critical) {
+            State::Critical
+        } else if reached(self.threshold_warning) {
+            State::Warning
+        } else {
+            State::Idle
+        });
+        Ok(Some(self.update_interval.into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.text]
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VNSTAT: &str = r#"{"vnstatversion":"2.6","jsonversion":"2","interfaces":[
+        {"name":"wlp3s0","alias":"","traffic":{
+            "day":[
+                {"id":1,"date":{"year":2020,"month":6,"day":13},"rx":500,"tx":50},
+                {"id":2,"date":{"year":2020,"month":6,"day":14},"rx":300,"tx":30}],
+            "month":[
+                {"id":1,"date":{"year":2020,"month":5},"rx":9000,"tx":900},
+                {"id":2,"date":{"year":2020,"month":6},"rx":800,"tx":80}]}}]}"#;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 6, day).unwrap()
+    }
+
+    #[test]
+    fn test_parse_vnstat() {
+        assert_eq!(
+            parse_vnstat(VNSTAT, "wlp3s0", Period::Day, day(14)),
+            Some((300, 30))
+        );
+        assert_eq!(
+            parse_vnstat(VNSTAT, "wlp3s0", Period::Month, day(14)),
+            Some((800, 80))
+        );
+        assert_eq!(
+            parse_vnstat(VNSTAT, "wlp3s0", Period::Day, day(15)),
+            Some((0, 0))
+        );
+        assert_eq!(parse_vnstat(VNSTAT, "eth0", Period::Day, day(14)), None);
+        assert_eq!(parse_vnstat("", "wlp3s0", Period::Day, day(14)), None);
+
+        let old = r#"{"vnstatversion":"1.18","jsonversion":"1","interfaces":[
+            {"id":"eth0","traffic":{"months":[
+                {"id":0,"date":{"year":2020,"month":6},"rx":2,"tx":1}]}}]}"#;
+        assert_eq!(
+            parse_vnstat(old, "eth0", Period::Month, day(14)),
+            Some((2048, 1024))
+        );
+    }
+
+    #[test]
+    fn test_count() {
+        let mut usage = Usage {
+            period: "2020-06".to_string(),
+            rx_counter: 100,
+            tx_counter: 10,
+            boot_id: "a".to_string(),
+            ..Usage::default()
+        };
+        usage.count("2020-06", "a", 150, 20);
+        assert_eq!((usage.rx, usage.tx), (50, 10));
+        // The counters started over with the interface.
+        usage.count("2020-06", "a", 30, 5);
+        assert_eq!((usage.rx, usage.tx), (80, 15));
+        // A reboot, after which the counters already passed the last ones.
+        usage.count("2020-06", "b", 200, 40);
+        assert_eq!((usage.rx, usage.tx), (280, 55));
+        // A new month.
+        usage.count("2020-07", "b", 210, 41);
+        assert_eq!((usage.rx, usage.tx), (10, 1));
+        assert_eq!(usage.period, "2020-07");
+    }
+
+    #[test]
+    fn test_period_key() {
+        assert_eq!(period_key(Period::Day, day(4)), "2020-06-04");
+        assert_eq!(period_key(Period::Month, day(4)), "2020-06");
+    }
+}
@@ -1,8 +1,11 @@
+use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
 use std::time::Duration;
 
 use crossbeam_channel::Sender;
 use nix::sys::statvfs::statvfs;
+use serde::de::{self, Deserializer};
 use serde_derive::Deserialize;
 use uuid::Uuid;
 
@@ -11,8 +14,9 @@ use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::scheduler::Task;
-use crate::util::{format_percent_bar, FormatTemplate};
+use crate::util::FormatTemplate;
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::text::TextWidget;
 
@@ -41,6 +45,109 @@ impl Unit {
     }
 }
 
+/// A warning or alert level, either in the configured unit (`10`) or as a
+/// percentage of the disk size (`"10%"`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Threshold {
+    Absolute(f64),
+    Percent(f64),
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, percent) = match s.strip_suffix('%') {
+            Some(number) => (number.trim_end(), true),
+            None => (s, false),
+        };
+        let value = number
+            .parse::<f64>()
+            .map_err(|_| format!("'{}' is neither a number nor a percentage", s))?;
+        if !value.is_finite() || value < 0. {
+            return Err(format!("'{}' must not be negative", s));
+        }
+        if percent {
+            if value > 100. {
+                return Err(format!("'{}' is more than 100%", s));
+            }
+            Ok(Threshold::Percent(value))
+        } else {
+            Ok(Threshold::Absolute(value))
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Threshold {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ThresholdVisitor;
+
+        impl<'de> de::Visitor<'de> for ThresholdVisitor {
+            type Value = Threshold;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(r#"a number or a percentage such as "10%""#)
+            }
+
+            fn visit_i64<E>(self, value: i64) -> std::result::Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                self.visit_f64(value as f64)
+            }
+
+            fn visit_u64<E>(self, value: u64) -> std::result::Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                self.visit_f64(value as f64)
+            }
+
+            fn visit_f64<E>(self, value: f64) -> std::result::Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                if value < 0. {
+                    return Err(de::Error::custom(format!(
+                        "'{}' must not be negative",
+                        value
+                    )));
+                }
+                Ok(Threshold::Absolute(value))
+            }
+
+            fn visit_str<E>(self, value: &str) -> std::result::Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value.parse().map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ThresholdVisitor)
+    }
+}
+
+impl Threshold {
+    fn value(self) -> f64 {
+        match self {
+            Threshold::Absolute(value) | Threshold::Percent(value) => value,
+        }
+    }
+
+    /// Picks the measurement matching the form the threshold was given in.
+    fn measure(self, absolute: f64, percentage: f64) -> f64 {
+        match self {
+            Threshold::Absolute(_) => absolute,
+            Threshold::Percent(_) => percentage,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum InfoType {
@@ -58,8 +165,9 @@ pub struct DiskSpace {
     path: String,
     unit: Unit,
     info_type: InfoType,
-    warning: f64,
-    alert: f64,
+    warning: Threshold,
+    alert: Threshold,
+    percentage_decimals: usize,
     show_percentage: bool,
     show_bar: bool,
     format: FormatTemplate,
@@ -84,8 +192,8 @@ pub struct DiskSpaceConfig {
     pub info_type: InfoType,
 
     /// Format string for output
-    /// placeholders: {percentage}, {bar}, {path}, {alias}, {available}, {free}, {total}, {used},
-    ///               {unit}
+    /// placeholders: {percentage}, {percentage_free}, {percentage_used}, {bar}, {path}, {alias},
+    ///               {available}, {free}, {total}, {used}, {unit}
     #[serde(default = "DiskSpaceConfig::default_format")]
     pub format: String,
 
@@ -100,13 +208,17 @@ pub struct DiskSpaceConfig {
     )]
     pub interval: Duration,
 
-    /// Diskspace warning in GiB (yellow)
+    /// Diskspace warning in the configured unit, or in percent with a "%" suffix (yellow)
     #[serde(default = "DiskSpaceConfig::default_warning")]
-    pub warning: f64,
+    pub warning: Threshold,
 
-    /// Diskspace alert in GiB (red)
+    /// Diskspace alert in the configured unit, or in percent with a "%" suffix (red)
     #[serde(default = "DiskSpaceConfig::default_alert")]
-    pub alert: f64,
+    pub alert: Threshold,
+
+    /// Number of digits after the decimal point of the percentage placeholders
+    #[serde(default = "DiskSpaceConfig::default_percentage_decimals")]
+    pub percentage_decimals: usize,
 
     /// Show percentage - deprecated for format string, kept for previous configs
     #[serde(default = "DiskSpaceConfig::default_show_percentage")]
@@ -142,12 +254,16 @@ impl DiskSpaceConfig {
         Duration::from_secs(20)
     }
 
-    fn default_warning() -> f64 {
-        20.
+    fn default_warning() -> Threshold {
+        Threshold::Absolute(20.)
+    }
+
+    fn default_alert() -> Threshold {
+        Threshold::Absolute(10.)
     }
 
-    fn default_alert() -> f64 {
-        10.
+    fn default_percentage_decimals() -> usize {
+        2
     }
 
     // Deprecated with format string, kept for previous config support
@@ -166,28 +282,29 @@ enum AlertType {
     Below,
 }
 
-impl DiskSpace {
-    fn compute_state(&self, value: f64, warning: f64, alert: f64, alert_type: AlertType) -> State {
+/// Computes the state for a disk usage value (`absolute`, in the configured
+/// unit, and `percentage`). Each threshold is compared against the
+/// measurement in its own form.
+fn compute_state(
+    absolute: f64,
+    percentage: f64,
+    warning: Threshold,
+    alert: Threshold,
+    alert_type: AlertType,
+) -> State {
+    let crossed = |threshold: Threshold| {
+        let value = threshold.measure(absolute, percentage);
         match alert_type {
-            AlertType::Above => {
-                if value > alert {
-                    State::Critical
-                } else if value <= alert && value > warning {
-                    State::Warning
-                } else {
-                    State::Idle
-                }
-            }
-            AlertType::Below => {
-                if 0. <= value && value < alert {
-                    State::Critical
-                } else if alert <= value && value < warning {
-                    State::Warning
-                } else {
-                    State::Idle
-                }
-            }
+            AlertType::Above => value > threshold.value(),
+            AlertType::Below => 0. <= value && value < threshold.value(),
         }
+    };
+    if crossed(alert) {
+        State::Critical
+    } else if crossed(warning) {
+        State::Warning
+    } else {
+        State::Idle
     }
 }
 
@@ -216,6 +333,7 @@ impl ConfigBlock for DiskSpace {
             unit: block_config.unit,
             warning: block_config.warning,
             alert: block_config.alert,
+            percentage_decimals: block_config.percentage_decimals,
             show_percentage: block_config.show_percentage,
             show_bar: block_config.show_bar,
             icon,
@@ -228,7 +346,7 @@ impl Block for DiskSpace {
         let statvfs = statvfs(Path::new(self.path.as_str()))
             .block_error("disk_space", "failed to retrieve statvfs")?;
 
-        let mut result;
+        let result;
         let total = (statvfs.blocks() as u64) * (statvfs.fragment_size() as u64);
         let used = ((statvfs.blocks() as u64) - (statvfs.blocks_free() as u64))
             * (statvfs.fragment_size() as u64);
@@ -266,28 +384,36 @@ impl Block for DiskSpace {
             self.format = FormatTemplate::from_string("{alias} {result} {unit} {bar}")?;
         }
 
-        let values = map!("{percentage}" => format!("{:.2}%", percentage),
-        "{bar}" => format_percent_bar(percentage),
-        "{alias}" => self.alias.clone(),
-        "{unit}" => format!("{:?}", self.unit),
-        "{path}" => self.path.clone(),
-        "{total}" => format!("{:.2}", Unit::bytes_in_unit(self.unit, total)),
-        "{used}" => format!("{:.2}", Unit::bytes_in_unit(self.unit, used)),
-        "{available}" => format!("{:.2}", Unit::bytes_in_unit(self.unit, available)),
-        "{free}" => format!("{:.2}", Unit::bytes_in_unit(self.unit, free)),
-        "{icon}" => self.icon.to_string(),
-        "{result}" => format!("{:.2}", result)
+        let decimals = self.percentage_decimals;
+        let percentage_of_total = |bytes: u64| (bytes as f32) / (total as f32) * 100f32;
+        let in_unit = |bytes: u64| Value::float(Unit::bytes_in_unit(self.unit, bytes), 2);
+        let values = map!("{percentage}" => Value::float(percentage, decimals).with_suffix("%"),
+        "{percentage_free}" => Value::float(percentage_of_total(free), decimals).with_suffix("%"),
+        "{percentage_used}" => Value::float(percentage_of_total(used), decimals).with_suffix("%"),
+        "{bar}" => Value::bar(percentage),
+        "{alias}" => Value::from(self.alias.clone()),
+        "{unit}" => Value::from(format!("{:?}", self.unit)),
+        "{path}" => Value::from(self.path.clone()),
+        "{total}" => in_unit(total),
+        "{used}" => in_unit(used),
+        "{available}" => in_unit(available),
+        "{free}" => in_unit(free),
+        "{icon}" => Value::from(self.icon.clone()),
+        "{result}" => Value::integer(result)
         );
         self.disk_space
             .set_text(self.format.render_static_str(&values)?);
 
-        if self.unit == Unit::Percent {
+        let absolute = if self.unit == Unit::Percent {
             // Note this does not override format, used to set type for alerts
-            result = percentage as u64;
-        }
-
-        let state = self.compute_state(
-            Unit::bytes_in_unit(self.unit, result),
+            percentage as f64
+        } else {
+            Unit::bytes_in_unit(self.unit, result)
+        };
+
+        let state = compute_state(
+            absolute,
+            percentage as f64,
             self.warning,
             self.alert,
             alert_type,
@@ -305,3 +431,59 @@ impl Block for DiskSpace {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_threshold() {
+        assert_eq!("10".parse(), Ok(Threshold::Absolute(10.)));
+        assert_eq!("2.5".parse(), Ok(Threshold::Absolute(2.5)));
+        assert_eq!("10%".parse(), Ok(Threshold::Percent(10.)));
+        assert_eq!(" 7.5 % ".parse(), Ok(Threshold::Percent(7.5)));
+        assert!("-5%".parse::<Threshold>().is_err());
+        assert!("-5".parse::<Threshold>().is_err());
+        assert!("10GB%".parse::<Threshold>().is_err());
+        assert!("10%%".parse::<Threshold>().is_err());
+        assert!("150%".parse::<Threshold>().is_err());
+        assert!("%".parse::<Threshold>().is_err());
+        assert!("".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_threshold() {
+        #[derive(Deserialize)]
+        struct Thresholds {
+            warning: Threshold,
+            alert: Threshold,
+        }
+        let parsed: Thresholds = toml::from_str("warning = 20\nalert = \"10%\"").unwrap();
+        assert_eq!(parsed.warning, Threshold::Absolute(20.));
+        assert_eq!(parsed.alert, Threshold::Percent(10.));
+        assert!(toml::from_str::<Thresholds>("warning = -1.5\nalert = 1").is_err());
+    }
+
+    #[test]
+    fn test_compute_state() {
+        let state = |absolute, percentage, warning, alert| {
+            compute_state(absolute, percentage, warning, alert, AlertType::Below)
+        };
+        // 50 GB available, 8% of the disk.
+        let below_percent = state(50., 8., Threshold::Absolute(20.), Threshold::Percent(10.));
+        assert!(matches!(below_percent, State::Critical));
+        let below_absolute = state(50., 8., Threshold::Absolute(60.), Threshold::Absolute(10.));
+        assert!(matches!(below_absolute, State::Warning));
+        let fine = state(50., 8., Threshold::Percent(5.), Threshold::Absolute(10.));
+        assert!(matches!(fine, State::Idle));
+
+        let used = compute_state(
+            900.,
+            90.,
+            Threshold::Percent(80.),
+            Threshold::Percent(95.),
+            AlertType::Above,
+        );
+        assert!(matches!(used, State::Warning));
+    }
+}
@@ -1,4 +1,6 @@
-use std::process::Command;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use crossbeam_channel::Sender;
@@ -13,14 +15,19 @@ use crate::errors::*;
 use crate::input::I3BarEvent;
 use crate::scheduler::Task;
 use crate::util::FormatTemplate;
-use crate::widget::I3BarWidget;
+use crate::widget::{I3BarWidget, State};
 use crate::widgets::text::TextWidget;
 
+/// How long to wait for the daemon to answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Docker {
     text: TextWidget,
     id: String,
     format: FormatTemplate,
     update_interval: Duration,
+    socket_path: PathBuf,
+    containers: Vec<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -41,6 +48,15 @@ struct Status {
     images: i64,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+struct Container {
+    #[serde(rename = "Names")]
+    names: Vec<String>,
+
+    #[serde(rename = "State")]
+    state: String,
+}
+
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct DockerConfig {
@@ -54,6 +70,14 @@ pub struct DockerConfig {
     /// Format override
     #[serde(default = "DockerConfig::default_format")]
     pub format: String,
+
+    /// The socket of the Docker daemon
+    #[serde(default = "DockerConfig::default_socket_path")]
+    pub socket_path: PathBuf,
+
+    /// Names of containers that should be running
+    #[serde(default)]
+    pub containers: Vec<String>,
 }
 
 impl DockerConfig {
@@ -61,7 +85,10 @@ impl DockerConfig {
         Duration::from_secs(5)
     }
     fn default_format() -> String {
-        "{running}%".to_owned()
+        "{running}".to_owned()
+    }
+    fn default_socket_path() -> PathBuf {
+        PathBuf::from("/var/run/docker.sock")
     }
 }
 
@@ -75,46 +102,107 @@ impl ConfigBlock for Docker {
             format: FormatTemplate::from_string(&block_config.format)
                 .block_error("docker", "Invalid format specified")?,
             update_interval: block_config.interval,
+            socket_path: block_config.socket_path,
+            containers: block_config.containers,
         })
     }
 }
 
+/// The body of a successful response to a `GET` of `path` from the Docker API
+/// on `socket_path`, or `None` if the daemon cannot be reached.
+fn get(socket_path: &Path, path: &str) -> Result<Option<String>> {
+    let mut stream = match UnixStream::connect(socket_path) {
+        Ok(stream) => stream,
+        Err(_) => return Ok(None),
+    };
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .block_error("docker", "Failed to set a timeout")?;
+    // In one write, so the daemon never sees half a request.
+    let request = format!("GET {} HTTP/1.0\r\nHost: docker\r\n\r\n", path);
+    stream
+        .write_all(request.as_bytes())
+        .block_error("docker", "Failed to send request")?;
+    // HTTP/1.0 responses are neither chunked nor kept alive, so the body is
+    // everything up to the end of the stream.
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .block_error("docker", "Failed to read response")?;
+    response_body(&response).map(|body| Some(body.to_string()))
+}
+
+/// The body of an HTTP response, if its status is 200.
+fn response_body(response: &str) -> Result<&str> {
+    let status = response.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(BlockError(
+            "docker".to_string(),
+            format!("Unexpected response: {}", status.trim()),
+        ));
+    }
+    response
+        .find("\r\n\r\n")
+        .map(|end| &response[end + 4..])
+        .block_error("docker", "Malformed response")
+}
+
+/// Those of `wanted` that are not among the running `containers`.
+fn missing(wanted: &[String], containers: &[Container]) -> Vec<String> {
+    wanted
+        .iter()
+        .filter(|name| {
+            !containers.iter().any(|container| {
+                container.state == "running"
+                    && container
+                        .names
+                        .iter()
+                        .any(|n| n.trim_start_matches('/') == name.as_str())
+            })
+        })
+        .cloned()
+        .collect()
+}
+
 impl Block for Docker {
     fn update(&mut self) -> Result<Option<Update>> {
-        let output = match Command::new("sh")
-            .args(&[
-                "-c",
-                "curl --fail --unix-socket /var/run/docker.sock http:/api/info",
-            ])
-            .output()
-        {
-            Ok(raw_output) => {
-                String::from_utf8(raw_output.stdout).block_error("docker", "Failed to decode")?
-            }
-            Err(_) => {
+        let output = match get(&self.socket_path, "/info")? {
+            Some(output) => output,
+            None => {
                 // We don't want the bar to crash if we can't reach the docker daemon.
                 self.text.set_text("N/A".to_string());
+                self.text.set_state(State::Idle);
                 return Ok(Some(self.update_interval.into()));
             }
         };
 
-        if output.is_empty() {
-            self.text.set_text("N/A".to_string());
-            return Ok(Some(self.update_interval.into()));
-        }
-
         let status: Status = serde_json::from_str(&output)
             .block_error("docker", "Failed to parse JSON response.")?;
 
+        let missing = if self.containers.is_empty() {
+            Vec::new()
+        } else {
+            let output = get(&self.socket_path, "/containers/json?all=true")?;
+            let containers: Vec<Container> = serde_json::from_str(&output.unwrap_or_default())
+                .block_error("docker", "Failed to parse JSON response.")?;
+            missing(&self.containers, &containers)
+        };
+
         let values = map!(
             "{total}" => format!("{}", status.total),
             "{running}" => format!("{}", status.running),
             "{paused}" => format!("{}", status.paused),
             "{stopped}" => format!("{}", status.stopped),
-            "{images}" => format!("{}", status.images)
+            "{images}" => format!("{}", status.images),
+            "{missing}" => missing.join(" ")
         );
 
         self.text.set_text(self.format.render_static_str(&values)?);
+        self.text.set_state(if missing.is_empty() {
+            State::Idle
+        } else {
+            State::Critical
+        });
 
         Ok(Some(self.update_interval.into()))
     }
@@ -131,3 +219,59 @@ impl Block for Docker {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_response_body() {
+        assert_eq!(
+            response_body("HTTP/1.0 200 OK\r\nApi-Version: 1.40\r\n\r\n{}\n").unwrap(),
+            "{}\n"
+        );
+        assert!(response_body("HTTP/1.0 404 Not Found\r\n\r\n{}").is_err());
+        assert!(response_body("HTTP/1.0 200 OK\r\n").is_err());
+        assert!(response_body("").is_err());
+    }
+
+    #[test]
+    fn test_missing() {
+        let containers: Vec<Container> = serde_json::from_str(
+            r#"[{"Names": ["/db"], "State": "running"},
+                {"Names": ["/web", "/www"], "State": "exited"}]"#,
+        )
+        .unwrap();
+        let wanted = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(missing(&wanted(&["db"]), &containers).is_empty());
+        assert_eq!(
+            missing(&wanted(&["web", "db", "cache"]), &containers),
+            wanted(&["web", "cache"])
+        );
+    }
+
+    #[test]
+    fn test_get() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("docker.sock");
+        assert_eq!(get(&path, "/info").unwrap(), None);
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 256];
+            let read = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n[]")
+                .unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+        assert_eq!(get(&path, "/info").unwrap(), Some("[]".to_string()));
+        assert!(server.join().unwrap().starts_with("GET /info HTTP/1.0\r\n"));
+    }
+}
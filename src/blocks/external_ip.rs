@@ -0,0 +1,418 @@
+//! A block showing the public IP addresses of the machine, and optionally
+//! where they are, e.g. to check the exit of a VPN.
+//!
+//! The addresses are fetched with curl from an endpoint answering with just
+//! the address, on a background thread. They are fetched again every
+//! `interval`, and as soon as the default route changes, which is checked by
+//! reading `/proc/net/route` and `/proc/net/ipv6_route` every few seconds.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::blocks::{Block, ConfigBlock, Update};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::scheduler::Task;
+use crate::stop::{self, Stop};
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
+
+/// How often to check whether the default route changed.
+const ROUTE_CHECK: Duration = Duration::from_secs(3);
+
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// Show the failure, in critical state.
+    #[default]
+    Warn,
+    /// Show nothing.
+    Hide,
+}
+
+/// What the background thread got the last time.
+#[derive(Debug, Clone, PartialEq)]
+enum Lookup {
+    Pending,
+    /// The placeholder values.
+    Done(HashMap<String, String>),
+    Failed(String),
+}
+
+pub struct ExternalIp {
+    id: String,
+    text: TextWidget,
+    format: FormatTemplate,
+    on_failure: OnFailure,
+    lookup: Arc<Mutex<Lookup>>,
+    hidden: bool,
+    /// Stops looking up the address when the block is dropped.
+    _lookuper: Stop,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalIpConfig {
+    /// An endpoint answering with the IPv4 address
+    #[serde(default = "ExternalIpConfig::default_url")]
+    pub url: String,
+
+    /// An endpoint answering with the IPv6 address
+    #[serde(default)]
+    pub url_v6: Option<String>,
+
+    /// An endpoint answering with JSON about the address, which replaces
+    /// `{ip}` in it
+    #[serde(default)]
+    pub geo_url: Option<String>,
+
+    /// Placeholder name -> JSON pointer to its value in the `geo_url` response
+    #[serde(default = "ExternalIpConfig::default_geo_values")]
+    pub geo_values: BTreeMap<String, String>,
+
+    /// Format override
+    #[serde(default = "ExternalIpConfig::default_format")]
+    pub format: String,
+
+    /// What to do when a lookup fails
+    #[serde(default)]
+    pub on_failure: OnFailure,
+
+    /// Time between lookups, besides those when the default route changes
+    #[serde(
+        default = "ExternalIpConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// How long a request may take
+    #[serde(
+        default = "ExternalIpConfig::default_timeout",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub timeout: Duration,
+}
+
+impl ExternalIpConfig {
+    fn default_url() -> String {
+        "https://api.ipify.org".to_owned()
+    }
+
+    fn default_geo_values() -> BTreeMap<String, String> {
+        vec![
+            ("country", "/country"),
+            ("country_code", "/countryCode"),
+            ("city", "/city"),
+        ]
+        .into_iter()
+        .map(|(name, pointer)| (name.to_owned(), pointer.to_owned()))
+        .collect()
+    }
+
+    fn default_format() -> String {
+        "{ip}".to_owned()
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+}
+
+/// The body at `url`.
+fn fetch(url: &str, timeout: Duration) -> std::result::Result<String, String> {
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .arg("--max-time")
+        .arg(timeout.as_secs_f64().to_string())
+        .arg(url)
+        .output()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// The values `pointers` point at in the JSON `body`. Missing ones are empty.
+fn geo_values(
+    body: &str,
+    pointers: &BTreeMap<String, String>,
+) -> std::result::Result<HashMap<String, String>, String> {
+    let json: JsonValue =
+        serde_json::from_str(body).map_err(|_| "invalid geolocation JSON".to_string())?;
+    Ok(pointers
+        .iter()
+        .map(|(name, pointer)| {
+            let value = match json.pointer(pointer) {
+                Some(JsonValue::String(text)) => text.clone(),
+                Some(JsonValue::Null) | None => String::new(),
+                Some(other) => other.to_string(),
+            };
+            (name.clone(), value)
+        })
+        .collect())
+}
+
+/// The default routes in `/proc/net/route` and `/proc/net/ipv6_route`, i.e.
+/// those to everywhere, as their lines.
+fn default_routes(route: &str, ipv6_route: &str) -> Vec<String> {
+    let ipv4 = route.lines().skip(1).filter(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        fields.len() > 7 && fields[1] == "00000000" && fields[7] == "00000000"
+    });
+    let ipv6 = ipv6_route.lines().filter(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        fields.len() > 9 && fields[0].chars().all(|c| c == '0') && fields[1] == "00"
+            // Not the unreachable route of the loopback device.
+            && fields[9] != "lo"
+    });
+    ipv4.chain(ipv6).map(str::to_string).collect()
+}
+
+fn read_default_routes() -> Vec<String> {
+    default_routes(
+        &fs::read_to_string("/proc/net/route").unwrap_or_default(),
+        &fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default(),
+    )
+}
+
+/// Looks up the placeholder values.
+struct Lookuper {
+    url: String,
+    url_v6: Option<String>,
+    geo_url: Option<String>,
+    geo_values: BTreeMap<String, String>,
+    timeout: Duration,
+}
+
+impl Lookuper {
+    fn lookup(&self) -> Lookup {
+        match self.try_lookup() {
+            Ok(values) => Lookup::Done(values),
+            Err(error) => Lookup::Failed(error),
+        }
+    }
+
+    fn try_lookup(&self) -> std::result::Result<HashMap<String, String>, String> {
+        let ip = fetch(&self.url, self.timeout)?;
+        let mut values = HashMap::new();
+        if let Some(ref url_v6) = self.url_v6 {
+            values.insert("ipv6".to_string(), fetch(url_v6, self.timeout)?);
+        }
+        if let Some(ref geo_url) = self.geo_url {
+            let body = fetch(&geo_url.replace("{ip}", &ip), self.timeout)?;
+            values.extend(geo_values(&body, &self.geo_values)?);
+        }
+        values.insert("ip".to_string(), ip);
+        Ok(values)
+    }
+}
+
+impl ConfigBlock for ExternalIp {
+    type Config = ExternalIpConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        let lookup = Arc::new(Mutex::new(Lookup::Pending));
+        let lookuper = Lookuper {
+            url: block_config.url,
+            url_v6: block_config.url_v6,
+            geo_url: block_config.geo_url,
+            geo_values: block_config.geo_values,
+            timeout: block_config.timeout,
+        };
+        let interval = block_config.interval;
+        let lookup_copy = lookup.clone();
+        let id_copy = id.clone();
+        let (stop, stopped) = stop::pair()?;
+        thread::Builder::new()
+            .name("external_ip".into())
+            .spawn(move || loop {
+                let result = lookuper.lookup();
+                if let Ok(mut lookup) = lookup_copy.lock() {
+                    // Only log what changed, not every failed lookup.
+                    if let Lookup::Failed(ref error) = result {
+                        if *lookup != result {
+                            log_block_error(&id_copy, "external_ip", error);
+                        }
+                    }
+                    *lookup = result;
+                }
+                let task = Task {
+                    id: id_copy.clone(),
+                    update_time: Instant::now(),
+                };
+                if send.send(task).is_err() {
+                    break;
+                }
+
+                let routes = read_default_routes();
+                let start = Instant::now();
+                while start.elapsed() < interval && read_default_routes() == routes {
+                    if !stopped.sleep(ROUTE_CHECK.min(interval)) {
+                        return;
+                    }
+                }
+            })
+            .block_error("external_ip", "failed to spawn thread")?;
+
+        Ok(ExternalIp {
+            id,
+            text: TextWidget::new(config)
+                .with_text("…")
+                .with_icon("net_wired"),
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("external_ip", "Invalid format specified")?,
+            on_failure: block_config.on_failure,
+            lookup,
+            hidden: false,
+            _lookuper: stop,
+        })
+    }
+}
+
+impl ExternalIp {
+    fn show(&mut self, lookup: &Lookup) -> Result<()> {
+        self.hidden = false;
+        match lookup {
+            Lookup::Pending => {}
+            Lookup::Done(values) => {
+                let values = values
+                    .iter()
+                    .map(|(name, value)| (format!("{{{}}}", name), value.clone()))
+                    .collect::<HashMap<_, _>>();
+                let values = values
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.clone()))
+                    .collect();
+                self.text.set_text(self.format.render_static_str(&values)?);
+                self.text.set_state(State::Idle);
+            }
+            Lookup::Failed(_) if self.on_failure == OnFailure::Hide => self.hidden = true,
+            Lookup::Failed(_) => {
+                self.text.set_text("offline".to_string());
+                self.text.set_state(State::Critical);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Block for ExternalIp {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let lookup = self
+            .lookup
+            .lock()
+            .block_error("external_ip", "failed to get the lookup")?
+            .clone();
+        self.show(&lookup)?;
+        Ok(None)
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        if self.hidden {
+            vec![]
+        } else {
+            vec![&self.text]
+        }
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlp2s0\t00000000\t0102A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlp2s0\t0002A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+
+    const IPV6_ROUTE: &str = "\
+fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001   wlp2s0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe80000000000000021122fffe334455 00000400 00000002 00000000 00000003   wlp2s0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+";
+
+    fn block(options: &str) -> ExternalIp {
+        let lookup = Arc::new(Mutex::new(Lookup::Pending));
+        let config: ExternalIpConfig = toml::from_str(options).unwrap();
+        ExternalIp {
+            id: "ip".to_string(),
+            text: TextWidget::new(Config::default()),
+            format: FormatTemplate::from_string(&config.format).unwrap(),
+            on_failure: config.on_failure,
+            lookup,
+            hidden: false,
+            _lookuper: stop::pair().unwrap().0,
+        }
+    }
+
+    #[test]
+    fn test_default_routes() {
+        let routes = default_routes(ROUTE, IPV6_ROUTE);
+        assert_eq!(routes.len(), 2);
+        assert!(routes[0].starts_with("wlp2s0\t00000000\t0102A8C0"));
+        assert!(routes[1].contains("fe80000000000000021122fffe334455"));
+        assert!(default_routes("", "").is_empty());
+    }
+
+    #[test]
+    fn test_geo_values() {
+        let pointers = ExternalIpConfig::default_geo_values();
+        let values = geo_values(
+            r#"{"status": "success", "country": "Sweden", "countryCode": "SE", "city": null}"#,
+            &pointers,
+        )
+        .unwrap();
+        assert_eq!(values["country"], "Sweden");
+        assert_eq!(values["country_code"], "SE");
+        assert_eq!(values["city"], "");
+        assert!(geo_values("<html>", &pointers).is_err());
+    }
+
+    #[test]
+    fn test_show() {
+        let done = Lookup::Done(
+            vec![("ip", "198.51.100.7"), ("country_code", "SE")]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        );
+        let failed = Lookup::Failed("Could not resolve host".to_string());
+
+        let mut warn = block("format = \"{ip} {country_code}\"");
+        warn.show(&done).unwrap();
+        assert_eq!(warn.text.widget_state().text, "198.51.100.7 SE");
+        warn.show(&failed).unwrap();
+        assert_eq!(warn.text.widget_state().text, "offline");
+        assert_eq!(warn.text.widget_state().state, State::Critical);
+        assert_eq!(warn.view().len(), 1);
+
+        let mut hide = block("on_failure = \"hide\"");
+        hide.show(&failed).unwrap();
+        assert!(hide.view().is_empty());
+        hide.show(&done).unwrap();
+        assert_eq!(hide.view().len(), 1);
+    }
+}
@@ -12,6 +12,7 @@ use crate::blocks::{Block, ConfigBlock, Update};
 use crate::config::Config;
 use crate::errors::*;
 use crate::scheduler::Task;
+use crate::util::FormatTemplate;
 use crate::widget::I3BarWidget;
 use crate::widgets::text::TextWidget;
 
@@ -29,6 +30,7 @@ pub struct FocusedWindow {
     marks: Arc<Mutex<String>>,
     show_marks: MarksType,
     max_width: usize,
+    format: FormatTemplate,
     id: String,
 }
 
@@ -42,6 +44,10 @@ pub struct FocusedWindowConfig {
     /// Show marks in place of title (if exist)
     #[serde(default = "FocusedWindowConfig::default_show_marks")]
     pub show_marks: MarksType,
+
+    /// Format override, with the placeholders `{window}`, `{title}` and `{marks}`
+    #[serde(default = "FocusedWindowConfig::default_format")]
+    pub format: String,
 }
 
 impl FocusedWindowConfig {
@@ -52,6 +58,10 @@ impl FocusedWindowConfig {
     fn default_show_marks() -> MarksType {
         MarksType::None
     }
+
+    fn default_format() -> String {
+        "{window}".to_owned()
+    }
 }
 
 impl ConfigBlock for FocusedWindow {
@@ -167,12 +177,17 @@ impl ConfigBlock for FocusedWindow {
                         _ => false,
                     };
 
-                    if updated {
-                        tx.send(Task {
-                            id: id_clone.clone(),
-                            update_time: Instant::now(),
-                        })
-                        .expect("could not communicate with channel in `window` block");
+                    // The block is gone if nobody receives, e.g. after a
+                    // reload replaced it.
+                    if updated
+                        && tx
+                            .send(Task {
+                                id: id_clone.clone(),
+                                update_time: Instant::now(),
+                            })
+                            .is_err()
+                    {
+                        break;
                     }
                 }
             })
@@ -183,6 +198,10 @@ impl ConfigBlock for FocusedWindow {
             text: TextWidget::new(config),
             max_width: block_config.max_width,
             show_marks: block_config.show_marks,
+            format: FormatTemplate::from_string(&block_config.format).block_error(
+                "focused_window",
+                "Invalid format specified for focused_window",
+            )?,
             title,
             marks,
         })
@@ -203,17 +222,20 @@ impl Block for FocusedWindow {
             .block_error("focused_window", "failed to acquire lock")?)
         .clone();
         title_string = title_string.chars().take(self.max_width).collect();
-        let out_str = match self.show_marks {
-            MarksType::None => title_string,
+        let window_string = match self.show_marks {
+            MarksType::None => title_string.clone(),
             _ => {
                 if !marks_string.is_empty() {
-                    marks_string
+                    marks_string.clone()
                 } else {
-                    title_string
+                    title_string.clone()
                 }
             }
         };
-        self.text.set_text(out_str);
+        let values = map!("{window}" => window_string,
+                          "{title}" => title_string,
+                          "{marks}" => marks_string);
+        self.text.set_text(self.format.render_static_str(&values)?);
 
         Ok(None)
     }
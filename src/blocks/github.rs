@@ -12,21 +12,25 @@ use crate::blocks::{Block, ConfigBlock, Update};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
-use crate::input::I3BarEvent;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::registry::Values;
 use crate::scheduler::Task;
+use crate::subprocess::spawn_child_async;
 use crate::util::FormatTemplate;
 use crate::widget::I3BarWidget;
-use crate::widgets::text::TextWidget;
+use crate::widgets::button::ButtonWidget;
 
 const GITHUB_TOKEN_ENV: &str = "I3RS_GITHUB_TOKEN";
 
 pub struct Github {
-    text: TextWidget,
+    text: ButtonWidget,
     id: String,
     update_interval: Duration,
     api_server: String,
     token: String,
     format: FormatTemplate,
+    notifications_url: String,
+    aggregations: HashMap<String, u64>,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -45,6 +49,10 @@ pub struct GithubConfig {
     /// Format override
     #[serde(default = "GithubConfig::default_format")]
     pub format: String,
+
+    /// The page opened in a browser on click
+    #[serde(default = "GithubConfig::default_notifications_url")]
+    pub notifications_url: String,
 }
 
 impl GithubConfig {
@@ -59,6 +67,10 @@ impl GithubConfig {
     fn default_format() -> String {
         "{total}".to_owned()
     }
+
+    fn default_notifications_url() -> String {
+        "https://github.com/notifications".to_owned()
+    }
 }
 
 impl ConfigBlock for Github {
@@ -75,14 +87,19 @@ impl ConfigBlock for Github {
             }
         };
 
+        let id = Uuid::new_v4().to_simple().to_string();
         Ok(Github {
-            id: Uuid::new_v4().to_simple().to_string(),
             update_interval: block_config.interval,
-            text: TextWidget::new(config).with_text("x").with_icon("github"),
+            text: ButtonWidget::new(config, &id)
+                .with_text("x")
+                .with_icon("github"),
+            id,
             api_server: block_config.api_server,
             token,
             format: FormatTemplate::from_string(&block_config.format)
                 .block_error("github", "Invalid format specified")?,
+            notifications_url: block_config.notifications_url,
+            aggregations: HashMap::new(),
         })
     }
 }
@@ -127,6 +144,7 @@ impl Block for Github {
         );
 
         self.text.set_text(self.format.render_static_str(&values)?);
+        self.aggregations = aggregations;
 
         Ok(Some(self.update_interval.into()))
     }
@@ -135,10 +153,21 @@ impl Block for Github {
         vec![&self.text]
     }
 
-    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) && event.button == MouseButton::Left {
+            spawn_child_async("xdg-open", &[&self.notifications_url])
+                .block_error("github", "could not spawn xdg-open")?;
+        }
         Ok(())
     }
 
+    fn values(&self) -> Values {
+        self.aggregations
+            .iter()
+            .map(|(reason, count)| (reason.clone(), *count as f64))
+            .collect()
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
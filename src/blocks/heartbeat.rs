@@ -0,0 +1,353 @@
+//! A block watching heartbeat files that scripts touch to show they are alive.
+//!
+//! A heartbeat is a file named after it in the heartbeat directory. Scripts
+//! either touch it or write a Unix timestamp into it. A heartbeat whose last
+//! beat is older than its `max_age` is stale.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::Sender;
+use inotify::{Inotify, WatchMask};
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+use crate::stop::{self, Stop};
+use crate::subprocess::spawn_child_async;
+use crate::util::{format_age, xdg_runtime_dir, FormatTemplate};
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatEntry {
+    pub name: String,
+
+    /// Heartbeats older than this are stale
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_age: Duration,
+}
+
+/// The last beat of a heartbeat, as of the last update.
+#[derive(Debug, Clone)]
+struct Status {
+    name: String,
+    max_age: Duration,
+    /// `None` if the heartbeat never beat.
+    age: Option<Duration>,
+}
+
+impl Status {
+    fn is_stale(&self) -> bool {
+        self.age.is_none_or(|age| age > self.max_age)
+    }
+
+    /// How far the heartbeat is through its `max_age`, the higher the worse.
+    fn badness(&self) -> f64 {
+        match self.age {
+            Some(age) => age.as_secs_f64() / self.max_age.as_secs_f64().max(1.0),
+            None => f64::INFINITY,
+        }
+    }
+
+    fn format_age(&self) -> String {
+        match self.age {
+            Some(age) => format_age(age),
+            None => "never".to_string(),
+        }
+    }
+}
+
+/// The time of the last beat: the timestamp written into the file, or when
+/// the file was last modified.
+fn last_beat(path: &Path) -> Option<SystemTime> {
+    let content = fs::read_to_string(path).ok()?;
+    parse_timestamp(&content).or_else(|| fs::metadata(path).ok()?.modified().ok())
+}
+
+/// Parses a Unix timestamp in seconds, with or without a fractional part.
+fn parse_timestamp(content: &str) -> Option<SystemTime> {
+    let seconds = content.trim().parse::<f64>().ok()?;
+    if !seconds.is_finite() || seconds < 0. {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs_f64(seconds))
+}
+
+/// The age of a beat at `now`. Beats in the future count as just now.
+fn age(beat: Option<SystemTime>, now: SystemTime) -> Option<Duration> {
+    beat.map(|beat| now.duration_since(beat).unwrap_or_default())
+}
+
+/// The status that decides how the block looks: the stalest one.
+fn worst(statuses: &[Status]) -> Option<&Status> {
+    statuses
+        .iter()
+        .max_by(|a, b| a.badness().total_cmp(&b.badness()))
+}
+
+pub struct Heartbeat {
+    id: String,
+    output: ButtonWidget,
+    format: FormatTemplate,
+    path: PathBuf,
+    heartbeats: Vec<HeartbeatEntry>,
+    interval: Duration,
+    statuses: Vec<Status>,
+    /// Stops watching for heartbeats when the block is dropped.
+    _watcher: Stop,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    /// The heartbeats to watch
+    pub heartbeats: Vec<HeartbeatEntry>,
+
+    /// Directory holding the heartbeat files
+    #[serde(default = "HeartbeatConfig::default_path")]
+    pub path: PathBuf,
+
+    /// How often ages are re-evaluated without any change to the files
+    #[serde(
+        default = "HeartbeatConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// Format override
+    #[serde(default = "HeartbeatConfig::default_format")]
+    pub format: String,
+}
+
+impl HeartbeatConfig {
+    fn default_path() -> PathBuf {
+        xdg_runtime_dir().join("i3status-rust/heartbeats")
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn default_format() -> String {
+        "{name} {age}".to_owned()
+    }
+}
+
+fn watch(path: &Path) -> Result<Inotify> {
+    let mut notify = Inotify::init().block_error("heartbeat", "failed to start inotify")?;
+    notify
+        .add_watch(
+            path,
+            WatchMask::CLOSE_WRITE
+                | WatchMask::ATTRIB
+                | WatchMask::MOVED_TO
+                | WatchMask::DELETE
+                | WatchMask::CREATE,
+        )
+        .block_error(
+            "heartbeat",
+            &format!("failed to watch {}", path.to_string_lossy()),
+        )?;
+    Ok(notify)
+}
+
+impl ConfigBlock for Heartbeat {
+    type Config = HeartbeatConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        if block_config.heartbeats.is_empty() {
+            return Err(BlockError(
+                "heartbeat".to_owned(),
+                "at least one heartbeat has to be configured".to_owned(),
+            ));
+        }
+
+        let path = block_config.path;
+        fs::create_dir_all(&path).block_error(
+            "heartbeat",
+            &format!("failed to create directory {}", path.to_string_lossy()),
+        )?;
+        let mut notify = watch(&path)?;
+        let id_copy = id.clone();
+        let (stop, stopped) = stop::pair()?;
+        thread::Builder::new()
+            .name("heartbeat".into())
+            .spawn(move || {
+                let mut buffer = [0; 1024];
+                while stopped.inotify_events(&mut notify, &mut buffer).is_some() {
+                    let task = Task {
+                        id: id_copy.clone(),
+                        update_time: Instant::now(),
+                    };
+                    if send.send(task).is_err() {
+                        break;
+                    }
+                }
+            })
+            .block_error("heartbeat", "failed to spawn thread")?;
+
+        Ok(Heartbeat {
+            output: ButtonWidget::new(config, &id),
+            id,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("heartbeat", "Invalid format specified")?,
+            path,
+            heartbeats: block_config.heartbeats,
+            interval: block_config.interval,
+            statuses: Vec::new(),
+            _watcher: stop,
+        })
+    }
+}
+
+impl Block for Heartbeat {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let now = SystemTime::now();
+        self.statuses = self
+            .heartbeats
+            .iter()
+            .map(|heartbeat| Status {
+                name: heartbeat.name.clone(),
+                max_age: heartbeat.max_age,
+                age: age(last_beat(&self.path.join(&heartbeat.name)), now),
+            })
+            .collect();
+
+        if let Some(worst) = worst(&self.statuses) {
+            let stale = self.statuses.iter().filter(|s| s.is_stale()).count();
+            let values = map!("{name}" => Value::from(worst.name.clone()),
+                              "{age}" => Value::from(worst.format_age()),
+                              "{stale}" => Value::integer(stale),
+                              "{total}" => Value::integer(self.statuses.len()));
+            self.output
+                .set_text(self.format.render_static_str(&values)?);
+            self.output.set_state(if worst.is_stale() {
+                State::Critical
+            } else {
+                State::Good
+            });
+        }
+
+        Ok(Some(self.interval.into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.output]
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) && event.button == MouseButton::Left {
+            let body = self
+                .statuses
+                .iter()
+                .map(|status| {
+                    format!(
+                        "{}: {}{}",
+                        status.name,
+                        status.format_age(),
+                        if status.is_stale() { " (stale)" } else { "" }
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            spawn_child_async("notify-send", &["Heartbeats", &body])
+                .block_error("heartbeat", "could not spawn notify-send")?;
+        }
+        Ok(())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    fn status(name: &str, max_age: u64, age: Option<u64>) -> Status {
+        Status {
+            name: name.to_owned(),
+            max_age: Duration::from_secs(max_age),
+            age: age.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp("1600000000\n"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+        assert_eq!(
+            parse_timestamp("1600000000.5"),
+            Some(UNIX_EPOCH + Duration::from_millis(1_600_000_000_500))
+        );
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("ok"), None);
+        assert_eq!(parse_timestamp("-5"), None);
+    }
+
+    #[test]
+    fn test_last_beat() {
+        let dir = TempDir::new().unwrap();
+        let touched = dir.path().join("touched");
+        fs::write(&touched, "").unwrap();
+        assert!(last_beat(&touched).is_some());
+
+        let written = dir.path().join("written");
+        fs::write(&written, "1600000000").unwrap();
+        assert_eq!(
+            last_beat(&written),
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+
+        assert_eq!(last_beat(&dir.path().join("missing")), None);
+    }
+
+    #[test]
+    fn test_age() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let beat = UNIX_EPOCH + Duration::from_secs(400);
+        assert_eq!(age(Some(beat), now), Some(Duration::from_secs(600)));
+        assert_eq!(
+            age(Some(now + Duration::from_secs(5)), now),
+            Some(Duration::default())
+        );
+        assert_eq!(age(None, now), None);
+    }
+
+    #[test]
+    fn test_worst() {
+        let statuses = vec![
+            status("backup", 86400, Some(3600)),
+            status("sync", 600, Some(500)),
+            status("mail", 300, Some(10)),
+        ];
+        let worst_status = worst(&statuses).unwrap();
+        assert_eq!(worst_status.name, "sync");
+        assert!(!worst_status.is_stale());
+
+        let statuses = vec![
+            status("backup", 86400, Some(90000)),
+            status("sync", 600, Some(500)),
+            status("never", 600, None),
+        ];
+        assert_eq!(worst(&statuses).unwrap().name, "never");
+        assert_eq!(statuses.iter().filter(|s| s.is_stale()).count(), 2);
+        assert!(worst(&[]).is_none());
+    }
+}
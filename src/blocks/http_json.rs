@@ -0,0 +1,761 @@
+//! A block polling a URL for JSON and showing values picked out of it with
+//! JSON pointers (RFC 6901), such as `/bpi/USD/rate_float`.
+//!
+//! Requests are made with curl on a background thread. The URL and headers
+//! are handed to curl on its standard input rather than on its command line,
+//! so that tokens do not show up in the process list.
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::blocks::{self, Block, ConfigBlock, Update};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::I3BarEvent;
+use crate::registry::Values;
+use crate::scheduler::Task;
+use crate::stop::{self, Stop};
+use crate::util::{format_age, FormatTemplate};
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
+
+/// The exit code of curl when the request took longer than `--max-time`.
+const CURL_TIMEOUT: i32 = 28;
+
+/// Why the last request did not give fresh values.
+#[derive(Debug, Clone, PartialEq)]
+enum FetchError {
+    Timeout,
+    /// A status other than 200 or 304.
+    Status(u16),
+    /// A pointer that does not point at anything in the response.
+    Missing(String),
+    InvalidJson,
+    /// curl failed for another reason, such as the host not resolving.
+    Failed(String),
+}
+
+impl FetchError {
+    /// The short text shown on the bar.
+    fn label(&self) -> String {
+        match self {
+            FetchError::Timeout => "timeout".to_string(),
+            FetchError::Status(status) => format!("HTTP {}", status),
+            FetchError::Missing(pointer) => format!("no {}", pointer),
+            FetchError::InvalidJson => "invalid JSON".to_string(),
+            FetchError::Failed(_) => "error".to_string(),
+        }
+    }
+
+    fn state(&self) -> State {
+        match self {
+            FetchError::Timeout => State::Warning,
+            _ => State::Critical,
+        }
+    }
+}
+
+/// What the background thread got so far.
+#[derive(Debug, Default, Clone)]
+struct Fetched {
+    /// The values of the last good response, by placeholder name.
+    values: Option<BTreeMap<String, Value>>,
+    last_success: Option<Instant>,
+    /// Why the last request failed, `None` if it did not.
+    error: Option<FetchError>,
+    /// Counts the good responses, so that updates can tell a new one.
+    successes: u64,
+}
+
+/// The parts of an HTTP response the block looks at.
+#[derive(Debug, Clone, PartialEq)]
+struct Response {
+    status: u16,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+/// Splits the output of `curl --dump-header -` into the status and headers
+/// of the final response and its body. Redirects and `100 Continue` add
+/// header blocks of their own in front.
+fn parse_response(output: &str) -> Option<Response> {
+    let mut rest = output;
+    let mut head = None;
+    while rest.starts_with("HTTP/") {
+        let (block, body) = match rest.find("\r\n\r\n") {
+            Some(end) => (&rest[..end], &rest[end + 4..]),
+            None => (rest, ""),
+        };
+        head = Some(block);
+        rest = body;
+    }
+    let mut lines = head?.lines();
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let mut response = Response {
+        status,
+        etag: None,
+        last_modified: None,
+        body: rest.to_string(),
+    };
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let value = parts.next().map(|value| value.trim().to_string());
+        match name.as_str() {
+            "etag" => response.etag = value,
+            "last-modified" => response.last_modified = value,
+            _ => {}
+        }
+    }
+    Some(response)
+}
+
+/// A JSON value as a placeholder value. Numbers stay numbers, so that the
+/// flags of the placeholder apply.
+fn to_value(json: &JsonValue) -> Value {
+    match json {
+        JsonValue::Number(number) => match number.as_i64() {
+            Some(integer) => Value::integer(integer),
+            None => Value::float(number.as_f64().unwrap_or(f64::NAN), 2),
+        },
+        JsonValue::String(text) => Value::from(text.as_str()),
+        JsonValue::Null => Value::from(""),
+        other => Value::from(other.to_string()),
+    }
+}
+
+/// The values `pointers` point at in `body`, or `None` if the response is
+/// not modified.
+fn extract(
+    response: &Response,
+    pointers: &BTreeMap<String, String>,
+) -> std::result::Result<Option<BTreeMap<String, Value>>, FetchError> {
+    match response.status {
+        200 => {}
+        304 => return Ok(None),
+        status => return Err(FetchError::Status(status)),
+    }
+    let json: JsonValue =
+        serde_json::from_str(&response.body).map_err(|_| FetchError::InvalidJson)?;
+    pointers
+        .iter()
+        .map(|(name, pointer)| match json.pointer(pointer) {
+            Some(value) => Ok((name.clone(), to_value(value))),
+            None => Err(FetchError::Missing(pointer.clone())),
+        })
+        .collect::<std::result::Result<_, _>>()
+        .map(Some)
+}
+
+/// Replaces `${NAME}` in `value` with the environment variable `NAME`. Returns
+/// the name of the first variable that is not set as the error.
+fn expand_env<F>(value: &str, lookup: F) -> std::result::Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&lookup(name).ok_or_else(|| name.to_string())?);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Quotes `value` for a curl config file. Line breaks are dropped, as they
+/// would end the header.
+fn curl_quote(value: &str) -> String {
+    let escaped: String = value
+        .chars()
+        .filter(|c| *c != '\r' && *c != '\n')
+        .flat_map(|c| match c {
+            '\\' | '"' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect();
+    format!("\"{}\"", escaped)
+}
+
+/// Makes requests, remembering what the server said about caching.
+struct Fetcher {
+    url: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Fetcher {
+    /// The curl config for the next request.
+    fn curl_config(&self) -> String {
+        let mut headers = self.headers.clone();
+        if let Some(ref etag) = self.etag {
+            headers.push(("If-None-Match".to_string(), etag.clone()));
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            headers.push(("If-Modified-Since".to_string(), last_modified.clone()));
+        }
+        let mut config = format!("url = {}\n", curl_quote(&self.url));
+        for (name, value) in headers {
+            config.push_str(&format!(
+                "header = {}\n",
+                curl_quote(&format!("{}: {}", name, value))
+            ));
+        }
+        config
+    }
+
+    fn fetch(&mut self) -> std::result::Result<Response, FetchError> {
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--location"])
+            .args(["--dump-header", "-", "--config", "-"])
+            .arg("--max-time")
+            .arg(self.timeout.as_secs_f64().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| FetchError::Failed(format!("failed to run curl: {}", e)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(self.curl_config().as_bytes())
+                .map_err(|e| FetchError::Failed(format!("failed to write to curl: {}", e)))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| FetchError::Failed(format!("failed to run curl: {}", e)))?;
+        match output.status.code() {
+            Some(0) => {}
+            Some(CURL_TIMEOUT) => return Err(FetchError::Timeout),
+            _ => {
+                return Err(FetchError::Failed(
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                ))
+            }
+        }
+        let response = parse_response(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| FetchError::Failed("unexpected curl output".to_string()))?;
+        if response.status == 200 {
+            self.etag = response.etag.clone();
+            self.last_modified = response.last_modified.clone();
+        }
+        Ok(response)
+    }
+}
+
+pub struct HttpJson {
+    id: String,
+    text: TextWidget,
+    format: FormatTemplate,
+    threshold_key: Option<String>,
+    warning: Option<f64>,
+    critical: Option<f64>,
+    fetched: Arc<Mutex<Fetched>>,
+    /// `Fetched::successes` as of the last update.
+    seen: u64,
+    values: Values,
+    /// Stops fetching when the block is dropped.
+    _fetcher: Stop,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HttpJsonConfig {
+    pub url: String,
+
+    /// Headers sent with each request. `${NAME}` in a value is replaced with
+    /// the environment variable `NAME`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    /// Placeholder name -> JSON pointer to its value
+    pub values: BTreeMap<String, String>,
+
+    /// Format override
+    #[serde(default = "HttpJsonConfig::default_format")]
+    pub format: Option<String>,
+
+    /// Time between requests
+    #[serde(
+        default = "HttpJsonConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// How long a request may take
+    #[serde(
+        default = "HttpJsonConfig::default_timeout",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub timeout: Duration,
+
+    /// The value that `warning` and `critical` apply to
+    #[serde(default = "HttpJsonConfig::default_threshold_key")]
+    pub threshold_key: Option<String>,
+
+    #[serde(default = "HttpJsonConfig::default_threshold")]
+    pub warning: Option<f64>,
+
+    #[serde(default = "HttpJsonConfig::default_threshold")]
+    pub critical: Option<f64>,
+}
+
+impl HttpJsonConfig {
+    fn default_format() -> Option<String> {
+        None
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn default_threshold_key() -> Option<String> {
+        None
+    }
+
+    fn default_threshold() -> Option<f64> {
+        None
+    }
+}
+
+impl ConfigBlock for HttpJson {
+    type Config = HttpJsonConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        if block_config.values.is_empty() {
+            return Err(BlockError(
+                "http_json".to_owned(),
+                "at least one value has to be configured".to_owned(),
+            ));
+        }
+        if let Some(ref key) = block_config.threshold_key {
+            if !block_config.values.contains_key(key) {
+                return Err(BlockError(
+                    "http_json".to_owned(),
+                    format!("threshold_key '{}' is not one of the values", key),
+                ));
+            }
+        }
+        let headers = block_config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = expand_env(value, |name| env::var(name).ok()).map_err(|var| {
+                    BlockError(
+                        "http_json".to_owned(),
+                        format!(
+                            "environment variable {} for header {} is not set",
+                            var, name
+                        ),
+                    )
+                })?;
+                Ok((name.clone(), value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Without a format, show all values.
+        let format = match block_config.format {
+            Some(format) => format,
+            None => block_config
+                .values
+                .keys()
+                .map(|name| format!("{{{}}}", name))
+                .collect::<Vec<_>>()
+                .join(" "),
+        };
+
+        let fetched = Arc::new(Mutex::new(Fetched::default()));
+        let mut fetcher = Fetcher {
+            url: block_config.url,
+            headers,
+            timeout: block_config.timeout,
+            etag: None,
+            last_modified: None,
+        };
+        let pointers = block_config.values;
+        let interval = block_config.interval;
+        let fetched_copy = fetched.clone();
+        let id_copy = id.clone();
+        let (stop, stopped) = stop::pair()?;
+        thread::Builder::new()
+            .name("http_json".into())
+            .spawn(move || loop {
+                let result = fetcher
+                    .fetch()
+                    .and_then(|response| extract(&response, &pointers));
+                if let Ok(mut fetched) = fetched_copy.lock() {
+                    match result {
+                        Ok(values) => {
+                            if values.is_some() {
+                                fetched.values = values;
+                            }
+                            fetched.last_success = Some(Instant::now());
+                            fetched.error = None;
+                            fetched.successes += 1;
+                        }
+                        Err(error) => {
+                            // Only log what changed, not every failed request.
+                            if fetched.error.as_ref() != Some(&error) {
+                                let message = match error {
+                                    FetchError::Failed(ref message) => message.clone(),
+                                    ref error => error.label(),
+                                };
+                                log_block_error(&id_copy, "http_json", &message);
+                            }
+                            fetched.error = Some(error);
+                        }
+                    }
+                }
+                let task = Task {
+                    id: id_copy.clone(),
+                    update_time: Instant::now(),
+                };
+                if send.send(task).is_err() || !stopped.sleep(interval) {
+                    break;
+                }
+            })
+            .block_error("http_json", "failed to spawn thread")?;
+
+        Ok(HttpJson {
+            id,
+            text: TextWidget::new(config).with_text("…"),
+            format: FormatTemplate::from_string(&format)
+                .block_error("http_json", "Invalid format specified")?,
+            threshold_key: block_config.threshold_key,
+            warning: block_config.warning,
+            critical: block_config.critical,
+            fetched,
+            seen: 0,
+            values: Values::new(),
+            _fetcher: stop,
+        })
+    }
+}
+
+impl HttpJson {
+    /// The state for the value of `threshold_key`, the higher the worse.
+    fn threshold_state(&self, values: &BTreeMap<String, Value>) -> State {
+        let value = match self.threshold_key.as_ref().and_then(|key| values.get(key)) {
+            Some(Value::Integer(value, _)) => *value as f64,
+            Some(Value::Float(value, _)) => *value,
+            _ => return State::Idle,
+        };
+        if self.critical.is_some_and(|critical| value >= critical) {
+            State::Critical
+        } else if self.warning.is_some_and(|warning| value >= warning) {
+            State::Warning
+        } else {
+            State::Idle
+        }
+    }
+
+    /// The text and state showing `fetched` at `now`. After an error the last
+    /// good values stay, marked with the error and their age.
+    fn render(&self, fetched: &Fetched, now: Instant) -> Result<Option<(String, State)>> {
+        let (values, error) = match (&fetched.values, &fetched.error) {
+            (Some(values), error) => (values, error),
+            (None, Some(error)) => return Ok(Some((error.label(), error.state()))),
+            (None, None) => return Ok(None),
+        };
+        let placeholders: HashMap<String, Value> = values
+            .iter()
+            .map(|(name, value)| (format!("{{{}}}", name), value.clone()))
+            .collect();
+        let placeholders: HashMap<&str, Value> = placeholders
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        let mut text = self.format.render_static_str(&placeholders)?;
+        match error {
+            Some(error) => {
+                let age = fetched
+                    .last_success
+                    .map(|success| format_age(now.saturating_duration_since(success)))
+                    .unwrap_or_default();
+                text.push_str(&format!(" ({}, {})", error.label(), age));
+                Ok(Some((text, error.state())))
+            }
+            None => Ok(Some((text, self.threshold_state(values)))),
+        }
+    }
+}
+
+impl Block for HttpJson {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let fetched = self
+            .fetched
+            .lock()
+            .block_error("http_json", "failed to acquire lock")?
+            .clone();
+        if fetched.successes != self.seen {
+            self.seen = fetched.successes;
+            blocks::data_refreshed();
+        }
+        if let Some((text, state)) = self.render(&fetched, Instant::now())? {
+            self.text.set_text(text);
+            self.text.set_state(state);
+        }
+        self.values = fetched
+            .values
+            .iter()
+            .flatten()
+            .filter_map(|(name, value)| match value {
+                Value::Integer(value, _) => Some((name.clone(), *value as f64)),
+                Value::Float(value, _) => Some((name.clone(), *value)),
+                Value::Text(_) | Value::Bar(_) => None,
+            })
+            .collect();
+
+        // Keep the age of stale values current.
+        Ok(Some(Duration::from_secs(60).into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.text]
+    }
+
+    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
+        Ok(())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn values(&self) -> Values {
+        self.values.clone()
+    }
+
+    fn reports_refreshes(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            etag: None,
+            last_modified: None,
+            body: body.to_string(),
+        }
+    }
+
+    fn pointers(list: &[(&str, &str)]) -> BTreeMap<String, String> {
+        list.iter()
+            .map(|(name, pointer)| (name.to_string(), pointer.to_string()))
+            .collect()
+    }
+
+    fn block(format: &str) -> HttpJson {
+        HttpJson {
+            id: "http_json".to_string(),
+            text: TextWidget::new(Config::default()),
+            format: FormatTemplate::from_string(format).unwrap(),
+            threshold_key: Some("load".to_string()),
+            warning: Some(0.7),
+            critical: Some(0.9),
+            fetched: Arc::new(Mutex::new(Fetched::default())),
+            seen: 0,
+            values: Values::new(),
+            _fetcher: stop::pair().unwrap().0,
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let output = concat!(
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: https://example.com/\r\n\r\n",
+            "HTTP/2 200\r\nETag: \"abc\"\r\nlast-modified: Wed, 21 Oct 2015 07:28:00 GMT\r\n\r\n",
+            "{\"a\": 1}"
+        );
+        assert_eq!(
+            parse_response(output),
+            Some(Response {
+                status: 200,
+                etag: Some("\"abc\"".to_string()),
+                last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+                body: "{\"a\": 1}".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_response("HTTP/1.1 304 Not Modified\r\n\r\n"),
+            Some(response(304, ""))
+        );
+        assert_eq!(parse_response("{\"a\": 1}"), None);
+    }
+
+    #[test]
+    fn test_extract() {
+        let body =
+            r#"{"bpi": {"USD": {"rate": "9,241.53", "rate_float": 9241.5317}}, "n": [3, 4]}"#;
+        let values = extract(
+            &response(200, body),
+            &pointers(&[
+                ("usd", "/bpi/USD/rate_float"),
+                ("n", "/n/1"),
+                ("rate", "/bpi/USD/rate"),
+            ]),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(values["usd"], Value::float(9241.5317, 2));
+        assert_eq!(values["n"], Value::integer(4));
+        assert_eq!(values["rate"], Value::from("9,241.53"));
+
+        let missing = extract(&response(200, body), &pointers(&[("eur", "/bpi/EUR/rate")]));
+        assert_eq!(
+            missing,
+            Err(FetchError::Missing("/bpi/EUR/rate".to_string()))
+        );
+        assert_eq!(
+            extract(&response(503, "busy"), &pointers(&[("n", "/n")])),
+            Err(FetchError::Status(503))
+        );
+        assert_eq!(
+            extract(&response(200, "<html>"), &pointers(&[("n", "/n")])),
+            Err(FetchError::InvalidJson)
+        );
+        assert_eq!(
+            extract(&response(304, ""), &pointers(&[("n", "/n")])),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_to_value() {
+        assert_eq!(to_value(&serde_json::json!(-3)), Value::integer(-3));
+        assert_eq!(to_value(&serde_json::json!(0.5)), Value::float(0.5, 2));
+        assert_eq!(to_value(&serde_json::json!(true)), Value::from("true"));
+        assert_eq!(to_value(&serde_json::json!(null)), Value::from(""));
+        assert_eq!(to_value(&serde_json::json!([1, 2])), Value::from("[1,2]"));
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let lookup = |name: &str| match name {
+            "TOKEN" => Some("s3cr3t".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            expand_env("Bearer ${TOKEN}", lookup),
+            Ok("Bearer s3cr3t".to_string())
+        );
+        assert_eq!(expand_env("plain", lookup), Ok("plain".to_string()));
+        assert_eq!(
+            expand_env("${TOKEN}${TOKEN", lookup),
+            Ok("s3cr3t${TOKEN".to_string())
+        );
+        assert_eq!(expand_env("${NOPE}", lookup), Err("NOPE".to_string()));
+    }
+
+    #[test]
+    fn test_curl_config() {
+        let mut fetcher = Fetcher {
+            url: "https://example.com/a?b=\"c\"".to_string(),
+            headers: vec![(
+                "Authorization".to_string(),
+                "Bearer x\r\nEvil: 1".to_string(),
+            )],
+            timeout: Duration::from_secs(10),
+            etag: None,
+            last_modified: None,
+        };
+        assert_eq!(
+            fetcher.curl_config(),
+            concat!(
+                "url = \"https://example.com/a?b=\\\"c\\\"\"\n",
+                "header = \"Authorization: Bearer xEvil: 1\"\n"
+            )
+        );
+        fetcher.headers.clear();
+        fetcher.etag = Some("\"abc\"".to_string());
+        assert!(fetcher
+            .curl_config()
+            .contains("header = \"If-None-Match: \\\"abc\\\"\"\n"));
+    }
+
+    #[test]
+    fn test_render() {
+        let block = block("{load:.1} {host}");
+        let now = Instant::now();
+        let shown = |fetched: &Fetched, later: u64| {
+            block
+                .render(fetched, now + Duration::from_secs(later))
+                .unwrap()
+                .map(|(text, state)| (text, format!("{:?}", state)))
+        };
+        let mut fetched = Fetched::default();
+        assert_eq!(shown(&fetched, 0), None);
+
+        fetched.error = Some(FetchError::Timeout);
+        assert_eq!(
+            shown(&fetched, 0),
+            Some(("timeout".to_string(), "Warning".to_string()))
+        );
+
+        let mut values = BTreeMap::new();
+        values.insert("load".to_string(), Value::float(0.75, 2));
+        values.insert("host".to_string(), Value::from("a"));
+        fetched.values = Some(values);
+        fetched.last_success = Some(now);
+        fetched.error = None;
+        assert_eq!(
+            shown(&fetched, 0),
+            Some(("0.8 a".to_string(), "Warning".to_string()))
+        );
+
+        // Failures keep the values, marked with why and how old they are.
+        fetched.error = Some(FetchError::Status(502));
+        assert_eq!(
+            shown(&fetched, 600),
+            Some(("0.8 a (HTTP 502, 10m)".to_string(), "Critical".to_string()))
+        );
+        fetched.error = Some(FetchError::Missing("/load".to_string()));
+        assert_eq!(
+            shown(&fetched, 60),
+            Some(("0.8 a (no /load, 1m)".to_string(), "Critical".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let block = block("{load}");
+        let state = |value: Value| {
+            let mut values = BTreeMap::new();
+            values.insert("load".to_string(), value);
+            block.threshold_state(&values)
+        };
+        assert!(matches!(state(Value::float(0.5, 2)), State::Idle));
+        assert!(matches!(state(Value::float(0.7, 2)), State::Warning));
+        assert!(matches!(state(Value::integer(1)), State::Critical));
+        assert!(matches!(state(Value::from("high")), State::Idle));
+    }
+}
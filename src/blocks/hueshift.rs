@@ -5,13 +5,14 @@ use crossbeam_channel::Sender;
 use serde_derive::Deserialize;
 use uuid::Uuid;
 
-use crate::blocks::{Block, ConfigBlock, Update};
+use crate::blocks::{Block, ClickSafety, ConfigBlock, Update};
 use crate::config::{Config, LogicalDirection};
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
-use crate::util::has_command;
+use crate::util::{has_command, FormatTemplate};
 use crate::widget::I3BarWidget;
 use crate::widgets::button::ButtonWidget;
 
@@ -25,6 +26,7 @@ pub struct Hueshift {
     min_temp: u16,
     hue_shifter: Option<String>,
     click_temp: u16,
+    format: FormatTemplate,
 
     //useful, but optional
     #[allow(dead_code)]
@@ -62,6 +64,10 @@ pub struct HueshiftConfig {
     pub step: u16,
     #[serde(default = "HueshiftConfig::default_click_temp")]
     pub click_temp: u16,
+
+    /// Format override, with the placeholder `{temperature}`
+    #[serde(default = "HueshiftConfig::default_format")]
+    pub format: String,
 }
 
 impl HueshiftConfig {
@@ -101,6 +107,9 @@ impl HueshiftConfig {
     fn default_click_temp() -> u16 {
         6500 as u16
     }
+    fn default_format() -> String {
+        "{temperature}".to_owned()
+    }
 }
 
 impl ConfigBlock for Hueshift {
@@ -129,7 +138,7 @@ impl ConfigBlock for Hueshift {
         Ok(Hueshift {
             id: id.clone(),
             update_interval: block_config.interval,
-            text: ButtonWidget::new(config.clone(), &id).with_text(&current_temp.to_string()),
+            text: ButtonWidget::new(config.clone(), &id),
             tx_update_request,
             step,
             max_temp,
@@ -137,6 +146,8 @@ impl ConfigBlock for Hueshift {
             current_temp,
             hue_shifter: block_config.hue_shifter,
             click_temp: block_config.click_temp,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("hueshift", "Invalid format specified for hueshift")?,
             config,
         })
     }
@@ -144,7 +155,8 @@ impl ConfigBlock for Hueshift {
 
 impl Block for Hueshift {
     fn update(&mut self) -> Result<Option<Update>> {
-        self.text.set_text(&self.current_temp.to_string());
+        let values = map!("{temperature}" => Value::integer(self.current_temp));
+        self.text.set_text(self.format.render_static_str(&values)?);
         Ok(Some(self.update_interval.into()))
     }
 
@@ -196,6 +208,10 @@ impl Block for Hueshift {
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
@@ -1,50 +1,395 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs::{read_dir, File};
 use std::io::prelude::*;
-use std::sync::{Arc, Mutex};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crossbeam_channel::Sender;
 use dbus::ffidisp::stdintf::org_freedesktop_dbus::Properties;
 use dbus::{
     arg,
     ffidisp::{Connection, ConnectionItem},
+    Message,
 };
+use lazy_static::lazy_static;
 use regex::Regex;
 use serde_derive::Deserialize;
 use uuid::Uuid;
 
+use crate::blocks::keyboard_layout::{KeyboardLayoutMonitor, SetXkbMap};
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
+use crate::de::deserialize_duration;
 use crate::errors::*;
-use crate::input::I3BarEvent;
+use crate::input::{ClickAction, ClickBindings, I3BarEvent, MouseButton};
 use crate::scheduler::Task;
-use crate::util::{xdg_config_home, FormatTemplate};
-use crate::widget::I3BarWidget;
-use crate::widgets::text::TextWidget;
+use crate::stop::{self, Stop};
+use crate::subprocess::spawn_child_async;
+use crate::util::{glob_match, xdg_config_home, FormatTemplate};
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+/// An input engine, as described by IBus.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Engine {
+    name: String,
+    /// Language code such as `en` or `zh_CN`, empty if unknown.
+    language: String,
+    /// A short indicator for panels, such as `あ`, empty if the engine has
+    /// none.
+    symbol: String,
+}
+
+/// What is known about the current engine.
+#[derive(Debug, Clone, PartialEq)]
+enum EngineState {
+    /// Still connecting to IBus or waiting for its answer.
+    Loading,
+    Ready(Engine),
+    /// IBus could not be reached.
+    Error(String),
+    /// IBus keeps an engine per window, and no window is focused.
+    NoFocus,
+}
+
+/// How long the listener waits before connecting to IBus again, doubled after
+/// every failed attempt up to `MAX_RECONNECT_DELAY`.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// How long the block shows a warning after IBus rejected an engine.
+const WARNING_DURATION: Duration = Duration::from_secs(2);
 
 pub struct IBus {
     id: String,
-    text: TextWidget,
-    engine: Arc<Mutex<String>>,
+    text: ButtonWidget,
+    engine: Arc<Mutex<EngineState>>,
     mappings: Option<BTreeMap<String, String>>,
     format: FormatTemplate,
+    variant_format: FormatTemplate,
+    loading_text: String,
+    display: LanguageDisplay,
+    lang_overrides: BTreeMap<String, String>,
+    use_symbol: bool,
+    as_icon: bool,
+    /// With the auto backend: how to fall back on XKB if IBus fails.
+    xkb_fallback: Option<(Sender<Task>, Duration)>,
+    /// Stops watching XKB when the block is dropped.
+    xkb_watcher: Option<Stop>,
+    click: ClickBindings<EngineAction>,
+    /// Until when to show that switching engines failed.
+    warning_until: Option<Instant>,
+    send: Sender<Task>,
+    /// How long IBus may stay silent before the engine shown is considered
+    /// stale, `None` if that is not checked.
+    stale_after: Option<Duration>,
+    stale_marker: String,
+    clock: fn() -> Instant,
+    /// How often update() asks IBus for the engine itself, `None` if the
+    /// engine comes from a background thread.
+    poll_interval: Option<Duration>,
+    client: Box<dyn IBusClient>,
+    /// The installed engines the engine filter lets through, `None` if it
+    /// lets every engine through.
+    engines: Option<Vec<String>>,
+    preedit_marker: String,
+    no_focus_text: String,
+    error_format: FormatTemplate,
+    /// Why the block failed to start, until starting it again on click
+    /// worked.
+    start_error: Option<String>,
+    setup: Setup,
+}
+
+/// Where the block gets the current engine or layout from.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IBusBackend {
+    /// IBus' GlobalEngineChanged signal.
+    #[default]
+    IBus,
+    /// The XKB layout, polled with `setxkbmap`, for when IBus does not
+    /// manage layouts.
+    Xkb,
+    /// IBus if its daemon can be reached, XKB otherwise.
+    Auto,
+}
+
+/// Whether the block shows the engine of the focused window, for when IBus
+/// does not share one engine among all of them.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PerWindow {
+    /// If IBus is set up not to share the engine.
+    #[default]
+    Auto,
+    Force,
+    Off,
+}
+
+/// How the `{language}` placeholder shows the language of the engine.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LanguageDisplay {
+    /// The code reported by IBus, e.g. `zh_CN`.
+    #[default]
+    Code,
+    /// The English name of the language, e.g. `Chinese`.
+    Name,
+    /// The flag of the country of the language, e.g. 🇨🇳.
+    Flag,
+}
+
+/// Which of the installed engines the block offers.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct EngineFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl EngineFilter {
+    fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Engines matching an include pattern always pass, even if they match an
+    /// exclude pattern too. Otherwise engines pass unless they are excluded
+    /// or there are include patterns.
+    fn matches(&self, engine: &str) -> bool {
+        let any = |patterns: &[String]| patterns.iter().any(|p| glob_match(p, engine));
+        any(&self.include) || (self.include.is_empty() && !any(&self.exclude))
+    }
+
+    fn apply(&self, engines: &[String]) -> Result<Vec<String>> {
+        let matching: Vec<String> = engines
+            .iter()
+            .filter(|engine| self.matches(engine))
+            .cloned()
+            .collect();
+        if matching.is_empty() {
+            return Err(BlockError(
+                "ibus".to_string(),
+                format!(
+                    "no engine matches engines_include and engines_exclude, IBus has: {}",
+                    engines.join(", ")
+                ),
+            ));
+        }
+        Ok(matching)
+    }
+}
+
+/// What a click on the ibus block can do, given as the engine to switch to,
+/// or `"next"`, `"cycle"` or `"prev"` to cycle through the engines.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "String")]
+pub enum EngineAction {
+    Switch(String),
+    /// Switches this many engines forward, or backward if negative.
+    Cycle(isize),
+}
+
+impl From<String> for EngineAction {
+    fn from(engine: String) -> Self {
+        match engine.as_str() {
+            "next" | "cycle" => EngineAction::Cycle(1),
+            "prev" => EngineAction::Cycle(-1),
+            _ => EngineAction::Switch(engine),
+        }
+    }
+}
+
+/// The engine `step` engines after `current` in `engines`, wrapping around.
+/// If `current` is not among them, stepping forward starts at the first and
+/// stepping backward at the last.
+fn cycle<'a>(engines: &'a [String], current: Option<&str>, step: isize) -> Option<&'a str> {
+    if engines.is_empty() {
+        return None;
+    }
+    let len = engines.len() as isize;
+    let index = match engines
+        .iter()
+        .position(|engine| Some(engine.as_str()) == current)
+    {
+        Some(index) => index as isize + step,
+        None if step > 0 => step - 1,
+        None => len + step,
+    };
+    Some(&engines[index.rem_euclid(len) as usize])
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct IBusConfig {
+    #[serde(default = "IBusBackend::default")]
+    pub backend: IBusBackend,
+
+    #[serde(default = "PerWindow::default")]
+    pub per_window: PerWindow,
+
+    /// Value of `{preedit}` while text is being composed.
+    #[serde(default = "IBusConfig::default_preedit_marker")]
+    pub preedit_marker: String,
+
+    /// Text shown while IBus keeps an engine per window and none is focused.
+    #[serde(default = "IBusConfig::default_no_focus_text")]
+    pub no_focus_text: String,
+
+    /// How often the XKB layout is polled.
+    #[serde(
+        default = "IBusConfig::default_xkb_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub xkb_interval: Duration,
+
+    /// How long to wait for IBus to answer before giving up.
+    #[serde(
+        default = "IBusConfig::default_connect_timeout",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub connect_timeout: Duration,
+
+    /// The socket file to read the address of IBus from, instead of looking
+    /// for the one of the current display.
+    #[serde(default = "IBusConfig::default_socket")]
+    pub socket: Option<PathBuf>,
+
+    /// Timeout of each D-Bus call in milliseconds, overriding
+    /// `connect_timeout`.
+    #[serde(default = "IBusConfig::default_dbus_timeout_ms")]
+    pub dbus_timeout_ms: Option<u64>,
+
+    /// How long after showing an engine change further changes are held
+    /// back, in milliseconds, as switching engines can send a burst of
+    /// signals.
+    #[serde(default = "IBusConfig::default_signal_debounce_ms")]
+    pub signal_debounce_ms: u64,
+
+    /// How long IBus may not answer before the engine shown is marked as
+    /// stale, 0 to never mark it.
+    #[serde(
+        default = "IBusConfig::default_stale_after",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub stale_after: Duration,
+
+    /// Text appended to a stale engine.
+    #[serde(default = "IBusConfig::default_stale_marker")]
+    pub stale_marker: String,
+
+    /// How often to ask IBus for the engine instead of listening for its
+    /// signals, 0 to listen.
+    #[serde(
+        default = "IBusConfig::default_poll_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub poll_interval: Duration,
+
+    /// Text shown until IBus has answered.
+    #[serde(default = "IBusConfig::default_loading_text")]
+    pub loading_text: String,
+
+    /// Format shown if the block failed to start.
+    #[serde(default = "IBusConfig::default_error_format")]
+    pub error_format: String,
+
+    #[serde(default = "LanguageDisplay::default")]
+    pub display: LanguageDisplay,
+
+    /// Language code -> country code used for its flag, for codes without a
+    /// country (e.g. `en = "GB"`).
+    #[serde(default)]
+    pub lang_overrides: BTreeMap<String, String>,
+
     #[serde(default = "IBusConfig::default_mappings")]
     pub mappings: Option<BTreeMap<String, String>>,
 
+    /// Engines to switch to on click, replacing cycling through them.
+    #[serde(default, alias = "click_bindings")]
+    pub click: ClickBindings<EngineAction>,
+
+    /// Patterns of the engines to offer, e.g. `xkb:us*`. Engines matching
+    /// one are offered even if they match `engines_exclude`.
+    #[serde(default)]
+    pub engines_include: Vec<String>,
+
+    /// Patterns of the engines not to offer.
+    #[serde(default)]
+    pub engines_exclude: Vec<String>,
+
     #[serde(default = "IBusConfig::default_format")]
     pub format: String,
+
+    /// Show the symbol of the engine (e.g. `あ`) instead of the format, for
+    /// engines that have one.
+    #[serde(default = "IBusConfig::default_use_symbol")]
+    pub use_symbol: bool,
+
+    /// Show a short abbreviation of the engine (e.g. `us` or `ja`) instead of
+    /// the format, unless it has a mapping.
+    #[serde(default = "IBusConfig::default_as_icon")]
+    pub as_icon: bool,
+
+    /// Format of the `{variant}` placeholder, used only if the variant is not
+    /// empty.
+    #[serde(default = "IBusConfig::default_variant_format")]
+    pub variant_format: String,
 }
 
 impl IBusConfig {
+    fn default_xkb_interval() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_connect_timeout() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn default_socket() -> Option<PathBuf> {
+        None
+    }
+
+    fn default_dbus_timeout_ms() -> Option<u64> {
+        None
+    }
+
+    fn default_signal_debounce_ms() -> u64 {
+        50
+    }
+
+    fn default_stale_after() -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn default_stale_marker() -> String {
+        "!".into()
+    }
+
+    fn default_poll_interval() -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn default_loading_text() -> String {
+        "…".into()
+    }
+
+    fn default_preedit_marker() -> String {
+        "✎".into()
+    }
+
+    fn default_no_focus_text() -> String {
+        "-".into()
+    }
+
+    fn default_error_format() -> String {
+        "ibus: err".into()
+    }
+
     fn default_mappings() -> Option<BTreeMap<String, String>> {
         None
     }
@@ -52,214 +397,2563 @@ impl IBusConfig {
     fn default_format() -> String {
         "{engine}".into()
     }
+
+    fn default_variant_format() -> String {
+        "{variant}".into()
+    }
+
+    fn default_use_symbol() -> bool {
+        false
+    }
+
+    fn default_as_icon() -> bool {
+        false
+    }
 }
 
 impl ConfigBlock for IBus {
     type Config = IBusConfig;
 
     fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
-        let id: String = Uuid::new_v4().to_simple().to_string();
-        let id_copy = id.clone();
+        let timeout = block_config
+            .dbus_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(block_config.connect_timeout);
+        let client = DBusClient {
+            socket: block_config.socket.clone(),
+            per_window: block_config.per_window,
+            timeout,
+            signal_debounce: Duration::from_millis(block_config.signal_debounce_ms),
+            connection: None,
+        };
+        IBus::with_client(block_config, config, send, Box::new(client))
+    }
+}
 
-        let ibus_address = get_ibus_address()?;
-        let c = Connection::open_private(&ibus_address).block_error(
-            "ibus",
-            &format!("Failed to establish D-Bus connection to {}", ibus_address),
-        )?;
-        let p = c.with_path("org.freedesktop.IBus", "/org/freedesktop/IBus", 5000);
-        let info: arg::Variant<Box<dyn arg::RefArg>> = p
-            .get("org.freedesktop.IBus", "GlobalEngine")
-            .block_error("ibus", "Failed to query IBus")?;
-
-        // `info` should contain something containing an array with the contents as such:
-        // [name, longname, description, language, license, author, icon, layout, layout_variant, layout_option, rank, hotkeys, symbol, setup, version, textdomain, icon_prop_key]
-        // Refer to: https://github.com/ibus/ibus/blob/7cef5bf572596361bc502e8fa917569676a80372/src/ibusenginedesc.c
-        // e.g.                   name           longname        description     language
-        // ["IBusEngineDesc", {}, "xkb:us::eng", "English (US)", "English (US)", "en", "GPL", "Peng Huang <shawn.p.huang@gmail.com>", "ibus-keyboard", "us", 99, "", "", "", "", "", "", "", ""]
-        //                         ↑ We will use this element (name) as it is what GlobalEngineChanged signal returns.
-        let current_engine = info
-            .0
-            .as_iter()
-            .block_error("ibus", "Failed to parse D-Bus message (step 1)")?
-            .nth(2)
-            .block_error("ibus", "Failed to parse D-Bus message (step 2)")?
-            .as_str()
-            .unwrap_or("??");
-
-        let engine_original = Arc::new(Mutex::new(String::from(current_engine)));
-        let engine = engine_original.clone();
-        thread::Builder::new()
-            .name("ibus".into())
-            .spawn(move || {
-                let c = Connection::open_private(&ibus_address)
-                    .expect("Failed to establish D-Bus connection in thread");
-                c.add_match("interface='org.freedesktop.IBus',member='GlobalEngineChanged'")
-                    .expect("Failed to add D-Bus message rule - has IBus interface changed?");
-                loop {
-                    for ci in c.iter(100_000) {
-                        if let Some(engine_name) = parse_msg(&ci) {
-                            let mut engine = engine_original.lock().unwrap();
-                            *engine = engine_name.to_string();
-                            // Tell block to update now.
-                            send.send(Task {
-                                id: id.clone(),
-                                update_time: Instant::now(),
-                            })
-                            .unwrap();
-                        };
-                    }
-                }
-            })
-            .unwrap();
+impl IBus {
+    /// Creates the block, talking to IBus through `client`.
+    fn with_client(
+        block_config: IBusConfig,
+        config: Config,
+        send: Sender<Task>,
+        mut client: Box<dyn IBusClient>,
+    ) -> Result<Self> {
+        let id: String = Uuid::new_v4().to_simple().to_string();
+        let setup = Setup {
+            id: id.clone(),
+            send: send.clone(),
+            backend: block_config.backend,
+            xkb_interval: block_config.xkb_interval,
+            stale_after: Some(block_config.stale_after).filter(|d| *d > Duration::default()),
+            poll_interval: poll_interval(block_config.backend, block_config.poll_interval),
+            engine_filter: EngineFilter {
+                include: block_config.engines_include,
+                exclude: block_config.engines_exclude,
+            },
+        };
+        let (started, start_error) = match start(&setup, &mut *client) {
+            Ok(started) => (started, None),
+            Err(e) => {
+                log_block_error(&id, "ibus", &format!("{:?}", e));
+                (Started::default(), Some(short_message(e)))
+            }
+        };
 
         Ok(IBus {
-            id: id_copy,
-            text: TextWidget::new(config).with_text("IBus"),
-            engine,
+            text: ButtonWidget::new(config, &id).with_text(&block_config.loading_text),
+            id,
+            engine: started.engine,
             mappings: block_config.mappings,
             format: FormatTemplate::from_string(&block_config.format)?,
+            variant_format: FormatTemplate::from_string(&block_config.variant_format)?,
+            loading_text: block_config.loading_text,
+            display: block_config.display,
+            lang_overrides: block_config.lang_overrides,
+            use_symbol: block_config.use_symbol,
+            as_icon: block_config.as_icon,
+            xkb_fallback: started.xkb_fallback,
+            xkb_watcher: started.xkb_watcher,
+            click: block_config.click,
+            warning_until: None,
+            send,
+            stale_after: started.stale_after,
+            stale_marker: block_config.stale_marker,
+            clock: Instant::now,
+            poll_interval: started.poll_interval,
+            client,
+            engines: started.engines,
+            preedit_marker: block_config.preedit_marker,
+            no_focus_text: block_config.no_focus_text,
+            error_format: FormatTemplate::from_string(&block_config.error_format)?,
+            start_error,
+            setup,
         })
     }
 }
 
-impl Block for IBus {
-    fn id(&self) -> &str {
-        &self.id
+/// What the block needs to start watching the engine, kept to try again if
+/// that failed.
+#[derive(Clone)]
+struct Setup {
+    id: String,
+    send: Sender<Task>,
+    backend: IBusBackend,
+    xkb_interval: Duration,
+    stale_after: Option<Duration>,
+    poll_interval: Option<Duration>,
+    engine_filter: EngineFilter,
+}
+
+/// A block that started watching the engine.
+struct Started {
+    engine: Arc<Mutex<EngineState>>,
+    stale_after: Option<Duration>,
+    poll_interval: Option<Duration>,
+    xkb_fallback: Option<(Sender<Task>, Duration)>,
+    xkb_watcher: Option<Stop>,
+    engines: Option<Vec<String>>,
+}
+
+impl Default for Started {
+    fn default() -> Self {
+        Started {
+            engine: Arc::new(Mutex::new(EngineState::Loading)),
+            stale_after: None,
+            poll_interval: None,
+            xkb_fallback: None,
+            xkb_watcher: None,
+            engines: None,
+        }
     }
+}
 
-    // Updates the internal state of the block.
-    fn update(&mut self) -> Result<Option<Update>> {
-        let engine = (*self
-            .engine
-            .lock()
-            .block_error("ibus", "failed to acquire lock")?)
-        .clone();
-        let display_engine = if let Some(m) = &self.mappings {
-            match m.get(&engine) {
-                Some(mapping) => mapping.to_string(),
-                None => engine,
-            }
-        } else {
+/// Starts watching the engine as `setup` says.
+fn start(setup: &Setup, client: &mut dyn IBusClient) -> Result<Started> {
+    let engines = if setup.engine_filter.is_empty() || setup.backend == IBusBackend::Xkb {
+        None
+    } else {
+        Some(setup.engine_filter.apply(&engine_names(client)?)?)
+    };
+    let mut started = Started {
+        stale_after: setup.stale_after,
+        poll_interval: setup.poll_interval,
+        engines,
+        ..Started::default()
+    };
+    if setup.backend == IBusBackend::Auto {
+        started.xkb_fallback = Some((setup.send.clone(), setup.xkb_interval));
+    }
+    started.engine = match (setup.backend, setup.poll_interval) {
+        (IBusBackend::Xkb, _) => {
+            started.stale_after = None;
+            let (engine, watcher) = watch_xkb(&setup.id, setup.send.clone(), setup.xkb_interval)?;
+            started.xkb_watcher = Some(watcher);
             engine
-        };
+        }
+        // Every update asks IBus, so an engine shown is never stale.
+        (_, Some(_)) => {
+            started.stale_after = None;
+            Arc::new(Mutex::new(EngineState::Loading))
+        }
+        (_, None) => client.watch(&setup.id, setup.send.clone(), setup.stale_after),
+    };
+    Ok(started)
+}
 
-        let values = map!(
-            "{engine}" => display_engine
-        );
+/// The names of the engines installed.
+fn engine_names(client: &mut dyn IBusClient) -> Result<Vec<String>> {
+    Ok(client
+        .list_engines()?
+        .into_iter()
+        .map(|engine| engine.name)
+        .collect())
+}
 
-        self.text.set_text(self.format.render_static_str(&values)?);
-        Ok(None)
+/// The message of `e` without the name of the block.
+fn short_message(e: Error) -> String {
+    match e {
+        BlockError(_, message) => message,
+        e => e.to_string(),
     }
+}
 
-    // Returns the view of the block, comprised of widgets.
-    fn view(&self) -> Vec<&dyn I3BarWidget> {
-        vec![&self.text]
+/// How often the block polls IBus with `backend` and the configured
+/// `poll_interval`, `None` if it listens for signals or watches XKB instead.
+fn poll_interval(backend: IBusBackend, poll_interval: Duration) -> Option<Duration> {
+    Some(poll_interval)
+        .filter(|interval| backend != IBusBackend::Xkb && *interval > Duration::default())
+}
+
+/// State shared by every IBus block in the process, so that running several
+/// instances (e.g. one per bar) only needs a single D-Bus connection and
+/// listener thread.
+#[derive(Default)]
+struct SharedState {
+    /// The current engine, or `None` until the first block subscribed.
+    engine: Option<Arc<Mutex<EngineState>>>,
+    /// Block id -> channel used to ask that block to update.
+    subscribers: HashMap<String, Sender<Task>>,
+    history: EngineHistory,
+    /// When IBus last sent a signal or answered a query.
+    last_contact: Option<Instant>,
+    /// How often to ask IBus for the engine while it is quiet, to notice it
+    /// no longer answers. `None` if no block cares.
+    probe_interval: Option<Duration>,
+    preedit: Preedit,
+    /// Whether the listener thread is running.
+    listening: bool,
+}
+
+/// The text being composed with the engine, as IBus tells its panel.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Preedit {
+    /// In characters.
+    length: usize,
+    visible: bool,
+}
+
+impl Preedit {
+    /// Whether text is being composed.
+    fn active(&self) -> bool {
+        self.visible && self.length > 0
     }
 
-    // This function is called on every block for every click.
-    // TODO: Filter events by using the event.name property,
-    // and use to switch between input engines?
-    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
-        Ok(())
+    /// Applies `event`, and returns whether that started or stopped
+    /// composing.
+    fn handle(&mut self, event: PreeditEvent) -> bool {
+        let was_active = self.active();
+        match event {
+            PreeditEvent::Update { length, visible } => {
+                self.length = length;
+                self.visible = visible;
+            }
+            PreeditEvent::Show => self.visible = true,
+            PreeditEvent::Hide => self.visible = false,
+        }
+        self.active() != was_active
     }
 }
 
-fn parse_msg(ci: &ConnectionItem) -> Option<&str> {
-    let m = if let ConnectionItem::Signal(ref s) = *ci {
-        s
-    } else {
-        return None;
-    };
-    if &*m.interface().unwrap() != "org.freedesktop.IBus" {
-        return None;
-    };
-    if &*m.member().unwrap() != "GlobalEngineChanged" {
-        return None;
+/// The current and the previously active engine, to switch back to.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct EngineHistory {
+    current: Option<String>,
+    previous: Option<String>,
+}
+
+impl EngineHistory {
+    fn switch_to(&mut self, name: &str) {
+        if self.current.as_deref() != Some(name) {
+            self.previous = self.current.replace(name.to_string());
+        }
+    }
+}
+
+impl SharedState {
+    /// Tracks the preedit text, and has the blocks update if composing
+    /// started or stopped.
+    fn handle_preedit(&mut self, event: PreeditEvent) {
+        if self.preedit.handle(event) {
+            self.notify_all();
+        }
+    }
+
+    /// Asks every subscribed block to update, forgetting those whose channel
+    /// has gone away.
+    fn notify_all(&mut self) {
+        self.subscribers.retain(|id, send| {
+            send.send(Task {
+                id: id.clone(),
+                update_time: Instant::now(),
+            })
+            .is_ok()
+        });
+    }
+}
+
+lazy_static! {
+    static ref SHARED_STATE: Mutex<SharedState> = Mutex::new(SharedState::default());
+}
+
+// None of the code holding these locks can leave the state inconsistent, so a
+// panic elsewhere should not take every IBus block down with it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Registers the block `id` for engine change notifications and returns the
+/// shared engine state. The first block to subscribe starts connecting in the
+/// background; if that failed, the next block to subscribe tries again.
+/// Blocks marking the engine as stale after `stale_after` have IBus asked
+/// often enough to notice in time.
+fn subscribe(
+    id: &str,
+    send: Sender<Task>,
+    socket: Option<PathBuf>,
+    per_window: PerWindow,
+    timeout: Duration,
+    debounce: Duration,
+    stale_after: Option<Duration>,
+) -> Arc<Mutex<EngineState>> {
+    let mut shared = lock(&SHARED_STATE);
+    if let Some(stale_after) = stale_after {
+        let probe_interval = stale_after / 2;
+        shared.probe_interval = Some(
+            shared
+                .probe_interval
+                .map_or(probe_interval, |other| other.min(probe_interval)),
+        );
+    }
+    let engine = shared
+        .engine
+        .get_or_insert_with(|| Arc::new(Mutex::new(EngineState::Loading)))
+        .clone();
+    if !shared.listening {
+        let mut state = lock(&engine);
+        *state = EngineState::Loading;
+        let engine = engine.clone();
+        let spawned = thread::Builder::new()
+            .name("ibus".into())
+            .spawn(move || listen(engine, socket, per_window, timeout, debounce));
+        match spawned {
+            Ok(_) => shared.listening = true,
+            Err(_) => *state = EngineState::Error("Failed to spawn listener thread".to_string()),
+        }
+    }
+    shared.subscribers.insert(id.to_string(), send);
+    engine
+}
+
+fn unsubscribe(id: &str) {
+    lock(&SHARED_STATE).subscribers.remove(id);
+}
+
+fn set_engine_state(engine: &Mutex<EngineState>, state: EngineState) {
+    let name = match state {
+        EngineState::Ready(ref engine) => Some(engine.name.clone()),
+        _ => None,
     };
-    m.get1::<&str>()
+    *lock(engine) = state;
+    let mut shared = lock(&SHARED_STATE);
+    if let Some(name) = name {
+        shared.history.switch_to(&name);
+        shared.last_contact = Some(Instant::now());
+    }
+    // Tell blocks to update now.
+    shared.notify_all();
 }
 
-// Gets the address being used by the currently running ibus daemon.
-//
-// By default ibus will write the address to `$XDG_CONFIG_HOME/ibus/bus/aaa-bbb-ccc`
-// where aaa = dbus machine id, usually found at /etc/machine-id
-//       bbb = hostname - seems to be "unix" in most cases [see L99 of reference]
-//       ccc = display number from $DISPLAY
-// Refer to: https://github.com/ibus/ibus/blob/7cef5bf572596361bc502e8fa917569676a80372/src/ibusshare.c
-//
-// Example file contents:
-// ```
-// # This file is created by ibus-daemon, please do not modify it
-// IBUS_ADDRESS=unix:abstract=/tmp/dbus-8EeieDfT,guid=7542d73dce451c2461a044e24bc131f4
-// IBUS_DAEMON_PID=11140
-// ```
-fn get_ibus_address() -> Result<String> {
-    if let Ok(address) = env::var("IBUS_ADDRESS") {
-        return Ok(address);
+/// Asks IBus for the engine to see whether it still answers, and has the
+/// blocks update to show whether it did.
+fn probe(c: &Connection, engine: &Mutex<EngineState>, timeout: Duration) {
+    let current = query_engine(c, timeout);
+    if let Ok(current) = current {
+        if *lock(engine) != EngineState::Ready(current.clone()) {
+            set_engine_state(engine, EngineState::Ready(current));
+            return;
+        }
+        lock(&SHARED_STATE).last_contact = Some(Instant::now());
     }
+    lock(&SHARED_STATE).notify_all();
+}
 
-    let socket_dir = xdg_config_home().join("ibus/bus");
-    let socket_files: Vec<String> = read_dir(socket_dir.clone())
-        .block_error("ibus", &format!("Could not open '{:?}'.", socket_dir))?
-        .filter(|entry| entry.is_ok())
-        // The path will be valid unicode, so this is safe to unwrap.
-        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
-        .collect();
+/// How long ago IBus was last heard from, as of `now`.
+fn since_last_contact(now: Instant) -> Option<Duration> {
+    lock(&SHARED_STATE)
+        .last_contact
+        .map(|contact| now.saturating_duration_since(contact))
+}
 
-    if socket_files.is_empty() {
-        return Err(BlockError(
-            "ibus".to_string(),
-            "Could not locate an IBus socket file.".to_string(),
-        ));
+/// The engine that was active before the current one, if any.
+fn previous_engine() -> Option<String> {
+    lock(&SHARED_STATE).history.previous.clone()
+}
+
+/// Connects to IBus, queries the current engine and then listens for changes,
+/// or for windows gaining the focus if IBus keeps an engine per window. Runs
+/// on a background thread, as a hung IBus daemon would otherwise block the
+/// whole bar.
+fn listen(
+    engine: Arc<Mutex<EngineState>>,
+    socket: Option<PathBuf>,
+    per_window: PerWindow,
+    timeout: Duration,
+    debounce: Duration,
+) {
+    let mut retry = RECONNECT_DELAY;
+    loop {
+        // Looking for the address again, as a restarted daemon has a new one.
+        match connect(socket.as_deref(), per_window, timeout) {
+            Ok((c, current_engine)) => {
+                retry = RECONNECT_DELAY;
+                let follow_focus = current_engine.is_none();
+                set_engine_state(
+                    &engine,
+                    current_engine.map_or(EngineState::NoFocus, EngineState::Ready),
+                );
+                handle_messages(&c, &engine, follow_focus, timeout, debounce);
+                let message = "Lost the connection to IBus".to_string();
+                log_block_error("ibus", "ibus", &message);
+                set_engine_state(&engine, EngineState::Error(message));
+            }
+            Err(e) => {
+                let message = short_message(e);
+                log_block_error("ibus", "ibus", &message);
+                set_engine_state(&engine, EngineState::Error(message));
+            }
+        }
+        thread::sleep(retry);
+        retry = (retry * 2).min(MAX_RECONNECT_DELAY);
+        // Blocks falling back on XKB no longer need IBus.
+        let mut shared = lock(&SHARED_STATE);
+        if shared.subscribers.is_empty() {
+            shared.listening = false;
+            return;
+        }
     }
+}
 
-    // Only check $DISPLAY if we need to.
-    let socket_path = if socket_files.len() == 1 {
-        socket_dir.join(&socket_files[0])
-    } else {
-        let w_display_var = env::var("WAYLAND_DISPLAY");
-        let x_display_var = env::var("DISPLAY");
-
-        let display_suffix = if let Ok(x) = w_display_var {
-            x
-        } else if let Ok(x) = x_display_var {
-            let re = Regex::new(r"^:([0-9]{1})$").unwrap(); // Valid regex is safe to unwrap.
-            let cap = re
-                .captures(&x)
-                .block_error("ibus", "Failed to extract display number from $DISPLAY")?;
-            cap[1].to_string()
-        } else {
-            return Err(BlockError(
-                "ibus".to_string(),
-                "Could not read DISPLAY or WAYLAND_DISPLAY.".to_string(),
-            ));
+/// Keeps `engine` up to date with the messages IBus sends over `c`, until the
+/// connection is lost.
+fn handle_messages(
+    c: &Connection,
+    engine: &Mutex<EngineState>,
+    follow_focus: bool,
+    timeout: Duration,
+    debounce: Duration,
+) {
+    // The engine changed to while changes were held back.
+    let mut pending: Option<String> = None;
+    let mut last_sent: Option<Instant> = None;
+    // The input context of the focused window.
+    let mut focused: Option<String> = None;
+    loop {
+        if last_sent.is_some_and(|sent| sent.elapsed() >= debounce) {
+            if let Some(engine_name) = pending.take() {
+                if shown_engine(engine).as_ref() != Some(&engine_name) {
+                    show_engine(c, engine, engine_name, timeout);
+                    last_sent = Some(Instant::now());
+                }
+            }
+        }
+        let probe_interval = lock(&SHARED_STATE).probe_interval;
+        let wait = match (&pending, last_sent, probe_interval) {
+            (Some(_), Some(sent), _) => {
+                debounce.saturating_sub(sent.elapsed()).as_millis().max(1) as i32
+            }
+            (_, _, Some(interval)) => interval.as_millis().max(1) as i32,
+            _ => 100_000,
         };
+        let ci = match c.iter(wait).next() {
+            Some(ci) => ci,
+            None => continue,
+        };
+        if !c.is_connected() {
+            return;
+        }
+        // A single bad message must never stop updates for good.
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+            if let Some(event) = parse_preedit_msg(&ci) {
+                lock(&SHARED_STATE).handle_preedit(event);
+            } else if let Some(focus) = parse_focus_msg(&ci) {
+                match focus {
+                    Focus::In(context) => focused = Some(context),
+                    Focus::Out(context) => {
+                        if focused.as_ref() == Some(&context) {
+                            focused = None;
+                        }
+                    }
+                }
+                show_focused_engine(c, engine, focused.as_deref(), timeout);
+            } else if let (Some(_), true) = (parse_msg(&ci), follow_focus) {
+                // The focused window may have switched engines.
+                show_focused_engine(c, engine, focused.as_deref(), timeout);
+            } else if let Some(engine_name) = parse_msg(&ci) {
+                match signal_action(
+                    shown_engine(engine).as_deref(),
+                    engine_name,
+                    last_sent,
+                    Instant::now(),
+                    debounce,
+                ) {
+                    SignalAction::Ignore => pending = None,
+                    SignalAction::Hold => pending = Some(engine_name.to_string()),
+                    SignalAction::Show => {
+                        pending = None;
+                        show_engine(c, engine, engine_name.to_string(), timeout);
+                        last_sent = Some(Instant::now());
+                    }
+                }
+            } else if let (ConnectionItem::Nothing, None, Some(_)) = (&ci, &pending, probe_interval)
+            {
+                if follow_focus {
+                    show_focused_engine(c, engine, focused.as_deref(), timeout);
+                } else {
+                    probe(c, engine, timeout);
+                }
+            }
+        }));
+        if handled.is_err() {
+            log_block_error("ibus", "ibus", "Failed to handle D-Bus message");
+        }
+    }
+}
 
-        let candidate = socket_files
-            .iter()
-            .filter(|fname| fname.ends_with(&display_suffix))
-            .take(1)
-            .next()
-            .block_error(
-                "ibus",
-                &"Could not find an IBus socket file matching $DISPLAY.".to_string(),
-            )?;
-        socket_dir.join(candidate)
+/// What to do about a signal that the engine changed to `incoming`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SignalAction {
+    /// It is the engine shown already.
+    Ignore,
+    /// Show it now.
+    Show,
+    /// Hold it back until `debounce` after the last change shown, and show
+    /// it then unless another signal came in.
+    Hold,
+}
+
+/// What to do about a signal that the engine changed to `incoming` at `now`,
+/// if `previous` is shown and the last change was shown at `last_sent`.
+fn signal_action(
+    previous: Option<&str>,
+    incoming: &str,
+    last_sent: Option<Instant>,
+    now: Instant,
+    debounce: Duration,
+) -> SignalAction {
+    if previous == Some(incoming) {
+        return SignalAction::Ignore;
+    }
+    match last_sent {
+        Some(sent) if now.saturating_duration_since(sent) < debounce => SignalAction::Hold,
+        _ => SignalAction::Show,
+    }
+}
+
+/// The name of the engine shown, if any.
+fn shown_engine(engine: &Mutex<EngineState>) -> Option<String> {
+    match &*lock(engine) {
+        EngineState::Ready(engine) => Some(engine.name.clone()),
+        _ => None,
+    }
+}
+
+/// Shows `engine_name`, asking IBus for the rest of the engine, as the signal
+/// only carries the name.
+fn show_engine(
+    c: &Connection,
+    engine: &Mutex<EngineState>,
+    engine_name: String,
+    timeout: Duration,
+) {
+    let current = match query_engine(c, timeout) {
+        Ok(current) if current.name == engine_name => current,
+        _ => Engine {
+            name: engine_name,
+            ..Engine::default()
+        },
     };
+    set_engine_state(engine, EngineState::Ready(current));
+}
 
-    let re = Regex::new(r"ADDRESS=(.*),guid").unwrap(); // Valid regex is safe to unwrap.
-    let mut address = String::new();
-    File::open(&socket_path)
-        .block_error("ibus", &format!("Could not open '{:?}'.", socket_path))?
-        .read_to_string(&mut address)
-        .block_error(
-            "ibus",
-            &format!("Error reading contents of '{:?}'.", socket_path),
-        )?;
-    let cap = re.captures(&address).block_error(
-        "ibus",
-        &format!("Failed to extract address out of '{}'.", address),
-    )?;
+/// Queries the current engine.
+fn query_engine(c: &Connection, timeout: Duration) -> Result<Engine> {
+    let p = c.with_path(
+        "org.freedesktop.IBus",
+        "/org/freedesktop/IBus",
+        timeout.as_millis() as i32,
+    );
+    let info: arg::Variant<Box<dyn arg::RefArg>> = p
+        .get("org.freedesktop.IBus", "GlobalEngine")
+        .block_error("ibus", "Failed to query IBus")?;
+    parse_engine_desc(&info)
+}
+
+/// What the block asks of IBus, so that it can run against a fake IBus in
+/// tests.
+trait IBusClient {
+    fn current_engine(&mut self) -> Result<Engine>;
+
+    /// Makes `name` the current engine.
+    fn set_engine(&mut self, name: &str) -> Result<()>;
+
+    /// All installed engines.
+    fn list_engines(&mut self) -> Result<Vec<Engine>>;
+
+    /// Starts keeping the returned engine up to date, and asking the block
+    /// `id` over `send` to update when it changed.
+    fn watch(
+        &mut self,
+        id: &str,
+        send: Sender<Task>,
+        stale_after: Option<Duration>,
+    ) -> Arc<Mutex<EngineState>>;
+
+    /// Stops asking the block `id` to update.
+    fn unwatch(&mut self, id: &str);
+}
+
+/// IBus over D-Bus.
+struct DBusClient {
+    socket: Option<PathBuf>,
+    per_window: PerWindow,
+    timeout: Duration,
+    signal_debounce: Duration,
+    /// The connection for the calls of the block, kept between them. `None`
+    /// until the first call or after one failed.
+    connection: Option<Connection>,
+}
+
+impl DBusClient {
+    /// Runs `call` over the kept connection, connecting first if there is
+    /// none. Drops the connection if the call fails, so that the next one
+    /// reconnects.
+    fn call<T>(&mut self, call: impl Fn(&Connection, Duration) -> Result<T>) -> Result<T> {
+        let c = match self.connection.take() {
+            Some(c) => c,
+            None => open_connection(self.socket.as_deref())?,
+        };
+        let result = call(&c, self.timeout);
+        if result.is_ok() {
+            self.connection = Some(c);
+        }
+        result
+    }
+}
+
+impl IBusClient for DBusClient {
+    fn current_engine(&mut self) -> Result<Engine> {
+        // A kept connection fails if IBus restarted, so try a new one then.
+        let kept = self.connection.is_some();
+        match self.call(query_engine) {
+            Err(_) if kept => self.call(query_engine),
+            result => result,
+        }
+    }
 
-    Ok(cap[1].to_string())
+    fn set_engine(&mut self, name: &str) -> Result<()> {
+        self.call(|c, timeout| set_global_engine(c, name, timeout))
+    }
+
+    fn list_engines(&mut self) -> Result<Vec<Engine>> {
+        self.call(list_engines)
+    }
+
+    fn watch(
+        &mut self,
+        id: &str,
+        send: Sender<Task>,
+        stale_after: Option<Duration>,
+    ) -> Arc<Mutex<EngineState>> {
+        subscribe(
+            id,
+            send,
+            self.socket.clone(),
+            self.per_window,
+            self.timeout,
+            self.signal_debounce,
+            stale_after,
+        )
+    }
+
+    fn unwatch(&mut self, id: &str) {
+        unsubscribe(id);
+    }
+}
+
+fn open_connection(socket: Option<&Path>) -> Result<Connection> {
+    let ibus_address = get_ibus_address(socket)?;
+    Connection::open_private(&ibus_address).block_error(
+        "ibus",
+        &format!("Failed to establish D-Bus connection to {}", ibus_address),
+    )
+}
+
+/// Opens a connection to IBus that receives engine changes, and queries the
+/// current engine. There is none if IBus keeps an engine per window as
+/// `per_window` says, and the connection receives focus changes instead.
+fn connect(
+    socket: Option<&Path>,
+    per_window: PerWindow,
+    timeout: Duration,
+) -> Result<(Connection, Option<Engine>)> {
+    let c = open_connection(socket)?;
+    let current_engine = match per_window {
+        PerWindow::Off => Some(query_engine(&c, timeout)?),
+        PerWindow::Force => None,
+        PerWindow::Auto => match use_global_engine(&c, timeout) {
+            Some(false) => None,
+            Some(true) => Some(query_engine(&c, timeout)?),
+            // Without its config service, IBus only has a global engine if it
+            // shares one.
+            None => query_engine(&c, timeout).ok(),
+        },
+    };
+
+    c.add_match("interface='org.freedesktop.IBus',member='GlobalEngineChanged'")
+        .block_error(
+            "ibus",
+            "Failed to add D-Bus message rule - has IBus interface changed?",
+        )?;
+    if current_engine.is_none() {
+        // IBus tells its panel about focus changes, listen in.
+        for member in &["FocusIn", "FocusOut"] {
+            c.add_match(&panel_rule(member)).block_error(
+                "ibus",
+                "Failed to add D-Bus message rule - has IBus interface changed?",
+            )?;
+        }
+    }
+    // Only needed for `{preedit}`, so no reason to fail if IBus does not let
+    // us listen in.
+    for member in &["UpdatePreeditText", "ShowPreeditText", "HidePreeditText"] {
+        c.add_match(&panel_rule(member)).ok();
+    }
+    Ok((c, current_engine))
+}
+
+/// A match rule for the calls of IBus to `member` of its panel.
+fn panel_rule(member: &str) -> String {
+    format!(
+        "type='method_call',interface='org.freedesktop.IBus.Panel',member='{}',eavesdrop='true'",
+        member
+    )
+}
+
+/// Whether IBus is set up to share one engine among all windows, `None` if its
+/// config service does not say.
+fn use_global_engine(c: &Connection, timeout: Duration) -> Option<bool> {
+    let msg = Message::new_method_call(
+        "org.freedesktop.IBus.Config",
+        "/org/freedesktop/IBus/Config",
+        "org.freedesktop.IBus.Config",
+        "GetValue",
+    )
+    .ok()?
+    .append2("general", "use_global_engine");
+    let value: arg::Variant<bool> = c
+        .send_with_reply_and_block(msg, timeout.as_millis() as i32)
+        .ok()?
+        .read1()
+        .ok()?;
+    Some(value.0)
+}
+
+/// Queries the engine of the input context at `context`.
+fn query_context_engine(c: &Connection, context: &str, timeout: Duration) -> Result<Engine> {
+    let msg = Message::new_method_call(
+        "org.freedesktop.IBus",
+        context,
+        "org.freedesktop.IBus.InputContext",
+        "GetEngine",
+    )
+    .block_error("ibus", "Failed to create D-Bus message")?;
+    let info: arg::Variant<Box<dyn arg::RefArg>> = c
+        .send_with_reply_and_block(msg, timeout.as_millis() as i32)
+        .block_error("ibus", "Failed to query the engine of the focused window")?
+        .read1()
+        .block_error("ibus", "Failed to read the engine of the focused window")?;
+    parse_engine_desc(&info)
+}
+
+/// Shows the engine of the input context at `focused`, and has the blocks
+/// update.
+fn show_focused_engine(
+    c: &Connection,
+    engine: &Mutex<EngineState>,
+    focused: Option<&str>,
+    timeout: Duration,
+) {
+    let current = match focused.map(|context| query_context_engine(c, context, timeout)) {
+        Some(Ok(current)) => EngineState::Ready(current),
+        // The window has no engine, e.g. as it does not take text.
+        _ => EngineState::NoFocus,
+    };
+    if *lock(engine) != current {
+        set_engine_state(engine, current);
+        return;
+    }
+    if let EngineState::Ready(_) = current {
+        lock(&SHARED_STATE).last_contact = Some(Instant::now());
+    }
+    lock(&SHARED_STATE).notify_all();
+}
+
+/// All installed engines.
+fn list_engines(c: &Connection, timeout: Duration) -> Result<Vec<Engine>> {
+    let msg = Message::new_method_call(
+        "org.freedesktop.IBus",
+        "/org/freedesktop/IBus",
+        "org.freedesktop.IBus",
+        "ListEngines",
+    )
+    .block_error("ibus", "Failed to create D-Bus message")?;
+    let engines: Vec<arg::Variant<Box<dyn arg::RefArg>>> = c
+        .send_with_reply_and_block(msg, timeout.as_millis() as i32)
+        .block_error("ibus", "Failed to list IBus engines")?
+        .read1()
+        .block_error("ibus", "Failed to read IBus engines")?;
+    engines.iter().map(parse_engine_desc).collect()
+}
+
+/// Makes `name` the current engine.
+fn set_global_engine(c: &Connection, name: &str, timeout: Duration) -> Result<()> {
+    let msg = Message::new_method_call(
+        "org.freedesktop.IBus",
+        "/org/freedesktop/IBus",
+        "org.freedesktop.IBus",
+        "SetGlobalEngine",
+    )
+    .block_error("ibus", "Failed to create D-Bus message")?
+    .append1(name);
+    c.send_with_reply_and_block(msg, timeout.as_millis() as i32)
+        .map_err(|e| {
+            BlockError(
+                "ibus".to_string(),
+                format!("Failed to switch to engine {}: {}", name, e),
+            )
+        })?;
+    Ok(())
+}
+
+impl IBus {
+    fn map_engine(&self, engine: String) -> String {
+        if let Some(m) = &self.mappings {
+            match m.get(&engine) {
+                Some(mapping) => mapping.to_string(),
+                None => engine,
+            }
+        } else {
+            engine
+        }
+    }
+
+    /// Lists the installed engines again, for when one of them went away.
+    fn refresh_engines(&mut self) -> Result<()> {
+        if self.engines.is_some() {
+            self.engines = Some(
+                self.setup
+                    .engine_filter
+                    .apply(&engine_names(&mut *self.client)?)?,
+            );
+        }
+        Ok(())
+    }
+
+    /// The engine `step` engines after the current one.
+    fn cycle(&mut self, step: isize) -> Result<Option<String>> {
+        let engines = match &self.engines {
+            Some(engines) => engines.clone(),
+            None => engine_names(&mut *self.client)?,
+        };
+        let current = match &*lock(&self.engine) {
+            EngineState::Ready(engine) => Some(engine.name.clone()),
+            _ => None,
+        };
+        Ok(cycle(&engines, current.as_deref(), step).map(String::from))
+    }
+
+    /// Shows a warning for a little while. The update showing it schedules
+    /// the one removing it again, see `warning_left`.
+    fn warn(&mut self) {
+        self.warning_until = Some((self.clock)() + WARNING_DURATION);
+        self.send
+            .send(Task {
+                id: self.id.clone(),
+                update_time: Instant::now(),
+            })
+            .ok();
+    }
+
+    /// How much longer the warning is shown, if it is.
+    fn warning_left(&self) -> Option<Duration> {
+        let now = (self.clock)();
+        self.warning_until
+            .filter(|until| now < *until)
+            .map(|until| until - now)
+    }
+
+    /// Tries to start the block again after it failed to, then updates it.
+    fn restart(&mut self) -> Result<()> {
+        match start(&self.setup, &mut *self.client) {
+            Ok(started) => {
+                self.engine = started.engine;
+                self.stale_after = started.stale_after;
+                self.poll_interval = started.poll_interval;
+                self.xkb_fallback = started.xkb_fallback;
+                self.xkb_watcher = started.xkb_watcher;
+                self.engines = started.engines;
+                self.start_error = None;
+            }
+            Err(e) => log_block_error(&self.id, "ibus", &format!("{:?}", e)),
+        }
+        self.update()?;
+        Ok(())
+    }
+
+    /// Shows the engine in `self.engine`.
+    fn show(&mut self) -> Result<()> {
+        let state = lock(&self.engine).clone();
+        let Engine {
+            name: engine,
+            language,
+            symbol,
+        } = match state {
+            EngineState::Ready(engine) => engine,
+            EngineState::Loading => {
+                self.text.set_text(self.loading_text.clone());
+                self.text.set_state(State::Idle);
+                return Ok(());
+            }
+            EngineState::NoFocus => {
+                self.text.set_text(self.no_focus_text.clone());
+                self.text.set_state(State::Idle);
+                return Ok(());
+            }
+            EngineState::Error(message) => {
+                if let Some((send, interval)) = self.xkb_fallback.take() {
+                    self.client.unwatch(&self.id);
+                    self.stale_after = None;
+                    let (engine, watcher) = watch_xkb(&self.id, send, interval)?;
+                    self.engine = engine;
+                    self.xkb_watcher = Some(watcher);
+                    self.poll_interval = None;
+                    return self.show();
+                }
+                self.text.set_text(message);
+                self.text.set_state(State::Critical);
+                return Ok(());
+            }
+        };
+        let now = (self.clock)();
+        let warning = self.warning_until.is_some_and(|until| now < until);
+        let stale = match (self.stale_after, since_last_contact(now)) {
+            (Some(stale_after), Some(silence)) => silence > stale_after,
+            _ => false,
+        };
+        let composing = lock(&SHARED_STATE).preedit.active();
+        self.text.set_state(if warning || stale {
+            State::Warning
+        } else if composing {
+            State::Info
+        } else {
+            State::Idle
+        });
+        let xkb = XkbEngine::parse(&engine);
+        let layout = xkb.layout.to_string();
+        let lang = xkb.lang.to_string();
+        let variant = if xkb.variant.is_empty() {
+            String::new()
+        } else {
+            self.variant_format
+                .render_static_str(&map!("{variant}" => xkb.variant))?
+        };
+        let icon = self
+            .mappings
+            .as_ref()
+            .and_then(|mappings| mappings.get(&engine))
+            .cloned()
+            .unwrap_or_else(|| abbreviation(&engine, &language));
+        let display_engine = self.map_engine(engine);
+        let prev_engine = previous_engine()
+            .map(|engine| self.map_engine(engine))
+            .unwrap_or_default();
+
+        let values = map!(
+            "{engine}" => display_engine,
+            "{prev_engine}" => prev_engine,
+            "{layout}" => layout,
+            "{variant}" => variant,
+            "{lang}" => lang,
+            "{preedit}" => if composing { self.preedit_marker.clone() } else { String::new() },
+            "{language}" => display_language(&language, self.display, &self.lang_overrides)
+        );
+
+        // Engines without a symbol fall back on the format.
+        let mut text = if self.use_symbol && !symbol.trim().is_empty() {
+            symbol
+        } else if self.as_icon {
+            icon
+        } else {
+            self.format.render_static_str(&values)?
+        };
+        if stale {
+            text.push_str(&self.stale_marker);
+        }
+        self.text.set_text(text);
+        Ok(())
+    }
+}
+
+impl Block for IBus {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    // Updates the internal state of the block.
+    fn update(&mut self) -> Result<Option<Update>> {
+        if let Some(error) = &self.start_error {
+            let text = self
+                .error_format
+                .render_static_str(&map!("{error}" => error.clone()))?;
+            self.text.set_text(text);
+            self.text.set_state(State::Critical);
+            return Ok(None);
+        }
+        if self.poll_interval.is_some() {
+            let state = match self.client.current_engine() {
+                Ok(engine) => {
+                    lock(&SHARED_STATE).history.switch_to(&engine.name);
+                    EngineState::Ready(engine)
+                }
+                Err(e) => EngineState::Error(short_message(e)),
+            };
+            *lock(&self.engine) = state;
+        }
+        self.show()?;
+        // Polling stops if the block fell back on XKB, which updates it by
+        // itself. A warning is removed again once it is over.
+        let next = match (self.poll_interval, self.warning_left()) {
+            (Some(interval), Some(left)) => Some(interval.min(left)),
+            (interval, left) => interval.or(left),
+        };
+        Ok(next.map(Into::into))
+    }
+
+    // Returns the view of the block, comprised of widgets.
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.text]
+    }
+
+    // This function is called on every block for every click.
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if !event.matches_name(&self.id) {
+            return Ok(());
+        }
+        // Any click tries to start the block again.
+        if self.start_error.is_some() {
+            return self.restart();
+        }
+        // Unless bound, middle click switches back to the previous engine, and
+        // left click and scrolling cycle through the engines.
+        let cycles = self.setup.backend != IBusBackend::Xkb;
+        let action = match self.click.get(event).cloned() {
+            Some(ClickAction::Block(action)) => Some(action),
+            Some(ClickAction::Command { cmd }) => {
+                spawn_child_async("sh", &["-c", &cmd])
+                    .block_error("ibus", "could not spawn child")?;
+                return Ok(());
+            }
+            None => match event.button {
+                MouseButton::Middle => previous_engine().map(EngineAction::Switch),
+                MouseButton::Left | MouseButton::WheelUp if cycles => Some(EngineAction::Cycle(1)),
+                MouseButton::WheelDown if cycles => Some(EngineAction::Cycle(-1)),
+                _ => None,
+            },
+        };
+        let engine = match action {
+            Some(EngineAction::Switch(engine)) => Ok(Some(engine)),
+            Some(EngineAction::Cycle(step)) => self.cycle(step),
+            None => Ok(None),
+        };
+        let engine = match engine {
+            Ok(engine) => engine,
+            Err(e) => {
+                log_block_error(&self.id, "ibus", &short_message(e));
+                self.warn();
+                None
+            }
+        };
+        if let Some(engine) = engine {
+            if let Err(e) = self.client.set_engine(&engine) {
+                log_block_error(&self.id, "ibus", &short_message(e));
+                self.warn();
+                // The engine may no longer be installed.
+                if let Err(e) = self.refresh_engines() {
+                    log_block_error(&self.id, "ibus", &format!("{:?}", e));
+                }
+            }
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+}
+
+/// Polls the XKB layout in a background thread. The layout is stored as an
+/// xkb engine name (e.g. `xkb:us::`), so that it is displayed just like an
+/// engine reported by IBus.
+fn watch_xkb(
+    id: &str,
+    send: Sender<Task>,
+    interval: Duration,
+) -> Result<(Arc<Mutex<EngineState>>, Stop)> {
+    let monitor = SetXkbMap::new()?;
+    let as_engine = |layout: String| {
+        EngineState::Ready(Engine {
+            name: format!("xkb:{}::", layout),
+            ..Engine::default()
+        })
+    };
+    let engine = Arc::new(Mutex::new(as_engine(monitor.keyboard_layout()?)));
+    let engine_copy = engine.clone();
+    let id = id.to_string();
+    let (stop, stopped) = stop::pair()?;
+    thread::Builder::new()
+        .name("ibus_xkb".into())
+        .spawn(move || {
+            while stopped.sleep(interval) {
+                // Errors are transient (e.g. no X server during a restart), keep
+                // showing the last layout.
+                if let Ok(layout) = monitor.keyboard_layout() {
+                    let layout = as_engine(layout);
+                    let mut current = lock(&engine_copy);
+                    if *current != layout {
+                        *current = layout;
+                        drop(current);
+                        if send
+                            .send(Task {
+                                id: id.clone(),
+                                update_time: Instant::now(),
+                            })
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+        .block_error("ibus", "Failed to spawn xkb thread")?;
+    Ok((engine, stop))
+}
+
+/// The components of an xkb engine name such as `xkb:us:intl:eng`. All of
+/// them are empty for engines that are not xkb engines.
+#[derive(Debug, Default, PartialEq)]
+struct XkbEngine<'a> {
+    layout: &'a str,
+    variant: &'a str,
+    lang: &'a str,
+}
+
+impl<'a> XkbEngine<'a> {
+    fn parse(engine: &'a str) -> Self {
+        let mut parts = engine.split(':');
+        if parts.next() != Some("xkb") {
+            return XkbEngine::default();
+        }
+        XkbEngine {
+            layout: parts.next().unwrap_or_default(),
+            variant: parts.next().unwrap_or_default(),
+            lang: parts.next().unwrap_or_default(),
+        }
+    }
+}
+
+impl Drop for IBus {
+    fn drop(&mut self) {
+        self.client.unwatch(&self.id);
+    }
+}
+
+// `info` should contain something containing an array with the contents as such:
+// [name, longname, description, language, license, author, icon, layout, rank, hotkeys, symbol, setup, layout_variant, layout_option, version, textdomain, icon_prop_key]
+// Refer to: https://github.com/ibus/ibus/blob/7cef5bf572596361bc502e8fa917569676a80372/src/ibusenginedesc.c
+// e.g.                   name           longname        description     language
+// ["IBusEngineDesc", {}, "xkb:us::eng", "English (US)", "English (US)", "en", "GPL", "Peng Huang <shawn.p.huang@gmail.com>", "ibus-keyboard", "us", 99, "", "", "", "", "", "", "", ""]
+//                         ↑ We will use this element (name) as it is what GlobalEngineChanged signal returns.
+// The language and the symbol (e.g. "あ" for mozc) are used as well.
+fn parse_engine_desc(info: &arg::Variant<Box<dyn arg::RefArg>>) -> Result<Engine> {
+    let mut fields = info
+        .0
+        .as_iter()
+        .block_error("ibus", "Failed to parse D-Bus message (step 1)")?
+        .skip(2);
+    let name = fields
+        .next()
+        .block_error("ibus", "Failed to parse D-Bus message (step 2)")?
+        .as_str()
+        .block_error("ibus", "Failed to parse D-Bus message (step 3)")?
+        .to_string();
+    // The language and symbol are nice to have, but not worth failing over.
+    let language = fields
+        .nth(2)
+        .and_then(|language| language.as_str().map(|s| s.to_string()))
+        .unwrap_or_default();
+    let symbol = fields
+        .nth(6)
+        .and_then(|symbol| symbol.as_str().map(|s| s.to_string()))
+        .unwrap_or_default();
+    Ok(Engine {
+        name,
+        language,
+        symbol,
+    })
+}
+
+/// English names of languages, by ISO 639-1 code.
+const LANGUAGE_NAMES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("bg", "Bulgarian"),
+    ("bn", "Bengali"),
+    ("cs", "Czech"),
+    ("da", "Danish"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("eo", "Esperanto"),
+    ("es", "Spanish"),
+    ("et", "Estonian"),
+    ("fa", "Persian"),
+    ("fi", "Finnish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("hr", "Croatian"),
+    ("hu", "Hungarian"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ka", "Georgian"),
+    ("kk", "Kazakh"),
+    ("km", "Khmer"),
+    ("ko", "Korean"),
+    ("lo", "Lao"),
+    ("lt", "Lithuanian"),
+    ("lv", "Latvian"),
+    ("mn", "Mongolian"),
+    ("my", "Burmese"),
+    ("nb", "Norwegian Bokmål"),
+    ("nl", "Dutch"),
+    ("no", "Norwegian"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ro", "Romanian"),
+    ("ru", "Russian"),
+    ("sk", "Slovak"),
+    ("sl", "Slovenian"),
+    ("sr", "Serbian"),
+    ("sv", "Swedish"),
+    ("ta", "Tamil"),
+    ("th", "Thai"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
+/// Splits a code such as `zh_CN`, `pt-BR` or `ja` into language and region.
+fn split_language_code(code: &str) -> (&str, Option<&str>) {
+    // Drop encodings and modifiers, as in `sr_RS@latin` or `en_US.UTF-8`.
+    let code = code.split(['.', '@']).next().unwrap_or_default();
+    let mut parts = code.splitn(2, ['_', '-']);
+    let language = parts.next().unwrap_or_default();
+    (language, parts.next())
+}
+
+/// A short abbreviation of the engine `name` with `language`: the layout of
+/// xkb engines, the language code else, or the start of the name if IBus does
+/// not know the language.
+fn abbreviation(name: &str, language: &str) -> String {
+    let layout = XkbEngine::parse(name).layout;
+    if !layout.is_empty() {
+        return layout.to_string();
+    }
+    let (language, _) = split_language_code(language);
+    if !language.is_empty() && language != "other" {
+        return language.to_string();
+    }
+    name.chars()
+        .take_while(|c| c.is_alphanumeric())
+        .take(2)
+        .collect()
+}
+
+/// The flag emoji of a two letter country code, made of regional indicator
+/// symbols.
+fn flag(country: &str) -> Option<String> {
+    if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    country
+        .to_ascii_uppercase()
+        .chars()
+        .map(|c| std::char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+        .collect()
+}
+
+/// Renders a language code for display. Never fails: flags fall back on the
+/// name, and names on the code itself.
+fn display_language(
+    code: &str,
+    display: LanguageDisplay,
+    overrides: &BTreeMap<String, String>,
+) -> String {
+    let (language, region) = split_language_code(code);
+    let name = || {
+        LANGUAGE_NAMES
+            .iter()
+            .find(|(c, _)| c.eq_ignore_ascii_case(language))
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| code.to_string())
+    };
+    match display {
+        LanguageDisplay::Code => code.to_string(),
+        LanguageDisplay::Name => name(),
+        // An override for the exact code beats the region of the code, which
+        // beats an override for the language.
+        LanguageDisplay::Flag => overrides
+            .get(code)
+            .map(|country| country.as_str())
+            .or(region)
+            .or_else(|| overrides.get(language).map(|country| country.as_str()))
+            .and_then(flag)
+            .unwrap_or_else(name),
+    }
+}
+
+/// The new engine name if `ci` is a GlobalEngineChanged signal. Anything else,
+/// including signals missing their optional interface or member, is ignored.
+fn parse_msg(ci: &ConnectionItem) -> Option<&str> {
+    let m = match *ci {
+        ConnectionItem::Signal(ref s) => s,
+        _ => return None,
+    };
+    match m.interface() {
+        Some(ref interface) if &**interface == "org.freedesktop.IBus" => {}
+        _ => return None,
+    }
+    match m.member() {
+        Some(ref member) if &**member == "GlobalEngineChanged" => {}
+        _ => return None,
+    }
+    m.get1::<&str>()
+}
+
+/// A window gaining or losing the focus, as IBus tells its panel.
+#[derive(Debug, Clone, PartialEq)]
+enum Focus {
+    /// The path of the input context of the window.
+    In(String),
+    Out(String),
+}
+
+fn parse_focus_msg(ci: &ConnectionItem) -> Option<Focus> {
+    let m = match *ci {
+        ConnectionItem::MethodCall(ref m) => m,
+        _ => return None,
+    };
+    match m.interface() {
+        Some(ref interface) if &**interface == "org.freedesktop.IBus.Panel" => {}
+        _ => return None,
+    }
+    let context = m.get1::<dbus::Path>()?.to_string();
+    match m.member() {
+        Some(ref member) if &**member == "FocusIn" => Some(Focus::In(context)),
+        Some(ref member) if &**member == "FocusOut" => Some(Focus::Out(context)),
+        _ => None,
+    }
+}
+
+/// A change of the text being composed, as IBus tells its panel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PreeditEvent {
+    Update { length: usize, visible: bool },
+    Show,
+    Hide,
+}
+
+// UpdatePreeditText carries the text, the cursor position and whether the text
+// is visible. The text is an IBusText, e.g.
+// ["IBusText", {}, "にほん", ["IBusAttrList", {}, []]]
+//                  ↑ the composed text
+fn parse_preedit_msg(ci: &ConnectionItem) -> Option<PreeditEvent> {
+    let m = match *ci {
+        ConnectionItem::MethodCall(ref m) => m,
+        _ => return None,
+    };
+    match m.interface() {
+        Some(ref interface) if &**interface == "org.freedesktop.IBus.Panel" => {}
+        _ => return None,
+    }
+    match m.member() {
+        Some(ref member) if &**member == "UpdatePreeditText" => {
+            let (text, _cursor, visible) =
+                m.get3::<arg::Variant<Box<dyn arg::RefArg>>, u32, bool>();
+            let length = text?.0.as_iter()?.nth(2)?.as_str()?.chars().count();
+            Some(PreeditEvent::Update {
+                length,
+                visible: visible?,
+            })
+        }
+        Some(ref member) if &**member == "ShowPreeditText" => Some(PreeditEvent::Show),
+        Some(ref member) if &**member == "HidePreeditText" => Some(PreeditEvent::Hide),
+        _ => None,
+    }
+}
+
+// Gets the address being used by the currently running ibus daemon: the one in
+// the `socket` file if configured, else `$IBUS_ADDRESS`, else the one in the
+// socket file of this machine and display, else in the newest socket file.
+//
+// By default ibus will write the address to `$XDG_CONFIG_HOME/ibus/bus/aaa-bbb-ccc`
+// where aaa = dbus machine id, usually found at /etc/machine-id
+//       bbb = hostname - seems to be "unix" in most cases [see L99 of reference]
+//       ccc = display number from $DISPLAY
+// Refer to: https://github.com/ibus/ibus/blob/7cef5bf572596361bc502e8fa917569676a80372/src/ibusshare.c
+//
+// Example file contents:
+// ```
+// # This file is created by ibus-daemon, please do not modify it
+// IBUS_ADDRESS=unix:abstract=/tmp/dbus-8EeieDfT,guid=7542d73dce451c2461a044e24bc131f4
+// IBUS_DAEMON_PID=11140
+// ```
+fn get_ibus_address(socket: Option<&Path>) -> Result<String> {
+    if let Some(socket) = socket {
+        return read_socket_file(socket)?.address.block_error(
+            "ibus",
+            &format!("Failed to extract address out of '{:?}'.", socket),
+        );
+    }
+    if let Ok(address) = env::var("IBUS_ADDRESS") {
+        return Ok(address);
+    }
+
+    let socket_dir = xdg_config_home().join("ibus/bus");
+    let socket_files: Vec<SocketFile> = read_dir(socket_dir.clone())
+        .block_error("ibus", &format!("Could not open '{:?}'.", socket_dir))?
+        .flatten()
+        // Files that can't be read can't be used either.
+        .filter_map(|entry| read_socket_file(&entry.path()).ok())
+        .filter(|file| file.address.is_some())
+        .collect();
+
+    let (file, exact) = pick_socket_file(
+        &socket_files,
+        machine_id().as_deref(),
+        display_number().as_deref(),
+        |pid| Path::new("/proc").join(pid.to_string()).exists(),
+    )
+    .block_error("ibus", "Could not locate an IBus socket file.")?;
+    if !exact {
+        log_block_error(
+            "ibus",
+            "ibus",
+            &format!(
+                "No IBus socket file matches this machine and display, using the newest one, '{}'.",
+                file.name
+            ),
+        );
+    }
+    Ok(file.address.clone().unwrap_or_default())
+}
+
+/// A file in which IBus left its address.
+#[derive(Debug, Clone, PartialEq)]
+struct SocketFile {
+    name: String,
+    modified: SystemTime,
+    address: Option<String>,
+    /// The process id of the daemon, if the file says.
+    daemon_pid: Option<u32>,
+}
+
+fn read_socket_file(path: &Path) -> Result<SocketFile> {
+    let mut contents = String::new();
+    let mut file =
+        File::open(path).block_error("ibus", &format!("Could not open '{:?}'.", path))?;
+    file.read_to_string(&mut contents)
+        .block_error("ibus", &format!("Error reading contents of '{:?}'.", path))?;
+    let mut socket_file = parse_socket_file(&contents);
+    socket_file.name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    socket_file.modified = file
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or(UNIX_EPOCH);
+    Ok(socket_file)
+}
+
+/// The address and daemon of a socket file with `contents`.
+fn parse_socket_file(contents: &str) -> SocketFile {
+    lazy_static! {
+        static ref ADDRESS: Regex = Regex::new(r"ADDRESS=(.*),guid").unwrap();
+        static ref DAEMON_PID: Regex = Regex::new(r"IBUS_DAEMON_PID=([0-9]+)").unwrap();
+    }
+    SocketFile {
+        name: String::new(),
+        modified: UNIX_EPOCH,
+        address: ADDRESS.captures(contents).map(|cap| cap[1].to_string()),
+        daemon_pid: DAEMON_PID
+            .captures(contents)
+            .and_then(|cap| cap[1].parse().ok()),
+    }
+}
+
+/// The socket file of the daemon for `machine_id` and `display`, or else the
+/// newest one, skipping those of daemons no longer `running`. Also returns
+/// whether it matched.
+fn pick_socket_file<'a>(
+    files: &'a [SocketFile],
+    machine_id: Option<&str>,
+    display: Option<&str>,
+    running: impl Fn(u32) -> bool,
+) -> Option<(&'a SocketFile, bool)> {
+    let live: Vec<&SocketFile> = files
+        .iter()
+        .filter(|file| file.daemon_pid.is_none_or(&running))
+        .collect();
+    if let (Some(machine_id), Some(display)) = (machine_id, display) {
+        let prefix = format!("{}-", machine_id);
+        let suffix = format!("-{}", display);
+        if let Some(file) = live
+            .iter()
+            .find(|file| file.name.starts_with(&prefix) && file.name.ends_with(&suffix))
+        {
+            return Some((file, true));
+        }
+    }
+    live.into_iter()
+        .max_by_key(|file| file.modified)
+        .map(|file| (file, false))
+}
+
+/// The D-Bus machine id, which IBus names its socket files after.
+fn machine_id() -> Option<String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// The display the bar runs on, as at the end of IBus socket file names.
+fn display_number() -> Option<String> {
+    if let Ok(display) = env::var("WAYLAND_DISPLAY") {
+        return Some(display);
+    }
+    parse_x_display(&env::var("DISPLAY").ok()?)
+}
+
+/// The display number of an X display like `:1` or `host:1.0`.
+fn parse_x_display(display: &str) -> Option<String> {
+    lazy_static! {
+        static ref DISPLAY: Regex = Regex::new(r"^[^:]*:([0-9]+)(\.[0-9]+)?$").unwrap();
+    }
+    DISPLAY.captures(display).map(|cap| cap[1].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crossbeam_channel::Receiver;
+
+    use super::*;
+
+    fn signal(interface: &str, member: &str) -> Message {
+        Message::new_signal("/org/freedesktop/IBus", interface, member).unwrap()
+    }
+
+    fn desc(fields: &[&str]) -> arg::Variant<Box<dyn arg::RefArg>> {
+        let mut desc: Vec<Box<dyn arg::RefArg>> = vec![
+            Box::new("IBusEngineDesc".to_string()),
+            Box::new(HashMap::<String, String>::new()),
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            // The rank is the only field that is not a string.
+            if i == 8 {
+                desc.push(Box::new(field.parse::<u32>().unwrap()));
+            } else {
+                desc.push(Box::new(field.to_string()));
+            }
+        }
+        arg::Variant(Box::new(desc))
+    }
+
+    #[test]
+    fn test_parse_engine_desc() {
+        let mozc = desc(&[
+            "mozc-jp",
+            "Mozc",
+            "Mozc (Japanese Input Method)",
+            "ja",
+            "New BSD",
+            "Google Inc.",
+            "/usr/share/ibus-mozc/icons/product_icon.png",
+            "default",
+            "80",
+            "",
+            "あ",
+            "/usr/lib/ibus-mozc/ibus-setup-mozc-jp",
+            "",
+            "",
+            "0.0.0.0",
+            "ibus-mozc",
+            "InputMode",
+        ]);
+        let engine = parse_engine_desc(&mozc).unwrap();
+        assert_eq!(engine.name, "mozc-jp");
+        assert_eq!(engine.language, "ja");
+        assert_eq!(engine.symbol, "あ");
+
+        // Symbols can be several code points, or missing.
+        let hangul = desc(&[
+            "hangul", "Hangul", "", "ko", "GPL", "", "", "kr", "99", "", "한", "", "", "", "", "",
+            "",
+        ]);
+        assert_eq!(parse_engine_desc(&hangul).unwrap().symbol, "한");
+        let flag = desc(&[
+            "xkb:us::eng",
+            "",
+            "",
+            "en",
+            "",
+            "",
+            "",
+            "us",
+            "99",
+            "",
+            "🇺🇸",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ]);
+        assert_eq!(parse_engine_desc(&flag).unwrap().symbol, "🇺🇸");
+        let us = desc(&[
+            "xkb:us::eng",
+            "English (US)",
+            "English (US)",
+            "en",
+            "GPL",
+            "",
+            "ibus-keyboard",
+            "us",
+            "99",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ]);
+        assert_eq!(parse_engine_desc(&us).unwrap().symbol, "");
+
+        // Old IBus versions send fewer fields.
+        let short = desc(&["anthy", "Anthy", "", "ja"]);
+        let engine = parse_engine_desc(&short).unwrap();
+        assert_eq!(engine.language, "ja");
+        assert_eq!(engine.symbol, "");
+    }
+
+    #[test]
+    fn test_engine_history() {
+        let mut history = EngineHistory::default();
+        history.switch_to("A");
+        assert_eq!(history.previous, None);
+        history.switch_to("B");
+        assert_eq!(history.previous.as_deref(), Some("A"));
+        history.switch_to("A");
+        assert_eq!(history.previous.as_deref(), Some("B"));
+        history.switch_to("C");
+        assert_eq!(history.previous.as_deref(), Some("A"));
+        // Repeated signals for the same engine change nothing.
+        history.switch_to("C");
+        assert_eq!(history.previous.as_deref(), Some("A"));
+        assert_eq!(history.current.as_deref(), Some("C"));
+    }
+
+    #[test]
+    fn test_click_bindings() {
+        let config: IBusConfig = toml::from_str(concat!(
+            "[click]\nleft = \"mozc-jp\"\nright = \"xkb:us::eng\"\n",
+            "wheel_up = \"prev\"\nwheel_down = \"next\"\nmiddle = \"cycle\"\n",
+            "\"shift+left\" = { cmd = \"ibus-setup\" }",
+        ))
+        .unwrap();
+        let action = |button| {
+            let event = I3BarEvent {
+                name: None,
+                instance: None,
+                x: 0,
+                y: 0,
+                button,
+                modifiers: Vec::new(),
+                double_click: false,
+            };
+            config.click.get(&event).cloned()
+        };
+        assert_eq!(
+            action(MouseButton::Left),
+            Some(ClickAction::Block(EngineAction::Switch(
+                "mozc-jp".to_string()
+            )))
+        );
+        assert_eq!(
+            action(MouseButton::Right),
+            Some(ClickAction::Block(EngineAction::Switch(
+                "xkb:us::eng".to_string()
+            )))
+        );
+        assert_eq!(
+            action(MouseButton::WheelUp),
+            Some(ClickAction::Block(EngineAction::Cycle(-1)))
+        );
+        assert_eq!(
+            action(MouseButton::WheelDown),
+            Some(ClickAction::Block(EngineAction::Cycle(1)))
+        );
+        assert_eq!(
+            action(MouseButton::Middle),
+            Some(ClickAction::Block(EngineAction::Cycle(1)))
+        );
+        assert_eq!(action(MouseButton::Forward), None);
+
+        // The table of the first version of the bindings still works.
+        let config: IBusConfig = toml::from_str("[click_bindings]\nleft = \"anthy\"").unwrap();
+        assert!(!config.click.is_empty());
+        assert!(toml::from_str::<IBusConfig>("[click]\nthumb = \"anthy\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_msg() {
+        let valid = signal("org.freedesktop.IBus", "GlobalEngineChanged").append1("anthy");
+        assert_eq!(parse_msg(&ConnectionItem::Signal(valid)), Some("anthy"));
+
+        let cases = vec![
+            // Wrong interface
+            signal("org.freedesktop.DBus", "GlobalEngineChanged").append1("anthy"),
+            // Wrong member
+            signal("org.freedesktop.IBus", "RegistryChanged").append1("anthy"),
+            // Wrong argument type
+            signal("org.freedesktop.IBus", "GlobalEngineChanged").append1(42u32),
+            // No argument at all
+            signal("org.freedesktop.IBus", "GlobalEngineChanged"),
+        ];
+        for m in cases {
+            assert_eq!(parse_msg(&ConnectionItem::Signal(m)), None);
+        }
+
+        let call = Message::new_method_call(
+            "org.freedesktop.IBus",
+            "/org/freedesktop/IBus",
+            "org.freedesktop.IBus",
+            "GlobalEngineChanged",
+        )
+        .unwrap()
+        .append1("anthy");
+        assert_eq!(parse_msg(&ConnectionItem::MethodCall(call)), None);
+        assert_eq!(parse_msg(&ConnectionItem::Nothing), None);
+    }
+
+    #[test]
+    fn test_parse_focus_msg() {
+        let call = |interface, member| {
+            Message::new_method_call(
+                "org.freedesktop.IBus.Panel",
+                "/org/freedesktop/IBus/Panel",
+                interface,
+                member,
+            )
+            .unwrap()
+        };
+        let context = || dbus::Path::new("/org/freedesktop/IBus/InputContext_12").unwrap();
+        assert_eq!(
+            parse_focus_msg(&ConnectionItem::MethodCall(
+                call("org.freedesktop.IBus.Panel", "FocusIn").append1(context())
+            )),
+            Some(Focus::In(
+                "/org/freedesktop/IBus/InputContext_12".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_focus_msg(&ConnectionItem::MethodCall(
+                call("org.freedesktop.IBus.Panel", "FocusOut").append1(context())
+            )),
+            Some(Focus::Out(
+                "/org/freedesktop/IBus/InputContext_12".to_string()
+            ))
+        );
+
+        let cases = vec![
+            // Wrong interface
+            call("org.freedesktop.IBus", "FocusIn").append1(context()),
+            // Wrong member
+            call("org.freedesktop.IBus.Panel", "UpdateProperty").append1(context()),
+            // Wrong argument type
+            call("org.freedesktop.IBus.Panel", "FocusIn").append1("anthy"),
+        ];
+        for m in cases {
+            assert_eq!(parse_focus_msg(&ConnectionItem::MethodCall(m)), None);
+        }
+        let signal = signal("org.freedesktop.IBus.Panel", "FocusIn").append1(context());
+        assert_eq!(parse_focus_msg(&ConnectionItem::Signal(signal)), None);
+    }
+
+    fn panel_call(member: &str) -> Message {
+        Message::new_method_call(
+            "org.freedesktop.IBus.Panel",
+            "/org/freedesktop/IBus/Panel",
+            "org.freedesktop.IBus.Panel",
+            member,
+        )
+        .unwrap()
+    }
+
+    fn update_preedit(text: &str, visible: bool) -> ConnectionItem {
+        let text = arg::Variant((
+            "IBusText",
+            HashMap::<&str, arg::Variant<u32>>::new(),
+            text,
+            arg::Variant(0u32),
+        ));
+        ConnectionItem::MethodCall(panel_call("UpdatePreeditText").append3(text, 0u32, visible))
+    }
+
+    #[test]
+    fn test_parse_preedit_msg() {
+        assert_eq!(
+            parse_preedit_msg(&update_preedit("にほん", true)),
+            Some(PreeditEvent::Update {
+                length: 3,
+                visible: true
+            })
+        );
+        assert_eq!(
+            parse_preedit_msg(&ConnectionItem::MethodCall(panel_call("HidePreeditText"))),
+            Some(PreeditEvent::Hide)
+        );
+        assert_eq!(
+            parse_preedit_msg(&ConnectionItem::MethodCall(panel_call("ShowPreeditText"))),
+            Some(PreeditEvent::Show)
+        );
+        // No text
+        assert_eq!(
+            parse_preedit_msg(&ConnectionItem::MethodCall(
+                panel_call("UpdatePreeditText").append1(true)
+            )),
+            None
+        );
+        assert_eq!(
+            parse_preedit_msg(&ConnectionItem::Signal(signal(
+                "org.freedesktop.IBus.Panel",
+                "HidePreeditText"
+            ))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_preedit_updates() {
+        let (send, updates) = crossbeam_channel::unbounded();
+        let mut shared = SharedState::default();
+        shared.subscribers.insert("ibus".to_string(), send);
+        // Typing "nihon" with mozc, then committing it.
+        let burst = vec![
+            update_preedit("", false),
+            update_preedit("n", true),
+            update_preedit("に", true),
+            update_preedit("にh", true),
+            update_preedit("にほ", true),
+            update_preedit("にほn", true),
+            update_preedit("にほん", true),
+            ConnectionItem::MethodCall(panel_call("ShowPreeditText")),
+            update_preedit("", true),
+            ConnectionItem::MethodCall(panel_call("HidePreeditText")),
+            update_preedit("", false),
+        ];
+        for ci in &burst {
+            shared.handle_preedit(parse_preedit_msg(ci).unwrap());
+        }
+        assert_eq!(updates.try_iter().count(), 2);
+        assert!(!shared.preedit.active());
+
+        let mut preedit = Preedit::default();
+        assert!(preedit.handle(PreeditEvent::Update {
+            length: 2,
+            visible: true
+        }));
+        assert!(preedit.handle(PreeditEvent::Hide));
+        assert!(preedit.handle(PreeditEvent::Show));
+        assert!(!preedit.handle(PreeditEvent::Update {
+            length: 1,
+            visible: true
+        }));
+    }
+
+    #[test]
+    fn test_no_focus() {
+        let mut block = block(EngineState::NoFocus);
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "-");
+        assert_eq!(block.text.widget_state().state, State::Idle);
+    }
+
+    #[test]
+    fn test_parse_xkb_engine() {
+        let cases = [
+            ("xkb:us::eng", "us", "", "eng"),
+            ("xkb:us:intl:eng", "us", "intl", "eng"),
+            ("xkb:de:nodeadkeys:ger", "de", "nodeadkeys", "ger"),
+            ("xkb:gb:extd:eng", "gb", "extd", "eng"),
+            ("xkb:jp::jpn", "jp", "", "jpn"),
+            ("xkb:us", "us", "", ""),
+            ("anthy", "", "", ""),
+            ("libpinyin", "", "", ""),
+            ("mozc-jp", "", "", ""),
+            ("", "", "", ""),
+        ];
+        for &(engine, layout, variant, lang) in cases.iter() {
+            assert_eq!(
+                XkbEngine::parse(engine),
+                XkbEngine {
+                    layout,
+                    variant,
+                    lang
+                },
+                "parsing {}",
+                engine
+            );
+        }
+    }
+
+    #[test]
+    fn test_abbreviation() {
+        assert_eq!(abbreviation("xkb:us::eng", "en"), "us");
+        assert_eq!(abbreviation("xkb:de:nodeadkeys:ger", "de"), "de");
+        assert_eq!(abbreviation("mozc-jp", "ja"), "ja");
+        assert_eq!(abbreviation("libpinyin", "zh_CN"), "zh");
+        assert_eq!(abbreviation("table:latex", "other"), "ta");
+        assert_eq!(abbreviation("m17n:t:unicode", ""), "m1");
+    }
+
+    #[test]
+    fn test_as_icon() {
+        let mut block = block(EngineState::Ready(Engine {
+            name: "mozc-jp".to_string(),
+            language: "ja".to_string(),
+            symbol: "あ".to_string(),
+        }));
+        block.as_icon = true;
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "ja");
+
+        block.mappings = Some(BTreeMap::from([("mozc-jp".to_string(), "JP".to_string())]));
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "JP");
+
+        // The symbol comes first.
+        block.use_symbol = true;
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "あ");
+    }
+
+    #[test]
+    fn test_display_language() {
+        let none = BTreeMap::new();
+        let overrides: BTreeMap<String, String> =
+            map_to_owned!("en" => "GB", "sr_RS@latin" => "ME")
+                .into_iter()
+                .collect();
+        let cases = [
+            // code, name, flag (without overrides), flag (with overrides)
+            ("zh_CN", "Chinese", "🇨🇳", "🇨🇳"),
+            ("zh_TW", "Chinese", "🇹🇼", "🇹🇼"),
+            ("ja", "Japanese", "Japanese", "Japanese"),
+            ("en", "English", "English", "🇬🇧"),
+            ("en_US", "English", "🇺🇸", "🇺🇸"),
+            ("pt-BR", "Portuguese", "🇧🇷", "🇧🇷"),
+            ("sr_RS@latin", "Serbian", "🇷🇸", "🇲🇪"),
+            // A region that is not a country
+            ("es_419", "Spanish", "Spanish", "Spanish"),
+            // Unknown languages show the code
+            ("tlh", "tlh", "tlh", "tlh"),
+            ("", "", "", ""),
+        ];
+        for &(code, name, flag, overridden) in cases.iter() {
+            assert_eq!(display_language(code, LanguageDisplay::Code, &none), code);
+            assert_eq!(
+                display_language(code, LanguageDisplay::Name, &overrides),
+                name,
+                "name of {}",
+                code
+            );
+            assert_eq!(
+                display_language(code, LanguageDisplay::Flag, &none),
+                flag,
+                "flag of {}",
+                code
+            );
+            assert_eq!(
+                display_language(code, LanguageDisplay::Flag, &overrides),
+                overridden,
+                "flag of {} with overrides",
+                code
+            );
+        }
+    }
+
+    fn block(engine: EngineState) -> IBus {
+        let (send, _recv) = crossbeam_channel::unbounded();
+        IBus {
+            id: "ibus".to_string(),
+            text: ButtonWidget::new(Config::default(), "ibus"),
+            engine: Arc::new(Mutex::new(engine)),
+            mappings: None,
+            format: FormatTemplate::from_string("{layout}").unwrap(),
+            variant_format: FormatTemplate::from_string("{variant}").unwrap(),
+            loading_text: String::new(),
+            display: LanguageDisplay::Code,
+            lang_overrides: BTreeMap::new(),
+            use_symbol: false,
+            as_icon: false,
+            xkb_fallback: None,
+            xkb_watcher: None,
+            click: ClickBindings::default(),
+            warning_until: None,
+            send,
+            stale_after: None,
+            stale_marker: "!".to_string(),
+            clock: Instant::now,
+            poll_interval: None,
+            client: Box::new(FakeIBus::default()),
+            engines: None,
+            preedit_marker: "✎".to_string(),
+            no_focus_text: "-".to_string(),
+            error_format: FormatTemplate::from_string("ibus: err").unwrap(),
+            start_error: None,
+            setup: Setup {
+                id: "ibus".to_string(),
+                send: crossbeam_channel::unbounded().0,
+                backend: IBusBackend::IBus,
+                xkb_interval: Duration::from_secs(1),
+                stale_after: None,
+                poll_interval: None,
+                engine_filter: EngineFilter::default(),
+            },
+        }
+    }
+
+    /// A fake IBus for the tests to script. Clones share it.
+    #[derive(Clone, Default)]
+    struct FakeIBus(Rc<RefCell<FakeState>>);
+
+    #[derive(Default)]
+    struct FakeState {
+        /// The current engine, `None` while IBus is down.
+        engine: Option<String>,
+        installed: Vec<String>,
+        /// The engine kept up to date for watching blocks, once one is.
+        watched: Option<Arc<Mutex<EngineState>>>,
+        watchers: HashMap<String, Sender<Task>>,
+    }
+
+    fn engine(name: &str) -> Engine {
+        Engine {
+            name: name.to_string(),
+            ..Engine::default()
+        }
+    }
+
+    fn gone() -> Error {
+        BlockError("ibus".to_string(), "IBus is gone".to_string())
+    }
+
+    impl FakeIBus {
+        fn up(engine: &str) -> Self {
+            let ibus = FakeIBus::default();
+            ibus.0.borrow_mut().installed =
+                vec!["xkb:us::eng".to_string(), "xkb:de::ger".to_string()];
+            ibus.switch(engine);
+            ibus
+        }
+
+        fn down(&self) {
+            self.0.borrow_mut().engine = None;
+        }
+
+        /// Switches engines as if from elsewhere, and signals it.
+        fn switch(&self, name: &str) {
+            let mut state = self.0.borrow_mut();
+            state.engine = Some(name.to_string());
+            if let Some(watched) = &state.watched {
+                *lock(watched) = EngineState::Ready(engine(name));
+            }
+            for (id, send) in &state.watchers {
+                send.send(Task {
+                    id: id.clone(),
+                    update_time: Instant::now(),
+                })
+                .unwrap();
+            }
+        }
+    }
+
+    impl IBusClient for FakeIBus {
+        fn current_engine(&mut self) -> Result<Engine> {
+            self.0
+                .borrow()
+                .engine
+                .as_deref()
+                .map(engine)
+                .ok_or_else(gone)
+        }
+
+        fn set_engine(&mut self, name: &str) -> Result<()> {
+            self.current_engine()?;
+            if !self
+                .0
+                .borrow()
+                .installed
+                .iter()
+                .any(|installed| installed == name)
+            {
+                return Err(BlockError(
+                    "ibus".to_string(),
+                    format!("Failed to switch to engine {}", name),
+                ));
+            }
+            self.switch(name);
+            Ok(())
+        }
+
+        fn list_engines(&mut self) -> Result<Vec<Engine>> {
+            self.current_engine()?;
+            Ok(self
+                .0
+                .borrow()
+                .installed
+                .iter()
+                .map(|name| engine(name))
+                .collect())
+        }
+
+        fn watch(
+            &mut self,
+            id: &str,
+            send: Sender<Task>,
+            _stale_after: Option<Duration>,
+        ) -> Arc<Mutex<EngineState>> {
+            let current = match self.current_engine() {
+                Ok(engine) => EngineState::Ready(engine),
+                Err(e) => EngineState::Error(short_message(e)),
+            };
+            let mut state = self.0.borrow_mut();
+            state.watchers.insert(id.to_string(), send);
+            state
+                .watched
+                .get_or_insert_with(|| Arc::new(Mutex::new(current)))
+                .clone()
+        }
+
+        fn unwatch(&mut self, id: &str) {
+            self.0.borrow_mut().watchers.remove(id);
+        }
+    }
+
+    /// A block with `options` on top of `ibus`, and the channel it asks to be
+    /// updated on.
+    fn fake_block(options: &str, ibus: &FakeIBus) -> (IBus, Receiver<Task>) {
+        let (send, recv) = crossbeam_channel::unbounded();
+        let block = IBus::with_client(
+            toml::from_str(options).unwrap(),
+            Config::default(),
+            send,
+            Box::new(ibus.clone()),
+        )
+        .unwrap();
+        (block, recv)
+    }
+
+    fn click(block: &IBus, button: MouseButton) -> I3BarEvent {
+        I3BarEvent {
+            name: Some(block.id.clone()),
+            instance: None,
+            x: 0,
+            y: 0,
+            button,
+            modifiers: Vec::new(),
+            double_click: false,
+        }
+    }
+
+    #[test]
+    fn test_engine_change_signal() {
+        let ibus = FakeIBus::up("xkb:us::eng");
+        let (mut block, updates) = fake_block("format = \"{layout}\"", &ibus);
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "us");
+
+        ibus.switch("xkb:de::ger");
+        assert_eq!(updates.try_recv().unwrap().id, block.id);
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "de");
+    }
+
+    #[test]
+    fn test_click_switches_engine() {
+        let ibus = FakeIBus::up("xkb:us::eng");
+        let (mut block, updates) = fake_block(
+            "format = \"{layout}\"\n[click]\nleft = \"anthy\"\nright = \"xkb:de::ger\"",
+            &ibus,
+        );
+        block.click(&click(&block, MouseButton::Right)).unwrap();
+        assert_eq!(block.text.widget_state().text, "de");
+        assert_eq!(block.text.widget_state().state, State::Idle);
+
+        // Engines IBus rejects are shown as a warning.
+        let start = Instant::now();
+        NOW.with(|now| now.set(Some(start)));
+        block.clock = fake_now;
+        updates.try_iter().count();
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert_eq!(block.text.widget_state().text, "de");
+        assert_eq!(block.text.widget_state().state, State::Warning);
+
+        // The block asks for the update showing the warning, which schedules
+        // the one removing it.
+        assert!(updates.try_iter().any(|task| task.id == block.id));
+        assert_eq!(
+            block.update().unwrap(),
+            Some(Update::Every(WARNING_DURATION))
+        );
+        NOW.with(|now| now.set(Some(start + WARNING_DURATION)));
+        assert_eq!(block.update().unwrap(), None);
+        assert_eq!(block.text.widget_state().state, State::Idle);
+    }
+
+    thread_local! {
+        static NOW: std::cell::Cell<Option<Instant>> = const { std::cell::Cell::new(None) };
+    }
+
+    fn fake_now() -> Instant {
+        NOW.with(|now| now.get().unwrap())
+    }
+
+    #[test]
+    fn test_cycle() {
+        let engines: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+        assert_eq!(cycle(&engines, Some("a"), 1), Some("b"));
+        assert_eq!(cycle(&engines, Some("c"), 1), Some("a"));
+        assert_eq!(cycle(&engines, Some("a"), -1), Some("c"));
+        assert_eq!(cycle(&engines, Some("b"), -1), Some("a"));
+        assert_eq!(cycle(&engines, Some("b"), 4), Some("c"));
+        // From an engine left out of the cycle.
+        assert_eq!(cycle(&engines, Some("x"), 1), Some("a"));
+        assert_eq!(cycle(&engines, Some("x"), -1), Some("c"));
+        assert_eq!(cycle(&engines, None, 1), Some("a"));
+        assert_eq!(cycle(&[], Some("a"), 1), None);
+    }
+
+    #[test]
+    fn test_click_cycles_engines() {
+        let ibus = FakeIBus::up("xkb:us::eng");
+        ibus.0.borrow_mut().installed.push("anthy".to_string());
+        let (mut block, _updates) = fake_block("format = \"{engine}\"", &ibus);
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert_eq!(block.text.widget_state().text, "xkb:de::ger");
+        block.click(&click(&block, MouseButton::WheelUp)).unwrap();
+        assert_eq!(block.text.widget_state().text, "anthy");
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert_eq!(block.text.widget_state().text, "xkb:us::eng");
+        block.click(&click(&block, MouseButton::WheelDown)).unwrap();
+        assert_eq!(block.text.widget_state().text, "anthy");
+
+        // Only through the engines let through.
+        let (mut block, _updates) = fake_block(
+            "format = \"{engine}\"\nengines_include = [\"xkb:*\"]",
+            &ibus,
+        );
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "anthy");
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert_eq!(block.text.widget_state().text, "xkb:us::eng");
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert_eq!(block.text.widget_state().text, "xkb:de::ger");
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert_eq!(block.text.widget_state().text, "xkb:us::eng");
+    }
+
+    #[test]
+    fn test_start_error() {
+        let ibus = FakeIBus::up("xkb:us::eng");
+        ibus.down();
+        // Listing the engines to filter them fails without IBus.
+        let (mut block, _updates) = fake_block(
+            "format = \"{layout}\"\nengines_include = [\"xkb:*\"]",
+            &ibus,
+        );
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "ibus: err");
+        assert_eq!(block.text.widget_state().state, State::Critical);
+
+        block.error_format = FormatTemplate::from_string("ibus: {error}").unwrap();
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert_eq!(block.text.widget_state().text, "ibus: IBus is gone");
+        assert!(block.start_error.is_some());
+
+        ibus.switch("xkb:us::eng");
+        block.click(&click(&block, MouseButton::Left)).unwrap();
+        assert!(block.start_error.is_none());
+        assert_eq!(block.text.widget_state().text, "us");
+        assert_eq!(block.text.widget_state().state, State::Idle);
+    }
+
+    #[test]
+    fn test_stale_engine() {
+        let start = Instant::now();
+        NOW.with(|now| now.set(Some(start)));
+        let engine = Engine {
+            name: "xkb:us::eng".to_string(),
+            ..Engine::default()
+        };
+        let mut block = block(EngineState::Ready(engine));
+        block.stale_after = Some(Duration::from_secs(30));
+        block.clock = fake_now;
+        let shown = |block: &IBus| {
+            let json = block.text.get_rendered();
+            (
+                json["full_text"].as_str().unwrap().trim().to_string(),
+                json["color"].clone(),
+            )
+        };
+        lock(&SHARED_STATE).last_contact = Some(start);
+        block.update().unwrap();
+        let (text, fresh) = shown(&block);
+        assert_eq!(text, "us");
+
+        NOW.with(|now| now.set(Some(start + Duration::from_secs(30))));
+        block.update().unwrap();
+        assert_eq!(shown(&block), ("us".to_string(), fresh.clone()));
+
+        NOW.with(|now| now.set(Some(start + Duration::from_secs(31))));
+        block.update().unwrap();
+        let (text, stale) = shown(&block);
+        assert_eq!(text, "us!");
+        assert_ne!(stale, fresh);
+
+        // Any answer from IBus clears it again.
+        lock(&SHARED_STATE).last_contact = Some(start + Duration::from_secs(31));
+        block.update().unwrap();
+        assert_eq!(shown(&block), ("us".to_string(), fresh.clone()));
+
+        // Blocks not checking for it never go stale.
+        block.stale_after = None;
+        NOW.with(|now| now.set(Some(start + Duration::from_secs(3600))));
+        block.update().unwrap();
+        assert_eq!(shown(&block), ("us".to_string(), fresh));
+    }
+
+    #[test]
+    fn test_signal_action() {
+        let debounce = Duration::from_millis(50);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Repeated identical signals.
+        assert_eq!(
+            signal_action(Some("a"), "a", None, at(0), debounce),
+            SignalAction::Ignore
+        );
+        assert_eq!(
+            signal_action(Some("a"), "a", Some(at(0)), at(10), debounce),
+            SignalAction::Ignore
+        );
+
+        // A -> B -> A: B is shown, A held back until the burst is over.
+        assert_eq!(
+            signal_action(Some("a"), "b", None, at(0), debounce),
+            SignalAction::Show
+        );
+        assert_eq!(
+            signal_action(Some("b"), "a", Some(at(0)), at(10), debounce),
+            SignalAction::Hold
+        );
+        // Back on B before A was shown, which drops A.
+        assert_eq!(
+            signal_action(Some("b"), "b", Some(at(0)), at(20), debounce),
+            SignalAction::Ignore
+        );
+
+        // Spaced-out changes.
+        assert_eq!(
+            signal_action(Some("b"), "c", Some(at(0)), at(50), debounce),
+            SignalAction::Show
+        );
+        assert_eq!(
+            signal_action(Some("c"), "a", Some(at(50)), at(500), debounce),
+            SignalAction::Show
+        );
+        assert_eq!(
+            signal_action(None, "a", None, at(0), debounce),
+            SignalAction::Show
+        );
+    }
+
+    #[test]
+    fn test_parse_socket_file() {
+        let file = parse_socket_file(concat!(
+            "# This file is created by ibus-daemon, please do not modify it\n",
+            "IBUS_ADDRESS=unix:abstract=/tmp/dbus-8EeieDfT,guid=7542d73dce451c2461a044e24bc131f4\n",
+            "IBUS_DAEMON_PID=11140\n",
+        ));
+        assert_eq!(
+            file.address.as_deref(),
+            Some("unix:abstract=/tmp/dbus-8EeieDfT")
+        );
+        assert_eq!(file.daemon_pid, Some(11140));
+        assert_eq!(parse_socket_file("").daemon_pid, None);
+    }
+
+    #[test]
+    fn test_parse_x_display() {
+        assert_eq!(parse_x_display(":0").as_deref(), Some("0"));
+        assert_eq!(parse_x_display(":12.0").as_deref(), Some("12"));
+        assert_eq!(parse_x_display("host:1").as_deref(), Some("1"));
+        assert_eq!(parse_x_display("wayland-0"), None);
+    }
+
+    #[test]
+    fn test_pick_socket_file() {
+        let file = |name: &str, modified: u64, daemon_pid: u32| SocketFile {
+            name: name.to_string(),
+            modified: UNIX_EPOCH + Duration::from_secs(modified),
+            address: Some(format!("unix:path=/tmp/{}", name)),
+            daemon_pid: Some(daemon_pid),
+        };
+        let files = vec![
+            file("abc-unix-0", 10, 1),
+            file("abc-unix-1", 20, 2),
+            file("def-unix-2", 40, 3),
+            file("abc-unix-3", 30, 4),
+        ];
+        let running = |pid| pid != 3;
+        let pick = |machine_id, display| {
+            pick_socket_file(&files, machine_id, display, running)
+                .map(|(file, exact)| (file.name.as_str(), exact))
+        };
+
+        assert_eq!(pick(Some("abc"), Some("1")), Some(("abc-unix-1", true)));
+        // The newest file of a daemon still running.
+        assert_eq!(pick(Some("abc"), Some("2")), Some(("abc-unix-3", false)));
+        assert_eq!(pick(None, Some("0")), Some(("abc-unix-3", false)));
+        assert_eq!(pick(Some("abc"), None), Some(("abc-unix-3", false)));
+        assert_eq!(
+            pick_socket_file(&files, Some("def"), Some("2"), |_| false),
+            None
+        );
+        assert_eq!(pick_socket_file(&[], Some("abc"), Some("0"), running), None);
+    }
+
+    #[test]
+    fn test_engine_filter() {
+        let engines: Vec<String> = ["xkb:us::eng", "xkb:us:intl:eng", "xkb:de::ger", "mozc-jp"]
+            .iter()
+            .map(|engine| engine.to_string())
+            .collect();
+        let filter = |include: &[&str], exclude: &[&str]| EngineFilter {
+            include: include.iter().map(|p| p.to_string()).collect(),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+        };
+        let cases: [(&[&str], &[&str], &[&str]); 5] = [
+            (
+                &[],
+                &[],
+                &["xkb:us::eng", "xkb:us:intl:eng", "xkb:de::ger", "mozc-jp"],
+            ),
+            (&[], &["xkb:*"], &["mozc-jp"]),
+            (&["xkb:us*"], &[], &["xkb:us::eng", "xkb:us:intl:eng"]),
+            // Included engines stay even if they are excluded too.
+            (
+                &["xkb:us::eng", "mozc*"],
+                &["xkb:us*", "mozc*"],
+                &["xkb:us::eng", "mozc-jp"],
+            ),
+            (&["mozc*"], &["xkb:*"], &["mozc-jp"]),
+        ];
+        for (include, exclude, expected) in cases.iter() {
+            assert_eq!(
+                filter(include, exclude).apply(&engines).unwrap(),
+                expected.to_vec(),
+                "{:?} {:?}",
+                include,
+                exclude
+            );
+        }
+
+        let error = filter(&["anthy*"], &[]).apply(&engines).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            BlockError(
+                "ibus".to_string(),
+                "no engine matches engines_include and engines_exclude, IBus has: \
+                 xkb:us::eng, xkb:us:intl:eng, xkb:de::ger, mozc-jp"
+                    .to_string()
+            )
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn test_poll_interval() {
+        let off = Duration::from_secs(0);
+        let every_10s = Duration::from_secs(10);
+        assert_eq!(poll_interval(IBusBackend::IBus, off), None);
+        assert_eq!(poll_interval(IBusBackend::IBus, every_10s), Some(every_10s));
+        assert_eq!(poll_interval(IBusBackend::Auto, every_10s), Some(every_10s));
+        // XKB is always polled by its own thread.
+        assert_eq!(poll_interval(IBusBackend::Xkb, every_10s), None);
+    }
+
+    #[test]
+    fn test_polled_update() {
+        let ibus = FakeIBus::up("xkb:de::ger");
+        let (mut block, _updates) = fake_block("format = \"{layout}\"\npoll_interval = 10", &ibus);
+        let every_10s = |next: Option<Update>| matches!(next, Some(Update::Every(interval)) if interval == Duration::from_secs(10));
+        assert!(every_10s(block.update().unwrap()));
+        assert_eq!(block.text.widget_state().text, "de");
+
+        // Failed queries are shown, and tried again at the next update.
+        ibus.down();
+        assert!(every_10s(block.update().unwrap()));
+        assert_eq!(block.text.widget_state().text, "IBus is gone");
+        assert_eq!(block.text.widget_state().state, State::Critical);
+
+        ibus.switch("xkb:us::eng");
+        assert!(every_10s(block.update().unwrap()));
+        assert_eq!(block.text.widget_state().text, "us");
+
+        // Blocks listening to IBus leave scheduling to the listener.
+        block.poll_interval = None;
+        assert!(block.update().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_notify_all_fans_out_and_forgets_closed_channels() {
+        let mut shared = SharedState::default();
+        let (tx_a, rx_a) = crossbeam_channel::unbounded();
+        let (tx_b, rx_b) = crossbeam_channel::unbounded();
+        shared.subscribers.insert("a".to_string(), tx_a);
+        shared.subscribers.insert("b".to_string(), tx_b);
+
+        shared.notify_all();
+        assert_eq!(rx_a.try_recv().unwrap().id, "a");
+        assert_eq!(rx_b.try_recv().unwrap().id, "b");
+
+        drop(rx_b);
+        shared.notify_all();
+        assert_eq!(rx_a.try_iter().count(), 1);
+        assert_eq!(shared.subscribers.len(), 1);
+    }
 }
@@ -0,0 +1,446 @@
+//! A block for counting unread mails on IMAP servers.
+//!
+//! Every account is asked for the number of unseen messages in each of its
+//! folders with an IMAP `STATUS` command over TLS, sent with `curl`. Passwords
+//! are never part of the configuration: they are the output of a command such
+//! as `pass show mail/work`, which is run once and remembered until the server
+//! rejects the login. They are handed to `curl` on its standard input, so they
+//! do not show up in the process list either.
+//!
+//! An account that cannot be counted, e.g. because its server is down, is
+//! logged and keeps its last count, while the other accounts are still
+//! counted. The block only fails if every account does.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::registry::Values;
+use crate::scheduler::Task;
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
+
+/// The exit code of `curl` when the server denies the login.
+const LOGIN_DENIED: i32 = 67;
+
+pub struct Imap {
+    text: TextWidget,
+    id: String,
+    update_interval: Duration,
+    format: FormatTemplate,
+    accounts: Vec<Account>,
+    threshold_warning: u64,
+    threshold_critical: u64,
+    /// Counts the unread mails in a folder of an account, replaced in tests.
+    count_unread: fn(&mut Account, &str) -> Result<u64>,
+}
+
+/// An IMAP account, see [`AccountConfig`](./struct.AccountConfig.html).
+struct Account {
+    config: AccountConfig,
+    password: Option<String>,
+    unread: u64,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    /// The name of the account, which is also its placeholder
+    pub name: String,
+
+    pub server: String,
+
+    #[serde(default = "AccountConfig::default_port")]
+    pub port: u16,
+
+    pub user: String,
+
+    /// A command printing the password on its first line
+    pub password_command: String,
+
+    /// The folders to count unread mails in
+    #[serde(default = "AccountConfig::default_folders")]
+    pub folders: Vec<String>,
+}
+
+impl AccountConfig {
+    fn default_port() -> u16 {
+        993
+    }
+
+    fn default_folders() -> Vec<String> {
+        vec!["INBOX".to_owned()]
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImapConfig {
+    /// Update interval in seconds
+    #[serde(
+        default = "ImapConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// Format override
+    #[serde(default = "ImapConfig::default_format")]
+    pub format: String,
+
+    pub accounts: Vec<AccountConfig>,
+
+    #[serde(default = "ImapConfig::default_threshold_warning")]
+    pub threshold_warning: u64,
+
+    #[serde(default = "ImapConfig::default_threshold_critical")]
+    pub threshold_critical: u64,
+}
+
+impl ImapConfig {
+    fn default_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_format() -> String {
+        "{total}".to_owned()
+    }
+
+    fn default_threshold_warning() -> u64 {
+        1
+    }
+
+    fn default_threshold_critical() -> u64 {
+        10
+    }
+}
+
+impl ConfigBlock for Imap {
+    type Config = ImapConfig;
+
+    fn new(block_config: Self::Config, config: Config, _: Sender<Task>) -> Result<Self> {
+        let mut names = HashSet::new();
+        for account in &block_config.accounts {
+            if account.name == "total" || !names.insert(account.name.clone()) {
+                return Err(BlockError(
+                    "imap".to_owned(),
+                    format!("account name '{}' is already taken", account.name),
+                ));
+            }
+        }
+
+        Ok(Imap {
+            id: Uuid::new_v4().to_simple().to_string(),
+            text: TextWidget::new(config).with_text("").with_icon("mail"),
+            update_interval: block_config.interval,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("imap", "Invalid format specified")?,
+            accounts: block_config
+                .accounts
+                .into_iter()
+                .map(|config| Account {
+                    config,
+                    password: None,
+                    unread: 0,
+                })
+                .collect(),
+            threshold_warning: block_config.threshold_warning,
+            threshold_critical: block_config.threshold_critical,
+            count_unread: Account::unread,
+        })
+    }
+}
+
+impl Account {
+    /// The password of the account, from its `password_command`.
+    fn password(&mut self) -> Result<String> {
+        if let Some(ref password) = self.password {
+            return Ok(password.clone());
+        }
+        let output = Command::new("sh")
+            .args(["-c", &self.config.password_command])
+            .stdin(Stdio::null())
+            .output()
+            .block_error("imap", "failed to run password_command")?;
+        if !output.status.success() {
+            return Err(BlockError(
+                "imap".to_owned(),
+                format!("password_command of '{}' failed", self.config.name),
+            ));
+        }
+        let output = String::from_utf8(output.stdout)
+            .block_error("imap", "password_command printed invalid UTF-8")?;
+        let password = output.lines().next().unwrap_or_default().to_string();
+        self.password = Some(password.clone());
+        Ok(password)
+    }
+
+    /// The number of unread mails in `folder`.
+    fn unread(&mut self, folder: &str) -> Result<u64> {
+        let password = self.password()?;
+        let mut child = Command::new("curl")
+            .args([
+                "--silent",
+                "--show-error",
+                "--max-time",
+                "10",
+                "--config",
+                "-",
+                &format!("imaps://{}:{}/", self.config.server, self.config.port),
+                "--request",
+                &format!("STATUS {} (UNSEEN)", quote(folder)),
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .block_error("imap", "failed to run curl")?;
+        child
+            .stdin
+            .take()
+            .block_error("imap", "failed to open stdin of curl")?
+            .write_all(
+                format!(
+                    "user = {}\n",
+                    quote(&format!("{}:{}", self.config.user, password))
+                )
+                .as_bytes(),
+            )
+            .block_error("imap", "failed to write to curl")?;
+        let output = child
+            .wait_with_output()
+            .block_error("imap", "failed to run curl")?;
+
+        if output.status.code() == Some(LOGIN_DENIED) {
+            // Ask for the password again next time, it may have changed.
+            self.password = None;
+        }
+        if !output.status.success() {
+            return Err(BlockError(
+                "imap".to_owned(),
+                format!(
+                    "{}: {}",
+                    self.config.name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        parse_unseen(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+            BlockError(
+                "imap".to_owned(),
+                format!("{}: no status for folder '{}'", self.config.name, folder),
+            )
+        })
+    }
+}
+
+/// A quoted string, as understood by both IMAP and the configuration files of
+/// `curl`.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The `UNSEEN` count of a `STATUS` response, e.g. `* STATUS INBOX (UNSEEN 3)`.
+fn parse_unseen(response: &str) -> Option<u64> {
+    response
+        .lines()
+        .filter(|line| line.starts_with("* STATUS "))
+        .find_map(|line| {
+            let start = line.rfind("UNSEEN ")? + "UNSEEN ".len();
+            let count = &line[start..];
+            let end = count
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(count.len());
+            count[..end].parse().ok()
+        })
+}
+
+impl Block for Imap {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let count_unread = self.count_unread;
+        let mut errors = Vec::new();
+        for account in &mut self.accounts {
+            let unread = account
+                .config
+                .folders
+                .clone()
+                .iter()
+                .map(|folder| count_unread(account, folder))
+                .sum::<Result<u64>>();
+            match unread {
+                Ok(unread) => account.unread = unread,
+                Err(error) => {
+                    log_block_error(&self.id, "imap", error.parts().1);
+                    errors.push(error);
+                }
+            }
+        }
+        if !errors.is_empty() && errors.len() == self.accounts.len() {
+            return Err(errors.remove(0));
+        }
+
+        let total: u64 = self.accounts.iter().map(|account| account.unread).sum();
+        let mut values: HashMap<String, String> = self
+            .accounts
+            .iter()
+            .map(|account| {
+                (
+                    format!("{{{}}}", account.config.name),
+                    account.unread.to_string(),
+                )
+            })
+            .collect();
+        values.insert("{total}".to_owned(), total.to_string());
+        let values = values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.clone()))
+            .collect();
+
+        self.text.set_text(self.format.render_static_str(&values)?);
+        self.text.set_state(if total >= self.threshold_critical {
+            State::Critical
+        } else if total >= self.threshold_warning {
+            State::Warning
+        } else {
+            State::Idle
+        });
+        Ok(Some(self.update_interval.into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.text]
+    }
+
+    fn values(&self) -> Values {
+        let mut values: Values = self
+            .accounts
+            .iter()
+            .map(|account| (account.config.name.clone(), account.unread as f64))
+            .collect();
+        values.insert(
+            "total".to_owned(),
+            self.accounts
+                .iter()
+                .map(|account| account.unread as f64)
+                .sum(),
+        );
+        values
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(password_command: &str) -> Account {
+        Account {
+            config: toml::from_str(&format!(
+                "name = \"work\"\nserver = \"imap.example.com\"\nuser = \"me\"\npassword_command = {}",
+                quote(password_command)
+            ))
+            .unwrap(),
+            password: None,
+            unread: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_unseen() {
+        assert_eq!(parse_unseen("* STATUS INBOX (UNSEEN 3)\r\n"), Some(3));
+        assert_eq!(
+            parse_unseen("* OK ready\r\n* STATUS \"Sent (UNSEEN 9)\" (UNSEEN 12)\r\n"),
+            Some(12)
+        );
+        assert_eq!(parse_unseen("* STATUS INBOX (MESSAGES 4)\r\n"), None);
+        assert_eq!(parse_unseen(""), None);
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("INBOX"), "\"INBOX\"");
+        assert_eq!(quote(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+    }
+
+    #[test]
+    fn test_password() {
+        let mut work = account("echo 'hunter2'; echo second line");
+        assert_eq!(work.config.port, 993);
+        assert_eq!(work.config.folders, vec!["INBOX".to_owned()]);
+        assert_eq!(work.password().unwrap(), "hunter2");
+        // Remembered without running the command again.
+        work.config.password_command = "false".to_owned();
+        assert_eq!(work.password().unwrap(), "hunter2");
+        work.password = None;
+        assert!(work.password().is_err());
+    }
+
+    #[test]
+    fn test_account_names() {
+        let block = |accounts: &str| {
+            Imap::new(
+                toml::from_str(accounts).unwrap(),
+                Config::default(),
+                crossbeam_channel::unbounded().0,
+            )
+        };
+        assert!(block(&(accounts_entry("work") + &accounts_entry("home"))).is_ok());
+        assert!(block(&(accounts_entry("work") + &accounts_entry("work"))).is_err());
+        assert!(block(&accounts_entry("total")).is_err());
+    }
+
+    /// An entry of `accounts` in the configuration of the block.
+    fn accounts_entry(name: &str) -> String {
+        format!(
+            "[[accounts]]\nname = \"{}\"\nserver = \"s\"\nuser = \"u\"\npassword_command = \"c\"\n",
+            name
+        )
+    }
+
+    /// Counts as many unread mails as the folder name is long, except for
+    /// the account "down".
+    fn fake_unread(account: &mut Account, folder: &str) -> Result<u64> {
+        if account.config.name == "down" {
+            return Err(BlockError(
+                "imap".to_owned(),
+                "down: Failed to connect".to_owned(),
+            ));
+        }
+        Ok(folder.len() as u64)
+    }
+
+    #[test]
+    fn test_failing_account() {
+        let mut block = Imap::new(
+            toml::from_str(&(accounts_entry("work") + &accounts_entry("down"))).unwrap(),
+            Config::default(),
+            crossbeam_channel::unbounded().0,
+        )
+        .unwrap();
+        block.count_unread = fake_unread;
+
+        // The other accounts are still counted.
+        assert!(block.update().is_ok());
+        let values = block.values();
+        assert_eq!(values["work"], 5.0);
+        assert_eq!(values["down"], 0.0);
+        assert_eq!(values["total"], 5.0);
+
+        // Only failing accounts fail the block.
+        block.accounts.remove(0);
+        assert!(block.update().is_err());
+    }
+}
@@ -12,8 +12,10 @@ use uuid::Uuid;
 use crate::blocks::{Block, ConfigBlock, Update};
 use crate::config::Config;
 use crate::errors::*;
-use crate::input::I3BarEvent;
+use crate::input::{ClickAction, ClickBindings, I3BarEvent, MouseButton};
 use crate::scheduler::Task;
+use crate::stop::{self, Stop};
+use crate::subprocess::spawn_child_async;
 use crate::util::{battery_level_to_icon, FormatTemplate};
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::button::ButtonWidget;
@@ -36,6 +38,9 @@ pub struct KDEConnect {
     format_disconnected: FormatTemplate,
     output: ButtonWidget,
     config: Config,
+    click: ClickBindings<KDEConnectAction>,
+    /// Stops listening for signals when the block is dropped.
+    _listener: Stop,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -67,6 +72,21 @@ pub struct KDEConnectConfig {
     /// Format string for displaying phone information when it is disconnected.
     #[serde(default = "KDEConnectConfig::default_format_disconnected")]
     pub format_disconnected: String,
+
+    /// Actions bound to clicks, replacing showing the notifications on left
+    /// click and ringing the phone on right click
+    #[serde(default)]
+    pub click: ClickBindings<KDEConnectAction>,
+}
+
+/// What a click on the kdeconnect block can do.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KDEConnectAction {
+    /// Make the phone ring, to find it.
+    Ring,
+    /// Show the notifications of the phone in a desktop notification.
+    Notifications,
 }
 
 impl KDEConnectConfig {
@@ -208,6 +228,7 @@ impl ConfigBlock for KDEConnect {
         //    Arc::new(Mutex::new(initial_notifications.get(0).unwrap().to_string()))
         //};
 
+        let (stop, stopped) = stop::pair()?;
         thread::Builder::new()
             .name("kdeconnect".into())
             .spawn(move || {
@@ -237,7 +258,7 @@ impl ConfigBlock for KDEConnect {
                             id: id1.clone(),
                             update_time: Instant::now(),
                         })
-                        .unwrap();
+                        .ok();
 
                         true
                     },
@@ -265,7 +286,7 @@ impl ConfigBlock for KDEConnect {
                                 id: id2.clone(),
                                 update_time: Instant::now(),
                             })
-                            .unwrap();
+                            .ok();
 
                         true
                     },
@@ -284,7 +305,7 @@ impl ConfigBlock for KDEConnect {
                                 id: id3.clone(),
                                 update_time: Instant::now(),
                             })
-                            .unwrap();
+                            .ok();
 
                         true
                     },
@@ -307,7 +328,7 @@ impl ConfigBlock for KDEConnect {
                                 id: id4.clone(),
                                 update_time: Instant::now(),
                             })
-                            .unwrap();
+                            .ok();
 
                         true
                     },
@@ -326,7 +347,7 @@ impl ConfigBlock for KDEConnect {
                                 id: id5.clone(),
                                 update_time: Instant::now(),
                             })
-                            .unwrap();
+                            .ok();
 
                         true
                     },
@@ -353,7 +374,7 @@ impl ConfigBlock for KDEConnect {
                                 id: id6.clone(),
                                 update_time: Instant::now(),
                             })
-                            .unwrap();
+                            .ok();
 
                         true
                     },
@@ -379,20 +400,19 @@ impl ConfigBlock for KDEConnect {
                                 id: id7.clone(),
                                 update_time: Instant::now(),
                             })
-                            .unwrap();
+                            .ok();
 
                         true
                     },
                 );
 
-                loop {
+                while !stopped.is_stopped() {
                     c.process(Duration::from_millis(1000)).unwrap();
                 }
             })
             .unwrap();
 
         Ok(KDEConnect {
-            id,
             device_id,
             device_name,
             battery_charge: charge,
@@ -407,8 +427,11 @@ impl ConfigBlock for KDEConnect {
             bat_critical: block_config.bat_critical,
             format: FormatTemplate::from_string(&block_config.format)?,
             format_disconnected: FormatTemplate::from_string(&block_config.format_disconnected)?,
-            output: ButtonWidget::new(config.clone(), "kdeconnect").with_icon("phone"),
+            output: ButtonWidget::new(config.clone(), &id).with_icon("phone"),
+            id,
             config,
+            click: block_config.click,
+            _listener: stop,
         })
     }
 }
@@ -517,9 +540,111 @@ impl Block for KDEConnect {
         vec![&self.output]
     }
 
-    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
-        Ok(())
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if !event.matches_name(&self.id) {
+            return Ok(());
+        }
+        let action = match self.click.get(event).cloned() {
+            Some(ClickAction::Block(action)) => action,
+            Some(ClickAction::Command { cmd }) => {
+                return spawn_child_async("sh", &["-c", &cmd])
+                    .block_error("kdeconnect", "could not spawn child");
+            }
+            None => match event.button {
+                MouseButton::Left => KDEConnectAction::Notifications,
+                MouseButton::Right => KDEConnectAction::Ring,
+                _ => return Ok(()),
+            },
+        };
+        let c = Connection::new_session()
+            .block_error("kdeconnect", "Failed to establish D-Bus connection")?;
+        let device_path = format!("/modules/kdeconnect/devices/{}", self.device_id);
+        let proxy = |path: &str| {
+            c.with_proxy(
+                "org.kde.kdeconnect",
+                path.to_string(),
+                Duration::from_millis(5000),
+            )
+        };
+        match action {
+            KDEConnectAction::Ring => proxy(&format!("{}/findmyphone", device_path))
+                .method_call("org.kde.kdeconnect.device.findmyphone", "ring", ())
+                .block_error("kdeconnect", "Failed to ring the device"),
+            KDEConnectAction::Notifications => {
+                // Newer versions of KDE Connect have the plugin on a path of
+                // its own, older ones on the path of the device.
+                let path = format!("{}/notifications", device_path);
+                let (ids,): (Vec<String>,) = [&path, &device_path]
+                    .iter()
+                    .find_map(|path| {
+                        proxy(path)
+                            .method_call(
+                                "org.kde.kdeconnect.device.notifications",
+                                "activeNotifications",
+                                (),
+                            )
+                            .ok()
+                    })
+                    .block_error("kdeconnect", "Failed to get the notifications")?;
+                let notifications: Vec<Notification> = ids
+                    .iter()
+                    .map(|id| {
+                        let notification = proxy(&format!("{}/{}", path, id));
+                        let get = |property| -> String {
+                            notification
+                                .get(
+                                    "org.kde.kdeconnect.device.notifications.notification",
+                                    property,
+                                )
+                                .unwrap_or_default()
+                        };
+                        Notification {
+                            app: get("appName"),
+                            title: get("title"),
+                            text: get("text"),
+                        }
+                    })
+                    .collect();
+                let name = self
+                    .device_name
+                    .lock()
+                    .block_error("kdeconnect", "failed to acquire lock for `name`")?
+                    .clone();
+                spawn_child_async("notify-send", &[&name, &notification_list(&notifications)])
+                    .block_error("kdeconnect", "could not spawn notify-send")
+            }
+        }
+    }
+}
+
+/// A notification on the phone.
+struct Notification {
+    app: String,
+    title: String,
+    text: String,
+}
+
+/// One line for each notification, e.g. `Signal: Alice — See you at 8`.
+fn notification_list(notifications: &[Notification]) -> String {
+    if notifications.is_empty() {
+        return "No notifications".to_string();
     }
+    notifications
+        .iter()
+        .map(|notification| {
+            let details: Vec<&str> = [&notification.title, &notification.text]
+                .iter()
+                .map(|part| part.as_str())
+                .filter(|part| !part.is_empty() && *part != notification.app)
+                .collect();
+            match (notification.app.as_str(), details.join(" — ")) {
+                (app, details) if details.is_empty() => app.to_string(),
+                ("", details) => details,
+                (app, details) => format!("{}: {}", app, details),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 // Code below generated using the command below and Results changed to explcitly use std::Result
@@ -796,3 +921,30 @@ impl dbus::message::SignalArgs for OrgKdeKdeconnectDaemonDeviceListChanged {
     const NAME: &'static str = "deviceListChanged";
     const INTERFACE: &'static str = "org.kde.kdeconnect.daemon";
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_list() {
+        let notification = |app: &str, title: &str, text: &str| Notification {
+            app: app.to_string(),
+            title: title.to_string(),
+            text: text.to_string(),
+        };
+        assert_eq!(notification_list(&[]), "No notifications");
+        assert_eq!(
+            notification_list(&[
+                notification("Signal", "Alice", "See you at 8"),
+                notification("Clock", "Clock", "Alarm in 5 minutes"),
+                notification("", "Battery low", ""),
+                notification("Updates", "", ""),
+            ]),
+            "Signal: Alice — See you at 8\n\
+             Clock: Alarm in 5 minutes\n\
+             Battery low\n\
+             Updates"
+        );
+    }
+}
@@ -5,10 +5,7 @@ use std::time::{Duration, Instant};
 
 use crossbeam_channel::Sender;
 use dbus::ffidisp::stdintf::org_freedesktop_dbus::Properties;
-use dbus::{
-    ffidisp::{MsgHandlerResult, MsgHandlerType},
-    Message,
-};
+use dbus::Message;
 use serde_derive::Deserialize;
 use swayipc::reply::Event;
 use swayipc::reply::InputChange;
@@ -16,14 +13,16 @@ use swayipc::{Connection, EventType};
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
+use crate::dbus_manager::{self, Bus};
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
 use crate::util::FormatTemplate;
 use crate::widget::I3BarWidget;
-use crate::widgets::text::TextWidget;
+use crate::widgets::button::ButtonWidget;
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -50,7 +49,20 @@ pub trait KeyboardLayoutMonitor {
 
     /// Monitor layout changes and send updates via the `update_request`
     /// channel. By default, this method does nothing.
-    fn monitor(&self, _id: String, _update_request: Sender<Task>) {}
+    fn monitor(&self, _id: String, _update_request: Sender<Task>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the monitor can switch layouts with `cycle_layout`.
+    fn can_cycle(&self) -> bool {
+        false
+    }
+
+    /// Switch to the next layout, or to the previous one if `forward` is
+    /// false. By default, this method does nothing.
+    fn cycle_layout(&self, _forward: bool) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct SetXkbMap;
@@ -62,37 +74,47 @@ impl SetXkbMap {
     }
 }
 
-fn setxkbmap_layouts() -> Result<String> {
-    let output = Command::new("setxkbmap")
+fn setxkbmap_query() -> Result<String> {
+    Command::new("setxkbmap")
         .args(&["-query"])
         .output()
         .block_error("keyboard_layout", "Failed to execute setxkbmap.")
         .and_then(|raw| {
             String::from_utf8(raw.stdout).block_error("keyboard_layout", "Non-UTF8 input.")
-        })?;
-
-    // Find the "layout:    xxxx" entry.
-    let layout = output
-        .split('\n')
-        .find(|line| line.starts_with("layout"))
-        .ok_or_else(|| {
-            BlockError(
-                "keyboard_layout".to_string(),
-                "Could not find the layout entry from setxkbmap.".to_string(),
-            )
-        })?
-        .split(char::is_whitespace)
-        .last();
-
-    match layout {
+        })
+}
+
+/// The value of the `key:    xxxx` entry in the output of `setxkbmap -query`.
+fn query_entry<'a>(output: &'a str, key: &str) -> Option<&'a str> {
+    output
+        .lines()
+        .find(|line| line.starts_with(key) && line[key.len()..].starts_with(':'))
+        .and_then(|line| line.split(char::is_whitespace).last())
+}
+
+fn setxkbmap_layouts() -> Result<String> {
+    let output = setxkbmap_query()?;
+    match query_entry(&output, "layout") {
         Some(layout) => Ok(layout.to_string()),
         None => Err(BlockError(
             "keyboard_layout".to_string(),
-            "Could not read the layout entry from setxkbmap.".to_string(),
+            "Could not find the layout entry from setxkbmap.".to_string(),
         )),
     }
 }
 
+/// Rotate a comma separated list of layouts (or variants) by one, so that the
+/// next or previous one comes first. X uses the first layout of a keymap.
+fn rotate_list(list: &str, forward: bool) -> String {
+    let mut items: Vec<&str> = list.split(',').collect();
+    if forward {
+        items.rotate_left(1);
+    } else {
+        items.rotate_right(1);
+    }
+    items.join(",")
+}
+
 impl KeyboardLayoutMonitor for SetXkbMap {
     fn keyboard_layout(&self) -> Result<String> {
         setxkbmap_layouts()
@@ -101,6 +123,29 @@ impl KeyboardLayoutMonitor for SetXkbMap {
     fn must_poll(&self) -> bool {
         true
     }
+
+    fn can_cycle(&self) -> bool {
+        true
+    }
+
+    /// Rotate the layouts of the keymap, keeping each variant with its layout.
+    fn cycle_layout(&self, forward: bool) -> Result<()> {
+        let output = setxkbmap_query()?;
+        let layouts = match query_entry(&output, "layout") {
+            Some(layouts) if layouts.contains(',') => layouts,
+            _ => return Ok(()),
+        };
+        let mut args = vec!["-layout".to_string(), rotate_list(layouts, forward)];
+        if let Some(variants) = query_entry(&output, "variant") {
+            args.push("-variant".to_string());
+            args.push(rotate_list(variants, forward));
+        }
+        Command::new("setxkbmap")
+            .args(&args)
+            .output()
+            .block_error("keyboard_layout", "Failed to execute setxkbmap.")
+            .map(|_| ())
+    }
 }
 
 pub struct LocaleBus {
@@ -131,39 +176,21 @@ impl KeyboardLayoutMonitor for LocaleBus {
         false
     }
 
-    /// Monitor Locale property changes in a separate thread and send updates
-    /// via the `update_request` channel.
-    fn monitor(&self, id: String, update_request: Sender<Task>) {
-        thread::Builder::new()
-            .name("keyboard_layout".into())
-            .spawn(move || {
-                let con = dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::System)
-                    .expect("Failed to establish D-Bus connection.");
-                let rule = "type='signal',\
-                        path='/org/freedesktop/locale1',\
-                        interface='org.freedesktop.DBus.Properties',\
-                        member='PropertiesChanged'";
-
-                // Skip the NameAcquired event.
-                con.incoming(10_000).next();
-
-                con.add_match(&rule)
-                    .expect("Failed to add D-Bus match rule.");
-
-                loop {
-                    // TODO: This actually seems to trigger twice for each localectl
-                    // change.
-                    if con.incoming(10_000).next().is_some() {
-                        update_request
-                            .send(Task {
-                                id: id.clone(),
-                                update_time: Instant::now(),
-                            })
-                            .unwrap();
-                    }
-                }
-            })
-            .unwrap();
+    /// Monitor Locale property changes and send updates via the
+    /// `update_request` channel.
+    fn monitor(&self, id: String, update_request: Sender<Task>) -> Result<()> {
+        // TODO: This actually seems to trigger twice for each localectl
+        // change.
+        dbus_manager::request_updates(
+            Bus::System,
+            "type='signal',\
+             path='/org/freedesktop/locale1',\
+             interface='org.freedesktop.DBus.Properties',\
+             member='PropertiesChanged'",
+            id,
+            update_request,
+        )
+        .block_error("locale", "Failed to add D-Bus match rule.")
     }
 }
 
@@ -242,66 +269,62 @@ impl KeyboardLayoutMonitor for KbdDaemonBus {
         false
     }
 
-    // Monitor KbdDaemon 'layoutChanged' property in a separate thread and send updates
-    // via the `update_request` channel.
-    fn monitor(&self, id: String, update_request: Sender<Task>) {
-        let arc = Arc::clone(&self.kbdd_layout_id);
-        thread::Builder::new()
-            .name("keyboard_layout".into())
-            .spawn(move || {
-                let c = dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::Session)
-                    .unwrap();
-                c.add_match(
-                    "interface='ru.gentoo.kbdd',\
-                 member='layoutChanged',\
-                 path='/ru/gentoo/KbddService'",
-                )
-                .expect("Failed to add D-Bus match rule, is kbdd started?");
-
-                // skip NameAcquired
-                c.incoming(10_000).next();
-
-                c.add_handler(KbddMessageHandler(arc));
-                loop {
-                    for ci in c.iter(100_000) {
-                        if let dbus::ffidisp::ConnectionItem::Signal(_) = ci {
-                            update_request
-                                .send(Task {
-                                    id: id.clone(),
-                                    update_time: Instant::now(),
-                                })
-                                .unwrap();
-                        }
-                    }
-                }
-            })
-            .unwrap();
+    fn can_cycle(&self) -> bool {
+        true
     }
-}
 
-struct KbddMessageHandler(Arc<Mutex<u32>>);
+    fn cycle_layout(&self, forward: bool) -> Result<()> {
+        let c = dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::Session)
+            .block_error("kbddaemonbus", "can't connect to dbus")?;
+
+        let send_msg = Message::new_method_call(
+            "ru.gentoo.KbddService",
+            "/ru/gentoo/KbddService",
+            "ru.gentoo.kbdd",
+            if forward {
+                "next_layout"
+            } else {
+                "prev_layout"
+            },
+        )
+        .block_error("kbddaemonbus", "Create switch-layout message failure")?;
 
-impl dbus::ffidisp::MsgHandler for KbddMessageHandler {
-    fn handler_type(&self) -> MsgHandlerType {
-        dbus::ffidisp::MsgHandlerType::MsgType(dbus::MessageType::Signal)
+        c.send_with_reply_and_block(send_msg, 5000)
+            .map(|_| ())
+            .block_error("kbddaemonbus", "Is kbdd running?")
     }
 
-    fn handle_msg(&mut self, msg: &Message) -> Option<MsgHandlerResult> {
-        let layout: Option<u32> = msg.get1();
-        if let Some(idx) = layout {
-            let mut val = self.0.lock().unwrap();
-            *val = idx;
-        }
-        //handled=false - because we still need to call update_request.send in monitor
-        Some(MsgHandlerResult {
-            handled: false,
-            done: false,
-            reply: vec![],
-        })
+    // Monitor KbdDaemon 'layoutChanged' property and send updates via the
+    // `update_request` channel.
+    fn monitor(&self, id: String, update_request: Sender<Task>) -> Result<()> {
+        let layout_id = Arc::clone(&self.kbdd_layout_id);
+        dbus_manager::subscribe(
+            Bus::Session,
+            "type='signal',\
+             interface='ru.gentoo.kbdd',\
+             member='layoutChanged',\
+             path='/ru/gentoo/KbddService'",
+            move |msg| {
+                if let Some(idx) = msg.get1::<u32>() {
+                    *layout_id.lock().unwrap() = idx;
+                }
+                update_request
+                    .send(Task {
+                        id: id.clone(),
+                        update_time: Instant::now(),
+                    })
+                    .is_ok()
+            },
+        )
+        .block_error(
+            "kbddaemonbus",
+            "Failed to add D-Bus match rule, is kbdd started?",
+        )
     }
 }
 
 pub struct Sway {
+    sway_kb_identifier: String,
     sway_kb_layout: Arc<Mutex<String>>,
 }
 
@@ -312,12 +335,13 @@ impl Sway {
             .get_inputs()
             .unwrap()
             .into_iter()
-            .find(|input| input.identifier == sway_kb_identifier)
+            .find(|input| input.identifier == *sway_kb_identifier)
             .and_then(|input| input.xkb_active_layout_name)
             .ok_or_else(|| "".to_string())
             .block_error("sway", "Failed to get xkb_active_layout_name.")?;
 
         Ok(Sway {
+            sway_kb_identifier,
             sway_kb_layout: Arc::new(Mutex::new(layout)),
         })
     }
@@ -333,9 +357,27 @@ impl KeyboardLayoutMonitor for Sway {
         false
     }
 
+    fn can_cycle(&self) -> bool {
+        true
+    }
+
+    /// The layout change is picked up by `monitor` like any other.
+    fn cycle_layout(&self, forward: bool) -> Result<()> {
+        Connection::new()
+            .and_then(|mut conn| {
+                conn.run_command(format!(
+                    "input \"{}\" xkb_switch_layout {}",
+                    self.sway_kb_identifier,
+                    if forward { "next" } else { "prev" }
+                ))
+            })
+            .map(|_| ())
+            .block_error("sway", "Failed to switch the layout.")
+    }
+
     /// Monitor layout changes in a separate thread and send updates
     /// via the `update_request` channel.
-    fn monitor(&self, id: String, update_request: Sender<Task>) {
+    fn monitor(&self, id: String, update_request: Sender<Task>) -> Result<()> {
         let arc = Arc::clone(&self.sway_kb_layout);
         thread::Builder::new()
             .name("keyboard_layout".into())
@@ -352,24 +394,26 @@ impl KeyboardLayoutMonitor for Sway {
                                     let mut layout = arc.lock().unwrap();
                                     *layout = name;
                                 }
-                                update_request
-                                    .send(Task {
-                                        id: id.clone(),
-                                        update_time: Instant::now(),
-                                    })
-                                    .unwrap();
+                                let task = Task {
+                                    id: id.clone(),
+                                    update_time: Instant::now(),
+                                };
+                                if update_request.send(task).is_err() {
+                                    break;
+                                }
                             }
                             InputChange::XkbKeymap => {
                                 if let Some(name) = e.input.xkb_active_layout_name {
                                     let mut layout = arc.lock().unwrap();
                                     *layout = name;
                                 }
-                                update_request
-                                    .send(Task {
-                                        id: id.clone(),
-                                        update_time: Instant::now(),
-                                    })
-                                    .unwrap();
+                                let task = Task {
+                                    id: id.clone(),
+                                    update_time: Instant::now(),
+                                };
+                                if update_request.send(task).is_err() {
+                                    break;
+                                }
                             }
                             _ => {}
                         },
@@ -378,6 +422,7 @@ impl KeyboardLayoutMonitor for Sway {
                 }
             })
             .unwrap();
+        Ok(())
     }
 }
 
@@ -409,7 +454,7 @@ impl KeyboardLayoutConfig {
 
 pub struct KeyboardLayout {
     id: String,
-    output: TextWidget,
+    output: ButtonWidget,
     monitor: Box<dyn KeyboardLayoutMonitor>,
     update_interval: Option<Duration>,
     format: FormatTemplate,
@@ -424,17 +469,17 @@ impl ConfigBlock for KeyboardLayout {
             KeyboardLayoutDriver::SetXkbMap => Box::new(SetXkbMap::new()?),
             KeyboardLayoutDriver::LocaleBus => {
                 let monitor = LocaleBus::new()?;
-                monitor.monitor(id.clone(), send);
+                monitor.monitor(id.clone(), send)?;
                 Box::new(monitor)
             }
             KeyboardLayoutDriver::KbddBus => {
                 let monitor = KbdDaemonBus::new()?;
-                monitor.monitor(id.clone(), send);
+                monitor.monitor(id.clone(), send)?;
                 Box::new(monitor)
             }
             KeyboardLayoutDriver::Sway => {
                 let monitor = Sway::new(block_config.sway_kb_identifier)?;
-                monitor.monitor(id.clone(), send);
+                monitor.monitor(id.clone(), send)?;
                 Box::new(monitor)
             }
         };
@@ -444,8 +489,8 @@ impl ConfigBlock for KeyboardLayout {
             None
         };
         Ok(KeyboardLayout {
+            output: ButtonWidget::new(config, &id),
             id,
-            output: TextWidget::new(config),
             monitor,
             update_interval,
             format: FormatTemplate::from_string(&block_config.format).block_error(
@@ -473,4 +518,53 @@ impl Block for KeyboardLayout {
     fn view(&self) -> Vec<&dyn I3BarWidget> {
         vec![&self.output]
     }
+
+    /// Left click switches to the next layout and right click to the previous
+    /// one, if the driver can switch layouts.
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if !event.matches_name(&self.id) {
+            return Ok(());
+        }
+        let forward = match event.button {
+            MouseButton::Left => true,
+            MouseButton::Right => false,
+            _ => return Ok(()),
+        };
+        self.monitor.cycle_layout(forward)?;
+        if self.monitor.must_poll() {
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    fn click_safety(&self) -> ClickSafety {
+        if self.monitor.can_cycle() {
+            ClickSafety::Unsafe
+        } else {
+            ClickSafety::Safe
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_entry() {
+        let output =
+            "rules:      evdev\nmodel:      pc105\nlayout:     us,ru\nvariant:    ,phonetic\n";
+        assert_eq!(query_entry(output, "layout"), Some("us,ru"));
+        assert_eq!(query_entry(output, "variant"), Some(",phonetic"));
+        assert_eq!(query_entry(output, "options"), None);
+        assert_eq!(query_entry("layouts:    de\n", "layout"), None);
+    }
+
+    #[test]
+    fn test_rotate_list() {
+        assert_eq!(rotate_list("us,ru,de", true), "ru,de,us");
+        assert_eq!(rotate_list("us,ru,de", false), "de,us,ru");
+        assert_eq!(rotate_list(",phonetic", true), "phonetic,");
+        assert_eq!(rotate_list("us", true), "us");
+    }
 }
@@ -0,0 +1,2723 @@
+//! A block for displaying the brightness of a keyboard backlight.
+//!
+//! Keyboard backlights are LEDs in `sysfs` (`/sys/class/leds`), with the same
+//! `brightness` and `max_brightness` files as the screen backlights used by the
+//! [`Backlight`](../backlight/struct.Backlight.html) block. Changes are picked
+//! up with `inotify` as they happen. The block also updates every `interval`,
+//! which looks for unplugged LEDs again and reads the AC adapter, and at
+//! `fast_interval` for a few updates after each change, for keys that step the
+//! brightness one after another.
+//!
+//! Scrolling steps the brightness up or down, and clicks can be bound to
+//! turning it off and on, to the lowest or highest level, or to cycling
+//! through the levels, optionally ramping up or down to them for a while.
+//! Without write access to `sysfs`
+//! the new level is set through logind's `SetBrightness` call instead, which
+//! needs no extra permissions for the user of an active session.
+//!
+//! Alternatively the brightness is read and set through UPower's
+//! `KbdBacklight` interface, which signals changes. A simulated backlight,
+//! which only exists in memory, lets configurations be tried without one.
+//! They all sit behind the `Backend` trait, so the block does not care which
+//! it uses.
+//!
+//! Some laptops split their keyboard backlight into zones, separate LEDs that
+//! the block shows side by side or, if merged, as one.
+//!
+//! With `export_path` the block also writes what it shows to a file, for
+//! scripts that cannot read the LED themselves.
+//!
+//! When `on_battery_level` or `on_ac_level` is set, the block also watches the
+//! AC adapter and sets that brightness once the laptop is unplugged or plugged
+//! in, leaving it to the user meanwhile.
+//!
+//! A block hidden at zero brightness can flash up for a moment when the
+//! brightness changes, e.g. by the keys of the keyboard.
+//!
+//! External keyboards come and go. While the LED of one is gone the block
+//! shows `missing_format`, and on every update looks for an LED by the same
+//! name again.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use dbus::ffidisp::{BusType, Connection};
+use dbus::Message;
+use serde::de::{Deserialize, Deserializer};
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::led_common::{in_logind_session, percent_to_raw, raw_to_percent, LedDevice};
+use crate::blocks::Update;
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
+use crate::config::{Config, LogicalDirection, Scrolling};
+use crate::dbus_manager::{self, Bus};
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::{self, Spec, Value};
+use crate::input::{ClickAction, ClickBindings, I3BarEvent};
+use crate::scheduler::Task;
+use crate::stop::Stop;
+use crate::subprocess::spawn_child_async;
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+/// Part of the name of every keyboard backlight LED, e.g.
+/// `asus::kbd_backlight` or `tpacpi::kbd_backlight`.
+const KBD_BACKLIGHT: &str = "kbd_backlight";
+
+const LEDS: &str = "/sys/class/leds";
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// How many updates follow a change at `fast_interval`.
+const FAST_UPDATES: u32 = 5;
+
+/// How long a ramp waits between writes, at most.
+const RAMP_STEP: Duration = Duration::from_millis(25);
+
+/// Devices with up to this many levels change without a ramp, which would be
+/// little more than a delay.
+const MAX_UNRAMPED: u64 = 3;
+
+/// The range `root_scaling` is clamped to.
+const MIN_ROOT_SCALING: f64 = 0.1;
+const MAX_ROOT_SCALING: f64 = 10.0;
+
+const UPOWER: &str = "org.freedesktop.UPower";
+const UPOWER_KBD_BACKLIGHT: &str = "/org/freedesktop/UPower/KbdBacklight";
+const UPOWER_KBD_BACKLIGHT_INTERFACE: &str = "org.freedesktop.UPower.KbdBacklight";
+
+/// A block for displaying the brightness of a keyboard backlight.
+pub struct Keylight {
+    id: String,
+    /// One widget per device, or a single one if they are merged.
+    outputs: Vec<ButtonWidget>,
+    zones: Vec<Zone>,
+    merge: Option<MergeStrategy>,
+    zone_labels: BTreeMap<String, String>,
+    format: FormatTemplate,
+    /// Digits after the decimal separator of `{brightness}`.
+    precision: usize,
+    /// The width `{brightness}` is padded to, with `pad_char`.
+    pad_width: usize,
+    pad_char: char,
+    /// `None` hides devices while they are missing.
+    missing_format: Option<FormatTemplate>,
+    update_interval: Duration,
+    fast_interval: Duration,
+    /// How many more updates come at `fast_interval`.
+    fast_updates: u32,
+    /// Whether the brightness was scrolled since the last update.
+    scrolled: bool,
+    /// Steps scrolled but not written yet.
+    pending: Option<Pending>,
+    write_debounce: Duration,
+    /// Whether writing the last steps failed.
+    write_failed: bool,
+    ramp_duration: Duration,
+    ramp_curve: RampCurve,
+    /// The levels still to write of the ramp clicked off, if any.
+    ramp: Option<Ramp>,
+    /// What the widgets showed at the last update.
+    last_texts: Option<Vec<String>>,
+    /// The brightness on each widget, `None` if it shows none.
+    brightness: Vec<Option<u64>>,
+    hide_when_zero: bool,
+    /// `None` unless the block flashes on changes.
+    flash: Option<Flash>,
+    check_permissions: bool,
+    change_threshold: u64,
+    export_path: Option<PathBuf>,
+    /// What was last written to `export_path`.
+    exported: Option<String>,
+    auto_dim: Option<AutoDim>,
+    step_width: u64,
+    root_scaling: f64,
+    scrolling: Scrolling,
+    click: ClickBindings<KeylightAction>,
+    update_request: Sender<Task>,
+}
+
+/// The brightness to set when the laptop is unplugged or plugged in.
+struct AutoDim {
+    on_battery_level: Option<u64>,
+    on_ac_level: Option<u64>,
+    /// The `online` file of the AC adapter.
+    online: PathBuf,
+    /// Whether the laptop was on AC at the last update.
+    on_ac: Option<bool>,
+}
+
+/// Levels scrolled to, but not written yet.
+struct Pending {
+    /// The device scrolled, `None` for all of them.
+    device: Option<usize>,
+    /// The level of each device scrolled, by its index.
+    levels: Vec<(usize, u64)>,
+    /// When it was last scrolled.
+    since: Instant,
+}
+
+/// Shows the block for a while after the brightness changed.
+struct Flash {
+    duration: Duration,
+    until: Option<Instant>,
+}
+
+impl Flash {
+    /// Flashes from `now` on, for longer if it flashes already.
+    fn start(&mut self, now: Instant) {
+        self.until = Some(now + self.duration);
+    }
+
+    /// How much longer it flashes at `now`, `None` if it is over.
+    fn remaining(&mut self, now: Instant) -> Option<Duration> {
+        match self.until {
+            Some(until) if until > now => Some(until - now),
+            _ => {
+                self.until = None;
+                None
+            }
+        }
+    }
+
+    fn flashing(&self) -> bool {
+        self.until.is_some()
+    }
+}
+
+/// Writes of a ramp, as the time since `since` to write it at, the device and
+/// its level, in order.
+struct Ramp {
+    steps: Vec<(Duration, usize, u64)>,
+    since: Instant,
+}
+
+/// A device of the block, which may be unplugged.
+struct Zone {
+    device: Box<dyn Backend>,
+    present: bool,
+    /// Why the brightness cannot be set, if it cannot.
+    read_only: Option<String>,
+    /// The raw brightness last taken for a change, see `change_threshold`.
+    accepted: Option<u64>,
+    /// The raw brightness before it was toggled off.
+    lit: Option<u64>,
+    /// Stops watching for changes when the zone is dropped or the device
+    /// unplugged.
+    watcher: Option<Stop>,
+}
+
+/// Configuration for the [`Keylight`](./struct.Keylight.html) block.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KeylightConfig {
+    /// What the brightness is read from and written to.
+    #[serde(default = "KeylightConfig::default_backend")]
+    pub backend: KeylightBackend,
+
+    /// The LED in `/sys/class/leds/` to read brightness from, or with `*` and
+    /// `?` wildcards in it, the LEDs. Defaults to the first keyboard backlight,
+    /// or all of them if they are merged.
+    #[serde(default = "KeylightConfig::default_device")]
+    pub device: Option<String>,
+
+    /// Whether to show the devices as one.
+    #[serde(default)]
+    pub merge: bool,
+
+    /// How the brightness of merged devices is shown.
+    #[serde(default = "KeylightConfig::default_merge_strategy")]
+    pub merge_strategy: MergeStrategy,
+
+    /// Names to show devices by instead of those of their LEDs.
+    #[serde(default)]
+    pub zone_labels: BTreeMap<String, String>,
+
+    /// The brightness the simulated backend starts at, in raw units.
+    #[serde(default = "KeylightConfig::default_simulated_value")]
+    pub simulated_value: u64,
+
+    /// The highest brightness of the simulated backend, in raw units.
+    #[serde(default = "KeylightConfig::default_simulated_max")]
+    pub simulated_max: u64,
+
+    /// Format override
+    #[serde(default = "KeylightConfig::default_format")]
+    pub format: Option<String>,
+
+    /// Whether the default format ends in a percent sign.
+    #[serde(default = "KeylightConfig::default_show_percent_sign")]
+    pub show_percent_sign: bool,
+
+    /// Digits after the decimal separator of `{brightness}`.
+    #[serde(default)]
+    pub precision: usize,
+
+    /// The width to pad `{brightness}` to, on the left, in characters, so
+    /// that the block stays as wide. `0` leaves it as it is.
+    #[serde(default)]
+    pub pad_width: usize,
+
+    /// The character to pad `{brightness}` with.
+    #[serde(default = "KeylightConfig::default_pad_char")]
+    pub pad_char: char,
+
+    /// Shown while a device is unplugged. Empty hides it.
+    #[serde(default = "KeylightConfig::default_missing_format")]
+    pub missing_format: String,
+
+    /// Update interval in seconds, also how often unplugged devices are looked
+    /// for.
+    #[serde(
+        default = "KeylightConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// Whether to hide devices with a brightness of zero.
+    #[serde(default)]
+    pub hide_when_zero: bool,
+
+    /// Whether to show devices highlighted for `flash_duration` when their
+    /// brightness changes other than by scrolling, even if hidden at zero.
+    #[serde(default)]
+    pub flash_on_change: bool,
+
+    /// How long to flash for, in seconds.
+    #[serde(
+        default = "KeylightConfig::default_flash_duration",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub flash_duration: Duration,
+
+    /// How long scrolling has to pause before the brightness is written, in
+    /// milliseconds.
+    #[serde(default = "KeylightConfig::default_write_debounce_ms")]
+    pub write_debounce_ms: u64,
+
+    /// Update interval in seconds for a few updates after the brightness
+    /// changed.
+    #[serde(
+        default = "KeylightConfig::default_fast_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub fast_interval: Duration,
+
+    /// How much the raw brightness has to change to show, so that devices
+    /// jittering between levels do not keep updating the bar.
+    #[serde(default)]
+    pub change_threshold: u64,
+
+    /// A file to write the brightness to as JSON whenever it changes.
+    #[serde(default)]
+    pub export_path: Option<PathBuf>,
+
+    /// The brightness to set when the laptop is unplugged, in percent.
+    #[serde(default)]
+    pub on_battery_level: Option<u64>,
+
+    /// The brightness to set when the laptop is plugged in, in percent.
+    #[serde(default)]
+    pub on_ac_level: Option<u64>,
+
+    /// The power supply in `/sys/class/power_supply/` telling whether the
+    /// laptop is plugged in. Defaults to the first of type `Mains`.
+    #[serde(default)]
+    pub ac_device: Option<String>,
+
+    /// Whether to tell if the brightness of a device cannot be set, rather
+    /// than trying anyway, e.g. through logind.
+    #[serde(default = "KeylightConfig::default_check_permissions")]
+    pub check_permissions: bool,
+
+    /// The brightness increment when scrolling, in percent. Every step changes
+    /// the level of the device by at least one.
+    #[serde(default)]
+    pub step_width: Option<u64>,
+
+    /// The exponent relating raw brightness values to perceived brightness:
+    /// the block shows `(brightness / max) ^ (1 / root_scaling)`, and steps
+    /// by that. Clamped to 0.1 to 10.
+    #[serde(
+        default = "KeylightConfig::default_root_scaling",
+        deserialize_with = "deserialize_root_scaling"
+    )]
+    pub root_scaling: f64,
+
+    /// Actions bound to clicks, replacing the change of brightness on scroll
+    #[serde(default)]
+    pub click: ClickBindings<KeylightAction>,
+
+    /// How long clicked actions take to change the brightness, in
+    /// milliseconds. `0` changes it at once.
+    #[serde(default)]
+    pub ramp_duration_ms: u64,
+
+    /// How the brightness changes over `ramp_duration_ms`.
+    #[serde(default)]
+    pub ramp_curve: RampCurve,
+}
+
+/// How the brightness of a ramp changes over time.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RampCurve {
+    /// At the same rate.
+    #[default]
+    Linear,
+    /// Slowly at the start and end, and fastest halfway.
+    Ease,
+}
+
+/// What a click on the keylight block can do.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeylightAction {
+    /// Turns the backlight off, or back on to the level it had.
+    Toggle,
+    Max,
+    /// The lowest level that is not off.
+    Min,
+    /// Steps up by `step_width`, and from the highest level to off.
+    Cycle,
+    BrightnessUp,
+    BrightnessDown,
+}
+
+/// What the brightness of the keyboard backlight is read from and written to.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeylightBackend {
+    /// The LED in `sysfs`.
+    #[default]
+    Sysfs,
+    /// UPower's `KbdBacklight` interface.
+    Upower,
+    /// UPower if it has a keyboard backlight, `sysfs` otherwise.
+    Auto,
+    /// A keyboard backlight in memory, for trying configurations.
+    Simulated,
+}
+
+/// How the brightness of merged devices is shown.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// The average percentage.
+    #[default]
+    Avg,
+    /// The highest percentage.
+    Max,
+}
+
+impl KeylightConfig {
+    fn default_backend() -> KeylightBackend {
+        KeylightBackend::default()
+    }
+
+    fn default_merge_strategy() -> MergeStrategy {
+        MergeStrategy::default()
+    }
+
+    fn default_device() -> Option<String> {
+        None
+    }
+
+    fn default_simulated_value() -> u64 {
+        1
+    }
+
+    fn default_simulated_max() -> u64 {
+        3
+    }
+
+    fn default_format() -> Option<String> {
+        None
+    }
+
+    fn default_show_percent_sign() -> bool {
+        true
+    }
+
+    fn default_pad_char() -> char {
+        ' '
+    }
+
+    fn default_missing_format() -> String {
+        "".to_owned()
+    }
+
+    fn default_interval() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn default_fast_interval() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_write_debounce_ms() -> u64 {
+        50
+    }
+
+    fn default_flash_duration() -> Duration {
+        Duration::from_secs(2)
+    }
+
+    fn default_check_permissions() -> bool {
+        true
+    }
+
+    fn default_step_width() -> u64 {
+        5
+    }
+
+    /// Whether the block is set up to change the brightness on clicks.
+    fn interactive(&self) -> bool {
+        self.step_width.is_some() || !self.click.is_empty()
+    }
+
+    fn default_root_scaling() -> f64 {
+        1.0
+    }
+}
+
+fn deserialize_root_scaling<'de, D>(deserializer: D) -> std::result::Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let scaling = f64::deserialize(deserializer)?;
+    if scaling.is_nan() {
+        return Ok(KeylightConfig::default_root_scaling());
+    }
+    Ok(scaling.clamp(MIN_ROOT_SCALING, MAX_ROOT_SCALING))
+}
+
+impl ConfigBlock for Keylight {
+    type Config = KeylightConfig;
+
+    fn new(
+        block_config: Self::Config,
+        config: Config,
+        tx_update_request: Sender<Task>,
+    ) -> Result<Self> {
+        let sysfs = || -> Result<Vec<Box<dyn Backend>>> {
+            let leds = find_leds(
+                Path::new(LEDS),
+                block_config.device.as_deref(),
+                block_config.merge,
+            )?;
+            Ok(leds
+                .into_iter()
+                .map(|led| Box::new(led) as Box<dyn Backend>)
+                .collect())
+        };
+        let devices: Vec<Box<dyn Backend>> = match block_config.backend {
+            KeylightBackend::Sysfs => sysfs()?,
+            KeylightBackend::Upower => vec![Box::new(UPower::connect()?)],
+            KeylightBackend::Auto => match UPower::connect() {
+                Ok(upower) => vec![Box::new(upower)],
+                Err(_) => sysfs()?,
+            },
+            KeylightBackend::Simulated => vec![Box::new(Simulated::new(
+                block_config.simulated_value,
+                block_config.simulated_max,
+            )?)],
+        };
+        let mut keylight =
+            Keylight::with_devices(block_config, config, devices, tx_update_request)?;
+        for zone in &mut keylight.zones {
+            zone.watcher = zone
+                .device
+                .watch(keylight.id.clone(), keylight.update_request.clone())?;
+        }
+
+        Ok(keylight)
+    }
+}
+
+impl Keylight {
+    fn with_devices(
+        block_config: KeylightConfig,
+        config: Config,
+        devices: Vec<Box<dyn Backend>>,
+        update_request: Sender<Task>,
+    ) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        let scrolling = config.scrolling;
+        let merge = Some(block_config.merge_strategy).filter(|_| block_config.merge);
+        let widgets = if merge.is_some() { 1 } else { devices.len() };
+        let outputs = (0..widgets)
+            .map(|i| {
+                let output = ButtonWidget::new(config.clone(), &id).with_icon("keyboard");
+                match merge {
+                    Some(_) => output,
+                    None => output.with_instance(&instance(&id, devices[i].name())),
+                }
+            })
+            .collect();
+        let missing_format = match block_config.missing_format.as_str() {
+            "" => None,
+            format => Some(
+                FormatTemplate::from_string(format)
+                    .block_error("keylight", "Invalid missing_format specified")?,
+            ),
+        };
+        if let Some(ref path) = block_config.export_path {
+            let directory = match path.parent() {
+                Some(parent) if parent != Path::new("") => parent,
+                _ => Path::new("."),
+            };
+            if !directory.is_dir() {
+                return Err(ConfigurationError(
+                    "keylight".to_string(),
+                    (
+                        format!(
+                            "The directory of export_path {} does not exist",
+                            path.to_string_lossy()
+                        ),
+                        "invalid export_path".to_string(),
+                    ),
+                ));
+            }
+        }
+        let auto_dim =
+            if block_config.on_battery_level.is_some() || block_config.on_ac_level.is_some() {
+                match find_ac(Path::new(POWER_SUPPLY), block_config.ac_device.as_deref()) {
+                    Some(online) => Some(AutoDim {
+                        on_battery_level: block_config.on_battery_level,
+                        on_ac_level: block_config.on_ac_level,
+                        online,
+                        on_ac: None,
+                    }),
+                    None => {
+                        log_block_error(
+                            &id,
+                            "keylight",
+                            "No AC adapter found, on_battery_level and on_ac_level do nothing",
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+        let flash = Some(Flash {
+            duration: block_config.flash_duration,
+            until: None,
+        })
+        .filter(|_| block_config.flash_on_change);
+        // Blocks only showing the brightness do not care whether it can be set.
+        let check_permissions = block_config.check_permissions && block_config.interactive();
+        Ok(Keylight {
+            outputs,
+            id,
+            zones: devices
+                .into_iter()
+                .map(|device| Zone {
+                    read_only: device.read_only().filter(|_| check_permissions),
+                    accepted: None,
+                    lit: None,
+                    device,
+                    present: true,
+                    watcher: None,
+                })
+                .collect(),
+            merge,
+            zone_labels: block_config.zone_labels,
+            format: FormatTemplate::from_string(block_config.format.as_deref().unwrap_or(
+                if block_config.show_percent_sign {
+                    "{brightness}%"
+                } else {
+                    "{brightness}"
+                },
+            ))
+            .block_error("keylight", "Invalid format specified")?,
+            precision: block_config.precision,
+            pad_width: block_config.pad_width,
+            pad_char: block_config.pad_char,
+            missing_format,
+            update_interval: block_config.interval,
+            fast_interval: block_config.fast_interval,
+            fast_updates: 0,
+            scrolled: false,
+            pending: None,
+            write_debounce: Duration::from_millis(block_config.write_debounce_ms),
+            write_failed: false,
+            ramp_duration: Duration::from_millis(block_config.ramp_duration_ms),
+            ramp_curve: block_config.ramp_curve,
+            ramp: None,
+            last_texts: None,
+            brightness: vec![None; widgets],
+            hide_when_zero: block_config.hide_when_zero,
+            flash,
+            check_permissions,
+            change_threshold: block_config.change_threshold,
+            export_path: block_config.export_path,
+            exported: None,
+            auto_dim,
+            step_width: block_config
+                .step_width
+                .unwrap_or_else(KeylightConfig::default_step_width),
+            root_scaling: block_config.root_scaling,
+            scrolling,
+            click: block_config.click,
+            update_request,
+        })
+    }
+
+    /// Notes which devices were unplugged, and looks for those that were
+    /// again, by the names they had.
+    fn replug(&mut self) -> Result<()> {
+        let check_permissions = self.check_permissions;
+        for zone in &mut self.zones {
+            if zone.present && !zone.device.present() {
+                zone.present = false;
+                zone.watcher = None;
+            } else if !zone.present {
+                if let Some(device) = zone.device.rediscover() {
+                    zone.watcher = device.watch(self.id.clone(), self.update_request.clone())?;
+                    zone.read_only = device.read_only().filter(|_| check_permissions);
+                    zone.device = device;
+                    zone.present = true;
+                    zone.accepted = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the brightness for the power source once it changed.
+    fn auto_dim(&mut self) {
+        let auto_dim = match self.auto_dim {
+            Some(ref mut auto_dim) => auto_dim,
+            None => return,
+        };
+        let on_ac = match fs::read_to_string(&auto_dim.online) {
+            Ok(online) => online.trim() == "1",
+            Err(_) => return,
+        };
+        let changed = auto_dim.on_ac == Some(!on_ac);
+        auto_dim.on_ac = Some(on_ac);
+        let level = if on_ac {
+            auto_dim.on_ac_level
+        } else {
+            auto_dim.on_battery_level
+        };
+        if let (true, Some(level)) = (changed, level) {
+            if let Err(error) = self.set_level(level) {
+                log_block_error(&self.id, "keylight", error.parts().1);
+            }
+            self.reread();
+        }
+    }
+
+    /// Sets every device plugged in to `percent`.
+    fn set_level(&self, percent: u64) -> Result<()> {
+        for zone in self.zones.iter().filter(|zone| zone.present) {
+            if let Some(ref read_only) = zone.read_only {
+                return Err(BlockError("keylight".to_string(), read_only.clone()));
+            }
+            let max = zone.device.get_max();
+            zone.device
+                .set(percent_to_raw(percent as f64, max, self.root_scaling))?;
+        }
+        Ok(())
+    }
+
+    /// Whether the `i`th widget has nothing to show.
+    fn hidden(&self, i: usize) -> bool {
+        let missing = match self.merge {
+            Some(_) => self.zones.iter().all(|zone| !zone.present),
+            None => !self.zones[i].present,
+        };
+        let flashing = self.flash.as_ref().is_some_and(Flash::flashing);
+        (missing && self.missing_format.is_none())
+            || (self.hide_when_zero && self.brightness[i] == Some(0) && !flashing)
+    }
+
+    /// The perceived brightness of the `i`th device, as a percent, of the
+    /// last level it changed to by at least `change_threshold`.
+    fn percentage(&mut self, i: usize) -> Result<f64> {
+        if let Some(level) = self.pending_level(i) {
+            let max = self.zones[i].device.get_max();
+            return Ok(raw_to_percent(level, max, self.root_scaling));
+        }
+        let zone = &mut self.zones[i];
+        let reading = zone.device.get()?;
+        let accepted = match zone.accepted {
+            Some(accepted) if !is_change(accepted, reading, self.change_threshold) => accepted,
+            _ => reading,
+        };
+        zone.accepted = Some(accepted);
+        Ok(raw_to_percent(
+            accepted,
+            zone.device.get_max(),
+            self.root_scaling,
+        ))
+    }
+
+    /// Shows whatever the devices read next, for changes made by the block.
+    fn reread(&mut self) {
+        for zone in &mut self.zones {
+            zone.accepted = None;
+        }
+    }
+
+    /// The perceived brightness of the merged devices that are plugged in, as
+    /// a percent, `None` without any. Devices failing to tell are dropped,
+    /// with a warning, lest they break the block.
+    fn merged_percentage(&mut self, strategy: MergeStrategy) -> Option<f64> {
+        let mut percentages = Vec::new();
+        let mut failed = Vec::new();
+        for i in 0..self.zones.len() {
+            if !self.zones[i].present {
+                continue;
+            }
+            match self.percentage(i) {
+                Ok(percentage) => percentages.push(percentage),
+                Err(error) => {
+                    log_block_error(
+                        &self.id,
+                        "keylight",
+                        &format!(
+                            "{} left the merged devices: {}",
+                            self.zones[i].device.name(),
+                            error.parts().1
+                        ),
+                    );
+                    failed.push(i);
+                }
+            }
+        }
+        if !failed.is_empty() {
+            // They refer to devices by where they were.
+            self.pending = None;
+            self.ramp = None;
+        }
+        for i in failed.into_iter().rev() {
+            self.zones.remove(i);
+        }
+        merge_percentages(&percentages, strategy)
+    }
+
+    /// What the device `name` shows as.
+    fn label<'a>(&'a self, name: &'a str) -> &'a str {
+        self.zone_labels.get(name).map_or(name, String::as_str)
+    }
+
+    /// The device a click on the block is for, `None` if it hit none of them.
+    fn clicked(&self, event: &I3BarEvent) -> Option<usize> {
+        match event.instance {
+            Some(ref clicked) => (0..self.zones.len())
+                .find(|&i| instance(&self.id, self.zones[i].device.name()) == *clicked),
+            // Bars leaving out the instance can only mean a single device.
+            None if self.zones.len() == 1 => Some(0),
+            None => None,
+        }
+    }
+
+    /// The raw levels the devices at `levels` of `(current, max)` go to with
+    /// a step of `step_width` in `direction`. Merged devices step together,
+    /// from their merged percentage.
+    fn step_targets(&self, levels: &[(u64, u64)], direction: LogicalDirection) -> Vec<u64> {
+        let steps: Vec<u64> = levels
+            .iter()
+            .map(|&(current, max)| {
+                step_target(current, max, self.step_width, self.root_scaling, direction)
+                    .unwrap_or(current)
+            })
+            .collect();
+        let strategy = match self.merge {
+            Some(strategy) => strategy,
+            None => return steps,
+        };
+        let percentages: Vec<f64> = levels
+            .iter()
+            .map(|&(current, max)| raw_to_percent(current, max, self.root_scaling))
+            .collect();
+        let merged = merge_percentages(&percentages, strategy).unwrap_or_default();
+        let target = match direction {
+            LogicalDirection::Up => merged + self.step_width as f64,
+            LogicalDirection::Down => merged - self.step_width as f64,
+        };
+        let targets: Vec<u64> = levels
+            .iter()
+            .map(|&(_, max)| percent_to_raw(target, max, self.root_scaling))
+            .collect();
+        // With only a few levels the target may round to where the devices
+        // are, so then they step on their own.
+        if targets
+            .iter()
+            .zip(levels)
+            .all(|(target, level)| *target == level.0)
+        {
+            steps
+        } else {
+            targets
+        }
+    }
+
+    /// Steps the brightness of the `device`th device up or down by
+    /// `step_width`, or without `device` that of every device plugged in, at
+    /// `now`. The block shows the step right away, but only writes it once
+    /// scrolling paused for `write_debounce`, along with the steps meanwhile.
+    fn scroll(
+        &mut self,
+        direction: LogicalDirection,
+        device: Option<usize>,
+        now: Instant,
+    ) -> Result<()> {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.device != device)
+        {
+            self.flush()?;
+        }
+        let levels = match self.pending {
+            Some(ref pending) => pending.levels.clone(),
+            None => match device {
+                Some(i) => vec![(i, self.zones[i].device.get()?)],
+                None => (0..self.zones.len())
+                    .filter(|&i| self.zones[i].present)
+                    .map(|i| Ok((i, self.zones[i].device.get()?)))
+                    .collect::<Result<_>>()?,
+            },
+        };
+        let raw: Vec<(u64, u64)> = levels
+            .iter()
+            .map(|&(i, current)| (current, self.zones[i].device.get_max()))
+            .collect();
+        let targets = self.step_targets(&raw, direction);
+        self.pending = Some(Pending {
+            device,
+            levels: levels
+                .iter()
+                .zip(targets)
+                .map(|(&(i, _), target)| (i, target))
+                .collect(),
+            since: now,
+        });
+        Ok(())
+    }
+
+    /// Sets the `device`th device, or without `device` every device plugged
+    /// in, to the level `action` takes it to, at `now`. Merged devices count
+    /// as lit if any of them is, so toggling turns them all off or all back
+    /// on. With `ramp_duration` the levels are only ramped to from here on,
+    /// by the updates, except on devices with few levels.
+    fn set_action(
+        &mut self,
+        action: KeylightAction,
+        device: Option<usize>,
+        now: Instant,
+    ) -> Result<()> {
+        self.flush()?;
+        let zones: Vec<usize> = match device {
+            Some(i) => vec![i],
+            None => (0..self.zones.len())
+                .filter(|&i| self.zones[i].present)
+                .collect(),
+        };
+        let levels = zones
+            .iter()
+            .map(|&i| Ok((self.zones[i].device.get()?, self.zones[i].device.get_max())))
+            .collect::<Result<Vec<_>>>()?;
+        let lit = levels.iter().any(|&(current, _)| current > 0);
+        let at_max = levels.iter().all(|&(current, max)| current >= max);
+        let targets: Vec<u64> = match action {
+            KeylightAction::Max => levels.iter().map(|&(_, max)| max).collect(),
+            KeylightAction::Min => levels.iter().map(|&(_, max)| max.min(1)).collect(),
+            KeylightAction::Toggle if lit => vec![0; levels.len()],
+            KeylightAction::Toggle => zones
+                .iter()
+                .zip(&levels)
+                .map(|(&i, &(_, max))| {
+                    self.zones[i]
+                        .lit
+                        .filter(|&level| level > 0 && level <= max)
+                        .unwrap_or(max)
+                })
+                .collect(),
+            KeylightAction::Cycle if at_max => vec![0; levels.len()],
+            KeylightAction::Cycle | KeylightAction::BrightnessUp => {
+                self.step_targets(&levels, LogicalDirection::Up)
+            }
+            KeylightAction::BrightnessDown => self.step_targets(&levels, LogicalDirection::Down),
+        };
+        if action == KeylightAction::Toggle && lit {
+            for (&i, &(current, _)) in zones.iter().zip(&levels) {
+                self.zones[i].lit = Some(current);
+            }
+        }
+        let mut steps = Vec::new();
+        let mut written = Ok(());
+        for ((&i, &(current, max)), target) in zones.iter().zip(&levels).zip(targets) {
+            if self.ramp_duration > Duration::from_secs(0) && max > MAX_UNRAMPED {
+                steps.extend(
+                    ramp(current, target, self.ramp_duration, self.ramp_curve)
+                        .into_iter()
+                        .map(|(at, level)| (at, i, level)),
+                );
+            } else if written.is_ok() {
+                written = self.zones[i].device.set(target);
+            }
+        }
+        steps.sort_by_key(|&(at, _, _)| at);
+        if !steps.is_empty() {
+            self.ramp = Some(Ramp { steps, since: now });
+        }
+        self.reread();
+        written
+    }
+
+    /// Writes the levels of the ramp due at `now`, and tells how long until
+    /// the next ones are. `None` without any left, or if writing fails, which
+    /// ends the ramp.
+    fn advance_ramp(&mut self, now: Instant) -> Option<Duration> {
+        let ramp = self.ramp.as_mut()?;
+        let elapsed = now.saturating_duration_since(ramp.since);
+        let due = ramp
+            .steps
+            .iter()
+            .take_while(|&&(at, _, _)| at <= elapsed)
+            .count();
+        let due: Vec<(Duration, usize, u64)> = ramp.steps.drain(..due).collect();
+        let next = ramp.steps.first().map(|&(at, _, _)| at - elapsed);
+        if !due.is_empty() {
+            self.reread();
+        }
+        let written = due
+            .into_iter()
+            .try_for_each(|(_, i, level)| self.zones[i].device.set(level));
+        if let Err(error) = written {
+            log_block_error(&self.id, "keylight", error.parts().1);
+            self.ramp = None;
+            return None;
+        }
+        if next.is_none() {
+            self.ramp = None;
+        }
+        next
+    }
+
+    /// The level the `i`th device is scrolled to but not set to yet, if any.
+    fn pending_level(&self, i: usize) -> Option<u64> {
+        let pending = self.pending.as_ref()?;
+        pending
+            .levels
+            .iter()
+            .find(|&&(zone, _)| zone == i)
+            .map(|&(_, level)| level)
+    }
+
+    /// Writes the levels scrolled to. If that fails, the block shows where
+    /// the devices are instead, in the warning state.
+    fn flush(&mut self) -> Result<()> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let written = pending
+            .levels
+            .iter()
+            .try_for_each(|&(i, level)| self.zones[i].device.set(level));
+        self.reread();
+        self.write_failed = written.is_err();
+        written
+    }
+
+    /// Why the brightness shown on the `i`th widget cannot be set, if it
+    /// cannot.
+    fn read_only(&self, i: usize) -> Option<&str> {
+        let mut zones = match self.merge {
+            Some(_) => &self.zones[..],
+            None => &self.zones[i..=i],
+        }
+        .iter()
+        .filter(|zone| zone.present);
+        zones.find_map(|zone| zone.read_only.as_deref())
+    }
+
+    /// Shows `percentage` of the device `name` on the `i`th widget.
+    fn show(&mut self, i: usize, percentage: f64, name: &str) -> Result<()> {
+        let brightness = percentage.round() as u64;
+        let shown = format_brightness(percentage, self.precision, self.pad_width, self.pad_char);
+        let values = map!("{brightness}" => shown,
+                          "{bar}" => Value::bar(brightness),
+                          "{device}" => Value::from(name.to_string()));
+        let mut text = self.format.render_static_str(&values)?;
+        let state = match self.read_only(i) {
+            Some(_) => {
+                text.push_str(" ro");
+                State::Warning
+            }
+            None if self.write_failed => State::Warning,
+            None => State::Idle,
+        };
+        self.outputs[i].set_text(text);
+        self.outputs[i].set_state(state);
+        self.brightness[i] = Some(brightness);
+        Ok(())
+    }
+
+    /// Writes `percentage` along with the raw brightness of the first device
+    /// plugged in to `export_path`, if it changed. Readers never see half of
+    /// it, as it is written elsewhere first and then moved there.
+    fn export(&mut self, percentage: f64) -> Result<()> {
+        let path = match self.export_path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let zone = match self.zones.iter().find(|zone| zone.present) {
+            Some(zone) => zone,
+            None => return Ok(()),
+        };
+        let brightness = match zone.accepted {
+            Some(accepted) => accepted,
+            None => zone.device.get()?,
+        };
+        let contents = json!({
+            "brightness": brightness,
+            "max": zone.device.get_max(),
+            "percent": percentage.round() as u64,
+        })
+        .to_string()
+            + "\n";
+        if self.exported.as_ref() == Some(&contents) {
+            return Ok(());
+        }
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, &contents).block_error(
+            "keylight",
+            &format!("failed to write {}", Path::new(&tmp_path).to_string_lossy()),
+        )?;
+        fs::rename(&tmp_path, path).block_error(
+            "keylight",
+            &format!("failed to move export to {}", path.to_string_lossy()),
+        )?;
+        self.exported = Some(contents);
+        Ok(())
+    }
+
+    /// Shows the `i`th widget as missing the device `name`.
+    fn show_missing(&mut self, i: usize, name: &str) -> Result<()> {
+        if let Some(ref format) = self.missing_format {
+            let values = map!("{device}" => Value::from(name.to_string()));
+            let text = format.render_static_str(&values)?;
+            self.outputs[i].set_text(text);
+        }
+        self.brightness[i] = None;
+        Ok(())
+    }
+}
+
+/// The LEDs in `leds` to show for the `device` option: every one matching it
+/// if it has wildcards, otherwise the one it names or else the first one whose
+/// name contains it. Without `device`, the first keyboard backlight, or all of
+/// them if they are merged.
+fn find_leds(leds: &Path, device: Option<&str>, merge: bool) -> Result<Vec<LedDevice>> {
+    let glob = match device {
+        Some(device) if device.contains(['*', '?']) => device.to_string(),
+        None if merge => format!("*{}*", KBD_BACKLIGHT),
+        _ => {
+            let led = LedDevice::discover_in(leds, Some(device.unwrap_or(KBD_BACKLIGHT)))?;
+            return Ok(vec![led]);
+        }
+    };
+    let found = LedDevice::find_devices(&leds.join(&glob).to_string_lossy())?;
+    if found.is_empty() {
+        return Err(BlockError(
+            "keylight".to_string(),
+            format!("No device matching '{}' found ({})", glob, leds.display()),
+        ));
+    }
+    Ok(found)
+}
+
+/// The `online` file of the AC adapter `name` in `power_supply`, or without
+/// `name` that of the first power supply of type `Mains`.
+fn find_ac(power_supply: &Path, name: Option<&str>) -> Option<PathBuf> {
+    if let Some(name) = name {
+        let online = power_supply.join(name).join("online");
+        return Some(online).filter(|online| online.exists());
+    }
+    let mut supplies: Vec<PathBuf> = fs::read_dir(power_supply)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    supplies.sort();
+    supplies
+        .into_iter()
+        .find(|supply| {
+            fs::read_to_string(supply.join("type")).is_ok_and(|kind| kind.trim() == "Mains")
+        })
+        .map(|supply| supply.join("online"))
+        .filter(|online| online.exists())
+}
+
+/// How long until the next update, and how many of the updates after it come
+/// at `fast_interval`, `fast_updates` of which were left before this one. A
+/// `changed` brightness or one just `scrolled` makes a few updates fast.
+fn next_interval(
+    fast_updates: u32,
+    changed: bool,
+    scrolled: bool,
+    interval: Duration,
+    fast_interval: Duration,
+) -> (Duration, u32) {
+    let fast_updates = if changed || scrolled {
+        FAST_UPDATES
+    } else {
+        fast_updates.saturating_sub(1)
+    };
+    match fast_updates {
+        0 => (interval, 0),
+        _ => (fast_interval, fast_updates),
+    }
+}
+
+/// Whether the raw brightness `reading` changed from the last level
+/// `accepted` by at least `threshold`.
+fn is_change(accepted: u64, reading: u64, threshold: u64) -> bool {
+    accepted.abs_diff(reading) >= threshold.max(1)
+}
+
+/// `percentage` with `precision` digits after the decimal separator. Padded
+/// on the left with `pad_char` to `pad_width` characters it is text, which the
+/// flags of the placeholder no longer pad.
+fn format_brightness(percentage: f64, precision: usize, pad_width: usize, pad_char: char) -> Value {
+    let value = match precision {
+        0 => Value::integer(percentage.round() as u64),
+        precision => Value::float(percentage, precision),
+    };
+    if pad_width == 0 {
+        return value;
+    }
+    let number = formatting::render(&value, Spec::default());
+    let padding = pad_width.saturating_sub(number.chars().count());
+    Value::from(
+        std::iter::repeat_n(pad_char, padding)
+            .chain(number.chars())
+            .collect::<String>(),
+    )
+}
+
+/// The levels, and when to write them, to ramp from `from` to `to` over
+/// `duration` along `curve`. Writes come every `RAMP_STEP`, or less often to
+/// change by at least a level each, and the last one is `to` at `duration`.
+fn ramp(from: u64, to: u64, duration: Duration, curve: RampCurve) -> Vec<(Duration, u64)> {
+    if from == to {
+        return Vec::new();
+    }
+    let steps =
+        (duration.as_nanos() / RAMP_STEP.as_nanos()).clamp(1, u128::from(from.abs_diff(to))) as u32;
+    (1..=steps)
+        .map(|step| {
+            let t = f64::from(step) / f64::from(steps);
+            let progress = match curve {
+                RampCurve::Linear => t,
+                RampCurve::Ease => t * t * (3.0 - 2.0 * t),
+            };
+            let level = from as f64 + (to as f64 - from as f64) * progress;
+            (duration * step / steps, level.round() as u64)
+        })
+        .collect()
+}
+
+/// The instance of the widget showing the device `name` of the block `id`.
+fn instance(id: &str, name: &str) -> String {
+    format!("{}:{}", id, name)
+}
+
+/// The brightness merged devices show as, `None` without devices.
+fn merge_percentages(percentages: &[f64], strategy: MergeStrategy) -> Option<f64> {
+    if percentages.is_empty() {
+        return None;
+    }
+    Some(match strategy {
+        MergeStrategy::Avg => percentages.iter().sum::<f64>() / percentages.len() as f64,
+        MergeStrategy::Max => percentages.iter().cloned().fold(0.0, f64::max),
+    })
+}
+
+/// Where the brightness of a keyboard backlight is read from and written to.
+trait Backend {
+    /// The name of the device, for the `{device}` placeholder.
+    fn name(&self) -> &str;
+
+    /// The current raw brightness, never above `get_max`.
+    fn get(&self) -> Result<u64>;
+
+    /// The highest raw brightness.
+    fn get_max(&self) -> u64;
+
+    /// Sets the raw brightness, clamped to `get_max`.
+    fn set(&self, raw: u64) -> Result<()>;
+
+    /// Requests an update of the block `id` whenever the brightness changes.
+    /// Backends watching in a thread of their own return the `Stop` for it.
+    fn watch(&self, id: String, update_request: Sender<Task>) -> Result<Option<Stop>>;
+
+    /// Whether the device is plugged in.
+    fn present(&self) -> bool {
+        true
+    }
+
+    /// Why the brightness cannot be set, `None` if it can or there is no
+    /// telling.
+    fn read_only(&self) -> Option<String> {
+        None
+    }
+
+    /// The device plugged in again after it was unplugged, `None` while it is
+    /// not.
+    fn rediscover(&self) -> Option<Box<dyn Backend>> {
+        None
+    }
+}
+
+impl Backend for LedDevice {
+    fn name(&self) -> &str {
+        LedDevice::name(self)
+    }
+
+    fn get(&self) -> Result<u64> {
+        self.brightness()
+    }
+
+    fn get_max(&self) -> u64 {
+        self.max_brightness()
+    }
+
+    fn set(&self, raw: u64) -> Result<()> {
+        self.set_brightness(raw)
+    }
+
+    fn watch(&self, id: String, update_request: Sender<Task>) -> Result<Option<Stop>> {
+        self.watch_updates("keylight", id, update_request).map(Some)
+    }
+
+    fn present(&self) -> bool {
+        self.brightness_file().exists()
+    }
+
+    fn read_only(&self) -> Option<String> {
+        // logind sets the brightness when writing it fails.
+        if self.writable() || in_logind_session() {
+            return None;
+        }
+        Some(format!(
+            "Cannot write to {}, nor set it through logind outside of a session. Add \
+             yourself to a group allowed to write it, e.g. by a udev rule like \
+             ACTION==\"add\", SUBSYSTEM==\"leds\", KERNEL==\"{}\", \
+             RUN+=\"/bin/chgrp input /sys/class/leds/%k/brightness\", \
+             RUN+=\"/bin/chmod g+w /sys/class/leds/%k/brightness\"",
+            self.writable_file().display(),
+            self.name()
+        ))
+    }
+
+    fn rediscover(&self) -> Option<Box<dyn Backend>> {
+        match LedDevice::new(self.device_path()) {
+            Ok(device) if Backend::present(&device) => Some(Box::new(device)),
+            _ => None,
+        }
+    }
+}
+
+/// The keyboard backlight of UPower, which works without permissions on
+/// `sysfs` and signals changes.
+struct UPower {
+    con: Connection,
+    max: u64,
+}
+
+impl UPower {
+    /// Fails if UPower is not running or has no keyboard backlight.
+    fn connect() -> Result<Self> {
+        let con = Connection::get_private(BusType::System)
+            .block_error("keylight", "Failed to establish D-Bus connection")?;
+        let mut upower = UPower { con, max: 0 };
+        upower.max = upower.call("GetMaxBrightness", None)?;
+        if upower.max == 0 {
+            return Err(BlockError(
+                "keylight".to_string(),
+                "UPower's keyboard backlight has no brightness range".to_string(),
+            ));
+        }
+        Ok(upower)
+    }
+
+    /// Calls `method` of the keyboard backlight, with the brightness `value`
+    /// if given, and returns the brightness it replies with, if any.
+    fn call(&self, method: &str, value: Option<i32>) -> Result<u64> {
+        let mut msg = Message::new_method_call(
+            UPOWER,
+            UPOWER_KBD_BACKLIGHT,
+            UPOWER_KBD_BACKLIGHT_INTERFACE,
+            method,
+        )
+        .block_error("keylight", "Failed to create D-Bus message")?;
+        if let Some(value) = value {
+            msg = msg.append1(value);
+        }
+        let reply = self
+            .con
+            .send_with_reply_and_block(msg, 1000)
+            .block_error("keylight", &format!("UPower's {} failed", method))?;
+        Ok(reply.get1::<i32>().unwrap_or(0).max(0) as u64)
+    }
+}
+
+/// A keyboard backlight that only exists in memory.
+struct Simulated {
+    brightness: Cell<u64>,
+    max: u64,
+}
+
+impl Simulated {
+    fn new(brightness: u64, max: u64) -> Result<Self> {
+        if max == 0 || brightness > max {
+            return Err(ConfigurationError(
+                "keylight".to_string(),
+                (
+                    "simulated_value must be at most simulated_max, which must not be 0"
+                        .to_string(),
+                    "invalid simulated backlight".to_string(),
+                ),
+            ));
+        }
+        Ok(Simulated {
+            brightness: Cell::new(brightness),
+            max,
+        })
+    }
+}
+
+impl Backend for Simulated {
+    fn name(&self) -> &str {
+        "simulated"
+    }
+
+    fn get(&self) -> Result<u64> {
+        Ok(self.brightness.get())
+    }
+
+    fn get_max(&self) -> u64 {
+        self.max
+    }
+
+    fn set(&self, raw: u64) -> Result<()> {
+        self.brightness.set(raw.min(self.max));
+        Ok(())
+    }
+
+    fn watch(&self, _: String, _: Sender<Task>) -> Result<Option<Stop>> {
+        Ok(None)
+    }
+}
+
+impl Backend for UPower {
+    fn name(&self) -> &str {
+        "upower"
+    }
+
+    fn get(&self) -> Result<u64> {
+        Ok(self.call("GetBrightness", None)?.min(self.max))
+    }
+
+    fn get_max(&self) -> u64 {
+        self.max
+    }
+
+    fn set(&self, raw: u64) -> Result<()> {
+        self.call("SetBrightness", Some(raw.min(self.max) as i32))
+            .map(|_| ())
+    }
+
+    fn watch(&self, id: String, update_request: Sender<Task>) -> Result<Option<Stop>> {
+        dbus_manager::request_updates(
+            Bus::System,
+            "type='signal',\
+             path='/org/freedesktop/UPower/KbdBacklight',\
+             interface='org.freedesktop.UPower.KbdBacklight',\
+             member='BrightnessChanged'",
+            id,
+            update_request,
+        )
+        .block_error("keylight", "Failed to add D-Bus match rule")?;
+        Ok(None)
+    }
+}
+
+/// The raw level a step of `step_width` perceived percent in `direction` goes
+/// to from `current`, `None` at either end. Keyboard backlights often have only
+/// a few levels, and rounding could keep the low end of steep curves stuck, so
+/// a step is never smaller than one level.
+fn step_target(
+    current: u64,
+    max: u64,
+    step_width: u64,
+    scaling: f64,
+    direction: LogicalDirection,
+) -> Option<u64> {
+    let percent = raw_to_percent(current, max, scaling);
+    match direction {
+        LogicalDirection::Up if current < max => {
+            Some(percent_to_raw(percent + step_width as f64, max, scaling).max(current + 1))
+        }
+        LogicalDirection::Down if current > 0 => {
+            Some(percent_to_raw(percent - step_width as f64, max, scaling).min(current - 1))
+        }
+        _ => None,
+    }
+}
+
+impl Keylight {
+    fn update_at(&mut self, now: Instant) -> Result<Option<Update>> {
+        self.replug()?;
+        let ramping = self.ramp.is_some();
+        let mut write_in = self.advance_ramp(now);
+        if let Some(ref pending) = self.pending {
+            let scrolling = now.saturating_duration_since(pending.since);
+            if scrolling >= self.write_debounce {
+                if let Err(error) = self.flush() {
+                    log_block_error(&self.id, "keylight", error.parts().1);
+                }
+            } else {
+                let wait = self.write_debounce - scrolling;
+                write_in = Some(write_in.map_or(wait, |ramp: Duration| ramp.min(wait)));
+            }
+        }
+        self.auto_dim();
+        match self.merge {
+            Some(strategy) => {
+                let names: Vec<&str> = self
+                    .zones
+                    .iter()
+                    .map(|zone| self.label(zone.device.name()))
+                    .collect();
+                let names = names.join(",");
+                match self.merged_percentage(strategy) {
+                    Some(percentage) => {
+                        self.show(0, percentage, &names)?;
+                        self.export(percentage)?;
+                    }
+                    None if self.zones.is_empty() => {
+                        return Err(BlockError(
+                            "keylight".to_string(),
+                            "No devices left to merge".to_string(),
+                        ))
+                    }
+                    None => self.show_missing(0, &names)?,
+                }
+            }
+            None => {
+                let mut first = None;
+                for i in 0..self.zones.len() {
+                    let name = self.label(self.zones[i].device.name()).to_string();
+                    if self.zones[i].present {
+                        let percentage = self.percentage(i)?;
+                        self.show(i, percentage, &name)?;
+                        first = first.or(Some(percentage));
+                    } else {
+                        self.show_missing(i, &name)?;
+                    }
+                }
+                if let Some(percentage) = first {
+                    self.export(percentage)?;
+                }
+            }
+        }
+        let texts: Vec<String> = self
+            .outputs
+            .iter()
+            .map(|output| output.widget_state().text)
+            .collect();
+        let changed = self.last_texts.as_ref().is_some_and(|last| *last != texts);
+        self.last_texts = Some(texts);
+        let (interval, fast_updates) = next_interval(
+            self.fast_updates,
+            changed,
+            self.scrolled,
+            self.update_interval,
+            self.fast_interval,
+        );
+        self.fast_updates = fast_updates;
+        // Ramps are clicked off on the block, like scrolls.
+        let scrolled = self.scrolled || ramping;
+        self.scrolled = false;
+
+        let mut next = write_in.map_or(interval, |write_in| write_in.min(interval));
+        let flash = match self.flash {
+            Some(ref mut flash) => flash,
+            None => return Ok(Some(next.into())),
+        };
+        // Scrolling shows what it does on the block already.
+        if changed && !scrolled {
+            flash.start(now);
+        }
+        if let Some(remaining) = flash.remaining(now) {
+            next = next.min(remaining);
+            for output in &mut self.outputs {
+                if output.widget_state().state == State::Idle {
+                    output.set_state(State::Info);
+                }
+            }
+        }
+        Ok(Some(next.into()))
+    }
+}
+
+impl Block for Keylight {
+    fn update(&mut self) -> Result<Option<Update>> {
+        self.update_at(Instant::now())
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        self.outputs
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !self.hidden(i))
+            .map(|(_, output)| output as &dyn I3BarWidget)
+            .collect()
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if !event.matches_name(&self.id) {
+            return Ok(());
+        }
+        let action = match self.click.get(event).cloned() {
+            Some(ClickAction::Block(action)) => action,
+            Some(ClickAction::Command { cmd }) => {
+                spawn_child_async("sh", &["-c", &cmd])
+                    .block_error("keylight", "could not spawn child")?;
+                return Ok(());
+            }
+            None => match self.scrolling.to_logical_direction(event.button) {
+                Some(LogicalDirection::Up) => KeylightAction::BrightnessUp,
+                Some(LogicalDirection::Down) => KeylightAction::BrightnessDown,
+                None => return Ok(()),
+            },
+        };
+        // Anything clicked starts over from where the devices are.
+        self.ramp = None;
+        self.replug()?;
+        let device = match self.merge {
+            Some(_) if self.zones.iter().any(|zone| zone.present) => None,
+            None => match self.clicked(event) {
+                Some(device) if self.zones[device].present => Some(device),
+                _ => return Ok(()),
+            },
+            // Clicking on what is missing does nothing.
+            _ => return Ok(()),
+        };
+        if let Some(read_only) = self.read_only(device.unwrap_or(0)) {
+            return Err(BlockError("keylight".to_string(), read_only.to_string()));
+        }
+        match action {
+            KeylightAction::BrightnessUp => {
+                self.scroll(LogicalDirection::Up, device, Instant::now())?
+            }
+            KeylightAction::BrightnessDown => {
+                self.scroll(LogicalDirection::Down, device, Instant::now())?
+            }
+            action => self.set_action(action, device, Instant::now())?,
+        }
+        self.scrolled = true;
+        // Not every backend tells about the change.
+        self.update_request
+            .send(Task {
+                id: self.id.clone(),
+                update_time: Instant::now(),
+            })
+            .ok();
+        Ok(())
+    }
+
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+    use crate::input::{DoubleClicks, MouseButton};
+
+    fn keylight(dir: &TempDir, brightness: u64, max: u64, options: &str) -> Keylight {
+        let path = dir.path().join("tpacpi::kbd_backlight");
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("brightness"), format!("{}\n", brightness)).unwrap();
+        fs::write(path.join("max_brightness"), format!("{}\n", max)).unwrap();
+        let device: Box<dyn Backend> = Box::new(LedDevice::new(path).unwrap());
+        let options = toml::from_str(options).unwrap();
+        let (update_request, _) = crossbeam_channel::unbounded();
+        Keylight::with_devices(options, Config::default(), vec![device], update_request).unwrap()
+    }
+
+    #[test]
+    fn test_percentage() {
+        let dir = TempDir::new().unwrap();
+        let mut block = keylight(&dir, 1, 3, "");
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "33%");
+
+        let mut block = keylight(&dir, 2, 2, "");
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "100%");
+    }
+
+    #[test]
+    fn test_format() {
+        let dir = TempDir::new().unwrap();
+        let mut block = keylight(&dir, 0, 2, "format = \"{device} {brightness}\"");
+        block.update().unwrap();
+        assert_eq!(
+            block.outputs[0].widget_state().text,
+            "tpacpi::kbd_backlight 0"
+        );
+    }
+
+    #[test]
+    fn test_scroll_steps() {
+        let dir = TempDir::new().unwrap();
+        let brightness = dir.path().join("tpacpi::kbd_backlight/brightness");
+        let scroll = |block: &mut Keylight, button| {
+            block
+                .click(&I3BarEvent {
+                    name: Some(block.id.clone()),
+                    instance: None,
+                    x: 0,
+                    y: 0,
+                    button,
+                    modifiers: Vec::new(),
+                    double_click: false,
+                })
+                .unwrap();
+            block.flush().unwrap();
+            fs::read_to_string(&brightness).unwrap()
+        };
+
+        // With only three levels, 5% still moves by one level.
+        let mut block = keylight(&dir, 1, 3, "");
+        assert_eq!(scroll(&mut block, MouseButton::WheelUp), "2");
+        assert_eq!(scroll(&mut block, MouseButton::WheelUp), "3");
+        assert_eq!(scroll(&mut block, MouseButton::WheelUp), "3");
+        assert_eq!(scroll(&mut block, MouseButton::WheelDown), "2");
+
+        let mut block = keylight(&dir, 50, 100, "step_width = 20");
+        assert_eq!(scroll(&mut block, MouseButton::WheelDown), "30");
+        assert_eq!(scroll(&mut block, MouseButton::Left), "30");
+        assert_eq!(scroll(&mut block, MouseButton::WheelDown), "10");
+        assert_eq!(scroll(&mut block, MouseButton::WheelDown), "0");
+    }
+
+    #[test]
+    fn test_simulated() {
+        let mut block = Keylight::with_devices(
+            toml::from_str("step_width = 20").unwrap(),
+            Config::default(),
+            vec![Box::new(Simulated::new(5, 10).unwrap())],
+            crossbeam_channel::unbounded().0,
+        )
+        .unwrap();
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "50%");
+
+        block
+            .scroll(LogicalDirection::Up, None, Instant::now())
+            .unwrap();
+        block.flush().unwrap();
+        assert_eq!(block.zones[0].device.get().unwrap(), 7);
+        block.zones[0].device.set(11).unwrap();
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "100%");
+
+        assert!(Simulated::new(4, 3).is_err());
+        assert!(Simulated::new(0, 0).is_err());
+    }
+
+    #[test]
+    fn test_root_scaling() {
+        let dir = TempDir::new().unwrap();
+        let mut block = keylight(&dir, 25, 100, "root_scaling = 2");
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "50%");
+
+        let clamped = |scaling: &str| -> f64 {
+            let config: KeylightConfig =
+                toml::from_str(&format!("root_scaling = {}", scaling)).unwrap();
+            config.root_scaling
+        };
+        assert_eq!(clamped("0"), MIN_ROOT_SCALING);
+        assert_eq!(clamped("2.5"), 2.5);
+        assert_eq!(clamped("100"), MAX_ROOT_SCALING);
+    }
+
+    fn zones(dir: &TempDir, zones: &[(&str, u64, u64)]) {
+        for &(name, brightness, max) in zones {
+            let path = dir.path().join(name);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("brightness"), format!("{}\n", brightness)).unwrap();
+            fs::write(path.join("max_brightness"), format!("{}\n", max)).unwrap();
+        }
+    }
+
+    fn zoned_keylight(dir: &TempDir, options: &str) -> Keylight {
+        let options: KeylightConfig = toml::from_str(options).unwrap();
+        let devices = find_leds(dir.path(), options.device.as_deref(), options.merge)
+            .unwrap()
+            .into_iter()
+            .map(|led| Box::new(led) as Box<dyn Backend>)
+            .collect();
+        let (update_request, _) = crossbeam_channel::unbounded();
+        Keylight::with_devices(options, Config::default(), devices, update_request).unwrap()
+    }
+
+    #[test]
+    fn test_merge_percentages() {
+        assert_eq!(merge_percentages(&[], MergeStrategy::Avg), None);
+        assert_eq!(merge_percentages(&[], MergeStrategy::Max), None);
+        assert_eq!(
+            merge_percentages(&[20.0, 50.0], MergeStrategy::Avg),
+            Some(35.0)
+        );
+        assert_eq!(
+            merge_percentages(&[20.0, 50.0], MergeStrategy::Max),
+            Some(50.0)
+        );
+    }
+
+    #[test]
+    fn test_zones() {
+        let dir = TempDir::new().unwrap();
+        // Zones of the same keyboard with different maximum levels.
+        zones(
+            &dir,
+            &[
+                ("rgb:kbd_backlight_1", 1, 2),
+                ("rgb:kbd_backlight_2", 100, 100),
+                ("input3::capslock", 1, 1),
+            ],
+        );
+
+        let mut block = zoned_keylight(&dir, "merge = true");
+        block.update().unwrap();
+        assert_eq!(block.outputs.len(), 1);
+        assert_eq!(block.outputs[0].widget_state().text, "75%");
+
+        let mut block = zoned_keylight(
+            &dir,
+            "merge = true\nmerge_strategy = \"max\"\nformat = \"{device} {brightness}\"",
+        );
+        block.update().unwrap();
+        assert_eq!(
+            block.outputs[0].widget_state().text,
+            "rgb:kbd_backlight_1,rgb:kbd_backlight_2 100"
+        );
+
+        let mut block = zoned_keylight(&dir, "device = \"rgb:kbd_backlight_?\"");
+        block.update().unwrap();
+        let texts: Vec<_> = block
+            .outputs
+            .iter()
+            .map(|output| output.widget_state().text)
+            .collect();
+        assert_eq!(texts, ["50%", "100%"]);
+
+        // Without merging, only the first keyboard backlight.
+        let mut block = zoned_keylight(&dir, "");
+        block.update().unwrap();
+        assert_eq!(block.outputs.len(), 1);
+        assert_eq!(block.outputs[0].widget_state().text, "50%");
+
+        assert!(find_leds(dir.path(), Some("nothing*"), false).is_err());
+    }
+
+    #[test]
+    fn test_merged_steps() {
+        let dir = TempDir::new().unwrap();
+        zones(
+            &dir,
+            &[("a::kbd_backlight", 1, 2), ("b::kbd_backlight", 50, 100)],
+        );
+        let read =
+            |name: &str| fs::read_to_string(dir.path().join(name).join("brightness")).unwrap();
+
+        // From 50% to 75%, which the first zone cannot show, so it rounds up.
+        let mut block = zoned_keylight(&dir, "merge = true\nstep_width = 25");
+        block
+            .scroll(LogicalDirection::Up, None, Instant::now())
+            .unwrap();
+        block.flush().unwrap();
+        assert_eq!(read("a::kbd_backlight"), "2");
+        assert_eq!(read("b::kbd_backlight"), "75");
+        block
+            .scroll(LogicalDirection::Down, None, Instant::now())
+            .unwrap();
+        block.flush().unwrap();
+        assert_eq!(read("a::kbd_backlight"), "1");
+        assert_eq!(read("b::kbd_backlight"), "63");
+
+        // A zone that is unplugged is left out for as long as it is gone.
+        fs::remove_dir_all(dir.path().join("a::kbd_backlight")).unwrap();
+        block.update().unwrap();
+        assert_eq!(block.zones.len(), 2);
+        assert_eq!(block.outputs[0].widget_state().text, "63%");
+
+        // One that fails is dropped, the rest still show.
+        zones(&dir, &[("a::kbd_backlight", 0, 2)]);
+        fs::write(dir.path().join("b::kbd_backlight/brightness"), "broken").unwrap();
+        block.update().unwrap();
+        assert_eq!(block.zones.len(), 1);
+        assert_eq!(block.outputs[0].widget_state().text, "0%");
+    }
+
+    #[test]
+    fn test_unplugged() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("tpacpi::kbd_backlight");
+        let scroll = |block: &mut Keylight| {
+            block.click(&I3BarEvent {
+                name: Some(block.id.clone()),
+                instance: None,
+                x: 0,
+                y: 0,
+                button: MouseButton::WheelUp,
+                modifiers: Vec::new(),
+                double_click: false,
+            })
+        };
+
+        let mut block = keylight(&dir, 1, 3, "missing_format = \"{device} gone\"");
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "33%");
+
+        fs::remove_dir_all(&path).unwrap();
+        block.update().unwrap();
+        assert_eq!(block.view().len(), 1);
+        assert_eq!(
+            block.outputs[0].widget_state().text,
+            "tpacpi::kbd_backlight gone"
+        );
+        scroll(&mut block).unwrap();
+        assert!(!path.exists());
+
+        // Plugged in again, with the same name.
+        zones(&dir, &[("tpacpi::kbd_backlight", 2, 2)]);
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "100%");
+
+        // Hidden while gone by default.
+        let mut block = keylight(&dir, 1, 3, "");
+        fs::remove_dir_all(&path).unwrap();
+        assert!(scroll(&mut block).is_ok());
+        block.update().unwrap();
+        assert!(block.view().is_empty());
+        zones(&dir, &[("tpacpi::kbd_backlight", 1, 3)]);
+        block.update().unwrap();
+        assert_eq!(block.view().len(), 1);
+    }
+
+    #[test]
+    fn test_zone_clicks() {
+        let dir = TempDir::new().unwrap();
+        let names = [
+            "rgb:kbd_backlight_1",
+            "rgb:kbd_backlight_2",
+            "rgb:kbd_backlight_3",
+        ];
+        zones(
+            &dir,
+            &[
+                (names[0], 50, 100),
+                (names[1], 50, 100),
+                (names[2], 50, 100),
+            ],
+        );
+        let mut block = zoned_keylight(
+            &dir,
+            "device = \"rgb:kbd_backlight_*\"\nstep_width = 10\n[zone_labels]\n\"rgb:kbd_backlight_2\" = \"WASD\"",
+        );
+        let id = block.id.clone();
+        let mut scroll = |name: &str, instance: Option<String>| {
+            block
+                .click(&I3BarEvent {
+                    name: Some(name.to_string()),
+                    instance,
+                    x: 0,
+                    y: 0,
+                    button: MouseButton::WheelUp,
+                    modifiers: Vec::new(),
+                    double_click: false,
+                })
+                .unwrap();
+            block.flush().unwrap();
+            names
+                .iter()
+                .map(|name| {
+                    let path = dir.path().join(name).join("brightness");
+                    fs::read_to_string(path).unwrap().trim().to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            scroll(&id, Some(format!("{}:rgb:kbd_backlight_2", id))),
+            ["50", "60", "50"]
+        );
+        assert_eq!(
+            scroll(&id, Some(format!("{}:rgb:kbd_backlight_3", id))),
+            ["50", "60", "60"]
+        );
+        // Neither other instances, other blocks nor no instance at all with
+        // several devices mean any of them.
+        assert_eq!(
+            scroll(&id, Some(format!("{}:other", id))),
+            ["50", "60", "60"]
+        );
+        assert_eq!(scroll(&id, None), ["50", "60", "60"]);
+        assert_eq!(
+            scroll("other", Some(format!("{}:rgb:kbd_backlight_1", id))),
+            ["50", "60", "60"]
+        );
+
+        block.update().unwrap();
+        let rendered = block.outputs[1].get_rendered();
+        assert_eq!(rendered["instance"], format!("{}:rgb:kbd_backlight_2", id));
+        assert_eq!(block.outputs[0].widget_state().text, "50%");
+
+        let mut block = zoned_keylight(
+            &dir,
+            "device = \"rgb:kbd_backlight_2\"\nformat = \"{device} {brightness}%\"\n[zone_labels]\n\"rgb:kbd_backlight_2\" = \"WASD\"",
+        );
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "WASD 60%");
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = TempDir::new().unwrap();
+        let mut block = keylight(&dir, 1, 3, "");
+        block.zones[0].read_only = Some("Cannot write".to_string());
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "33% ro");
+        assert_eq!(block.outputs[0].widget_state().state, State::Warning);
+
+        let event = I3BarEvent {
+            name: Some(block.id.clone()),
+            instance: None,
+            x: 0,
+            y: 0,
+            button: MouseButton::WheelUp,
+            modifiers: Vec::new(),
+            double_click: false,
+        };
+        let error = block.click(&event).unwrap_err();
+        assert_eq!(error.parts().1, "Cannot write");
+        let brightness = dir.path().join("tpacpi::kbd_backlight/brightness");
+        assert_eq!(fs::read_to_string(brightness).unwrap().trim(), "1");
+
+        block.zones[0].read_only = None;
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "33%");
+        assert_eq!(block.outputs[0].widget_state().state, State::Idle);
+    }
+
+    #[test]
+    fn test_export() {
+        let dir = TempDir::new().unwrap();
+        let export = dir.path().join("keylight.json");
+        let options = format!("export_path = {:?}", export);
+        let mut block = keylight(&dir, 2, 3, &options);
+        block.update().unwrap();
+        assert_eq!(
+            fs::read_to_string(&export).unwrap(),
+            "{\"brightness\":2,\"max\":3,\"percent\":67}\n"
+        );
+
+        // Unchanged values are not written again.
+        fs::remove_file(&export).unwrap();
+        block.update().unwrap();
+        assert!(!export.exists());
+        block.zones[0].device.set(3).unwrap();
+        block.update().unwrap();
+        assert_eq!(
+            fs::read_to_string(&export).unwrap(),
+            "{\"brightness\":3,\"max\":3,\"percent\":100}\n"
+        );
+        assert!(!dir.path().join("keylight.json.tmp").exists());
+
+        let options: KeylightConfig = toml::from_str(&format!(
+            "export_path = {:?}",
+            dir.path().join("missing/keylight.json")
+        ))
+        .unwrap();
+        let device: Box<dyn Backend> = Box::new(Simulated::new(1, 3).unwrap());
+        let (update_request, _) = crossbeam_channel::unbounded();
+        assert!(
+            Keylight::with_devices(options, Config::default(), vec![device], update_request)
+                .is_err()
+        );
+    }
+
+    fn power_supply(dir: &TempDir, name: &str, kind: &str, online: bool) {
+        let path = dir.path().join("power_supply").join(name);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("type"), format!("{}\n", kind)).unwrap();
+        fs::write(path.join("online"), if online { "1\n" } else { "0\n" }).unwrap();
+    }
+
+    #[test]
+    fn test_find_ac() {
+        let dir = TempDir::new().unwrap();
+        let supplies = dir.path().join("power_supply");
+        power_supply(&dir, "BAT0", "Battery", true);
+        assert_eq!(find_ac(&supplies, None), None);
+
+        power_supply(&dir, "AC", "Mains", true);
+        power_supply(&dir, "ADP1", "Mains", true);
+        assert_eq!(find_ac(&supplies, None), Some(supplies.join("AC/online")));
+        assert_eq!(
+            find_ac(&supplies, Some("ADP1")),
+            Some(supplies.join("ADP1/online"))
+        );
+        assert_eq!(find_ac(&supplies, Some("ADP2")), None);
+        assert_eq!(find_ac(&dir.path().join("missing"), None), None);
+    }
+
+    #[test]
+    fn test_auto_dim() {
+        let dir = TempDir::new().unwrap();
+        let brightness = dir.path().join("tpacpi::kbd_backlight/brightness");
+        let level = || fs::read_to_string(&brightness).unwrap().trim().to_string();
+        power_supply(&dir, "AC", "Mains", true);
+        let mut block = keylight(&dir, 50, 100, "");
+        block.auto_dim = Some(AutoDim {
+            on_battery_level: Some(10),
+            on_ac_level: Some(80),
+            online: find_ac(&dir.path().join("power_supply"), None).unwrap(),
+            on_ac: None,
+        });
+
+        // Nothing is set on start.
+        block.update().unwrap();
+        assert_eq!(level(), "50");
+
+        power_supply(&dir, "AC", "Mains", false);
+        block.update().unwrap();
+        assert_eq!(level(), "10");
+        assert_eq!(block.outputs[0].widget_state().text, "10%");
+
+        // Changes meanwhile are left alone.
+        block.zones[0].device.set(30).unwrap();
+        block.update().unwrap();
+        assert_eq!(level(), "30");
+
+        power_supply(&dir, "AC", "Mains", true);
+        block.update().unwrap();
+        assert_eq!(level(), "80");
+        block.update().unwrap();
+        assert_eq!(level(), "80");
+
+        // Read only devices are left alone, too.
+        block.zones[0].read_only = Some("Cannot write".to_string());
+        power_supply(&dir, "AC", "Mains", false);
+        block.update().unwrap();
+        assert_eq!(level(), "80");
+    }
+
+    #[test]
+    fn test_next_interval() {
+        let (interval, fast) = (Duration::from_secs(10), Duration::from_secs(1));
+        let mut fast_updates = 0;
+        let mut next = |changed, scrolled| {
+            let (next, left) = next_interval(fast_updates, changed, scrolled, interval, fast);
+            fast_updates = left;
+            next
+        };
+
+        assert_eq!(next(false, false), interval);
+        assert_eq!(next(false, true), fast);
+        for _ in 1..FAST_UPDATES {
+            assert_eq!(next(false, false), fast);
+        }
+        assert_eq!(next(false, false), interval);
+
+        // Changes during the fast updates start them over.
+        assert_eq!(next(true, false), fast);
+        assert_eq!(next(false, false), fast);
+        assert_eq!(next(true, false), fast);
+        for _ in 1..FAST_UPDATES {
+            assert_eq!(next(false, false), fast);
+        }
+        assert_eq!(next(false, false), interval);
+    }
+
+    #[test]
+    fn test_fast_updates() {
+        let dir = TempDir::new().unwrap();
+        let mut block = keylight(&dir, 1, 3, "interval = 30\nfast_interval = 2");
+        let every = |update: Option<Update>| match update {
+            Some(Update::Every(d)) => d.as_secs(),
+            _ => panic!("no interval"),
+        };
+        assert_eq!(every(block.update().unwrap()), 30);
+        assert_eq!(every(block.update().unwrap()), 30);
+        block.zones[0].device.set(2).unwrap();
+        assert_eq!(every(block.update().unwrap()), 2);
+        assert_eq!(every(block.update().unwrap()), 2);
+    }
+
+    #[test]
+    fn test_flash() {
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+        let mut flash = Flash {
+            duration: secs(2),
+            until: None,
+        };
+        assert_eq!(flash.remaining(now), None);
+        flash.start(now);
+        assert_eq!(flash.remaining(now + secs(1)), Some(secs(1)));
+        // Another change extends it.
+        flash.start(now + secs(1));
+        assert_eq!(flash.remaining(now + secs(2)), Some(secs(1)));
+        assert!(flash.flashing());
+        assert_eq!(flash.remaining(now + secs(3)), None);
+        assert!(!flash.flashing());
+    }
+
+    #[test]
+    fn test_flash_on_change() {
+        let dir = TempDir::new().unwrap();
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+        let mut block = keylight(
+            &dir,
+            0,
+            3,
+            "hide_when_zero = true\nflash_on_change = true\nflash_duration = 3\ninterval = 10",
+        );
+        let every = |update: Option<Update>| match update {
+            Some(Update::Every(d)) => d,
+            _ => panic!("no interval"),
+        };
+        block.update_at(now).unwrap();
+        assert!(block.view().is_empty());
+
+        // A change by the keys flashes the block, even at zero.
+        block.zones[0].device.set(1).unwrap();
+        assert_eq!(every(block.update_at(now).unwrap()), secs(1));
+        assert_eq!(block.outputs[0].widget_state().state, State::Info);
+        block.zones[0].device.set(0).unwrap();
+        assert_eq!(every(block.update_at(now + secs(2)).unwrap()), secs(1));
+        assert_eq!(block.view().len(), 1);
+        block.update_at(now + secs(4)).unwrap();
+        assert_eq!(block.view().len(), 1);
+        block.update_at(now + secs(5)).unwrap();
+        assert!(block.view().is_empty());
+        assert_eq!(block.outputs[0].widget_state().state, State::Idle);
+
+        // Scrolling does not.
+        block.zones[0].device.set(2).unwrap();
+        block.update_at(now + secs(6)).unwrap();
+        block.update_at(now + secs(10)).unwrap();
+        block
+            .click(&I3BarEvent {
+                name: Some(block.id.clone()),
+                instance: None,
+                x: 0,
+                y: 0,
+                button: MouseButton::WheelUp,
+                modifiers: Vec::new(),
+                double_click: false,
+            })
+            .unwrap();
+        block.update_at(now + secs(10)).unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "100%");
+        assert_eq!(block.outputs[0].widget_state().state, State::Idle);
+    }
+
+    #[test]
+    fn test_is_change() {
+        // Readings jittering by one level keep the one taken.
+        let mut accepted = 50;
+        let mut changes = Vec::new();
+        for &reading in &[51, 50, 49, 51, 50, 54, 55, 54, 40] {
+            if is_change(accepted, reading, 3) {
+                accepted = reading;
+                changes.push(reading);
+            }
+        }
+        assert_eq!(changes, [54, 40]);
+
+        // Without a threshold, every change counts.
+        assert!(is_change(50, 51, 0));
+        assert!(is_change(50, 49, 1));
+        assert!(!is_change(50, 50, 0));
+    }
+
+    #[test]
+    fn test_change_threshold() {
+        let dir = TempDir::new().unwrap();
+        let mut block = keylight(&dir, 50, 100, "change_threshold = 3\nstep_width = 1");
+        block.update().unwrap();
+        block.zones[0].device.set(51).unwrap();
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "50%");
+        block.zones[0].device.set(49).unwrap();
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "50%");
+        block.zones[0].device.set(60).unwrap();
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "60%");
+
+        // Scrolling shows right away, though by less than the threshold.
+        block
+            .click(&I3BarEvent {
+                name: Some(block.id.clone()),
+                instance: None,
+                x: 0,
+                y: 0,
+                button: MouseButton::WheelUp,
+                modifiers: Vec::new(),
+                double_click: false,
+            })
+            .unwrap();
+        block.update().unwrap();
+        assert_eq!(block.outputs[0].widget_state().text, "61%");
+    }
+
+    /// A simulated device whose brightness cannot be set while `broken`.
+    struct Flaky {
+        device: Simulated,
+        broken: std::rc::Rc<Cell<bool>>,
+    }
+
+    impl Backend for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn get(&self) -> Result<u64> {
+            self.device.get()
+        }
+
+        fn get_max(&self) -> u64 {
+            self.device.get_max()
+        }
+
+        fn set(&self, raw: u64) -> Result<()> {
+            if self.broken.get() {
+                return Err(BlockError(
+                    "keylight".to_string(),
+                    "EC timed out".to_string(),
+                ));
+            }
+            self.device.set(raw)
+        }
+
+        fn watch(&self, _: String, _: Sender<Task>) -> Result<Option<Stop>> {
+            Ok(None)
+        }
+
+        fn read_only(&self) -> Option<String> {
+            Some("flaky is read-only".to_string()).filter(|_| self.broken.get())
+        }
+    }
+
+    #[test]
+    fn test_display_only() {
+        let shown = |options: &str| {
+            let device = Flaky {
+                device: Simulated::new(1, 3).unwrap(),
+                broken: std::rc::Rc::new(Cell::new(true)),
+            };
+            let mut block = Keylight::with_devices(
+                toml::from_str(options).unwrap(),
+                Config::default(),
+                vec![Box::new(device)],
+                crossbeam_channel::unbounded().0,
+            )
+            .unwrap();
+            block.update().unwrap();
+            block.outputs[0].widget_state().text
+        };
+        assert_eq!(shown(""), "33%");
+        assert_eq!(shown("step_width = 10"), "33% ro");
+        assert_eq!(shown("[click]\nleft = \"toggle\""), "33% ro");
+        assert_eq!(shown("step_width = 10\ncheck_permissions = false"), "33%");
+    }
+
+    #[test]
+    fn test_write_debounce() {
+        let broken = std::rc::Rc::new(Cell::new(false));
+        let device = Flaky {
+            device: Simulated::new(5, 10).unwrap(),
+            broken: broken.clone(),
+        };
+        let mut block = Keylight::with_devices(
+            toml::from_str("step_width = 10\nwrite_debounce_ms = 50").unwrap(),
+            Config::default(),
+            vec![Box::new(device)],
+            crossbeam_channel::unbounded().0,
+        )
+        .unwrap();
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        let text = |block: &Keylight| block.outputs[0].widget_state().text;
+        block.update_at(t0).unwrap();
+        assert_eq!(text(&block), "50%");
+
+        // A burst shows as it goes, and is written once it pauses.
+        for (i, &direction) in [
+            LogicalDirection::Up,
+            LogicalDirection::Up,
+            LogicalDirection::Down,
+            LogicalDirection::Up,
+        ]
+        .iter()
+        .enumerate()
+        {
+            block
+                .scroll(direction, Some(0), t0 + ms(10 * i as u64))
+                .unwrap();
+        }
+        assert_eq!(
+            block.update_at(t0 + ms(40)).unwrap(),
+            Some(Update::Every(ms(40)))
+        );
+        assert_eq!(text(&block), "70%");
+        assert_eq!(block.zones[0].device.get().unwrap(), 5);
+        block.update_at(t0 + ms(80)).unwrap();
+        assert_eq!(block.zones[0].device.get().unwrap(), 7);
+        assert_eq!(text(&block), "70%");
+        assert_eq!(block.outputs[0].widget_state().state, State::Idle);
+
+        // A failed write shows where the device is, as a warning.
+        broken.set(true);
+        block
+            .scroll(LogicalDirection::Up, Some(0), t0 + ms(100))
+            .unwrap();
+        block.update_at(t0 + ms(100)).unwrap();
+        assert_eq!(text(&block), "80%");
+        block.update_at(t0 + ms(150)).unwrap();
+        assert_eq!(text(&block), "70%");
+        assert_eq!(block.outputs[0].widget_state().state, State::Warning);
+
+        broken.set(false);
+        block
+            .scroll(LogicalDirection::Down, Some(0), t0 + ms(200))
+            .unwrap();
+        block.update_at(t0 + ms(250)).unwrap();
+        assert_eq!(text(&block), "60%");
+        assert_eq!(block.outputs[0].widget_state().state, State::Idle);
+    }
+
+    #[test]
+    fn test_format_brightness() {
+        let formatted = |percentage: f64, options: &str| {
+            let dir = TempDir::new().unwrap();
+            let mut block = keylight(&dir, 1, 3, options);
+            block.show(0, percentage, "kbd").unwrap();
+            block.outputs[0].widget_state().text
+        };
+        let all = |options: &str| -> Vec<String> {
+            [0.0, 7.0, 66.0, 100.0]
+                .iter()
+                .map(|&percentage| formatted(percentage, options))
+                .collect()
+        };
+        assert_eq!(all(""), ["0%", "7%", "66%", "100%"]);
+        assert_eq!(all("pad_width = 3"), ["  0%", "  7%", " 66%", "100%"]);
+        assert_eq!(
+            all("pad_width = 3\nshow_percent_sign = false"),
+            ["  0", "  7", " 66", "100"]
+        );
+        assert_eq!(
+            all("pad_width = 3\npad_char = \"·\"\nformat = \"⌨ {brightness}%\""),
+            ["⌨ ··0%", "⌨ ··7%", "⌨ ·66%", "⌨ 100%"]
+        );
+        assert_eq!(
+            all("pad_width = 5\nprecision = 1"),
+            ["  0.0%", "  7.0%", " 66.0%", "100.0%"]
+        );
+
+        let rendered = |value| formatting::render(&value, Spec::default());
+        assert_eq!(rendered(format_brightness(200.0 / 3.0, 1, 0, ' ')), "66.7");
+        assert_eq!(rendered(format_brightness(200.0 / 3.0, 0, 0, ' ')), "67");
+        assert_eq!(rendered(format_brightness(7.0, 0, 3, '0')), "007");
+        assert_eq!(formatted(7.0, "format = \"{brightness:3}%\""), "  7%");
+    }
+
+    #[test]
+    fn test_step_target() {
+        use LogicalDirection::{Down, Up};
+        assert_eq!(step_target(50, 100, 10, 1.0, Up), Some(60));
+        assert_eq!(step_target(100, 100, 10, 1.0, Up), None);
+        assert_eq!(step_target(0, 100, 10, 1.0, Down), None);
+        // 10% steps perceived the same across the range.
+        assert_eq!(step_target(25, 100, 10, 2.0, Up), Some(36));
+        assert_eq!(step_target(81, 100, 10, 2.0, Up), Some(100));
+
+        // Steep curves do not get stuck at the low end, either way.
+        for &scaling in &[1.0, 3.0, 10.0] {
+            let mut raw = 255;
+            let mut steps = 0;
+            while let Some(target) = step_target(raw, 255, 5, scaling, Down) {
+                assert!(target < raw);
+                raw = target;
+                steps += 1;
+            }
+            assert_eq!(raw, 0);
+            while let Some(target) = step_target(raw, 255, 5, scaling, Up) {
+                assert!(target > raw);
+                raw = target;
+                steps += 1;
+            }
+            assert_eq!(raw, 255);
+            assert!(steps <= 2 * 255);
+        }
+    }
+
+    fn click(block: &mut Keylight, button: MouseButton) {
+        block
+            .click(&I3BarEvent {
+                name: Some(block.id.clone()),
+                instance: None,
+                x: 0,
+                y: 0,
+                button,
+                modifiers: Vec::new(),
+                double_click: false,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_click_actions() {
+        let dir = TempDir::new().unwrap();
+        let brightness = dir.path().join("tpacpi::kbd_backlight/brightness");
+        let read = || fs::read_to_string(&brightness).unwrap();
+        let mut block = keylight(
+            &dir,
+            2,
+            3,
+            "[click]\nleft = \"toggle\"\nright = \"cycle\"\nmiddle = \"min\"\nforward = \"max\"",
+        );
+
+        click(&mut block, MouseButton::Left);
+        assert_eq!(read(), "0");
+        // Back on where it was.
+        click(&mut block, MouseButton::Left);
+        assert_eq!(read(), "2");
+        click(&mut block, MouseButton::Right);
+        assert_eq!(read(), "3");
+        click(&mut block, MouseButton::Right);
+        assert_eq!(read(), "0");
+        click(&mut block, MouseButton::Right);
+        assert_eq!(read(), "1");
+        click(&mut block, MouseButton::Forward);
+        assert_eq!(read(), "3");
+        click(&mut block, MouseButton::Middle);
+        assert_eq!(read(), "1");
+        // Scrolling stays unbound.
+        click(&mut block, MouseButton::WheelUp);
+        block.flush().unwrap();
+        assert_eq!(read(), "2");
+
+        // Turned off elsewhere, toggling turns it on fully.
+        let mut block = keylight(&dir, 0, 3, "[click]\nleft = \"toggle\"");
+        click(&mut block, MouseButton::Left);
+        assert_eq!(read(), "3");
+    }
+
+    #[test]
+    fn test_merged_toggle() {
+        let dir = TempDir::new().unwrap();
+        zones(
+            &dir,
+            &[("rgb:kbd_backlight_1", 0, 3), ("rgb:kbd_backlight_2", 2, 3)],
+        );
+        let read = |name: &str| {
+            let path = dir.path().join(name).join("brightness");
+            fs::read_to_string(path).unwrap().trim().to_string()
+        };
+        let mut block = zoned_keylight(&dir, "merge = true\n[click]\nleft = \"toggle\"");
+        click(&mut block, MouseButton::Left);
+        assert_eq!(read("rgb:kbd_backlight_1"), "0");
+        assert_eq!(read("rgb:kbd_backlight_2"), "0");
+        click(&mut block, MouseButton::Left);
+        assert_eq!(read("rgb:kbd_backlight_1"), "3");
+        assert_eq!(read("rgb:kbd_backlight_2"), "2");
+    }
+
+    #[test]
+    fn test_double_click_actions() {
+        let dir = TempDir::new().unwrap();
+        let brightness = dir.path().join("tpacpi::kbd_backlight/brightness");
+        let mut block = keylight(
+            &dir,
+            1,
+            3,
+            "[click]\nleft = \"toggle\"\ndouble_left = \"max\"\nright = \"min\"",
+        );
+        let mut double_clicks = DoubleClicks::new(Duration::from_millis(300));
+        let start = Instant::now();
+        let mut click = |button, millis| {
+            let mut event = I3BarEvent {
+                name: Some(block.id.clone()),
+                instance: None,
+                x: 0,
+                y: 0,
+                button,
+                modifiers: Vec::new(),
+                double_click: false,
+            };
+            double_clicks.detect_at(&mut event, start + Duration::from_millis(millis));
+            block.click(&event).unwrap();
+            fs::read_to_string(&brightness).unwrap()
+        };
+
+        // The first click toggles right away, the second one overrides it.
+        assert_eq!(click(MouseButton::Left, 0), "0");
+        assert_eq!(click(MouseButton::Left, 200), "3");
+        // Too slow for a double click, so two toggles.
+        assert_eq!(click(MouseButton::Left, 1000), "0");
+        assert_eq!(click(MouseButton::Left, 1400), "3");
+        // Other buttons in between break up double clicks.
+        assert_eq!(click(MouseButton::Left, 2000), "0");
+        assert_eq!(click(MouseButton::Right, 2100), "1");
+        assert_eq!(click(MouseButton::Left, 2200), "0");
+        assert_eq!(click(MouseButton::Left, 2300), "3");
+        // A third click starts over.
+        assert_eq!(click(MouseButton::Left, 2400), "0");
+    }
+
+    #[test]
+    fn test_ramp() {
+        let ms = Duration::from_millis;
+        let levels = |from, to, duration, curve| {
+            ramp(from, to, ms(duration), curve)
+                .into_iter()
+                .map(|(_, level)| level)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ramp(0, 255, ms(100), RampCurve::Linear),
+            [(ms(25), 64), (ms(50), 128), (ms(75), 191), (ms(100), 255)]
+        );
+        assert_eq!(
+            levels(0, 255, 250, RampCurve::Linear),
+            [26, 51, 77, 102, 128, 153, 179, 204, 230, 255]
+        );
+        assert_eq!(levels(0, 255, 100, RampCurve::Ease), [40, 128, 215, 255]);
+        assert_eq!(levels(255, 0, 100, RampCurve::Linear), [191, 128, 64, 0]);
+        // Shorter than a step, it is a single write.
+        assert_eq!(ramp(0, 255, ms(10), RampCurve::Linear), [(ms(10), 255)]);
+        // Never more writes than levels to go.
+        assert_eq!(
+            ramp(0, 2, ms(100), RampCurve::Linear),
+            [(ms(50), 1), (ms(100), 2)]
+        );
+        assert!(ramp(7, 7, ms(100), RampCurve::Linear).is_empty());
+    }
+
+    #[test]
+    fn test_ramp_clicks() {
+        let ms = Duration::from_millis;
+        let dir = TempDir::new().unwrap();
+        let brightness = dir.path().join("tpacpi::kbd_backlight/brightness");
+        let read = || fs::read_to_string(&brightness).unwrap().trim().to_string();
+        let options = "ramp_duration_ms = 100\n[click]\nleft = \"toggle\"";
+        let mut block = keylight(&dir, 0, 255, options);
+
+        let t0 = Instant::now();
+        block.set_action(KeylightAction::Toggle, None, t0).unwrap();
+        assert_eq!(read(), "0");
+        assert_eq!(block.update_at(t0).unwrap(), Some(Update::Every(ms(25))));
+        assert_eq!(
+            block.update_at(t0 + ms(30)).unwrap(),
+            Some(Update::Every(ms(20)))
+        );
+        assert_eq!(read(), "64");
+        assert_eq!(block.outputs[0].widget_state().text, "25%");
+        // Late updates catch up.
+        block.update_at(t0 + ms(80)).unwrap();
+        assert_eq!(read(), "191");
+        assert_eq!(
+            block.update_at(t0 + ms(100)).unwrap(),
+            Some(Update::Every(Duration::from_secs(1)))
+        );
+        assert_eq!(read(), "255");
+        assert!(block.ramp.is_none());
+
+        // Devices with few levels change at once.
+        let mut block = keylight(&dir, 0, 3, options);
+        block.set_action(KeylightAction::Toggle, None, t0).unwrap();
+        assert_eq!(read(), "3");
+        assert!(block.ramp.is_none());
+    }
+
+    #[test]
+    fn test_ramp_cancel() {
+        let ms = Duration::from_millis;
+        let dir = TempDir::new().unwrap();
+        let brightness = dir.path().join("tpacpi::kbd_backlight/brightness");
+        let read = || fs::read_to_string(&brightness).unwrap().trim().to_string();
+        let mut block = keylight(
+            &dir,
+            0,
+            255,
+            "ramp_duration_ms = 100\n[click]\nleft = \"toggle\"",
+        );
+
+        click(&mut block, MouseButton::Left);
+        let since = block.ramp.as_ref().unwrap().since;
+        block.update_at(since + ms(50)).unwrap();
+        assert_eq!(read(), "128");
+        // Toggling again mid-ramp turns off from where the ramp got to.
+        click(&mut block, MouseButton::Left);
+        let since = block.ramp.as_ref().unwrap().since;
+        assert_eq!(
+            block.ramp.as_ref().unwrap().steps,
+            [
+                (ms(25), 0, 96),
+                (ms(50), 0, 64),
+                (ms(75), 0, 32),
+                (ms(100), 0, 0)
+            ]
+        );
+        block.update_at(since + ms(100)).unwrap();
+        assert_eq!(read(), "0");
+
+        // Scrolling cancels the ramp, too. Toggled on, it goes back to 128.
+        click(&mut block, MouseButton::Left);
+        let since = block.ramp.as_ref().unwrap().since;
+        block.update_at(since + ms(25)).unwrap();
+        assert_eq!(read(), "32");
+        click(&mut block, MouseButton::WheelDown);
+        assert!(block.ramp.is_none());
+        block.update_at(since + ms(200)).unwrap();
+        assert_eq!(read(), "19");
+    }
+}
@@ -0,0 +1,600 @@
+//! Brightness-controlled devices exposed through `sysfs`.
+//!
+//! Screen backlights (`/sys/class/backlight`) and LEDs such as keyboard
+//! backlights (`/sys/class/leds`) share the same interface: a directory per
+//! device holding a `brightness` and a `max_brightness` file. This module
+//! implements reading and writing those files once, so that blocks built on
+//! top of it agree on parsing, clamping and error reporting.
+
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use inotify::{EventMask, Inotify, WatchMask};
+use nix::unistd::{getegid, geteuid, getgroups};
+
+use crate::errors::*;
+use crate::scheduler::Task;
+use crate::stop::{self, Stop};
+use crate::util::glob_match;
+
+const SYSFS_CLASS: &str = "/sys/class";
+
+const LOGIND: &str = "org.freedesktop.login1";
+/// The session of the process calling.
+const LOGIND_SESSION: &str = "/org/freedesktop/login1/session/auto";
+const LOGIND_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+fn device_error(path: &Path, message: &str) -> Error {
+    BlockError(
+        "led_common".to_string(),
+        format!("{} ({})", message, path.to_string_lossy()),
+    )
+}
+
+/// Read a raw brightness value from the given file.
+fn read_value(file: &Path) -> Result<u64> {
+    let content = fs::read_to_string(file).map_err(|_| device_error(file, "Failed to read"))?;
+    parse_value(&content, file)
+}
+
+/// Parse the `content` of `file` as a raw brightness value. Any whitespace
+/// around it, such as the trailing newline or `\r\n`, is ignored.
+fn parse_value(content: &str, file: &Path) -> Result<u64> {
+    content
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| device_error(file, "Failed to parse brightness value"))
+}
+
+/// A device in one of the `sysfs` classes whose brightness can be queried and
+/// set.
+#[derive(Debug, Clone)]
+pub struct LedDevice {
+    class: String,
+    device_path: PathBuf,
+    max_brightness: u64,
+}
+
+impl LedDevice {
+    /// Find a device of the given class (e.g. `"backlight"` or `"leds"`).
+    ///
+    /// Without a `pattern` the first device in alphabetical order is used.
+    /// Otherwise a device named exactly `pattern` is preferred, falling back on
+    /// the first device whose name contains it.
+    pub fn discover(class: &str, pattern: Option<&str>) -> Result<Self> {
+        Self::discover_in(&Path::new(SYSFS_CLASS).join(class), pattern)
+    }
+
+    /// Like [`discover`](#method.discover), among the devices in `class_path`.
+    pub fn discover_in(class_path: &Path, pattern: Option<&str>) -> Result<Self> {
+        let devices = Self::find_devices(&class_path.join("*").to_string_lossy())?;
+        let device = match pattern {
+            None => devices.first(),
+            Some(pattern) => devices
+                .iter()
+                .find(|device| device.name() == pattern)
+                .or_else(|| {
+                    devices
+                        .iter()
+                        .find(|device| device.name().contains(pattern))
+                }),
+        };
+        if let (None, Some(pattern)) = (device, pattern) {
+            // Tell what is wrong with a device named exactly so, if any.
+            let path = class_path.join(pattern);
+            if path.exists() {
+                return Self::new(path);
+            }
+        }
+        device.cloned().ok_or_else(|| {
+            device_error(
+                class_path,
+                &match pattern {
+                    Some(pattern) => format!("No device matching '{}' found", pattern),
+                    None => "No devices found".to_string(),
+                },
+            )
+        })
+    }
+
+    /// All usable devices whose path matches `glob`, sorted by name, e.g.
+    /// `/sys/class/leds/*::kbd_backlight*`. Only the last component of the
+    /// path may contain wildcards, `*` and `?`.
+    pub fn find_devices(glob: &str) -> Result<Vec<Self>> {
+        let glob = Path::new(glob);
+        let (directory, pattern) = match (glob.parent(), glob.file_name()) {
+            (Some(directory), Some(pattern)) => (directory, pattern.to_string_lossy()),
+            _ => return Err(device_error(glob, "Invalid device pattern")),
+        };
+        let mut paths: Vec<PathBuf> = directory
+            .read_dir()
+            .map_err(|_| device_error(directory, "Failed to read device directory"))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| glob_match(&pattern, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .collect();
+        paths.sort();
+        // Other entries of the directory, or devices without a brightness
+        // range, are of no use to anyone.
+        Ok(paths
+            .into_iter()
+            .filter_map(|path| Self::new(path).ok())
+            .collect())
+    }
+
+    /// Use the device at `device_path`, which must contain a `max_brightness`
+    /// file. Its class is the name of the directory it is in.
+    pub fn new<P: Into<PathBuf>>(device_path: P) -> Result<Self> {
+        let device_path = device_path.into();
+        if !device_path.exists() {
+            return Err(device_error(&device_path, "Device does not exist"));
+        }
+        let max_brightness = read_value(&device_path.join("max_brightness"))?;
+        if max_brightness == 0 {
+            return Err(device_error(&device_path, "Device has no brightness range"));
+        }
+        let class = device_path
+            .parent()
+            .and_then(|class| class.file_name())
+            .map(|class| class.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(LedDevice {
+            class,
+            device_path,
+            max_brightness,
+        })
+    }
+
+    /// The name of the device, i.e. its directory name.
+    pub fn name(&self) -> &str {
+        self.device_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+
+    /// The directory of the device in `sysfs`.
+    pub fn device_path(&self) -> &Path {
+        &self.device_path
+    }
+
+    /// The file the current brightness is read from.
+    // amdgpu drivers set the actual_brightness in a different scale than [0, max_brightness],
+    // so we have to use the 'brightness' file instead. This may be fixed in the new 5.7 kernel?
+    pub fn brightness_file(&self) -> PathBuf {
+        let actual = self.device_path.join("actual_brightness");
+        if self.device_path.ends_with("amdgpu_bl0") || !actual.exists() {
+            self.device_path.join("brightness")
+        } else {
+            actual
+        }
+    }
+
+    /// The highest raw brightness value of the device.
+    pub fn max_brightness(&self) -> u64 {
+        self.max_brightness
+    }
+
+    /// The current raw brightness value, never above `max_brightness`.
+    pub fn brightness(&self) -> Result<u64> {
+        let raw = read_value(&self.brightness_file())?;
+        // Some drivers report more than the maximum after resuming.
+        if raw > self.max_brightness {
+            if_debug!({
+                eprintln!(
+                    "{}: brightness {} above the maximum of {}",
+                    self.device_path.display(),
+                    raw,
+                    self.max_brightness
+                );
+            });
+        }
+        Ok(raw.min(self.max_brightness))
+    }
+
+    /// The current brightness as a percentage of `max_brightness`.
+    pub fn percentage(&self) -> Result<f64> {
+        self.scaled_percentage(1.0)
+    }
+
+    /// The current brightness as a percentage, on a non-linear scale.
+    ///
+    /// `scaling` is the exponent relating raw values to perceived brightness:
+    /// with a value of 2 for example, half of `max_brightness` shows as 71%.
+    pub fn scaled_percentage(&self, scaling: f64) -> Result<f64> {
+        Ok(raw_to_percent(
+            self.brightness()?,
+            self.max_brightness,
+            scaling,
+        ))
+    }
+
+    /// The file brightness values are written to.
+    pub fn writable_file(&self) -> PathBuf {
+        self.device_path.join("brightness")
+    }
+
+    /// Whether this process may write brightness values to the device, as far
+    /// as the permissions of the file tell.
+    pub fn writable(&self) -> bool {
+        let metadata = match fs::metadata(self.writable_file()) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        let mut groups = getgroups().unwrap_or_default();
+        groups.push(getegid());
+        let groups: Vec<u32> = groups.into_iter().map(|group| group.as_raw()).collect();
+        writable_by(
+            metadata.mode(),
+            metadata.uid(),
+            metadata.gid(),
+            geteuid().as_raw(),
+            &groups,
+        )
+    }
+
+    /// Set the raw brightness value, clamped to `max_brightness`.
+    pub fn set_brightness(&self, raw: u64) -> Result<()> {
+        let raw = raw.min(self.max_brightness);
+        let path = self.writable_file();
+        match OpenOptions::new().write(true).truncate(true).open(&path) {
+            Ok(mut file) => file
+                .write_all(raw.to_string().as_bytes())
+                .map_err(|_| device_error(&path, "Failed to write")),
+            // Most likely we lack the permissions, but logind can do it for us.
+            Err(_) => self.set_brightness_via_dbus(raw),
+        }
+    }
+
+    /// Set the brightness as a percentage of `max_brightness`.
+    pub fn set_percentage(&self, percent: f64) -> Result<()> {
+        self.set_scaled_percentage(percent, 1.0)
+    }
+
+    /// Set the brightness as a percentage, see
+    /// [`scaled_percentage`](#method.scaled_percentage) for the meaning of
+    /// `scaling`.
+    pub fn set_scaled_percentage(&self, percent: f64, scaling: f64) -> Result<()> {
+        self.set_brightness(percent_to_raw(percent, self.max_brightness, scaling))
+    }
+
+    fn set_brightness_via_dbus(&self, raw: u64) -> Result<()> {
+        let con = dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::System)
+            .map_err(|_| device_error(&self.device_path, "Failed to establish D-Bus connection"))?;
+        let msg = dbus::Message::new_method_call(
+            LOGIND,
+            LOGIND_SESSION,
+            LOGIND_SESSION_INTERFACE,
+            "SetBrightness",
+        )
+        .map_err(|_| device_error(&self.device_path, "Failed to create D-Bus message"))?
+        .append2(self.class.as_str(), self.name())
+        .append1(raw as u32);
+
+        con.send_with_reply_and_block(msg, 1000)
+            .map(|_| ())
+            .map_err(|_| device_error(&self.device_path, "Failed to set brightness via D-Bus"))
+    }
+
+    /// Create an inotify instance watching the brightness file for changes.
+    pub fn watch(&self) -> Result<Inotify> {
+        let file = self.brightness_file();
+        let mut notify =
+            Inotify::init().map_err(|_| device_error(&file, "Failed to start inotify"))?;
+        notify
+            .add_watch(&file, WatchMask::MODIFY)
+            .map_err(|_| device_error(&file, "Failed to watch"))?;
+        Ok(notify)
+    }
+
+    /// Request an update of the block `id` whenever the brightness changes,
+    /// from a thread named `name`, until the returned `Stop` is dropped.
+    pub fn watch_updates(&self, name: &str, id: String, tx: Sender<Task>) -> Result<Stop> {
+        let mut notify = self.watch()?;
+        let (stop, stopped) = stop::pair()?;
+        thread::Builder::new()
+            .name(name.into())
+            .spawn(move || {
+                let mut buffer = [0; 1024];
+                while let Some(mut events) = stopped.inotify_events(&mut notify, &mut buffer) {
+                    if events.any(|event| event.mask.contains(EventMask::MODIFY)) {
+                        let task = Task {
+                            id: id.clone(),
+                            update_time: Instant::now(),
+                        };
+                        if tx.send(task).is_err() {
+                            return;
+                        }
+                    }
+
+                    // Avoid update spam.
+                    if !stopped.sleep(Duration::from_millis(250)) {
+                        return;
+                    }
+                }
+            })
+            .map_err(|_| device_error(&self.device_path, "Failed to start watching thread"))?;
+        Ok(stop)
+    }
+}
+
+/// Whether this process runs in an active logind session, through which
+/// [`set_brightness`](struct.LedDevice.html#method.set_brightness) can set the
+/// brightness without permissions on `sysfs`.
+pub fn in_logind_session() -> bool {
+    let con = match dbus::ffidisp::Connection::get_private(dbus::ffidisp::BusType::System) {
+        Ok(con) => con,
+        Err(_) => return false,
+    };
+    let msg = match dbus::Message::new_method_call(
+        LOGIND,
+        LOGIND_SESSION,
+        "org.freedesktop.DBus.Properties",
+        "Get",
+    ) {
+        Ok(msg) => msg.append2(LOGIND_SESSION_INTERFACE, "Active"),
+        Err(_) => return false,
+    };
+    con.send_with_reply_and_block(msg, 1000)
+        .ok()
+        .and_then(|reply| reply.get1::<dbus::arg::Variant<bool>>())
+        .is_some_and(|active| active.0)
+}
+
+/// Whether the user `uid` in `groups` may write to a file with `mode`, owned
+/// by `owner` and `group`. Root may write to anything.
+fn writable_by(mode: u32, owner: u32, group: u32, uid: u32, groups: &[u32]) -> bool {
+    if uid == 0 {
+        true
+    } else if uid == owner {
+        mode & 0o200 != 0
+    } else if groups.contains(&group) {
+        mode & 0o020 != 0
+    } else {
+        mode & 0o002 != 0
+    }
+}
+
+/// The percentage a `raw` brightness value up to `max` shows as. See
+/// [`LedDevice::scaled_percentage`](struct.LedDevice.html#method.scaled_percentage)
+/// for the meaning of `scaling`.
+pub fn raw_to_percent(raw: u64, max: u64, scaling: f64) -> f64 {
+    let ratio = (raw as f64 / max as f64).min(1.0);
+    ratio.powf(1.0 / scaling) * 100.0
+}
+
+/// The raw brightness value up to `max` closest to showing as `percent`, the
+/// inverse of [`raw_to_percent`](fn.raw_to_percent.html).
+pub fn percent_to_raw(percent: f64, max: u64, scaling: f64) -> u64 {
+    let ratio = (percent / 100.0).clamp(0.0, 1.0);
+    (ratio.powf(scaling) * max as f64).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    fn fake_device(root: &Path, class: &str, name: &str, brightness: u64, max: u64) -> PathBuf {
+        let path = root.join(class).join(name);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("brightness"), format!("{}\n", brightness)).unwrap();
+        fs::write(path.join("max_brightness"), format!("{}\n", max)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_discover() {
+        let root = TempDir::new().unwrap();
+        fake_device(root.path(), "leds", "input3::capslock", 0, 1);
+        fake_device(root.path(), "leds", "asus::kbd_backlight", 1, 3);
+        fake_device(root.path(), "leds", "asus::kbd_backlight_1", 1, 3);
+
+        let first = LedDevice::discover_in(&root.path().join("leds"), None).unwrap();
+        assert_eq!(first.name(), "asus::kbd_backlight");
+
+        let exact =
+            LedDevice::discover_in(&root.path().join("leds"), Some("asus::kbd_backlight_1"))
+                .unwrap();
+        assert_eq!(exact.name(), "asus::kbd_backlight_1");
+
+        let partial = LedDevice::discover_in(&root.path().join("leds"), Some("capslock")).unwrap();
+        assert_eq!(partial.name(), "input3::capslock");
+
+        assert!(LedDevice::discover_in(&root.path().join("leds"), Some("numlock")).is_err());
+        assert!(LedDevice::discover_in(&root.path().join("backlight"), None).is_err());
+    }
+
+    #[test]
+    fn test_find_devices() {
+        let root = TempDir::new().unwrap();
+        fake_device(root.path(), "leds", "input3::capslock", 0, 1);
+        fake_device(root.path(), "leds", "tpacpi::kbd_backlight", 1, 2);
+        fake_device(root.path(), "leds", "asus::kbd_backlight", 1, 3);
+        fake_device(root.path(), "leds", "broken::kbd_backlight", 0, 0);
+        let leds = root.path().join("leds");
+        let names = |glob: &str| -> Vec<String> {
+            LedDevice::find_devices(&leds.join(glob).to_string_lossy())
+                .unwrap()
+                .iter()
+                .map(|device| device.name().to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            names("*::kbd_backlight"),
+            ["asus::kbd_backlight", "tpacpi::kbd_backlight"]
+        );
+        assert_eq!(names("input?::*"), ["input3::capslock"]);
+        assert_eq!(names("*").len(), 3);
+        assert!(names("*numlock").is_empty());
+        assert!(LedDevice::find_devices(&root.path().join("nope/*").to_string_lossy()).is_err());
+
+        // The device named is broken, so say why rather than that it is missing.
+        match LedDevice::discover_in(&root.path().join("leds"), Some("broken::kbd_backlight")) {
+            Err(BlockError(_, message)) => assert!(message.contains("no brightness range")),
+            _ => panic!("expected the broken device to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_class() {
+        let root = TempDir::new().unwrap();
+        let path = fake_device(root.path(), "backlight", "intel_backlight", 30, 120);
+        assert_eq!(LedDevice::new(path).unwrap().class, "backlight");
+    }
+
+    #[test]
+    fn test_read_and_write() {
+        let root = TempDir::new().unwrap();
+        let path = fake_device(root.path(), "backlight", "intel_backlight", 30, 120);
+        let device = LedDevice::new(path.clone()).unwrap();
+
+        assert_eq!(device.max_brightness(), 120);
+        assert_eq!(device.brightness().unwrap(), 30);
+        assert_eq!(device.percentage().unwrap(), 25.0);
+
+        device.set_brightness(500).unwrap();
+        assert_eq!(fs::read_to_string(path.join("brightness")).unwrap(), "120");
+        device.set_percentage(50.0).unwrap();
+        assert_eq!(device.brightness().unwrap(), 60);
+    }
+
+    #[test]
+    fn test_errors_carry_path() {
+        let root = TempDir::new().unwrap();
+        let path = fake_device(root.path(), "backlight", "broken", 0, 0);
+        match LedDevice::new(path) {
+            Err(BlockError(_, message)) => assert!(message.contains("broken")),
+            _ => panic!("expected a zero max_brightness to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_stray_whitespace() {
+        let root = TempDir::new().unwrap();
+        let path = fake_device(root.path(), "leds", "tpacpi::kbd_backlight", 0, 2);
+        fs::write(path.join("brightness"), " 1 \n\n").unwrap();
+        fs::write(path.join("max_brightness"), "\t2\n").unwrap();
+        let device = LedDevice::new(path.clone()).unwrap();
+        assert_eq!(device.max_brightness(), 2);
+        assert_eq!(device.brightness().unwrap(), 1);
+
+        fs::write(path.join("brightness"), "1 2\n").unwrap();
+        assert!(device.brightness().is_err());
+    }
+
+    #[test]
+    fn test_writable_by() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new().unwrap();
+        let file = root.path().join("brightness");
+        fs::write(&file, "0").unwrap();
+        // The mode as the file system has it, for a file of user 1000 and
+        // group 100.
+        let writable = |mode: u32, uid: u32, groups: &[u32]| {
+            fs::set_permissions(&file, fs::Permissions::from_mode(mode)).unwrap();
+            let mode = fs::metadata(&file).unwrap().mode();
+            writable_by(mode, 1000, 100, uid, groups)
+        };
+
+        assert!(writable(0o644, 1000, &[]));
+        assert!(!writable(0o444, 1000, &[100]));
+        assert!(!writable(0o644, 1001, &[100]));
+        assert!(writable(0o664, 1001, &[10, 100]));
+        assert!(!writable(0o664, 1001, &[10]));
+        assert!(writable(0o646, 1001, &[]));
+        assert!(writable(0o444, 0, &[]));
+    }
+
+    #[test]
+    fn test_parse_value() {
+        let file = Path::new("brightness");
+        assert_eq!(parse_value("255\n", file).unwrap(), 255);
+        assert_eq!(parse_value("255", file).unwrap(), 255);
+        assert_eq!(parse_value("255\r\n", file).unwrap(), 255);
+        assert_eq!(parse_value("70000\n", file).unwrap(), 70000);
+        assert_eq!(parse_value("16777215", file).unwrap(), 16_777_215);
+        assert!(parse_value("", file).is_err());
+        assert!(parse_value("-1", file).is_err());
+        assert!(parse_value("max\n", file).is_err());
+    }
+
+    #[test]
+    fn test_above_max_brightness() {
+        let root = TempDir::new().unwrap();
+        let path = fake_device(root.path(), "leds", "asus::kbd_backlight", 5, 3);
+        let device = LedDevice::new(path).unwrap();
+        assert_eq!(device.brightness().unwrap(), 3);
+        assert_eq!(device.percentage().unwrap(), 100.0);
+    }
+
+    #[test]
+    fn test_missing_max_brightness() {
+        let root = TempDir::new().unwrap();
+        let path = fake_device(root.path(), "leds", "input3::capslock", 0, 1);
+        fs::remove_file(path.join("max_brightness")).unwrap();
+        match LedDevice::new(path) {
+            Err(BlockError(_, message)) => assert!(message.contains("max_brightness")),
+            _ => panic!("expected a device without max_brightness to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_unwritable_brightness() {
+        let root = TempDir::new().unwrap();
+        let path = fake_device(root.path(), "leds", "input3::capslock", 0, 1);
+        let device = LedDevice::new(path.clone()).unwrap();
+        // Not even root can write to a directory, neither can logind set the
+        // brightness of a device that does not exist.
+        fs::remove_file(path.join("brightness")).unwrap();
+        fs::create_dir(path.join("brightness")).unwrap();
+        match device.set_brightness(1) {
+            Err(BlockError(_, message)) => assert!(message.contains("input3::capslock")),
+            _ => panic!("expected writing to fail"),
+        }
+    }
+
+    #[test]
+    fn test_scaling() {
+        assert_eq!(percent_to_raw(50.0, 100, 1.0), 50);
+        assert_eq!(percent_to_raw(50.0, 100, 2.0), 25);
+        assert_eq!(percent_to_raw(150.0, 100, 2.0), 100);
+        assert_eq!(percent_to_raw(-5.0, 100, 2.0), 0);
+        assert_eq!(raw_to_percent(25, 100, 2.0), 50.0);
+        let raw = percent_to_raw(70.0, 255, 2.0);
+        assert!((raw_to_percent(raw, 255, 2.0) - 70.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_scaling_round_trip() {
+        // Setting then reading a percentage gives it back but for what one
+        // raw value spans, however many levels and whatever the curve.
+        for &max in &[1, 3, 100, 255, 70000] {
+            for &scaling in &[0.1, 0.5, 1.0, 2.0, 3.5, 10.0] {
+                for percent in 0..=100 {
+                    let percent = percent as f64;
+                    let raw = percent_to_raw(percent, max, scaling);
+                    assert!(raw <= max);
+                    let below = raw_to_percent(raw.saturating_sub(1), max, scaling);
+                    let above = raw_to_percent((raw + 1).min(max), max, scaling);
+                    assert!(
+                        below <= percent && percent <= above,
+                        "{}% of {} with scaling {} set {}",
+                        percent,
+                        max,
+                        scaling,
+                        raw
+                    );
+                }
+            }
+        }
+    }
+}
@@ -12,9 +12,11 @@ use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::scheduler::Task;
 use crate::util::FormatTemplate;
 use crate::widget::{I3BarWidget, State};
+use crate::widgets::graph::{GraphConfig, GraphWidget};
 use crate::widgets::text::TextWidget;
 
 pub struct Load {
@@ -26,6 +28,7 @@ pub struct Load {
     minimum_info: f32,
     minimum_warning: f32,
     minimum_critical: f32,
+    graph: Option<GraphWidget>,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -50,6 +53,10 @@ pub struct LoadConfig {
     /// Minimum load, where state is set to critical
     #[serde(default = "LoadConfig::default_critical")]
     pub critical: f32,
+
+    /// Show a graph of the recent 1 minute load next to the text
+    #[serde(default)]
+    pub graph: Option<GraphConfig>,
 }
 
 impl LoadConfig {
@@ -82,6 +89,11 @@ impl ConfigBlock for Load {
         config: Config,
         _tx_update_request: Sender<Task>,
     ) -> Result<Self> {
+        let graph = block_config.graph.map(|graph| {
+            GraphWidget::new(config.clone())
+                .with_graph(&graph)
+                .with_range(Some(0.0), None)
+        });
         let text = TextWidget::new(config)
             .with_icon("cogs")
             .with_state(State::Info);
@@ -113,6 +125,7 @@ impl ConfigBlock for Load {
             format: FormatTemplate::from_string(&block_config.format)
                 .block_error("load", "Invalid format specified for load")?,
             text,
+            graph,
         })
     }
 }
@@ -132,21 +145,29 @@ impl Block for Load {
 
         let split: Vec<&str> = (&loadavg).split(' ').collect();
 
-        let values = map!("{1m}" => split[0],
-                          "{5m}" => split[1],
-                          "{15m}" => split[2]);
+        let mut loads = [0f32; 3];
+        for (load, value) in loads.iter_mut().zip(split.iter()) {
+            *load = value
+                .parse::<f32>()
+                .block_error("load", "failed to parse float percentage")?;
+        }
+        let values = map!("{1m}" => Value::float(loads[0], 2),
+                          "{5m}" => Value::float(loads[1], 2),
+                          "{15m}" => Value::float(loads[2], 2));
 
-        let used_perc = values["{1m}"]
-            .parse::<f32>()
-            .block_error("load", "failed to parse float percentage")?
-            / self.logical_cores as f32;
+        let used_perc = loads[0] / self.logical_cores as f32;
 
-        self.text.set_state(match used_perc {
+        let state = match used_perc {
             x if x > self.minimum_critical => State::Critical,
             x if x > self.minimum_warning => State::Warning,
             x if x > self.minimum_info => State::Info,
             _ => State::Idle,
-        });
+        };
+        self.text.set_state(state);
+        if let Some(ref mut graph) = self.graph {
+            graph.set_state(state);
+            graph.push(loads[0]);
+        }
 
         self.text.set_text(self.format.render_static_str(&values)?);
 
@@ -154,7 +175,10 @@ impl Block for Load {
     }
 
     fn view(&self) -> Vec<&dyn I3BarWidget> {
-        vec![&self.text]
+        match self.graph {
+            Some(ref graph) => vec![&self.text, graph],
+            None => vec![&self.text],
+        }
     }
 
     fn id(&self) -> &str {
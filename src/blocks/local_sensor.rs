@@ -0,0 +1,298 @@
+use std::env;
+use std::process::Command;
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::temperature::read_sensors;
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::I3BarEvent;
+use crate::scheduler::Task;
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalSensorDriver {
+    /// Any hwmon chip known to lm-sensors.
+    #[default]
+    Hwmon,
+    /// A command printing `{"temp": 21.5, "humidity": 40}`.
+    Command,
+}
+
+/// A single measurement. Either value may be missing if the sensor does not
+/// provide it.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+struct Reading {
+    temp: Option<f64>,
+    humidity: Option<f64>,
+}
+
+pub struct LocalSensor {
+    text: TextWidget,
+    id: String,
+    update_interval: Duration,
+    format: FormatTemplate,
+    driver: LocalSensorDriver,
+    chip: Option<String>,
+    inputs: Option<Vec<String>>,
+    command: Option<String>,
+    temp_warning: Option<f64>,
+    temp_critical: Option<f64>,
+    humidity_warning: Option<f64>,
+    humidity_critical: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LocalSensorConfig {
+    /// Update interval in seconds
+    #[serde(
+        default = "LocalSensorConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    /// Where to read the sensor from
+    #[serde(default = "LocalSensorDriver::default")]
+    pub driver: LocalSensorDriver,
+
+    /// Format override
+    #[serde(default = "LocalSensorConfig::default_format")]
+    pub format: String,
+
+    /// Chip override (hwmon driver)
+    #[serde(default = "LocalSensorConfig::default_chip")]
+    pub chip: Option<String>,
+
+    /// Inputs whitelist (hwmon driver)
+    #[serde(default = "LocalSensorConfig::default_inputs")]
+    pub inputs: Option<Vec<String>>,
+
+    /// Shell command printing a JSON reading (command driver)
+    #[serde(default = "LocalSensorConfig::default_command")]
+    pub command: Option<String>,
+
+    /// Temperature from which state is set to warning
+    #[serde(default = "LocalSensorConfig::default_threshold")]
+    pub temp_warning: Option<f64>,
+
+    /// Temperature from which state is set to critical
+    #[serde(default = "LocalSensorConfig::default_threshold")]
+    pub temp_critical: Option<f64>,
+
+    /// Relative humidity from which state is set to warning
+    #[serde(default = "LocalSensorConfig::default_threshold")]
+    pub humidity_warning: Option<f64>,
+
+    /// Relative humidity from which state is set to critical
+    #[serde(default = "LocalSensorConfig::default_threshold")]
+    pub humidity_critical: Option<f64>,
+}
+
+impl LocalSensorConfig {
+    fn default_interval() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn default_format() -> String {
+        "{temp}° {humidity}%".to_owned()
+    }
+
+    fn default_chip() -> Option<String> {
+        None
+    }
+
+    fn default_inputs() -> Option<Vec<String>> {
+        None
+    }
+
+    fn default_command() -> Option<String> {
+        None
+    }
+
+    fn default_threshold() -> Option<f64> {
+        None
+    }
+}
+
+impl ConfigBlock for LocalSensor {
+    type Config = LocalSensorConfig;
+
+    fn new(
+        block_config: Self::Config,
+        config: Config,
+        _tx_update_request: Sender<Task>,
+    ) -> Result<Self> {
+        if let LocalSensorDriver::Command = block_config.driver {
+            if block_config.command.is_none() {
+                return Err(BlockError(
+                    "local_sensor".to_owned(),
+                    "the command driver requires `command` to be set".to_owned(),
+                ));
+            }
+        }
+
+        Ok(LocalSensor {
+            text: TextWidget::new(config).with_icon("thermometer"),
+            id: Uuid::new_v4().to_simple().to_string(),
+            update_interval: block_config.interval,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("local_sensor", "Invalid format specified")?,
+            driver: block_config.driver,
+            chip: block_config.chip,
+            inputs: block_config.inputs,
+            command: block_config.command,
+            temp_warning: block_config.temp_warning,
+            temp_critical: block_config.temp_critical,
+            humidity_warning: block_config.humidity_warning,
+            humidity_critical: block_config.humidity_critical,
+        })
+    }
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+fn parse_command_output(output: &str) -> Result<Reading> {
+    serde_json::from_str(output.trim())
+        .block_error("local_sensor", "command output is not a valid reading")
+}
+
+fn threshold_state(value: Option<f64>, warning: Option<f64>, critical: Option<f64>) -> State {
+    match value {
+        Some(v) if critical.is_some_and(|c| v >= c) => State::Critical,
+        Some(v) if warning.is_some_and(|w| v >= w) => State::Warning,
+        _ => State::Idle,
+    }
+}
+
+fn format_value(value: Option<f64>) -> Value {
+    match value {
+        Some(v) => Value::float(v, 0),
+        None => Value::from("?"),
+    }
+}
+
+impl LocalSensor {
+    fn read(&self) -> Result<Reading> {
+        match self.driver {
+            LocalSensorDriver::Hwmon => {
+                let chip = self.chip.as_deref();
+                let inputs = self.inputs.as_ref();
+                Ok(Reading {
+                    temp: average(&read_sensors(chip, inputs, "temp")?),
+                    humidity: average(&read_sensors(chip, inputs, "humidity")?),
+                })
+            }
+            LocalSensorDriver::Command => {
+                // Checked in new()
+                let command = self.command.as_deref().unwrap_or_default();
+                let output = Command::new(env::var("SHELL").unwrap_or_else(|_| "sh".to_owned()))
+                    .args(["-c", command])
+                    .output()
+                    .block_error("local_sensor", "failed to run command")?;
+                parse_command_output(&String::from_utf8_lossy(&output.stdout))
+            }
+        }
+    }
+}
+
+impl Block for LocalSensor {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let reading = self.read()?;
+
+        let values = map!("{temp}" => format_value(reading.temp),
+                          "{humidity}" => format_value(reading.humidity));
+        self.text.set_text(self.format.render_static_str(&values)?);
+
+        let temp_state = threshold_state(reading.temp, self.temp_warning, self.temp_critical);
+        let humidity_state = threshold_state(
+            reading.humidity,
+            self.humidity_warning,
+            self.humidity_critical,
+        );
+        let state = match (temp_state, humidity_state) {
+            (State::Critical, _) | (_, State::Critical) => State::Critical,
+            (State::Warning, _) | (_, State::Warning) => State::Warning,
+            _ => State::Idle,
+        };
+        self.text.set_state(state);
+
+        Ok(Some(self.update_interval.into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.text]
+    }
+
+    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
+        Ok(())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command_output() {
+        assert_eq!(
+            parse_command_output("{\"temp\": 21.5, \"humidity\": 40}\n").unwrap(),
+            Reading {
+                temp: Some(21.5),
+                humidity: Some(40.0)
+            }
+        );
+        assert_eq!(
+            parse_command_output("{\"temp\": 19}").unwrap(),
+            Reading {
+                temp: Some(19.0),
+                humidity: None
+            }
+        );
+        assert!(parse_command_output("sensor not found").is_err());
+    }
+
+    #[test]
+    fn test_threshold_state() {
+        assert!(matches!(
+            threshold_state(Some(20.0), Some(25.0), Some(30.0)),
+            State::Idle
+        ));
+        assert!(matches!(
+            threshold_state(Some(25.0), Some(25.0), Some(30.0)),
+            State::Warning
+        ));
+        assert!(matches!(
+            threshold_state(Some(35.0), Some(25.0), Some(30.0)),
+            State::Critical
+        ));
+        assert!(matches!(
+            threshold_state(Some(35.0), None, None),
+            State::Idle
+        ));
+        assert!(matches!(
+            threshold_state(None, Some(25.0), Some(30.0)),
+            State::Idle
+        ));
+    }
+}
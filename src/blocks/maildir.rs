@@ -1,3 +1,4 @@
+use std::path::Path;
 use std::time::Duration;
 
 use crossbeam_channel::Sender;
@@ -8,12 +9,15 @@ use uuid::Uuid;
 use crate::blocks::Update;
 use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
-use crate::de::deserialize_duration;
+use crate::de::deserialize_opt_duration;
 use crate::errors::*;
-use crate::input::I3BarEvent;
+use crate::formatting::Value;
+use crate::registry::Values;
 use crate::scheduler::Task;
+use crate::stop::Stop;
+use crate::util::{watch_dirs, FormatTemplate};
 use crate::widget::{I3BarWidget, State};
-use crate::widgets::text::TextWidget;
+use crate::widgets::button::ButtonWidget;
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -40,24 +44,25 @@ impl Default for MailType {
 }
 
 pub struct Maildir {
-    text: TextWidget,
+    text: ButtonWidget,
     id: String,
-    update_interval: Duration,
+    update_interval: Option<Duration>,
     inboxes: Vec<String>,
     threshold_warning: usize,
     threshold_critical: usize,
     display_type: MailType,
+    format: FormatTemplate,
+    count: usize,
+    /// Stops watching for changes when the block is dropped.
+    _watcher: Stop,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct MaildirConfig {
-    /// Update interval in seconds
-    #[serde(
-        default = "MaildirConfig::default_interval",
-        deserialize_with = "deserialize_duration"
-    )]
-    pub interval: Duration,
+    /// Update interval in seconds, on top of the updates when mails arrive
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    pub interval: Option<Duration>,
     pub inboxes: Vec<String>,
     #[serde(default = "MaildirConfig::default_threshold_warning")]
     pub threshold_warning: usize,
@@ -67,12 +72,11 @@ pub struct MaildirConfig {
     pub display_type: MailType,
     #[serde(default = "MaildirConfig::default_icon")]
     pub icon: bool,
+    #[serde(default = "MaildirConfig::default_format")]
+    pub format: String,
 }
 
 impl MaildirConfig {
-    fn default_interval() -> Duration {
-        Duration::from_secs(5)
-    }
     fn default_threshold_warning() -> usize {
         1 as usize
     }
@@ -82,6 +86,9 @@ impl MaildirConfig {
     fn default_icon() -> bool {
         true
     }
+    fn default_format() -> String {
+        "{count}".to_owned()
+    }
 }
 
 impl ConfigBlock for Maildir {
@@ -90,11 +97,20 @@ impl ConfigBlock for Maildir {
     fn new(
         block_config: Self::Config,
         config: Config,
-        _tx_update_request: Sender<Task>,
+        tx_update_request: Sender<Task>,
     ) -> Result<Self> {
-        let widget = TextWidget::new(config).with_text("");
+        let id = Uuid::new_v4().to_simple().to_string();
+        // Mails arrive in `new` and are moved to `cur` once seen.
+        let dirs: Vec<_> = block_config
+            .inboxes
+            .iter()
+            .flat_map(|inbox| vec![Path::new(inbox).join("new"), Path::new(inbox).join("cur")])
+            .collect();
+        let watcher = watch_dirs("maildir", &dirs, id.clone(), tx_update_request)?;
+
+        let widget = ButtonWidget::new(config, &id).with_text("");
         Ok(Maildir {
-            id: Uuid::new_v4().to_simple().to_string(),
+            id,
             update_interval: block_config.interval,
             text: if block_config.icon {
                 widget.with_icon("mail")
@@ -105,6 +121,10 @@ impl ConfigBlock for Maildir {
             threshold_warning: block_config.threshold_warning,
             threshold_critical: block_config.threshold_critical,
             display_type: block_config.display_type,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("maildir", "Invalid format specified for maildir")?,
+            count: 0,
+            _watcher: watcher,
         })
     }
 }
@@ -124,19 +144,79 @@ impl Block for Maildir {
             state = State::Warning;
         }
         self.text.set_state(state);
-        self.text.set_text(format!("{}", newmails));
-        Ok(Some(self.update_interval.into()))
+        let values = map!("{count}" => Value::integer(newmails));
+        self.text.set_text(self.format.render_static_str(&values)?);
+        self.count = newmails;
+        Ok(self.update_interval.map(Into::into))
     }
 
     fn view(&self) -> Vec<&dyn I3BarWidget> {
         vec![&self.text]
     }
 
-    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
-        Ok(())
+    fn values(&self) -> Values {
+        let mut values = Values::new();
+        values.insert("count".to_owned(), self.count as f64);
+        values
     }
 
     fn id(&self) -> &str {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_updates_on_new_mail() {
+        let dir = TempDir::new().unwrap();
+        for sub in &["new", "cur", "tmp"] {
+            fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut block = Maildir::new(
+            toml::from_str(&format!("inboxes = [{:?}]", dir.path())).unwrap(),
+            Config::default(),
+            tx,
+        )
+        .unwrap();
+        assert!(block.update().unwrap().is_none());
+        assert_eq!(block.text.widget_state().text, "0");
+
+        fs::write(dir.path().join("tmp/1"), "Subject: hi\n\n").unwrap();
+        fs::rename(dir.path().join("tmp/1"), dir.path().join("new/1")).unwrap();
+        let task = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(task.id, block.id);
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "1");
+    }
+
+    #[test]
+    fn test_format() {
+        let dir = TempDir::new().unwrap();
+        for sub in &["new", "cur"] {
+            fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        fs::write(dir.path().join("new/1"), "Subject: hi\n\n").unwrap();
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        let mut block = Maildir::new(
+            toml::from_str(&format!(
+                "inboxes = [{:?}]\nformat = \"{{count:02}} new\"",
+                dir.path()
+            ))
+            .unwrap(),
+            Config::default(),
+            tx,
+        )
+        .unwrap();
+        block.update().unwrap();
+        assert_eq!(block.text.widget_state().text, "01 new");
+    }
+}
@@ -12,6 +12,7 @@ use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
 use crate::util::*;
@@ -43,6 +44,13 @@ impl fmt::Display for Unit {
 }
 
 impl Unit {
+    fn value(&self) -> Value {
+        match *self {
+            Unit::MiB(n) | Unit::KiB(n) => Value::integer(n),
+            Unit::GiB(n) => Value::float(n, 1),
+        }
+    }
+
     fn n(&self) -> u64 {
         match self.kib() {
             Unit::KiB(n) => n,
@@ -269,50 +277,50 @@ impl Memory {
         let mem_avail = Unit::KiB(mem_total.n() - mem_used.n());
 
         let values = map!(
-            "{MTg}" => format!("{:.1}", mem_total.gib()),
-            "{MTm}" => format!("{}", mem_total.mib()),
-            "{MFg}" => format!("{:.1}", mem_free.gib()),
-            "{MFm}" => format!("{}", mem_free.mib()),
-            "{MFp}" => format!("{:.2}", mem_free.percent(mem_total)),
-            "{MFpi}" => format!("{:02}", mem_free.percent(mem_total) as i32),
-            "{MFpb}" => format_percent_bar(mem_free.percent(mem_total)),
-            "{MUg}" => format!("{:.1}", mem_total_used.gib()),
-            "{MUm}" => format!("{}", mem_total_used.mib()),
-            "{MUp}" => format!("{:.2}", mem_total_used.percent(mem_total)),
-            "{MUpi}" => format!("{:02}", mem_total_used.percent(mem_total) as i32),
-            "{MUpb}" => format_percent_bar(mem_total_used.percent(mem_total)),
-            "{Mug}" => format!("{:.1}", mem_used.gib()),
-            "{Mum}" => format!("{}", mem_used.mib()),
-            "{Mup}" => format!("{:.2}", mem_used.percent(mem_total)),
-            "{Mupi}" => format!("{:02}", mem_used.percent(mem_total) as i32),
-            "{Mupb}" => format_percent_bar(mem_used.percent(mem_total)),
-            "{MAg}" => format!("{:.1}", mem_avail.gib()),
-            "{MAm}" => format!("{}", mem_avail.mib()),
-            "{MAp}" => format!("{:.2}", mem_avail.percent(mem_total)),
-            "{MApi}" => format!("{:02}", mem_avail.percent(mem_total) as i32),
-            "{MApb}" => format_percent_bar(mem_avail.percent(mem_total)),
-            "{STg}" => format!("{:.1}", swap_total.gib()),
-            "{STm}" => format!("{}", swap_total.mib()),
-            "{SFg}" => format!("{:.1}", swap_free.gib()),
-            "{SFm}" => format!("{}", swap_free.mib()),
-            "{SFp}" => format!("{:.2}", swap_free.percent(swap_total)),
-            "{SFpi}" => format!("{:02}", swap_free.percent(swap_total) as i32),
-            "{SFpb}" => format_percent_bar(swap_free.percent(swap_total)),
-            "{SUg}" => format!("{:.1}", swap_used.gib()),
-            "{SUm}" => format!("{}", swap_used.mib()),
-            "{SUp}" => format!("{:.2}", swap_used.percent(swap_total)),
-            "{SUpi}" => format!("{:02}", swap_used.percent(swap_total) as i32),
-            "{SUpb}" => format_percent_bar(swap_used.percent(swap_total)),
-            "{Bg}" => format!("{:.1}", buffers.gib()),
-            "{Bm}" => format!("{}", buffers.mib()),
-            "{Bp}" => format!("{:.2}", buffers.percent(mem_total)),
-            "{Bpi}" => format!("{:02}", buffers.percent(mem_total) as i32),
-            "{Bpb}" => format_percent_bar(buffers.percent(mem_total)),
-            "{Cg}" => format!("{:.1}", cached.gib()),
-            "{Cm}" => format!("{}", cached.mib()),
-            "{Cp}" => format!("{:.2}", cached.percent(mem_total)),
-            "{Cpi}" => format!("{:02}", cached.percent(mem_total) as i32),
-            "{Cpb}" => format_percent_bar(cached.percent(mem_total)));
+            "{MTg}" => mem_total.gib().value(),
+            "{MTm}" => mem_total.mib().value(),
+            "{MFg}" => mem_free.gib().value(),
+            "{MFm}" => mem_free.mib().value(),
+            "{MFp}" => Value::float(mem_free.percent(mem_total), 2),
+            "{MFpi}" => Value::integer(mem_free.percent(mem_total) as i32).zero_padded(2),
+            "{MFpb}" => Value::bar(mem_free.percent(mem_total)),
+            "{MUg}" => mem_total_used.gib().value(),
+            "{MUm}" => mem_total_used.mib().value(),
+            "{MUp}" => Value::float(mem_total_used.percent(mem_total), 2),
+            "{MUpi}" => Value::integer(mem_total_used.percent(mem_total) as i32).zero_padded(2),
+            "{MUpb}" => Value::bar(mem_total_used.percent(mem_total)),
+            "{Mug}" => mem_used.gib().value(),
+            "{Mum}" => mem_used.mib().value(),
+            "{Mup}" => Value::float(mem_used.percent(mem_total), 2),
+            "{Mupi}" => Value::integer(mem_used.percent(mem_total) as i32).zero_padded(2),
+            "{Mupb}" => Value::bar(mem_used.percent(mem_total)),
+            "{MAg}" => mem_avail.gib().value(),
+            "{MAm}" => mem_avail.mib().value(),
+            "{MAp}" => Value::float(mem_avail.percent(mem_total), 2),
+            "{MApi}" => Value::integer(mem_avail.percent(mem_total) as i32).zero_padded(2),
+            "{MApb}" => Value::bar(mem_avail.percent(mem_total)),
+            "{STg}" => swap_total.gib().value(),
+            "{STm}" => swap_total.mib().value(),
+            "{SFg}" => swap_free.gib().value(),
+            "{SFm}" => swap_free.mib().value(),
+            "{SFp}" => Value::float(swap_free.percent(swap_total), 2),
+            "{SFpi}" => Value::integer(swap_free.percent(swap_total) as i32).zero_padded(2),
+            "{SFpb}" => Value::bar(swap_free.percent(swap_total)),
+            "{SUg}" => swap_used.gib().value(),
+            "{SUm}" => swap_used.mib().value(),
+            "{SUp}" => Value::float(swap_used.percent(swap_total), 2),
+            "{SUpi}" => Value::integer(swap_used.percent(swap_total) as i32).zero_padded(2),
+            "{SUpb}" => Value::bar(swap_used.percent(swap_total)),
+            "{Bg}" => buffers.gib().value(),
+            "{Bm}" => buffers.mib().value(),
+            "{Bp}" => Value::float(buffers.percent(mem_total), 2),
+            "{Bpi}" => Value::integer(buffers.percent(mem_total) as i32).zero_padded(2),
+            "{Bpb}" => Value::bar(buffers.percent(mem_total)),
+            "{Cg}" => cached.gib().value(),
+            "{Cm}" => cached.mib().value(),
+            "{Cp}" => Value::float(cached.percent(mem_total), 2),
+            "{Cpi}" => Value::integer(cached.percent(mem_total) as i32).zero_padded(2),
+            "{Cpb}" => Value::bar(cached.percent(mem_total)));
 
         match self.memtype {
             Memtype::Memory => self.output.0.set_state(match mem_used.percent(mem_total) {
@@ -1,30 +1,36 @@
 use std::boxed::Box;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crossbeam_channel::Sender;
+use crossbeam_channel::{RecvTimeoutError, Sender};
 use dbus::arg::{Array, RefArg};
 use dbus::ffidisp::stdintf::org_freedesktop_dbus::Properties;
 use dbus::{
     arg,
-    ffidisp::{BusType, Connection, ConnectionItem},
+    ffidisp::{BusType, Connection},
     Message,
 };
 use serde_derive::Deserialize;
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
+use crate::dbus_manager::{self, Bus};
 use crate::de::deserialize_duration;
 use crate::errors::*;
-use crate::input::I3BarEvent;
+use crate::input::{ClickAction, ClickBindings, I3BarEvent};
 use crate::scheduler::Task;
 use crate::subprocess::spawn_child_async;
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::button::ButtonWidget;
 use crate::widgets::rotatingtext::RotatingTextWidget;
 
+/// How long changes to the set of running players are collected before the
+/// block is updated.
+const PLAYER_CHANGE_DEBOUNCE: Duration = Duration::from_millis(500);
+
 pub struct Music {
     id: String,
     current_song: RotatingTextWidget,
@@ -34,6 +40,8 @@ pub struct Music {
     on_collapsed_click_widget: ButtonWidget,
     on_collapsed_click: Option<String>,
     dbus_conn: Connection,
+    /// Bus names of the running players, in the order they appeared.
+    players: Arc<Mutex<Vec<String>>>,
     player_avail: bool,
     marquee: bool,
     player: Option<String>,
@@ -41,6 +49,7 @@ pub struct Music {
     smart_trim: bool,
     max_width: usize,
     separator: String,
+    click: ClickBindings<MusicAction>,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -91,6 +100,33 @@ pub struct MusicConfig {
 
     #[serde(default = "MusicConfig::default_on_collapsed_click")]
     pub on_collapsed_click: Option<String>,
+
+    /// Actions bound to clicks anywhere on the block, replacing what the
+    /// buttons do
+    #[serde(default)]
+    pub click: ClickBindings<MusicAction>,
+}
+
+/// What a click on the music block can do.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MusicAction {
+    PlayPause,
+    Next,
+    Prev,
+    Stop,
+}
+
+impl MusicAction {
+    /// The MPRIS method of the player doing this.
+    fn method(self) -> &'static str {
+        match self {
+            MusicAction::PlayPause => "PlayPause",
+            MusicAction::Next => "Next",
+            MusicAction::Prev => "Previous",
+            MusicAction::Stop => "Stop",
+        }
+    }
 }
 
 impl MusicConfig {
@@ -138,22 +174,82 @@ impl ConfigBlock for Music {
         let id: String = Uuid::new_v4().to_simple().to_string();
         let id_copy = id.clone();
 
-        thread::Builder::new().name("music".into()).spawn(move || {
-            let c = Connection::get_private(BusType::Session).unwrap();
-            c.add_match("interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',path='/org/mpris/MediaPlayer2'")
-                .unwrap();
-            loop {
-                for ci in c.iter(100_000) {
-                    if let ConnectionItem::Signal(_) = ci {
-                        send.send(Task {
-                            id: id.clone(),
-                            update_time: Instant::now(),
-                        })
-                        .unwrap();
+        let dbus_conn = Connection::get_private(BusType::Session)
+            .block_error("music", "failed to establish D-Bus connection")?;
+        let players = Arc::new(Mutex::new(list_players(&dbus_conn)?));
+        let players_copy = players.clone();
+
+        dbus_manager::request_updates(
+            Bus::Session,
+            "type='signal',\
+             interface='org.freedesktop.DBus.Properties',\
+             member='PropertiesChanged',\
+             path='/org/mpris/MediaPlayer2'",
+            id.clone(),
+            send.clone(),
+        )
+        .block_error("music", "failed to add D-Bus match rule")?;
+        let (owner_changes, owner_changed) = crossbeam_channel::unbounded();
+        dbus_manager::subscribe(
+            Bus::Session,
+            "type='signal',\
+             interface='org.freedesktop.DBus',\
+             member='NameOwnerChanged',\
+             arg0namespace='org.mpris.MediaPlayer2'",
+            move |msg| match msg.get3::<&str, &str, &str>() {
+                (Some(name), _, Some(new_owner)) => owner_changes
+                    .send((name.to_string(), new_owner.to_string()))
+                    .is_ok(),
+                _ => true,
+            },
+        )
+        .block_error("music", "failed to add D-Bus match rule")?;
+
+        let id_thread = id.clone();
+        thread::Builder::new()
+            .name("music".into())
+            .spawn(move || {
+                // Changes to the set of players are collected for a short while
+                // before being published, so that a player quickly going away and
+                // coming back (e.g. browsers on navigation) does not flicker.
+                let mut pending: Option<(Vec<String>, Instant)> = None;
+                loop {
+                    let change = match &pending {
+                        Some((_, since)) => match owner_changed
+                            .recv_timeout(PLAYER_CHANGE_DEBOUNCE.saturating_sub(since.elapsed()))
+                        {
+                            Ok(change) => Some(change),
+                            Err(RecvTimeoutError::Timeout) => None,
+                            Err(RecvTimeoutError::Disconnected) => return,
+                        },
+                        None => match owner_changed.recv() {
+                            Ok(change) => Some(change),
+                            Err(_) => return,
+                        },
+                    };
+                    match change {
+                        Some((name, new_owner)) => {
+                            let (ref mut changed, _) = pending.get_or_insert_with(|| {
+                                (players_copy.lock().unwrap().clone(), Instant::now())
+                            });
+                            apply_owner_change(changed, &name, &new_owner);
+                        }
+                        None => {
+                            if let Some((changed, _)) = pending.take() {
+                                *players_copy.lock().unwrap() = changed;
+                                let task = Task {
+                                    id: id_thread.clone(),
+                                    update_time: Instant::now(),
+                                };
+                                if send.send(task).is_err() {
+                                    return;
+                                }
+                            }
+                        }
                     }
                 }
-            }
-        }).unwrap();
+            })
+            .block_error("music", "failed to spawn thread")?;
 
         let mut play: Option<ButtonWidget> = None;
         let mut prev: Option<ButtonWidget> = None;
@@ -191,7 +287,6 @@ impl ConfigBlock for Music {
         }
 
         Ok(Music {
-            id: id_copy,
             current_song: RotatingTextWidget::new(
                 Duration::new(block_config.marquee_interval.as_secs(), 0),
                 Duration::new(0, block_config.marquee_speed.subsec_nanos()),
@@ -200,6 +295,7 @@ impl ConfigBlock for Music {
                 config.clone(),
             )
             .with_icon("music")
+            .with_name(&id_copy)
             .with_state(State::Info),
             prev,
             play,
@@ -208,8 +304,8 @@ impl ConfigBlock for Music {
                 .with_icon("music")
                 .with_state(State::Info),
             on_collapsed_click: block_config.on_collapsed_click,
-            dbus_conn: Connection::get_private(BusType::Session)
-                .block_error("music", "failed to establish D-Bus connection")?,
+            dbus_conn,
+            players,
             player_avail: false,
             auto_discover: block_config.player.is_none(),
             player: if block_config.player.is_none() {
@@ -224,6 +320,8 @@ impl ConfigBlock for Music {
             smart_trim: block_config.smart_trim,
             max_width: block_config.max_width,
             separator: block_config.separator,
+            click: block_config.click,
+            id: id_copy,
         })
     }
 }
@@ -239,8 +337,21 @@ impl Block for Music {
         } else {
             (false, None)
         };
-        if !rotated && self.player.is_none() {
-            self.player = get_first_available_player(&self.dbus_conn)
+        if !rotated {
+            let players = self
+                .players
+                .lock()
+                .block_error("music", "failed to acquire lock")?;
+            let available = select_player(self.player.as_deref(), &players, self.auto_discover);
+            if self.auto_discover {
+                self.player = available.clone();
+            }
+            if available.is_none() {
+                // The player went away, don't keep showing its last song.
+                self.player_avail = false;
+                self.current_song.set_text(String::new());
+                return Ok(Some(Duration::new(2, 0).into()));
+            }
         }
         if !(rotated || self.player.is_none()) {
             let c = self.dbus_conn.with_path(
@@ -383,38 +494,56 @@ impl Block for Music {
     }
 
     fn click(&mut self, event: &I3BarEvent) -> Result<()> {
-        if let Some(ref name) = event.name {
-            let action = match name as &str {
-                "play" => "PlayPause",
-                "next" => "Next",
-                "prev" => "Previous",
-                _ => "",
-            };
-            if action != "" {
+        let name = match event.name {
+            Some(ref name) => name.as_str(),
+            None => return Ok(()),
+        };
+        if name != self.id && !["play", "next", "prev", "on_collapsed_click"].contains(&name) {
+            return Ok(());
+        }
+        let action = match self.click.get(event).cloned() {
+            Some(ClickAction::Block(action)) => Some(action),
+            Some(ClickAction::Command { cmd }) => {
+                spawn_child_async("sh", &["-c", &cmd])
+                    .block_error("music", "could not spawn child")?;
+                None
+            }
+            None => match name {
+                "play" => Some(MusicAction::PlayPause),
+                "next" => Some(MusicAction::Next),
+                "prev" => Some(MusicAction::Prev),
+                "on_collapsed_click" => {
+                    if let Some(ref command) = self.on_collapsed_click {
+                        spawn_child_async("sh", &["-c", command])
+                            .block_error("music", "could not spawn child")?;
+                    }
+                    None
+                }
+                _ => None,
+            },
+        };
+        match (action, &self.player) {
+            (Some(action), Some(player)) => {
                 let m = Message::new_method_call(
-                    self.player.as_ref().unwrap(),
+                    player,
                     "/org/mpris/MediaPlayer2",
                     "org.mpris.MediaPlayer2.Player",
-                    action,
+                    action.method(),
                 )
                 .block_error("music", "failed to create D-Bus method call")?;
                 self.dbus_conn
                     .send(m)
                     .block_error("music", "failed to call method via D-Bus")
                     .map(|_| ())
-            } else {
-                if name == "on_collapsed_click" && self.on_collapsed_click.is_some() {
-                    let command = self.on_collapsed_click.as_ref().unwrap();
-                    spawn_child_async("sh", &["-c", command])
-                        .block_error("music", "could not spawn child")?;
-                }
-                Ok(())
             }
-        } else {
-            Ok(())
+            _ => Ok(()),
         }
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn view(&self) -> Vec<&dyn I3BarWidget> {
         if self.player_avail {
             let mut elements: Vec<&dyn I3BarWidget> = Vec::new();
@@ -482,20 +611,77 @@ fn extract_from_metadata(metadata: &Box<dyn arg::RefArg>) -> Result<(String, Str
     Ok((title, artist))
 }
 
-fn get_first_available_player(connection: &Connection) -> Option<String> {
+/// The bus names of all running MPRIS players.
+fn list_players(connection: &Connection) -> Result<Vec<String>> {
     let m = Message::new_method_call(
         "org.freedesktop.DBus",
         "/",
         "org.freedesktop.DBus",
         "ListNames",
     )
-    .unwrap();
-    let r = connection.send_with_reply_and_block(m, 2000).unwrap();
+    .block_error("music", "failed to create D-Bus method call")?;
+    let r = connection
+        .send_with_reply_and_block(m, 2000)
+        .block_error("music", "failed to list D-Bus names")?;
     // ListNames returns one argument, which is an array of strings.
-    let mut arr: Array<&str, _> = r.get1().unwrap();
-    if let Some(name) = arr.find(|entry| entry.starts_with("org.mpris.MediaPlayer2")) {
-        Some(String::from(name))
-    } else {
-        None
+    let arr: Array<&str, _> = r
+        .get1()
+        .block_error("music", "failed to parse D-Bus names")?;
+    Ok(arr
+        .filter(|entry| entry.starts_with("org.mpris.MediaPlayer2."))
+        .map(String::from)
+        .collect())
+}
+
+/// Updates the list of running players after a NameOwnerChanged signal. An
+/// empty new owner means the name was released.
+fn apply_owner_change(players: &mut Vec<String>, name: &str, new_owner: &str) {
+    players.retain(|player| player != name);
+    if !new_owner.is_empty() {
+        players.push(name.to_string());
+    }
+}
+
+/// The player to display. With auto-discovery the current player is kept
+/// while it is running, otherwise the first running player is picked.
+fn select_player(current: Option<&str>, players: &[String], auto_discover: bool) -> Option<String> {
+    match current {
+        Some(current) if players.iter().any(|p| p == current) => Some(current.to_string()),
+        _ if auto_discover => players.first().cloned(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_owner_change() {
+        let mut players = vec!["org.mpris.MediaPlayer2.spotify".to_string()];
+        apply_owner_change(&mut players, "org.mpris.MediaPlayer2.vlc", ":1.42");
+        apply_owner_change(&mut players, "org.mpris.MediaPlayer2.spotify", "");
+        assert_eq!(players, vec!["org.mpris.MediaPlayer2.vlc"]);
+        // A restart of the same player keeps a single entry.
+        apply_owner_change(&mut players, "org.mpris.MediaPlayer2.vlc", "");
+        apply_owner_change(&mut players, "org.mpris.MediaPlayer2.vlc", ":1.43");
+        assert_eq!(players, vec!["org.mpris.MediaPlayer2.vlc"]);
+    }
+
+    #[test]
+    fn test_select_player() {
+        let players = vec![
+            "org.mpris.MediaPlayer2.spotify".to_string(),
+            "org.mpris.MediaPlayer2.vlc".to_string(),
+        ];
+        let vlc = Some("org.mpris.MediaPlayer2.vlc");
+        let mpv = Some("org.mpris.MediaPlayer2.mpv");
+        assert_eq!(select_player(vlc, &players, true).as_deref(), vlc);
+        assert_eq!(
+            select_player(mpv, &players, true).as_deref(),
+            Some("org.mpris.MediaPlayer2.spotify")
+        );
+        assert_eq!(select_player(mpv, &players, false), None);
+        assert_eq!(select_player(None, &[], true), None);
     }
 }
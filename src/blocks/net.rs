@@ -2,12 +2,19 @@ use std::fmt;
 use std::fs::read_to_string;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::{Duration, Instant};
 
 use crossbeam_channel::Sender;
 use lazy_static::lazy_static;
+use nix::errno::Errno;
+use nix::sys::socket::{bind, recv, socket, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType};
+use nix::unistd;
 use regex::bytes::Regex;
 use serde_derive::Deserialize;
 use uuid::Uuid;
@@ -17,19 +24,25 @@ use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
-use crate::input::{I3BarEvent, MouseButton};
+use crate::formatting::{self, Prefix, Spec, Value};
 use crate::scheduler::Task;
-use crate::subprocess::spawn_child_async;
-use crate::util::{
-    escape_pango_text, format_percent_bar, format_speed, format_vec_to_bar_graph, FormatTemplate,
-};
+use crate::stop::{self, Stop, Wake};
+use crate::util::{escape_pango_text, format_percent_bar, format_speed, FormatTemplate};
 use crate::widget::I3BarWidget;
 use crate::widgets::button::ButtonWidget;
+use crate::widgets::graph::{GraphConfig, History};
 use std::ffi::OsStr;
 use std::io::ErrorKind;
 
+/// The rtnetlink multicast groups of changes to links, which includes wireless
+/// associations, and to IPv4 and IPv6 addresses and routes.
+const RTMGRP_LINK: u32 = 0x1;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
 lazy_static! {
-    static ref DEFAULT_DEV_REGEX: Regex = Regex::new("default.*dev (\\w*).*").unwrap();
     static ref WHITESPACE_REGEX: Regex = Regex::new("\\s+").unwrap();
     static ref ETHTOOL_SPEED_REGEX: Regex = Regex::new("Speed: (\\d+\\w\\w/s)").unwrap();
     static ref IW_SSID_REGEX: Regex = Regex::new("SSID: (.*)").unwrap();
@@ -91,21 +104,13 @@ impl NetworkDevice {
     /// A default device is usually selected by the network manager
     /// and will change when the status of devices change.
     pub fn default_device() -> Option<String> {
-        String::from_utf8(
-            Command::new("ip")
-                .args(&["route", "show", "default"])
+        ["-4", "-6"].iter().find_map(|family| {
+            let output = Command::new("ip")
+                .args([family, "route", "show", "default"])
                 .output()
-                .ok()
-                .and_then(|o| {
-                    let mut captures = DEFAULT_DEV_REGEX.captures_iter(&o.stdout);
-                    if let Some(cap) = captures.next() {
-                        cap.get(1).map(|x| x.as_bytes().to_vec())
-                    } else {
-                        None
-                    }
-                })?,
-        )
-        .ok()
+                .ok()?;
+            default_route_device(&String::from_utf8_lossy(&output.stdout))
+        })
     }
 
     /// Check whether the device exists.
@@ -345,8 +350,108 @@ impl NetworkDevice {
     }
 }
 
+/// Requests an update of the block `id` and sets `changed` whenever a link goes
+/// up or down, associates with a wireless network, or gains or loses an address
+/// or a route. Bursts of changes, like a DHCP lease, are folded into one update
+/// every tenth of a second. Dropping the `Stop` closes the socket and ends the
+/// thread.
+fn watch_netlink(id: String, tx: Sender<Task>, changed: Arc<AtomicBool>) -> Result<Stop> {
+    // Protocol 0 is `NETLINK_ROUTE`.
+    let fd = socket(
+        AddressFamily::Netlink,
+        SockType::Raw,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .block_error("net", "failed to open netlink socket")?;
+    let groups = RTMGRP_LINK
+        | RTMGRP_IPV4_IFADDR
+        | RTMGRP_IPV4_ROUTE
+        | RTMGRP_IPV6_IFADDR
+        | RTMGRP_IPV6_ROUTE;
+    let listening = bind(fd, &SockAddr::new_netlink(0, groups))
+        .block_error("net", "failed to listen to netlink")
+        .and_then(|_| stop::pair());
+    let (stop, stopped) = match listening {
+        Ok(pair) => pair,
+        Err(error) => {
+            unistd::close(fd).ok();
+            return Err(error);
+        }
+    };
+    let spawned = thread::Builder::new().name("net".into()).spawn(move || {
+        let mut buffer = [0; 8192];
+        while stopped.wait(Some(fd), None).ok() == Some(Wake::Ready)
+            && read_netlink(fd, &mut buffer)
+            && stopped.sleep(Duration::from_millis(100))
+        {
+            // Drops what came in meanwhile.
+            while recv(fd, &mut buffer, MsgFlags::MSG_DONTWAIT).is_ok() {}
+            changed.store(true, Ordering::SeqCst);
+            let task = Task {
+                id: id.clone(),
+                update_time: Instant::now(),
+            };
+            if tx.send(task).is_err() {
+                break;
+            }
+        }
+        unistd::close(fd).ok();
+    });
+    if spawned.is_err() {
+        unistd::close(fd).ok();
+        return Err(BlockError(
+            "net".to_string(),
+            "failed to start netlink thread".to_string(),
+        ));
+    }
+    Ok(stop)
+}
+
+/// Reads a netlink message off `fd` once it has one, and tells whether it can
+/// go on.
+fn read_netlink(fd: RawFd, buffer: &mut [u8]) -> bool {
+    loop {
+        match recv(fd, buffer, MsgFlags::MSG_DONTWAIT) {
+            Ok(_) => return true,
+            // Woken up for nothing.
+            Err(nix::Error::Sys(Errno::EAGAIN)) => return true,
+            // Messages were lost, which is a change as well.
+            Err(nix::Error::Sys(Errno::ENOBUFS)) => return true,
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+/// The device of the default route with the lowest metric, from the output of
+/// `ip route show default`.
+fn default_route_device(routes: &str) -> Option<String> {
+    routes
+        .lines()
+        .filter_map(|route| {
+            let words: Vec<&str> = route.split_whitespace().collect();
+            let after = |key| {
+                words
+                    .iter()
+                    .position(|word| *word == key)
+                    .and_then(|i| words.get(i + 1))
+            };
+            let device = after("dev")?;
+            let metric = after("metric").and_then(|m| m.parse().ok()).unwrap_or(0u32);
+            Some((metric, device.to_string()))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, device)| device)
+}
+
 pub struct Net {
     format: FormatTemplate,
+    /// The format as written, to tell which values it shows.
+    format_str: String,
+    /// The units of `{speed_up}` and `{speed_down}`, if the format has them.
+    speed_up_unit: Option<Spec>,
+    speed_down_unit: Option<Spec>,
     output: ButtonWidget,
     config: Config,
     network: ButtonWidget,
@@ -365,18 +470,30 @@ pub struct Net {
     update_interval: Duration,
     device: NetworkDevice,
     auto_device: bool,
-    tx_buff: Vec<u64>,
-    rx_buff: Vec<u64>,
+    /// Set when netlink reports a change, if it does.
+    changed: Arc<AtomicBool>,
+    netlink: bool,
+    /// Stops watching netlink when the block is dropped.
+    _netlink_watcher: Option<Stop>,
+    tx_history: History,
+    rx_history: History,
+    graph_chars: String,
     tx_bytes: u64,
     rx_bytes: u64,
+    /// When `tx_bytes` and `rx_bytes` were read.
+    last_sample: Instant,
+    /// The latest speeds, in bytes per second.
+    tx_speed: u64,
+    rx_speed: u64,
     use_bits: bool,
     speed_min_unit: Unit,
+    speed_prefixes: Prefixes,
+    speed_fixed_unit: bool,
     speed_digits: usize,
     active: bool,
     hide_inactive: bool,
     hide_missing: bool,
     last_update: Instant,
-    on_click: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize)]
@@ -400,6 +517,15 @@ impl fmt::Display for Unit {
     }
 }
 
+/// Whether the `{up}` and `{down}` speeds are scaled by 1000 or by 1024.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Prefixes {
+    #[default]
+    Si,
+    Iec,
+}
+
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct NetConfig {
@@ -413,10 +539,12 @@ pub struct NetConfig {
     #[serde(default = "NetConfig::default_format")]
     pub format: String,
 
-    /// Which interface in /sys/class/net/ to read from.
-    #[serde(default = "NetConfig::default_device")]
-    pub device: String,
+    /// Which interface in /sys/class/net/ to read from, by default the one
+    /// of the default route.
+    #[serde(default)]
+    pub device: Option<String>,
 
+    /// Whether to follow the default route even though `device` is set.
     #[serde(default = "NetConfig::default_auto_device")]
     pub auto_device: bool,
 
@@ -472,6 +600,14 @@ pub struct NetConfig {
     #[serde(default = "NetConfig::default_speed_min_unit")]
     pub speed_min_unit: Unit,
 
+    /// Whether `{up}` and `{down}` have SI or IEC prefixes.
+    #[serde(default)]
+    pub speed_prefixes: Prefixes,
+
+    /// Whether `{up}` and `{down}` always keep to `speed_min_unit`.
+    #[serde(default)]
+    pub speed_fixed_unit: bool,
+
     /// Whether to show the download throughput indicator of active networks.
     #[serde(default = "NetConfig::default_speed_down")]
     pub speed_down: bool,
@@ -484,8 +620,9 @@ pub struct NetConfig {
     #[serde(default = "NetConfig::default_graph_down")]
     pub graph_down: bool,
 
-    #[serde(default = "NetConfig::default_on_click")]
-    pub on_click: Option<String>,
+    /// How many samples the throughput graphs show, and with which characters.
+    #[serde(default)]
+    pub graph: GraphConfig,
 }
 
 impl NetConfig {
@@ -497,13 +634,6 @@ impl NetConfig {
         "{speed_up} {speed_down}".to_owned()
     }
 
-    fn default_device() -> String {
-        match NetworkDevice::default_device() {
-            Some(ref s) if !s.is_empty() => s.to_string(),
-            _ => "lo".to_string(),
-        }
-    }
-
     fn default_auto_device() -> bool {
         false
     }
@@ -571,10 +701,6 @@ impl NetConfig {
     fn default_speed_digits() -> usize {
         3
     }
-
-    fn default_on_click() -> Option<String> {
-        None
-    }
 }
 
 impl ConfigBlock for Net {
@@ -583,14 +709,19 @@ impl ConfigBlock for Net {
     fn new(
         block_config: Self::Config,
         config: Config,
-        _tx_update_request: Sender<Task>,
+        tx_update_request: Sender<Task>,
     ) -> Result<Self> {
-        let device = NetworkDevice::from_device(block_config.device);
-        let init_rx_bytes = device.rx_bytes().unwrap_or(0);
-        let init_tx_bytes = device.tx_bytes().unwrap_or(0);
-        let wireless = device.is_wireless();
-        let vpn = device.is_vpn();
+        let auto_device = block_config.auto_device || block_config.device.is_none();
+        // A `device` is where an `auto_device` starts without a default route.
+        let device = if auto_device {
+            NetworkDevice::default_device().or(block_config.device)
+        } else {
+            block_config.device
+        }
+        .unwrap_or_else(|| "lo".to_string());
         let id = Uuid::new_v4().to_simple().to_string();
+        let changed = Arc::new(AtomicBool::new(true));
+        let netlink_watcher = watch_netlink(id.clone(), tx_update_request, changed.clone()).ok();
 
         let (_, net_config) = config
             .blocks
@@ -609,73 +740,59 @@ impl ConfigBlock for Net {
             block_config.format
         };
 
-        Ok(Net {
+        let template =
+            FormatTemplate::from_string(&format).block_error("net", "Invalid format specified")?;
+        let unit = |key| {
+            template
+                .spec(key)
+                .filter(|spec| spec.prefix.is_some() || spec.binary || spec.bits.is_some())
+        };
+        let mut net = Net {
             id: id.clone(),
             update_interval: block_config.interval,
-            format: FormatTemplate::from_string(&format)
-                .block_error("net", "Invalid format specified")?,
+            speed_up_unit: unit("{speed_up}"),
+            speed_down_unit: unit("{speed_down}"),
+            format: template,
+            format_str: format,
             output: ButtonWidget::new(config.clone(), "").with_text(""),
             config: config.clone(),
             use_bits: block_config.use_bits,
             speed_min_unit: block_config.speed_min_unit,
+            speed_prefixes: block_config.speed_prefixes,
+            speed_fixed_unit: block_config.speed_fixed_unit,
             speed_digits: block_config.speed_digits,
-            network: ButtonWidget::new(config, &id).with_icon(if wireless {
-                "net_wireless"
-            } else if vpn {
-                "net_vpn"
-            } else {
-                "net_wired"
-            }),
-            // Might want to signal an error if the user wants the SSID of a
-            // wired connection instead.
-            ssid: if wireless && format.contains("{ssid}") {
-                Some(" ".to_string())
-            } else {
-                None
-            },
+            network: ButtonWidget::new(config, &id),
+            ssid: None,
             max_ssid_width: block_config.max_ssid_width,
-            signal_strength: if wireless && format.contains("{signal_strength}") {
-                Some(0.to_string())
-            } else {
-                None
-            },
-            signal_strength_bar: if wireless && format.contains("{signal_strength_bar}") {
-                Some("".to_string())
-            } else {
-                None
-            },
-            // TODO: a better way to deal with this?
-            bitrate: if format.contains("{bitrate}") {
-                Some("".to_string())
-            } else {
-                None
-            },
-            ip_addr: if format.contains("{ip}") {
-                Some("".to_string())
-            } else {
-                None
-            },
-            ipv6_addr: if format.contains("{ipv6}") {
-                Some("".to_string())
-            } else {
-                None
-            },
+            signal_strength: None,
+            signal_strength_bar: None,
+            bitrate: None,
+            ip_addr: None,
+            ipv6_addr: None,
             output_tx: Some("".to_string()),
             output_rx: Some("".to_string()),
             graph_tx: Some("".to_string()),
             graph_rx: Some("".to_string()),
-            device,
-            auto_device: block_config.auto_device,
-            rx_buff: vec![0; 10],
-            tx_buff: vec![0; 10],
-            rx_bytes: init_rx_bytes,
-            tx_bytes: init_tx_bytes,
+            device: NetworkDevice::from_device(device.clone()),
+            auto_device,
+            changed,
+            netlink: netlink_watcher.is_some(),
+            _netlink_watcher: netlink_watcher,
+            rx_history: History::new(block_config.graph.length),
+            tx_history: History::new(block_config.graph.length),
+            graph_chars: block_config.graph.chars,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            last_sample: Instant::now(),
+            rx_speed: 0,
+            tx_speed: 0,
             active: true,
             hide_inactive: block_config.hide_inactive,
             hide_missing: block_config.hide_missing,
-            last_update: Instant::now() - Duration::from_secs(30),
-            on_click: block_config.on_click,
-        })
+            last_update: Instant::now(),
+        };
+        net.set_device(device);
+        Ok(net)
     }
 }
 
@@ -728,22 +845,53 @@ fn read_file(path: &Path) -> Result<String> {
 }
 
 impl Net {
+    /// Switches to the device of the default route, if it changed. Without a
+    /// default route, the last device is kept.
     fn update_device(&mut self) {
         if self.auto_device {
-            let dev = NetConfig::default_device();
-            if self.device.device() != dev {
-                self.device = NetworkDevice::from_device(dev);
-                self.network.set_icon(if self.device.is_wireless() {
-                    "net_wireless"
-                } else if self.device.is_vpn() {
-                    "net_vpn"
-                } else {
-                    "net_wired"
-                });
+            match NetworkDevice::default_device() {
+                Some(device) if device != self.device.device() => self.set_device(device),
+                _ => (),
             }
         }
     }
 
+    /// Starts showing `device`, with the icon and values that fit it.
+    fn set_device(&mut self, device: String) {
+        self.device = NetworkDevice::from_device(device);
+        let wireless = self.device.is_wireless();
+        self.network.set_icon(if wireless {
+            "net_wireless"
+        } else if self.device.is_vpn() {
+            "net_vpn"
+        } else {
+            "net_wired"
+        });
+
+        let format = &self.format_str;
+        let shown = |placeholder: &str, only_wireless: bool| {
+            if (wireless || !only_wireless) && format.contains(placeholder) {
+                Some("".to_string())
+            } else {
+                None
+            }
+        };
+        // Might want to signal an error if the user wants the SSID of a
+        // wired connection instead.
+        self.ssid = shown("{ssid}", true);
+        self.signal_strength = shown("{signal_strength}", true);
+        self.signal_strength_bar = shown("{signal_strength_bar}", true);
+        self.bitrate = shown("{bitrate}", false);
+        self.ip_addr = shown("{ip}", false);
+        self.ipv6_addr = shown("{ipv6}", false);
+
+        // The speeds are counted from here, and everything else is due.
+        self.rx_bytes = self.device.rx_bytes().unwrap_or(0);
+        self.tx_bytes = self.device.tx_bytes().unwrap_or(0);
+        self.last_sample = Instant::now();
+        self.last_update = Instant::now() - Duration::from_secs(30);
+    }
+
     fn update_bitrate(&mut self) -> Result<()> {
         if let Some(ref mut bitrate_string) = self.bitrate {
             let bitrate = self.device.bitrate()?;
@@ -801,15 +949,40 @@ impl Net {
         Ok(())
     }
 
+    /// A speed in bytes per second for the `{up}` and `{down}` placeholders,
+    /// scaled to `speed_min_unit`, or larger unless the unit is fixed.
+    fn speed_value(&self, bytes: u64) -> Value {
+        let (value, unit) = if self.use_bits {
+            (bytes * 8, "b/s")
+        } else {
+            (bytes, "B/s")
+        };
+        let prefix = match self.speed_min_unit {
+            Unit::B => Prefix::One,
+            Unit::K => Prefix::Kilo,
+            Unit::M => Prefix::Mega,
+            Unit::G => Prefix::Giga,
+            Unit::T => Prefix::Tera,
+        };
+        Value::float(value, 1)
+            .with_suffix(unit)
+            .scaled(prefix)
+            .binary(self.speed_prefixes == Prefixes::Iec)
+            .fixed(self.speed_fixed_unit)
+    }
+
     fn update_tx_rx(&mut self) -> Result<()> {
-        // TODO: consider using `as_nanos`
-        let update_interval = (self.update_interval.as_secs() as f64)
-            // Update the throughput/graph widgets if they are enabled
-            + (self.update_interval.subsec_nanos() as f64 / 1_000_000_000.0);
+        // Updates for netlink events come in between the samples.
+        let now = Instant::now();
+        let update_interval = now.duration_since(self.last_sample).as_secs_f64();
+        self.last_sample = now;
+        // Update the throughput/graph widgets if they are enabled
         if self.output_tx.is_some() || self.graph_tx.is_some() {
             let current_tx = self.device.tx_bytes()?;
-            let tx_bytes = ((current_tx - self.tx_bytes) as f64 / update_interval) as u64;
+            let tx_bytes =
+                (current_tx.saturating_sub(self.tx_bytes) as f64 / update_interval) as u64;
             self.tx_bytes = current_tx;
+            self.tx_speed = tx_bytes;
 
             if let Some(ref mut tx) = self.output_tx {
                 *tx = format_speed(
@@ -821,15 +994,16 @@ impl Net {
             };
 
             if let Some(ref mut graph_tx) = self.graph_tx {
-                self.tx_buff.remove(0);
-                self.tx_buff.push(tx_bytes);
-                *graph_tx = format_vec_to_bar_graph(&self.tx_buff, None, None);
+                self.tx_history.push(tx_bytes);
+                *graph_tx = self.tx_history.render(None, None, &self.graph_chars);
             }
         }
         if self.output_rx.is_some() || self.graph_rx.is_some() {
             let current_rx = self.device.rx_bytes()?;
-            let rx_bytes = ((current_rx - self.rx_bytes) as f64 / update_interval) as u64;
+            let rx_bytes =
+                (current_rx.saturating_sub(self.rx_bytes) as f64 / update_interval) as u64;
             self.rx_bytes = current_rx;
+            self.rx_speed = rx_bytes;
 
             if let Some(ref mut rx) = self.output_rx {
                 *rx = format_speed(
@@ -841,9 +1015,8 @@ impl Net {
             };
 
             if let Some(ref mut graph_rx) = self.graph_rx {
-                self.rx_buff.remove(0);
-                self.rx_buff.push(rx_bytes);
-                *graph_rx = format_vec_to_bar_graph(&self.rx_buff, None, None);
+                self.rx_history.push(rx_bytes);
+                *graph_rx = self.rx_history.render(None, None, &self.graph_chars);
             }
         }
         Ok(())
@@ -852,7 +1025,11 @@ impl Net {
 
 impl Block for Net {
     fn update(&mut self) -> Result<Option<Update>> {
-        self.update_device();
+        let changed = self.changed.swap(false, Ordering::SeqCst);
+        // Without netlink, the default route is looked up on every update.
+        if changed || !self.netlink {
+            self.update_device();
+        }
 
         // skip updating if device is not up.
         let exists = self.device.exists()?;
@@ -873,51 +1050,59 @@ impl Block for Net {
         self.active = true;
         self.network.set_text("".to_string());
 
-        // Update SSID and IP address every 30s and the bitrate every 10s
+        // Update the SSID, IP addresses and bitrate when netlink reports a
+        // change, and everything every 30s, since the signal strength changes
+        // without a word.
         let now = Instant::now();
-        if now.duration_since(self.last_update).as_secs() % 10 == 0 {
+        if changed || now.duration_since(self.last_update).as_secs() >= 30 {
             self.update_bitrate()?;
-        }
-        if now.duration_since(self.last_update).as_secs() > 30 {
             self.update_ssid()?;
             self.update_signal_strength()?;
             self.update_ip_addr()?;
             self.last_update = now;
         }
 
-        self.update_tx_rx()?;
+        // An update for a netlink event right after a sample is not one.
+        if !changed || now.duration_since(self.last_sample) >= self.update_interval / 2 {
+            self.update_tx_rx()?;
+        }
 
         let empty_string = "".to_string();
-        let s_up = format!(
-            "{} {}",
-            self.config
-                .icons
-                .get("net_up")
-                .cloned()
-                .unwrap_or_else(|| "".to_string()),
-            self.output_tx.as_ref().unwrap_or(&empty_string)
-        );
-        let s_dn = format!(
-            "{} {}",
-            self.config
-                .icons
-                .get("net_down")
-                .cloned()
-                .unwrap_or_else(|| "".to_string()),
-            self.output_rx.as_ref().unwrap_or(&empty_string)
+        // With a unit, as in `{speed_up;K*b}`, the speeds are numbers.
+        let speed = |icon: &str, text: &Option<String>, unit: Option<Spec>, bytes: u64| {
+            format!(
+                "{} {}",
+                self.config.icons.get(icon).map_or("", String::as_str),
+                match unit {
+                    Some(spec) => formatting::render(&self.speed_value(bytes), spec),
+                    None => text.clone().unwrap_or_default(),
+                }
+            )
+        };
+        let s_up = speed("net_up", &self.output_tx, self.speed_up_unit, self.tx_speed);
+        let s_dn = speed(
+            "net_down",
+            &self.output_rx,
+            self.speed_down_unit,
+            self.rx_speed,
         );
 
+        let text =
+            |value: &Option<String>| Value::from(value.as_ref().unwrap_or(&empty_string).as_str());
         let values = map!(
-            "{ssid}" => self.ssid.as_ref().unwrap_or(&empty_string),
-            "{signal_strength}" => self.signal_strength.as_ref().unwrap_or(&empty_string),
-            "{signal_strength_bar}" => self.signal_strength_bar.as_ref().unwrap_or(&empty_string),
-            "{bitrate}" =>  self.bitrate.as_ref().unwrap_or(&empty_string),
-            "{ip}" =>  self.ip_addr.as_ref().unwrap_or(&empty_string),
-            "{ipv6}" =>  self.ipv6_addr.as_ref().unwrap_or(&empty_string),
-            "{speed_up}" =>  &s_up,
-            "{speed_down}" => &s_dn,
-            "{graph_up}" =>  self.graph_tx.as_ref().unwrap_or(&empty_string),
-            "{graph_down}" =>  self.graph_rx.as_ref().unwrap_or(&empty_string)
+            "{device}" => Value::from(self.device.device()),
+            "{ssid}" => text(&self.ssid),
+            "{signal_strength}" => text(&self.signal_strength),
+            "{signal_strength_bar}" => text(&self.signal_strength_bar),
+            "{bitrate}" =>  text(&self.bitrate),
+            "{ip}" =>  text(&self.ip_addr),
+            "{ipv6}" =>  text(&self.ipv6_addr),
+            "{speed_up}" =>  Value::from(s_up),
+            "{speed_down}" => Value::from(s_dn),
+            "{up}" => self.speed_value(self.tx_speed),
+            "{down}" => self.speed_value(self.rx_speed),
+            "{graph_up}" =>  text(&self.graph_tx),
+            "{graph_down}" =>  text(&self.graph_rx)
         );
 
         self.output
@@ -936,21 +1121,6 @@ impl Block for Net {
         }
     }
 
-    fn click(&mut self, e: &I3BarEvent) -> Result<()> {
-        if let Some(ref name) = e.name {
-            if name.as_str() == self.id {
-                if let MouseButton::Left = e.button {
-                    if let Some(ref cmd) = self.on_click {
-                        spawn_child_async("sh", &["-c", cmd])
-                            .block_error("net", "could not spawn child")?;
-                    }
-                }
-            }
-        }
-
-        Ok(())
-    }
-
     fn id(&self) -> &str {
         &self.id
     }
@@ -1106,3 +1276,24 @@ fn maybe_ssid_convert(raw: Option<&[u8]>) -> Result<Option<String>> {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_route_device() {
+        assert_eq!(
+            default_route_device(
+                "default via 192.168.1.1 dev wlp3s0 proto dhcp metric 600\n\
+                 default via 10.0.0.1 dev enp0s31f6 proto dhcp metric 100\n"
+            ),
+            Some("enp0s31f6".to_string())
+        );
+        assert_eq!(
+            default_route_device("default dev wg-home scope link\n"),
+            Some("wg-home".to_string())
+        );
+        assert_eq!(default_route_device(""), None);
+    }
+}
@@ -1,16 +1,12 @@
-use std::ffi::OsStr;
 use std::fmt;
 use std::net::Ipv4Addr;
-use std::process::Command;
 use std::result;
-use std::thread;
-use std::time::Instant;
 
 use crossbeam_channel::Sender;
 use dbus::arg::{Array, Iter, Variant};
 use dbus::{
     arg::messageitem::MessageItem,
-    ffidisp::{BusType, Connection, ConnectionItem},
+    ffidisp::{BusType, Connection},
     Message, Path,
 };
 use regex::Regex;
@@ -20,8 +16,8 @@ use uuid::Uuid;
 use crate::blocks::Update;
 use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
+use crate::dbus_manager::{self, Bus};
 use crate::errors::*;
-use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
 use crate::util::FormatTemplate;
 use crate::widget::{I3BarWidget, State};
@@ -452,7 +448,6 @@ pub struct NetworkManager {
     dbus_conn: Connection,
     manager: ConnectionManager,
     config: Config,
-    on_click: Option<String>,
     primary_only: bool,
     max_ssid_width: usize,
     ap_format: FormatTemplate,
@@ -465,9 +460,6 @@ pub struct NetworkManager {
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkManagerConfig {
-    #[serde(default = "NetworkManagerConfig::default_on_click")]
-    pub on_click: Option<String>,
-
     /// Whether to only show the primary connection, or all active connections.
     #[serde(default = "NetworkManagerConfig::default_primary_only")]
     pub primary_only: bool,
@@ -498,10 +490,6 @@ pub struct NetworkManagerConfig {
 }
 
 impl NetworkManagerConfig {
-    fn default_on_click() -> Option<String> {
-        None
-    }
-
     fn default_primary_only() -> bool {
         false
     }
@@ -536,39 +524,20 @@ impl ConfigBlock for NetworkManager {
 
     fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
         let id: String = Uuid::new_v4().to_simple().to_string();
-        let id_copy = id.clone();
         let dbus_conn = Connection::get_private(BusType::System)
             .block_error("networkmanager", "failed to establish D-Bus connection")?;
         let manager = ConnectionManager::new();
 
-        thread::Builder::new()
-            .name("networkmanager".into())
-            .spawn(move || {
-                let c = Connection::get_private(BusType::System).unwrap();
-                let rule = "type='signal',\
-                        path='/org/freedesktop/NetworkManager',\
-                        interface='org.freedesktop.NetworkManager',\
-                        member='PropertiesChanged'";
-
-                c.add_match(&rule).unwrap();
-
-                loop {
-                    let timeout = 300_000;
-
-                    for event in c.iter(timeout) {
-                        match event {
-                            ConnectionItem::Nothing => (),
-                            _ => send
-                                .send(Task {
-                                    id: id_copy.clone(),
-                                    update_time: Instant::now(),
-                                })
-                                .unwrap(),
-                        }
-                    }
-                }
-            })
-            .unwrap();
+        dbus_manager::request_updates(
+            Bus::System,
+            "type='signal',\
+             path='/org/freedesktop/NetworkManager',\
+             interface='org.freedesktop.NetworkManager',\
+             member='PropertiesChanged'",
+            id.clone(),
+            send,
+        )
+        .block_error("networkmanager", "failed to add D-Bus match rule")?;
 
         fn compile_regexps(patterns: Vec<String>) -> result::Result<Vec<Regex>, regex::Error> {
             patterns.iter().map(|p| Regex::new(&p)).collect()
@@ -581,7 +550,6 @@ impl ConfigBlock for NetworkManager {
             output: Vec::new(),
             dbus_conn,
             manager,
-            on_click: block_config.on_click,
             primary_only: block_config.primary_only,
             max_ssid_width: block_config.max_ssid_width,
             ap_format: FormatTemplate::from_string(&block_config.ap_format)?,
@@ -811,22 +779,4 @@ impl Block for NetworkManager {
             self.output.iter().map(|x| x as &dyn I3BarWidget).collect()
         }
     }
-
-    fn click(&mut self, e: &I3BarEvent) -> Result<()> {
-        if let Some(ref name) = e.name {
-            if name.as_str() == self.id {
-                if let MouseButton::Left = e.button {
-                    if let Some(ref cmd) = self.on_click {
-                        let command_broken: Vec<&str> = cmd.split_whitespace().collect();
-                        let mut itr = command_broken.iter();
-                        let mut _cmd = Command::new(OsStr::new(&itr.next().unwrap()))
-                            .args(itr)
-                            .spawn();
-                    }
-                }
-            }
-        }
-
-        Ok(())
-    }
 }
@@ -1,4 +1,5 @@
 use std::env;
+use std::path::Path;
 use std::time::Duration;
 
 use crossbeam_channel::Sender;
@@ -8,17 +9,21 @@ use uuid::Uuid;
 use crate::blocks::Update;
 use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
-use crate::de::deserialize_duration;
+use crate::de::deserialize_opt_duration;
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::input::{I3BarEvent, MouseButton};
+use crate::registry::Values;
 use crate::scheduler::Task;
+use crate::stop::Stop;
+use crate::util::{watch_dirs, FormatTemplate};
 use crate::widget::{I3BarWidget, State};
-use crate::widgets::text::TextWidget;
+use crate::widgets::button::ButtonWidget;
 
 pub struct Notmuch {
-    text: TextWidget,
+    text: ButtonWidget,
     id: String,
-    update_interval: Duration,
+    update_interval: Option<Duration>,
     query: String,
     db: String,
     threshold_info: u32,
@@ -26,17 +31,19 @@ pub struct Notmuch {
     threshold_warning: u32,
     threshold_critical: u32,
     name: Option<String>,
+    format: FormatTemplate,
+    count: u32,
+    /// Stops watching for changes when the block is dropped.
+    _watcher: Stop,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct NotmuchConfig {
-    /// Update interval in seconds
-    #[serde(
-        default = "NotmuchConfig::default_interval",
-        deserialize_with = "deserialize_duration"
-    )]
-    pub interval: Duration,
+    /// Update interval in seconds, on top of the updates when the database
+    /// changes
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    pub interval: Option<Duration>,
     #[serde(default = "NotmuchConfig::default_maildir")]
     pub maildir: String,
     #[serde(default = "NotmuchConfig::default_query")]
@@ -53,13 +60,12 @@ pub struct NotmuchConfig {
     pub name: Option<String>,
     #[serde(default = "NotmuchConfig::default_no_icon")]
     pub no_icon: bool,
+    /// Format override, with the placeholders `{count}` and `{name}`
+    #[serde(default)]
+    pub format: Option<String>,
 }
 
 impl NotmuchConfig {
-    fn default_interval() -> Duration {
-        Duration::from_secs(10)
-    }
-
     fn default_maildir() -> String {
         #[allow(deprecated)]
         let home_dir = match env::home_dir() {
@@ -110,14 +116,18 @@ impl ConfigBlock for Notmuch {
     fn new(
         block_config: Self::Config,
         config: Config,
-        _tx_update_request: Sender<Task>,
+        tx_update_request: Sender<Task>,
     ) -> Result<Self> {
-        let mut widget = TextWidget::new(config);
+        let id = Uuid::new_v4().to_simple().to_string();
+        let xapian = Path::new(&block_config.maildir).join(".notmuch/xapian");
+        let watcher = watch_dirs("notmuch", &[xapian], id.clone(), tx_update_request)?;
+
+        let mut widget = ButtonWidget::new(config, &id);
         if !block_config.no_icon {
             widget.set_icon("mail");
         }
         Ok(Notmuch {
-            id: Uuid::new_v4().to_simple().to_string(),
+            id,
             update_interval: block_config.interval,
             db: block_config.maildir,
             query: block_config.query,
@@ -125,7 +135,17 @@ impl ConfigBlock for Notmuch {
             threshold_good: block_config.threshold_good,
             threshold_warning: block_config.threshold_warning,
             threshold_critical: block_config.threshold_critical,
+            format: FormatTemplate::from_string(block_config.format.as_deref().unwrap_or(
+                if block_config.name.is_some() {
+                    "{name}:{count}"
+                } else {
+                    "{count}"
+                },
+            ))
+            .block_error("notmuch", "Invalid format specified for notmuch")?,
             name: block_config.name,
+            count: 0,
+            _watcher: watcher,
 
             text: widget,
         })
@@ -133,12 +153,11 @@ impl ConfigBlock for Notmuch {
 }
 
 impl Notmuch {
-    fn update_text(&mut self, count: u32) {
-        let text = match self.name {
-            Some(ref s) => format!("{}:{}", s, count),
-            _ => format!("{}", count),
-        };
-        self.text.set_text(text);
+    fn update_text(&mut self, count: u32) -> Result<()> {
+        let values = map!("{count}" => Value::integer(count),
+                          "{name}" => Value::from(self.name.clone().unwrap_or_default()));
+        self.text.set_text(self.format.render_static_str(&values)?);
+        Ok(())
     }
 
     fn update_state(&mut self, count: u32) {
@@ -160,9 +179,10 @@ impl Block for Notmuch {
     fn update(&mut self) -> Result<Option<Update>> {
         match run_query(&self.db, &self.query) {
             Ok(count) => {
-                self.update_text(count);
+                self.count = count;
+                self.update_text(count)?;
                 self.update_state(count);
-                Ok(Some(self.update_interval.into()))
+                Ok(self.update_interval.map(Into::into))
             }
             Err(e) => Err(BlockError("notmuch".to_string(), e.to_string())),
         }
@@ -182,6 +202,12 @@ impl Block for Notmuch {
         Ok(())
     }
 
+    fn values(&self) -> Values {
+        let mut values = Values::new();
+        values.insert("count".to_owned(), self.count as f64);
+        values
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
@@ -0,0 +1,388 @@
+//! A block showing one of several "pages" of text written to a FIFO.
+//!
+//! Scripts write lines of the form `page_name<TAB>text` to the FIFO owned by
+//! the block. Each page keeps the last text written to it, an empty text
+//! removes the page. The block cycles through the pages on click and,
+//! optionally, on a timer.
+
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_opt_duration;
+use crate::errors::*;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+use crate::stop::{self, Stop, Stopped, Wake};
+use crate::util::{xdg_runtime_dir, FormatTemplate};
+use crate::widget::I3BarWidget;
+use crate::widgets::button::ButtonWidget;
+
+/// The pages in the order they were first written, and the one displayed.
+#[derive(Debug, Default)]
+struct Pages {
+    pages: Vec<(String, String)>,
+    current: usize,
+}
+
+impl Pages {
+    /// Applies a `name<TAB>text` line. Returns whether it was well-formed.
+    fn apply_line(&mut self, line: &str) -> bool {
+        let mut parts = line.splitn(2, '\t');
+        let name = match parts.next() {
+            Some(name) if !name.is_empty() => name,
+            _ => return false,
+        };
+        let text = match parts.next() {
+            Some(text) => text.trim_end_matches('\r'),
+            None => return false,
+        };
+
+        let position = self.pages.iter().position(|(n, _)| n == name);
+        match (position, text.is_empty()) {
+            (Some(i), false) => self.pages[i].1 = text.to_string(),
+            (None, false) => self.pages.push((name.to_string(), text.to_string())),
+            (Some(i), true) => {
+                self.pages.remove(i);
+                // Keep showing the same page if one before it was removed.
+                if i < self.current {
+                    self.current -= 1;
+                }
+                if self.current >= self.pages.len() {
+                    self.current = 0;
+                }
+            }
+            (None, true) => {}
+        }
+        true
+    }
+
+    fn next(&mut self) {
+        if !self.pages.is_empty() {
+            self.current = (self.current + 1) % self.pages.len();
+        }
+    }
+
+    fn current(&self) -> Option<&(String, String)> {
+        self.pages.get(self.current)
+    }
+}
+
+/// Splits a byte stream into lines, keeping incomplete lines until the rest
+/// arrives.
+#[derive(Debug, Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line[..end]).into_owned());
+        }
+        lines
+    }
+
+    /// The last line of a writer that did not end it with a newline.
+    fn finish(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            let line = String::from_utf8_lossy(&self.pending).into_owned();
+            self.pending.clear();
+            Some(line)
+        }
+    }
+}
+
+pub struct Pager {
+    id: String,
+    output: ButtonWidget,
+    format: FormatTemplate,
+    pages: Arc<Mutex<Pages>>,
+    rotate_interval: Option<Duration>,
+    last_rotation: Instant,
+    /// Stops reading the FIFO when the block is dropped.
+    _reader: Stop,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PagerConfig {
+    /// Path of the FIFO to read pages from
+    #[serde(default = "PagerConfig::default_path")]
+    pub path: PathBuf,
+
+    /// Format override
+    #[serde(default = "PagerConfig::default_format")]
+    pub format: String,
+
+    /// Switch to the next page automatically after this long
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    pub rotate_interval: Option<Duration>,
+}
+
+impl PagerConfig {
+    fn default_path() -> PathBuf {
+        xdg_runtime_dir().join("i3status-rust/pager")
+    }
+
+    fn default_format() -> String {
+        "{text}".to_owned()
+    }
+}
+
+/// Creates a FIFO at `path`, reusing an existing one.
+fn create_fifo(path: &Path) -> Result<()> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.file_type().is_fifo() => return Ok(()),
+        Ok(_) => {
+            return Err(BlockError(
+                "pager".to_string(),
+                format!("'{}' exists and is not a FIFO", path.to_string_lossy()),
+            ))
+        }
+        Err(_) => {}
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).block_error(
+            "pager",
+            &format!("failed to create directory {}", parent.to_string_lossy()),
+        )?;
+    }
+    mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR).block_error(
+        "pager",
+        &format!("failed to create FIFO {}", path.to_string_lossy()),
+    )
+}
+
+fn read_fifo(
+    path: PathBuf,
+    pages: Arc<Mutex<Pages>>,
+    id: String,
+    send: Sender<Task>,
+    stopped: Stopped,
+) {
+    let mut buffer = [0; 4096];
+    loop {
+        // Opened without blocking, so that waiting for a writer can be
+        // stopped. Once all writers are gone we read EOF and wait for the
+        // next one.
+        let mut fifo = match OpenOptions::new()
+            .read(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(&path)
+        {
+            Ok(fifo) => fifo,
+            Err(e) => {
+                log_block_error(&id, "pager", &format!("failed to open FIFO: {}", e));
+                if !stopped.sleep(Duration::from_secs(1)) {
+                    return;
+                }
+                continue;
+            }
+        };
+        let mut lines = LineBuffer::default();
+        loop {
+            if stopped.wait(Some(fifo.as_raw_fd()), None).ok() != Some(Wake::Ready) {
+                return;
+            }
+            let mut received = match fifo.read(&mut buffer) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Ok(0) | Err(_) => break,
+                Ok(n) => lines.push(&buffer[..n]),
+            };
+            if received.is_empty() {
+                continue;
+            }
+            if let Ok(mut pages) = pages.lock() {
+                for line in received.drain(..) {
+                    pages.apply_line(&line);
+                }
+            }
+            send.send(Task {
+                id: id.clone(),
+                update_time: Instant::now(),
+            })
+            .ok();
+        }
+        if let Some(line) = lines.finish() {
+            if let Ok(mut pages) = pages.lock() {
+                pages.apply_line(&line);
+            }
+            send.send(Task {
+                id: id.clone(),
+                update_time: Instant::now(),
+            })
+            .ok();
+        }
+    }
+}
+
+impl ConfigBlock for Pager {
+    type Config = PagerConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        create_fifo(&block_config.path)?;
+
+        let pages = Arc::new(Mutex::new(Pages::default()));
+        let pages_copy = pages.clone();
+        let id_copy = id.clone();
+        let path = block_config.path;
+        let (stop, stopped) = stop::pair()?;
+        thread::Builder::new()
+            .name("pager".into())
+            .spawn(move || read_fifo(path, pages_copy, id_copy, send, stopped))
+            .block_error("pager", "failed to spawn thread")?;
+
+        Ok(Pager {
+            output: ButtonWidget::new(config, &id),
+            id,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("pager", "Invalid format specified")?,
+            pages,
+            rotate_interval: block_config.rotate_interval,
+            last_rotation: Instant::now(),
+            _reader: stop,
+        })
+    }
+}
+
+impl Pager {
+    fn render(&mut self) -> Result<()> {
+        let pages = self
+            .pages
+            .lock()
+            .block_error("pager", "failed to acquire lock")?;
+        if let Some((name, text)) = pages.current() {
+            let values = map!("{name}" => name.clone(), "{text}" => text.clone());
+            self.output
+                .set_text(self.format.render_static_str(&values)?);
+        }
+        Ok(())
+    }
+}
+
+impl Block for Pager {
+    fn update(&mut self) -> Result<Option<Update>> {
+        if let Some(interval) = self.rotate_interval {
+            if self.last_rotation.elapsed() >= interval {
+                self.last_rotation = Instant::now();
+                self.pages
+                    .lock()
+                    .block_error("pager", "failed to acquire lock")?
+                    .next();
+            }
+        }
+        self.render()?;
+        Ok(self.rotate_interval.map(|interval| interval.into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        match self.pages.lock() {
+            Ok(ref pages) if pages.current().is_some() => vec![&self.output],
+            _ => vec![],
+        }
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) && event.button == MouseButton::Left {
+            self.pages
+                .lock()
+                .block_error("pager", "failed to acquire lock")?
+                .next();
+            self.last_rotation = Instant::now();
+            self.render()?;
+        }
+        Ok(())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current(pages: &Pages) -> Option<&str> {
+        pages.current().map(|(name, _)| name.as_str())
+    }
+
+    #[test]
+    fn test_partial_writes() {
+        let mut buffer = LineBuffer::default();
+        assert!(buffer.push(b"mail\t3 unr").is_empty());
+        assert_eq!(buffer.push(b"ead\nvpn\ton"), vec!["mail\t3 unread"]);
+        assert_eq!(buffer.push(b"\n\n"), vec!["vpn\ton", ""]);
+        assert_eq!(
+            buffer.push("caf\u{e9}\t\u{2615}".as_bytes()),
+            Vec::<String>::new()
+        );
+        assert_eq!(buffer.finish(), Some("caf\u{e9}\t\u{2615}".to_string()));
+        assert_eq!(buffer.finish(), None);
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let mut pages = Pages::default();
+        assert!(!pages.apply_line("no tab here"));
+        assert!(!pages.apply_line("\ttext without a page"));
+        assert!(current(&pages).is_none());
+    }
+
+    #[test]
+    fn test_add_and_remove_while_rotating() {
+        let mut pages = Pages::default();
+        pages.apply_line("a\t1");
+        pages.apply_line("b\t2");
+        pages.apply_line("c\t3");
+        pages.next();
+        assert_eq!(current(&pages), Some("b"));
+
+        // Updating a page keeps its position.
+        pages.apply_line("b\t2 again");
+        assert_eq!(pages.current().unwrap().1, "2 again");
+
+        // Removing a page before the current one keeps showing the same page.
+        pages.apply_line("a\t");
+        assert_eq!(current(&pages), Some("b"));
+
+        // Removing the current page moves on to the next one.
+        pages.apply_line("b\t");
+        assert_eq!(current(&pages), Some("c"));
+
+        // Removing the last page wraps around.
+        pages.apply_line("d\t4");
+        pages.next();
+        assert_eq!(current(&pages), Some("d"));
+        pages.apply_line("d\t");
+        assert_eq!(current(&pages), Some("c"));
+
+        pages.apply_line("c\t");
+        assert!(current(&pages).is_none());
+        pages.next();
+        assert!(current(&pages).is_none());
+    }
+}
@@ -1,17 +1,21 @@
-use std::fmt;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use chrono::offset::{Local, TimeZone};
+use chrono::NaiveDate;
 use crossbeam_channel::Sender;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::value::Value;
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
 use crate::errors::*;
+use crate::formatting;
 use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
 use crate::subprocess::spawn_child_async;
+use crate::util::FormatTemplate;
 use crate::widget::I3BarWidget;
 use crate::widgets::button::ButtonWidget;
 
@@ -33,30 +37,70 @@ impl State {
     }
 }
 
-impl fmt::Display for State {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            State::Stopped => write!(f, "\u{25a0} 0:00"),
-            State::Started(_) => write!(
-                f,
-                "\u{f04b} {}:{:02}",
-                self.elapsed().as_secs() / 60,
-                self.elapsed().as_secs() % 60
-            ),
-            State::OnBreak(_) => write!(
-                f,
-                "\u{2615} {}:{:02}",
-                self.elapsed().as_secs() / 60,
-                self.elapsed().as_secs() % 60
-            ),
-            State::Paused(duration) => write!(
-                f,
-                "\u{f04c} {}:{:02}",
-                duration.as_secs() / 60,
-                duration.as_secs() % 60
-            ),
+/// The serializable form of `State` used to survive restarts of the bar.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SavedState {
+    count: usize,
+    state: String,
+    elapsed_secs: u64,
+    saved_at: u64,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl SavedState {
+    fn new(state: &State, count: usize, now: u64) -> Self {
+        let (name, elapsed) = match state {
+            State::Stopped => ("stopped", Duration::from_secs(0)),
+            State::Started(_) => ("started", state.elapsed()),
+            State::Paused(duration) => ("paused", duration.to_owned()),
+            State::OnBreak(_) => ("break", state.elapsed()),
+        };
+        SavedState {
+            count,
+            state: name.to_string(),
+            elapsed_secs: elapsed.as_secs(),
+            saved_at: now,
         }
     }
+
+    /// Rebuilds the state. Running timers also account for the time the bar
+    /// was not running.
+    fn to_state(&self, now: u64) -> Result<State> {
+        let gap = now.saturating_sub(self.saved_at);
+        let running_since = |secs: u64| {
+            Instant::now()
+                .checked_sub(Duration::from_secs(secs))
+                .unwrap_or_else(Instant::now)
+        };
+        Ok(match self.state.as_str() {
+            "stopped" => State::Stopped,
+            "started" => State::Started(running_since(self.elapsed_secs + gap)),
+            "paused" => State::Paused(Duration::from_secs(self.elapsed_secs)),
+            "break" => State::OnBreak(running_since(self.elapsed_secs + gap)),
+            other => {
+                return Err(BlockError(
+                    "pomodoro".to_string(),
+                    format!("unknown saved state '{}'", other),
+                ))
+            }
+        })
+    }
+}
+
+/// Minutes and seconds, e.g. `4:05`, rounded up as they count down.
+fn format_minutes(duration: Duration) -> String {
+    let secs = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+fn today() -> NaiveDate {
+    Local::today().naive_local()
 }
 
 pub struct Pomodoro {
@@ -65,18 +109,60 @@ pub struct Pomodoro {
     state: State,
     length: Duration,
     break_length: Duration,
+    long_break_length: Duration,
+    long_break_interval: usize,
     update_interval: Duration,
     message: String,
     break_message: String,
+    /// Pomodoros completed on `day`.
     count: usize,
+    day: NaiveDate,
+    notify: bool,
     use_nag: bool,
     nag_path: std::path::PathBuf,
+    format: FormatTemplate,
 }
 
 impl Pomodoro {
-    fn set_text(&mut self) {
-        self.time
-            .set_text(format!("{} | {}", self.count, self.state));
+    /// The length of the break after the pomodoros counted so far, which is
+    /// a long one after every `long_break_interval` pomodoros.
+    fn current_break_length(&self) -> Duration {
+        if self.long_break_interval > 0
+            && self.count > 0
+            && self.count.is_multiple_of(self.long_break_interval)
+        {
+            self.long_break_length
+        } else {
+            self.break_length
+        }
+    }
+
+    fn set_text(&mut self) -> Result<()> {
+        let remaining =
+            |length: Duration| format_minutes(length.saturating_sub(self.state.elapsed()));
+        let (status, time) = match self.state {
+            State::Stopped => ("\u{25a0}", format_minutes(self.length)),
+            State::Started(_) => ("\u{f04b}", remaining(self.length)),
+            State::Paused(_) => ("\u{f04c}", remaining(self.length)),
+            State::OnBreak(_) => ("\u{2615}", remaining(self.current_break_length())),
+        };
+        let values = map!("{count}" => formatting::Value::integer(self.count),
+                          "{status}" => formatting::Value::from(status),
+                          "{time}" => formatting::Value::from(time));
+        self.time.set_text(self.format.render_static_str(&values)?);
+        Ok(())
+    }
+
+    /// Tells about the end of a pomodoro or a break.
+    fn announce(&self, message: &str, level: &str) -> Result<()> {
+        if self.use_nag {
+            self.nag(message, level);
+        }
+        if self.notify {
+            spawn_child_async("notify-send", &["Pomodoro", message])
+                .block_error("pomodoro", "could not spawn notify-send")?;
+        }
+        Ok(())
     }
 
     fn nag(&self, message: &str, level: &str) {
@@ -95,14 +181,25 @@ pub struct PomodoroConfig {
     pub length: u64,
     #[serde(default = "PomodoroConfig::default_break_length")]
     pub break_length: u64,
+    #[serde(default = "PomodoroConfig::default_long_break_length")]
+    pub long_break_length: u64,
+    /// Every how many pomodoros the break is a long one, never if 0
+    #[serde(default = "PomodoroConfig::default_long_break_interval")]
+    pub long_break_interval: usize,
     #[serde(default = "PomodoroConfig::default_message")]
     pub message: String,
     #[serde(default = "PomodoroConfig::default_break_message")]
     pub break_message: String,
+    /// Whether to send a desktop notification when a pomodoro or break is over
+    #[serde(default = "PomodoroConfig::default_notify")]
+    pub notify: bool,
     #[serde(default = "PomodoroConfig::default_use_nag")]
     pub use_nag: bool,
     #[serde(default = "PomodoroConfig::default_nag_path")]
     pub nag_path: std::path::PathBuf,
+    /// Format override, with the placeholders `{count}`, `{status}` and `{time}`
+    #[serde(default = "PomodoroConfig::default_format")]
+    pub format: String,
 }
 
 impl PomodoroConfig {
@@ -114,6 +211,18 @@ impl PomodoroConfig {
         5
     }
 
+    fn default_long_break_length() -> u64 {
+        15
+    }
+
+    fn default_long_break_interval() -> usize {
+        4
+    }
+
+    fn default_notify() -> bool {
+        true
+    }
+
     fn default_message() -> String {
         "Pomodoro over! Take a break!".to_owned()
     }
@@ -129,6 +238,10 @@ impl PomodoroConfig {
     fn default_nag_path() -> std::path::PathBuf {
         std::path::PathBuf::from("i3-nagbar")
     }
+
+    fn default_format() -> String {
+        "{count} | {status} {time}".to_owned()
+    }
 }
 
 impl ConfigBlock for Pomodoro {
@@ -143,12 +256,18 @@ impl ConfigBlock for Pomodoro {
             state: State::Stopped,
             length: Duration::from_secs(block_config.length * 60), // convert to minutes
             break_length: Duration::from_secs(block_config.break_length * 60), // convert to minutes
+            long_break_length: Duration::from_secs(block_config.long_break_length * 60),
+            long_break_interval: block_config.long_break_interval,
             update_interval: Duration::from_millis(1000),
             message: block_config.message,
             break_message: block_config.break_message,
+            notify: block_config.notify,
             use_nag: block_config.use_nag,
             count: 0,
+            day: today(),
             nag_path: block_config.nag_path,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("pomodoro", "Invalid format specified for pomodoro")?,
         })
     }
 }
@@ -159,27 +278,25 @@ impl Block for Pomodoro {
     }
 
     fn update(&mut self) -> Result<Option<Update>> {
-        self.set_text();
-        match &self.state {
-            State::Started(_) => {
-                if self.state.elapsed() >= self.length {
-                    if self.use_nag {
-                        self.nag(&self.message, "error");
-                    }
-
-                    self.state = State::OnBreak(Instant::now());
-                }
+        if self.day != today() {
+            self.day = today();
+            self.count = 0;
+        }
+        let announcement = match &self.state {
+            State::Started(_) if self.state.elapsed() >= self.length => {
+                self.count += 1;
+                self.state = State::OnBreak(Instant::now());
+                Some((self.message.clone(), "error"))
             }
-            State::OnBreak(_) => {
-                if self.state.elapsed() >= self.break_length {
-                    if self.use_nag {
-                        self.nag(&self.break_message, "warning");
-                    }
-                    self.state = State::Stopped;
-                    self.count += 1;
-                }
+            State::OnBreak(_) if self.state.elapsed() >= self.current_break_length() => {
+                self.state = State::Stopped;
+                Some((self.break_message.clone(), "warning"))
             }
-            _ => {}
+            _ => None,
+        };
+        self.set_text()?;
+        if let Some((message, level)) = announcement {
+            self.announce(&message, level)?;
         }
 
         Ok(Some(self.update_interval.into()))
@@ -213,11 +330,158 @@ impl Block for Pomodoro {
             }
         }
 
-        self.set_text();
+        self.set_text()?;
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn view(&self) -> Vec<&dyn I3BarWidget> {
         vec![&self.time]
     }
+
+    fn save_state(&self) -> Option<Value> {
+        serde_json::to_value(SavedState::new(&self.state, self.count, unix_now())).ok()
+    }
+
+    fn restore_state(&mut self, state: Value) -> Result<()> {
+        let saved: SavedState =
+            serde_json::from_value(state).block_error("pomodoro", "failed to parse saved state")?;
+        self.state = saved.to_state(unix_now())?;
+        // The count is per day.
+        if Local
+            .timestamp(saved.saved_at as i64, 0)
+            .date()
+            .naive_local()
+            == self.day
+        {
+            self.count = saved.count;
+        }
+        self.set_text()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pomodoro() -> Pomodoro {
+        Pomodoro::new(
+            toml::from_str("length = 2\nbreak_length = 1\nlong_break_length = 3\nlong_break_interval = 2\nnotify = false").unwrap(),
+            Config::default(),
+            crossbeam_channel::unbounded().0,
+        )
+        .unwrap()
+    }
+
+    fn ago(secs: u64) -> Instant {
+        Instant::now() - Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_counts_down() {
+        let mut block = pomodoro();
+        block.update().unwrap();
+        assert_eq!(block.time.widget_state().text, "0 | \u{25a0} 2:00");
+        block.state = State::Started(ago(55));
+        block.update().unwrap();
+        assert_eq!(block.time.widget_state().text, "0 | \u{f04b} 1:05");
+        block.state = State::Paused(Duration::from_secs(100));
+        block.update().unwrap();
+        assert_eq!(block.time.widget_state().text, "0 | \u{f04c} 0:20");
+    }
+
+    #[test]
+    fn test_format() {
+        let mut block = Pomodoro::new(
+            toml::from_str("length = 2\nformat = \"{time} ({count:02})\"").unwrap(),
+            Config::default(),
+            crossbeam_channel::unbounded().0,
+        )
+        .unwrap();
+        block.update().unwrap();
+        assert_eq!(block.time.widget_state().text, "2:00 (00)");
+    }
+
+    #[test]
+    fn test_breaks() {
+        let mut block = pomodoro();
+        block.state = State::Started(ago(120));
+        block.update().unwrap();
+        assert_eq!(block.count, 1);
+        assert_eq!(block.time.widget_state().text, "1 | \u{2615} 1:00");
+        block.state = State::OnBreak(ago(60));
+        block.update().unwrap();
+        assert_eq!(block.time.widget_state().text, "1 | \u{25a0} 2:00");
+
+        // Every second break is a long one.
+        block.state = State::Started(ago(120));
+        block.update().unwrap();
+        assert_eq!(block.time.widget_state().text, "2 | \u{2615} 3:00");
+        block.state = State::OnBreak(ago(60));
+        block.update().unwrap();
+        assert!(matches!(block.state, State::OnBreak(_)));
+    }
+
+    #[test]
+    fn test_count_is_daily() {
+        let mut block = pomodoro();
+        block.count = 3;
+        block.day = today().pred();
+        block.update().unwrap();
+        assert_eq!(block.count, 0);
+
+        let saved = |saved_at| {
+            serde_json::to_value(SavedState {
+                count: 5,
+                state: "stopped".to_string(),
+                elapsed_secs: 0,
+                saved_at,
+            })
+            .unwrap()
+        };
+        block
+            .restore_state(saved(unix_now() - 2 * 24 * 60 * 60))
+            .unwrap();
+        assert_eq!(block.count, 0);
+        block.restore_state(saved(unix_now())).unwrap();
+        assert_eq!(block.count, 5);
+    }
+
+    #[test]
+    fn test_saved_state_round_trip() {
+        let paused = SavedState::new(&State::Paused(Duration::from_secs(90)), 3, 1000);
+        assert_eq!(paused.state, "paused");
+        assert_eq!(paused.elapsed_secs, 90);
+        match paused.to_state(5000).unwrap() {
+            State::Paused(d) => assert_eq!(d, Duration::from_secs(90)),
+            _ => panic!("expected a paused state"),
+        }
+    }
+
+    #[test]
+    fn test_running_state_includes_downtime() {
+        let started = SavedState {
+            count: 0,
+            state: "started".to_string(),
+            elapsed_secs: 60,
+            saved_at: 1000,
+        };
+        let elapsed = started.to_state(1030).unwrap().elapsed().as_secs();
+        assert!((90..=91).contains(&elapsed));
+    }
+
+    #[test]
+    fn test_unknown_state_is_an_error() {
+        let broken = SavedState {
+            count: 0,
+            state: "sleeping".to_string(),
+            elapsed_secs: 0,
+            saved_at: 0,
+        };
+        assert!(broken.to_state(0).is_err());
+    }
 }
@@ -0,0 +1,298 @@
+//! A block counting the logind sessions of other logins on this machine, e.g.
+//! someone SSHing in or logging on at another seat.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::process;
+
+use crossbeam_channel::Sender;
+use dbus::ffidisp::stdintf::org_freedesktop_dbus::Properties;
+use dbus::ffidisp::{BusType, Connection};
+use dbus::Message;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::dbus_manager::{self, Bus};
+use crate::errors::*;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+use crate::subprocess::spawn_child_async;
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER: &str = "org.freedesktop.login1.Manager";
+const SESSION: &str = "org.freedesktop.login1.Session";
+
+/// The parts of a logind session the block cares about.
+#[derive(Debug, Clone, PartialEq)]
+struct Session {
+    id: String,
+    user: String,
+    /// Empty for sessions not attached to a seat, e.g. SSH logins.
+    seat: String,
+    /// `user`, `greeter`, `lock-screen` or `background`.
+    class: String,
+    /// `online`, `active` or `closing`.
+    state: String,
+    remote: bool,
+}
+
+impl Session {
+    fn is_remote(&self) -> bool {
+        self.remote || self.seat.is_empty()
+    }
+}
+
+/// The sessions of people logged in besides `own_session`. Greeters, lock
+/// screens and background sessions (e.g. cron) are nobody logging in, and
+/// sessions lingering after a logout are on their way out.
+fn other_sessions<'a>(sessions: &'a [Session], own_session: Option<&str>) -> Vec<&'a Session> {
+    sessions
+        .iter()
+        .filter(|session| Some(session.id.as_str()) != own_session)
+        .filter(|session| session.class == "user" && session.state != "closing")
+        .collect()
+}
+
+/// The users of `sessions`, sorted and without duplicates.
+fn user_names(sessions: &[&Session]) -> Vec<String> {
+    sessions
+        .iter()
+        .map(|session| session.user.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+pub struct Sessions {
+    id: String,
+    output: ButtonWidget,
+    format: FormatTemplate,
+    con: Connection,
+    own_session: Option<String>,
+    others: Vec<Session>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// Format override
+    #[serde(default = "SessionsConfig::default_format")]
+    pub format: String,
+}
+
+impl SessionsConfig {
+    fn default_format() -> String {
+        "{count}".to_owned()
+    }
+}
+
+impl ConfigBlock for Sessions {
+    type Config = SessionsConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        let con = Connection::get_private(BusType::System)
+            .block_error("sessions", "Failed to establish D-Bus connection")?;
+        let own_session = own_session(&con);
+
+        for member in &["SessionNew", "SessionRemoved"] {
+            let rule = format!("type='signal',interface='{}',member='{}'", MANAGER, member);
+            if dbus_manager::request_updates(Bus::System, &rule, id.clone(), send.clone()).is_err()
+            {
+                log_block_error(&id, "sessions", "Failed to add D-Bus match rule");
+            }
+        }
+
+        Ok(Sessions {
+            output: ButtonWidget::new(config, &id),
+            id,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("sessions", "Invalid format specified")?,
+            con,
+            own_session,
+            others: Vec::new(),
+        })
+    }
+}
+
+/// The id of the session the bar runs in. `XDG_SESSION_ID` is not set when
+/// the bar is started by e.g. a systemd user service, so ask logind as well.
+fn own_session(con: &Connection) -> Option<String> {
+    if let Ok(id) = env::var("XDG_SESSION_ID") {
+        return Some(id);
+    }
+    let msg = Message::new_method_call(LOGIND, LOGIND_PATH, MANAGER, "GetSessionByPID")
+        .ok()?
+        .append1(process::id());
+    let reply = con.send_with_reply_and_block(msg, 1000).ok()?;
+    let path: dbus::Path = reply.get1()?;
+    con.with_path(LOGIND, path, 1000).get(SESSION, "Id").ok()
+}
+
+impl Sessions {
+    fn list_sessions(&self) -> Result<Vec<Session>> {
+        let msg = Message::new_method_call(LOGIND, LOGIND_PATH, MANAGER, "ListSessions")
+            .block_error("sessions", "Failed to create D-Bus message")?;
+        let reply = self
+            .con
+            .send_with_reply_and_block(msg, 1000)
+            .block_error("sessions", "Failed to list sessions")?;
+        let listed: Vec<(String, u32, String, String, dbus::Path)> = reply
+            .read1()
+            .block_error("sessions", "Failed to parse session list")?;
+
+        Ok(listed
+            .into_iter()
+            .map(|(id, _uid, user, seat, path)| {
+                // Sessions can go away while we look at them, which makes the
+                // property lookups fail. They are then filtered out as closing.
+                let p = self.con.with_path(LOGIND, path, 1000);
+                Session {
+                    id,
+                    user,
+                    seat,
+                    class: p.get(SESSION, "Class").unwrap_or_default(),
+                    state: p
+                        .get(SESSION, "State")
+                        .unwrap_or_else(|_| "closing".to_owned()),
+                    remote: p.get(SESSION, "Remote").unwrap_or(false),
+                }
+            })
+            .collect())
+    }
+}
+
+impl Block for Sessions {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let sessions = self.list_sessions()?;
+        self.others = other_sessions(&sessions, self.own_session.as_deref())
+            .into_iter()
+            .cloned()
+            .collect();
+
+        let others: Vec<&Session> = self.others.iter().collect();
+        let values = map!("{count}" => others.len().to_string(),
+                          "{users}" => user_names(&others).join(", "));
+        self.output
+            .set_text(self.format.render_static_str(&values)?);
+        self.output
+            .set_state(if others.iter().any(|session| session.is_remote()) {
+                State::Warning
+            } else {
+                State::Idle
+            });
+        Ok(None)
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        if self.others.is_empty() {
+            vec![]
+        } else {
+            vec![&self.output]
+        }
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) && event.button == MouseButton::Left {
+            let body = self
+                .others
+                .iter()
+                .map(|session| {
+                    let location = if session.is_remote() {
+                        "remote"
+                    } else {
+                        session.seat.as_str()
+                    };
+                    format!("{} ({})", session.user, location)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            spawn_child_async("notify-send", &["Other sessions", &body])
+                .block_error("sessions", "could not spawn notify-send")?;
+        }
+        Ok(())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(
+        id: &str,
+        user: &str,
+        seat: &str,
+        class: &str,
+        state: &str,
+        remote: bool,
+    ) -> Session {
+        Session {
+            id: id.to_owned(),
+            user: user.to_owned(),
+            seat: seat.to_owned(),
+            class: class.to_owned(),
+            state: state.to_owned(),
+            remote,
+        }
+    }
+
+    fn ids<'a>(sessions: &[&'a Session]) -> Vec<&'a str> {
+        sessions.iter().map(|session| session.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_single_user_desktop() {
+        // `loginctl list-sessions` on a laptop: the own graphical session, a
+        // tty login of the same user, and the greeter that started it all.
+        let sessions = vec![
+            session("2", "alice", "seat0", "user", "active", false),
+            session("5", "alice", "seat0", "user", "online", false),
+            session("c1", "gdm", "seat0", "greeter", "online", false),
+        ];
+        let others = other_sessions(&sessions, Some("2"));
+        assert_eq!(ids(&others), vec!["5"]);
+        assert!(!others[0].is_remote());
+    }
+
+    #[test]
+    fn test_shared_server() {
+        let sessions = vec![
+            session("3", "alice", "seat0", "user", "active", false),
+            session("41", "bob", "", "user", "active", true),
+            session("42", "bob", "", "user", "active", true),
+            session("43", "carol", "", "user", "closing", true),
+            session("44", "root", "", "background", "active", false),
+            session("c2", "lightdm", "seat1", "greeter", "online", false),
+            session("c3", "dave", "seat1", "lock-screen", "active", false),
+        ];
+        let others = other_sessions(&sessions, Some("3"));
+        assert_eq!(ids(&others), vec!["41", "42"]);
+        assert!(others.iter().all(|session| session.is_remote()));
+        assert_eq!(user_names(&others), vec!["bob"]);
+    }
+
+    #[test]
+    fn test_unknown_own_session() {
+        // Without knowing our own session it is counted, which is better
+        // than hiding someone else's.
+        let sessions = vec![
+            session("3", "alice", "seat0", "user", "active", false),
+            session("7", "bob", "seat1", "user", "active", false),
+        ];
+        let others = other_sessions(&sessions, None);
+        assert_eq!(ids(&others), vec!["3", "7"]);
+        assert_eq!(user_names(&others), vec!["alice", "bob"]);
+        assert!(other_sessions(&[], Some("3")).is_empty());
+    }
+}
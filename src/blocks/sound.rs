@@ -13,7 +13,6 @@ use {
     crossbeam_channel::unbounded,
     lazy_static::lazy_static,
     std::cell::RefCell,
-    std::cmp::min,
     std::collections::HashMap,
     std::convert::{TryFrom, TryInto},
     std::ops::Deref,
@@ -21,9 +20,12 @@ use {
     std::sync::Mutex,
 };
 
-use std::cmp::max;
+use std::cmp::{max, min};
 use std::collections::BTreeMap;
+use std::fs;
 use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -33,13 +35,15 @@ use serde_derive::Deserialize;
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::{Config, LogicalDirection};
 use crate::errors::*;
-use crate::input::{I3BarEvent, MouseButton};
+use crate::formatting::Value;
+use crate::input::{ClickAction, ClickBindings, I3BarEvent, MouseButton};
 use crate::scheduler::Task;
+use crate::stop::{self, Stop, Wake};
 use crate::subprocess::spawn_child_async;
-use crate::util::{format_percent_bar, FormatTemplate};
+use crate::util::{format_percent_bar, xdg_state_home, FormatTemplate};
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::button::ButtonWidget;
 
@@ -60,6 +64,8 @@ struct AlsaSoundDevice {
     natural_mapping: bool,
     volume: u32,
     muted: bool,
+    /// Stops monitoring volume changes when the block is dropped.
+    _monitor: Option<Stop>,
 }
 
 impl AlsaSoundDevice {
@@ -70,6 +76,7 @@ impl AlsaSoundDevice {
             natural_mapping,
             volume: 0,
             muted: false,
+            _monitor: None,
         };
         sd.get_info()?;
 
@@ -165,38 +172,47 @@ impl SoundDevice for AlsaSoundDevice {
     }
 
     fn monitor(&mut self, id: String, tx_update_request: Sender<Task>) -> Result<()> {
+        let (stop, stopped) = stop::pair()?;
         // Monitor volume changes in a separate thread.
         thread::Builder::new()
             .name("sound_alsa".into())
             .spawn(move || {
                 // Line-buffer to reduce noise.
-                let mut monitor = Command::new("stdbuf")
+                let mut child = Command::new("stdbuf")
                     .args(&["-oL", "alsactl", "monitor"])
                     .stdout(Stdio::piped())
                     .spawn()
-                    .expect("Failed to start alsactl monitor")
+                    .expect("Failed to start alsactl monitor");
+                let mut monitor = child
                     .stdout
+                    .take()
                     .expect("Failed to pipe alsactl monitor output");
 
                 let mut buffer = [0; 1024]; // Should be more than enough.
-                loop {
-                    // Block until we get some output. Doesn't really matter what
-                    // the output actually is -- these are events -- we just update
-                    // the sound information if *something* happens.
+                                            // Block until we get some output. Doesn't really matter what
+                                            // the output actually is -- these are events -- we just update
+                                            // the sound information if *something* happens.
+                while stopped.wait(Some(monitor.as_raw_fd()), None).ok() == Some(Wake::Ready) {
                     if monitor.read(&mut buffer).is_ok() {
-                        tx_update_request
-                            .send(Task {
-                                id: id.clone(),
-                                update_time: Instant::now(),
-                            })
-                            .unwrap();
+                        let task = Task {
+                            id: id.clone(),
+                            update_time: Instant::now(),
+                        };
+                        if tx_update_request.send(task).is_err() {
+                            break;
+                        }
                     }
                     // Don't update too often. Wait 1/4 second, fast enough for
                     // volume button mashing but slow enough to skip event spam.
-                    thread::sleep(Duration::new(0, 250_000_000))
+                    if !stopped.sleep(Duration::new(0, 250_000_000)) {
+                        break;
+                    }
                 }
+                child.kill().ok();
+                child.wait().ok();
             })
             .unwrap();
+        self._monitor = Some(stop);
 
         Ok(())
     }
@@ -558,14 +574,18 @@ impl PulseAudioClient {
     }
 
     fn send_update_event() {
-        for (id, tx_update_request) in &*PULSEAUDIO_EVENT_LISTENER.lock().unwrap() {
-            tx_update_request
-                .send(Task {
-                    id: id.clone(),
-                    update_time: Instant::now(),
-                })
-                .unwrap();
-        }
+        // Blocks replaced by a reload no longer listen.
+        PULSEAUDIO_EVENT_LISTENER
+            .lock()
+            .unwrap()
+            .retain(|id, tx_update_request| {
+                tx_update_request
+                    .send(Task {
+                        id: id.clone(),
+                        update_time: Instant::now(),
+                    })
+                    .is_ok()
+            });
     }
 }
 
@@ -685,6 +705,89 @@ impl SoundDevice for PulseAudioSoundDevice {
     }
 }
 
+/// Remembers the volume of each device, so that a device coming back (e.g.
+/// Bluetooth headphones reconnecting) can be set back to it.
+#[derive(Debug, Default)]
+struct VolumeMemory {
+    path: PathBuf,
+    volumes: BTreeMap<String, u32>,
+    /// Volumes further off the remembered one than this are restored.
+    threshold: u32,
+    /// The device seen at the last update.
+    current: Option<String>,
+    /// The volume the current device came up with, if it was restored and
+    /// the restored volume has not shown up yet.
+    restoring: Option<u32>,
+}
+
+impl VolumeMemory {
+    fn load(path: PathBuf, threshold: u32) -> Self {
+        let volumes = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        VolumeMemory {
+            path,
+            volumes,
+            threshold,
+            ..VolumeMemory::default()
+        }
+    }
+
+    /// Takes note of the volume of the device `name`. Returns the volume to
+    /// set it to if the device just appeared with a volume too far off the
+    /// remembered one; this happens only once per appearance, so the block
+    /// never fights with other tools over the volume.
+    fn observe(&mut self, name: &str, volume: u32) -> Option<u32> {
+        if self.current.as_deref() != Some(name) {
+            self.current = Some(name.to_string());
+            self.restoring = None;
+            if let Some(&remembered) = self.volumes.get(name) {
+                if (remembered as i64 - volume as i64).abs() > self.threshold as i64 {
+                    self.restoring = Some(volume);
+                    return Some(remembered);
+                }
+            }
+        }
+
+        // Until the restored volume comes through, updates still show the
+        // volume the device came up with, which must not be remembered.
+        if self.restoring == Some(volume) {
+            return None;
+        }
+        self.restoring = None;
+        if self.volumes.get(name) != Some(&volume) {
+            self.volumes.insert(name.to_string(), volume);
+            if let Err(e) = save_volumes(&self.path, &self.volumes) {
+                eprintln!("Failed to save volumes: {:?}", e);
+            }
+        }
+        None
+    }
+}
+
+fn save_volumes(path: &Path, volumes: &BTreeMap<String, u32>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).block_error(
+            "sound",
+            &format!("failed to create directory {}", parent.to_string_lossy()),
+        )?;
+    }
+    let contents =
+        serde_json::to_string(volumes).block_error("sound", "failed to serialize volumes")?;
+    // Write to a temporary file first so a crash midway never leaves a
+    // truncated file behind.
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, contents).block_error(
+        "sound",
+        &format!("failed to write {}", tmp_path.to_string_lossy()),
+    )?;
+    fs::rename(&tmp_path, path).block_error(
+        "sound",
+        &format!("failed to move volumes to {}", path.to_string_lossy()),
+    )
+}
+
 // TODO: Use the alsa control bindings to implement push updates
 pub struct Sound {
     text: ButtonWidget,
@@ -694,11 +797,12 @@ pub struct Sound {
     step_width: u32,
     format: FormatTemplate,
     config: Config,
-    on_click: Option<String>,
     show_volume_when_muted: bool,
     bar: bool,
     mappings: Option<BTreeMap<String, String>>,
     max_vol: Option<u32>,
+    volume_memory: Option<VolumeMemory>,
+    click: ClickBindings<SoundAction>,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
@@ -757,9 +861,6 @@ pub struct SoundConfig {
     #[serde(default = "SoundConfig::default_format")]
     pub format: String,
 
-    #[serde(default = "SoundConfig::default_on_click")]
-    pub on_click: Option<String>,
-
     #[serde(default = "SoundConfig::default_show_volume_when_muted")]
     pub show_volume_when_muted: bool,
 
@@ -772,6 +873,30 @@ pub struct SoundConfig {
 
     #[serde(default = "SoundConfig::default_max_vol")]
     pub max_vol: Option<u32>,
+
+    /// Remember the volume of each device, and set a device back to it when
+    /// it appears with a different volume
+    #[serde(default = "SoundConfig::default_restore_volumes")]
+    pub restore_volumes: bool,
+
+    /// How far, in percent, the volume of a device may be off the remembered
+    /// one before it is restored
+    #[serde(default = "SoundConfig::default_restore_threshold")]
+    pub restore_threshold: u32,
+
+    /// Actions bound to clicks, replacing muting on right click and changing
+    /// the volume on scroll
+    #[serde(default)]
+    pub click: ClickBindings<SoundAction>,
+}
+
+/// What a click on the sound block can do.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SoundAction {
+    ToggleMute,
+    VolumeUp,
+    VolumeDown,
 }
 
 #[derive(Deserialize, Copy, Clone, Debug)]
@@ -810,10 +935,6 @@ impl SoundConfig {
         "{volume}%".into()
     }
 
-    fn default_on_click() -> Option<String> {
-        None
-    }
-
     fn default_show_volume_when_muted() -> bool {
         false
     }
@@ -829,6 +950,14 @@ impl SoundConfig {
     fn default_max_vol() -> Option<u32> {
         None
     }
+
+    fn default_restore_volumes() -> bool {
+        false
+    }
+
+    fn default_restore_threshold() -> u32 {
+        5
+    }
 }
 
 impl Sound {
@@ -851,8 +980,22 @@ impl Sound {
     fn display(&mut self) -> Result<()> {
         self.device.get_info()?;
 
-        let volume = self.device.volume();
         let output_name = self.device.output_name();
+        // PulseAudio names the default device `@DEFAULT_SINK@` until it told
+        // us which one that is.
+        if !output_name.starts_with('@') {
+            let volume = self.device.volume();
+            let restore = self
+                .volume_memory
+                .as_mut()
+                .and_then(|memory| memory.observe(&output_name, volume));
+            if let Some(restore) = restore {
+                self.device
+                    .set_volume(restore as i32 - volume as i32, None)?;
+            }
+        }
+
+        let volume = self.device.volume();
         let mapped_output_name = if let Some(m) = &self.mappings {
             match m.get(&output_name) {
                 Some(mapping) => mapping.to_string(),
@@ -861,8 +1004,9 @@ impl Sound {
         } else {
             output_name
         };
-        let values = map!("{volume}" => format!("{:02}", volume),
-                          "{output_name}" => mapped_output_name
+        let values = map!("{volume}" => Value::from(format!("{:02}", volume)),
+                          "{bar}" => Value::bar(volume),
+                          "{output_name}" => Value::from(mapped_output_name)
         );
         let text = self.format.render_static_str(&values)?;
 
@@ -944,11 +1088,19 @@ impl ConfigBlock for Sound {
             format: FormatTemplate::from_string(&block_config.format)?,
             step_width,
             config,
-            on_click: block_config.on_click,
             show_volume_when_muted: block_config.show_volume_when_muted,
             bar: block_config.bar,
             mappings: block_config.mappings,
             max_vol: block_config.max_vol,
+            volume_memory: if block_config.restore_volumes {
+                Some(VolumeMemory::load(
+                    xdg_state_home().join("i3status-rust/sound_volumes.json"),
+                    block_config.restore_threshold,
+                ))
+            } else {
+                None
+            },
+            click: block_config.click,
         };
 
         sound.device.monitor(id, tx_update_request)?;
@@ -971,37 +1123,86 @@ impl Block for Sound {
     }
 
     fn click(&mut self, e: &I3BarEvent) -> Result<()> {
-        if let Some(ref name) = e.name {
-            if name.as_str() == self.id {
-                match e.button {
-                    MouseButton::Right => self.device.toggle()?,
-                    MouseButton::Left => {
-                        if let Some(ref cmd) = self.on_click {
-                            spawn_child_async("sh", &["-c", cmd])
-                                .block_error("sound", "could not spawn child")?;
-                        }
-                    }
-                    _ => {
-                        use LogicalDirection::*;
-                        match self.config.scrolling.to_logical_direction(e.button) {
-                            Some(Up) => self
-                                .device
-                                .set_volume(self.step_width as i32, self.max_vol)?,
-                            Some(Down) => self
-                                .device
-                                .set_volume(-(self.step_width as i32), self.max_vol)?,
-                            None => (),
-                        }
-                    }
+        if e.matches_name(&self.id) {
+            let action = match self.click.get(e).cloned() {
+                Some(ClickAction::Block(action)) => Some(action),
+                Some(ClickAction::Command { cmd }) => {
+                    spawn_child_async("sh", &["-c", &cmd])
+                        .block_error("sound", "could not spawn child")?;
+                    None
                 }
-                self.display()?;
+                None => match e.button {
+                    MouseButton::Right => Some(SoundAction::ToggleMute),
+                    _ => match self.config.scrolling.to_logical_direction(e.button) {
+                        Some(LogicalDirection::Up) => Some(SoundAction::VolumeUp),
+                        Some(LogicalDirection::Down) => Some(SoundAction::VolumeDown),
+                        None => None,
+                    },
+                },
+            };
+            match action {
+                Some(SoundAction::ToggleMute) => self.device.toggle()?,
+                Some(SoundAction::VolumeUp) => self
+                    .device
+                    .set_volume(self.step_width as i32, self.max_vol)?,
+                Some(SoundAction::VolumeDown) => self
+                    .device
+                    .set_volume(-(self.step_width as i32), self.max_vol)?,
+                None => (),
             }
+            self.display()?;
         }
 
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn test_restore_once_per_appearance() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("volumes.json");
+        let mut memory = VolumeMemory::load(path.clone(), 5);
+        assert_eq!(memory.observe("headphones", 40), None);
+        assert_eq!(memory.observe("speakers", 70), None);
+
+        // The headphones reconnect at full blast and are turned down again.
+        assert_eq!(memory.observe("headphones", 100), Some(40));
+        assert_eq!(memory.observe("headphones", 100), None);
+        assert_eq!(memory.observe("headphones", 40), None);
+
+        // Changes after that are remembered, not fought.
+        assert_eq!(memory.observe("headphones", 90), None);
+        assert_eq!(memory.observe("speakers", 70), None);
+        assert_eq!(memory.observe("headphones", 93), None);
+
+        // The remembered volumes survive a restart.
+        let mut memory = VolumeMemory::load(path, 5);
+        assert_eq!(memory.observe("speakers", 70), None);
+        assert_eq!(memory.observe("headphones", 20), Some(93));
+    }
+
+    #[test]
+    fn test_restore_threshold() {
+        let dir = TempDir::new().unwrap();
+        let mut memory = VolumeMemory::load(dir.path().join("volumes.json"), 5);
+        assert_eq!(memory.observe("headphones", 40), None);
+        assert_eq!(memory.observe("speakers", 70), None);
+        // Close enough to leave alone, and remembered as is.
+        assert_eq!(memory.observe("headphones", 45), None);
+        assert_eq!(memory.observe("speakers", 70), None);
+        assert_eq!(memory.observe("headphones", 51), Some(45));
+    }
+}
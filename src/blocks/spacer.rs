@@ -0,0 +1,201 @@
+//! A block showing a fixed text, to group or pad other blocks.
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::errors::*;
+use crate::input::I3BarEvent;
+use crate::scheduler::Task;
+use crate::widget::{Align, I3BarWidget, MinWidth, State};
+use crate::widgets::text::TextWidget;
+
+pub struct Spacer {
+    id: String,
+    text: TextWidget,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SpacerConfig {
+    /// The text to show, may be empty
+    #[serde(default = "SpacerConfig::default_text")]
+    pub text: String,
+
+    /// The theme colors to use
+    #[serde(default = "SpacerConfig::default_state")]
+    pub state: State,
+
+    /// Minimum width, in pixels or as wide as a text
+    #[serde(default = "SpacerConfig::default_min_width")]
+    pub min_width: Option<MinWidth>,
+
+    /// Alignment of the text within `min_width`
+    #[serde(default = "SpacerConfig::default_align")]
+    pub align: Option<Align>,
+}
+
+impl SpacerConfig {
+    fn default_text() -> String {
+        String::new()
+    }
+
+    fn default_state() -> State {
+        State::Idle
+    }
+
+    fn default_min_width() -> Option<MinWidth> {
+        None
+    }
+
+    fn default_align() -> Option<Align> {
+        None
+    }
+}
+
+impl ConfigBlock for Spacer {
+    type Config = SpacerConfig;
+
+    fn new(block_config: Self::Config, config: Config, _send: Sender<Task>) -> Result<Self> {
+        if block_config.align.is_some() && block_config.min_width.is_none() {
+            return Err(BlockError(
+                "spacer".to_owned(),
+                "align has no effect without min_width".to_owned(),
+            ));
+        }
+        Ok(Spacer {
+            id: Uuid::new_v4().to_simple().to_string(),
+            text: TextWidget::new(config)
+                .with_min_width(block_config.min_width)
+                .with_align(block_config.align)
+                .with_state(block_config.state)
+                .with_text(&block_config.text),
+        })
+    }
+}
+
+impl Block for Spacer {
+    fn update(&mut self) -> Result<Option<Update>> {
+        Ok(None)
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.text]
+    }
+
+    fn click(&mut self, _: &I3BarEvent) -> Result<()> {
+        Ok(())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocks::create_block;
+    use crate::config::SharedBlockConfig;
+    use crate::util::render_blocks;
+
+    use super::*;
+
+    /// The line the bar prints for the blocks in `blocks`, set up as on
+    /// startup.
+    fn render(blocks: &str) -> String {
+        let config: Config =
+            toml::from_str(&format!("[theme]\nname = \"plain\"\n{}", blocks)).unwrap();
+        let (send, _recv) = crossbeam_channel::unbounded();
+        let mut created = Vec::new();
+        for (name, block_config) in &config.blocks {
+            let mut block_config = block_config.clone();
+            let shared_config = SharedBlockConfig::extract(name, &mut block_config).unwrap();
+            let block = create_block(
+                name,
+                block_config,
+                shared_config.block_config(&config).unwrap(),
+                send.clone(),
+            )
+            .unwrap();
+            shared_config.register(&*block).unwrap();
+            created.push(block);
+        }
+        let blocks: Vec<&dyn Block> = created.iter().map(|block| &**block).collect();
+        let mut line = render_blocks(&blocks, &config).unwrap();
+        // Widgets are named after their block, whose id is random.
+        for (i, block) in blocks.iter().enumerate() {
+            line = line.replace(block.id(), &format!("block{}", i));
+        }
+        line
+    }
+
+    const SEPARATOR: &str = r##"{"background":"#000000","color":"#a9a9a9","full_text":"| ","markup":"pango","separator":false,"separator_block_width":0}"##;
+
+    #[test]
+    fn test_label() {
+        assert_eq!(
+            render("[[block]]\nblock = \"spacer\"\ntext = \"work\"\n"),
+            format!(
+                r##"[{},{{"background":"#000000","color":"#93a1a1","full_text":" work ","name":"block0","separator":false,"separator_block_width":0}}],"##,
+                SEPARATOR
+            )
+        );
+    }
+
+    #[test]
+    fn test_empty_spacer_with_width() {
+        assert_eq!(
+            render("[[block]]\nblock = \"spacer\"\nmin_width = 40\nstate = \"Critical\"\n"),
+            format!(
+                r##"[{},{{"background":"#000000","color":"#dc322f","full_text":"  ","min_width":40,"name":"block0","separator":false,"separator_block_width":0}}],"##,
+                SEPARATOR
+            )
+        );
+        assert_eq!(
+            render(concat!(
+                "[[block]]\nblock = \"spacer\"\ntext = \"a\"\n",
+                "min_width = \"mmmm\"\nalign = \"right\"\n",
+            )),
+            format!(
+                r##"[{},{{"align":"right","background":"#000000","color":"#93a1a1","full_text":" a ","min_width":"mmmm","name":"block0","separator":false,"separator_block_width":0}}],"##,
+                SEPARATOR
+            )
+        );
+    }
+
+    #[test]
+    fn test_theme_overrides_and_separator() {
+        let line = render(concat!(
+            "[[block]]\nblock = \"spacer\"\ntext = \"a\"\n",
+            "[[block]]\nblock = \"spacer\"\ntext = \"b\"\nseparator = false\n",
+            "state = \"Good\"\n",
+            "theme_overrides = { good_bg = \"#123456\", good_fg = \"auto\" }\n",
+        ));
+        assert_eq!(
+            line,
+            format!(
+                r##"[{},{{"background":"#000000","color":"#93a1a1","full_text":" a ","name":"block0","separator":false,"separator_block_width":0}},{{"background":"#123456","color":"#ffffff","full_text":" b ","name":"block1","separator":false,"separator_block_width":0}}],"##,
+                SEPARATOR
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let (send, _recv) = crossbeam_channel::unbounded();
+        let create = |options: &str| {
+            create_block(
+                "spacer",
+                toml::from_str(options).unwrap(),
+                Config::default(),
+                send.clone(),
+            )
+        };
+        assert!(create("align = \"left\"").is_err());
+        assert!(create("state = \"Bright\"").is_err());
+        assert!(create("min_width = -5").is_err());
+    }
+}
@@ -9,7 +9,7 @@ use serde_derive::Deserialize;
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
@@ -129,8 +129,9 @@ fn make_thread(
 ) {
     thread::Builder::new()
         .name("speedtest".into())
-        .spawn(move || loop {
-            if recv.recv().is_ok() {
+        .spawn(move || {
+            // Ends once the block, and with it the sending end, is dropped.
+            while recv.recv().is_ok() {
                 if let Ok(output) = get_values(config.bytes) {
                     if let Ok(vals) = parse_values(&output) {
                         if vals.len() == 3 {
@@ -145,7 +146,7 @@ fn make_thread(
                                 id: id.clone(),
                                 update_time: Instant::now(),
                             })
-                            .unwrap();
+                            .ok();
                         }
                     }
                 }
@@ -249,6 +250,10 @@ impl Block for SpeedTest {
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn view(&self) -> Vec<&dyn I3BarWidget> {
         let mut new: Vec<&dyn I3BarWidget> = Vec::with_capacity(self.text.len());
         for w in &self.text {
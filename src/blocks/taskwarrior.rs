@@ -22,8 +22,8 @@ pub struct Taskwarrior {
     update_interval: Duration,
     warning_threshold: u32,
     critical_threshold: u32,
-    filter_tags: Vec<String>,
-    block_mode: TaskwarriorBlockMode,
+    filters: Vec<Filter>,
+    filter_index: usize,
     format: FormatTemplate,
     format_singular: FormatTemplate,
     format_everything_done: FormatTemplate,
@@ -57,6 +57,11 @@ pub struct TaskwarriorConfig {
     #[serde(default = "TaskwarriorConfig::default_filter_tags")]
     pub filter_tags: Vec<String>,
 
+    /// Named filters to count pending tasks with, cycled by right clicking.
+    /// Replaces `filter_tags`.
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+
     /// Format override
     #[serde(default = "TaskwarriorConfig::default_format")]
     pub format: String,
@@ -70,11 +75,12 @@ pub struct TaskwarriorConfig {
     pub format_everything_done: String,
 }
 
-enum TaskwarriorBlockMode {
-    // Show only the tasks which are filtered by the set tags and which are not completed.
-    OnlyFilteredPendingTasks,
-    // Show all pending tasks and ignore the filtering tags.
-    AllPendingTasks,
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    pub name: String,
+    /// A taskwarrior filter, e.g. `+urgent project:work`
+    pub filter: String,
 }
 
 impl TaskwarriorConfig {
@@ -97,6 +103,30 @@ impl TaskwarriorConfig {
     fn default_format() -> String {
         "{count}".to_owned()
     }
+
+    /// The configured `filters`, or else a filter for `filter_tags` and one for
+    /// all tasks.
+    fn filters(&self) -> Result<Vec<Filter>> {
+        if self.filters.is_empty() {
+            Ok(vec![
+                Filter {
+                    name: "filtered".to_owned(),
+                    filter: tags_to_filter(&self.filter_tags),
+                },
+                Filter {
+                    name: "all".to_owned(),
+                    filter: String::new(),
+                },
+            ])
+        } else if self.filter_tags.is_empty() {
+            Ok(self.filters.clone())
+        } else {
+            Err(BlockError(
+                "taskwarrior".to_owned(),
+                "filter_tags and filters cannot be used together".to_owned(),
+            ))
+        }
+    }
 }
 
 impl ConfigBlock for Taskwarrior {
@@ -112,8 +142,8 @@ impl ConfigBlock for Taskwarrior {
             update_interval: block_config.interval,
             warning_threshold: block_config.warning_threshold,
             critical_threshold: block_config.critical_threshold,
-            filter_tags: block_config.filter_tags,
-            block_mode: TaskwarriorBlockMode::OnlyFilteredPendingTasks,
+            filters: block_config.filters()?,
+            filter_index: 0,
             output: ButtonWidget::new(config.clone(), "taskwarrior")
                 .with_icon("tasks")
                 .with_text("-"),
@@ -162,15 +192,12 @@ fn tags_to_filter(tags: &[String]) -> String {
         .join(" ")
 }
 
-fn get_number_of_pending_tasks(tags: &[String]) -> Result<u32> {
+fn get_number_of_pending_tasks(filter: &str) -> Result<u32> {
     String::from_utf8(
         Command::new("sh")
             .args(&[
                 "-c",
-                &format!(
-                    "task rc.gc=off -COMPLETED -DELETED {} count",
-                    tags_to_filter(tags)
-                ),
+                &format!("task rc.gc=off -COMPLETED -DELETED {} count", filter),
             ])
             .output()
             .block_error(
@@ -193,12 +220,10 @@ impl Block for Taskwarrior {
         if !has_taskwarrior()? {
             self.output.set_text("?")
         } else {
-            let filter_tags = match self.block_mode {
-                TaskwarriorBlockMode::OnlyFilteredPendingTasks => self.filter_tags.clone(),
-                TaskwarriorBlockMode::AllPendingTasks => vec![],
-            };
-            let number_of_pending_tasks = get_number_of_pending_tasks(&filter_tags)?;
-            let values = map!("{count}" => number_of_pending_tasks);
+            let filter = &self.filters[self.filter_index];
+            let number_of_pending_tasks = get_number_of_pending_tasks(&filter.filter)?;
+            let values = map!("{count}" => format!("{}", number_of_pending_tasks),
+                              "{filter_name}" => filter.name.clone());
             self.output.set_text(match number_of_pending_tasks {
                 0 => self.format_everything_done.render_static_str(&values)?,
                 1 => self.format_singular.render_static_str(&values)?,
@@ -233,14 +258,7 @@ impl Block for Taskwarrior {
                     self.update()?;
                 }
                 MouseButton::Right => {
-                    match self.block_mode {
-                        TaskwarriorBlockMode::OnlyFilteredPendingTasks => {
-                            self.block_mode = TaskwarriorBlockMode::AllPendingTasks
-                        }
-                        TaskwarriorBlockMode::AllPendingTasks => {
-                            self.block_mode = TaskwarriorBlockMode::OnlyFilteredPendingTasks
-                        }
-                    }
+                    self.filter_index = (self.filter_index + 1) % self.filters.len();
                     self.update()?;
                 }
                 _ => {}
@@ -254,3 +272,44 @@ impl Block for Taskwarrior {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters(config: &str) -> Result<Vec<Filter>> {
+        toml::from_str::<TaskwarriorConfig>(config)
+            .unwrap()
+            .filters()
+    }
+
+    fn filter(name: &str, filter: &str) -> Filter {
+        Filter {
+            name: name.to_owned(),
+            filter: filter.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            filters("filter_tags = [\"work\", \"important\"]").unwrap(),
+            vec![filter("filtered", "+work +important"), filter("all", "")]
+        );
+        assert_eq!(
+            filters(
+                "[[filters]]\nname = \"urgent\"\nfilter = \"+PENDING +urgent\"\n\
+                 [[filters]]\nname = \"work\"\nfilter = \"project:work\""
+            )
+            .unwrap(),
+            vec![
+                filter("urgent", "+PENDING +urgent"),
+                filter("work", "project:work")
+            ]
+        );
+        assert!(
+            filters("filter_tags = [\"work\"]\n[[filters]]\nname = \"all\"\nfilter = \"\"")
+                .is_err()
+        );
+    }
+}
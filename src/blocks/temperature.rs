@@ -11,6 +11,7 @@ use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
 use crate::util::FormatTemplate;
@@ -143,49 +144,69 @@ impl ConfigBlock for Temperature {
 type SensorsOutput = HashMap<String, HashMap<String, serde_json::Value>>;
 type InputReadings = HashMap<String, f64>;
 
-impl Block for Temperature {
-    fn update(&mut self) -> Result<Option<Update>> {
-        let mut args = vec!["-j"];
-        if let Some(ref chip) = &self.chip {
-            args.push(chip);
-        }
-        let output = Command::new("sensors")
-            .args(&args)
-            .output()
-            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
-            .unwrap_or_else(|e| e.to_string());
-
-        let parsed: SensorsOutput = serde_json::from_str(&output)
-            .block_error("temperature", "sensors output is invalid")?;
+/// Read all values called `<kind><n>_input` (e.g. `temp1_input` or
+/// `humidity1_input`) reported by `sensors`, optionally limited to one chip and
+/// to a whitelist of input labels.
+pub fn read_sensors(
+    chip: Option<&str>,
+    inputs: Option<&Vec<String>>,
+    kind: &str,
+) -> Result<Vec<f64>> {
+    let mut args = vec!["-j"];
+    if let Some(chip) = chip {
+        args.push(chip);
+    }
+    let output = Command::new("sensors")
+        .args(&args)
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+        .unwrap_or_else(|e| e.to_string());
+
+    let parsed: SensorsOutput =
+        serde_json::from_str(&output).block_error("sensors", "sensors output is invalid")?;
+    Ok(collect_readings(parsed, inputs, kind))
+}
 
-        let mut temperatures: Vec<i64> = Vec::new();
-        for (_chip, inputs) in parsed {
-            for (input_name, input_values) in inputs {
-                if let Some(ref whitelist) = self.inputs {
-                    if !whitelist.contains(&input_name) {
-                        continue;
-                    }
+fn collect_readings(parsed: SensorsOutput, inputs: Option<&Vec<String>>, kind: &str) -> Vec<f64> {
+    let mut readings = Vec::new();
+    for (_chip, chip_inputs) in parsed {
+        for (input_name, input_values) in chip_inputs {
+            if let Some(whitelist) = inputs {
+                if !whitelist.contains(&input_name) {
+                    continue;
                 }
+            }
 
-                let values_parsed: InputReadings = match serde_json::from_value(input_values) {
-                    Ok(values) => values,
-                    Err(_) => continue, // probably the "Adapter" key, just ignore.
-                };
-
-                for (value_name, value) in values_parsed {
-                    if !value_name.starts_with("temp") || !value_name.ends_with("input") {
-                        continue;
-                    }
-
-                    if value > -101f64 && value < 151f64 {
-                        temperatures.push(value as i64);
-                    } else {
-                        // This error is recoverable and therefore should not stop the program
-                        eprintln!("Temperature ({}) outside of range ([-100, 150])", value);
-                    }
+            let values_parsed: InputReadings = match serde_json::from_value(input_values) {
+                Ok(values) => values,
+                Err(_) => continue, // probably the "Adapter" key, just ignore.
+            };
+
+            for (value_name, value) in values_parsed {
+                if value_name.starts_with(kind) && value_name.ends_with("input") {
+                    readings.push(value);
                 }
             }
         }
+    }
+    readings
+}
+
+impl Block for Temperature {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let mut temperatures: Vec<i64> = Vec::new();
+        for value in read_sensors(self.chip.as_deref(), self.inputs.as_ref(), "temp")? {
+            if value > -101f64 && value < 151f64 {
+                temperatures.push(value as i64);
+            } else {
+                // This error is recoverable and therefore should not stop the program
+                log_block_error(
+                    &self.id,
+                    "temperature",
+                    &format!("Temperature ({}) outside of range ([-100, 150])", value),
+                );
+            }
+        }
 
         if !temperatures.is_empty() {
             let max: i64 = *temperatures
@@ -199,9 +220,9 @@ impl Block for Temperature {
             let avg: i64 = (temperatures.iter().sum::<i64>() as f64 / temperatures.len() as f64)
                 .round() as i64;
 
-            let values = map!("{average}" => avg,
-                              "{min}" => min,
-                              "{max}" => max);
+            let values = map!("{average}" => Value::integer(avg),
+                              "{min}" => Value::integer(min),
+                              "{max}" => Value::integer(max));
 
             self.output = self.format.render_static_str(&values)?;
             if !self.collapsed {
@@ -245,3 +266,38 @@ impl Block for Temperature {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_readings() {
+        let parsed: SensorsOutput = serde_json::from_str(
+            r#"{
+                "coretemp-isa-0000": {
+                    "Adapter": "ISA adapter",
+                    "Core 0": {"temp2_input": 45.0, "temp2_max": 100.0},
+                    "Core 1": {"temp3_input": 47.0, "temp3_max": 100.0}
+                },
+                "sht3x-i2c-1-44": {
+                    "Adapter": "i2c-tiny-usb",
+                    "temp1": {"temp1_input": 21.5},
+                    "humidity1": {"humidity1_input": 40.2}
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let mut temps = collect_readings(parsed.clone(), None, "temp");
+        temps.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(temps, vec![21.5, 45.0, 47.0]);
+
+        let whitelist = vec!["Core 1".to_string()];
+        assert_eq!(
+            collect_readings(parsed.clone(), Some(&whitelist), "temp"),
+            vec![47.0]
+        );
+        assert_eq!(collect_readings(parsed, None, "humidity"), vec![40.2]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::time::Duration;
 
-use chrono::offset::{Local, Utc};
+use chrono::offset::{FixedOffset, Local, Offset, TimeZone, Utc};
+use chrono::DateTime;
 use chrono_tz::Tz;
 use crossbeam_channel::Sender;
 use serde_derive::Deserialize;
@@ -8,11 +9,10 @@ use uuid::Uuid;
 
 use crate::blocks::{Block, ConfigBlock, Update};
 use crate::config::Config;
-use crate::de::{deserialize_duration, deserialize_timezone};
+use crate::de::{deserialize_duration, deserialize_timezone, deserialize_timezones};
 use crate::errors::*;
 use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
-use crate::subprocess::spawn_child_async;
 use crate::widget::I3BarWidget;
 use crate::widgets::button::ButtonWidget;
 
@@ -20,17 +20,25 @@ pub struct Time {
     time: ButtonWidget,
     id: String,
     update_interval: Duration,
-    format: String,
-    on_click: Option<String>,
-    timezone: Option<Tz>,
+    /// The formats to cycle through, the current one first.
+    formats: Vec<String>,
+    /// The timezones to show, `None` being the local one.
+    timezones: Vec<Option<Tz>>,
+    separator: String,
+    cycle: bool,
+    current: usize,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct TimeConfig {
     /// Format string.<br/> See [chrono docs](https://docs.rs/chrono/0.3.0/chrono/format/strftime/index.html#specifiers) for all options.
-    #[serde(default = "TimeConfig::default_format")]
-    pub format: String,
+    #[serde(default)]
+    pub format: Option<String>,
+
+    /// Several format strings, cycled by clicking
+    #[serde(default)]
+    pub formats: Vec<String>,
 
     /// Update interval in seconds
     #[serde(
@@ -39,14 +47,23 @@ pub struct TimeConfig {
     )]
     pub interval: Duration,
 
-    #[serde(default = "TimeConfig::default_on_click")]
-    pub on_click: Option<String>,
-
     #[serde(
         default = "TimeConfig::default_timezone",
         deserialize_with = "deserialize_timezone"
     )]
     pub timezone: Option<Tz>,
+
+    /// Several timezones, shown one after the other or cycled by clicking
+    #[serde(default, deserialize_with = "deserialize_timezones")]
+    pub timezones: Vec<Tz>,
+
+    /// What goes between the times of several timezones
+    #[serde(default = "TimeConfig::default_separator")]
+    pub separator: String,
+
+    /// Whether to show one timezone at a time, the next one on click
+    #[serde(default)]
+    pub cycle: bool,
 }
 
 impl TimeConfig {
@@ -58,12 +75,12 @@ impl TimeConfig {
         Duration::from_secs(5)
     }
 
-    fn default_on_click() -> Option<String> {
+    fn default_timezone() -> Option<Tz> {
         None
     }
 
-    fn default_timezone() -> Option<Tz> {
-        None
+    fn default_separator() -> String {
+        " | ".to_owned()
     }
 }
 
@@ -75,41 +92,138 @@ impl ConfigBlock for Time {
         config: Config,
         _tx_update_request: Sender<Task>,
     ) -> Result<Self> {
+        let timezones = match (block_config.timezone, block_config.timezones.is_empty()) {
+            (timezone, true) => vec![timezone],
+            (None, false) => block_config.timezones.into_iter().map(Some).collect(),
+            (Some(_), false) => {
+                return Err(BlockError(
+                    "time".to_owned(),
+                    "timezone and timezones cannot be used together".to_owned(),
+                ))
+            }
+        };
+        let formats = match (block_config.format, block_config.formats.is_empty()) {
+            (format, true) => vec![format.unwrap_or_else(TimeConfig::default_format)],
+            (None, false) => block_config.formats,
+            (Some(_), false) => {
+                return Err(BlockError(
+                    "time".to_owned(),
+                    "format and formats cannot be used together".to_owned(),
+                ))
+            }
+        };
         let i = Uuid::new_v4().to_simple().to_string();
         Ok(Time {
             id: i.clone(),
-            format: block_config.format,
+            formats,
             time: ButtonWidget::new(config, i.as_str())
                 .with_text("")
                 .with_icon("time"),
             update_interval: block_config.interval,
-            on_click: block_config.on_click,
-            timezone: block_config.timezone,
+            timezones,
+            separator: block_config.separator,
+            cycle: block_config.cycle,
+            current: 0,
         })
     }
 }
 
+/// The next Unix time after `now` that is a multiple of `interval` seconds in
+/// a timezone `offset` seconds ahead of UTC, so that e.g. a minute long
+/// interval updates as the minute changes.
+fn next_tick(now: i64, offset: i64, interval: i64) -> i64 {
+    let local = now + offset;
+    local - local.rem_euclid(interval) + interval - offset
+}
+
+/// `now` in `timezone`, or in the local timezone, in `format` with
+/// `{timezone}` replaced by the name of the timezone.
+fn format_time(now: DateTime<Utc>, timezone: Option<Tz>, format: &str) -> (String, FixedOffset) {
+    match timezone {
+        Some(tz) => {
+            let time = now.with_timezone(&tz);
+            (
+                time.format(format)
+                    .to_string()
+                    .replace("{timezone}", tz.name()),
+                time.offset().fix(),
+            )
+        }
+        None => {
+            let time = now.with_timezone(&Local);
+            (
+                time.format(format)
+                    .to_string()
+                    .replace("{timezone}", "Local"),
+                time.offset().fix(),
+            )
+        }
+    }
+}
+
+impl Time {
+    /// The timezones shown right now.
+    fn shown(&self) -> &[Option<Tz>] {
+        if self.cycle {
+            &self.timezones[self.current..=self.current]
+        } else {
+            &self.timezones
+        }
+    }
+
+    fn next_timezone(&mut self) {
+        self.current = (self.current + 1) % self.timezones.len();
+    }
+
+    /// Shows the time at `now`, and returns the offsets from UTC of the
+    /// timezones shown, in seconds.
+    fn show(&mut self, now: DateTime<Utc>) -> Vec<i64> {
+        let (texts, offsets): (Vec<_>, Vec<_>) = self
+            .shown()
+            .iter()
+            .map(|&timezone| {
+                let (text, offset) = format_time(now, timezone, &self.formats[0]);
+                (text, i64::from(offset.local_minus_utc()))
+            })
+            .unzip();
+        self.time.set_text(texts.join(&self.separator));
+        offsets
+    }
+}
+
 impl Block for Time {
     fn update(&mut self) -> Result<Option<Update>> {
-        let time = match self.timezone {
-            Some(tz) => Utc::now().with_timezone(&tz).format(&self.format),
-            None => Local::now().format(&self.format),
-        };
-        self.time.set_text(format!("{}", time));
-        Ok(Some(self.update_interval.into()))
-    }
-
-    fn click(&mut self, e: &I3BarEvent) -> Result<()> {
-        if let Some(ref name) = e.name {
-            if name.as_str() == self.id {
-                if let MouseButton::Left = e.button {
-                    if let Some(ref cmd) = self.on_click {
-                        spawn_child_async("sh", &["-c", cmd])
-                            .block_error("time", "could not spawn child")?;
-                    }
-                }
-            }
+        let now = Utc::now();
+        let offsets = self.show(now);
+
+        // Intervals of whole seconds follow the wall clock, of whichever
+        // timezone ticks first.
+        let interval = self.update_interval;
+        if interval.subsec_nanos() != 0 || interval.as_secs() == 0 {
+            return Ok(Some(interval.into()));
+        }
+        let next = offsets
+            .into_iter()
+            .map(|offset| next_tick(now.timestamp(), offset, interval.as_secs() as i64))
+            .min()
+            .unwrap_or_else(|| now.timestamp() + interval.as_secs() as i64);
+        Ok(Some(Update::AtWallClock(Utc.timestamp(next, 0))))
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if !event.matches_name(&self.id) {
+            return Ok(());
         }
+        // Left clicks cycle the formats, if there are several, otherwise the
+        // timezones, which are cycled by right clicks then.
+        let several_formats = self.formats.len() > 1;
+        match event.button {
+            MouseButton::Left if several_formats => self.formats.rotate_left(1),
+            MouseButton::Left if self.cycle => self.next_timezone(),
+            MouseButton::Right if self.cycle && several_formats => self.next_timezone(),
+            _ => return Ok(()),
+        }
+        self.show(Utc::now());
         Ok(())
     }
 
@@ -121,3 +235,110 @@ impl Block for Time {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_tick() {
+        assert_eq!(next_tick(1_600_000_000, 0, 60), 1_600_000_020);
+        assert_eq!(next_tick(1_600_000_020, 0, 60), 1_600_000_080);
+        assert_eq!(next_tick(1_600_000_000, 0, 5), 1_600_000_005);
+        // Hours follow the local time, half an hour off UTC in India.
+        assert_eq!(next_tick(1_600_000_000, 19800, 3600), 1_600_000_200);
+        assert_eq!(next_tick(1_600_000_000, -3600, 3600), 1_600_002_000);
+    }
+
+    fn time(options: &str) -> Result<Time> {
+        Time::new(
+            toml::from_str(options).unwrap(),
+            Config::default(),
+            crossbeam_channel::unbounded().0,
+        )
+    }
+
+    #[test]
+    fn test_timezones() {
+        let now = Utc.ymd(2020, 9, 13).and_hms(12, 26, 40);
+        let mut block = time(
+            r#"format = "{timezone} %H:%M"
+               timezones = ["Europe/Paris", "America/New_York", "Asia/Kolkata"]"#,
+        )
+        .unwrap();
+        assert_eq!(block.show(now), vec![7200, -14400, 19800]);
+        assert_eq!(
+            block.time.widget_state().text,
+            "Europe/Paris 14:26 | America/New_York 08:26 | Asia/Kolkata 17:56"
+        );
+
+        assert!(time(
+            r#"timezone = "Asia/Tokyo"
+                        timezones = ["Europe/Paris"]"#
+        )
+        .is_err());
+        assert!(toml::from_str::<TimeConfig>(r#"timezones = ["Mars/Olympus_Mons"]"#).is_err());
+    }
+
+    fn click(block: &mut Time, button: MouseButton) {
+        block
+            .click(&I3BarEvent {
+                name: Some(block.id.clone()),
+                instance: None,
+                x: 0,
+                y: 0,
+                button,
+                modifiers: Vec::new(),
+                double_click: false,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_formats() {
+        let now = Utc.ymd(2020, 9, 13).and_hms(12, 26, 40);
+        let mut block = time(
+            r#"formats = ["%H:%M", "%a %Y-%m-%d %H:%M:%S", "%s"]
+               timezones = ["UTC", "Asia/Tokyo"]
+               cycle = true"#,
+        )
+        .unwrap();
+        block.show(now);
+        assert_eq!(block.time.widget_state().text, "12:26");
+        click(&mut block, MouseButton::Left);
+        block.show(now);
+        assert_eq!(block.time.widget_state().text, "Sun 2020-09-13 12:26:40");
+        click(&mut block, MouseButton::Right);
+        block.show(now);
+        assert_eq!(block.time.widget_state().text, "Sun 2020-09-13 21:26:40");
+        click(&mut block, MouseButton::Left);
+        click(&mut block, MouseButton::Left);
+        block.show(now);
+        assert_eq!(block.time.widget_state().text, "21:26");
+
+        assert!(time(
+            r#"format = "%R"
+                        formats = ["%R", "%s"]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let now = Utc.ymd(2020, 9, 13).and_hms(12, 26, 40);
+        let mut block = time(
+            r#"format = "%H:%M %Z"
+               timezones = ["Europe/Paris", "Asia/Tokyo"]
+               cycle = true"#,
+        )
+        .unwrap();
+        block.show(now);
+        assert_eq!(block.time.widget_state().text, "14:26 CEST");
+        for expected in &["Asia/Tokyo", "Europe/Paris"] {
+            click(&mut block, MouseButton::Left);
+            assert_eq!(block.shown(), &[Some(expected.parse().unwrap())]);
+        }
+        block.show(now);
+        assert_eq!(block.time.widget_state().text, "14:26 CEST");
+    }
+}
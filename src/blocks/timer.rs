@@ -0,0 +1,372 @@
+//! A stopwatch, or a countdown if it has a target.
+//!
+//! Left clicking starts and pauses the timer, right clicking resets it, and
+//! scrolling changes the countdown target by `step`. Scrolling the target down
+//! to nothing makes it a stopwatch again. While the timer runs, the block is
+//! updated right when the shown second changes, and not at all otherwise.
+
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::{Block, ClickSafety, ConfigBlock, Update};
+use crate::config::{Config, LogicalDirection, Scrolling};
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+use crate::subprocess::spawn_child_async;
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+const SECOND: Duration = Duration::from_secs(1);
+
+pub struct Timer {
+    id: String,
+    output: ButtonWidget,
+    /// How long the timer ran before it was last started.
+    elapsed: Duration,
+    running_since: Option<Instant>,
+    /// The countdown target, or zero for a stopwatch.
+    target: Duration,
+    finished: bool,
+    step: Duration,
+    command: Option<String>,
+    notify: bool,
+    message: String,
+    format: FormatTemplate,
+    scrolling: Scrolling,
+    tx_update_request: Sender<Task>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TimerConfig {
+    /// The countdown target in seconds, or zero for a stopwatch
+    #[serde(
+        default = "TimerConfig::default_countdown",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub countdown: Duration,
+
+    /// How much scrolling changes the countdown target, in seconds
+    #[serde(
+        default = "TimerConfig::default_step",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub step: Duration,
+
+    /// A shell command to run when the countdown is over
+    #[serde(default)]
+    pub command: Option<String>,
+
+    /// Whether to send a desktop notification when the countdown is over
+    #[serde(default)]
+    pub notify: bool,
+
+    #[serde(default = "TimerConfig::default_message")]
+    pub message: String,
+
+    /// Format override, with the placeholders `{time}`, `{hours}`, `{minutes}`
+    /// and `{seconds}`
+    #[serde(default = "TimerConfig::default_format")]
+    pub format: String,
+}
+
+impl TimerConfig {
+    fn default_countdown() -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn default_step() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_message() -> String {
+        "Time is up!".to_owned()
+    }
+
+    fn default_format() -> String {
+        "{time}".to_owned()
+    }
+}
+
+impl ConfigBlock for Timer {
+    type Config = TimerConfig;
+
+    fn new(
+        block_config: Self::Config,
+        config: Config,
+        tx_update_request: Sender<Task>,
+    ) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        let scrolling = config.scrolling;
+        let mut timer = Timer {
+            output: ButtonWidget::new(config, &id).with_icon("time"),
+            id,
+            elapsed: Duration::from_secs(0),
+            running_since: None,
+            target: block_config.countdown,
+            finished: false,
+            step: block_config.step,
+            command: block_config.command,
+            notify: block_config.notify,
+            message: block_config.message,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("timer", "Invalid format specified for timer")?,
+            scrolling,
+            tx_update_request,
+        };
+        timer.set_text()?;
+        Ok(timer)
+    }
+}
+
+/// Hours, minutes and seconds, e.g. `1:02:03` or `2:03`.
+fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+impl Timer {
+    fn elapsed(&self) -> Duration {
+        self.elapsed
+            + self
+                .running_since
+                .map_or(Duration::from_secs(0), |s| s.elapsed())
+    }
+
+    fn is_countdown(&self) -> bool {
+        self.target > Duration::from_secs(0)
+    }
+
+    /// What the timer shows: the time left, rounded up, of a countdown, or the
+    /// whole seconds elapsed on a stopwatch, and how long until that changes.
+    fn shown(&self) -> (u64, Duration) {
+        let elapsed = self.elapsed();
+        if self.is_countdown() {
+            let left = self.target.saturating_sub(elapsed);
+            let fraction = Duration::from_nanos(u64::from(left.subsec_nanos()));
+            let secs = left.as_secs() + u64::from(fraction > Duration::from_secs(0));
+            (
+                secs,
+                if fraction.as_nanos() == 0 {
+                    SECOND
+                } else {
+                    fraction
+                },
+            )
+        } else {
+            let fraction = Duration::from_nanos(u64::from(elapsed.subsec_nanos()));
+            (elapsed.as_secs(), SECOND - fraction)
+        }
+    }
+
+    fn set_text(&mut self) -> Result<()> {
+        let (secs, _) = self.shown();
+        let values = map!("{time}" => Value::from(format_duration(secs)),
+                          "{hours}" => Value::integer(secs / 3600),
+                          "{minutes}" => Value::integer(secs / 60 % 60),
+                          "{seconds}" => Value::integer(secs % 60));
+        self.output
+            .set_text(self.format.render_static_str(&values)?);
+        self.output.set_state(if self.finished {
+            State::Critical
+        } else {
+            State::Idle
+        });
+        Ok(())
+    }
+
+    fn pause(&mut self) {
+        self.elapsed = self.elapsed();
+        self.running_since = None;
+    }
+
+    fn reset(&mut self) {
+        self.elapsed = Duration::from_secs(0);
+        self.running_since = None;
+        self.finished = false;
+    }
+
+    /// Ends the countdown, with the command and notification for it.
+    fn finish(&mut self) -> Result<()> {
+        self.pause();
+        self.finished = true;
+        if let Some(ref command) = self.command {
+            spawn_child_async("sh", &["-c", command])
+                .block_error("timer", "could not spawn command")?;
+        }
+        if self.notify {
+            spawn_child_async("notify-send", &["Timer", &self.message])
+                .block_error("timer", "could not spawn notify-send")?;
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, direction: LogicalDirection) {
+        self.target = match direction {
+            LogicalDirection::Up => self.target + self.step,
+            LogicalDirection::Down => self.target.saturating_sub(self.step),
+        };
+        if self.finished && self.target > self.elapsed {
+            self.finished = false;
+        }
+    }
+}
+
+impl Block for Timer {
+    fn update(&mut self) -> Result<Option<Update>> {
+        if self.running_since.is_some() && self.is_countdown() && self.elapsed() >= self.target {
+            self.finish()?;
+        }
+        self.set_text()?;
+        Ok(self.running_since.map(|_| self.shown().1.into()))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.output]
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if !event.matches_name(&self.id) {
+            return Ok(());
+        }
+        match event.button {
+            MouseButton::Left if self.running_since.is_some() => self.pause(),
+            MouseButton::Left => {
+                if self.finished {
+                    self.reset();
+                }
+                self.running_since = Some(Instant::now());
+            }
+            MouseButton::Right => self.reset(),
+            button => match self.scrolling.to_logical_direction(button) {
+                Some(direction) => self.step(direction),
+                None => return Ok(()),
+            },
+        }
+        self.set_text()?;
+        // Starts the updates while running.
+        self.tx_update_request
+            .send(Task {
+                id: self.id.clone(),
+                update_time: Instant::now(),
+            })
+            .internal_error("timer", "failed to request update")
+    }
+
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::Receiver;
+
+    use super::*;
+
+    fn timer(options: &str) -> (Timer, Receiver<Task>) {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let block = Timer::new(toml::from_str(options).unwrap(), Config::default(), tx).unwrap();
+        (block, rx)
+    }
+
+    fn click(block: &mut Timer, button: MouseButton) {
+        block
+            .click(&I3BarEvent {
+                name: Some(block.id.clone()),
+                instance: None,
+                x: 0,
+                y: 0,
+                button,
+                modifiers: Vec::new(),
+                double_click: false,
+            })
+            .unwrap();
+    }
+
+    fn text(block: &Timer) -> String {
+        block.output.widget_state().text
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0:00");
+        assert_eq!(format_duration(125), "2:05");
+        assert_eq!(format_duration(3723), "1:02:03");
+    }
+
+    #[test]
+    fn test_stopwatch() {
+        let (mut block, _updates) = timer("");
+        assert!(block.update().unwrap().is_none());
+        assert_eq!(text(&block), "0:00");
+
+        click(&mut block, MouseButton::Left);
+        block.running_since = Some(Instant::now() - Duration::from_millis(61_300));
+        match block.update().unwrap() {
+            Some(Update::Every(next)) => assert!(next <= Duration::from_millis(700)),
+            _ => panic!("expected an update within a second"),
+        }
+        assert_eq!(text(&block), "1:01");
+
+        click(&mut block, MouseButton::Left);
+        assert!(block.update().unwrap().is_none());
+        assert_eq!(text(&block), "1:01");
+        click(&mut block, MouseButton::Right);
+        assert_eq!(text(&block), "0:00");
+    }
+
+    #[test]
+    fn test_countdown() {
+        let (mut block, _updates) = timer("countdown = 90");
+        assert_eq!(text(&block), "1:30");
+
+        click(&mut block, MouseButton::Left);
+        block.running_since = Some(Instant::now() - Duration::from_millis(29_500));
+        block.update().unwrap();
+        assert_eq!(text(&block), "1:01");
+        assert_eq!(block.output.widget_state().state, State::Idle);
+
+        block.running_since = Some(Instant::now() - Duration::from_secs(90));
+        assert!(block.update().unwrap().is_none());
+        assert_eq!(text(&block), "0:00");
+        assert_eq!(block.output.widget_state().state, State::Critical);
+
+        // Clicking a finished countdown starts it over.
+        click(&mut block, MouseButton::Left);
+        assert_eq!(text(&block), "1:30");
+        assert_eq!(block.output.widget_state().state, State::Idle);
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let (mut block, _updates) = timer("step = 30");
+        click(&mut block, MouseButton::WheelDown);
+        assert_eq!(text(&block), "0:00");
+        click(&mut block, MouseButton::WheelUp);
+        click(&mut block, MouseButton::WheelUp);
+        assert_eq!(text(&block), "1:00");
+        click(&mut block, MouseButton::WheelDown);
+        assert_eq!(text(&block), "0:30");
+    }
+
+    #[test]
+    fn test_format() {
+        let (block, _updates) = timer("countdown = 3723\nformat = \"{hours}h{minutes:02}\"");
+        assert_eq!(text(&block), "1h02");
+    }
+}
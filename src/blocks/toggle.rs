@@ -3,10 +3,10 @@ use crossbeam_channel::Sender;
 use serde_derive::Deserialize;
 use std::env;
 use std::process::Command;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::Config;
 use crate::de::deserialize_opt_duration;
 use crate::errors::*;
@@ -26,6 +26,7 @@ pub struct Toggle {
     update_interval: Option<Duration>,
     toggled: bool,
     id: String,
+    tx_update_request: Sender<Task>,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -72,7 +73,7 @@ impl ConfigBlock for Toggle {
     fn new(
         block_config: Self::Config,
         config: Config,
-        _tx_update_request: Sender<Task>,
+        tx_update_request: Sender<Task>,
     ) -> Result<Self> {
         let id = Uuid::new_v4().to_simple().to_string();
         Ok(Toggle {
@@ -85,6 +86,7 @@ impl ConfigBlock for Toggle {
             id,
             toggled: false,
             update_interval: block_config.interval,
+            tx_update_request,
         })
     }
 }
@@ -138,7 +140,12 @@ impl Block for Toggle {
                         self.icon_on.as_str()
                     } else {
                         self.icon_off.as_str()
-                    })
+                    });
+                    // The command may not have done what it should, check.
+                    self.tx_update_request.send(Task {
+                        id: self.id.clone(),
+                        update_time: Instant::now(),
+                    })?;
                 } else {
                     self.text.set_state(State::Critical);
                 };
@@ -148,6 +155,10 @@ impl Block for Toggle {
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
@@ -8,17 +8,21 @@ use uuid::Uuid;
 use crate::blocks::Update;
 use crate::blocks::{Block, ConfigBlock};
 use crate::config::Config;
-use crate::de::deserialize_duration;
+use crate::de::deserialize_opt_duration;
 use crate::errors::*;
 use crate::scheduler::Task;
-use crate::util::read_file;
+use crate::util::{read_file, FormatTemplate};
 use crate::widget::I3BarWidget;
 use crate::widgets::text::TextWidget;
 
 pub struct Uptime {
     text: TextWidget,
     id: String,
-    update_interval: Duration,
+    update_interval: Option<Duration>,
+    format: FormatTemplate,
+    /// The smallest unit in `format`, in seconds, or `None` for the units of
+    /// `{uptime}`.
+    format_unit: Option<u64>,
 
     //useful, but optional
     #[allow(dead_code)]
@@ -30,20 +34,30 @@ pub struct Uptime {
 #[derive(Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct UptimeConfig {
-    /// Update interval in seconds
-    #[serde(
-        default = "UptimeConfig::default_interval",
-        deserialize_with = "deserialize_duration"
-    )]
-    pub interval: Duration,
+    /// Update interval in seconds. By default the block updates when what it
+    /// shows changes.
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    pub interval: Option<Duration>,
+
+    /// Format override
+    #[serde(default = "UptimeConfig::default_format")]
+    pub format: String,
 }
 
 impl UptimeConfig {
-    fn default_interval() -> Duration {
-        Duration::from_secs(60)
+    fn default_format() -> String {
+        "{uptime}".to_owned()
     }
 }
 
+/// The placeholders for parts of the uptime, and their units in seconds.
+const UNITS: &[(&str, u64)] = &[
+    ("{days}", 86_400),
+    ("{hours}", 3600),
+    ("{minutes}", 60),
+    ("{seconds}", 1),
+];
+
 impl ConfigBlock for Uptime {
     type Config = UptimeConfig;
 
@@ -52,9 +66,22 @@ impl ConfigBlock for Uptime {
         config: Config,
         tx_update_request: Sender<Task>,
     ) -> Result<Self> {
+        let format_unit = if block_config.format.contains("{uptime}") {
+            None
+        } else {
+            UNITS
+                .iter()
+                .filter(|(placeholder, _)| block_config.format.contains(placeholder))
+                .map(|&(_, unit)| unit)
+                .min()
+                .or(Some(86_400))
+        };
         Ok(Uptime {
             id: Uuid::new_v4().to_simple().to_string(),
             update_interval: block_config.interval,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("uptime", "Invalid format specified")?,
+            format_unit,
             text: TextWidget::new(config.clone()).with_icon("uptime"),
             tx_update_request,
             config,
@@ -62,6 +89,52 @@ impl ConfigBlock for Uptime {
     }
 }
 
+/// The two largest units of `total_seconds`, e.g. `3d 4h` or `14m 5s`, and the
+/// smaller of the two in seconds.
+fn collapse(total_seconds: u64) -> (String, u64) {
+    // split up seconds into more human readable portions
+    let weeks = total_seconds / 604_800;
+    let rem_weeks = total_seconds % 604_800;
+    let days = rem_weeks / 86_400;
+    let rem_days = rem_weeks % 86_400;
+    let hours = rem_days / 3600;
+    let rem_hours = rem_days % 3600;
+    let minutes = rem_hours / 60;
+    let seconds = rem_hours % 60;
+
+    if hours == 0 && days == 0 && weeks == 0 {
+        (format!("{}m {}s", minutes, seconds), 1)
+    } else if days == 0 && weeks == 0 {
+        (format!("{}h {}m", hours, minutes), 60)
+    } else if weeks == 0 {
+        (format!("{}d {}h", days, hours), 3600)
+    } else if days == 0 {
+        (format!("{}w {}h", weeks, hours), 3600)
+    } else {
+        (format!("{}w {}d", weeks, days), 86_400)
+    }
+}
+
+impl Uptime {
+    /// Shows an uptime of `uptime` seconds, and returns how long until that
+    /// changes.
+    fn show(&mut self, uptime: f64) -> Result<Duration> {
+        let total_seconds = uptime as u64;
+        let (collapsed, unit) = collapse(total_seconds);
+        let values = map!(
+            "{uptime}" => collapsed,
+            "{days}" => format!("{}", total_seconds / 86_400),
+            "{hours}" => format!("{}", total_seconds / 3600 % 24),
+            "{minutes}" => format!("{}", total_seconds / 60 % 60),
+            "{seconds}" => format!("{}", total_seconds % 60)
+        );
+        self.text.set_text(self.format.render_static_str(&values)?);
+
+        let unit = self.format_unit.unwrap_or(unit) as f64;
+        Ok(Duration::from_secs_f64(unit - uptime % unit))
+    }
+}
+
 impl Block for Uptime {
     fn update(&mut self) -> Result<Option<Update>> {
         let uptime_raw = match read_file("uptime", Path::new("/proc/uptime")) {
@@ -83,8 +156,8 @@ impl Block for Uptime {
             }
         };
 
-        let total_seconds = match uptime.parse::<f64>() {
-            Ok(uptime) => uptime as u32,
+        let uptime = match uptime.parse::<f64>() {
+            Ok(uptime) => uptime,
             Err(e) => {
                 return Err(BlockError(
                     "Uptime".to_owned(),
@@ -93,33 +166,8 @@ impl Block for Uptime {
             }
         };
 
-        // split up seconds into more human readable portions
-        let weeks = (total_seconds / 604_800) as u32;
-        let rem_weeks = (total_seconds % 604_800) as u32;
-        let days = (rem_weeks / 86_400) as u32;
-        let rem_days = (rem_weeks % 86_400) as u32;
-        let hours = (rem_days / 3600) as u32;
-        let rem_hours = (rem_days % 3600) as u32;
-        let minutes = (rem_hours / 60) as u32;
-        let rem_minutes = (rem_hours % 60) as u32;
-        let seconds = rem_minutes as u32;
-
-        // Display the two largest units.
-        let text = if hours == 0 && days == 0 && weeks == 0 {
-            format!("{}m {}s", minutes, seconds)
-        } else if hours > 0 && days == 0 && weeks == 0 {
-            format!("{}h {}m", hours, minutes)
-        } else if days > 0 && weeks == 0 {
-            format!("{}d {}h", days, hours)
-        } else if days == 0 && weeks > 0 {
-            format!("{}w {}h", weeks, hours)
-        } else if weeks > 0 {
-            format!("{}w {}d", weeks, days)
-        } else {
-            unreachable!()
-        };
-        self.text.set_text(text);
-        Ok(Some(self.update_interval.into()))
+        let next = self.show(uptime)?;
+        Ok(Some(self.update_interval.unwrap_or(next).into()))
     }
 
     fn view(&self) -> Vec<&dyn I3BarWidget> {
@@ -130,3 +178,41 @@ impl Block for Uptime {
         &self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uptime(options: &str) -> Uptime {
+        Uptime::new(
+            toml::from_str(options).unwrap(),
+            Config::default(),
+            crossbeam_channel::unbounded().0,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_collapse() {
+        assert_eq!(collapse(845), ("14m 5s".to_owned(), 1));
+        assert_eq!(collapse(7260), ("2h 1m".to_owned(), 60));
+        assert_eq!(collapse(273_600), ("3d 4h".to_owned(), 3600));
+        assert_eq!(collapse(608_400), ("1w 1h".to_owned(), 3600));
+        assert_eq!(collapse(1_296_000), ("2w 1d".to_owned(), 86_400));
+    }
+
+    #[test]
+    fn test_adaptive_interval() {
+        let mut block = uptime("");
+        assert_eq!(block.show(845.25).unwrap(), Duration::from_millis(750));
+        assert_eq!(block.text.widget_state().text, "14m 5s");
+        assert_eq!(block.show(7290.0).unwrap(), Duration::from_secs(30));
+
+        let mut block = uptime("format = \"{days}d {hours}h {minutes}m\"");
+        assert_eq!(block.show(273_630.0).unwrap(), Duration::from_secs(30));
+        assert_eq!(block.text.widget_state().text, "3d 4h 0m");
+
+        let mut block = uptime("format = \"up for {days} days\"");
+        assert_eq!(block.show(273_600.0).unwrap(), Duration::from_secs(72_000));
+    }
+}
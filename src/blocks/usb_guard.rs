@@ -0,0 +1,504 @@
+//! A block counting the external USB devices, which alerts when a device that
+//! is not on the allowlist is plugged in.
+//!
+//! udev creates a device node under `/dev/bus/usb` for every USB device, so
+//! watching those directories tells when devices come and go. The devices
+//! themselves are read from sysfs.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use inotify::{Inotify, WatchMask};
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ConfigBlock};
+use crate::config::Config;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+use crate::stop::{self, Stop};
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+const SYSFS_DEVICES: &str = "/sys/bus/usb/devices";
+const DEV_BUS_USB: &str = "/dev/bus/usb";
+
+/// How often the block switches between critical and idle while alerting.
+const FLASH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+struct UsbDevice {
+    vendor: u16,
+    product: u16,
+    name: String,
+}
+
+impl UsbDevice {
+    fn id(&self) -> String {
+        format!("{:04x}:{:04x}", self.vendor, self.product)
+    }
+}
+
+/// Parses a `vendor:product` pair of hexadecimal ids, e.g. `046d:c52b`.
+fn parse_id(id: &str) -> Option<(u16, u16)> {
+    let mut parts = id.trim().splitn(2, ':');
+    let vendor = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let product = u16::from_str_radix(parts.next()?, 16).ok()?;
+    Some((vendor, product))
+}
+
+/// Reads the external USB devices from `root`, keyed by bus and device
+/// number. The kernel never reuses a device number before it wraps around,
+/// so a device plugged in again gets a new key.
+fn read_devices(root: &Path) -> BTreeMap<(u32, u32), UsbDevice> {
+    let mut devices = BTreeMap::new();
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return devices,
+    };
+    for entry in entries.flatten() {
+        let sysname = entry.file_name().to_string_lossy().to_string();
+        // Root hubs are the controllers themselves, and names with a colon
+        // are interfaces of a device.
+        if sysname.starts_with("usb") || sysname.contains(':') {
+            continue;
+        }
+        let path = entry.path();
+        let attribute = |name: &str| {
+            fs::read_to_string(path.join(name))
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        // Internal devices such as webcams and Bluetooth adapters.
+        if attribute("removable").as_deref() == Some("fixed") {
+            continue;
+        }
+        let key = match (attribute("busnum"), attribute("devnum")) {
+            (Some(bus), Some(dev)) => match (bus.parse(), dev.parse()) {
+                (Ok(bus), Ok(dev)) => (bus, dev),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let id = format!(
+            "{}:{}",
+            attribute("idVendor").unwrap_or_default(),
+            attribute("idProduct").unwrap_or_default()
+        );
+        let (vendor, product) = match parse_id(&id) {
+            Some(id) => id,
+            None => continue,
+        };
+        let name = attribute("product")
+            .or_else(|| attribute("manufacturer"))
+            .unwrap_or_else(|| id.clone());
+        devices.insert(
+            key,
+            UsbDevice {
+                vendor,
+                product,
+                name,
+            },
+        );
+    }
+    devices
+}
+
+/// The connected devices, and the devices not on the allowlist that were
+/// plugged in since the last acknowledgement.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Guard {
+    allowlist: Vec<(u16, u16)>,
+    devices: BTreeMap<(u32, u32), UsbDevice>,
+    alerts: Vec<UsbDevice>,
+}
+
+impl Guard {
+    fn new(allowlist: Vec<(u16, u16)>) -> Self {
+        Guard {
+            allowlist,
+            ..Guard::default()
+        }
+    }
+
+    fn is_allowed(&self, device: &UsbDevice) -> bool {
+        self.allowlist.contains(&(device.vendor, device.product))
+    }
+
+    /// Takes the devices connected at startup as they are.
+    fn seed(&mut self, devices: BTreeMap<(u32, u32), UsbDevice>) {
+        self.devices = devices;
+    }
+
+    /// Replaces the connected devices, alerting on any new one that is not
+    /// allowed. Alerts stay after the device is unplugged again.
+    fn rescan(&mut self, devices: BTreeMap<(u32, u32), UsbDevice>) {
+        for (key, device) in &devices {
+            if !self.devices.contains_key(key) && !self.is_allowed(device) {
+                self.alerts.push(device.clone());
+            }
+        }
+        self.devices = devices;
+    }
+
+    fn acknowledge(&mut self) {
+        self.alerts.clear();
+    }
+}
+
+pub struct UsbGuard {
+    id: String,
+    output: ButtonWidget,
+    format: FormatTemplate,
+    alert_format: FormatTemplate,
+    guard: Arc<Mutex<Guard>>,
+    /// Whether the block is critical in this phase of flashing.
+    flash: bool,
+    /// Stops watching for devices when the block is dropped.
+    _watcher: Stop,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UsbGuardConfig {
+    /// Devices that do not trigger an alert, as `vendor:product` ids
+    #[serde(default = "UsbGuardConfig::default_allowlist")]
+    pub allowlist: Vec<String>,
+
+    /// Format override
+    #[serde(default = "UsbGuardConfig::default_format")]
+    pub format: String,
+
+    /// Format used while alerting
+    #[serde(default = "UsbGuardConfig::default_alert_format")]
+    pub alert_format: String,
+}
+
+impl UsbGuardConfig {
+    fn default_allowlist() -> Vec<String> {
+        Vec::new()
+    }
+
+    fn default_format() -> String {
+        "{count}".to_owned()
+    }
+
+    fn default_alert_format() -> String {
+        "{name}".to_owned()
+    }
+}
+
+/// Watches `/dev/bus/usb` and each bus directory in it.
+fn watch() -> Result<Inotify> {
+    let mut notify = Inotify::init().block_error("usb_guard", "failed to start inotify")?;
+    notify
+        .add_watch(DEV_BUS_USB, WatchMask::CREATE)
+        .block_error("usb_guard", &format!("failed to watch {}", DEV_BUS_USB))?;
+    add_bus_watches(&mut notify);
+    Ok(notify)
+}
+
+/// Watching a directory twice is harmless, so this is simply done again
+/// whenever a bus may have appeared.
+fn add_bus_watches(notify: &mut Inotify) {
+    if let Ok(entries) = fs::read_dir(DEV_BUS_USB) {
+        for entry in entries.flatten() {
+            notify
+                .add_watch(entry.path(), WatchMask::CREATE | WatchMask::DELETE)
+                .ok();
+        }
+    }
+}
+
+impl ConfigBlock for UsbGuard {
+    type Config = UsbGuardConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        let allowlist = block_config
+            .allowlist
+            .iter()
+            .map(|id| {
+                parse_id(id).ok_or_else(|| {
+                    BlockError(
+                        "usb_guard".to_owned(),
+                        format!("invalid device id '{}', expected vendor:product", id),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Watch before reading the devices, so that none is missed.
+        let mut notify = watch()?;
+        let mut guard = Guard::new(allowlist);
+        guard.seed(read_devices(Path::new(SYSFS_DEVICES)));
+        let guard = Arc::new(Mutex::new(guard));
+
+        let guard_copy = guard.clone();
+        let id_copy = id.clone();
+        let (stop, stopped) = stop::pair()?;
+        thread::Builder::new()
+            .name("usb_guard".into())
+            .spawn(move || {
+                let mut buffer = [0; 1024];
+                while stopped.inotify_events(&mut notify, &mut buffer).is_some() {
+                    add_bus_watches(&mut notify);
+                    let devices = read_devices(Path::new(SYSFS_DEVICES));
+                    let changed = match guard_copy.lock() {
+                        Ok(mut guard) => {
+                            let before = guard.clone();
+                            guard.rescan(devices);
+                            *guard != before
+                        }
+                        Err(_) => break,
+                    };
+                    if changed {
+                        let task = Task {
+                            id: id_copy.clone(),
+                            update_time: Instant::now(),
+                        };
+                        if send.send(task).is_err() {
+                            break;
+                        }
+                    }
+                }
+            })
+            .block_error("usb_guard", "failed to spawn thread")?;
+
+        Ok(UsbGuard {
+            output: ButtonWidget::new(config, &id),
+            id,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("usb_guard", "Invalid format specified")?,
+            alert_format: FormatTemplate::from_string(&block_config.alert_format)
+                .block_error("usb_guard", "Invalid alert_format specified")?,
+            guard,
+            flash: false,
+            _watcher: stop,
+        })
+    }
+}
+
+impl Block for UsbGuard {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let guard = self
+            .guard
+            .lock()
+            .block_error("usb_guard", "failed to acquire lock")?
+            .clone();
+
+        match guard.alerts.last() {
+            Some(device) => {
+                let values = map!("{name}" => Value::from(device.name.clone()),
+                                  "{id}" => Value::from(device.id()),
+                                  "{alerts}" => Value::integer(guard.alerts.len()),
+                                  "{count}" => Value::integer(guard.devices.len()));
+                self.output
+                    .set_text(self.alert_format.render_static_str(&values)?);
+                self.flash = !self.flash;
+                self.output.set_state(if self.flash {
+                    State::Critical
+                } else {
+                    State::Idle
+                });
+                Ok(Some(FLASH_INTERVAL.into()))
+            }
+            None => {
+                let values = map!("{count}" => Value::integer(guard.devices.len()));
+                self.output
+                    .set_text(self.format.render_static_str(&values)?);
+                self.flash = false;
+                self.output.set_state(State::Idle);
+                Ok(None)
+            }
+        }
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.output]
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) && event.button == MouseButton::Left {
+            self.guard
+                .lock()
+                .block_error("usb_guard", "failed to acquire lock")?
+                .acknowledge();
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    fn device(vendor: u16, product: u16, name: &str) -> UsbDevice {
+        UsbDevice {
+            vendor,
+            product,
+            name: name.to_owned(),
+        }
+    }
+
+    fn devices(list: &[((u32, u32), &UsbDevice)]) -> BTreeMap<(u32, u32), UsbDevice> {
+        list.iter()
+            .map(|(key, device)| (*key, (*device).clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id("046d:c52b"), Some((0x046d, 0xc52b)));
+        assert_eq!(parse_id("1D6B:0002"), Some((0x1d6b, 0x0002)));
+        assert_eq!(parse_id("046d"), None);
+        assert_eq!(parse_id("046d:"), None);
+        assert_eq!(parse_id("046d:c52b:1"), None);
+        assert_eq!(parse_id("mouse:keyboard"), None);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let guard = Guard::new(vec![(0x046d, 0xc52b)]);
+        assert!(guard.is_allowed(&device(0x046d, 0xc52b, "Receiver")));
+        // Only the exact pair is allowed, not the whole vendor.
+        assert!(!guard.is_allowed(&device(0x046d, 0xc077, "Mouse")));
+        assert!(!guard.is_allowed(&device(0x0781, 0x5581, "Ultra")));
+    }
+
+    #[test]
+    fn test_alerts() {
+        let receiver = device(0x046d, 0xc52b, "Receiver");
+        let stick = device(0x0781, 0x5581, "Ultra");
+        let mut guard = Guard::new(vec![(0x046d, 0xc52b)]);
+
+        // Devices present at startup are not alerted on.
+        guard.seed(devices(&[((1, 2), &stick)]));
+        assert!(guard.alerts.is_empty());
+
+        // Allowed devices never alert.
+        guard.rescan(devices(&[((1, 2), &stick), ((1, 3), &receiver)]));
+        assert!(guard.alerts.is_empty());
+
+        // Plugging in the stick again makes it a new device.
+        guard.rescan(devices(&[((1, 3), &receiver)]));
+        guard.rescan(devices(&[((1, 3), &receiver), ((1, 4), &stick)]));
+        assert_eq!(guard.alerts, vec![stick.clone()]);
+
+        // Unplugging it does not clear the alert.
+        guard.rescan(devices(&[((1, 3), &receiver)]));
+        assert_eq!(guard.alerts, vec![stick.clone()]);
+        assert_eq!(guard.devices.len(), 1);
+
+        guard.acknowledge();
+        assert!(guard.alerts.is_empty());
+        // Rescanning without changes does not alert again.
+        guard.rescan(devices(&[((1, 3), &receiver)]));
+        assert!(guard.alerts.is_empty());
+    }
+
+    #[test]
+    fn test_flash() {
+        let stick = device(0x0781, 0x5581, "Ultra");
+        let mut block = UsbGuard {
+            id: "usb_guard".to_owned(),
+            output: ButtonWidget::new(Config::default(), "usb_guard"),
+            format: FormatTemplate::from_string("{count}").unwrap(),
+            alert_format: FormatTemplate::from_string("{name} {id}").unwrap(),
+            guard: Arc::new(Mutex::new(Guard::new(vec![]))),
+            flash: false,
+            _watcher: stop::pair().unwrap().0,
+        };
+
+        assert_eq!(block.update().unwrap(), None);
+        assert!(!block.flash);
+
+        block
+            .guard
+            .lock()
+            .unwrap()
+            .rescan(devices(&[((1, 4), &stick)]));
+        assert!(block.update().unwrap().is_some());
+        assert!(block.flash);
+        block.update().unwrap();
+        assert!(!block.flash);
+        block.update().unwrap();
+        assert!(block.flash);
+
+        block.guard.lock().unwrap().acknowledge();
+        assert_eq!(block.update().unwrap(), None);
+        assert!(!block.flash);
+    }
+
+    #[test]
+    fn test_read_devices() {
+        let dir = TempDir::new().unwrap();
+        let add = |name: &str, attributes: &[(&str, &str)]| {
+            let path = dir.path().join(name);
+            fs::create_dir(&path).unwrap();
+            for (attribute, value) in attributes {
+                fs::write(path.join(attribute), format!("{}\n", value)).unwrap();
+            }
+        };
+        let usb = [("busnum", "1"), ("devnum", "1"), ("idVendor", "1d6b")];
+        add("usb1", &usb);
+        add("1-1:1.0", &[("bInterfaceClass", "03")]);
+        add(
+            "1-1",
+            &[
+                ("busnum", "1"),
+                ("devnum", "7"),
+                ("idVendor", "0781"),
+                ("idProduct", "5581"),
+                ("product", "Ultra"),
+                ("removable", "removable"),
+            ],
+        );
+        add(
+            "1-2",
+            &[
+                ("busnum", "1"),
+                ("devnum", "3"),
+                ("idVendor", "0bda"),
+                ("idProduct", "58f4"),
+                ("product", "Integrated Webcam"),
+                ("removable", "fixed"),
+            ],
+        );
+        add(
+            "2-1",
+            &[
+                ("busnum", "2"),
+                ("devnum", "2"),
+                ("idVendor", "1234"),
+                ("idProduct", "abcd"),
+            ],
+        );
+
+        let found = read_devices(dir.path());
+        assert_eq!(
+            found.into_iter().collect::<Vec<_>>(),
+            vec![
+                ((1, 7), device(0x0781, 0x5581, "Ultra")),
+                ((2, 2), device(0x1234, 0xabcd, "1234:abcd")),
+            ]
+        );
+        assert!(read_devices(&dir.path().join("missing")).is_empty());
+    }
+}
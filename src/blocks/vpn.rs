@@ -0,0 +1,397 @@
+//! A block for the state of a VPN, with a click to connect or disconnect it.
+//!
+//! WireGuard and OpenVPN are connected while they have an interface, which
+//! is looked for in `/sys/class/net`. Tailscale and Mullvad are asked with
+//! their command line tools, which also tell the location of the exit node.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use serde_json::value::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::blocks::Update;
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
+use crate::config::Config;
+use crate::de::deserialize_duration;
+use crate::errors::*;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+/// How soon a VPN that is still connecting is checked again.
+const CONNECTING_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    WireGuard,
+    OpenVpn,
+    Tailscale,
+    Mullvad,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Status {
+    /// Connected, through an exit in a location if it is known.
+    Connected(String),
+    Connecting,
+    Disconnected,
+}
+
+pub struct Vpn {
+    text: ButtonWidget,
+    id: String,
+    update_interval: Duration,
+    backend: Backend,
+    interface: Option<String>,
+    format_connected: FormatTemplate,
+    format_disconnected: FormatTemplate,
+    command_connect: Option<String>,
+    command_disconnect: Option<String>,
+    status: Status,
+    tx_update_request: Sender<Task>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VpnConfig {
+    /// Update interval in seconds
+    #[serde(
+        default = "VpnConfig::default_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub interval: Duration,
+
+    #[serde(default)]
+    pub backend: Backend,
+
+    /// The interface of a WireGuard or OpenVPN tunnel, by default any
+    #[serde(default)]
+    pub interface: Option<String>,
+
+    #[serde(default = "VpnConfig::default_format_connected")]
+    pub format_connected: String,
+
+    #[serde(default)]
+    pub format_disconnected: String,
+
+    /// Shell command run by a click while disconnected
+    #[serde(default)]
+    pub command_connect: Option<String>,
+
+    /// Shell command run by a click while connected
+    #[serde(default)]
+    pub command_disconnect: Option<String>,
+}
+
+impl VpnConfig {
+    fn default_interval() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn default_format_connected() -> String {
+        "{location}".to_owned()
+    }
+}
+
+impl ConfigBlock for Vpn {
+    type Config = VpnConfig;
+
+    fn new(
+        block_config: Self::Config,
+        config: Config,
+        tx_update_request: Sender<Task>,
+    ) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+        // The tools that can connect without root have default commands.
+        let (connect, disconnect) = match block_config.backend {
+            Backend::Tailscale => (Some("tailscale up"), Some("tailscale down")),
+            Backend::Mullvad => (Some("mullvad connect"), Some("mullvad disconnect")),
+            Backend::WireGuard | Backend::OpenVpn => (None, None),
+        };
+        Ok(Vpn {
+            text: ButtonWidget::new(config, &id)
+                .with_icon("net_vpn")
+                .with_text(""),
+            id,
+            update_interval: block_config.interval,
+            backend: block_config.backend,
+            interface: block_config.interface,
+            format_connected: FormatTemplate::from_string(&block_config.format_connected)
+                .block_error("vpn", "Invalid format_connected specified")?,
+            format_disconnected: FormatTemplate::from_string(&block_config.format_disconnected)
+                .block_error("vpn", "Invalid format_disconnected specified")?,
+            command_connect: block_config
+                .command_connect
+                .or_else(|| connect.map(String::from)),
+            command_disconnect: block_config
+                .command_disconnect
+                .or_else(|| disconnect.map(String::from)),
+            status: Status::Disconnected,
+            tx_update_request,
+        })
+    }
+}
+
+/// The name of a tunnel interface in `root`, `/sys/class/net` but for tests:
+/// `interface` if it is given and up, else any of the `backend`.
+fn tunnel(root: &Path, backend: Backend, interface: Option<&str>) -> Option<String> {
+    let is_tunnel = |path: &Path| match backend {
+        Backend::OpenVpn => path.join("tun_flags").exists(),
+        _ => fs::read_to_string(path.join("uevent"))
+            .map(|uevent| uevent.lines().any(|line| line == "DEVTYPE=wireguard"))
+            .unwrap_or(false),
+    };
+    match interface {
+        Some(interface) if is_tunnel(&root.join(interface)) => Some(interface.to_string()),
+        Some(_) => None,
+        None => fs::read_dir(root)
+            .ok()?
+            .flatten()
+            .find(|entry| is_tunnel(&entry.path()))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned()),
+    }
+}
+
+/// The status from the output of `tailscale status --json`. The location is
+/// the one of the exit node, if there is one.
+fn parse_tailscale(json: &str) -> Option<Status> {
+    let status: JsonValue = serde_json::from_str(json).ok()?;
+    Some(match status["BackendState"].as_str()? {
+        "Running" => {
+            let exit = status["Peer"]
+                .as_object()
+                .and_then(|peers| peers.values().find(|peer| peer["ExitNode"] == true));
+            Status::Connected(match exit {
+                Some(exit) => match (
+                    exit["Location"]["City"].as_str(),
+                    exit["Location"]["Country"].as_str(),
+                ) {
+                    (Some(city), Some(country)) => format!("{}, {}", city, country),
+                    _ => exit["HostName"].as_str().unwrap_or_default().to_string(),
+                },
+                None => String::new(),
+            })
+        }
+        "Starting" => Status::Connecting,
+        _ => Status::Disconnected,
+    })
+}
+
+/// The status from the output of `mullvad status`, either
+/// `Connected to se-got-wg-001 in Gothenburg, Sweden` or, since 2023, with the
+/// location on a line of its own such as
+/// `Visible location: Sweden, Gothenburg. IPv4: 185.213.154.68`.
+fn parse_mullvad(output: &str) -> Status {
+    let mut lines = output.lines().map(str::trim);
+    let first = lines.next().unwrap_or_default();
+    if first.starts_with("Connecting") {
+        return Status::Connecting;
+    }
+    if !first.starts_with("Connected") {
+        return Status::Disconnected;
+    }
+    let location = match first.find(" in ") {
+        Some(start) => first[start + " in ".len()..].to_string(),
+        None => lines
+            .find_map(|line| line.strip_prefix("Visible location:"))
+            .map(|location| {
+                let location = location.trim();
+                location[..location.find(". ").unwrap_or(location.len())].to_string()
+            })
+            .unwrap_or_default(),
+    };
+    Status::Connected(location)
+}
+
+fn run(command: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(command)
+        .args(args)
+        .output()
+        .block_error("vpn", &format!("failed to run {}", command))?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl Vpn {
+    fn status(&self) -> Result<Status> {
+        Ok(match self.backend {
+            Backend::WireGuard | Backend::OpenVpn => match tunnel(
+                Path::new("/sys/class/net"),
+                self.backend,
+                self.interface.as_deref(),
+            ) {
+                Some(interface) => Status::Connected(interface),
+                None => Status::Disconnected,
+            },
+            // Disconnected, it exits with an error but still prints JSON.
+            Backend::Tailscale => parse_tailscale(&run("tailscale", &["status", "--json"])?)
+                .block_error("vpn", "unexpected output of tailscale status")?,
+            Backend::Mullvad => parse_mullvad(&run("mullvad", &["status"])?),
+        })
+    }
+}
+
+impl Block for Vpn {
+    fn update(&mut self) -> Result<Option<Update>> {
+        self.status = self.status()?;
+        let (text, state) = match self.status {
+            Status::Connected(ref location) => (
+                self.format_connected
+                    .render_static_str(&map!("{location}" => location.as_str()))?,
+                State::Good,
+            ),
+            Status::Connecting => (
+                self.format_disconnected
+                    .render_static_str(&map!("{location}" => ""))?,
+                State::Info,
+            ),
+            Status::Disconnected => (
+                self.format_disconnected
+                    .render_static_str(&map!("{location}" => ""))?,
+                State::Warning,
+            ),
+        };
+        self.text.set_text(text);
+        self.text.set_state(state);
+        Ok(Some(if self.status == Status::Connecting {
+            CONNECTING_INTERVAL.into()
+        } else {
+            self.update_interval.into()
+        }))
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        vec![&self.text]
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if !event.matches_name(&self.id) || event.button != MouseButton::Left {
+            return Ok(());
+        }
+        let command = match self.status {
+            Status::Disconnected => &self.command_connect,
+            _ => &self.command_disconnect,
+        };
+        if let Some(command) = command {
+            let output = Command::new(env::var("SHELL").unwrap_or_else(|_| "sh".to_owned()))
+                .args(["-c", command])
+                .output()
+                .block_error("vpn", "failed to run the command")?;
+            if !output.status.success() {
+                return Err(BlockError(
+                    "vpn".to_owned(),
+                    format!(
+                        "'{}' failed: {}",
+                        command,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ),
+                ));
+            }
+            self.tx_update_request.send(Task {
+                id: self.id.clone(),
+                update_time: Instant::now(),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_tunnel() {
+        let root = TempDir::new().unwrap();
+        let interface = |name: &str, uevent: &str| {
+            fs::create_dir(root.path().join(name)).unwrap();
+            fs::write(root.path().join(name).join("uevent"), uevent).unwrap();
+        };
+        interface("eth0", "INTERFACE=eth0\nIFINDEX=2\n");
+        interface("wg0", "DEVTYPE=wireguard\nINTERFACE=wg0\n");
+        interface("tun0", "INTERFACE=tun0\n");
+        fs::write(root.path().join("tun0/tun_flags"), "0x1001\n").unwrap();
+
+        let tunnel = |backend, interface| tunnel(root.path(), backend, interface);
+        assert_eq!(tunnel(Backend::WireGuard, None), Some("wg0".to_string()));
+        assert_eq!(
+            tunnel(Backend::WireGuard, Some("wg0")),
+            Some("wg0".to_string())
+        );
+        assert_eq!(tunnel(Backend::WireGuard, Some("wg1")), None);
+        assert_eq!(tunnel(Backend::WireGuard, Some("eth0")), None);
+        assert_eq!(tunnel(Backend::OpenVpn, None), Some("tun0".to_string()));
+    }
+
+    #[test]
+    fn test_parse_tailscale() {
+        assert_eq!(
+            parse_tailscale(r#"{"BackendState":"Running","Peer":{"a":{"HostName":"pi"}}}"#),
+            Some(Status::Connected(String::new()))
+        );
+        assert_eq!(
+            parse_tailscale(
+                r#"{"BackendState":"Running","Peer":{
+                    "a":{"HostName":"pi","ExitNode":false},
+                    "b":{"HostName":"se-got-wg-001.mullvad.ts.net","ExitNode":true,
+                         "Location":{"Country":"Sweden","City":"Gothenburg"}}}}"#
+            ),
+            Some(Status::Connected("Gothenburg, Sweden".to_string()))
+        );
+        assert_eq!(
+            parse_tailscale(
+                r#"{"BackendState":"Running","Peer":{"a":{"HostName":"home","ExitNode":true}}}"#
+            ),
+            Some(Status::Connected("home".to_string()))
+        );
+        assert_eq!(
+            parse_tailscale(r#"{"BackendState":"Stopped","Peer":null}"#),
+            Some(Status::Disconnected)
+        );
+        assert_eq!(
+            parse_tailscale(r#"{"BackendState":"Starting"}"#),
+            Some(Status::Connecting)
+        );
+        assert_eq!(parse_tailscale("failed to connect"), None);
+    }
+
+    #[test]
+    fn test_parse_mullvad() {
+        assert_eq!(
+            parse_mullvad("Connected to se-got-wg-001 in Gothenburg, Sweden\n"),
+            Status::Connected("Gothenburg, Sweden".to_string())
+        );
+        assert_eq!(
+            parse_mullvad(
+                "Connected\n    Relay:            se-got-wg-001\n    \
+                 Visible location: Sweden, Gothenburg. IPv4: 185.213.154.68\n"
+            ),
+            Status::Connected("Sweden, Gothenburg".to_string())
+        );
+        assert_eq!(
+            parse_mullvad("Connecting to se-got-wg-001...\n"),
+            Status::Connecting
+        );
+        assert_eq!(parse_mullvad("Disconnected\n"), Status::Disconnected);
+        assert_eq!(parse_mullvad(""), Status::Disconnected);
+    }
+}
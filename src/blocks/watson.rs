@@ -12,6 +12,7 @@ use crate::de::deserialize_local_timestamp;
 use crate::errors::*;
 use crate::input::I3BarEvent;
 use crate::scheduler::Task;
+use crate::stop::{self, Stop};
 use crate::util::xdg_config_home;
 use crate::widget::{I3BarWidget, State};
 use crate::widgets::button::ButtonWidget;
@@ -29,6 +30,8 @@ pub struct Watson {
     show_time: bool,
     prev_state: Option<WatsonState>,
     update_interval: Duration,
+    /// Stops watching the state file when the block is dropped.
+    _watcher: Stop,
 }
 
 #[derive(Deserialize, Debug, Default, Clone)]
@@ -71,6 +74,7 @@ impl ConfigBlock for Watson {
         tx_update_request: Sender<Task>,
     ) -> Result<Self> {
         let id = Uuid::new_v4().to_simple().to_string();
+        let (stop, stopped) = stop::pair()?;
 
         let watson = Watson {
             id: id.clone(),
@@ -79,6 +83,7 @@ impl ConfigBlock for Watson {
             show_time: block_config.show_time,
             update_interval: block_config.interval,
             prev_state: None,
+            _watcher: stop,
         };
 
         // Spin up a thread to watch for changes to the brightness file for the
@@ -107,20 +112,17 @@ impl ConfigBlock for Watson {
                 .expect("failed to watch watson state file");
 
             let mut buffer = [0; 1024];
-            loop {
-                let events = notify
-                    .read_events_blocking(&mut buffer)
-                    .expect("error while reading inotify events");
-
+            while let Some(events) = stopped.inotify_events(&mut notify, &mut buffer) {
                 for event in events {
                     match event.mask {
                         EventMask::CREATE if event.name == Some(&file_name) => {
-                            tx_update_request
-                                .send(Task {
-                                    id: id.clone(),
-                                    update_time: Instant::now(),
-                                })
-                                .expect("unable to send task from watson watcher");
+                            let task = Task {
+                                id: id.clone(),
+                                update_time: Instant::now(),
+                            };
+                            if tx_update_request.send(task).is_err() {
+                                return;
+                            }
                         }
                         _ => {}
                     }
@@ -0,0 +1,384 @@
+//! A block counting the windows on the focused workspace, and the urgent
+//! windows on any workspace. Works with i3 and sway.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crossbeam_channel::Sender;
+use serde_derive::Deserialize;
+use swayipc::reply::{Event, Node, NodeType, WindowChange, WorkspaceChange};
+use swayipc::{Connection, EventType};
+use uuid::Uuid;
+
+use crate::blocks::{Block, ClickSafety, ConfigBlock, Update};
+use crate::config::Config;
+use crate::errors::*;
+use crate::formatting::Value;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+use crate::util::FormatTemplate;
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::button::ButtonWidget;
+
+/// Whether `node` is a window rather than a container of windows.
+fn is_window(node: &Node) -> bool {
+    (node.node_type == NodeType::Con || node.node_type == NodeType::FloatingCon)
+        && node.nodes.is_empty()
+        && node.floating_nodes.is_empty()
+}
+
+fn children(node: &Node) -> impl Iterator<Item = &Node> {
+    node.nodes.iter().chain(node.floating_nodes.iter())
+}
+
+fn count_windows(node: &Node) -> usize {
+    if is_window(node) {
+        1
+    } else {
+        children(node).map(count_windows).sum()
+    }
+}
+
+fn has_focus(node: &Node) -> bool {
+    node.focused || children(node).any(has_focus)
+}
+
+/// The workspace holding the focused window, or the focused workspace itself
+/// if it is empty.
+fn focused_workspace(node: &Node) -> Option<&Node> {
+    if node.node_type == NodeType::Workspace {
+        return if has_focus(node) { Some(node) } else { None };
+    }
+    children(node).find_map(focused_workspace)
+}
+
+fn urgent_windows(node: &Node, urgent: &mut Vec<i64>) {
+    if is_window(node) && node.urgent {
+        urgent.push(node.id);
+    }
+    for child in children(node) {
+        urgent_windows(child, urgent);
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct WindowCounts {
+    /// Windows on the focused workspace.
+    focused: usize,
+    /// Ids of the urgent windows, in the order they became urgent.
+    urgent: Vec<i64>,
+}
+
+impl WindowCounts {
+    /// Recounts everything from the whole tree.
+    fn sync(&mut self, tree: &Node) {
+        self.focused = focused_workspace(tree).map(count_windows).unwrap_or(0);
+        let mut urgent = Vec::new();
+        urgent_windows(tree, &mut urgent);
+        // Keep the order of windows we already knew to be urgent.
+        self.urgent.retain(|id| urgent.contains(id));
+        for id in urgent {
+            self.set_urgent(id, true);
+        }
+    }
+
+    fn set_urgent(&mut self, id: i64, urgent: bool) {
+        if !urgent {
+            self.urgent.retain(|&other| other != id);
+        } else if !self.urgent.contains(&id) {
+            self.urgent.push(id);
+        }
+    }
+
+    /// Applies an IPC event. Returns `true` if the whole tree is needed to
+    /// keep the counts right.
+    fn apply(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Window(e) => match e.change {
+                WindowChange::Urgent => {
+                    self.set_urgent(e.container.id, e.container.urgent);
+                    false
+                }
+                // The event does not tell which workspace the window was on.
+                WindowChange::New
+                | WindowChange::Close
+                | WindowChange::Move
+                | WindowChange::Floating => true,
+                _ => false,
+            },
+            Event::Workspace(e) => match (&e.change, &e.current) {
+                (WorkspaceChange::Focus, Some(current)) => {
+                    self.focused = count_windows(current);
+                    false
+                }
+                (WorkspaceChange::Reload, _) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+pub struct WindowCount {
+    id: String,
+    output: ButtonWidget,
+    format: FormatTemplate,
+    counts: Arc<Mutex<WindowCounts>>,
+    hide_when_empty: bool,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WindowCountConfig {
+    /// Format override
+    #[serde(default = "WindowCountConfig::default_format")]
+    pub format: String,
+
+    /// Hide the block when there are no windows on the workspace and none is
+    /// urgent
+    #[serde(default = "WindowCountConfig::default_hide_when_empty")]
+    pub hide_when_empty: bool,
+}
+
+impl WindowCountConfig {
+    fn default_format() -> String {
+        "{count}".to_owned()
+    }
+
+    fn default_hide_when_empty() -> bool {
+        false
+    }
+}
+
+fn watch(counts: Arc<Mutex<WindowCounts>>, id: String, send: Sender<Task>) -> Result<()> {
+    let mut conn = Connection::new().block_error("window_count", "failed to connect to IPC")?;
+    let events = Connection::new()
+        .and_then(|conn| conn.subscribe(&[EventType::Window, EventType::Workspace]))
+        .block_error("window_count", "failed to subscribe to events")?;
+
+    for event in events {
+        let event = event.block_error("window_count", "failed to read event")?;
+        let mut current = counts
+            .lock()
+            .block_error("window_count", "failed to acquire lock")?;
+        let before = current.clone();
+        if current.apply(&event) {
+            let tree = conn
+                .get_tree()
+                .block_error("window_count", "failed to get tree")?;
+            current.sync(&tree);
+        }
+        if *current != before {
+            drop(current);
+            send.send(Task {
+                id: id.clone(),
+                update_time: Instant::now(),
+            })
+            .block_error("window_count", "failed to send update")?;
+        }
+    }
+    Ok(())
+}
+
+impl ConfigBlock for WindowCount {
+    type Config = WindowCountConfig;
+
+    fn new(block_config: Self::Config, config: Config, send: Sender<Task>) -> Result<Self> {
+        let id = Uuid::new_v4().to_simple().to_string();
+
+        let tree = Connection::new()
+            .and_then(|mut conn| conn.get_tree())
+            .block_error("window_count", "failed to get tree over IPC")?;
+        let mut counts = WindowCounts::default();
+        counts.sync(&tree);
+        let counts = Arc::new(Mutex::new(counts));
+
+        let counts_copy = counts.clone();
+        let id_copy = id.clone();
+        thread::Builder::new()
+            .name("window_count".into())
+            .spawn(move || {
+                if let Err(e) = watch(counts_copy, id_copy.clone(), send) {
+                    log_block_error(&id_copy, "window_count", &format!("{:?}", e));
+                }
+            })
+            .block_error("window_count", "failed to spawn thread")?;
+
+        Ok(WindowCount {
+            output: ButtonWidget::new(config, &id),
+            id,
+            format: FormatTemplate::from_string(&block_config.format)
+                .block_error("window_count", "Invalid format specified")?,
+            counts,
+            hide_when_empty: block_config.hide_when_empty,
+        })
+    }
+}
+
+impl WindowCount {
+    fn counts(&self) -> Result<WindowCounts> {
+        Ok(self
+            .counts
+            .lock()
+            .block_error("window_count", "failed to acquire lock")?
+            .clone())
+    }
+}
+
+impl Block for WindowCount {
+    fn update(&mut self) -> Result<Option<Update>> {
+        let counts = self.counts()?;
+        let values = map!("{count}" => Value::integer(counts.focused),
+                          "{urgent}" => Value::integer(counts.urgent.len()));
+        self.output
+            .set_text(self.format.render_static_str(&values)?);
+        self.output.set_state(if counts.urgent.is_empty() {
+            State::Idle
+        } else {
+            State::Critical
+        });
+        Ok(None)
+    }
+
+    fn view(&self) -> Vec<&dyn I3BarWidget> {
+        match self.counts() {
+            Ok(ref counts)
+                if self.hide_when_empty && counts.focused == 0 && counts.urgent.is_empty() =>
+            {
+                vec![]
+            }
+            _ => vec![&self.output],
+        }
+    }
+
+    fn click(&mut self, event: &I3BarEvent) -> Result<()> {
+        if event.matches_name(&self.id) && event.button == MouseButton::Left {
+            if let Some(&window) = self.counts()?.urgent.first() {
+                Connection::new()
+                    .and_then(|mut conn| conn.run_command(format!("[con_id={}] focus", window)))
+                    .block_error("window_count", "failed to focus urgent window")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn node(id: i64, node_type: &str, nodes: Vec<serde_json::Value>) -> serde_json::Value {
+        let rect = json!({"x": 0, "y": 0, "width": 0, "height": 0});
+        json!({
+            "id": id,
+            "name": null,
+            "type": node_type,
+            "border": "none",
+            "current_border_width": 0,
+            "layout": "splith",
+            "percent": null,
+            "rect": rect,
+            "window_rect": rect,
+            "deco_rect": rect,
+            "geometry": rect,
+            "urgent": false,
+            "focused": false,
+            "focus": [],
+            "nodes": nodes,
+            "floating_nodes": [],
+            "sticky": false,
+            "representation": null,
+            "fullscreen_mode": null,
+            "app_id": null,
+            "pid": null,
+            "window": null,
+            "num": null,
+            "window_properties": null,
+            "marks": [],
+            "inhibit_idle": null,
+            "idle_inhibitors": null,
+            "shell": null
+        })
+    }
+
+    fn window(id: i64) -> serde_json::Value {
+        node(id, "con", vec![])
+    }
+
+    fn with(mut node: serde_json::Value, key: &str) -> serde_json::Value {
+        node[key] = json!(true);
+        node
+    }
+
+    /// Two outputs: workspace 1 with a split holding two windows next to a
+    /// third, workspace 2 with one window, and an empty workspace 3.
+    fn tree(focused: i64, urgent: &[i64]) -> Node {
+        let mark = |id: i64| {
+            let mut w = window(id);
+            if id == focused {
+                w = with(w, "focused");
+            }
+            if urgent.contains(&id) {
+                w = with(w, "urgent");
+            }
+            w
+        };
+        let mut ws3 = node(30, "workspace", vec![]);
+        if focused == 30 {
+            ws3 = with(ws3, "focused");
+        }
+        let ws1 = node(
+            10,
+            "workspace",
+            vec![node(11, "con", vec![mark(12), mark(13)]), mark(14)],
+        );
+        let ws2 = node(20, "workspace", vec![mark(21)]);
+        let root = node(
+            1,
+            "root",
+            vec![
+                node(2, "output", vec![ws1, ws3]),
+                node(3, "output", vec![ws2]),
+            ],
+        );
+        serde_json::from_value(root).unwrap()
+    }
+
+    #[test]
+    fn test_sync() {
+        let mut counts = WindowCounts::default();
+        counts.sync(&tree(13, &[]));
+        assert_eq!(counts.focused, 3);
+        assert!(counts.urgent.is_empty());
+
+        counts.sync(&tree(21, &[14]));
+        assert_eq!(counts.focused, 1);
+        assert_eq!(counts.urgent, vec![14]);
+
+        counts.sync(&tree(30, &[21, 14]));
+        assert_eq!(counts.focused, 0);
+        // Windows that were urgent before stay first.
+        assert_eq!(counts.urgent, vec![14, 21]);
+    }
+
+    #[test]
+    fn test_set_urgent() {
+        let mut counts = WindowCounts::default();
+        counts.set_urgent(5, true);
+        counts.set_urgent(7, true);
+        counts.set_urgent(5, true);
+        assert_eq!(counts.urgent, vec![5, 7]);
+        counts.set_urgent(5, false);
+        assert_eq!(counts.urgent, vec![7]);
+    }
+}
@@ -8,10 +8,11 @@ use serde_derive::Deserialize;
 use uuid::Uuid;
 
 use crate::blocks::Update;
-use crate::blocks::{Block, ConfigBlock};
+use crate::blocks::{Block, ClickSafety, ConfigBlock};
 use crate::config::{Config, LogicalDirection};
 use crate::de::deserialize_duration;
 use crate::errors::*;
+use crate::formatting::Value;
 use crate::input::{I3BarEvent, MouseButton};
 use crate::scheduler::Task;
 use crate::util::FormatTemplate;
@@ -55,8 +56,7 @@ pub struct Xrandr {
     id: String,
     update_interval: Duration,
     monitors: Vec<Monitor>,
-    icons: bool,
-    resolution: bool,
+    format: FormatTemplate,
     step_width: u32,
     current_idx: usize,
 
@@ -82,6 +82,10 @@ pub struct XrandrConfig {
     #[serde(default = "XrandrConfig::default_resolution")]
     pub resolution: bool,
 
+    /// Format override, with the placeholders `{display}`, `{brightness}` and `{resolution}`
+    #[serde(default)]
+    pub format: Option<String>,
+
     /// The steps brightness is in/decreased for the selected screen (When greater than 50 it gets limited to 50)
     #[serde(default = "XrandrConfig::default_step_width")]
     pub step_width: u32,
@@ -103,6 +107,16 @@ impl XrandrConfig {
     fn default_step_width() -> u32 {
         5 as u32
     }
+
+    /// The format matching `icons` and `resolution`, when none is given.
+    fn default_format(&self) -> &'static str {
+        match (self.icons, self.resolution) {
+            (true, true) => "{display} \u{f185} {brightness} \u{f096} {resolution}",
+            (false, true) => "{display}: {brightness} [{resolution}]",
+            (true, false) => "{display} \u{f185} {brightness}",
+            (false, false) => "{display}: {brightness}",
+        }
+    }
 }
 
 macro_rules! unwrap_or_continue {
@@ -199,27 +213,12 @@ impl Xrandr {
 
     fn display(&mut self) -> Result<()> {
         if let Some(m) = self.monitors.get(self.current_idx) {
-            let brightness_str = m.brightness.to_string();
-            let values = map!("{display}" => m.name.clone(),
-                              "{brightness}" => brightness_str,
-                              "{resolution}" => m.resolution.clone());
+            let values = map!("{display}" => Value::from(m.name.clone()),
+                              "{brightness}" => Value::integer(m.brightness),
+                              "{resolution}" => Value::from(m.resolution.clone()));
 
             self.text.set_icon("xrandr");
-            let format_str = if self.resolution {
-                if self.icons {
-                    "{display} \u{f185} {brightness} \u{f096} {resolution}"
-                } else {
-                    "{display}: {brightness} [{resolution}]"
-                }
-            } else if self.icons {
-                "{display} \u{f185} {brightness}"
-            } else {
-                "{display}: {brightness}"
-            };
-
-            if let Ok(fmt_template) = FormatTemplate::from_string(format_str) {
-                self.text.set_text(fmt_template.render_static_str(&values)?);
-            }
+            self.text.set_text(self.format.render_static_str(&values)?);
         }
 
         Ok(())
@@ -244,8 +243,13 @@ impl ConfigBlock for Xrandr {
             id,
             update_interval: block_config.interval,
             current_idx: 0,
-            icons: block_config.icons,
-            resolution: block_config.resolution,
+            format: FormatTemplate::from_string(
+                block_config
+                    .format
+                    .as_deref()
+                    .unwrap_or_else(|| block_config.default_format()),
+            )
+            .block_error("xrandr", "Invalid format specified for xrandr")?,
             step_width,
             monitors: Vec::new(),
             config,
@@ -308,6 +312,10 @@ impl Block for Xrandr {
         Ok(())
     }
 
+    fn click_safety(&self) -> ClickSafety {
+        ClickSafety::Unsafe
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
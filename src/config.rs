@@ -1,18 +1,22 @@
 use std::collections::HashMap as Map;
 use std::marker::PhantomData;
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 use serde::de::{Deserialize, Deserializer, Error};
 use serde_derive::Deserialize;
 use toml::value;
 
+use crate::blocks::{self, Block};
 use crate::de::*;
+use crate::errors::ResultExtInternal;
+use crate::formatting::NumberFormat;
 use crate::input::MouseButton;
-use crate::themes::{Theme, ThemeConfig};
-use crate::util::deserialize_file;
-use crate::{errors, icons};
+use crate::themes::{Theme, ThemeConfig, ThemeOverrides};
+use crate::util::{self, deserialize_file};
+use crate::{errors, icons, registry, signals};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
@@ -27,6 +31,21 @@ pub struct Config {
     /// motion which is undesired for sliders. Use "natural" to invert this.
     #[serde(default = "Scrolling::default", rename = "scrolling")]
     pub scrolling: Scrolling,
+    /// Decimal separator used when rendering numbers
+    #[serde(default = "Config::default_decimal_separator")]
+    pub decimal_separator: String,
+    /// Separator between groups of thousands, used by placeholders with the `,` flag
+    #[serde(default = "Config::default_group_separator")]
+    pub group_separator: String,
+    /// Characters of bar placeholders, from empty to full
+    #[serde(default = "Config::default_bar_chars")]
+    pub bar_chars: String,
+    /// Longest time between the clicks of a double click
+    #[serde(
+        default = "Config::default_double_click_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub double_click_interval: Duration,
     #[serde(rename = "block", deserialize_with = "deserialize_blocks")]
     pub blocks: Vec<(String, value::Value)>,
 }
@@ -37,11 +56,41 @@ impl Default for Config {
             icons: icons::default(),
             theme: Theme::default(),
             scrolling: Scrolling::default(),
+            decimal_separator: Config::default_decimal_separator(),
+            group_separator: Config::default_group_separator(),
+            bar_chars: Config::default_bar_chars(),
+            double_click_interval: Config::default_double_click_interval(),
             blocks: Vec::new(),
         }
     }
 }
 
+impl Config {
+    fn default_decimal_separator() -> String {
+        NumberFormat::default().decimal_separator
+    }
+
+    fn default_group_separator() -> String {
+        NumberFormat::default().group_separator
+    }
+
+    fn default_bar_chars() -> String {
+        NumberFormat::default().bar_chars
+    }
+
+    fn default_double_click_interval() -> Duration {
+        Duration::from_millis(250)
+    }
+
+    pub fn number_format(&self) -> NumberFormat {
+        NumberFormat {
+            decimal_separator: self.decimal_separator.clone(),
+            group_separator: self.group_separator.clone(),
+            bar_chars: self.bar_chars.clone(),
+        }
+    }
+}
+
 impl From<LegacyConfig> for Config {
     fn from(legacy_config: LegacyConfig) -> Self {
         Config {
@@ -51,6 +100,10 @@ impl From<LegacyConfig> for Config {
                 .and_then(|s| Theme::from_name(s.as_str()))
                 .unwrap_or_default(),
             scrolling: legacy_config.scrolling,
+            decimal_separator: legacy_config.decimal_separator,
+            group_separator: legacy_config.group_separator,
+            bar_chars: legacy_config.bar_chars,
+            double_click_interval: legacy_config.double_click_interval,
             blocks: legacy_config.blocks,
         }
     }
@@ -69,6 +122,21 @@ pub struct LegacyConfig {
     /// motion which is undesired for sliders. Use "natural" to invert this.
     #[serde(default = "Scrolling::default", rename = "scrolling")]
     pub scrolling: Scrolling,
+    /// Decimal separator used when rendering numbers
+    #[serde(default = "Config::default_decimal_separator")]
+    pub decimal_separator: String,
+    /// Separator between groups of thousands, used by placeholders with the `,` flag
+    #[serde(default = "Config::default_group_separator")]
+    pub group_separator: String,
+    /// Characters of bar placeholders, from empty to full
+    #[serde(default = "Config::default_bar_chars")]
+    pub bar_chars: String,
+    /// Longest time between the clicks of a double click
+    #[serde(
+        default = "Config::default_double_click_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub double_click_interval: Duration,
     #[serde(rename = "block", deserialize_with = "deserialize_blocks")]
     pub blocks: Vec<(String, value::Value)>,
 }
@@ -79,6 +147,10 @@ impl Default for LegacyConfig {
             icons: icons::default(),
             theme: None,
             scrolling: Scrolling::default(),
+            decimal_separator: Config::default_decimal_separator(),
+            group_separator: Config::default_group_separator(),
+            bar_chars: Config::default_bar_chars(),
+            double_click_interval: Config::default_double_click_interval(),
             blocks: Vec::new(),
         }
     }
@@ -116,6 +188,96 @@ impl Default for Scrolling {
     }
 }
 
+/// Options that the framework handles for every block. They are taken out of
+/// the block's table before the block's own configuration is deserialized.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SharedBlockConfig {
+    /// The id other blocks can use to refer to this block. Unrelated to the
+    /// internal id returned by `Block::id`.
+    pub id: Option<String>,
+
+    /// Theme keys to change for this block only.
+    pub theme_overrides: Option<ThemeOverrides>,
+
+    /// Whether to draw the theme's separator in front of this block.
+    #[serde(default = "SharedBlockConfig::default_separator")]
+    pub separator: bool,
+
+    /// A shell command to run when the block is left clicked.
+    pub on_click: Option<String>,
+
+    /// Update the block on `SIGRTMIN` plus this.
+    pub signal: Option<i64>,
+}
+
+impl SharedBlockConfig {
+    const KEYS: &'static [&'static str] =
+        &["id", "theme_overrides", "separator", "on_click", "signal"];
+
+    /// Blocks that handle `on_click` themselves, because they do more than
+    /// run the command.
+    const OWN_ON_CLICK: &'static [&'static str] = &["custom"];
+
+    fn default_separator() -> bool {
+        true
+    }
+
+    /// Removes the shared options from `block_config`, the configuration of a
+    /// `block_name` block, and returns them.
+    pub fn extract(block_name: &str, block_config: &mut value::Value) -> errors::Result<Self> {
+        let mut shared = value::Table::new();
+        if let Some(table) = block_config.as_table_mut() {
+            for key in Self::KEYS {
+                if *key == "on_click" && Self::OWN_ON_CLICK.contains(&block_name) {
+                    continue;
+                }
+                if let Some(v) = table.remove(*key) {
+                    shared.insert((*key).to_string(), v);
+                }
+            }
+        }
+        let shared: Self = value::Value::Table(shared)
+            .try_into()
+            .configuration_error("Failed to deserialize shared block options.")?;
+        if let Some(offset) = shared.signal {
+            if signals::realtime_signal(offset).is_none() {
+                return Err(errors::ConfigurationError(
+                    format!("signal must be between 1 and {}", signals::max_offset()),
+                    (String::new(), String::new()),
+                ));
+            }
+        }
+        Ok(shared)
+    }
+
+    /// The configuration to create the block with.
+    pub fn block_config(&self, config: &Config) -> errors::Result<Config> {
+        let mut config = config.clone();
+        if let Some(ref overrides) = self.theme_overrides {
+            config
+                .theme
+                .apply_overrides(overrides.clone())
+                .configuration_error("Invalid theme_overrides.")?;
+        }
+        Ok(config)
+    }
+
+    /// Registers the options that apply once the block exists.
+    pub fn register(&self, block: &dyn Block) -> errors::Result<()> {
+        if let Some(ref name) = self.id {
+            registry::register_name(block.id(), name)?;
+        }
+        if !self.separator {
+            util::hide_separator(block.id());
+        }
+        if let Some(ref command) = self.on_click {
+            blocks::set_on_click(block.id(), command);
+        }
+        Ok(())
+    }
+}
+
 fn deserialize_blocks<'de, D>(deserializer: D) -> Result<Vec<(String, value::Value)>, D::Error>
 where
     D: Deserializer<'de>,
@@ -149,21 +311,27 @@ where
 {
     ThemeConfig::deserialize(deserializer)?
         .into_theme()
-        .ok_or_else(|| D::Error::custom("Unrecognized theme name."))
+        .map_err(D::Error::custom)
+}
+
+/// Where the configuration is read from if no path is given.
+pub fn default_config_path() -> PathBuf {
+    util::xdg_config_home().join("i3status-rust/config.toml")
 }
 
 // this function may belong somewhere else...
 pub fn load_config(config_path: &Path) -> errors::Result<Config> {
     let config: errors::Result<Config> = deserialize_file(config_path.to_str().unwrap());
-    config.or_else(|_| {
+    config.or_else(|error| {
         let legacy_config: errors::Result<LegacyConfig> =
             deserialize_file(config_path.to_str().unwrap());
-        legacy_config.map(|legacy| legacy.into())
+        // Report what is wrong with the config, not why it is no legacy config.
+        legacy_config.map(|legacy| legacy.into()).map_err(|_| error)
     })
 }
 #[cfg(test)]
 mod tests {
-    use crate::config::load_config;
+    use crate::config::{load_config, SharedBlockConfig};
     use assert_fs::prelude::{FileWriteStr, PathChild};
     use assert_fs::TempDir;
 
@@ -209,4 +377,18 @@ mod tests {
         let config = load_config(config_file_path.path());
         config.unwrap();
     }
+
+    #[test]
+    fn test_extract_on_click() {
+        let mut block_config: toml::Value = toml::from_str("on_click = \"xterm\"").unwrap();
+        let shared = SharedBlockConfig::extract("time", &mut block_config).unwrap();
+        assert_eq!(shared.on_click.as_deref(), Some("xterm"));
+        assert!(block_config.get("on_click").is_none());
+
+        // The custom block runs the command itself.
+        let mut block_config: toml::Value = toml::from_str("on_click = \"xterm\"").unwrap();
+        let shared = SharedBlockConfig::extract("custom", &mut block_config).unwrap();
+        assert_eq!(shared.on_click, None);
+        assert!(block_config.get("on_click").is_some());
+    }
 }
@@ -0,0 +1,202 @@
+//! Controlling the bar from scripts through a Unix socket.
+//!
+//! Each line written to the socket is a command, answered with a line that is
+//! either `ok` or `error: ` and the reason. Blocks are referred to by their
+//! `id` option. The commands are:
+//!
+//! - `hide <id>`, `show <id>` and `toggle <id>` hide or show a block,
+//! - `set_text <id> <text>` shows a text in a `custom` block until its next
+//!   update,
+//! - `refresh` updates every block right away, `refresh <id>` just one,
+//! - `theme <name>` switches to a built-in theme or a theme file.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+
+use crossbeam_channel::Sender;
+
+use crate::errors::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Hide(String),
+    Show(String),
+    Toggle(String),
+    SetText(String, String),
+    /// Updates the named block, or every block.
+    Refresh(Option<String>),
+    Theme(String),
+}
+
+/// A command, and where to send whether it worked.
+pub type Request = (Command, Sender<Result<()>>);
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command> {
+        let line = line.trim();
+        let (command, argument) = match line.find(char::is_whitespace) {
+            Some(end) => (&line[..end], line[end..].trim_start()),
+            None => (line, ""),
+        };
+        let argument = || {
+            if argument.is_empty() {
+                Err(InternalError(
+                    "control".to_owned(),
+                    format!("'{}' needs an argument", command),
+                    None,
+                ))
+            } else {
+                Ok(argument.to_string())
+            }
+        };
+        Ok(match command {
+            "hide" => Command::Hide(argument()?),
+            "show" => Command::Show(argument()?),
+            "toggle" => Command::Toggle(argument()?),
+            "set_text" => {
+                let argument = argument()?;
+                match argument.find(char::is_whitespace) {
+                    Some(end) => Command::SetText(
+                        argument[..end].to_string(),
+                        argument[end..].trim_start().to_string(),
+                    ),
+                    None => Command::SetText(argument, String::new()),
+                }
+            }
+            "refresh" => Command::Refresh(argument().ok()),
+            "theme" => Command::Theme(argument()?),
+            _ => {
+                return Err(InternalError(
+                    "control".to_owned(),
+                    format!("unknown command '{}'", command),
+                    None,
+                ))
+            }
+        })
+    }
+}
+
+/// Accepts commands on a socket at `path` and hands them to `requests`. A
+/// file left at `path` by an earlier run is replaced.
+pub fn listen(path: &Path, requests: Sender<Request>) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path).internal_error("control", "failed to remove old socket")?;
+    }
+    let listener = UnixListener::bind(path).internal_error(
+        "control",
+        &format!("failed to create socket at {}", path.display()),
+    )?;
+    thread::Builder::new()
+        .name("control".into())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let requests = requests.clone();
+                thread::Builder::new()
+                    .name("control-client".into())
+                    .spawn(move || serve(stream, &requests))
+                    .ok();
+            }
+        })
+        .internal_error("control", "failed to start control thread")?;
+    Ok(())
+}
+
+/// Answers the commands of one client until it disconnects.
+fn serve(stream: UnixStream, requests: &Sender<Request>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = Command::parse(&line).and_then(|command| {
+            let (reply, result) = crossbeam_channel::bounded(1);
+            requests
+                .send((command, reply))
+                .internal_error("control", "the bar is shutting down")?;
+            result
+                .recv()
+                .internal_error("control", "the bar is shutting down")?
+        });
+        let answer = match result {
+            Ok(()) => "ok".to_string(),
+            Err(error) => {
+                let (name, message) = error.parts();
+                format!("error: {}: {}", name, message)
+            }
+        };
+        if writeln!(writer, "{}", answer).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |line: &str| Command::parse(line).unwrap();
+        assert_eq!(parse("hide net"), Command::Hide("net".to_string()));
+        assert_eq!(parse("  show   net\n"), Command::Show("net".to_string()));
+        assert_eq!(parse("toggle net"), Command::Toggle("net".to_string()));
+        assert_eq!(
+            parse("set_text status  two  words "),
+            Command::SetText("status".to_string(), "two  words".to_string())
+        );
+        assert_eq!(
+            parse("set_text status"),
+            Command::SetText("status".to_string(), String::new())
+        );
+        assert_eq!(parse("refresh"), Command::Refresh(None));
+        assert_eq!(
+            parse("refresh net"),
+            Command::Refresh(Some("net".to_string()))
+        );
+        assert_eq!(
+            parse("theme gruvbox-dark"),
+            Command::Theme("gruvbox-dark".to_string())
+        );
+        assert!(Command::parse("hide").is_err());
+        assert!(Command::parse("explode net").is_err());
+    }
+
+    #[test]
+    fn test_socket() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("control.sock");
+        // A stale socket is replaced.
+        fs::write(&path, "").unwrap();
+        let (requests, received) = crossbeam_channel::unbounded();
+        listen(&path, requests).unwrap();
+        thread::spawn(move || {
+            for (command, reply) in received {
+                let result = match command {
+                    Command::Hide(_) => Ok(()),
+                    _ => Err(InternalError("runner".to_owned(), "nope".to_owned(), None)),
+                };
+                reply.send(result).unwrap();
+            }
+        });
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"hide net\n\nrefresh\nfly\n").unwrap();
+        let mut answers = BufReader::new(stream).lines();
+        let mut answer = || answers.next().unwrap().unwrap();
+        assert_eq!(answer(), "ok");
+        assert_eq!(answer(), "error: runner: nope");
+        assert_eq!(answer(), "error: control: unknown command 'fly'");
+    }
+}
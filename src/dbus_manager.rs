@@ -0,0 +1,462 @@
+//! One connection to each of the session and the system bus, shared by the
+//! blocks listening for D-Bus messages.
+//!
+//! Blocks [`subscribe`] a handler to the messages matching a match rule. Each
+//! bus has a single thread that reads messages off its connection and calls
+//! the handlers whose rule matches, so listening needs no connection and no
+//! thread per block. The connection and its thread are set up by the first
+//! subscription to the bus.
+//!
+//! Handlers run on the thread of the bus and must not block, they usually
+//! just ask for an update of their block. Method calls are not multiplexed,
+//! blocks make them on connections of their own. `ibus` talks to a bus of its
+//! own as well.
+
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender};
+use dbus::channel::{BusType, Channel};
+use dbus::{Message, MessageType};
+use lazy_static::lazy_static;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::unistd;
+
+use crate::errors::*;
+use crate::scheduler::Task;
+
+/// The timeout for adding match rules to the bus.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+impl Bus {
+    fn bus_type(self) -> BusType {
+        match self {
+            Bus::Session => BusType::Session,
+            Bus::System => BusType::System,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Bus::Session => "dbus-session",
+            Bus::System => "dbus-system",
+        }
+    }
+}
+
+/// A handler for messages, which returns whether it wants more of them.
+type Handler = Box<dyn FnMut(&Message) -> bool + Send>;
+
+/// The part of a match rule the bus checks that concerns a message itself, to
+/// tell which subscription a message is for.
+#[derive(Debug, Default, PartialEq)]
+struct Rule {
+    msg_type: Option<MessageType>,
+    sender: Option<String>,
+    path: Option<String>,
+    path_namespace: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    /// String arguments, by their index.
+    args: Vec<(u8, String)>,
+    arg0namespace: Option<String>,
+}
+
+impl Rule {
+    /// Parses a match rule as the bus takes it, e.g.
+    /// `type='signal',interface='org.freedesktop.DBus.Properties'`.
+    fn parse(rule: &str) -> Result<Rule> {
+        let invalid = || {
+            InternalError(
+                "dbus".to_owned(),
+                format!("invalid match rule: {}", rule),
+                None,
+            )
+        };
+        let mut parsed = Rule::default();
+        let mut rest = rule.trim();
+        while !rest.is_empty() {
+            let eq = rest.find('=').ok_or_else(invalid)?;
+            let key = rest[..eq].trim();
+            let quoted = rest[eq + 1..].trim_start();
+            if !quoted.starts_with('\'') {
+                return Err(invalid());
+            }
+            let end = quoted[1..].find('\'').ok_or_else(invalid)? + 1;
+            let value = quoted[1..end].to_string();
+            rest = quoted[end + 1..].trim_start();
+            if rest.starts_with(',') {
+                rest = rest[1..].trim_start();
+            } else if !rest.is_empty() {
+                return Err(invalid());
+            }
+
+            match key {
+                "type" => {
+                    parsed.msg_type = Some(match &*value {
+                        "signal" => MessageType::Signal,
+                        "method_call" => MessageType::MethodCall,
+                        "method_return" => MessageType::MethodReturn,
+                        "error" => MessageType::Error,
+                        _ => return Err(invalid()),
+                    })
+                }
+                "sender" => parsed.sender = Some(value),
+                "path" => parsed.path = Some(value),
+                "path_namespace" => parsed.path_namespace = Some(value),
+                "interface" => parsed.interface = Some(value),
+                "member" => parsed.member = Some(value),
+                "arg0namespace" => parsed.arg0namespace = Some(value),
+                // Only changes which messages the bus sends us.
+                "eavesdrop" | "destination" => {}
+                _ if key.starts_with("arg") => {
+                    let index = key[3..]
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|index| *index < 64)
+                        .ok_or_else(invalid)?;
+                    parsed.args.push((index, value));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn matches(&self, msg: &Message) -> bool {
+        fn matches(expected: &Option<String>, actual: Option<&str>) -> bool {
+            expected.is_none() || expected.as_deref() == actual
+        }
+
+        // Rules name senders by unique or well-known names, messages only
+        // have the unique one. The bus already checked the well-known ones.
+        let sender = self
+            .sender
+            .as_ref()
+            .filter(|sender| sender.starts_with(':'));
+        let path = msg.path();
+        let path = path.as_deref();
+        (self.msg_type.is_none() || self.msg_type == Some(msg.msg_type()))
+            && (sender.is_none() || sender.map(|s| &**s) == msg.sender().as_deref())
+            && matches(&self.path, path)
+            && match &self.path_namespace {
+                Some(namespace) => matches!(path, Some(path) if in_namespace(path, namespace, '/')),
+                None => true,
+            }
+            && matches(&self.interface, msg.interface().as_deref())
+            && matches(&self.member, msg.member().as_deref())
+            && self
+                .args
+                .iter()
+                .all(|(index, value)| string_arg(msg, *index).as_deref() == Some(&**value))
+            && match &self.arg0namespace {
+                Some(namespace) => {
+                    matches!(string_arg(msg, 0), Some(arg) if in_namespace(&arg, namespace, '.'))
+                }
+                None => true,
+            }
+    }
+}
+
+/// Whether `name` is `namespace` or below it.
+fn in_namespace(name: &str, namespace: &str, separator: char) -> bool {
+    name == namespace
+        || (name.starts_with(namespace)
+            && (namespace.ends_with(separator) || name[namespace.len()..].starts_with(separator)))
+}
+
+/// The argument of `msg` at `index`, if it is a string.
+fn string_arg(msg: &Message, index: u8) -> Option<String> {
+    let mut args = msg.iter_init();
+    for _ in 0..index {
+        if !args.next() {
+            return None;
+        }
+    }
+    args.get::<&str>().map(str::to_string)
+}
+
+struct Subscription {
+    rule: String,
+    handler: Handler,
+    /// Whether the bus took the rule.
+    added: Sender<Result<()>>,
+}
+
+/// The thread reading messages off the connection to a bus.
+struct Listener {
+    subscriptions: Sender<Subscription>,
+    /// Wakes the thread up for new subscriptions.
+    wakeup: RawFd,
+}
+
+lazy_static! {
+    static ref LISTENERS: Mutex<HashMap<Bus, Listener>> = Mutex::new(HashMap::new());
+}
+
+/// Calls `handler` with every message on `bus` that matches `rule`, until it
+/// returns `false`. Fails if there is no connection to the bus or it does not
+/// take the rule.
+pub fn subscribe<F>(bus: Bus, rule: &str, handler: F) -> Result<()>
+where
+    F: FnMut(&Message) -> bool + Send + 'static,
+{
+    Rule::parse(rule)?;
+    let (added, result) = crossbeam_channel::bounded(1);
+    let mut subscription = Subscription {
+        rule: rule.to_string(),
+        handler: Box::new(handler),
+        added,
+    };
+    {
+        let mut listeners = LISTENERS
+            .lock()
+            .internal_error("dbus", "failed to acquire lock")?;
+        loop {
+            let listener = match listeners.remove(&bus) {
+                Some(listener) => listener,
+                None => listen(bus)?,
+            };
+            // A listener whose connection failed is replaced.
+            match listener.subscriptions.send(subscription) {
+                Ok(()) => {
+                    unistd::write(listener.wakeup, &[0])
+                        .internal_error("dbus", "failed to wake up listener")?;
+                    listeners.insert(bus, listener);
+                    break;
+                }
+                Err(failed) => {
+                    unistd::close(listener.wakeup).ok();
+                    subscription = failed.0;
+                }
+            }
+        }
+    }
+    result
+        .recv()
+        .internal_error("dbus", "connection to the bus failed")?
+}
+
+/// Asks for an update of the block with `id` on every message on `bus` that
+/// matches `rule`.
+pub fn request_updates(
+    bus: Bus,
+    rule: &str,
+    id: String,
+    update_request: Sender<Task>,
+) -> Result<()> {
+    subscribe(bus, rule, move |_| {
+        update_request
+            .send(Task {
+                id: id.clone(),
+                update_time: Instant::now(),
+            })
+            .is_ok()
+    })
+}
+
+/// Connects to `bus` and starts a thread reading messages off it.
+fn listen(bus: Bus) -> Result<Listener> {
+    let mut channel = Channel::get_private(bus.bus_type())
+        .internal_error("dbus", "failed to establish D-Bus connection")?;
+    channel.set_watch_enabled(true);
+    let fd = channel.watch().fd;
+    let (wakeup_fd, wakeup) = unistd::pipe().internal_error("dbus", "failed to create pipe")?;
+    let (subscriptions, new_subscriptions) = crossbeam_channel::unbounded();
+
+    thread::Builder::new()
+        .name(bus.name().into())
+        .spawn(move || {
+            if let Err(error) = dispatch(&channel, fd, wakeup_fd, &new_subscriptions) {
+                eprintln!("{}: {:?}", bus.name(), error);
+            }
+            // Up to now, subscriptions were still accepted.
+            for subscription in new_subscriptions.try_iter() {
+                subscription.added.send(Err(lost_connection())).ok();
+            }
+            unistd::close(wakeup_fd).ok();
+        })
+        .internal_error("dbus", "failed to spawn thread")?;
+
+    Ok(Listener {
+        subscriptions,
+        wakeup,
+    })
+}
+
+fn lost_connection() -> Error {
+    InternalError(
+        "dbus".to_owned(),
+        "lost the connection to the bus".to_owned(),
+        None,
+    )
+}
+
+/// Hands the messages of `channel` to the handlers subscribed to them, until
+/// the connection fails.
+fn dispatch(
+    channel: &Channel,
+    fd: RawFd,
+    wakeup: RawFd,
+    new_subscriptions: &Receiver<Subscription>,
+) -> Result<()> {
+    let mut handlers: Vec<(String, Rule, Handler)> = Vec::new();
+    loop {
+        channel
+            .read_write(Some(Duration::from_millis(0)))
+            .map_err(|_| lost_connection())?;
+        // Messages may have been read off the socket already, while waiting
+        // for the bus to add a rule, and do not wake `poll` up.
+        while let Some(msg) = channel.pop_message() {
+            let mut i = 0;
+            while i < handlers.len() {
+                let (_, rule, handler) = &mut handlers[i];
+                if rule.matches(&msg) && !handler(&msg) {
+                    let (rule, _, _) = handlers.remove(i);
+                    remove_match(channel, &rule);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        let mut fds = [
+            PollFd::new(fd, PollFlags::POLLIN),
+            PollFd::new(wakeup, PollFlags::POLLIN),
+        ];
+        poll(&mut fds, -1).internal_error("dbus", "failed to wait for messages")?;
+        let woken = fds[1].revents().unwrap_or_else(PollFlags::empty);
+        if woken.contains(PollFlags::POLLIN) {
+            unistd::read(wakeup, &mut [0; 64]).internal_error("dbus", "failed to read pipe")?;
+            for subscription in new_subscriptions.try_iter() {
+                let result = add_match(channel, &subscription.rule);
+                if result.is_ok() {
+                    handlers.push((
+                        subscription.rule.clone(),
+                        Rule::parse(&subscription.rule)?,
+                        subscription.handler,
+                    ));
+                }
+                subscription.added.send(result).ok();
+            }
+        }
+    }
+}
+
+fn bus_call(method: &str, rule: &str) -> Message {
+    Message::new_method_call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        method,
+    )
+    .unwrap()
+    .append1(rule)
+}
+
+fn add_match(channel: &Channel, rule: &str) -> Result<()> {
+    channel
+        .send_with_reply_and_block(bus_call("AddMatch", rule), TIMEOUT)
+        .internal_error("dbus", &format!("failed to add match rule {}", rule))?;
+    Ok(())
+}
+
+fn remove_match(channel: &Channel, rule: &str) {
+    // The reply does not matter, it is dropped with the other messages.
+    channel.send(bus_call("RemoveMatch", rule)).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(path: &str, interface: &str, member: &str) -> Message {
+        Message::new_signal(path, interface, member).unwrap()
+    }
+
+    fn owner_changed(name: &str) -> Message {
+        signal(
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "NameOwnerChanged",
+        )
+        .append3(name, "", ":1.42")
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(
+            Rule::parse(
+                "type='signal',\
+                 path='/org/freedesktop/locale1',\
+                 interface='org.freedesktop.DBus.Properties',\
+                 member='PropertiesChanged'"
+            )
+            .unwrap(),
+            Rule {
+                msg_type: Some(MessageType::Signal),
+                path: Some("/org/freedesktop/locale1".to_string()),
+                interface: Some("org.freedesktop.DBus.Properties".to_string()),
+                member: Some("PropertiesChanged".to_string()),
+                ..Rule::default()
+            }
+        );
+        assert_eq!(
+            Rule::parse("arg2 = 'x', eavesdrop='true'").unwrap(),
+            Rule {
+                args: vec![(2, "x".to_string())],
+                ..Rule::default()
+            }
+        );
+        assert_eq!(Rule::parse("").unwrap(), Rule::default());
+        assert!(Rule::parse("type='beacon'").is_err());
+        assert!(Rule::parse("member=Changed").is_err());
+        assert!(Rule::parse("member='Changed").is_err());
+        assert!(Rule::parse("arg64='x'").is_err());
+        assert!(Rule::parse("colour='red'").is_err());
+    }
+
+    #[test]
+    fn test_match_rule() {
+        let matches = |rule: &str, msg: &Message| Rule::parse(rule).unwrap().matches(msg);
+        let changed = signal(
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "PropertiesChanged",
+        );
+        assert!(matches("", &changed));
+        assert!(matches(
+            "type='signal',path='/org/freedesktop/NetworkManager',member='PropertiesChanged'",
+            &changed
+        ));
+        assert!(matches("path_namespace='/org/freedesktop'", &changed));
+        assert!(!matches("path_namespace='/org/free'", &changed));
+        assert!(!matches("type='method_call'", &changed));
+        assert!(!matches("interface='org.freedesktop.DBus'", &changed));
+        // Checked by the bus.
+        assert!(matches("sender='org.freedesktop.NetworkManager'", &changed));
+        assert!(!matches("sender=':1.7'", &changed));
+
+        let spotify = owner_changed("org.mpris.MediaPlayer2.spotify");
+        let mpris = "member='NameOwnerChanged',arg0namespace='org.mpris.MediaPlayer2'";
+        assert!(matches(mpris, &spotify));
+        assert!(matches("arg2=':1.42'", &spotify));
+        assert!(!matches("arg1=':1.42'", &spotify));
+        assert!(!matches("arg3=':1.42'", &spotify));
+        assert!(matches(mpris, &owner_changed("org.mpris.MediaPlayer2")));
+        assert!(!matches(
+            mpris,
+            &owner_changed("org.mpris.MediaPlayer2Extra")
+        ));
+        assert!(!matches(mpris, &changed));
+    }
+}
@@ -245,6 +245,16 @@ where
     Tz::from_str(&s).map(Some).map_err(de::Error::custom)
 }
 
+pub fn deserialize_timezones<'de, D>(deserializer: D) -> Result<Vec<Tz>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| Tz::from_str(s).map_err(de::Error::custom))
+        .collect()
+}
+
 pub fn deserialize_local_timestamp<'de, D>(deserializer: D) -> Result<DateTime<Local>, D::Error>
 where
     D: Deserializer<'de>,
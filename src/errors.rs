@@ -1,5 +1,10 @@
+use std::collections::HashMap;
 pub use std::error::Error as StdError;
 use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
 
 pub use self::Error::{BlockError, ConfigurationError, InternalError};
 
@@ -103,6 +108,17 @@ impl fmt::Debug for Error {
     }
 }
 
+impl Error {
+    /// The block or context the error happened in, and what went wrong.
+    pub fn parts(&self) -> (&str, &str) {
+        match *self {
+            BlockError(ref block, ref message) => (block, message),
+            ConfigurationError(ref message, _) => ("configuration", message),
+            InternalError(ref context, ref message, _) => (context, message),
+        }
+    }
+}
+
 impl StdError for Error {
     fn description(&self) -> &str {
         match *self {
@@ -125,3 +141,307 @@ where
         InternalError("unknown".to_string(), "send error".to_string(), None)
     }
 }
+
+/// How long identical errors are collapsed into one message, and how long a
+/// block exceeding its error budget is silenced for.
+const ERROR_WINDOW: Duration = Duration::from_secs(10);
+
+/// How many errors a block may report per window before it is silenced.
+const ERROR_BUDGET: usize = 20;
+
+struct Repeated {
+    name: String,
+    since: Instant,
+    count: usize,
+}
+
+struct BlockErrors {
+    name: String,
+    window_start: Instant,
+    count: usize,
+    silenced_until: Option<Instant>,
+}
+
+/// Deduplicates and rate limits errors reported by blocks outside of their
+/// `update`, typically from background threads that lost their connection and
+/// would otherwise flood stderr with the same message.
+pub struct ErrorLimiter {
+    window: Duration,
+    budget: usize,
+    repeated: HashMap<(String, String), Repeated>,
+    blocks: HashMap<String, BlockErrors>,
+}
+
+impl ErrorLimiter {
+    pub fn new(window: Duration, budget: usize) -> Self {
+        ErrorLimiter {
+            window,
+            budget,
+            repeated: HashMap::new(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Records an error of the block `id` (called `name` in messages) and
+    /// returns the lines that should be logged because of it.
+    pub fn report(&mut self, id: &str, name: &str, message: &str, now: Instant) -> Vec<String> {
+        let mut lines = self.flush(now);
+
+        let window = self.window;
+        let block = self
+            .blocks
+            .entry(id.to_string())
+            .or_insert_with(|| BlockErrors {
+                name: name.to_string(),
+                window_start: now,
+                count: 0,
+                silenced_until: None,
+            });
+        if now.duration_since(block.window_start) >= window {
+            block.window_start = now;
+            block.count = 0;
+        }
+        block.count += 1;
+        if block.silenced_until.is_some() {
+            return lines;
+        }
+        if block.count > self.budget {
+            block.silenced_until = Some(now + window);
+            lines.push(format!(
+                "Block '{}' is failing repeatedly, silencing it for {}s",
+                name,
+                window.as_secs()
+            ));
+            return lines;
+        }
+
+        let repeated = self
+            .repeated
+            .entry((id.to_string(), message.to_string()))
+            .or_insert(Repeated {
+                name: name.to_string(),
+                since: now,
+                count: 0,
+            });
+        repeated.count += 1;
+        if repeated.count == 1 {
+            lines.push(format!("Error in block '{}': {}", name, message));
+        }
+        lines
+    }
+
+    /// Ends windows that have expired, returning summaries of the errors that
+    /// were collapsed during them.
+    pub fn flush(&mut self, now: Instant) -> Vec<String> {
+        let window = self.window;
+        let mut lines = Vec::new();
+        self.repeated.retain(|(_, message), repeated| {
+            if now.duration_since(repeated.since) < window {
+                return true;
+            }
+            if repeated.count > 1 {
+                lines.push(format!(
+                    "Error in block '{}': {} (repeated {} times)",
+                    repeated.name, message, repeated.count
+                ));
+            }
+            false
+        });
+        lines.sort();
+        for block in self.blocks.values_mut() {
+            if block.silenced_until.is_some_and(|until| now >= until) {
+                block.silenced_until = None;
+                block.window_start = now;
+                block.count = 0;
+            }
+        }
+        lines
+    }
+
+    /// Drops what is known about the blocks with the given ids.
+    pub fn forget(&mut self, ids: &[String]) {
+        self.blocks.retain(|id, _| !ids.contains(id));
+        self.repeated.retain(|(id, _), _| !ids.contains(id));
+    }
+
+    /// The name of the block `id` if it is currently silenced.
+    pub fn silenced(&self, id: &str) -> Option<&str> {
+        self.blocks
+            .get(id)
+            .filter(|block| block.silenced_until.is_some())
+            .map(|block| block.name.as_str())
+    }
+}
+
+lazy_static! {
+    static ref ERROR_LIMITER: Mutex<ErrorLimiter> =
+        Mutex::new(ErrorLimiter::new(ERROR_WINDOW, ERROR_BUDGET));
+}
+
+/// Logs an error of the block `id` to stderr, unless the same error was logged
+/// recently or the block has been reporting too many errors.
+pub fn log_block_error(id: &str, name: &str, message: &str) {
+    if let Ok(mut limiter) = ERROR_LIMITER.lock() {
+        for line in limiter.report(id, name, message, Instant::now()) {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+/// How long an error may be on the bar, in characters.
+const MAX_ERROR_LENGTH: usize = 40;
+
+lazy_static! {
+    /// What the bar shows for blocks whose last update failed, by block id.
+    static ref FAILED_UPDATES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+/// `error` shortened to fit on the bar, e.g. `sound: connection lost`.
+fn short_error(error: &Error) -> String {
+    let (name, message) = error.parts();
+    let message = message.lines().next().unwrap_or_default();
+    if message.chars().count() > MAX_ERROR_LENGTH {
+        let shortened: String = message.chars().take(MAX_ERROR_LENGTH - 1).collect();
+        format!("{}: {}…", name, shortened.trim_end())
+    } else {
+        format!("{}: {}", name, message)
+    }
+}
+
+/// Records the outcome of an update of the block `id`. Until it updates
+/// successfully again, a failed block shows its error instead.
+pub fn set_update_result<T>(id: &str, result: &Result<T>) {
+    if let Ok(mut failed) = FAILED_UPDATES.lock() {
+        match result {
+            Ok(_) => failed.remove(id),
+            Err(error) => failed.insert(id.to_string(), short_error(error)),
+        };
+    }
+}
+
+/// Forgets the errors of the blocks with the given ids, once they have been
+/// replaced.
+pub fn forget_blocks(ids: &[String]) {
+    if let Ok(mut failed) = FAILED_UPDATES.lock() {
+        failed.retain(|id, _| !ids.contains(id));
+    }
+    if let Ok(mut limiter) = ERROR_LIMITER.lock() {
+        limiter.forget(ids);
+    }
+}
+
+/// What the bar shows instead of the block `id`, if it is silenced or its
+/// last update failed.
+pub fn block_failure(id: &str) -> Option<String> {
+    if let Some(name) = silenced_block(id) {
+        return Some(format!("{} is failing repeatedly", name));
+    }
+    FAILED_UPDATES.lock().ok()?.get(id).cloned()
+}
+
+/// The name of the block `id` if it has been silenced for reporting too many
+/// errors, in which case the bar shows that instead of the block.
+pub fn silenced_block(id: &str) -> Option<String> {
+    let mut limiter = ERROR_LIMITER.lock().ok()?;
+    for line in limiter.flush(Instant::now()) {
+        eprintln!("{}", line);
+    }
+    limiter.silenced(id).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_errors_are_collapsed() {
+        let mut limiter = ErrorLimiter::new(Duration::from_secs(10), 1000);
+        let start = Instant::now();
+        let mut lines = Vec::new();
+        for i in 0..500 {
+            let now = start + Duration::from_millis(i * 10);
+            lines.extend(limiter.report("1", "sound", "connection lost", now));
+        }
+        assert_eq!(lines, vec!["Error in block 'sound': connection lost"]);
+
+        let lines = limiter.flush(start + Duration::from_secs(11));
+        assert_eq!(
+            lines,
+            vec!["Error in block 'sound': connection lost (repeated 500 times)"]
+        );
+        assert!(limiter.flush(start + Duration::from_secs(30)).is_empty());
+    }
+
+    #[test]
+    fn test_short_error() {
+        assert_eq!(
+            short_error(&BlockError("sound".into(), "connection lost".into())),
+            "sound: connection lost"
+        );
+        assert_eq!(
+            short_error(&BlockError(
+                "net".into(),
+                "failed to read /sys/class/net/wlp3s0/statistics/rx_bytes".into()
+            )),
+            "net: failed to read /sys/class/net/wlp3s0/st…"
+        );
+        assert_eq!(
+            short_error(&InternalError(
+                "scheduler".into(),
+                "could not get required block\nsecond line".into(),
+                None
+            )),
+            "scheduler: could not get required block"
+        );
+    }
+
+    #[test]
+    fn test_distinct_errors_are_all_logged() {
+        let mut limiter = ErrorLimiter::new(Duration::from_secs(10), 1000);
+        let now = Instant::now();
+        assert_eq!(limiter.report("1", "sound", "a", now).len(), 1);
+        assert_eq!(limiter.report("1", "sound", "b", now).len(), 1);
+        assert_eq!(limiter.report("2", "music", "a", now).len(), 1);
+    }
+
+    #[test]
+    fn test_error_storm_silences_block() {
+        let mut limiter = ErrorLimiter::new(Duration::from_secs(10), 5);
+        let start = Instant::now();
+        let mut lines = Vec::new();
+        for i in 0..1000 {
+            let now = start + Duration::from_millis(i);
+            lines.extend(limiter.report("1", "music", &format!("error {}", i), now));
+        }
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[5],
+            "Block 'music' is failing repeatedly, silencing it for 10s"
+        );
+        assert_eq!(limiter.silenced("1"), Some("music"));
+        assert_eq!(limiter.silenced("2"), None);
+
+        // The block gets a fresh budget once the window is over.
+        limiter.flush(start + Duration::from_secs(12));
+        assert_eq!(limiter.silenced("1"), None);
+        let lines = limiter.report("1", "music", "error", start + Duration::from_secs(12));
+        assert_eq!(lines, vec!["Error in block 'music': error"]);
+    }
+
+    #[test]
+    fn test_forgotten_blocks() {
+        let mut limiter = ErrorLimiter::new(Duration::from_secs(10), 1);
+        let now = Instant::now();
+        limiter.report("1", "music", "a", now);
+        limiter.report("1", "music", "b", now);
+        limiter.report("2", "sound", "a", now);
+        limiter.report("2", "sound", "b", now);
+        assert_eq!(limiter.silenced("1"), Some("music"));
+
+        // Blocks removed by a reload leave nothing behind.
+        limiter.forget(&["1".to_string()]);
+        assert_eq!(limiter.silenced("1"), None);
+        assert_eq!(limiter.silenced("2"), Some("sound"));
+        assert!(limiter.flush(now + Duration::from_secs(11)).is_empty());
+    }
+}
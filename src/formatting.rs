@@ -0,0 +1,670 @@
+//! Rendering of placeholder values.
+//!
+//! Blocks hand numbers to the format template as numbers rather than strings,
+//! so that the template can apply the configured decimal and group separators
+//! and the flags of the placeholder, as in `{used:08,.1}`, as well as the unit
+//! prefix, as in `{speed_down;M}` or `{speed_down;_Mi*b}`. Percentages can
+//! also be rendered as bars, as in `{bar:20}`.
+
+use std::fmt::Display;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use num_traits::ToPrimitive;
+
+/// The separators used when rendering numbers and the characters of bars, set
+/// by the top level `decimal_separator`, `group_separator` and `bar_chars`
+/// options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    pub decimal_separator: String,
+    pub group_separator: String,
+    /// From an empty to a full cell of a bar, with partly filled cells in
+    /// between.
+    pub bar_chars: String,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            decimal_separator: ".".to_string(),
+            group_separator: ",".to_string(),
+            bar_chars: "▁▂▃▄▅▆▇█".to_string(),
+        }
+    }
+}
+
+/// The length of bars without a width flag.
+const BAR_LENGTH: usize = 10;
+
+lazy_static! {
+    static ref NUMBER_FORMAT: RwLock<NumberFormat> = RwLock::new(NumberFormat::default());
+}
+
+/// Sets the separators and bar characters used by all format templates.
+pub fn set_number_format(format: NumberFormat) {
+    if let Ok(mut current) = NUMBER_FORMAT.write() {
+        *current = format;
+    }
+}
+
+fn number_format() -> NumberFormat {
+    NUMBER_FORMAT
+        .read()
+        .map(|format| format.clone())
+        .unwrap_or_default()
+}
+
+/// A prefix for numbers with a unit, `{key;K}`, which is an SI prefix unless
+/// the placeholder asks for binary ones.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Prefix {
+    One,
+    Kilo,
+    Mega,
+    Giga,
+    Tera,
+}
+
+impl Prefix {
+    const ALL: [Prefix; 5] = [
+        Prefix::One,
+        Prefix::Kilo,
+        Prefix::Mega,
+        Prefix::Giga,
+        Prefix::Tera,
+    ];
+
+    /// Parses the prefix after the `;` of a placeholder. Returns `None` if it
+    /// is not one of `K`, `M`, `G` or `T`.
+    pub fn parse(prefix: &str) -> Option<Prefix> {
+        Prefix::ALL[1..]
+            .iter()
+            .copied()
+            .find(|candidate| candidate.symbol() == prefix)
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Prefix::One => "",
+            Prefix::Kilo => "K",
+            Prefix::Mega => "M",
+            Prefix::Giga => "G",
+            Prefix::Tera => "T",
+        }
+    }
+
+    /// The symbol of the binary prefix, e.g. `Ki` for 1024.
+    fn binary_symbol(self) -> String {
+        match self {
+            Prefix::One => String::new(),
+            prefix => format!("{}i", prefix.symbol()),
+        }
+    }
+
+    fn factor(self, binary: bool) -> f64 {
+        let base: f64 = if binary { 1024. } else { 1000. };
+        base.powi(self as i32)
+    }
+
+    /// The largest prefix `value` is at least one of, but no smaller than
+    /// `self`.
+    fn fitting(self, value: f64, binary: bool) -> Prefix {
+        Prefix::ALL
+            .iter()
+            .copied()
+            .filter(|prefix| *prefix >= self)
+            .take_while(|prefix| *prefix == self || value.abs() >= prefix.factor(binary))
+            .last()
+            .unwrap_or(self)
+    }
+}
+
+/// The flags of a placeholder, `{key:[0][width][,][.precision]}`:
+///
+/// - `0` pads numbers with zeros instead of spaces,
+/// - `width` is the minimum width; numbers are aligned right, text left,
+/// - `,` groups the digits of numbers by thousands,
+/// - `precision` is the number of digits after the decimal separator.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Spec {
+    pub zero_pad: bool,
+    pub width: Option<usize>,
+    pub grouping: bool,
+    pub precision: Option<usize>,
+    /// A unit rendered right after the number, such as `%`. Only blocks set
+    /// this, placeholders have no flag for it.
+    pub suffix: &'static str,
+    /// The smallest prefix to scale the number to. Larger numbers get larger
+    /// prefixes, so that `1500` with `Kilo` and a precision of 1 renders as
+    /// `1.5K` and `2500000` as `2.5M`. Set by `{key;K}` or by the block.
+    pub prefix: Option<Prefix>,
+    /// Whether prefixes are powers of 1024 rather than 1000, `{key;Ki}`.
+    pub binary: bool,
+    /// Whether the number keeps to `prefix` however large it is, `{key;_K}`.
+    pub fixed: bool,
+    /// Whether a number of bytes, with a suffix starting with `B`, is shown in
+    /// bits, `{key;K*b}`, or a number of bits in bytes, `{key;K*B}`.
+    pub bits: Option<bool>,
+}
+
+impl Spec {
+    /// Parses the flags after the `:` of a placeholder. Returns `None` if
+    /// they are not valid.
+    pub fn parse(flags: &str) -> Option<Spec> {
+        let mut spec = Spec::default();
+        let mut rest = flags;
+        if let Some(stripped) = rest.strip_prefix('0') {
+            spec.zero_pad = true;
+            rest = stripped;
+        }
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits > 0 {
+            spec.width = Some(rest[..digits].parse().ok()?);
+            rest = &rest[digits..];
+        }
+        if let Some(stripped) = rest.strip_prefix(',') {
+            spec.grouping = true;
+            rest = stripped;
+        }
+        if let Some(precision) = rest.strip_prefix('.') {
+            spec.precision = Some(precision.parse().ok()?);
+            rest = "";
+        }
+        if rest.is_empty() {
+            Some(spec)
+        } else {
+            None
+        }
+    }
+
+    /// Parses the unit after the `;` of a placeholder,
+    /// `[_][K|M|G|T][i][*b|*B]`, into the flags of `self`. Returns `None` if
+    /// it is not valid.
+    pub fn with_unit(self, unit: &str) -> Option<Spec> {
+        let mut spec = self;
+        let mut rest = unit;
+        if let Some(stripped) = rest.strip_prefix('_') {
+            spec.fixed = true;
+            rest = stripped;
+        }
+        if let Some(prefix) = rest.get(..1).and_then(Prefix::parse) {
+            spec.prefix = Some(prefix);
+            rest = &rest[1..];
+        } else if spec.fixed {
+            // Without a prefix, `_` keeps to the plain unit.
+            spec.prefix = Some(Prefix::One);
+        }
+        if let Some(stripped) = rest.strip_prefix('i') {
+            spec.binary = true;
+            rest = stripped;
+        }
+        match rest {
+            "*b" => spec.bits = Some(true),
+            "*B" => spec.bits = Some(false),
+            _ => (),
+        }
+        if (rest.is_empty() || spec.bits.is_some()) && !unit.is_empty() {
+            Some(spec)
+        } else {
+            None
+        }
+    }
+
+    /// The flags of `self`, falling back on `defaults` for those not set.
+    fn or(self, defaults: Spec) -> Spec {
+        Spec {
+            zero_pad: self.zero_pad || defaults.zero_pad,
+            width: self.width.or(defaults.width),
+            grouping: self.grouping || defaults.grouping,
+            precision: self.precision.or(defaults.precision),
+            suffix: defaults.suffix,
+            prefix: self.prefix.or(defaults.prefix),
+            binary: self.binary || defaults.binary,
+            fixed: if self.prefix.is_some() {
+                self.fixed
+            } else {
+                defaults.fixed
+            },
+            bits: self.bits.or(defaults.bits),
+        }
+    }
+}
+
+/// A typed placeholder value. Numbers carry the block's default flags, which
+/// the flags in the format string override.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Integer(i64, Spec),
+    Float(f64, Spec),
+    /// A percentage shown as a bar, as long as the width flag.
+    Bar(f64),
+}
+
+impl Value {
+    pub fn integer<T: ToPrimitive>(value: T) -> Value {
+        Value::Integer(value.to_i64().unwrap_or(i64::MAX), Spec::default())
+    }
+
+    pub fn float<T: ToPrimitive>(value: T, precision: usize) -> Value {
+        Value::Float(
+            value.to_f64().unwrap_or(f64::NAN),
+            Spec {
+                precision: Some(precision),
+                ..Spec::default()
+            },
+        )
+    }
+
+    pub fn bar<T: ToPrimitive>(percent: T) -> Value {
+        Value::Bar(percent.to_f64().unwrap_or(0.0))
+    }
+
+    /// The number with other default flags.
+    fn with_spec(self, change: impl FnOnce(Spec) -> Spec) -> Value {
+        match self {
+            Value::Integer(value, spec) => Value::Integer(value, change(spec)),
+            Value::Float(value, spec) => Value::Float(value, change(spec)),
+            text => text,
+        }
+    }
+
+    /// Renders `suffix` right after the number.
+    pub fn with_suffix(self, suffix: &'static str) -> Value {
+        self.with_spec(|spec| Spec { suffix, ..spec })
+    }
+
+    /// Scales the number to `prefix` or a larger one, unless the format string
+    /// asks for another smallest prefix. The number must be in the base unit.
+    pub fn scaled(self, prefix: Prefix) -> Value {
+        self.with_spec(|spec| Spec {
+            prefix: Some(prefix),
+            ..spec
+        })
+    }
+
+    /// Uses binary prefixes unless the format string says otherwise.
+    pub fn binary(self, binary: bool) -> Value {
+        self.with_spec(|spec| Spec { binary, ..spec })
+    }
+
+    /// Keeps the number to its prefix unless the format string says otherwise.
+    pub fn fixed(self, fixed: bool) -> Value {
+        self.with_spec(|spec| Spec { fixed, ..spec })
+    }
+
+    /// Pads the number with zeros to `width` unless the format string says
+    /// otherwise.
+    pub fn zero_padded(self, width: usize) -> Value {
+        self.with_spec(|spec| Spec {
+            zero_pad: true,
+            width: Some(width),
+            ..spec
+        })
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Text(text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Value {
+        Value::Text(text.to_string())
+    }
+}
+
+/// Anything that can be used as the value of a placeholder.
+pub trait Placeholder {
+    fn render(&self, spec: Spec, numbers: &NumberFormat) -> String;
+}
+
+impl Placeholder for Value {
+    fn render(&self, spec: Spec, numbers: &NumberFormat) -> String {
+        match *self {
+            Value::Text(ref text) => pad_text(text, spec.width),
+            Value::Integer(value, defaults) => {
+                let spec = spec.or(defaults);
+                match (spec.precision, spec.prefix) {
+                    (None, None) => format_integer(value, spec, numbers),
+                    _ => format_float(value as f64, spec, numbers),
+                }
+            }
+            Value::Float(value, defaults) => format_float(value, spec.or(defaults), numbers),
+            Value::Bar(percent) => format_bar(percent, spec.width.unwrap_or(BAR_LENGTH), numbers),
+        }
+    }
+}
+
+/// Values blocks have formatted themselves are rendered as text.
+impl<T: Display> Placeholder for T {
+    fn render(&self, spec: Spec, _: &NumberFormat) -> String {
+        pad_text(&self.to_string(), spec.width)
+    }
+}
+
+/// Renders `value` with the configured separators.
+pub fn render<T: Placeholder + ?Sized>(value: &T, spec: Spec) -> String {
+    value.render(spec, &number_format())
+}
+
+fn pad_text(text: &str, width: Option<usize>) -> String {
+    let width = width.unwrap_or(0);
+    format!("{:<width$}", text, width = width)
+}
+
+/// Every cell of the bar stands for an equal share of 100%. Cells below
+/// `percent` are full, those above it empty and the one it falls into is
+/// filled in part.
+fn format_bar(percent: f64, length: usize, numbers: &NumberFormat) -> String {
+    let chars: Vec<char> = numbers.bar_chars.chars().collect();
+    if chars.is_empty() {
+        return String::new();
+    }
+    let filled = percent.clamp(0.0, 100.0) / 100.0 * length as f64;
+    (0..length)
+        .map(|cell| {
+            let fill = (filled - cell as f64).clamp(0.0, 1.0);
+            chars[((fill * chars.len() as f64) as usize).min(chars.len() - 1)]
+        })
+        .collect()
+}
+
+fn format_integer(value: i64, spec: Spec, numbers: &NumberFormat) -> String {
+    let digits = value.unsigned_abs().to_string();
+    assemble(value < 0, &digits, None, spec.suffix, spec, numbers)
+}
+
+fn format_float(value: f64, spec: Spec, numbers: &NumberFormat) -> String {
+    if !value.is_finite() {
+        return pad_text(&value.to_string(), spec.width);
+    }
+    let (value, suffix) = match (spec.bits, spec.suffix.get(..1)) {
+        (Some(true), Some("B")) => (value * 8., format!("b{}", &spec.suffix[1..])),
+        (Some(false), Some("b")) => (value / 8., format!("B{}", &spec.suffix[1..])),
+        _ => (value, spec.suffix.to_string()),
+    };
+    let (value, unit) = match spec.prefix {
+        Some(prefix) => {
+            let prefix = if spec.fixed {
+                prefix
+            } else {
+                prefix.fitting(value, spec.binary)
+            };
+            let symbol = if spec.binary {
+                prefix.binary_symbol()
+            } else {
+                prefix.symbol().to_string()
+            };
+            (
+                value / prefix.factor(spec.binary),
+                format!("{}{}", symbol, suffix),
+            )
+        }
+        None => (value, suffix),
+    };
+    let precision = spec.precision.unwrap_or(0);
+    let formatted = format!("{:.*}", precision, value.abs());
+    let (digits, fraction) = match formatted.find('.') {
+        Some(dot) => (&formatted[..dot], Some(&formatted[dot + 1..])),
+        None => (formatted.as_str(), None),
+    };
+    // Do not render e.g. -0.001 as "-0.00".
+    let negative = value < 0. && formatted.chars().any(|c| c.is_ascii_digit() && c != '0');
+    assemble(negative, digits, fraction, &unit, spec, numbers)
+}
+
+fn group(digits: &str, separator: &str) -> String {
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+fn assemble(
+    negative: bool,
+    digits: &str,
+    fraction: Option<&str>,
+    unit: &str,
+    spec: Spec,
+    numbers: &NumberFormat,
+) -> String {
+    let render = |digits: &str| {
+        let mut rendered = String::new();
+        if negative {
+            rendered.push('-');
+        }
+        if spec.grouping {
+            rendered.push_str(&group(digits, &numbers.group_separator));
+        } else {
+            rendered.push_str(digits);
+        }
+        if let Some(fraction) = fraction {
+            rendered.push_str(&numbers.decimal_separator);
+            rendered.push_str(fraction);
+        }
+        rendered.push_str(unit);
+        rendered
+    };
+
+    let width = spec.width.unwrap_or(0);
+    let mut digits = digits.to_string();
+    let mut rendered = render(&digits);
+    if spec.zero_pad {
+        // Padding zeros are grouped like any other digit.
+        while rendered.chars().count() < width {
+            digits.insert(0, '0');
+            rendered = render(&digits);
+        }
+        rendered
+    } else {
+        format!("{:>width$}", rendered, width = width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn european() -> NumberFormat {
+        NumberFormat {
+            decimal_separator: ",".to_string(),
+            group_separator: ".".to_string(),
+            ..NumberFormat::default()
+        }
+    }
+
+    fn render_with(value: Value, flags: &str, numbers: &NumberFormat) -> String {
+        value.render(Spec::parse(flags).unwrap(), numbers)
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(Spec::parse(""), Some(Spec::default()));
+        assert_eq!(
+            Spec::parse("08,.1"),
+            Some(Spec {
+                zero_pad: true,
+                width: Some(8),
+                grouping: true,
+                precision: Some(1),
+                ..Spec::default()
+            })
+        );
+        assert_eq!(
+            Spec::parse(","),
+            Some(Spec {
+                grouping: true,
+                ..Spec::default()
+            })
+        );
+        assert_eq!(Spec::parse("10").unwrap().width, Some(10));
+        assert_eq!(Spec::parse(".3").unwrap().precision, Some(3));
+        assert_eq!(Spec::parse("."), None);
+        assert_eq!(Spec::parse(",,"), None);
+        assert_eq!(Spec::parse(".1,"), None);
+    }
+
+    #[test]
+    fn test_separators() {
+        let numbers = european();
+        assert_eq!(render_with(Value::float(1.23456, 2), "", &numbers), "1,23");
+        assert_eq!(
+            render_with(Value::float(1234567.891, 1), ",", &numbers),
+            "1.234.567,9"
+        );
+        // Grouping only on request.
+        assert_eq!(
+            render_with(Value::integer(1234567), "", &numbers),
+            "1234567"
+        );
+        assert_eq!(
+            render_with(Value::integer(1234567), ",", &NumberFormat::default()),
+            "1,234,567"
+        );
+        // The format string overrides the block's precision.
+        assert_eq!(render_with(Value::float(2.5, 2), ".0", &numbers), "2");
+        assert_eq!(render_with(Value::integer(7), ".1", &numbers), "7,0");
+    }
+
+    #[test]
+    fn test_group_boundaries() {
+        let numbers = NumberFormat::default();
+        let grouped = |value: i64| render_with(Value::integer(value), ",", &numbers);
+        assert_eq!(grouped(0), "0");
+        assert_eq!(grouped(999), "999");
+        assert_eq!(grouped(1000), "1,000");
+        assert_eq!(grouped(100_000), "100,000");
+        assert_eq!(grouped(-100_000), "-100,000");
+        assert_eq!(grouped(-1_000_000), "-1,000,000");
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        let numbers = european();
+        assert_eq!(render_with(Value::float(-0.001, 2), "", &numbers), "0,00");
+        assert_eq!(render_with(Value::float(-12.5, 1), "", &numbers), "-12,5");
+        assert_eq!(render_with(Value::integer(-42), "6", &numbers), "   -42");
+        assert_eq!(render_with(Value::integer(-42), "06", &numbers), "-00042");
+    }
+
+    #[test]
+    fn test_padding() {
+        let numbers = NumberFormat::default();
+        assert_eq!(
+            render_with(Value::integer(1234), "08,", &numbers),
+            "0,001,234"
+        );
+        assert_eq!(
+            render_with(Value::integer(1234), "07,", &numbers),
+            "001,234"
+        );
+        assert_eq!(
+            render_with(Value::integer(-1234), "08,", &numbers),
+            "-001,234"
+        );
+        assert_eq!(render_with(Value::float(3.5, 1), "06", &numbers), "0003.5");
+        assert_eq!(
+            render_with(Value::integer(5).zero_padded(2), "", &numbers),
+            "05"
+        );
+        assert_eq!(
+            render_with(Value::integer(5).zero_padded(2), "3", &numbers),
+            "005"
+        );
+        assert_eq!(render_with(Value::from("ab"), "4", &numbers), "ab  ");
+        assert_eq!(
+            render_with(Value::float(7.26, 1).with_suffix("%"), "06", &numbers),
+            "007.3%"
+        );
+        assert_eq!(render_with(Value::integer(123456), "3", &numbers), "123456");
+    }
+
+    #[test]
+    fn test_prefixes() {
+        let numbers = NumberFormat::default();
+        let spec = |prefix: &str| Spec {
+            prefix: Prefix::parse(prefix),
+            ..Spec::default()
+        };
+        let speed = |bytes: u64| Value::float(bytes, 1).with_suffix("B/s");
+        assert_eq!(speed(1500).render(spec("K"), &numbers), "1.5KB/s");
+        assert_eq!(speed(999).render(spec("K"), &numbers), "1.0KB/s");
+        assert_eq!(speed(2_500_000).render(spec("K"), &numbers), "2.5MB/s");
+        assert_eq!(speed(2_500_000).render(spec("G"), &numbers), "0.0GB/s");
+        // Blocks set the smallest prefix the format string does not.
+        let scaled = speed(1500).scaled(Prefix::One);
+        assert_eq!(scaled.render(Spec::default(), &numbers), "1.5KB/s");
+        assert_eq!(scaled.render(spec("M"), &numbers), "0.0MB/s");
+        assert_eq!(
+            speed(999)
+                .scaled(Prefix::One)
+                .render(Spec::default(), &numbers),
+            "999.0B/s"
+        );
+        assert_eq!(Value::integer(1234).render(spec("K"), &numbers), "1K");
+        assert_eq!(Value::from("up").render(spec("K"), &numbers), "up");
+        assert_eq!(Prefix::parse("k"), None);
+        assert_eq!(Prefix::parse(""), None);
+    }
+
+    #[test]
+    fn test_units() {
+        let numbers = NumberFormat::default();
+        let unit = |unit: &str| Spec::default().with_unit(unit).unwrap();
+        let speed = |bytes: u64| {
+            Value::float(bytes, 1)
+                .with_suffix("B/s")
+                .scaled(Prefix::Kilo)
+        };
+        assert_eq!(speed(2048).render(unit("Ki"), &numbers), "2.0KiB/s");
+        assert_eq!(
+            speed(3 * 1024 * 1024).render(unit("Ki"), &numbers),
+            "3.0MiB/s"
+        );
+        assert_eq!(speed(2_500_000).render(unit("_K"), &numbers), "2500.0KB/s");
+        assert_eq!(speed(1500).render(unit("_"), &numbers), "1500.0B/s");
+        assert_eq!(speed(1500).render(unit("K*b"), &numbers), "12.0Kb/s");
+        assert_eq!(speed(1500).render(unit("*b"), &numbers), "12.0Kb/s");
+        assert_eq!(speed(2048).render(unit("_Ki*b"), &numbers), "16.0Kib/s");
+        // Bits back to bytes, and a unit that is neither.
+        let bits = Value::float(16000, 1)
+            .with_suffix("b/s")
+            .scaled(Prefix::One);
+        assert_eq!(bits.render(unit("*B"), &numbers), "2.0KB/s");
+        assert_eq!(Value::float(1500, 1).render(unit("K*b"), &numbers), "1.5K");
+        // The block's defaults, which the format string overrides.
+        let iec = speed(2048).binary(true).fixed(true);
+        assert_eq!(iec.render(Spec::default(), &numbers), "2.0KiB/s");
+        assert_eq!(iec.render(unit("M"), &numbers), "0.0MiB/s");
+        assert_eq!(Spec::default().with_unit(""), None);
+        assert_eq!(Spec::default().with_unit("Kx"), None);
+        assert_eq!(Spec::default().with_unit("K*"), None);
+        assert_eq!(Spec::default().with_unit("iK"), None);
+    }
+
+    #[test]
+    fn test_bars() {
+        let numbers = NumberFormat::default();
+        let bar = |percent: f64, flags: &str| render_with(Value::bar(percent), flags, &numbers);
+        assert_eq!(bar(0.0, ""), "▁▁▁▁▁▁▁▁▁▁");
+        assert_eq!(bar(100.0, ""), "██████████");
+        assert_eq!(bar(45.0, ""), "████▅▁▁▁▁▁");
+        assert_eq!(bar(60.0, "4"), "██▄▁");
+        assert_eq!(bar(150.0, "2"), "██");
+        assert_eq!(bar(-5.0, "2"), "▁▁");
+        let blocks = NumberFormat {
+            bar_chars: "░█".to_string(),
+            ..NumberFormat::default()
+        };
+        assert_eq!(render_with(Value::bar(60), "5", &blocks), "███░░");
+        assert_eq!(render_with(Value::bar(70), "5", &blocks), "████░");
+    }
+}
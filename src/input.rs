@@ -1,11 +1,14 @@
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io;
 use std::option::Option;
+use std::str::FromStr;
 use std::string::*;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use crossbeam_channel::Sender;
-use serde::{de, Deserializer};
+use serde::de::{self, Deserialize, Deserializer};
 use serde_derive::Deserialize;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -20,6 +23,63 @@ pub enum MouseButton {
     Unknown,
 }
 
+impl FromStr for MouseButton {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "left" => MouseButton::Left,
+            "middle" => MouseButton::Middle,
+            "right" => MouseButton::Right,
+            "wheel_up" | "up" => MouseButton::WheelUp,
+            "wheel_down" | "down" => MouseButton::WheelDown,
+            "forward" => MouseButton::Forward,
+            "back" => MouseButton::Back,
+            other => return Err(format!("unknown button '{}'", other)),
+        })
+    }
+}
+
+/// A modifier key held during a click, as named by i3bar. `Mod1` usually is
+/// Alt and `Mod4` the Super key.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Mod1,
+    Mod2,
+    Mod3,
+    Mod4,
+    Mod5,
+    Lock,
+    #[serde(other)]
+    Unknown,
+}
+
+impl Modifier {
+    /// Whether the modifier is a lock state rather than a key being held:
+    /// Caps Lock, and Num Lock on `Mod2`.
+    fn is_lock(self) -> bool {
+        matches!(self, Modifier::Lock | Modifier::Mod2)
+    }
+}
+
+impl FromStr for Modifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "shift" => Modifier::Shift,
+            "ctrl" | "control" => Modifier::Control,
+            "alt" | "mod1" => Modifier::Mod1,
+            "mod3" => Modifier::Mod3,
+            "super" | "mod4" => Modifier::Mod4,
+            "mod5" => Modifier::Mod5,
+            other => return Err(format!("unknown modifier '{}'", other)),
+        })
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct I3BarEvent {
     pub name: Option<String>,
@@ -29,6 +89,16 @@ pub struct I3BarEvent {
 
     #[serde(deserialize_with = "deserialize_mousebutton")]
     pub button: MouseButton,
+
+    /// Sent by i3 4.19 and later.
+    #[serde(default)]
+    pub modifiers: Vec<Modifier>,
+
+    /// Whether this is the second click of a double click, see
+    /// [`DoubleClicks`](struct.DoubleClicks.html). The first click has been
+    /// handed out as a single click already.
+    #[serde(skip)]
+    pub double_click: bool,
 }
 
 impl I3BarEvent {
@@ -38,6 +108,164 @@ impl I3BarEvent {
             _ => false,
         }
     }
+
+    /// The modifier keys held, without lock states, sorted.
+    pub fn held_modifiers(&self) -> Vec<Modifier> {
+        let mut modifiers: Vec<Modifier> = self
+            .modifiers
+            .iter()
+            .cloned()
+            .filter(|modifier| !modifier.is_lock())
+            .collect();
+        modifiers.sort();
+        modifiers.dedup();
+        modifiers
+    }
+
+    /// Whether this is a click of `button` without any modifier keys.
+    pub fn is_plain(&self, button: MouseButton) -> bool {
+        self.button == button && self.held_modifiers().is_empty()
+    }
+}
+
+/// Marks a click as a double click if it follows a click of the same button
+/// on the same widget with the same modifiers within `interval`.
+pub struct DoubleClicks {
+    interval: Duration,
+    last: Option<(I3BarEvent, Instant)>,
+}
+
+impl DoubleClicks {
+    pub fn new(interval: Duration) -> Self {
+        DoubleClicks {
+            interval,
+            last: None,
+        }
+    }
+
+    pub fn detect(&mut self, event: &mut I3BarEvent) {
+        self.detect_at(event, Instant::now())
+    }
+
+    /// Like `detect`, for a click at `now`.
+    pub(crate) fn detect_at(&mut self, event: &mut I3BarEvent, now: Instant) {
+        event.double_click = match self.last.take() {
+            Some((ref last, time)) => {
+                now.duration_since(time) <= self.interval
+                    && last.name == event.name
+                    && last.instance == event.instance
+                    && last.button == event.button
+                    && last.held_modifiers() == event.held_modifiers()
+            }
+            None => false,
+        };
+        // A third click starts the next double click.
+        if !event.double_click {
+            self.last = Some((event.clone(), now));
+        }
+    }
+}
+
+/// A button, optionally with modifier keys and as a double click, written as
+/// e.g. `right`, `ctrl+wheel_up` or `shift+double_left`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Click {
+    pub button: MouseButton,
+    pub modifiers: Vec<Modifier>,
+    pub double_click: bool,
+}
+
+impl FromStr for Click {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut keys: Vec<&str> = s.split('+').map(str::trim).collect();
+        let button = keys.pop().unwrap_or_default();
+        let (button, double_click) = match button.strip_prefix("double_") {
+            Some(button) => (button, true),
+            None => (button, false),
+        };
+        let mut modifiers = keys
+            .into_iter()
+            .map(Modifier::from_str)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        modifiers.sort();
+        modifiers.dedup();
+        Ok(Click {
+            button: button.parse()?,
+            modifiers,
+            double_click,
+        })
+    }
+}
+
+impl Click {
+    fn matches(&self, event: &I3BarEvent, double_click: bool) -> bool {
+        self.button == event.button
+            && self.double_click == double_click
+            && self.modifiers == event.held_modifiers()
+    }
+}
+
+/// What a click binding does: an action of the block, or a shell command
+/// given as `{ cmd = "..." }`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ClickAction<A> {
+    Block(A),
+    Command { cmd: String },
+}
+
+/// Actions bound to clicks in the configuration of a block, as a table from
+/// [`Click`](struct.Click.html)s to actions.
+#[derive(Debug, Clone)]
+pub struct ClickBindings<A> {
+    bindings: Vec<(Click, ClickAction<A>)>,
+}
+
+impl<A> Default for ClickBindings<A> {
+    fn default() -> Self {
+        ClickBindings {
+            bindings: Vec::new(),
+        }
+    }
+}
+
+impl<A> ClickBindings<A> {
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+
+    /// The action bound to `event`. The second click of a double click falls
+    /// back to the binding of a single click if there is none for the double
+    /// click.
+    pub fn get(&self, event: &I3BarEvent) -> Option<&ClickAction<A>> {
+        let find = |double_click| {
+            self.bindings
+                .iter()
+                .find(|(click, _)| click.matches(event, double_click))
+                .map(|(_, action)| action)
+        };
+        if event.double_click {
+            find(true).or_else(|| find(false))
+        } else {
+            find(false)
+        }
+    }
+}
+
+impl<'de, A: Deserialize<'de>> Deserialize<'de> for ClickBindings<A> {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let table = BTreeMap::<String, ClickAction<A>>::deserialize(deserializer)?;
+        let bindings = table
+            .into_iter()
+            .map(|(click, action)| Ok((click.parse().map_err(de::Error::custom)?, action)))
+            .collect::<std::result::Result<_, D::Error>>()?;
+        Ok(ClickBindings { bindings })
+    }
 }
 
 pub fn process_events(sender: Sender<I3BarEvent>) {
@@ -93,3 +321,142 @@ where
 
     deserializer.deserialize_any(MouseButtonVisitor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(json: &str) -> I3BarEvent {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_parse_event() {
+        let e = event(r#"{"name":"a","instance":null,"x":1,"y":2,"button":3}"#);
+        assert_eq!(e.button, MouseButton::Right);
+        assert!(e.modifiers.is_empty());
+
+        let e = event(
+            r#"{"name":"a","x":1,"y":2,"button":1,"modifiers":["Mod2","Shift","Mod4","Hyper"]}"#,
+        );
+        assert_eq!(
+            e.modifiers,
+            vec![
+                Modifier::Mod2,
+                Modifier::Shift,
+                Modifier::Mod4,
+                Modifier::Unknown
+            ]
+        );
+        // Num Lock is not a key being held.
+        assert_eq!(
+            e.held_modifiers(),
+            vec![Modifier::Shift, Modifier::Mod4, Modifier::Unknown]
+        );
+    }
+
+    #[test]
+    fn test_parse_click() {
+        assert_eq!(
+            "right".parse(),
+            Ok(Click {
+                button: MouseButton::Right,
+                modifiers: Vec::new(),
+                double_click: false
+            })
+        );
+        assert_eq!(
+            "shift + ctrl+double_left".parse(),
+            Ok(Click {
+                button: MouseButton::Left,
+                modifiers: vec![Modifier::Shift, Modifier::Control],
+                double_click: true
+            })
+        );
+        assert!("left+right".parse::<Click>().is_err());
+        assert!("hyper+left".parse::<Click>().is_err());
+        assert!("".parse::<Click>().is_err());
+    }
+
+    #[test]
+    fn test_double_clicks() {
+        let mut double_clicks = DoubleClicks::new(Duration::from_millis(250));
+        let start = Instant::now();
+        let mut click = |json: &str, millis| {
+            let mut e = event(json);
+            double_clicks.detect_at(&mut e, start + Duration::from_millis(millis));
+            e.double_click
+        };
+        let left = r#"{"name":"a","x":0,"y":0,"button":1}"#;
+        assert!(!click(left, 0));
+        assert!(click(left, 200));
+        // A third click starts over.
+        assert!(!click(left, 300));
+        assert!(!click(left, 600));
+        assert!(!click(r#"{"name":"b","x":0,"y":0,"button":1}"#, 700));
+        assert!(!click(r#"{"name":"b","x":0,"y":0,"button":3}"#, 800));
+        assert!(click(r#"{"name":"b","x":0,"y":0,"button":3}"#, 900));
+    }
+
+    #[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Action {
+        Mute,
+        Louder,
+    }
+
+    #[test]
+    fn test_bindings() {
+        #[derive(Deserialize)]
+        struct Config {
+            click: ClickBindings<Action>,
+        }
+        let bindings = toml::from_str::<Config>(concat!(
+            "[click]\n",
+            "right = \"mute\"\n",
+            "\"ctrl+wheel_up\" = \"louder\"\n",
+            "double_left = { cmd = \"pavucontrol\" }\n",
+        ))
+        .unwrap()
+        .click;
+        let get = |json: &str, double_click| {
+            let mut e = event(json);
+            e.double_click = double_click;
+            bindings.get(&e).cloned()
+        };
+
+        assert_eq!(
+            get(r#"{"name":"a","x":0,"y":0,"button":3}"#, false),
+            Some(ClickAction::Block(Action::Mute))
+        );
+        // Falls back on the single click.
+        assert_eq!(
+            get(r#"{"name":"a","x":0,"y":0,"button":3}"#, true),
+            Some(ClickAction::Block(Action::Mute))
+        );
+        assert_eq!(
+            get(
+                r#"{"name":"a","x":0,"y":0,"button":3,"modifiers":["Shift"]}"#,
+                false
+            ),
+            None
+        );
+        assert_eq!(
+            get(
+                r#"{"name":"a","x":0,"y":0,"button":4,"modifiers":["Control","Lock"]}"#,
+                false
+            ),
+            Some(ClickAction::Block(Action::Louder))
+        );
+        assert_eq!(get(r#"{"name":"a","x":0,"y":0,"button":1}"#, false), None);
+        assert_eq!(
+            get(r#"{"name":"a","x":0,"y":0,"button":1}"#, true),
+            Some(ClickAction::Command {
+                cmd: "pavucontrol".to_string()
+            })
+        );
+
+        assert!(toml::from_str::<Config>("[click]\nwheel = \"mute\"\n").is_err());
+        assert!(toml::from_str::<Config>("[click]\nright = \"unmute\"\n").is_err());
+    }
+}
@@ -0,0 +1,39 @@
+//! The blocks of i3status-rs, for bars other than i3bar.
+//!
+//! [`runner::BlockRunner`] runs the blocks of a [`config::Config`] and returns
+//! what they show as [`widget::WidgetState`]s whenever that changes. The
+//! `i3status-rs` binary is a thin frontend printing the same in the i3bar
+//! protocol.
+
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "pulseaudio")]
+use libpulse_binding as pulse;
+
+#[macro_use]
+mod de;
+#[macro_use]
+mod util;
+pub mod blocks;
+pub mod config;
+pub mod control;
+mod dbus_manager;
+pub mod errors;
+mod formatting;
+mod icons;
+pub mod input;
+mod registry;
+pub mod runner;
+pub mod scheduler;
+mod signals;
+mod stop;
+mod subprocess;
+mod themes;
+pub mod widget;
+pub mod widgets;
+
+// Only used by the i3bar frontend.
+#[doc(hidden)]
+pub mod persistence;
+#[doc(hidden)]
+pub mod self_test;
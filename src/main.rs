@@ -1,41 +1,32 @@
-#[macro_use]
-extern crate serde_json;
-#[cfg(feature = "pulseaudio")]
-use libpulse_binding as pulse;
-
-#[macro_use]
-mod de;
-#[macro_use]
-mod util;
-pub mod blocks;
-mod config;
-mod errors;
-mod icons;
-mod input;
-mod scheduler;
-mod subprocess;
-mod themes;
-mod widget;
-mod widgets;
-
 #[cfg(feature = "profiling")]
 use cpuprofiler::PROFILER;
 
-use std::collections::HashMap;
+use std::io::{self, Write};
 use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
+use std::thread;
 use std::time::Duration;
 
 use clap::{crate_authors, crate_description, crate_version, App, Arg, ArgMatches};
-use crossbeam_channel::{select, Receiver, Sender};
+use crossbeam_channel::{Receiver, Sender};
+use inotify::{Inotify, WatchMask};
+use signal_hook::iterator::Signals;
 
-use crate::blocks::create_block;
-use crate::blocks::Block;
-use crate::config::{load_config, Config};
-use crate::errors::*;
-use crate::input::{process_events, I3BarEvent};
-use crate::scheduler::{Task, UpdateScheduler};
-use crate::widget::{I3BarWidget, State};
-use crate::widgets::text::TextWidget;
+use i3status_rs::blocks::Block;
+#[cfg(feature = "profiling")]
+use i3status_rs::blocks::{self, create_block};
+#[cfg(feature = "profiling")]
+use i3status_rs::config::SharedBlockConfig;
+use i3status_rs::config::{default_config_path, load_config, Config};
+use i3status_rs::control;
+use i3status_rs::errors::*;
+use i3status_rs::input::process_events;
+use i3status_rs::persistence;
+use i3status_rs::runner::BlockRunner;
+use i3status_rs::scheduler::Task;
+use i3status_rs::self_test;
+use i3status_rs::widget::{I3BarWidget, State};
+use i3status_rs::widgets::text::TextWidget;
 
 fn main() {
     let mut builder = App::new("i3status-rs")
@@ -61,6 +52,25 @@ fn main() {
                 .long("never-pause")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("persist-state")
+                .help("Save the state of stateful blocks on exit and restore it on the next start")
+                .long("persist-state")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("control-socket")
+                .help("Accept commands from scripts on a Unix socket at this path")
+                .long("control-socket")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("self-test")
+                .help("Update every configured block once, send it test clicks and report the results")
+                .long("self-test")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("one-shot")
                 .help("Print blocks once and exit")
@@ -69,7 +79,7 @@ fn main() {
                 .hidden(true),
         );
 
-    if_debug!({
+    if cfg!(debug_assertions) {
         builder = builder
             .arg(
                 Arg::with_name("profile")
@@ -84,11 +94,19 @@ fn main() {
                     .default_value("10000")
                     .help("Number of times to execute update when profiling"),
             );
-    });
+    }
 
     let matches = builder.get_matches();
     let exit_on_error = matches.is_present("exit-on-error");
 
+    if matches.is_present("self-test") {
+        let passed = self_test::run(&config_path(&matches)).unwrap_or_else(|error| {
+            eprintln!("{:?}", error);
+            false
+        });
+        ::std::process::exit(if passed { 0 } else { 1 });
+    }
+
     // Run and match for potential error
     if let Err(error) = run(&matches) {
         if exit_on_error {
@@ -100,12 +118,14 @@ fn main() {
             .with_state(State::Critical)
             .with_text(&format!("{:?}", error));
         let error_rendered = error_widget.get_rendered();
-        println!(
-            "{}",
-            serde_json::to_string(&[error_rendered]).expect("failed to serialize error message")
-        );
+        let line =
+            serde_json::to_string(&[error_rendered]).expect("failed to serialize error message");
 
         eprintln!("\n\n{:?}", error);
+        // Without i3bar there is nobody to keep the message displayed for.
+        if write_line(&line).is_err() {
+            ::std::process::exit(1);
+        }
         // Do nothing, so the error message keeps displayed
         loop {
             ::std::thread::sleep(Duration::from_secs(::std::u64::MAX));
@@ -113,6 +133,13 @@ fn main() {
     }
 }
 
+fn config_path(matches: &ArgMatches) -> std::path::PathBuf {
+    match matches.value_of("config") {
+        Some(config_path) => std::path::PathBuf::from(config_path),
+        None => default_config_path(),
+    }
+}
+
 fn run(matches: &ArgMatches) -> Result<()> {
     // Now we can start to run the i3bar protocol
     let initialise = if matches.is_present("never-pause") {
@@ -123,21 +150,18 @@ fn run(matches: &ArgMatches) -> Result<()> {
     } else {
         "\"version\": 1, \"click_events\": true".to_string()
     };
-    print!("{{{}}}\n[", initialise);
+    if let Err(error) = write_line(&format!("{{{}}}\n[", initialise)) {
+        eprintln!("Exiting, i3bar went away: {}", error);
+        return Ok(());
+    }
 
     // Read & parse the config file
-    let config_path = match matches.value_of("config") {
-        Some(config_path) => std::path::PathBuf::from(config_path),
-        None => util::xdg_config_home().join("i3status-rust/config.toml"),
-    };
-    let config = load_config(&config_path)?;
-
-    // Update request channel
-    let (tx_update_requests, rx_update_requests): (Sender<Task>, Receiver<Task>) =
-        crossbeam_channel::unbounded();
+    let config = load_config(&config_path(matches))?;
 
     // In dev build, we might diverge into profiling blocks here
     if let Some(name) = matches.value_of("profile") {
+        let (tx_update_requests, _rx_update_requests): (Sender<Task>, Receiver<Task>) =
+            crossbeam_channel::unbounded();
         profile_config(
             name,
             matches.value_of("profile-runs").unwrap(),
@@ -147,122 +171,154 @@ fn run(matches: &ArgMatches) -> Result<()> {
         return Ok(());
     }
 
-    let mut config_alternating_tint = config.clone();
-    {
-        let tint_bg = &config.theme.alternating_tint_bg;
-        config_alternating_tint.theme.idle_bg =
-            util::add_colors(&config_alternating_tint.theme.idle_bg, tint_bg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.info_bg =
-            util::add_colors(&config_alternating_tint.theme.info_bg, tint_bg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.good_bg =
-            util::add_colors(&config_alternating_tint.theme.good_bg, tint_bg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.warning_bg =
-            util::add_colors(&config_alternating_tint.theme.warning_bg, tint_bg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.critical_bg =
-            util::add_colors(&config_alternating_tint.theme.critical_bg, tint_bg)
-                .configuration_error("can't parse alternative_tint color code")?;
-
-        let tint_fg = &config.theme.alternating_tint_fg;
-        config_alternating_tint.theme.idle_fg =
-            util::add_colors(&config_alternating_tint.theme.idle_fg, tint_fg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.info_fg =
-            util::add_colors(&config_alternating_tint.theme.info_fg, tint_fg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.good_fg =
-            util::add_colors(&config_alternating_tint.theme.good_fg, tint_fg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.warning_fg =
-            util::add_colors(&config_alternating_tint.theme.warning_fg, tint_fg)
-                .configuration_error("can't parse alternative_tint color code")?;
-        config_alternating_tint.theme.critical_fg =
-            util::add_colors(&config_alternating_tint.theme.critical_fg, tint_fg)
-                .configuration_error("can't parse alternative_tint color code")?;
+    let block_keys = persistence::block_keys(&config);
+    let mut runner = BlockRunner::new(config)?;
+
+    let persist_state = matches.is_present("persist-state");
+    let state_path = persistence::default_state_path();
+    if persist_state {
+        match persistence::load_state(&state_path) {
+            Ok(mut state) => {
+                for (key, block) in block_keys.iter().zip(runner.blocks_mut()) {
+                    persistence::restore_state(&mut state, key, block.deref_mut());
+                }
+            }
+            Err(e) => eprintln!("{:?}", e),
+        }
     }
 
-    let mut blocks: Vec<Box<dyn Block>> = Vec::new();
-
-    let mut alternator = false;
-    // Initialize the blocks
-    for &(ref block_name, ref block_config) in &config.blocks {
-        blocks.push(create_block(
-            block_name,
-            block_config.clone(),
-            if alternator {
-                config_alternating_tint.clone()
-            } else {
-                config.clone()
-            },
-            tx_update_requests.clone(),
-        )?);
-        alternator = !alternator;
+    // We wait for click events in a separate thread, to avoid blocking to wait for stdin
+    process_events(runner.click_sender());
+    watch_config(config_path(matches), runner.reload_sender())?;
+    let control_socket = matches.value_of("control-socket").map(Path::new);
+    if let Some(path) = control_socket {
+        control::listen(path, runner.control_sender())?;
     }
 
-    // We save the order of the blocks here,
-    // because they will be passed to an unordered HashMap
-    let order = blocks
-        .iter()
-        .map(|x| String::from(x.id()))
-        .collect::<Vec<_>>();
+    // When persisting state, termination requests end the main loop so the
+    // state can be written out before exiting.
+    let rx_exit = if persist_state {
+        let (tx_exit, rx_exit) = crossbeam_channel::bounded(1);
+        let signals = Signals::new([signal_hook::SIGTERM, signal_hook::SIGINT])
+            .internal_error("signals", "failed to register signal handlers")?;
+        std::thread::Builder::new()
+            .name("signals".into())
+            .spawn(move || {
+                if signals.forever().next().is_some() {
+                    tx_exit.send(()).ok();
+                }
+            })
+            .internal_error("signals", "failed to start signal thread")?;
+        rx_exit
+    } else {
+        crossbeam_channel::never()
+    };
 
-    let mut scheduler = UpdateScheduler::new(&blocks);
+    let result = main_loop(matches.is_present("one-shot"), &mut runner, &rx_exit);
 
-    let mut block_map: HashMap<String, &mut dyn Block> = HashMap::new();
+    if let Some(path) = control_socket {
+        std::fs::remove_file(path).ok();
+    }
 
-    for block in &mut blocks {
-        block_map.insert(String::from(block.id()), (*block).deref_mut());
+    if persist_state {
+        let keyed_blocks: Vec<(String, &dyn Block)> = persistence::block_keys(runner.config())
+            .iter()
+            .zip(runner.blocks())
+            .map(|(key, block)| (key.clone(), &**block))
+            .collect();
+        if let Err(e) = persistence::save_state(&state_path, &keyed_blocks) {
+            eprintln!("{:?}", e);
+        }
     }
 
-    // We wait for click events in a separate thread, to avoid blocking to wait for stdin
-    let (tx_clicks, rx_clicks): (Sender<I3BarEvent>, Receiver<I3BarEvent>) =
-        crossbeam_channel::unbounded();
-    process_events(tx_clicks);
-
-    // Time to next update channel.
-    // Fires immediately for first updates
-    let mut ttnu = crossbeam_channel::after(Duration::from_millis(0));
-
-    let one_shot = matches.is_present("one-shot");
-    loop {
-        // We use the message passing concept of channel selection
-        // to avoid busy wait
-        select! {
-            // Receive click events
-            recv(rx_clicks) -> res => if let Ok(event) = res {
-                    for block in block_map.values_mut() {
-                        block.click(&event)?;
-                    }
-                    util::print_blocks(&order, &block_map, &config)?;
-            },
-            // Receive async update requests
-            recv(rx_update_requests) -> request => if let Ok(req) = request {
-                // Process immediately and forget
-                block_map
-                    .get_mut(&req.id)
-                    .internal_error("scheduler", "could not get required block")?
-                    .update()?;
-                util::print_blocks(&order, &block_map, &config)?;
-            },
-            // Receive update timer events
-            recv(ttnu) -> _ => {
-                scheduler.do_scheduled_updates(&mut block_map)?;
-                // redraw the blocks, state changed
-                util::print_blocks(&order, &block_map, &config)?;
-            },
+    result
+}
+
+/// Loads the configuration at `path` again whenever the file changes or on
+/// SIGUSR2, and hands it to `reloads`. A configuration that cannot be loaded
+/// is logged and otherwise ignored.
+fn watch_config(path: PathBuf, reloads: Sender<Config>) -> Result<()> {
+    let config_path = path.clone();
+    let reload = move || match load_config(&config_path) {
+        Ok(config) => reloads.send(config).is_ok(),
+        Err(error) => {
+            eprintln!("Not reloading the configuration: {:?}", error);
+            true
         }
+    };
+    let reload_on_signal = reload.clone();
+
+    let signals = Signals::new([signal_hook::SIGUSR2])
+        .internal_error("reload", "failed to register signal handler")?;
+    thread::Builder::new()
+        .name("reload-signal".into())
+        .spawn(move || {
+            for _ in signals.forever() {
+                if !reload_on_signal() {
+                    break;
+                }
+            }
+        })
+        .internal_error("reload", "failed to start signal thread")?;
+
+    // Editors often replace the file rather than writing to it, so watch the
+    // directory for the file to be written or moved there.
+    let file_name = match path.file_name() {
+        Some(name) => name.to_owned(),
+        None => return Ok(()),
+    };
+    let directory = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let mut notify = Inotify::init().internal_error("reload", "failed to start inotify")?;
+    notify
+        .add_watch(&directory, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        .internal_error(
+            "reload",
+            &format!("failed to watch {}", directory.to_string_lossy()),
+        )?;
+    thread::Builder::new()
+        .name("reload-inotify".into())
+        .spawn(move || {
+            let mut buffer = [0; 1024];
+            while let Ok(mut events) = notify.read_events_blocking(&mut buffer) {
+                if events.any(|event| event.name == Some(&file_name)) {
+                    // Let the editor finish writing before reading the file.
+                    thread::sleep(Duration::from_millis(100));
+                    if !reload() {
+                        break;
+                    }
+                }
+            }
+        })
+        .internal_error("reload", "failed to start inotify thread")?;
+    Ok(())
+}
+
+/// Writes `line` to i3bar. Unlike `println!`, this fails rather than panics
+/// once i3bar went away, e.g. when i3 restarts it.
+fn write_line(line: &str) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    writeln!(handle, "{}", line).and_then(|_| handle.flush())
+}
 
-        // Set the time-to-next-update timer
-        if let Some(time) = scheduler.time_to_next_update() {
-            ttnu = crossbeam_channel::after(time)
+/// Prints the frames of `runner` until `rx_exit` receives or i3bar goes away,
+/// which ends the bar like a termination request, saving the state of the
+/// blocks with `--persist-state`.
+fn main_loop(one_shot: bool, runner: &mut BlockRunner, rx_exit: &Receiver<()>) -> Result<()> {
+    while let Some(frame) = runner.next_frame(rx_exit)? {
+        let line = frame.to_i3bar()?;
+        if let Err(error) = write_line(&line) {
+            eprintln!("Exiting, i3bar went away: {}", error);
+            break;
         }
         if one_shot {
-            break Ok(());
+            break;
         }
     }
+    Ok(())
 }
 
 #[cfg(feature = "profiling")]
@@ -283,7 +339,7 @@ fn profile(iterations: i32, name: &str, block: &mut dyn Block) {
     bar.set_job_title("Profiling...");
 
     for i in 0..iterations {
-        block.update().expect("block update failed");
+        blocks::update(block).expect("block update failed");
         bar.reach_percent(((i as f64 / iterations as f64) * 100.).round() as i32);
     }
 
@@ -297,8 +353,9 @@ fn profile_config(name: &str, runs: &str, config: &Config, update: Sender<Task>)
         .configuration_error("failed to parse --profile-runs as an integer")?;
     for &(ref block_name, ref block_config) in &config.blocks {
         if block_name == name {
-            let mut block =
-                create_block(&block_name, block_config.clone(), config.clone(), update)?;
+            let mut block_config = block_config.clone();
+            SharedBlockConfig::extract(block_name, &mut block_config)?;
+            let mut block = create_block(&block_name, block_config, config.clone(), update)?;
             profile(profile_runs, &block_name, block.deref_mut());
             break;
         }
@@ -0,0 +1,204 @@
+//! Carry block state over a restart of the bar.
+//!
+//! When i3 reloads it restarts i3bar, which in turn kills the status command
+//! with a broken pipe (or a SIGTERM). Blocks that keep meaningful in-memory
+//! state (counters, timers) can opt in by implementing
+//! [`Block::save_state`](../blocks/trait.Block.html#method.save_state) and
+//! [`Block::restore_state`](../blocks/trait.Block.html#method.restore_state).
+//! The snapshots are written to a single versioned JSON file on exit and read
+//! back by the next instance.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::value::Value;
+
+use crate::blocks::Block;
+use crate::config::Config;
+use crate::errors::*;
+use crate::util::xdg_runtime_dir;
+
+/// Bump this whenever the layout of the state file changes. Files written with
+/// a different version are ignored rather than misinterpreted.
+pub const STATE_VERSION: u64 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct StateFile {
+    version: u64,
+    blocks: BTreeMap<String, Value>,
+}
+
+/// The default location of the state file.
+pub fn default_state_path() -> PathBuf {
+    xdg_runtime_dir().join("i3status-rust/state.json")
+}
+
+/// The key under which the state of a block is stored. Block ids are random,
+/// so blocks are identified by their position in the configuration instead.
+pub fn block_key(index: usize, name: &str) -> String {
+    format!("{}-{}", index, name)
+}
+
+/// The keys of the blocks of `config`, in order.
+pub fn block_keys(config: &Config) -> Vec<String> {
+    config
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(index, (name, _))| block_key(index, name))
+        .collect()
+}
+
+/// The state of every block that provides one, by key.
+pub fn snapshot(blocks: &[(String, &dyn Block)]) -> BTreeMap<String, Value> {
+    blocks
+        .iter()
+        .filter_map(|(key, block)| block.save_state().map(|s| (key.clone(), s)))
+        .collect()
+}
+
+/// Writes the state of every block that provides one to `path`.
+pub fn save_state(path: &Path, blocks: &[(String, &dyn Block)]) -> Result<()> {
+    let state = StateFile {
+        version: STATE_VERSION,
+        blocks: snapshot(blocks),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).internal_error(
+            "persistence",
+            &format!("failed to create directory {}", parent.to_string_lossy()),
+        )?;
+    }
+
+    // Write to a temporary file first so a crash midway never leaves a
+    // truncated state file behind.
+    let tmp_path = path.with_extension("json.tmp");
+    let contents = serde_json::to_string(&state)
+        .internal_error("persistence", "failed to serialize block state")?;
+    File::create(&tmp_path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .internal_error(
+            "persistence",
+            &format!("failed to write {}", tmp_path.to_string_lossy()),
+        )?;
+    fs::rename(&tmp_path, path).internal_error(
+        "persistence",
+        &format!("failed to move state file to {}", path.to_string_lossy()),
+    )
+}
+
+/// Reads the state file at `path`. A missing file or a file written by an
+/// incompatible version yields an empty map.
+pub fn load_state(path: &Path) -> Result<BTreeMap<String, Value>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .internal_error(
+            "persistence",
+            &format!("failed to read {}", path.to_string_lossy()),
+        )?;
+    parse_state(&contents)
+}
+
+fn parse_state(contents: &str) -> Result<BTreeMap<String, Value>> {
+    let state: StateFile = serde_json::from_str(contents)
+        .internal_error("persistence", "failed to parse state file")?;
+    if state.version != STATE_VERSION {
+        return Ok(BTreeMap::new());
+    }
+    Ok(state.blocks)
+}
+
+/// Hands a block its saved state, if there is any. Failing to restore a block
+/// is not fatal; the error is reported and the block starts fresh.
+pub fn restore_state(state: &mut BTreeMap<String, Value>, key: &str, block: &mut dyn Block) {
+    if let Some(value) = state.remove(key) {
+        if let Err(e) = block.restore_state(value) {
+            eprintln!("Failed to restore state of block '{}': {:?}", key, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    use crate::input::I3BarEvent;
+    use crate::widget::I3BarWidget;
+
+    struct Counter {
+        count: u64,
+    }
+
+    impl Block for Counter {
+        fn id(&self) -> &str {
+            "counter"
+        }
+
+        fn view(&self) -> Vec<&dyn I3BarWidget> {
+            vec![]
+        }
+
+        fn click(&mut self, _: &I3BarEvent) -> Result<()> {
+            Ok(())
+        }
+
+        fn save_state(&self) -> Option<Value> {
+            Some(json!({ "count": self.count }))
+        }
+
+        fn restore_state(&mut self, state: Value) -> Result<()> {
+            self.count = state["count"]
+                .as_u64()
+                .internal_error("counter", "missing count")?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("nested/state.json");
+
+        let first = Counter { count: 42 };
+        save_state(&path, &[(block_key(0, "counter"), &first)]).unwrap();
+
+        let mut second = Counter { count: 0 };
+        let mut state = load_state(&path).unwrap();
+        restore_state(&mut state, &block_key(0, "counter"), &mut second);
+        assert_eq!(second.count, 42);
+    }
+
+    #[test]
+    fn test_missing_file_is_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let state = load_state(&temp_dir.path().join("state.json")).unwrap();
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_version_mismatch_is_ignored() {
+        let contents = r#"{"version": 0, "blocks": {"0-counter": {"count": 1}}}"#;
+        assert!(parse_state(contents).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_left_alone() {
+        let mut state = parse_state(&format!(
+            r#"{{"version": {}, "blocks": {{"1-counter": {{"count": 1}}}}}}"#,
+            STATE_VERSION
+        ))
+        .unwrap();
+        let mut block = Counter { count: 7 };
+        restore_state(&mut state, &block_key(0, "counter"), &mut block);
+        assert_eq!(block.count, 7);
+    }
+}
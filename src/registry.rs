@@ -0,0 +1,334 @@
+//! A process-wide registry of the values exposed by named blocks.
+//!
+//! Any block can be given an `id` in its configuration, which we call its name
+//! here to avoid confusion with the internal block id. After each round of
+//! updates the framework publishes the [`values`](../blocks/trait.Block.html#method.values)
+//! of named blocks here, and meta blocks (such as
+//! [`Aggregate`](../blocks/aggregate/struct.Aggregate.html)) read them back.
+//! Blocks that depend on other blocks declare so with [`depend`](fn.depend.html);
+//! they are then told to update whenever one of their sources changes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crossbeam_channel::Sender;
+use lazy_static::lazy_static;
+
+use crate::blocks::Block;
+use crate::errors::*;
+use crate::scheduler::Task;
+
+/// The numeric values of a block, keyed by placeholder name (without braces).
+pub type Values = BTreeMap<String, f64>;
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+/// The names and dependencies of some blocks, taken out of the registry when
+/// the blocks are replaced.
+#[derive(Default)]
+pub struct Registrations {
+    names: Vec<(String, String)>,
+    dependents: Vec<Dependent>,
+}
+
+struct Dependent {
+    id: String,
+    sources: Vec<String>,
+    update_request: Sender<Task>,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Block id -> configured name.
+    names: HashMap<String, String>,
+    /// Block name -> last published values.
+    values: HashMap<String, Values>,
+    dependents: Vec<Dependent>,
+}
+
+impl Registry {
+    fn register_name(&mut self, id: &str, name: &str) -> Result<()> {
+        if self.names.values().any(|n| n == name) {
+            return Err(ConfigurationError(
+                format!("block id '{}' is used more than once", name),
+                (String::new(), String::new()),
+            ));
+        }
+        self.names.insert(id.to_string(), name.to_string());
+        Ok(())
+    }
+
+    fn take(&mut self, ids: &[String]) -> Registrations {
+        let mut taken = Registrations::default();
+        for id in ids {
+            if let Some(name) = self.names.remove(id) {
+                self.values.remove(&name);
+                taken.names.push((id.clone(), name));
+            }
+        }
+        let (dependents, others) = self
+            .dependents
+            .drain(..)
+            .partition(|dependent| ids.contains(&dependent.id));
+        self.dependents = others;
+        taken.dependents = dependents;
+        taken
+    }
+
+    fn put_back(&mut self, registrations: Registrations) {
+        self.names.extend(registrations.names);
+        self.dependents.extend(registrations.dependents);
+    }
+
+    fn validate(&self) -> Result<()> {
+        for dependent in &self.dependents {
+            for source in &dependent.sources {
+                if !self.names.values().any(|n| n == source) {
+                    return Err(ConfigurationError(
+                        format!("no block has the id '{}'", source),
+                        (String::new(), String::new()),
+                    ));
+                }
+            }
+        }
+
+        // Only named blocks can be referenced, so only they can form a cycle.
+        let graph: HashMap<&str, &[String]> = self
+            .dependents
+            .iter()
+            .filter_map(|d| {
+                self.names
+                    .get(&d.id)
+                    .map(|name| (name.as_str(), d.sources.as_slice()))
+            })
+            .collect();
+        let mut start_names: Vec<&str> = graph.keys().cloned().collect();
+        start_names.sort();
+        for start in start_names {
+            let mut chain = vec![start];
+            if let Some(cycle) = find_cycle(&graph, &mut chain) {
+                return Err(ConfigurationError(
+                    format!("blocks reference each other in a cycle: {}", cycle),
+                    (String::new(), String::new()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn publish(&mut self, id: &str, block: &dyn Block) {
+        let name = match self.names.get(id) {
+            Some(name) => name.clone(),
+            None => return,
+        };
+        let values = block.values();
+        if self.values.get(&name) == Some(&values) {
+            return;
+        }
+        self.values.insert(name.clone(), values);
+        for dependent in &self.dependents {
+            if dependent.sources.contains(&name) {
+                // The receiving end only goes away when the bar shuts down.
+                dependent
+                    .update_request
+                    .send(Task {
+                        id: dependent.id.clone(),
+                        update_time: Instant::now(),
+                    })
+                    .ok();
+            }
+        }
+    }
+}
+
+fn find_cycle<'a>(
+    graph: &HashMap<&'a str, &'a [String]>,
+    chain: &mut Vec<&'a str>,
+) -> Option<String> {
+    let current = *chain.last()?;
+    for next in graph.get(current).map(|s| s.iter()).into_iter().flatten() {
+        if chain.contains(&next.as_str()) {
+            let mut cycle: Vec<&str> = chain.clone();
+            cycle.push(next);
+            return Some(cycle.join(" -> "));
+        }
+        chain.push(next);
+        if let Some(cycle) = find_cycle(graph, chain) {
+            return Some(cycle);
+        }
+        chain.pop();
+    }
+    None
+}
+
+/// Makes the block with the given id available to other blocks as `name`.
+pub fn register_name(id: &str, name: &str) -> Result<()> {
+    REGISTRY
+        .lock()
+        .internal_error("registry", "failed to acquire lock")?
+        .register_name(id, name)
+}
+
+/// Declares that the block `id` reads the values of the blocks named in
+/// `sources`. It is sent an update request whenever any of them changes.
+pub fn depend(id: &str, sources: Vec<String>, update_request: Sender<Task>) -> Result<()> {
+    REGISTRY
+        .lock()
+        .internal_error("registry", "failed to acquire lock")?
+        .dependents
+        .push(Dependent {
+            id: id.to_string(),
+            sources,
+            update_request,
+        });
+    Ok(())
+}
+
+/// Checks that every referenced block exists and that there are no cycles.
+/// Must be called once all blocks have been created.
+pub fn validate() -> Result<()> {
+    REGISTRY
+        .lock()
+        .internal_error("registry", "failed to acquire lock")?
+        .validate()
+}
+
+/// Removes the names and dependencies of the blocks with the given ids, e.g.
+/// before they are replaced by blocks configured anew.
+pub fn take(ids: &[String]) -> Result<Registrations> {
+    Ok(REGISTRY
+        .lock()
+        .internal_error("registry", "failed to acquire lock")?
+        .take(ids))
+}
+
+/// Registers blocks removed by [`take`](fn.take.html) again.
+pub fn put_back(registrations: Registrations) -> Result<()> {
+    REGISTRY
+        .lock()
+        .internal_error("registry", "failed to acquire lock")?
+        .put_back(registrations);
+    Ok(())
+}
+
+/// Publishes the current values of `block` if it is named.
+pub fn publish(id: &str, block: &dyn Block) -> Result<()> {
+    REGISTRY
+        .lock()
+        .internal_error("registry", "failed to acquire lock")?
+        .publish(id, block);
+    Ok(())
+}
+
+/// The last published values of the block called `name`.
+pub fn get(name: &str) -> Option<Values> {
+    REGISTRY.lock().ok()?.values.get(name).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::widget::I3BarWidget;
+
+    struct Fixed(Values);
+
+    impl Block for Fixed {
+        fn id(&self) -> &str {
+            "fixed"
+        }
+
+        fn view(&self) -> Vec<&dyn I3BarWidget> {
+            vec![]
+        }
+
+        fn values(&self) -> Values {
+            self.0.clone()
+        }
+    }
+
+    fn depend_on(
+        registry: &mut Registry,
+        id: &str,
+        sources: &[&str],
+    ) -> crossbeam_channel::Receiver<Task> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        registry.dependents.push(Dependent {
+            id: id.to_string(),
+            sources: sources.iter().map(|s| s.to_string()).collect(),
+            update_request: tx,
+        });
+        rx
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let mut registry = Registry::default();
+        registry.register_name("1", "mail").unwrap();
+        assert!(registry.register_name("2", "mail").is_err());
+    }
+
+    #[test]
+    fn test_missing_reference() {
+        let mut registry = Registry::default();
+        registry.register_name("1", "mail").unwrap();
+        depend_on(&mut registry, "2", &["mail", "github"]);
+        assert!(registry.validate().is_err());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut registry = Registry::default();
+        registry.register_name("1", "a").unwrap();
+        registry.register_name("2", "b").unwrap();
+        registry.register_name("3", "c").unwrap();
+        depend_on(&mut registry, "1", &["b"]);
+        depend_on(&mut registry, "2", &["c"]);
+        assert!(registry.validate().is_ok());
+        depend_on(&mut registry, "3", &["a"]);
+        match registry.validate() {
+            Err(ConfigurationError(message, _)) => assert!(message.contains("a -> b -> c -> a")),
+            _ => panic!("expected a cycle to be detected"),
+        }
+    }
+
+    #[test]
+    fn test_publish_notifies_on_change_only() {
+        let mut registry = Registry::default();
+        registry.register_name("1", "mail").unwrap();
+        let rx = depend_on(&mut registry, "2", &["mail"]);
+
+        let block = Fixed(vec![("count".to_string(), 3.0)].into_iter().collect());
+        registry.publish("1", &block);
+        registry.publish("1", &block);
+        assert_eq!(rx.try_iter().count(), 1);
+        assert_eq!(registry.values["mail"]["count"], 3.0);
+
+        // Unnamed blocks are never published.
+        registry.publish("3", &block);
+        assert_eq!(registry.values.len(), 1);
+    }
+
+    #[test]
+    fn test_take_and_put_back() {
+        let mut registry = Registry::default();
+        registry.register_name("1", "mail").unwrap();
+        registry.register_name("2", "total").unwrap();
+        depend_on(&mut registry, "2", &["mail"]);
+        let block = Fixed(vec![("count".to_string(), 3.0)].into_iter().collect());
+        registry.publish("1", &block);
+
+        // The names are free for the blocks replacing these.
+        let taken = registry.take(&["1".to_string(), "2".to_string()]);
+        assert!(registry.values.is_empty());
+        assert!(registry.dependents.is_empty());
+        registry.register_name("3", "mail").unwrap();
+        registry.take(&["3".to_string()]);
+
+        registry.put_back(taken);
+        assert!(registry.validate().is_ok());
+        assert!(registry.register_name("4", "mail").is_err());
+    }
+}
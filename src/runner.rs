@@ -0,0 +1,486 @@
+//! Running blocks, independent of the bar showing them.
+//!
+//! A [`BlockRunner`] creates the blocks of a configuration, updates them when
+//! they are due or ask for it and hands clicks to them. Every time what the
+//! blocks show may have changed, [`BlockRunner::next_frame`] returns a
+//! [`Frame`] to read the widgets from. Given a new configuration, it replaces
+//! the blocks without stopping. Scripts can control it through
+//! [`control`](../control/index.html) commands.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::DerefMut;
+use std::os::raw::c_int;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{select, Receiver, Sender};
+
+use crate::blocks::{self, create_block, Block};
+use crate::config::{Config, SharedBlockConfig};
+use crate::control::{Command, Request};
+use crate::errors::*;
+use crate::formatting;
+use crate::input::{DoubleClicks, I3BarEvent};
+use crate::persistence;
+use crate::registry;
+use crate::scheduler::{Task, UpdateScheduler};
+use crate::signals::{self, BlockSignals};
+use crate::themes::ThemeConfig;
+use crate::util;
+use crate::widget::{State, WidgetState};
+
+/// What the block with `id` shows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockState {
+    pub id: String,
+    /// Empty if the block is hidden.
+    pub widgets: Vec<WidgetState>,
+}
+
+pub struct BlockRunner {
+    config: Config,
+    blocks: Vec<Box<dyn Block>>,
+    scheduler: UpdateScheduler,
+    /// The channel the current blocks ask for updates on. Every reload starts
+    /// a new one, so that the threads of replaced blocks fail to send and
+    /// stop. Keeping the sender here keeps it open even if no block asks for
+    /// updates.
+    update_request: Sender<Task>,
+    update_requests: Receiver<Task>,
+    /// Update requests on signals, which outlive reloads.
+    signal_requests: Receiver<Task>,
+    clicks: Receiver<I3BarEvent>,
+    click_sender: Sender<I3BarEvent>,
+    reloads: Receiver<Config>,
+    reload_sender: Sender<Config>,
+    controls: Receiver<Request>,
+    control_sender: Sender<Request>,
+    /// The configured ids of the blocks, and their internal ids.
+    names: HashMap<String, String>,
+    /// The configured ids of hidden blocks.
+    hidden: HashSet<String>,
+    double_clicks: DoubleClicks,
+    signals: BlockSignals,
+    next_update: Receiver<Instant>,
+}
+
+/// `config` with the alternating tint of its theme added to every color.
+fn alternating_tint(config: &Config) -> Result<Config> {
+    let mut tinted = config.clone();
+    let theme = &mut tinted.theme;
+    let tint_bg = &config.theme.alternating_tint_bg;
+    let tint_fg = &config.theme.alternating_tint_fg;
+    for (color, tint) in vec![
+        (&mut theme.idle_bg, tint_bg),
+        (&mut theme.info_bg, tint_bg),
+        (&mut theme.good_bg, tint_bg),
+        (&mut theme.warning_bg, tint_bg),
+        (&mut theme.critical_bg, tint_bg),
+        (&mut theme.idle_fg, tint_fg),
+        (&mut theme.info_fg, tint_fg),
+        (&mut theme.good_fg, tint_fg),
+        (&mut theme.warning_fg, tint_fg),
+        (&mut theme.critical_fg, tint_fg),
+    ] {
+        *color = util::add_colors(color, tint)
+            .configuration_error("can't parse alternative_tint color code")?;
+    }
+    Ok(tinted)
+}
+
+fn block_map(blocks: &mut [Box<dyn Block>]) -> HashMap<String, &mut dyn Block> {
+    blocks
+        .iter_mut()
+        .map(|block| {
+            (
+                String::from(block.id()),
+                block.deref_mut() as &mut dyn Block,
+            )
+        })
+        .collect()
+}
+
+/// The blocks of a configuration.
+#[derive(Default)]
+struct Blocks {
+    blocks: Vec<Box<dyn Block>>,
+    /// The realtime signals to update some of the blocks on.
+    signaled: Vec<(String, c_int)>,
+    /// The configured ids of the blocks, and their internal ids.
+    names: HashMap<String, String>,
+}
+
+/// Creates the blocks of `config`, adding each to `blocks` right away. As on
+/// i3bar, every other block has the alternating tint of the theme.
+fn add_blocks(config: &Config, update_request: &Sender<Task>, blocks: &mut Blocks) -> Result<()> {
+    let config_alternating_tint = alternating_tint(config)?;
+    let mut alternator = false;
+    for (block_name, block_config) in &config.blocks {
+        let mut block_config = block_config.clone();
+        let shared_config = SharedBlockConfig::extract(block_name, &mut block_config)?;
+        let block = create_block(
+            block_name,
+            block_config,
+            shared_config.block_config(if alternator {
+                &config_alternating_tint
+            } else {
+                config
+            })?,
+            update_request.clone(),
+        )?;
+        let registered = shared_config.register(&*block);
+        if let Some(signal) = shared_config.signal.and_then(signals::realtime_signal) {
+            blocks.signaled.push((block.id().to_string(), signal));
+        }
+        if let Some(name) = &shared_config.id {
+            blocks.names.insert(name.clone(), block.id().to_string());
+        }
+        blocks.blocks.push(block);
+        registered?;
+        alternator = !alternator;
+    }
+    registry::validate()
+}
+
+fn block_ids(blocks: &[Box<dyn Block>]) -> Vec<String> {
+    blocks.iter().map(|block| block.id().to_string()).collect()
+}
+
+/// Creates the blocks of `config`. If any of them fails, the registrations
+/// of those created already are undone.
+fn create_blocks(config: &Config, update_request: &Sender<Task>) -> Result<Blocks> {
+    let mut blocks = Blocks::default();
+    match add_blocks(config, update_request, &mut blocks) {
+        Ok(()) => Ok(blocks),
+        Err(error) => {
+            registry::take(&block_ids(&blocks.blocks))?;
+            Err(error)
+        }
+    }
+}
+
+impl BlockRunner {
+    /// Creates the blocks of `config`.
+    pub fn new(config: Config) -> Result<BlockRunner> {
+        formatting::set_number_format(config.number_format());
+        let (update_request, update_requests) = crossbeam_channel::unbounded();
+        let Blocks {
+            blocks,
+            signaled,
+            names,
+        } = create_blocks(&config, &update_request)?;
+        let (signal_request, signal_requests) = crossbeam_channel::unbounded();
+        let signals = BlockSignals::start(signal_request)?;
+        signals.set(block_ids(&blocks), &signaled)?;
+
+        let (click_sender, clicks) = crossbeam_channel::unbounded();
+        let (reload_sender, reloads) = crossbeam_channel::unbounded();
+        let (control_sender, controls) = crossbeam_channel::unbounded();
+        Ok(BlockRunner {
+            scheduler: UpdateScheduler::new(&blocks),
+            double_clicks: DoubleClicks::new(config.double_click_interval),
+            signals,
+            config,
+            blocks,
+            update_request,
+            update_requests,
+            signal_requests,
+            clicks,
+            click_sender,
+            reloads,
+            reload_sender,
+            controls,
+            control_sender,
+            names,
+            hidden: HashSet::new(),
+            // Fires right away for the first updates.
+            next_update: crossbeam_channel::after(Duration::from_millis(0)),
+        })
+    }
+
+    /// Replaces the blocks with those of `config`. Blocks at the same position
+    /// and of the same kind as before get the state of the block they replace,
+    /// if they keep one. If any block of `config` cannot be created, the old
+    /// blocks are kept.
+    ///
+    /// The old blocks are dropped, which stops their threads, and what is
+    /// kept about them elsewhere is forgotten. Hidden blocks stay hidden.
+    pub fn reload(&mut self, config: Config) -> Result<()> {
+        let old_ids = block_ids(&self.blocks);
+        let old_registrations = registry::take(&old_ids)?;
+        let (update_request, update_requests) = crossbeam_channel::unbounded();
+        let Blocks {
+            mut blocks,
+            signaled,
+            names,
+        } = match create_blocks(&config, &update_request) {
+            Ok(blocks) => blocks,
+            Err(error) => {
+                registry::put_back(old_registrations)?;
+                return Err(error);
+            }
+        };
+
+        let old_keys = persistence::block_keys(&self.config);
+        let keyed: Vec<(String, &dyn Block)> = old_keys
+            .into_iter()
+            .zip(self.blocks.iter().map(|block| &**block))
+            .collect();
+        let mut state = persistence::snapshot(&keyed);
+        for (key, block) in persistence::block_keys(&config).iter().zip(&mut blocks) {
+            persistence::restore_state(&mut state, key, block.deref_mut());
+        }
+
+        formatting::set_number_format(config.number_format());
+        self.signals.set(block_ids(&blocks), &signaled)?;
+        self.scheduler = UpdateScheduler::new(&blocks);
+        self.double_clicks = DoubleClicks::new(config.double_click_interval);
+        // Dropping the old receiver makes the old blocks' threads fail to
+        // send from now on, even those that are not told to stop.
+        self.update_request = update_request;
+        self.update_requests = update_requests;
+        drop(old_registrations);
+        // New blocks may take over the ids of old ones.
+        let new_ids = block_ids(&blocks);
+        let gone: Vec<String> = old_ids
+            .into_iter()
+            .filter(|id| !new_ids.contains(id))
+            .collect();
+        blocks::forget(&gone);
+        self.blocks = blocks;
+        self.names = names;
+        self.config = config;
+        self.next_update = crossbeam_channel::after(Duration::from_millis(0));
+        Ok(())
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The blocks, in the order of the configuration.
+    pub fn blocks(&self) -> &[Box<dyn Block>] {
+        &self.blocks
+    }
+
+    pub fn blocks_mut(&mut self) -> &mut [Box<dyn Block>] {
+        &mut self.blocks
+    }
+
+    /// A channel to hand clicks to the blocks on, from any thread. They are
+    /// handled by the next call to `next_frame`.
+    pub fn click_sender(&self) -> Sender<I3BarEvent> {
+        self.click_sender.clone()
+    }
+
+    /// A channel to hand new configurations on, from any thread. The next call
+    /// to `next_frame` [`reload`](#method.reload)s the blocks, and logs it if
+    /// that fails.
+    pub fn reload_sender(&self) -> Sender<Config> {
+        self.reload_sender.clone()
+    }
+
+    /// A channel to hand [`control`](../control/index.html) commands on, from
+    /// any thread. The next call to `next_frame` carries them out and sends
+    /// back whether that worked.
+    pub fn control_sender(&self) -> Sender<Request> {
+        self.control_sender.clone()
+    }
+
+    /// The internal id of the block configured with `id = name`.
+    fn named(&self, name: &str) -> Result<String> {
+        self.names.get(name).cloned().ok_or_else(|| {
+            InternalError(
+                "control".to_owned(),
+                format!("no block has the id '{}'", name),
+                None,
+            )
+        })
+    }
+
+    /// Carries out a control command.
+    pub fn control(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::Hide(name) => {
+                self.named(&name)?;
+                self.hidden.insert(name);
+            }
+            Command::Show(name) => {
+                self.named(&name)?;
+                self.hidden.remove(&name);
+            }
+            Command::Toggle(name) => {
+                self.named(&name)?;
+                if !self.hidden.remove(&name) {
+                    self.hidden.insert(name);
+                }
+            }
+            Command::SetText(name, text) => {
+                let id = self.named(&name)?;
+                if let Some(block) = block_map(&mut self.blocks).get_mut(&id) {
+                    block.push_text(&text)?;
+                }
+            }
+            Command::Refresh(name) => {
+                let id = name.map(|name| self.named(&name)).transpose()?;
+                for block in &mut self.blocks {
+                    if id.is_none() || id.as_deref() == Some(block.id()) {
+                        self.scheduler.update(block.deref_mut(), false);
+                    }
+                }
+            }
+            Command::Theme(name) => {
+                let mut config = self.config.clone();
+                config.theme = ThemeConfig::load(&name)
+                    .configuration_error(&format!("cannot load theme '{}'", name))?;
+                self.reload(config)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands `event` to every block, which checks whether it is meant for it.
+    /// Marks it as a double click if it quickly follows the same click. A
+    /// block failing to handle it is logged, the others are unaffected.
+    pub fn click(&mut self, event: &I3BarEvent) {
+        let mut event = event.clone();
+        self.double_clicks.detect(&mut event);
+        for block in &mut self.blocks {
+            if let Err(error) = blocks::click(block.deref_mut(), &event) {
+                let (name, message) = error.parts();
+                log_block_error(block.id(), name, message);
+            }
+        }
+    }
+
+    /// Updates the block a request is for, if it is still there.
+    fn requested_update(&mut self, request: &Task) -> bool {
+        match block_map(&mut self.blocks).get_mut(&request.id) {
+            Some(block) => {
+                self.scheduler.update(*block, false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handles clicks, update requests and scheduled updates until the blocks
+    /// may show something new, and returns what they show then. Returns `None`
+    /// once `stop` receives something or is closed.
+    pub fn next_frame(&mut self, stop: &Receiver<()>) -> Result<Option<Frame<'_>>> {
+        loop {
+            let changed = select! {
+                recv(self.clicks) -> event => match event {
+                    Ok(event) => {
+                        self.click(&event);
+                        true
+                    }
+                    Err(_) => false,
+                },
+                recv(self.update_requests) -> request => match request {
+                    Ok(request) => self.requested_update(&request),
+                    Err(_) => false,
+                },
+                recv(self.signal_requests) -> request => match request {
+                    Ok(request) => self.requested_update(&request),
+                    Err(_) => false,
+                },
+                recv(self.reloads) -> config => match config {
+                    Ok(config) => {
+                        if let Err(error) = self.reload(config) {
+                            eprintln!("Failed to reload the configuration: {:?}", error);
+                        }
+                        true
+                    }
+                    Err(_) => false,
+                },
+                recv(self.controls) -> request => match request {
+                    Ok((command, reply)) => {
+                        reply.send(self.control(command)).ok();
+                        true
+                    }
+                    Err(_) => false,
+                },
+                recv(self.next_update) -> _ => {
+                    self.scheduler
+                        .do_scheduled_updates(&mut block_map(&mut self.blocks))?
+                },
+                recv(stop) -> _ => return Ok(None),
+            };
+
+            // Let blocks that depend on others know about changed values.
+            for block in &self.blocks {
+                registry::publish(block.id(), &**block)?;
+            }
+            self.next_update = match self.scheduler.time_to_next_update() {
+                Some(time) => crossbeam_channel::after(time),
+                None => crossbeam_channel::never(),
+            };
+
+            if changed {
+                let hidden = self
+                    .hidden
+                    .iter()
+                    .filter_map(|name| self.names.get(name).cloned())
+                    .collect();
+                return Ok(Some(Frame {
+                    config: &self.config,
+                    blocks: &self.blocks,
+                    hidden,
+                }));
+            }
+        }
+    }
+}
+
+/// What the blocks show at one point in time.
+pub struct Frame<'a> {
+    config: &'a Config,
+    blocks: &'a [Box<dyn Block>],
+    /// The ids of the blocks hidden by control commands.
+    hidden: HashSet<String>,
+}
+
+impl Frame<'_> {
+    /// What every block shows, in the order of the configuration. Blocks
+    /// whose last update failed or reporting too many errors show that
+    /// instead.
+    pub fn blocks(&self) -> Vec<BlockState> {
+        self.blocks
+            .iter()
+            .map(|block| BlockState {
+                id: block.id().to_string(),
+                widgets: match block_failure(block.id()) {
+                    _ if self.hidden.contains(block.id()) => Vec::new(),
+                    Some(failure) => vec![WidgetState {
+                        text: failure,
+                        icon: None,
+                        state: State::Critical,
+                        name: Some(block.id().to_string()),
+                    }],
+                    // Clicks on widgets without a name of their own go to
+                    // the block, as in `render_blocks`.
+                    None => block
+                        .view()
+                        .iter()
+                        .map(|widget| {
+                            let mut state = widget.widget_state();
+                            state.name.get_or_insert_with(|| block.id().to_string());
+                            state
+                        })
+                        .collect(),
+                },
+            })
+            .collect()
+    }
+
+    /// The blocks as a line of i3bar JSON.
+    pub fn to_i3bar(&self) -> Result<String> {
+        let blocks: Vec<&dyn Block> = self
+            .blocks
+            .iter()
+            .filter(|block| !self.hidden.contains(block.id()))
+            .map(|block| &**block)
+            .collect();
+        util::render_blocks(&blocks, self.config)
+    }
+}
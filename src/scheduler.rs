@@ -2,10 +2,11 @@ use crate::blocks::Update;
 use std::cmp;
 use std::collections::{BinaryHeap, HashMap};
 use std::fmt;
-use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::blocks::Block;
+use chrono::{DateTime, Utc};
+
+use crate::blocks::{self, Block};
 use crate::errors::*;
 
 #[derive(Debug, Clone)]
@@ -40,8 +41,56 @@ impl cmp::Ord for Task {
     }
 }
 
+/// How far the wall clock may drift from the monotonic clock before a task
+/// scheduled for a wall clock time is considered off and run right away.
+const DISCONTINUITY: i64 = 1000;
+
+/// How long the bar may sleep while a task waits for a wall clock time. The
+/// monotonic clock stands still during suspend, so a task waiting for it
+/// would run late after waking up.
+const WALL_CLOCK_CHECK: Duration = Duration::from_secs(5);
+
+/// How long the first retry of a failed update waits. Every further failure
+/// doubles this, up to `MAX_RETRY_DELAY`.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// How long to wait before retrying after the `failures`th failed update in a
+/// row.
+fn retry_delay(failures: u32) -> Duration {
+    RETRY_DELAY
+        .checked_mul(1 << failures.saturating_sub(1).min(16))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// A task waiting for a wall clock time, along with when the monotonic clock
+/// expects it.
+#[derive(Debug, Clone)]
+struct WallClockTask {
+    id: String,
+    at: DateTime<Utc>,
+    deadline: Instant,
+}
+
+/// `now - then`, which may be negative.
+fn signed_since(now: Instant, then: Instant) -> chrono::Duration {
+    let duration =
+        |d: Duration| chrono::Duration::from_std(d).unwrap_or_else(|_| chrono::Duration::zero());
+    if now >= then {
+        duration(now - then)
+    } else {
+        -duration(then - now)
+    }
+}
+
+#[derive(Default)]
 pub struct UpdateScheduler {
     schedule: BinaryHeap<Task>,
+    wall_clock: Vec<WallClockTask>,
+    /// How many updates in a row failed, by block id.
+    failures: HashMap<String, u32>,
 }
 
 impl UpdateScheduler {
@@ -56,72 +105,424 @@ impl UpdateScheduler {
             });
         }
 
-        UpdateScheduler { schedule }
+        UpdateScheduler {
+            schedule,
+            wall_clock: Vec::new(),
+            failures: HashMap::new(),
+        }
     }
 
-    pub fn time_to_next_update(&self) -> Option<Duration> {
-        if let Some(peeked) = self.schedule.peek() {
-            let next_update = peeked.update_time;
-            let now = Instant::now();
+    pub fn time_to_next_update(&mut self) -> Option<Duration> {
+        self.time_to_next_update_at(Instant::now(), Utc::now())
+    }
 
-            if next_update > now {
-                Some(next_update - now)
-            } else {
-                Some(Duration::new(0, 0))
-            }
+    fn time_to_next_update_at(&mut self, now: Instant, wall: DateTime<Utc>) -> Option<Duration> {
+        self.check_wall_clock(now, wall);
+        let next = self
+            .schedule
+            .peek()
+            .map(|task| task.update_time)
+            .into_iter()
+            .chain(self.wall_clock.iter().map(|task| task.deadline))
+            .min()?;
+        let time = next.saturating_duration_since(now);
+        if self.wall_clock.is_empty() {
+            Some(time)
         } else {
-            None
+            Some(time.min(WALL_CLOCK_CHECK))
         }
     }
 
-    pub fn do_scheduled_updates(
-        &mut self,
-        block_map: &mut HashMap<String, &mut dyn Block>,
-    ) -> Result<()> {
-        let t = self
+    /// Makes tasks for a wall clock time due if the wall clock is not where
+    /// the monotonic clock expects it, e.g. after suspend or when the clock
+    /// was set. The block then schedules itself again from the new time.
+    fn check_wall_clock(&mut self, now: Instant, wall: DateTime<Utc>) {
+        for task in &mut self.wall_clock {
+            let expected = task.at + signed_since(now, task.deadline);
+            if (wall - expected).num_milliseconds().abs() > DISCONTINUITY {
+                task.deadline = now;
+            }
+        }
+    }
+
+    /// Removes the tasks due at `now` and returns the ids of their blocks.
+    fn due_tasks(&mut self, now: Instant, wall: DateTime<Utc>) -> Vec<String> {
+        self.check_wall_clock(now, wall);
+        let mut due = Vec::new();
+        while self
             .schedule
-            .pop()
-            .internal_error("scheduler", "schedule is empty")?;
-        let mut tasks_next = vec![t.clone()];
-
-        while !self.schedule.is_empty()
-            && t.update_time
-                == self
-                    .schedule
-                    .peek()
-                    .internal_error("scheduler", "schedule is empty")?
-                    .update_time
+            .peek()
+            .is_some_and(|task| task.update_time <= now)
         {
-            tasks_next.push(
-                self.schedule
-                    .pop()
-                    .internal_error("scheduler", "schedule is empty")?,
-            )
+            if let Some(task) = self.schedule.pop() {
+                due.push(task.id);
+            }
         }
+        let (wall_due, waiting) = self
+            .wall_clock
+            .drain(..)
+            .partition::<Vec<_>, _>(|task| task.deadline <= now);
+        self.wall_clock = waiting;
+        due.extend(wall_due.into_iter().map(|task| task.id));
+        due
+    }
 
-        let now = Instant::now();
-        if t.update_time > now {
-            thread::sleep(t.update_time - now);
+    fn schedule(&mut self, id: String, update: Update, now: Instant, wall: DateTime<Utc>) {
+        match update {
+            Update::Every(d) => self.schedule.push(Task {
+                id,
+                update_time: now + d,
+            }),
+            Update::AtWallClock(at) => self.wall_clock.push(WallClockTask {
+                id,
+                at,
+                deadline: now + (at - wall).to_std().unwrap_or_default(),
+            }),
+            Update::Once => {} // do not schedule this task again
         }
+    }
 
-        let now = Instant::now();
+    /// Moves the updates of the block `id` scheduled after `at` to `at`. Leaves
+    /// blocks waiting for the wall clock alone.
+    fn bring_forward(&mut self, id: String, at: Instant) {
+        let sooner = self
+            .schedule
+            .iter()
+            .any(|task| task.id == id && task.update_time <= at);
+        if sooner || self.wall_clock.iter().any(|task| task.id == id) {
+            return;
+        }
+        self.schedule.retain(|task| task.id != id);
+        self.schedule.push(Task {
+            id,
+            update_time: at,
+        });
+    }
+
+    /// Whether the block `id` has an update scheduled.
+    fn is_scheduled(&self, id: &str) -> bool {
+        self.schedule.iter().any(|task| task.id == id)
+            || self.wall_clock.iter().any(|task| task.id == id)
+    }
+
+    /// Updates `block`, scheduling the update it asks for if `scheduled`, i.e.
+    /// this is the update it scheduled last time, or if it has none scheduled,
+    /// e.g. because it stopped updating itself while idle, or if it asks for
+    /// it sooner than the one scheduled. A failed update does not
+    /// take the bar down: the block shows the error and is updated again
+    /// after a delay that grows with every failure in a row.
+    pub fn update(&mut self, block: &mut dyn Block, scheduled: bool) {
+        let id = block.id().to_string();
+        let result = blocks::update(block);
+        set_update_result(&id, &result);
+        self.handle_result(id, result, scheduled, Instant::now(), Utc::now());
+    }
 
-        for task in tasks_next {
-            if let Some(dur) = block_map
-                .get_mut(&task.id)
-                .internal_error("scheduler", "could not get required block")?
-                .update()?
-            {
-                match dur {
-                    Update::Every(d) => self.schedule.push(Task {
-                        id: task.id,
-                        update_time: now + d,
-                    }),
-                    Update::Once => {} // do not schedule this task again
+    fn handle_result(
+        &mut self,
+        id: String,
+        result: Result<Option<Update>>,
+        scheduled: bool,
+        now: Instant,
+        wall: DateTime<Utc>,
+    ) {
+        match result {
+            Ok(update) => {
+                self.failures.remove(&id);
+                match update {
+                    Some(update) if scheduled || !self.is_scheduled(&id) => {
+                        self.schedule(id, update, now, wall)
+                    }
+                    // A block asking for its next update sooner than it has
+                    // one scheduled, e.g. to follow a click closely, gets it.
+                    Some(Update::Every(d)) => self.bring_forward(id, now + d),
+                    _ => {}
+                }
+            }
+            Err(error) => {
+                let (name, message) = error.parts();
+                log_block_error(&id, name, message);
+                let failures = self.failures.entry(id.clone()).or_insert(0);
+                *failures += 1;
+                let delay = retry_delay(*failures);
+                // An update the block asked for failing leaves its scheduled
+                // updates, if any, to retry.
+                if scheduled || !self.is_scheduled(&id) {
+                    self.schedule(id, Update::Every(delay), now, wall);
                 }
             }
         }
+    }
+
+    /// Updates the blocks that are due. Returns whether there were any.
+    pub fn do_scheduled_updates(
+        &mut self,
+        block_map: &mut HashMap<String, &mut dyn Block>,
+    ) -> Result<bool> {
+        let due = self.due_tasks(Instant::now(), Utc::now());
+        let updated = !due.is_empty();
+        for id in due {
+            self.update(
+                *block_map
+                    .get_mut(&id)
+                    .internal_error("scheduler", "could not get required block")?,
+                true,
+            );
+        }
+
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn wall_secs(secs: i64) -> chrono::Duration {
+        chrono::Duration::seconds(secs)
+    }
+
+    fn start() -> (Instant, DateTime<Utc>) {
+        (Instant::now(), Utc.timestamp(1_600_000_000, 0))
+    }
+
+    fn failure() -> Result<Option<Update>> {
+        Err(BlockError("net".into(), "device vanished".into()))
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), secs(1));
+        assert_eq!(retry_delay(2), secs(2));
+        assert_eq!(retry_delay(5), secs(16));
+        assert_eq!(retry_delay(9), secs(256));
+        assert_eq!(retry_delay(10), secs(300));
+        assert_eq!(retry_delay(u32::MAX), secs(300));
+    }
+
+    #[test]
+    fn test_failed_updates_back_off() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+        let id = || "net".to_string();
+
+        scheduler.handle_result(id(), failure(), true, i0, w0);
+        assert_eq!(scheduler.time_to_next_update_at(i0, w0), Some(secs(1)));
+        assert_eq!(scheduler.due_tasks(i0 + secs(1), w0), vec!["net"]);
+        scheduler.handle_result(id(), failure(), true, i0 + secs(1), w0);
+        assert_eq!(
+            scheduler.time_to_next_update_at(i0 + secs(1), w0),
+            Some(secs(2))
+        );
+        assert_eq!(scheduler.due_tasks(i0 + secs(3), w0), vec!["net"]);
+        scheduler.handle_result(id(), failure(), true, i0 + secs(3), w0);
+        assert_eq!(
+            scheduler.time_to_next_update_at(i0 + secs(3), w0),
+            Some(secs(4))
+        );
+
+        // Once the block works again, it is back on its own schedule and the
+        // next failure starts over.
+        assert_eq!(scheduler.due_tasks(i0 + secs(7), w0), vec!["net"]);
+        let update = Ok(Some(Update::Every(secs(10))));
+        scheduler.handle_result(id(), update, true, i0 + secs(7), w0);
+        assert_eq!(scheduler.due_tasks(i0 + secs(17), w0), vec!["net"]);
+        scheduler.handle_result(id(), failure(), true, i0 + secs(17), w0);
+        assert_eq!(
+            scheduler.time_to_next_update_at(i0 + secs(17), w0),
+            Some(secs(1))
+        );
+    }
+
+    #[test]
+    fn test_failed_requested_update() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+
+        // A block updated on request only is retried.
+        scheduler.handle_result("music".to_string(), failure(), false, i0, w0);
+        assert_eq!(scheduler.due_tasks(i0 + secs(1), w0), vec!["music"]);
+
+        // A block with an update scheduled already keeps that one.
+        scheduler.schedule("cpu".to_string(), Update::Every(secs(5)), i0, w0);
+        scheduler.handle_result("cpu".to_string(), failure(), false, i0, w0);
+        assert!(scheduler.due_tasks(i0 + secs(4), w0).is_empty());
+        assert_eq!(scheduler.due_tasks(i0 + secs(5), w0), vec!["cpu"]);
+        assert!(scheduler.due_tasks(i0 + secs(60), w0).is_empty());
+    }
+
+    #[test]
+    fn test_requested_update_resumes_schedule() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+        let every = |d| Ok(Some(Update::Every(d)));
+
+        // A block that stopped updating itself starts again.
+        scheduler.handle_result("timer".to_string(), every(secs(1)), false, i0, w0);
+        assert_eq!(scheduler.due_tasks(i0 + secs(1), w0), vec!["timer"]);
+
+        // A block with an update scheduled already keeps just that one.
+        scheduler.schedule("cpu".to_string(), Update::Every(secs(5)), i0, w0);
+        scheduler.handle_result("cpu".to_string(), every(secs(10)), false, i0, w0);
+        assert!(scheduler.due_tasks(i0 + secs(4), w0).is_empty());
+        assert_eq!(scheduler.due_tasks(i0 + secs(5), w0), vec!["cpu"]);
+        assert!(scheduler.due_tasks(i0 + secs(60), w0).is_empty());
+    }
+
+    #[test]
+    fn test_requested_update_brings_schedule_forward() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+        let every = |d| Ok(Some(Update::Every(d)));
+
+        // Asking for an update sooner replaces the one scheduled.
+        scheduler.schedule("keylight".to_string(), Update::Every(secs(10)), i0, w0);
+        scheduler.handle_result("keylight".to_string(), every(secs(1)), false, i0, w0);
+        assert_eq!(scheduler.due_tasks(i0 + secs(1), w0), vec!["keylight"]);
+        assert!(scheduler.due_tasks(i0 + secs(60), w0).is_empty());
+
+        // Blocks waiting for the wall clock keep waiting.
+        scheduler.schedule(
+            "time".to_string(),
+            Update::AtWallClock(w0 + wall_secs(60)),
+            i0,
+            w0,
+        );
+        scheduler.handle_result("time".to_string(), every(secs(1)), false, i0, w0);
+        assert!(scheduler
+            .due_tasks(i0 + secs(1), w0 + wall_secs(1))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_every_ignores_wall_clock() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+        scheduler.schedule("a".to_string(), Update::Every(secs(10)), i0, w0);
+
+        // The wall clock jumping forward does not make the task due.
+        assert!(scheduler
+            .due_tasks(i0 + secs(1), w0 + wall_secs(3600))
+            .is_empty());
+        assert_eq!(
+            scheduler.time_to_next_update_at(i0 + secs(1), w0 + wall_secs(3600)),
+            Some(secs(9))
+        );
+        // Nor does it jumping backwards keep the task waiting.
+        assert_eq!(
+            scheduler.due_tasks(i0 + secs(10), w0 - wall_secs(3600)),
+            vec!["a"]
+        );
+        assert!(scheduler
+            .due_tasks(i0 + secs(10), w0 - wall_secs(3600))
+            .is_empty());
+        assert_eq!(scheduler.time_to_next_update_at(i0 + secs(10), w0), None);
+    }
+
+    #[test]
+    fn test_wall_clock() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+        scheduler.schedule(
+            "time".to_string(),
+            Update::AtWallClock(w0 + wall_secs(60)),
+            i0,
+            w0,
+        );
+
+        // The bar wakes up regularly to look at the wall clock.
+        assert_eq!(
+            scheduler.time_to_next_update_at(i0, w0),
+            Some(WALL_CLOCK_CHECK)
+        );
+        let at = |s: u64| (i0 + secs(s), w0 + wall_secs(s as i64));
+        let (now, wall) = at(59);
+        assert!(scheduler.due_tasks(now, wall).is_empty());
+        assert_eq!(scheduler.time_to_next_update_at(now, wall), Some(secs(1)));
+        let (now, wall) = at(60);
+        assert_eq!(scheduler.due_tasks(now, wall), vec!["time"]);
+        assert!(scheduler.due_tasks(now, wall).is_empty());
+    }
+
+    #[test]
+    fn test_suspend() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+        scheduler.schedule(
+            "time".to_string(),
+            Update::AtWallClock(w0 + wall_secs(60)),
+            i0,
+            w0,
+        );
+        scheduler.schedule("cpu".to_string(), Update::Every(secs(30)), i0, w0);
+
+        // The monotonic clock did not count the hour spent suspended.
+        let (now, wall) = (i0 + secs(5), w0 + wall_secs(3605));
+        assert_eq!(scheduler.time_to_next_update_at(now, wall), Some(secs(0)));
+        assert_eq!(scheduler.due_tasks(now, wall), vec!["time"]);
+        assert!(scheduler.due_tasks(now, wall).is_empty());
+
+        // The block schedules itself for the next minute again.
+        scheduler.schedule(
+            "time".to_string(),
+            Update::AtWallClock(w0 + wall_secs(3660)),
+            now,
+            wall,
+        );
+        assert_eq!(
+            scheduler.due_tasks(i0 + secs(30), w0 + wall_secs(3630)),
+            vec!["cpu"]
+        );
+        assert_eq!(
+            scheduler.due_tasks(i0 + secs(60), w0 + wall_secs(3660)),
+            vec!["time"]
+        );
+    }
+
+    #[test]
+    fn test_clock_set_backwards() {
+        let (i0, w0) = start();
+        let mut scheduler = UpdateScheduler::default();
+        scheduler.schedule(
+            "time".to_string(),
+            Update::AtWallClock(w0 + wall_secs(60)),
+            i0,
+            w0,
+        );
+
+        // Waiting for the old time would keep the block frozen for an hour.
+        let (now, wall) = (i0 + secs(30), w0 - wall_secs(3570));
+        assert_eq!(scheduler.due_tasks(now, wall), vec!["time"]);
+        scheduler.schedule(
+            "time".to_string(),
+            Update::AtWallClock(wall + wall_secs(30)),
+            now,
+            wall,
+        );
+        assert!(scheduler
+            .due_tasks(i0 + secs(59), w0 - wall_secs(3541))
+            .is_empty());
+        assert_eq!(
+            scheduler.due_tasks(i0 + secs(60), w0 - wall_secs(3540)),
+            vec!["time"]
+        );
 
-        Ok(())
+        // Small corrections are left alone.
+        scheduler.schedule(
+            "time".to_string(),
+            Update::AtWallClock(w0 - wall_secs(3480)),
+            i0 + secs(60),
+            w0 - wall_secs(3540),
+        );
+        let (now, wall) = (
+            i0 + secs(90),
+            w0 - wall_secs(3510) + chrono::Duration::milliseconds(300),
+        );
+        assert!(scheduler.due_tasks(now, wall).is_empty());
     }
 }
@@ -0,0 +1,154 @@
+//! `--self-test`: create every configured block, update it once and send it a
+//! click of each mouse button, then report how that went.
+//!
+//! The output is meant to be pasted into bug reports, so it is plain text
+//! rather than i3bar JSON.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::blocks::{self, create_block, Block, ClickSafety};
+use crate::config::{load_config, SharedBlockConfig};
+use crate::errors::*;
+use crate::formatting;
+use crate::input::{I3BarEvent, MouseButton};
+use crate::scheduler::Task;
+
+const BUTTONS: [MouseButton; 5] = [
+    MouseButton::Left,
+    MouseButton::Middle,
+    MouseButton::Right,
+    MouseButton::WheelUp,
+    MouseButton::WheelDown,
+];
+
+/// The outcome of testing a single block.
+struct Report {
+    name: String,
+    init_time: Duration,
+    update_time: Option<Duration>,
+    clicked: bool,
+    errors: Vec<String>,
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        "panicked".to_string()
+    }
+}
+
+/// Runs `f`, turning both errors and panics into a message.
+fn attempt<T, F: FnOnce() -> Result<T>>(what: &str, f: F) -> std::result::Result<T, String> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(error)) => Err(format!("{}: {:?}", what, error)),
+        Err(payload) => Err(format!("{}: {}", what, panic_message(payload))),
+    }
+}
+
+fn test_block(block: &mut dyn Block, report: &mut Report) {
+    let start = Instant::now();
+    let updated = attempt("update", || blocks::update(block));
+    report.update_time = Some(start.elapsed());
+    if let Err(error) = updated {
+        report.errors.push(error);
+    }
+
+    if block.click_safety() == ClickSafety::Unsafe {
+        return;
+    }
+    report.clicked = true;
+    for button in BUTTONS.iter() {
+        let event = I3BarEvent {
+            name: Some(block.id().to_string()),
+            instance: None,
+            x: 0,
+            y: 0,
+            button: *button,
+            modifiers: Vec::new(),
+            double_click: false,
+        };
+        if let Err(error) = attempt(&format!("{:?} click", button), || {
+            blocks::click(block, &event)
+        }) {
+            report.errors.push(error);
+        }
+    }
+}
+
+fn format_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(d) => format!("{:.1}ms", d.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+fn print_reports(reports: &[Report]) {
+    println!(
+        "{:<20} {:>10} {:>10} {:>7}  Result",
+        "Block", "Init", "Update", "Clicks"
+    );
+    for report in reports {
+        let result = if report.errors.is_empty() {
+            "ok".to_string()
+        } else {
+            report.errors.join("; ")
+        };
+        println!(
+            "{:<20} {:>10} {:>10} {:>7}  {}",
+            report.name,
+            format_duration(Some(report.init_time)),
+            format_duration(report.update_time),
+            if report.clicked { "sent" } else { "skipped" },
+            result
+        );
+    }
+}
+
+/// Tests every block configured in `config_path` and prints a summary.
+/// Returns whether all blocks passed.
+pub fn run(config_path: &Path) -> Result<bool> {
+    let config = load_config(config_path)?;
+    formatting::set_number_format(config.number_format());
+    // Blocks may ask for updates, but nobody is listening.
+    let (tx_update_requests, _rx_update_requests) = crossbeam_channel::unbounded::<Task>();
+
+    let mut reports = Vec::new();
+    for (block_name, block_config) in &config.blocks {
+        let mut report = Report {
+            name: block_name.clone(),
+            init_time: Duration::default(),
+            update_time: None,
+            clicked: false,
+            errors: Vec::new(),
+        };
+
+        let start = Instant::now();
+        let created = attempt("init", || {
+            let mut block_config = block_config.clone();
+            let shared_config = SharedBlockConfig::extract(block_name, &mut block_config)?;
+            create_block(
+                block_name,
+                block_config,
+                shared_config.block_config(&config)?,
+                tx_update_requests.clone(),
+            )
+        });
+        report.init_time = start.elapsed();
+
+        match created {
+            Ok(mut block) => test_block(&mut *block, &mut report),
+            Err(error) => report.errors.push(error),
+        }
+        reports.push(report);
+    }
+
+    print_reports(&reports);
+    Ok(reports.iter().all(|report| report.errors.is_empty()))
+}
@@ -0,0 +1,172 @@
+//! Updating blocks on signals, as i3blocks does.
+//!
+//! A block configured with `signal = N` is updated whenever the bar receives
+//! `SIGRTMIN+N`, e.g. from `pkill -RTMIN+N i3status-rs`, and every block on
+//! `SIGUSR1`. The signals are handled by a thread of their own, which asks the
+//! scheduler for an update of the blocks they belong to.
+
+use std::collections::HashMap;
+use std::os::raw::c_int;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crossbeam_channel::Sender;
+use signal_hook::iterator::Signals;
+use signal_hook::SIGUSR1;
+
+use crate::errors::*;
+use crate::scheduler::Task;
+
+// The C library reserves some realtime signals for itself, so their range is
+// only known at runtime. Both glibc and musl have these.
+extern "C" {
+    fn __libc_current_sigrtmin() -> c_int;
+    fn __libc_current_sigrtmax() -> c_int;
+}
+
+fn sigrtmin() -> c_int {
+    unsafe { __libc_current_sigrtmin() }
+}
+
+fn sigrtmax() -> c_int {
+    unsafe { __libc_current_sigrtmax() }
+}
+
+/// The number of the signal `offset` above `SIGRTMIN`, if it is within the
+/// realtime signals.
+pub fn realtime_signal(offset: i64) -> Option<c_int> {
+    let max = i64::from(sigrtmax() - sigrtmin());
+    if (1..=max).contains(&offset) {
+        Some(sigrtmin() + offset as c_int)
+    } else {
+        None
+    }
+}
+
+/// The largest offset `realtime_signal` accepts.
+pub fn max_offset() -> c_int {
+    sigrtmax() - sigrtmin()
+}
+
+/// The ids of the blocks updated on each signal.
+#[derive(Default)]
+struct SignaledBlocks {
+    /// Updated on `SIGUSR1`.
+    all: Vec<String>,
+    /// Updated on realtime signals, by signal.
+    realtime: HashMap<c_int, Vec<String>>,
+}
+
+impl SignaledBlocks {
+    fn get(&self, signal: c_int) -> Vec<String> {
+        if signal == SIGUSR1 {
+            self.all.clone()
+        } else {
+            self.realtime.get(&signal).cloned().unwrap_or_default()
+        }
+    }
+}
+
+pub struct BlockSignals {
+    signals: Signals,
+    blocks: Arc<Mutex<SignaledBlocks>>,
+}
+
+impl BlockSignals {
+    /// Starts the thread sending update requests for signaled blocks.
+    pub fn start(update_request: Sender<Task>) -> Result<Self> {
+        let signals = Signals::new([SIGUSR1])
+            .internal_error("signals", "failed to set up signal handling")?;
+        let blocks = Arc::new(Mutex::new(SignaledBlocks::default()));
+
+        let (signals_copy, blocks_copy) = (signals.clone(), blocks.clone());
+        thread::Builder::new()
+            .name("block-signals".into())
+            .spawn(move || {
+                for signal in signals_copy.forever() {
+                    let ids = match blocks_copy.lock() {
+                        Ok(blocks) => blocks.get(signal),
+                        Err(_) => break,
+                    };
+                    for id in ids {
+                        let task = Task {
+                            id,
+                            update_time: Instant::now(),
+                        };
+                        if update_request.send(task).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+            .internal_error("signals", "failed to start signal thread")?;
+
+        Ok(BlockSignals { signals, blocks })
+    }
+
+    /// Updates the blocks with the ids in `all` on `SIGUSR1` and those in
+    /// `realtime` on the realtime signals paired with them, instead of the
+    /// blocks set before.
+    pub fn set(&self, all: Vec<String>, realtime: &[(String, c_int)]) -> Result<()> {
+        let mut signaled = SignaledBlocks {
+            all,
+            realtime: HashMap::new(),
+        };
+        for (id, signal) in realtime {
+            // Signals stay registered once handled, there is no way to give
+            // the default action back. A signal without blocks is ignored.
+            self.signals
+                .add_signal(*signal)
+                .internal_error("signals", "failed to handle signal")?;
+            signaled
+                .realtime
+                .entry(*signal)
+                .or_default()
+                .push(id.clone());
+        }
+        *self
+            .blocks
+            .lock()
+            .internal_error("signals", "failed to acquire lock")? = signaled;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_realtime_signal() {
+        assert_eq!(realtime_signal(1), Some(sigrtmin() + 1));
+        assert_eq!(realtime_signal(i64::from(max_offset())), Some(sigrtmax()));
+        assert_eq!(realtime_signal(0), None);
+        assert_eq!(realtime_signal(i64::from(max_offset()) + 1), None);
+        assert_eq!(realtime_signal(-3), None);
+    }
+
+    #[test]
+    fn test_signal_updates_block() {
+        let (send, updates) = crossbeam_channel::unbounded();
+        let signals = BlockSignals::start(send).unwrap();
+        let signal = realtime_signal(max_offset().into()).unwrap();
+        signals
+            .set(
+                vec!["a".to_string(), "b".to_string()],
+                &[("b".to_string(), signal)],
+            )
+            .unwrap();
+        let next = || {
+            updates
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap()
+                .id
+        };
+
+        unsafe { nix::libc::raise(signal) };
+        assert_eq!(next(), "b");
+        unsafe { nix::libc::raise(SIGUSR1) };
+        assert_eq!((next(), next()), ("a".to_string(), "b".to_string()));
+    }
+}
@@ -0,0 +1,168 @@
+//! Stopping the threads of blocks along with the blocks.
+//!
+//! Blocks that wait for events in a thread of their own keep a [`Stop`], and
+//! hand its [`Stopped`] to the thread. Dropping the block, e.g. when a reload
+//! replaces it, drops the `Stop` and wakes up the thread, which then returns.
+//! Underneath this is a pipe whose writing end is closed, so the thread can
+//! wait for it along with the file descriptor it is actually interested in.
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use inotify::{Events, Inotify};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::unistd;
+
+use crate::errors::*;
+
+/// Stops the thread holding the matching [`Stopped`] when dropped.
+pub struct Stop {
+    write: RawFd,
+}
+
+impl Drop for Stop {
+    fn drop(&mut self) {
+        unistd::close(self.write).ok();
+    }
+}
+
+/// What a thread waited for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Wake {
+    /// The file descriptor can be read from.
+    Ready,
+    TimedOut,
+    /// The [`Stop`] was dropped, the thread should return.
+    Stopped,
+}
+
+/// The end of a [`Stop`] that a thread waits on.
+pub struct Stopped {
+    read: RawFd,
+}
+
+impl Drop for Stopped {
+    fn drop(&mut self) {
+        unistd::close(self.read).ok();
+    }
+}
+
+/// A new pair of a [`Stop`] for a block and the [`Stopped`] for its thread.
+pub fn pair() -> Result<(Stop, Stopped)> {
+    let (read, write) =
+        unistd::pipe2(OFlag::O_CLOEXEC).internal_error("stop", "failed to create pipe")?;
+    Ok((Stop { write }, Stopped { read }))
+}
+
+impl Stopped {
+    /// Waits until `fd` can be read from, `timeout` passed or the [`Stop`] is
+    /// dropped, whichever comes first. Without `fd` it only waits for the
+    /// latter two and without `timeout` it waits as long as it takes.
+    pub fn wait(&self, fd: Option<RawFd>, timeout: Option<Duration>) -> Result<Wake> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let mut fds = vec![PollFd::new(self.read, PollFlags::POLLIN)];
+            if let Some(fd) = fd {
+                fds.push(PollFd::new(fd, PollFlags::POLLIN));
+            }
+            let milliseconds = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    // Rounded up, so that the timeout has passed on waking up.
+                    (left.as_nanos() as f64 / 1_000_000.0)
+                        .ceil()
+                        .min(i32::MAX as f64) as i32
+                }
+                None => -1,
+            };
+            match poll(&mut fds, milliseconds) {
+                Ok(_) => {}
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(_) => return Err(InternalError("stop".into(), "failed to wait".into(), None)),
+            }
+            let events = |fd: &PollFd| fd.revents().unwrap_or_else(PollFlags::empty);
+            // Nothing is ever written to the pipe, so anything on it means
+            // the other end was closed.
+            if !events(&fds[0]).is_empty() {
+                return Ok(Wake::Stopped);
+            }
+            if fds.len() > 1 && !events(&fds[1]).is_empty() {
+                return Ok(Wake::Ready);
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Ok(Wake::TimedOut);
+                }
+            }
+        }
+    }
+
+    /// Sleeps for `duration`, and returns `false` right away if the [`Stop`]
+    /// is dropped meanwhile.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        matches!(self.wait(None, Some(duration)), Ok(Wake::TimedOut))
+    }
+
+    /// Waits for events of `notify`. `None` once the [`Stop`] is dropped or if
+    /// reading them fails.
+    pub fn inotify_events<'a>(
+        &self,
+        notify: &mut Inotify,
+        buffer: &'a mut [u8],
+    ) -> Option<Events<'a>> {
+        match self.wait(Some(notify.as_raw_fd()), None) {
+            Ok(Wake::Ready) => notify.read_events(buffer).ok(),
+            _ => None,
+        }
+    }
+
+    /// Whether the [`Stop`] has been dropped, without waiting.
+    pub fn is_stopped(&self) -> bool {
+        matches!(
+            self.wait(None, Some(Duration::from_secs(0))),
+            Ok(Wake::Stopped)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop() {
+        let (stop, stopped) = pair().unwrap();
+        assert!(!stopped.is_stopped());
+        assert!(stopped.sleep(Duration::from_millis(10)));
+
+        let (send, done) = crossbeam_channel::unbounded();
+        let thread = std::thread::spawn(move || {
+            let wake = stopped.wait(None, None).unwrap();
+            send.send(wake).unwrap();
+        });
+        drop(stop);
+        assert_eq!(
+            done.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Wake::Stopped
+        );
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_fd() {
+        let (_stop, stopped) = pair().unwrap();
+        let (read, write) = unistd::pipe().unwrap();
+        assert_eq!(
+            stopped
+                .wait(Some(read), Some(Duration::from_millis(10)))
+                .unwrap(),
+            Wake::TimedOut
+        );
+        unistd::write(write, b"x").unwrap();
+        assert_eq!(stopped.wait(Some(read), None).unwrap(), Wake::Ready);
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+}
@@ -196,6 +196,33 @@ impl Default for Theme {
     }
 }
 
+/// The foregrounds that can be `"auto"`, and the backgrounds they go on.
+const AUTO_FOREGROUNDS: &[(&str, &str)] = &[
+    ("idle_fg", "idle_bg"),
+    ("info_fg", "info_bg"),
+    ("good_fg", "good_bg"),
+    ("warning_fg", "warning_bg"),
+    ("critical_fg", "critical_bg"),
+];
+
+const KEYS: &[&str] = &[
+    "idle_bg",
+    "idle_fg",
+    "info_bg",
+    "info_fg",
+    "good_bg",
+    "good_fg",
+    "warning_bg",
+    "warning_fg",
+    "critical_bg",
+    "critical_fg",
+    "separator",
+    "separator_bg",
+    "separator_fg",
+    "alternating_tint_bg",
+    "alternating_tint_fg",
+];
+
 impl Theme {
     pub fn from_name(name: &str) -> Option<Theme> {
         match name {
@@ -212,6 +239,130 @@ impl Theme {
         }
     }
 
+    fn get_mut(&mut self, key: &str) -> Option<&mut String> {
+        match key {
+            "idle_bg" => Some(&mut self.idle_bg),
+            "idle_fg" => Some(&mut self.idle_fg),
+            "info_bg" => Some(&mut self.info_bg),
+            "info_fg" => Some(&mut self.info_fg),
+            "good_bg" => Some(&mut self.good_bg),
+            "good_fg" => Some(&mut self.good_fg),
+            "warning_bg" => Some(&mut self.warning_bg),
+            "warning_fg" => Some(&mut self.warning_fg),
+            "critical_bg" => Some(&mut self.critical_bg),
+            "critical_fg" => Some(&mut self.critical_fg),
+            "separator" => Some(&mut self.separator),
+            "separator_bg" => Some(&mut self.separator_bg),
+            "separator_fg" => Some(&mut self.separator_fg),
+            "alternating_tint_bg" => Some(&mut self.alternating_tint_bg),
+            "alternating_tint_fg" => Some(&mut self.alternating_tint_fg),
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&String> {
+        match key {
+            "idle_bg" => Some(&self.idle_bg),
+            "idle_fg" => Some(&self.idle_fg),
+            "info_bg" => Some(&self.info_bg),
+            "info_fg" => Some(&self.info_fg),
+            "good_bg" => Some(&self.good_bg),
+            "good_fg" => Some(&self.good_fg),
+            "warning_bg" => Some(&self.warning_bg),
+            "warning_fg" => Some(&self.warning_fg),
+            "critical_bg" => Some(&self.critical_bg),
+            "critical_fg" => Some(&self.critical_fg),
+            "separator" => Some(&self.separator),
+            "separator_bg" => Some(&self.separator_bg),
+            "separator_fg" => Some(&self.separator_fg),
+            "alternating_tint_bg" => Some(&self.alternating_tint_bg),
+            "alternating_tint_fg" => Some(&self.alternating_tint_fg),
+            _ => None,
+        }
+    }
+
+    /// The value of `key` with references to other keys, such as
+    /// `"$idle_bg"`, followed. `chain` holds the keys that led here.
+    fn resolve_key(&self, key: &str, chain: &mut Vec<String>) -> Result<String, String> {
+        if chain.iter().any(|k| k == key) {
+            chain.push(key.to_string());
+            return Err(format!(
+                "theme references form a cycle: {}",
+                chain.join(" -> ")
+            ));
+        }
+        let value = self.get(key).ok_or_else(|| {
+            format!(
+                "theme reference to unknown key '{}' in {}",
+                key,
+                chain.last().map(String::as_str).unwrap_or("theme")
+            )
+        })?;
+        match value.strip_prefix('$') {
+            Some(target) => {
+                chain.push(key.to_string());
+                self.resolve_key(target, chain)
+            }
+            None => Ok(value.clone()),
+        }
+    }
+
+    /// Follows references to other keys, then picks black or white for
+    /// foregrounds set to `"auto"`, whichever contrasts better with their
+    /// background.
+    fn resolve(&mut self) -> Result<(), String> {
+        let unresolved = self.clone();
+        for key in KEYS {
+            let value = unresolved.resolve_key(key, &mut Vec::new())?;
+            if let Some(slot) = self.get_mut(key) {
+                *slot = value;
+            }
+        }
+        for (fg, bg) in AUTO_FOREGROUNDS {
+            if self.get(fg).map(String::as_str) == Some("auto") {
+                let background = self.get(bg).cloned().unwrap_or_default();
+                let color = util::contrasting_color(&background).ok_or_else(|| {
+                    format!(
+                        "cannot pick an automatic {} for {} '{}'",
+                        fg, bg, background
+                    )
+                })?;
+                if let Some(slot) = self.get_mut(fg) {
+                    *slot = color;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the keys set in `overrides`, which may refer to other keys or
+    /// be `"auto"` foregrounds.
+    pub fn apply_overrides(&mut self, overrides: ThemeOverrides) -> Result<(), String> {
+        let values = vec![
+            ("idle_bg", overrides.idle_bg),
+            ("idle_fg", overrides.idle_fg),
+            ("info_bg", overrides.info_bg),
+            ("info_fg", overrides.info_fg),
+            ("good_bg", overrides.good_bg),
+            ("good_fg", overrides.good_fg),
+            ("warning_bg", overrides.warning_bg),
+            ("warning_fg", overrides.warning_fg),
+            ("critical_bg", overrides.critical_bg),
+            ("critical_fg", overrides.critical_fg),
+            ("separator", overrides.separator),
+            ("separator_bg", overrides.separator_bg),
+            ("separator_fg", overrides.separator_fg),
+            ("alternating_tint_bg", overrides.alternating_tint_bg),
+            ("alternating_tint_fg", overrides.alternating_tint_fg),
+        ];
+        for (key, value) in values {
+            if let (Some(value), Some(slot)) = (value, self.get_mut(key)) {
+                *slot = value;
+            }
+        }
+        self.resolve()
+    }
+
     pub fn from_file(file: &str) -> Option<Theme> {
         let full_path = Path::new(file);
         let xdg_path = util::xdg_config_home()
@@ -260,35 +411,83 @@ pub struct ThemeConfig {
 }
 
 impl ThemeConfig {
-    pub fn into_theme(self) -> Option<Theme> {
+    /// The built-in theme called `name`, or else the theme in the file `name`.
+    pub fn load(name: &str) -> Result<Theme, String> {
+        let mut theme = Theme::from_name(name)
+            .or_else(|| Theme::from_file(name))
+            .ok_or_else(|| "Unrecognized theme name.".to_string())?;
+        theme.resolve()?;
+        Ok(theme)
+    }
+
+    pub fn into_theme(self) -> Result<Theme, String> {
         let mut theme = if let Some(name) = self.name {
             Theme::from_name(&name)
         } else if let Some(file) = self.file {
             Theme::from_file(&file)
         } else {
             None
-        }?;
+        }
+        .ok_or_else(|| "Unrecognized theme name.".to_string())?;
         if let Some(overrides) = self.overrides {
-            theme.idle_bg = overrides.idle_bg.unwrap_or(theme.idle_bg);
-            theme.idle_fg = overrides.idle_fg.unwrap_or(theme.idle_fg);
-            theme.info_bg = overrides.info_bg.unwrap_or(theme.info_bg);
-            theme.info_fg = overrides.info_fg.unwrap_or(theme.info_fg);
-            theme.good_bg = overrides.good_bg.unwrap_or(theme.good_bg);
-            theme.good_fg = overrides.good_fg.unwrap_or(theme.good_fg);
-            theme.warning_bg = overrides.warning_bg.unwrap_or(theme.warning_bg);
-            theme.warning_fg = overrides.warning_fg.unwrap_or(theme.warning_fg);
-            theme.critical_bg = overrides.critical_bg.unwrap_or(theme.critical_bg);
-            theme.critical_fg = overrides.critical_fg.unwrap_or(theme.critical_fg);
-            theme.separator = overrides.separator.unwrap_or(theme.separator);
-            theme.separator_bg = overrides.separator_bg.unwrap_or(theme.separator_bg);
-            theme.separator_fg = overrides.separator_fg.unwrap_or(theme.separator_fg);
-            theme.alternating_tint_bg = overrides
-                .alternating_tint_bg
-                .unwrap_or(theme.alternating_tint_bg);
-            theme.alternating_tint_fg = overrides
-                .alternating_tint_fg
-                .unwrap_or(theme.alternating_tint_fg);
+            theme.apply_overrides(overrides)?;
+        } else {
+            theme.resolve()?;
         }
-        Some(theme)
+        Ok(theme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn theme(overrides: &str) -> Result<Theme, String> {
+        let config: ThemeConfig =
+            toml::from_str(&format!("name = \"plain\"\n[overrides]\n{}", overrides)).unwrap();
+        config.into_theme()
+    }
+
+    #[test]
+    fn test_references() {
+        let theme = theme(concat!(
+            "idle_bg = \"#123456\"\n",
+            "warning_bg = \"$idle_bg\"\n",
+            "critical_fg = \"$warning_bg\"\n",
+        ))
+        .unwrap();
+        assert_eq!(theme.warning_bg, "#123456");
+        assert_eq!(theme.critical_fg, "#123456");
+        // Keys that are not overridden can be referred to as well.
+        let theme = self::theme("good_fg = \"$idle_fg\"").unwrap();
+        assert_eq!(theme.good_fg, PLAIN.idle_fg);
+    }
+
+    #[test]
+    fn test_reference_errors() {
+        let error = theme(concat!(
+            "idle_bg = \"$warning_bg\"\n",
+            "warning_bg = \"$critical_bg\"\n",
+            "critical_bg = \"$idle_bg\"\n",
+        ))
+        .unwrap_err();
+        assert!(error.contains("idle_bg -> warning_bg -> critical_bg -> idle_bg"));
+        assert!(theme("idle_bg = \"$idle_bg\"").is_err());
+        assert!(theme("idle_bg = \"$nonsense\"").is_err());
+    }
+
+    #[test]
+    fn test_auto_foreground() {
+        let theme = theme(concat!(
+            "idle_bg = \"#ffc107\"\n",
+            "idle_fg = \"auto\"\n",
+            "critical_bg = \"$info_bg\"\n",
+            "info_bg = \"#002b36\"\n",
+            "critical_fg = \"auto\"\n",
+        ))
+        .unwrap();
+        assert_eq!(theme.idle_fg, "#000000");
+        assert_eq!(theme.critical_fg, "#ffffff");
+        assert!(self::theme("idle_bg = \"none\"\nidle_fg = \"auto\"").is_err());
     }
 }
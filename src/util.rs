@@ -1,13 +1,17 @@
-use num_traits::{clamp, ToPrimitive};
-use std::collections::HashMap;
-use std::fmt::Display;
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::prelude::v1::String;
 use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crossbeam_channel::Sender;
+use inotify::{Inotify, WatchMask};
+use lazy_static::lazy_static;
 use regex::Regex;
 use serde::de::DeserializeOwned;
 use serde_json::value::Value;
@@ -15,6 +19,11 @@ use serde_json::value::Value;
 use crate::blocks::Block;
 use crate::config::Config;
 use crate::errors::*;
+use crate::formatting::{self, Placeholder, Spec};
+use crate::scheduler::Task;
+use crate::stop::{self, Stop};
+use crate::widget::{I3BarWidget, State};
+use crate::widgets::text::TextWidget;
 
 pub const USR_SHARE_PATH: &str = "/usr/share/i3status-rust";
 
@@ -92,6 +101,17 @@ pub fn format_speed(
     format!("{:.*}{}", decimal_precision as usize, value, unit)
 }
 
+/// A short human readable age, e.g. "5m" or "2h".
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => "now".to_string(),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
 pub fn battery_level_to_icon(charge_level: Result<u64>) -> &'static str {
     match charge_level {
         Ok(0..=5) => "bat_empty",
@@ -112,6 +132,22 @@ pub fn xdg_config_home() -> PathBuf {
     PathBuf::from(&config_path)
 }
 
+pub fn xdg_state_home() -> PathBuf {
+    let state_path = std::env::var("XDG_STATE_HOME").unwrap_or(format!(
+        "{}/.local/state",
+        std::env::var("HOME").unwrap_or_else(|_| "".to_string())
+    ));
+    PathBuf::from(&state_path)
+}
+
+pub fn xdg_runtime_dir() -> PathBuf {
+    // Without $XDG_RUNTIME_DIR, the system temporary directory is the closest
+    // thing to a per-session scratch space.
+    std::env::var("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir())
+}
+
 pub fn deserialize_file<T>(file: &str) -> Result<T>
 where
     T: DeserializeOwned,
@@ -140,6 +176,43 @@ pub fn read_file(blockname: &str, path: &Path) -> Result<String> {
     Ok(content)
 }
 
+/// Requests an update of the block `id` whenever a file is added to, removed
+/// from or written in one of `dirs`. Bursts of changes, like a mail sync, are
+/// folded into one update every quarter of a second. The watching stops when
+/// the returned `Stop` is dropped.
+pub fn watch_dirs(blockname: &str, dirs: &[PathBuf], id: String, tx: Sender<Task>) -> Result<Stop> {
+    let mut notify = Inotify::init().block_error(blockname, "failed to start inotify")?;
+    for dir in dirs {
+        notify
+            .add_watch(
+                dir,
+                WatchMask::CREATE
+                    | WatchMask::DELETE
+                    | WatchMask::MOVED_FROM
+                    | WatchMask::MOVED_TO
+                    | WatchMask::CLOSE_WRITE,
+            )
+            .block_error(blockname, &format!("failed to watch {}", dir.display()))?;
+    }
+    let (stop, stopped) = stop::pair()?;
+    thread::Builder::new()
+        .name(blockname.into())
+        .spawn(move || {
+            let mut buffer = [0; 1024];
+            while stopped.inotify_events(&mut notify, &mut buffer).is_some() {
+                let task = Task {
+                    id: id.clone(),
+                    update_time: Instant::now(),
+                };
+                if tx.send(task).is_err() || !stopped.sleep(Duration::from_millis(250)) {
+                    return;
+                }
+            }
+        })
+        .block_error(blockname, "failed to start watching thread")?;
+    Ok(stop)
+}
+
 #[allow(dead_code)]
 pub fn get_file(name: &str) -> Result<String> {
     let mut file_contents = String::new();
@@ -212,22 +285,56 @@ impl PrintState {
     }
 }
 
-pub fn print_blocks(
-    order: &[String],
-    block_map: &HashMap<String, &mut dyn Block>,
-    config: &Config,
-) -> Result<()> {
+lazy_static! {
+    /// Blocks drawn without the theme's separator in front of them.
+    static ref NO_SEPARATOR: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Leaves out the separator in front of the block `id`.
+pub fn hide_separator(id: &str) {
+    if let Ok(mut ids) = NO_SEPARATOR.lock() {
+        ids.insert(id.to_string());
+    }
+}
+
+fn has_separator(id: &str) -> bool {
+    NO_SEPARATOR.lock().map_or(true, |ids| !ids.contains(id))
+}
+
+/// The line of JSON that shows `blocks` on the bar, in that order.
+/// The JSON of `widget`, named after its block if it has no name of its own
+/// (e.g. text widgets), so that clicks on it reach the block.
+fn named_widget(widget: &dyn I3BarWidget, block_id: &str) -> String {
+    let rendered = widget.get_rendered();
+    match rendered.get("name") {
+        Some(name) if !name.is_null() => widget.to_string(),
+        _ => {
+            let mut rendered = rendered.clone();
+            rendered["name"] = Value::String(block_id.to_string());
+            rendered.to_string()
+        }
+    }
+}
+
+pub fn render_blocks(blocks: &[&dyn Block], config: &Config) -> Result<String> {
     let mut state = PrintState {
         has_predecessor: false,
         last_bg: None,
     };
 
-    print!("[");
-    for block_id in order {
-        let block = &(*(block_map
-            .get(block_id)
-            .internal_error("util", "couldn't get block by id")?));
-        let widgets = block.view();
+    let mut line = String::from("[");
+    for block in blocks {
+        let block_id = block.id();
+        let failing;
+        let widgets = match block_failure(block_id) {
+            Some(failure) => {
+                failing = TextWidget::new(config.clone())
+                    .with_state(State::Critical)
+                    .with_text(&failure);
+                vec![&failing as &dyn I3BarWidget]
+            }
+            None => block.view(),
+        };
         if widgets.is_empty() {
             continue;
         }
@@ -259,21 +366,22 @@ pub fn print_blocks(
             "color": sep_fg,
             "markup": "pango"
         });
-        print!(
-            "{}{},",
-            if state.has_predecessor { "," } else { "" },
-            separator.to_string()
-        );
-        print!("{}", first.to_string());
+        if state.has_predecessor {
+            line.push(',');
+        }
+        if has_separator(block_id) {
+            line.push_str(&format!("{},", separator));
+        }
+        line.push_str(&named_widget(first, block_id));
         state.set_last_bg(color.to_owned());
         state.set_predecessor(true);
 
         for widget in widgets.iter().skip(1) {
-            print!(
+            line.push_str(&format!(
                 "{}{}",
                 if state.has_predecessor { "," } else { "" },
-                widget.to_string()
-            );
+                named_widget(*widget, block_id)
+            ));
             state.set_last_bg(String::from(
                 widget.get_rendered()["background"]
                     .as_str()
@@ -282,9 +390,8 @@ pub fn print_blocks(
             state.set_predecessor(true);
         }
     }
-    println!("],");
-
-    Ok(())
+    line.push_str("],");
+    Ok(line)
 }
 
 pub fn color_from_rgba(
@@ -298,6 +405,33 @@ pub fn color_from_rgba(
     ))
 }
 
+/// The relative luminance of an sRGB color, from 0 for black to 1 for white,
+/// as defined by WCAG.
+pub fn relative_luminance(color: (u8, u8, u8, u8)) -> f64 {
+    let linear = |channel: u8| {
+        let c = channel as f64 / 255.;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(color.0) + 0.7152 * linear(color.1) + 0.0722 * linear(color.2)
+}
+
+/// Black or white, whichever contrasts better with `background`. `None` if
+/// `background` is not a color.
+pub fn contrasting_color(background: &str) -> Option<String> {
+    let luminance = relative_luminance(color_from_rgba(background).ok()?);
+    // The contrast ratios with black and white are (L + 0.05) / 0.05 and
+    // 1.05 / (L + 0.05); they are equal at L = 0.179.
+    if luminance > 0.179 {
+        Some("#000000".to_string())
+    } else {
+        Some("#ffffff".to_string())
+    }
+}
+
 pub fn color_to_rgba(color: (u8, u8, u8, u8)) -> String {
     format!(
         "#{:02X}{:02X}{:02X}{:02X}",
@@ -305,65 +439,9 @@ pub fn color_to_rgba(color: (u8, u8, u8, u8)) -> String {
     )
 }
 
+/// `percent` as a bar of the default length, see `formatting::Value::bar`.
 pub fn format_percent_bar(percent: f32) -> String {
-    let percent = percent.min(100.0);
-    let percent = percent.max(0.0);
-
-    (0..10)
-        .map(|index| {
-            let bucket_min = (index * 10) as f32;
-            let fraction = percent - bucket_min;
-            //println!("Fraction: {}", fraction);
-            if fraction < 1.25 {
-                '\u{2581}' // 1/8 block for empty so the whole bar is always visible
-            } else if fraction < 2.5 {
-                '\u{2582}' // 2/8 block
-            } else if fraction < 3.75 {
-                '\u{2583}' // 3/8 block
-            } else if fraction < 5.0 {
-                '\u{2584}' // 4/8 block
-            } else if fraction < 6.25 {
-                '\u{2585}' // 5/8 block
-            } else if fraction < 7.5 {
-                '\u{2586}' // 6/8 block
-            } else if fraction < 8.75 {
-                '\u{2587}' // 7/8 block
-            } else {
-                '\u{2588}' // Full block
-            }
-        })
-        .collect()
-}
-
-pub fn format_vec_to_bar_graph<T>(content: &[T], min: Option<T>, max: Option<T>) -> String
-where
-    T: Ord + ToPrimitive,
-{
-    // (x * one eighth block) https://en.wikipedia.org/wiki/Block_Elements
-    let bars = [
-        '\u{2581}', '\u{2582}', '\u{2583}', '\u{2584}', '\u{2585}', '\u{2586}', '\u{2587}',
-        '\u{2588}',
-    ];
-    let min: f64 = match min {
-        Some(x) => x.to_f64().unwrap(),
-        None => content.iter().min().unwrap().to_f64().unwrap(),
-    };
-    let max: f64 = match max {
-        Some(x) => x.to_f64().unwrap(),
-        None => content.iter().max().unwrap().to_f64().unwrap(),
-    };
-    let extant = max - min;
-    if extant.is_normal() {
-        let length = bars.len() as f64 - 1.0;
-        content
-            .iter()
-            .map(|x| {
-                bars[((clamp(x.to_f64().unwrap(), min, max) - min) / extant * length) as usize]
-            })
-            .collect::<_>()
-    } else {
-        (0..content.len() - 1).map(|_| bars[0]).collect::<_>()
-    }
+    formatting::render(&formatting::Value::bar(percent), Spec::default())
 }
 
 // TODO: Allow for other non-additive tints
@@ -382,20 +460,34 @@ pub fn add_colors(a: &str, b: &str) -> ::std::result::Result<String, Box<dyn std
 #[derive(Debug, Clone)]
 pub enum FormatTemplate {
     Str(String, Option<Box<FormatTemplate>>),
-    Var(String, Option<Box<FormatTemplate>>),
+    Var(String, Spec, Option<Box<FormatTemplate>>),
+}
+
+/// The `{age}` placeholder every block has unless it defines its own: how old
+/// the data of the block being rendered is.
+fn render_age(spec: Spec) -> String {
+    let age = match crate::blocks::data_age() {
+        Some(age) => format_age(age),
+        None => "never".to_string(),
+    };
+    formatting::render(&age, spec)
 }
 
 impl FormatTemplate {
     pub fn from_string(s: &str) -> Result<FormatTemplate> {
         let s_as_bytes = s.as_bytes();
 
-        //valid var tokens: {} containing any amount of alphanumericals
-        let re = Regex::new(r"\{[a-zA-Z0-9_-]+?\}").internal_error("util", "invalid regex")?;
+        //valid var tokens: {} containing any amount of alphanumericals, optionally followed
+        //by flags and a unit, e.g. {used:08,.1} or {speed_down:.1;Mi*b}
+        let re = Regex::new(r"\{([a-zA-Z0-9_-]+?)(?::([0-9,.]*))?(?:;([a-zA-Z_*]*))?\}")
+            .internal_error("util", "invalid regex")?;
 
         let mut token_vec: Vec<FormatTemplate> = vec![];
         let mut start: usize = 0;
 
-        for re_match in re.find_iter(&s) {
+        for captures in re.captures_iter(&s) {
+            // Group 0 always exists.
+            let re_match = captures.get(0).unwrap();
             if re_match.start() != start {
                 let str_vec: Vec<u8> = (&s_as_bytes)[start..re_match.start()].to_vec();
                 token_vec.push(FormatTemplate::Str(
@@ -404,7 +496,29 @@ impl FormatTemplate {
                     None,
                 ));
             }
-            token_vec.push(FormatTemplate::Var(re_match.as_str().to_string(), None));
+            let mut spec = match captures.get(2) {
+                Some(flags) => Spec::parse(flags.as_str())
+                    .ok_or_else(|| "expected [0][width][,][.precision]".to_string())
+                    .configuration_error(&format!(
+                        "invalid flags in placeholder {}",
+                        re_match.as_str()
+                    ))?,
+                None => Spec::default(),
+            };
+            if let Some(unit) = captures.get(3) {
+                spec = spec
+                    .with_unit(unit.as_str())
+                    .ok_or_else(|| "expected [_][K|M|G|T][i][*b|*B]".to_string())
+                    .configuration_error(&format!(
+                        "invalid unit in placeholder {}",
+                        re_match.as_str()
+                    ))?;
+            }
+            token_vec.push(FormatTemplate::Var(
+                format!("{{{}}}", &captures[1]),
+                spec,
+                None,
+            ));
             start = re_match.end();
         }
         let str_vec: Vec<u8> = (&s_as_bytes)[start..].to_vec();
@@ -420,14 +534,16 @@ impl FormatTemplate {
         while let Some(token) = token_vec.pop() {
             template = match token {
                 FormatTemplate::Str(s, _) => FormatTemplate::Str(s, Some(Box::new(template))),
-                FormatTemplate::Var(s, _) => FormatTemplate::Var(s, Some(Box::new(template))),
+                FormatTemplate::Var(s, spec, _) => {
+                    FormatTemplate::Var(s, spec, Some(Box::new(template)))
+                }
             }
         }
         Ok(template)
     }
 
     // TODO: Make this function tail-recursive for compiler optimization, also only use the version below, static_str
-    pub fn render<T: Display>(&self, vars: &HashMap<String, T>) -> String {
+    pub fn render<T: Placeholder>(&self, vars: &HashMap<String, T>) -> String {
         use self::FormatTemplate::*;
         let mut rendered = String::new();
         match *self {
@@ -437,12 +553,12 @@ impl FormatTemplate {
                     rendered.push_str(&*next.render(vars));
                 };
             }
-            Var(ref key, ref next) => {
-                rendered.push_str(&format!(
-                    "{}",
-                    vars.get(key)
-                        .unwrap_or_else(|| panic!("Unknown placeholder in format string: {}", key))
-                ));
+            Var(ref key, spec, ref next) => {
+                match vars.get(key) {
+                    Some(value) => rendered.push_str(&formatting::render(value, spec)),
+                    None if key == "{age}" => rendered.push_str(&render_age(spec)),
+                    None => panic!("Unknown placeholder in format string: {}", key),
+                }
                 if let Some(ref next) = *next {
                     rendered.push_str(&*next.render(vars));
                 };
@@ -451,7 +567,7 @@ impl FormatTemplate {
         rendered
     }
 
-    pub fn render_static_str<T: Display>(&self, vars: &HashMap<&str, T>) -> Result<String> {
+    pub fn render_static_str<T: Placeholder>(&self, vars: &HashMap<&str, T>) -> Result<String> {
         use self::FormatTemplate::*;
         let mut rendered = String::new();
         match *self {
@@ -461,14 +577,18 @@ impl FormatTemplate {
                     rendered.push_str(&*next.render_static_str(vars)?);
                 };
             }
-            Var(ref key, ref next) => {
-                rendered.push_str(&format!(
-                    "{}",
-                    vars.get(&**key).internal_error(
-                        "util",
-                        &format!("Unknown placeholder in format string: {}", key)
-                    )?
-                ));
+            Var(ref key, spec, ref next) => {
+                match vars.get(&**key) {
+                    Some(value) => rendered.push_str(&formatting::render(value, spec)),
+                    None if key == "{age}" => rendered.push_str(&render_age(spec)),
+                    None => {
+                        return Err(InternalError(
+                            "util".to_string(),
+                            format!("Unknown placeholder in format string: {}", key),
+                            None,
+                        ))
+                    }
+                }
                 if let Some(ref next) = *next {
                     rendered.push_str(&*next.render_static_str(vars)?);
                 };
@@ -476,6 +596,46 @@ impl FormatTemplate {
         };
         Ok(rendered)
     }
+
+    /// The flags of the first placeholder named `key`, e.g. `{speed_up}`.
+    pub fn spec(&self, key: &str) -> Option<Spec> {
+        let mut token = Some(self);
+        while let Some(current) = token {
+            token = match *current {
+                FormatTemplate::Str(_, ref next) => next.as_deref(),
+                FormatTemplate::Var(ref var, spec, _) if var == key => return Some(spec),
+                FormatTemplate::Var(_, _, ref next) => next.as_deref(),
+            };
+        }
+        None
+    }
+}
+
+/// Whether `text` matches `pattern`, in which `*` stands for any text and `?`
+/// for any single character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Where to resume after the last `*` if the rest does not match.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the `*` take one more character.
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 macro_rules! if_debug {
@@ -484,7 +644,15 @@ macro_rules! if_debug {
 
 #[cfg(test)]
 mod tests {
-    use crate::util::{color_from_rgba, has_command};
+    use crate::blocks::{self, Block, Update};
+    use crate::errors::*;
+    use crate::formatting::Value;
+    use crate::util::{
+        color_from_rgba, contrasting_color, format_age, glob_match, has_command,
+        relative_luminance, FormatTemplate,
+    };
+    use crate::widget::I3BarWidget;
+    use std::time::Duration;
 
     #[test]
     // we assume sh is always available
@@ -527,4 +695,131 @@ mod tests {
         let rgba = color_from_rgba(invalid);
         assert!(rgba.is_err());
     }
+
+    #[test]
+    fn test_contrasting_color() {
+        assert_eq!(relative_luminance((0, 0, 0, 255)), 0.);
+        assert!((relative_luminance((255, 255, 255, 255)) - 1.).abs() < 1e-9);
+        assert_eq!(contrasting_color("#ffffff").unwrap(), "#000000");
+        assert_eq!(contrasting_color("#000000").unwrap(), "#ffffff");
+        assert_eq!(contrasting_color("#8bc34a").unwrap(), "#000000");
+        assert_eq!(contrasting_color("#f44336").unwrap(), "#000000");
+        assert_eq!(contrasting_color("#2196f380").unwrap(), "#000000");
+        assert_eq!(contrasting_color("#424242").unwrap(), "#ffffff");
+        assert_eq!(contrasting_color("auto"), None);
+    }
+
+    #[test]
+    fn test_format_template_flags() {
+        let template = FormatTemplate::from_string("{used:06,.1}/{total} {unit:4}|").unwrap();
+        let values = map!("{used}" => Value::float(1234.56, 2),
+                          "{total}" => Value::integer(2048),
+                          "{unit}" => Value::from("GB"));
+        assert_eq!(
+            template.render_static_str(&values).unwrap(),
+            "1,234.6/2048 GB  |"
+        );
+        assert!(FormatTemplate::from_string("{used:,,}").is_err());
+        // Text that merely looks like a placeholder is left alone.
+        let literal = FormatTemplate::from_string("{used:x}").unwrap();
+        let values = map!("{used}" => "unused");
+        assert_eq!(literal.render_static_str(&values).unwrap(), "{used:x}");
+    }
+
+    #[test]
+    fn test_format_template_prefix() {
+        let template = FormatTemplate::from_string("{down;K} {up:.0;M}").unwrap();
+        let values = map!("{down}" => Value::float(2_345_678, 1).with_suffix("B/s"),
+                          "{up}" => Value::float(2_345_678, 1).with_suffix("B/s"));
+        assert_eq!(
+            template.render_static_str(&values).unwrap(),
+            "2.3MB/s 2MB/s"
+        );
+        let bits = FormatTemplate::from_string("{down;_Mi*b}").unwrap();
+        assert_eq!(bits.render_static_str(&values).unwrap(), "17.9Mib/s");
+        assert_eq!(bits.spec("{down}").unwrap().bits, Some(true));
+        assert!(FormatTemplate::from_string("{down;X}").is_err());
+        assert!(FormatTemplate::from_string("{down;K*x}").is_err());
+        assert!(FormatTemplate::from_string("{down;}").is_err());
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::from_secs(10)), "now");
+        assert_eq!(format_age(Duration::from_secs(300)), "5m");
+        assert_eq!(format_age(Duration::from_secs(7300)), "2h");
+        assert_eq!(format_age(Duration::from_secs(200_000)), "2d");
+    }
+
+    struct AgeBlock {
+        format: FormatTemplate,
+        text: String,
+        reports_refreshes: bool,
+    }
+
+    impl Block for AgeBlock {
+        fn id(&self) -> &str {
+            "age"
+        }
+
+        fn view(&self) -> Vec<&dyn I3BarWidget> {
+            vec![]
+        }
+
+        fn update(&mut self) -> Result<Option<Update>> {
+            let values = map!("{name}" => "weather");
+            self.text = self.format.render_static_str(&values)?;
+            Ok(None)
+        }
+
+        fn reports_refreshes(&self) -> bool {
+            self.reports_refreshes
+        }
+    }
+
+    #[test]
+    fn test_age_placeholder() {
+        let format = FormatTemplate::from_string("{name} {age}").unwrap();
+        let values = map!("{name}" => "weather");
+        assert_eq!(format.render_static_str(&values).unwrap(), "weather never");
+
+        let mut block = AgeBlock {
+            format: format.clone(),
+            text: String::new(),
+            reports_refreshes: true,
+        };
+        blocks::update(&mut block).unwrap();
+        assert_eq!(block.text, "weather never");
+
+        block.reports_refreshes = false;
+        blocks::update(&mut block).unwrap();
+        assert_eq!(block.text, "weather now");
+
+        // Blocks with an `{age}` of their own keep it.
+        let values = map!("{name}" => "heartbeat", "{age}" => "5m");
+        assert_eq!(format.render_static_str(&values).unwrap(), "heartbeat 5m");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let cases = [
+            ("xkb:us*", "xkb:us::eng", true),
+            ("xkb:us*", "xkb:de::ger", false),
+            ("*:eng", "xkb:gb:extd:eng", true),
+            ("xkb:??::*", "xkb:us::eng", true),
+            ("xkb:??::*", "xkb:usa::eng", false),
+            ("*a*b", "xaxxab", true),
+            ("*a*b", "xaxxa", false),
+            ("*kbd*", "asus::kbd_backlight", true),
+            ("**light", "kbd_backlight", true),
+            ("a?c", "ac", false),
+            ("kbd", "kbd_backlight", false),
+            ("anthy", "anthy", true),
+            ("*", "", true),
+            ("", "", true),
+        ];
+        for (pattern, text, expected) in cases.iter() {
+            assert_eq!(glob_match(pattern, text), *expected, "{} {}", pattern, text);
+        }
+    }
 }
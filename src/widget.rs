@@ -7,12 +7,17 @@ use serde_json::value::Value;
 
 use crate::themes::Theme;
 
-#[derive(Debug, Copy, Clone, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
 pub enum State {
+    #[serde(alias = "idle")]
     Idle,
+    #[serde(alias = "info")]
     Info,
+    #[serde(alias = "good")]
     Good,
+    #[serde(alias = "warning")]
     Warning,
+    #[serde(alias = "critical")]
     Critical,
 }
 
@@ -38,7 +43,55 @@ impl FromStr for State {
     }
 }
 
+/// The minimum width of a widget: a number of pixels, or the width of a text.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum MinWidth {
+    Pixels(u32),
+    Text(String),
+}
+
+impl MinWidth {
+    pub fn to_json(&self) -> Value {
+        match *self {
+            MinWidth::Pixels(pixels) => Value::from(pixels),
+            MinWidth::Text(ref text) => Value::from(text.clone()),
+        }
+    }
+}
+
+/// Where the text goes in a widget that is wider than it.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Align::Left => "left",
+            Align::Center => "center",
+            Align::Right => "right",
+        }
+    }
+}
+
+/// What a widget shows, for bars drawing it themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WidgetState {
+    pub text: String,
+    /// The name of the icon in the icon set, such as `cpu`.
+    pub icon: Option<String>,
+    pub state: State,
+    /// The name clicks on the widget have to carry to reach its block.
+    pub name: Option<String>,
+}
+
 pub trait I3BarWidget {
     fn to_string(&self) -> String;
     fn get_rendered(&self) -> &Value;
+    fn widget_state(&self) -> WidgetState;
 }
@@ -1,15 +1,20 @@
 use serde_json::value::Value;
 
-use super::super::widget::I3BarWidget;
+use super::super::widget::{I3BarWidget, WidgetState};
 use crate::config::Config;
 use crate::widget::State;
 
 #[derive(Clone, Debug)]
 pub struct ButtonWidget {
     content: Option<String>,
+    /// Shown instead of `content` when i3bar runs out of space.
+    short_text: Option<String>,
     icon: Option<String>,
+    icon_name: Option<String>,
     state: State,
     id: String,
+    /// Tells widgets of the same block apart in click events.
+    instance: Option<String>,
     rendered: Value,
     cached_output: Option<String>,
     config: Config,
@@ -19,9 +24,12 @@ impl ButtonWidget {
     pub fn new(config: Config, id: &str) -> Self {
         ButtonWidget {
             content: None,
+            short_text: None,
             icon: None,
+            icon_name: None,
             state: State::Idle,
             id: String::from(id),
+            instance: None,
             rendered: json!({
                 "full_text": "",
                 "separator": false,
@@ -37,6 +45,13 @@ impl ButtonWidget {
 
     pub fn with_icon(mut self, name: &str) -> Self {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
+        self.update();
+        self
+    }
+
+    pub fn with_instance(mut self, instance: &str) -> Self {
+        self.instance = Some(String::from(instance));
         self.update();
         self
     }
@@ -64,8 +79,14 @@ impl ButtonWidget {
         self.update();
     }
 
+    pub fn set_short_text(&mut self, short_text: Option<String>) {
+        self.short_text = short_text;
+        self.update();
+    }
+
     pub fn set_icon(&mut self, name: &str) {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
         self.update();
     }
 
@@ -77,9 +98,10 @@ impl ButtonWidget {
     fn update(&mut self) {
         let (key_bg, key_fg) = self.state.theme_keys(&self.config.theme);
 
+        let icon = self.icon.clone().unwrap_or_else(|| String::from(" "));
         self.rendered = json!({
             "full_text": format!("{}{} ",
-                                self.icon.clone().unwrap_or_else(|| String::from(" ")),
+                                icon,
                                 self.content.clone().unwrap_or_else(|| String::from(""))),
             "separator": false,
             "name": self.id.clone(),
@@ -88,6 +110,12 @@ impl ButtonWidget {
             "color": key_fg,
             "markup": "pango"
         });
+        if let Some(ref instance) = self.instance {
+            self.rendered["instance"] = Value::from(instance.clone());
+        }
+        if let Some(ref short_text) = self.short_text {
+            self.rendered["short_text"] = Value::from(format!("{}{} ", icon, short_text));
+        }
 
         self.cached_output = Some(self.rendered.to_string());
     }
@@ -103,4 +131,13 @@ impl I3BarWidget for ButtonWidget {
     fn get_rendered(&self) -> &Value {
         &self.rendered
     }
+
+    fn widget_state(&self) -> WidgetState {
+        WidgetState {
+            text: self.content.clone().unwrap_or_default(),
+            icon: self.icon_name.clone(),
+            state: self.state,
+            name: Some(self.id.clone()),
+        }
+    }
 }
@@ -1,26 +1,129 @@
+use std::collections::VecDeque;
+
 use num_traits::{clamp, ToPrimitive};
+use serde_derive::Deserialize;
 use serde_json::value::Value;
 
-use super::super::widget::I3BarWidget;
+use super::super::widget::{I3BarWidget, WidgetState};
 use crate::config::Config;
 use crate::widget::State;
 
+/// The `graph` option of blocks showing the history of a value, e.g.
+/// `graph = { length = 20, chars = "⣀⣄⣤⣦⣶⣷⣿" }`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GraphConfig {
+    /// The number of samples to show.
+    #[serde(default = "GraphConfig::default_length")]
+    pub length: usize,
+
+    /// The characters to draw samples with, from lowest to highest.
+    #[serde(default = "GraphConfig::default_chars")]
+    pub chars: String,
+}
+
+impl GraphConfig {
+    fn default_length() -> usize {
+        10
+    }
+
+    fn default_chars() -> String {
+        "▁▂▃▄▅▆▇█".to_owned()
+    }
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        GraphConfig {
+            length: GraphConfig::default_length(),
+            chars: GraphConfig::default_chars(),
+        }
+    }
+}
+
+/// The last `length` samples of a value.
+#[derive(Clone, Debug)]
+pub struct History {
+    samples: VecDeque<f64>,
+    length: usize,
+}
+
+impl History {
+    pub fn new(length: usize) -> Self {
+        History {
+            samples: VecDeque::with_capacity(length),
+            length,
+        }
+    }
+
+    /// Adds a sample, dropping the oldest one if the history is full.
+    pub fn push<T: ToPrimitive>(&mut self, sample: T) {
+        if self.length == 0 {
+            return;
+        }
+        if self.samples.len() == self.length {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample.to_f64().unwrap_or(0.0));
+    }
+
+    /// Renders the samples with `chars`, oldest first. Without `min` or `max`
+    /// the graph is scaled to the lowest or highest sample.
+    pub fn render(&self, min: Option<f64>, max: Option<f64>, chars: &str) -> String {
+        let chars: Vec<char> = chars.chars().collect();
+        if chars.is_empty() {
+            return String::new();
+        }
+        let min = min.unwrap_or_else(|| self.samples.iter().copied().fold(f64::INFINITY, f64::min));
+        let max = max.unwrap_or_else(|| {
+            self.samples
+                .iter()
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max)
+        });
+        let extant = max - min;
+        let top = chars.len() - 1;
+        self.samples
+            .iter()
+            .map(|sample| {
+                if extant.is_normal() {
+                    let level = (clamp(*sample, min, max) - min) / extant * top as f64;
+                    chars[(level.round() as usize).min(top)]
+                } else {
+                    chars[0]
+                }
+            })
+            .collect()
+    }
+}
+
+/// A sparkline of the history of a value.
 #[derive(Clone, Debug)]
 pub struct GraphWidget {
     content: Option<String>,
     icon: Option<String>,
+    icon_name: Option<String>,
     state: State,
     rendered: Value,
     cached_output: Option<String>,
     config: Config,
+    history: History,
+    chars: String,
+    min: Option<f64>,
+    max: Option<f64>,
 }
-#[allow(dead_code)]
+
 impl GraphWidget {
     pub fn new(config: Config) -> Self {
         GraphWidget {
             content: None,
             icon: None,
+            icon_name: None,
             state: State::Idle,
+            history: History::new(GraphConfig::default_length()),
+            chars: GraphConfig::default_chars(),
+            min: None,
+            max: None,
             rendered: json!({
                 "full_text": "",
                 "separator": false,
@@ -33,18 +136,44 @@ impl GraphWidget {
         }
     }
 
+    /// Keeps `graph.length` samples and draws them with `graph.chars`.
+    pub fn with_graph(mut self, graph: &GraphConfig) -> Self {
+        self.history = History::new(graph.length);
+        self.chars = graph.chars.clone();
+        self
+    }
+
+    /// Scales the graph to a fixed range, e.g. 0 to 100 for percentages,
+    /// rather than to the lowest and highest sample.
+    pub fn with_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    #[allow(dead_code)]
     pub fn with_icon(mut self, name: &str) -> Self {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
         self.update();
         self
     }
 
+    #[allow(dead_code)]
     pub fn with_state(mut self, state: State) -> Self {
         self.state = state;
         self.update();
         self
     }
 
+    /// Adds a sample to the history and redraws the graph.
+    pub fn push<T: ToPrimitive>(&mut self, sample: T) {
+        self.history.push(sample);
+        self.content = Some(self.history.render(self.min, self.max, &self.chars));
+        self.update();
+    }
+
+    #[allow(dead_code)]
     pub fn set_values<T>(&mut self, content: &[T], min: Option<T>, max: Option<T>)
     where
         T: Ord + ToPrimitive,
@@ -79,8 +208,10 @@ impl GraphWidget {
         self.update();
     }
 
+    #[allow(dead_code)]
     pub fn set_icon(&mut self, name: &str) {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
         self.update();
     }
 
@@ -116,4 +247,61 @@ impl I3BarWidget for GraphWidget {
     fn get_rendered(&self) -> &Value {
         &self.rendered
     }
+
+    fn widget_state(&self) -> WidgetState {
+        WidgetState {
+            text: self.content.clone().unwrap_or_default(),
+            icon: self.icon_name.clone(),
+            state: self.state,
+            name: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let mut history = History::new(3);
+        assert_eq!(history.render(None, None, "_-^"), "");
+        history.push(0);
+        assert_eq!(history.render(None, None, "_-^"), "_");
+        for sample in &[10, 5, 0, 10] {
+            history.push(*sample);
+        }
+        assert_eq!(history.render(None, None, "_-^"), "-_^");
+        assert_eq!(history.render(Some(0.0), Some(20.0), "_-^"), "-_-");
+        // Samples out of the range are clamped to it.
+        history.push(50);
+        assert_eq!(history.render(Some(0.0), Some(20.0), "_-^"), "_-^");
+        assert_eq!(history.render(None, None, ""), "");
+
+        let mut flat = History::new(2);
+        flat.push(3);
+        flat.push(3);
+        assert_eq!(flat.render(None, None, "▁▂▃"), "▁▁");
+
+        let mut none = History::new(0);
+        none.push(1);
+        assert_eq!(none.render(None, None, "▁▂▃"), "");
+    }
+
+    #[test]
+    fn test_graph_config() {
+        let graph: GraphConfig = toml::from_str("length = 4").unwrap();
+        assert_eq!(graph.chars, GraphConfig::default().chars);
+        let mut widget = GraphWidget::new(Config::default())
+            .with_graph(&GraphConfig {
+                length: 2,
+                chars: "ab".to_string(),
+            })
+            .with_range(Some(0.0), Some(1.0));
+        widget.push(1);
+        widget.push(0);
+        widget.push(1);
+        assert_eq!(widget.widget_state().text, "ab");
+        assert!(toml::from_str::<GraphConfig>("size = 4").is_err());
+    }
 }
@@ -4,7 +4,7 @@ use serde_json::value::Value;
 
 use crate::config::Config;
 use crate::errors::*;
-use crate::widget::{I3BarWidget, State};
+use crate::widget::{I3BarWidget, State, WidgetState};
 
 #[derive(Clone, Debug)]
 pub struct RotatingTextWidget {
@@ -16,6 +16,8 @@ pub struct RotatingTextWidget {
     next_rotation: Option<Instant>,
     content: String,
     icon: Option<String>,
+    icon_name: Option<String>,
+    name: Option<String>,
     state: State,
     rendered: Value,
     cached_output: Option<String>,
@@ -41,6 +43,8 @@ impl RotatingTextWidget {
             next_rotation: None,
             content: String::new(),
             icon: None,
+            icon_name: None,
+            name: None,
             state: State::Idle,
             rendered: json!({
                 "full_text": "",
@@ -57,6 +61,14 @@ impl RotatingTextWidget {
 
     pub fn with_icon(mut self, name: &str) -> Self {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
+        self.update();
+        self
+    }
+
+    /// Names the widget, so that i3bar reports clicks on it.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
         self.update();
         self
     }
@@ -86,6 +98,7 @@ impl RotatingTextWidget {
 
     pub fn set_icon(&mut self, name: &str) {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
         self.update();
     }
 
@@ -159,10 +172,14 @@ impl RotatingTextWidget {
             "background": key_bg,
             "color": key_fg
         });
+        if let Some(ref name) = self.name {
+            self.rendered["name"] = json!(name);
+        }
 
         self.cached_output = Some(self.rendered.to_string());
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Result<(bool, Option<Duration>)> {
         if let Some(next_rotation) = self.next_rotation {
             let now = Instant::now();
@@ -201,4 +218,13 @@ impl I3BarWidget for RotatingTextWidget {
     fn get_rendered(&self) -> &Value {
         &self.rendered
     }
+
+    fn widget_state(&self) -> WidgetState {
+        WidgetState {
+            text: self.content.clone(),
+            icon: self.icon_name.clone(),
+            state: self.state,
+            name: self.name.clone(),
+        }
+    }
 }
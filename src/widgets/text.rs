@@ -1,14 +1,17 @@
 use serde_json::value::Value;
 
-use super::super::widget::I3BarWidget;
+use super::super::widget::{I3BarWidget, WidgetState};
 use crate::config::Config;
-use crate::widget::State;
+use crate::widget::{Align, MinWidth, State};
 
 #[derive(Clone, Debug)]
 pub struct TextWidget {
     content: Option<String>,
     icon: Option<String>,
+    icon_name: Option<String>,
     state: State,
+    min_width: Option<MinWidth>,
+    align: Option<Align>,
     rendered: Value,
     cached_output: Option<String>,
     config: Config,
@@ -19,7 +22,10 @@ impl TextWidget {
         TextWidget {
             content: None,
             icon: None,
+            icon_name: None,
             state: State::Idle,
+            min_width: None,
+            align: None,
             rendered: json!({
                 "full_text": "",
                 "separator": false,
@@ -34,6 +40,7 @@ impl TextWidget {
 
     pub fn with_icon(mut self, name: &str) -> Self {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
         self.update();
         self
     }
@@ -50,6 +57,20 @@ impl TextWidget {
         self
     }
 
+    /// Makes the widget at least `min_width` wide.
+    pub fn with_min_width(mut self, min_width: Option<MinWidth>) -> Self {
+        self.min_width = min_width;
+        self.update();
+        self
+    }
+
+    /// Aligns the text within `min_width`, left by default.
+    pub fn with_align(mut self, align: Option<Align>) -> Self {
+        self.align = align;
+        self.update();
+        self
+    }
+
     pub fn set_text(&mut self, content: String) {
         self.content = Some(content);
         self.update();
@@ -57,6 +78,7 @@ impl TextWidget {
 
     pub fn set_icon(&mut self, name: &str) {
         self.icon = self.config.icons.get(name).cloned();
+        self.icon_name = Some(name.to_string());
         self.update();
     }
 
@@ -77,6 +99,12 @@ impl TextWidget {
             "background": key_bg.to_owned(),
             "color": key_fg.to_owned()
         });
+        if let Some(ref min_width) = self.min_width {
+            self.rendered["min_width"] = min_width.to_json();
+            if let Some(align) = self.align {
+                self.rendered["align"] = Value::from(align.as_str());
+            }
+        }
 
         self.cached_output = Some(self.rendered.to_string());
     }
@@ -92,4 +120,13 @@ impl I3BarWidget for TextWidget {
     fn get_rendered(&self) -> &Value {
         &self.rendered
     }
+
+    fn widget_state(&self) -> WidgetState {
+        WidgetState {
+            text: self.content.clone().unwrap_or_default(),
+            icon: self.icon_name.clone(),
+            state: self.state,
+            name: None,
+        }
+    }
 }
@@ -0,0 +1,356 @@
+use std::time::{Duration, Instant};
+
+use i3status_rs::config::Config;
+use i3status_rs::control::Command;
+use i3status_rs::input::{I3BarEvent, MouseButton};
+use i3status_rs::runner::BlockRunner;
+use i3status_rs::widget::State;
+
+fn runner(blocks: &str) -> BlockRunner {
+    let config: Config = toml::from_str(&format!("[theme]\nname = \"plain\"\n{}", blocks)).unwrap();
+    BlockRunner::new(config).unwrap()
+}
+
+/// The texts of the widgets of every block in the next frame.
+fn next_texts(runner: &mut BlockRunner) -> Vec<Vec<String>> {
+    let frame = runner
+        .next_frame(&crossbeam_channel::never())
+        .unwrap()
+        .unwrap();
+    frame
+        .blocks()
+        .into_iter()
+        .map(|block| {
+            block
+                .widgets
+                .into_iter()
+                .map(|widget| widget.text)
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_frames() {
+    let mut runner = runner(concat!(
+        "[[block]]\nblock = \"spacer\"\ntext = \"a\"\nstate = \"Good\"\n",
+        "[[block]]\nblock = \"custom\"\ncommand = \"echo hi\"\n",
+    ));
+    let stop = crossbeam_channel::never();
+    let frame = runner.next_frame(&stop).unwrap().unwrap();
+    let blocks = frame.blocks();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0].widgets[0].text, "a");
+    assert_eq!(blocks[0].widgets[0].state, State::Good);
+    assert_eq!(blocks[1].widgets[0].text, "hi");
+    assert_eq!(
+        blocks[1].widgets[0].name.as_deref(),
+        Some(blocks[1].id.as_str())
+    );
+
+    // The same frame as i3bar JSON.
+    let line = frame.to_i3bar().unwrap();
+    assert!(line.starts_with('[') && line.ends_with("],"));
+    assert!(line.contains(r#""full_text":" hi ""#));
+}
+
+#[test]
+fn test_clicks() {
+    let mut runner =
+        runner("[[block]]\nblock = \"custom\"\ncycle = [\"echo one\", \"echo two\"]\n");
+    assert_eq!(next_texts(&mut runner), vec![vec!["one".to_string()]]);
+
+    let id = runner.blocks()[0].id().to_string();
+    runner
+        .click_sender()
+        .send(I3BarEvent {
+            name: Some(id),
+            instance: None,
+            x: 0,
+            y: 0,
+            button: MouseButton::Left,
+            modifiers: Vec::new(),
+            double_click: false,
+        })
+        .unwrap();
+    // The block asks for an update after handling the click.
+    let texts = (0..3)
+        .map(|_| next_texts(&mut runner))
+        .find(|texts| texts != &vec![vec!["one".to_string()]]);
+    assert_eq!(texts, Some(vec![vec!["two".to_string()]]));
+}
+
+#[test]
+fn test_keylight_simulated() {
+    let mut runner = runner(concat!(
+        "[[block]]\nblock = \"keylight\"\nbackend = \"simulated\"\n",
+        "simulated_value = 1\nsimulated_max = 3\n",
+    ));
+    let text = |texts: Vec<Vec<String>>| texts[0][0].trim().to_string();
+    assert!(text(next_texts(&mut runner)).ends_with("33%"));
+
+    let id = runner.blocks()[0].id().to_string();
+    let mut click = |button| {
+        runner.click(&I3BarEvent {
+            name: Some(id.clone()),
+            instance: None,
+            x: 0,
+            y: 0,
+            button,
+            modifiers: Vec::new(),
+            double_click: false,
+        });
+        // The frame of the update the block asks for.
+        text(next_texts(&mut runner))
+    };
+    assert!(click(MouseButton::WheelUp).ends_with("67%"));
+    assert!(click(MouseButton::WheelUp).ends_with("100%"));
+    assert!(click(MouseButton::WheelDown).ends_with("67%"));
+}
+
+#[test]
+fn test_stop() {
+    let mut runner = runner("[[block]]\nblock = \"spacer\"\n");
+    next_texts(&mut runner);
+    let (stop, stopped) = crossbeam_channel::bounded(1);
+    stop.send(()).unwrap();
+    assert!(runner.next_frame(&stopped).unwrap().is_none());
+}
+
+#[test]
+fn test_reload() {
+    let block = |text: &str| {
+        format!(
+            "[[block]]\nblock = \"spacer\"\nid = \"reloaded\"\ntext = \"{}\"\n",
+            text
+        )
+    };
+    let config = |blocks: &str| -> Config {
+        toml::from_str(&format!("[theme]\nname = \"plain\"\n{}", blocks)).unwrap()
+    };
+    let mut runner = runner(&block("old"));
+    assert_eq!(next_texts(&mut runner), vec![vec!["old".to_string()]]);
+
+    // The new blocks can have the ids of the old ones.
+    let new = format!(
+        "{}{}",
+        block("new"),
+        "[[block]]\nblock = \"spacer\"\ntext = \"b\"\n"
+    );
+    runner.reload_sender().send(config(&new)).unwrap();
+    assert_eq!(
+        next_texts(&mut runner),
+        vec![vec!["new".to_string()], vec!["b".to_string()]]
+    );
+
+    // A configuration with a broken block keeps the old blocks.
+    let broken = format!(
+        "{}{}",
+        block("broken"),
+        "[[block]]\nblock = \"spacer\"\nalign = \"left\"\n"
+    );
+    assert!(runner.reload(config(&broken)).is_err());
+    assert_eq!(runner.blocks().len(), 2);
+    runner.reload(config(&block("again"))).unwrap();
+    assert_eq!(next_texts(&mut runner), vec![vec!["again".to_string()]]);
+}
+
+/// The number of threads of this process named `name`.
+fn threads_named(name: &str) -> usize {
+    std::fs::read_dir("/proc/self/task")
+        .unwrap()
+        .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+        .filter(|comm| comm.trim_end() == name)
+        .count()
+}
+
+#[test]
+fn test_reload_stops_old_threads() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let config = || -> Config {
+        toml::from_str(&format!(
+            concat!(
+                "[theme]\nname = \"plain\"\n",
+                "[[block]]\nblock = \"heartbeat\"\npath = {:?}\n",
+                "heartbeats = [{{ name = \"backup\", max_age = 60 }}]\n",
+                "[[block]]\nblock = \"net\"\ndevice = \"lo\"\n",
+            ),
+            dir.path()
+        ))
+        .unwrap()
+    };
+    let mut runner = BlockRunner::new(config()).unwrap();
+    assert_eq!(threads_named("heartbeat"), 1);
+    // Without netlink (e.g. in a sandbox), the net block has no thread.
+    let net_threads = threads_named("net");
+    assert!(net_threads <= 1);
+
+    // The threads of the replaced blocks wind down on their own.
+    runner.reload(config()).unwrap();
+    runner.reload(config()).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while (threads_named("heartbeat") > 1 || threads_named("net") > net_threads)
+        && Instant::now() < deadline
+    {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(threads_named("heartbeat"), 1);
+    assert_eq!(threads_named("net"), net_threads);
+}
+
+#[test]
+fn test_on_click_text_widget() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let clicked = dir.path().join("clicked");
+    let mut runner = runner(&format!(
+        "[[block]]\nblock = \"spacer\"\ntext = \"a\"\non_click = \"touch {}\"\n",
+        clicked.display()
+    ));
+    let name = {
+        let frame = runner
+            .next_frame(&crossbeam_channel::never())
+            .unwrap()
+            .unwrap();
+        // Text widgets are named after their block, both in the frame and
+        // for i3bar.
+        assert!(frame
+            .to_i3bar()
+            .unwrap()
+            .contains(&format!(r#""name":"{}""#, frame.blocks()[0].id)));
+        frame.blocks()[0].widgets[0].name.clone()
+    };
+    assert_eq!(name.as_deref(), Some(runner.blocks()[0].id()));
+
+    runner.click(&I3BarEvent {
+        name,
+        instance: None,
+        x: 0,
+        y: 0,
+        button: MouseButton::Left,
+        modifiers: Vec::new(),
+        double_click: false,
+    });
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !clicked.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(clicked.exists());
+}
+
+#[test]
+fn test_control() {
+    let mut runner = runner(concat!(
+        "[[block]]\nblock = \"spacer\"\nid = \"a\"\ntext = \"a\"\n",
+        "[[block]]\nblock = \"custom\"\nid = \"b\"\ncommand = \"echo b\"\ninterval = \"once\"\n",
+    ));
+    assert_eq!(
+        next_texts(&mut runner),
+        vec![vec!["a".to_string()], vec!["b".to_string()]]
+    );
+    let mut control = |command: Command| {
+        let (reply, result) = crossbeam_channel::bounded(1);
+        runner.control_sender().send((command, reply)).unwrap();
+        let texts = next_texts(&mut runner);
+        result.recv().unwrap().map(|()| texts)
+    };
+
+    // Hidden blocks show nothing.
+    let texts = control(Command::Hide("a".to_string())).unwrap();
+    assert_eq!(texts, vec![vec![], vec!["b".to_string()]]);
+    let texts = control(Command::SetText("b".to_string(), "pushed".to_string())).unwrap();
+    assert_eq!(texts, vec![vec![], vec!["pushed".to_string()]]);
+    let texts = control(Command::Toggle("a".to_string())).unwrap();
+    assert_eq!(texts[0], vec!["a".to_string()]);
+    let texts = control(Command::Refresh(None)).unwrap();
+    assert_eq!(texts[1], vec!["b".to_string()]);
+
+    assert!(control(Command::Hide("c".to_string())).is_err());
+    assert!(control(Command::SetText("a".to_string(), "x".to_string())).is_err());
+    assert!(control(Command::Theme("no-such-theme".to_string())).is_err());
+
+    // Switching themes keeps the blocks hidden. The new blocks show
+    // something once they have been updated.
+    control(Command::Hide("a".to_string())).unwrap();
+    control(Command::Theme("solarized-dark".to_string())).unwrap();
+    assert_eq!(next_texts(&mut runner), vec![vec![], vec!["b".to_string()]]);
+}
+
+#[test]
+fn test_persistent_command() {
+    let mut runner = runner(concat!(
+        "[[block]]\nblock = \"custom\"\npersistent = true\n",
+        "command = \"echo one; echo two; sleep 10\"\n",
+    ));
+    // Every line the command prints is shown in turn.
+    let texts = (0..5)
+        .map(|_| next_texts(&mut runner))
+        .find(|texts| texts == &vec![vec!["two".to_string()]]);
+    assert!(texts.is_some());
+
+    let cycling: Config = toml::from_str(concat!(
+        "[theme]\nname = \"plain\"\n",
+        "[[block]]\nblock = \"custom\"\npersistent = true\n",
+        "cycle = [\"echo one\"]\n",
+    ))
+    .unwrap();
+    assert!(BlockRunner::new(cycling).is_err());
+}
+
+#[test]
+fn test_json_output() {
+    let mut runner = runner(concat!(
+        "[[block]]\nblock = \"custom\"\njson = true\n",
+        r#"command = "echo '{\"text\": \"long\", \"short_text\": \"s\", \"state\": \"warning\"}'""#,
+        "\n",
+    ));
+    let frame = runner
+        .next_frame(&crossbeam_channel::never())
+        .unwrap()
+        .unwrap();
+    let widget = &frame.blocks()[0].widgets[0];
+    assert_eq!(widget.text, "long");
+    assert_eq!(widget.state, State::Warning);
+    let line = frame.to_i3bar().unwrap();
+    assert!(line.contains(r#""full_text":"long ""#));
+    assert!(line.contains(r#""short_text":"s ""#));
+}
+
+#[test]
+fn test_toggle_checks_state() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let state = dir.path().join("state");
+    // Turning on does not stick, which the block finds out after the click.
+    let mut runner = runner(&format!(
+        concat!(
+            "[[block]]\nblock = \"toggle\"\n",
+            "command_state = \"cat {0}\"\n",
+            "command_on = \"true\"\n",
+            "command_off = \"rm {0}\"\n",
+        ),
+        state.display()
+    ));
+    let icon = |runner: &mut BlockRunner| {
+        let frame = runner
+            .next_frame(&crossbeam_channel::never())
+            .unwrap()
+            .unwrap();
+        frame.blocks()[0].widgets[0].icon.clone().unwrap()
+    };
+    assert_eq!(icon(&mut runner), "toggle_off");
+
+    let id = runner.blocks()[0].id().to_string();
+    runner
+        .click_sender()
+        .send(I3BarEvent {
+            name: Some(id),
+            instance: None,
+            x: 0,
+            y: 0,
+            button: MouseButton::Left,
+            modifiers: Vec::new(),
+            double_click: false,
+        })
+        .unwrap();
+    assert_eq!(icon(&mut runner), "toggle_on");
+    assert_eq!(icon(&mut runner), "toggle_off");
+}
@@ -1,6 +1,11 @@
 #[cfg(test)]
 mod run_binary {
-    use std::process::Command;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use assert_fs::TempDir;
 
     #[test]
     #[cfg(debug_assertions)]
@@ -21,4 +26,37 @@ mod run_binary {
             .expect("failed to execute process");
         assert_eq!(output.success(), true);
     }
+
+    #[test]
+    fn exits_when_i3bar_goes_away() {
+        let runtime_dir = TempDir::new().unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_i3status-rs"))
+            .args(&["--persist-state", "./tests/testconfig1.toml"])
+            .env("XDG_RUNTIME_DIR", runtime_dir.path())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to execute process");
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut header = String::new();
+        stdout.read_line(&mut header).unwrap();
+        assert!(header.contains("\"version\": 1"));
+        // The opening of the endless array of frames.
+        stdout.read_line(&mut header).unwrap();
+        drop(stdout);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            if Instant::now() > deadline {
+                child.kill().ok();
+                panic!("the bar kept running without i3bar");
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        assert!(status.success());
+        assert!(runtime_dir.path().join("i3status-rust/state.json").exists());
+    }
 }